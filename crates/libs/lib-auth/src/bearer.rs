@@ -23,6 +23,14 @@ pub fn hash_key(content: ContentToHash) -> Result<String> {
     Ok(format!("#01#{}", res)) //prefix is helpful for versioning
 }
 
+/// Random secret of a new API key (two v4 uuids, b64u encoded)
+pub fn new_api_secret() -> String {
+    let mut secret = Vec::with_capacity(32);
+    secret.extend_from_slice(Uuid::new_v4().as_bytes());
+    secret.extend_from_slice(Uuid::new_v4().as_bytes());
+    b64u_encode(secret)
+}
+
 pub fn validate_key(content: ContentToHash, pwd_check: String) -> Result<()> {
     let hash = hash_key(content)?;
     if hash != pwd_check {
@@ -14,6 +14,9 @@ pub enum Error {
     ExpNotIso,
     Expired,
     HmacFailNewFromSlice,
+    JwtInvalid(String),
+    JwksFetch(String),
+    Introspection(String),
     Custom(String),
 }
 
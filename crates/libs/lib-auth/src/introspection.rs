@@ -0,0 +1,129 @@
+// OAuth 2.0 token introspection (RFC 7662), delegates opaque bearer tokens to an existing auth service
+
+use crate::error::{Error, Result};
+use crate::jwt::extract_roles;
+use lib_utils::envs::get_env;
+use serde_json::Value;
+use std::time::Duration;
+
+pub struct IntrospectionConfig {
+    /// Introspection endpoint, the token is posted as `token=<token>`
+    pub url: String,
+    /// Credentials of this server at the auth service, sent as basic auth
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    /// Claim holding the role(s), dotted paths are supported, space separated scopes are split
+    pub role_claim: String,
+    /// Claim holding the tenant of the subject, dotted paths are supported
+    pub tenant_claim: String,
+    /// How long an introspection result is reused for the same token
+    pub cache_ttl: Duration,
+}
+
+impl IntrospectionConfig {
+    /// `None` unless `AUTH_INTROSPECTION_URL` is set
+    pub fn load_from_env() -> Option<IntrospectionConfig> {
+        let url = get_env("AUTH_INTROSPECTION_URL").ok()?;
+        Some(IntrospectionConfig {
+            url,
+            client_id: get_env("AUTH_INTROSPECTION_CLIENT_ID").ok(),
+            client_secret: get_env("AUTH_INTROSPECTION_CLIENT_SECRET").ok(),
+            role_claim: get_env("AUTH_INTROSPECTION_ROLE_CLAIM")
+                .unwrap_or_else(|_| "scope".to_string()),
+            tenant_claim: get_env("AUTH_INTROSPECTION_TENANT_CLAIM")
+                .unwrap_or_else(|_| "tenant_id".to_string()),
+            cache_ttl: Duration::from_secs(get_env("AUTH_INTROSPECTION_CACHE_SEC").unwrap_or(60)),
+        })
+    }
+}
+
+/// Subject, roles and tenant of an active token
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntrospectedToken {
+    pub sub: String,
+    pub roles: Vec<String>,
+    pub tenant: Option<String>,
+}
+
+pub struct TokenIntrospector {
+    config: IntrospectionConfig,
+    http: reqwest::Client,
+}
+
+impl TokenIntrospector {
+    pub fn new(config: IntrospectionConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub fn config(&self) -> &IntrospectionConfig {
+        &self.config
+    }
+
+    /// `None` when the auth service reports the token as inactive
+    pub async fn introspect(&self, token: &str) -> Result<Option<IntrospectedToken>> {
+        let mut request = self.http.post(&self.config.url).form(&[("token", token)]);
+        if let Some(client_id) = &self.config.client_id {
+            request = request.basic_auth(client_id, self.config.client_secret.as_ref());
+        }
+        let response = request
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|e| Error::Introspection(e.to_string()))?
+            .json::<Value>()
+            .await
+            .map_err(|e| Error::Introspection(e.to_string()))?;
+        Ok(parse_response(&response, &self.config))
+    }
+}
+
+fn parse_response(response: &Value, config: &IntrospectionConfig) -> Option<IntrospectedToken> {
+    if response.get("active").and_then(Value::as_bool) != Some(true) {
+        return None;
+    }
+    let sub = ["sub", "username", "client_id"]
+        .iter()
+        .find_map(|claim| response.get(*claim).and_then(Value::as_str))?
+        .to_string();
+    let roles = extract_roles(response, &config.role_claim)
+        .iter()
+        .flat_map(|roles| roles.split_whitespace())
+        .map(str::to_string)
+        .collect();
+    let tenant = extract_roles(response, &config.tenant_claim)
+        .into_iter()
+        .next();
+    Some(IntrospectedToken { sub, roles, tenant })
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_response() {
+        let config = IntrospectionConfig {
+            url: "http://auth.local/introspect".to_string(),
+            client_id: None,
+            client_secret: None,
+            role_claim: "scope".to_string(),
+            tenant_claim: "tenant_id".to_string(),
+            cache_ttl: Duration::from_secs(60),
+        };
+        let response =
+            json!({"active": true, "sub": "svc-1", "scope": "embed admin", "tenant_id": "acme"});
+        let token = parse_response(&response, &config).unwrap();
+        assert_eq!(token.sub, "svc-1");
+        assert_eq!(token.roles, vec!["embed", "admin"]);
+        assert_eq!(token.tenant.as_deref(), Some("acme"));
+
+        assert!(parse_response(&json!({"active": false, "sub": "svc-1"}), &config).is_none());
+        assert!(parse_response(&json!({"active": true}), &config).is_none());
+    }
+}
+// endregion: Unit Test
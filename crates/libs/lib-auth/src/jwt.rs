@@ -0,0 +1,251 @@
+// JWT is used for bearer authentication behind an identity provider (Auth0, Keycloak, ...)
+
+use crate::error::{Error, Result};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use lib_utils::envs::get_env;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// Minimum delay between two JWKS fetches triggered by an unknown `kid`
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(30);
+
+pub struct JwtConfig {
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    /// JWKS endpoint of the identity provider, enables RS256
+    pub jwks_url: Option<String>,
+    /// Shared secret, enables HS256
+    pub hs256_secret: Option<String>,
+    /// Claim holding the role(s), dotted paths are supported (e.g. `realm_access.roles`)
+    pub role_claim: String,
+    /// Claim holding the tenant of the subject, dotted paths are supported
+    pub tenant_claim: String,
+    pub jwks_ttl: Duration,
+}
+
+impl JwtConfig {
+    /// `None` unless `JWT_JWKS_URL` or `JWT_HS256_SECRET` is set
+    pub fn load_from_env() -> Option<JwtConfig> {
+        let jwks_url = get_env("JWT_JWKS_URL").ok();
+        let hs256_secret = get_env("JWT_HS256_SECRET").ok();
+        if jwks_url.is_none() && hs256_secret.is_none() {
+            return None;
+        }
+
+        Some(JwtConfig {
+            issuer: get_env("JWT_ISSUER").ok(),
+            audience: get_env("JWT_AUDIENCE").ok(),
+            jwks_url,
+            hs256_secret,
+            role_claim: get_env("JWT_ROLE_CLAIM").unwrap_or_else(|_| "role".to_string()),
+            tenant_claim: get_env("JWT_TENANT_CLAIM").unwrap_or_else(|_| "tenant_id".to_string()),
+            jwks_ttl: Duration::from_secs(get_env("JWT_JWKS_TTL_SEC").unwrap_or(600)),
+        })
+    }
+}
+
+/// Claims extracted from a validated token
+#[derive(Debug, Clone, PartialEq)]
+pub struct JwtClaims {
+    pub sub: String,
+    pub roles: Vec<String>,
+    /// `None` when the token has no tenant claim
+    pub tenant: Option<String>,
+}
+
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+pub struct JwtValidator {
+    config: JwtConfig,
+    http: reqwest::Client,
+    jwks: RwLock<Option<CachedJwks>>,
+}
+
+impl JwtValidator {
+    pub fn new(config: JwtConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+            jwks: RwLock::new(None),
+        }
+    }
+
+    /// Cheap check used to tell JWTs apart from API keys
+    pub fn looks_like_jwt(token: &str) -> bool {
+        token.starts_with("eyJ") && token.split('.').count() == 3
+    }
+
+    pub async fn validate(&self, token: &str) -> Result<JwtClaims> {
+        let header = decode_header(token).map_err(|e| Error::JwtInvalid(e.to_string()))?;
+        let key = match header.alg {
+            Algorithm::HS256 => {
+                let secret = self
+                    .config
+                    .hs256_secret
+                    .as_ref()
+                    .ok_or_else(|| Error::JwtInvalid("HS256 is not enabled".to_string()))?;
+                DecodingKey::from_secret(secret.as_bytes())
+            }
+            Algorithm::RS256 => {
+                let kid = header
+                    .kid
+                    .ok_or_else(|| Error::JwtInvalid("missing `kid` header".to_string()))?;
+                self.rs256_key(&kid).await?
+            }
+            alg => return Err(Error::JwtInvalid(format!("unsupported algorithm {alg:?}"))),
+        };
+
+        let mut validation = Validation::new(header.alg);
+        match &self.config.issuer {
+            Some(issuer) => validation.set_issuer(&[issuer]),
+            None => validation.iss = None,
+        }
+        match &self.config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let data = decode::<Value>(token, &key, &validation)
+            .map_err(|e| Error::JwtInvalid(e.to_string()))?;
+        let sub = data
+            .claims
+            .get("sub")
+            .and_then(Value::as_str)
+            .ok_or_else(|| Error::JwtInvalid("missing `sub` claim".to_string()))?
+            .to_string();
+        let roles = extract_roles(&data.claims, &self.config.role_claim);
+        let tenant = extract_roles(&data.claims, &self.config.tenant_claim)
+            .into_iter()
+            .next();
+
+        Ok(JwtClaims { sub, roles, tenant })
+    }
+
+    /// Key of the JWKS matching `kid`, the set is refetched once expired or when the key is
+    /// unknown (key rotation), at most every `JWKS_MIN_REFRESH`
+    async fn rs256_key(&self, kid: &str) -> Result<DecodingKey> {
+        {
+            let cache = self.jwks.read().await;
+            if let Some(cached) = cache.as_ref() {
+                let expired = cached.fetched_at.elapsed() > self.config.jwks_ttl;
+                if let Some(jwk) = cached.keys.find(kid) {
+                    if !expired {
+                        return DecodingKey::from_jwk(jwk)
+                            .map_err(|e| Error::JwtInvalid(e.to_string()));
+                    }
+                } else if !expired && cached.fetched_at.elapsed() < JWKS_MIN_REFRESH {
+                    return Err(Error::JwtInvalid(format!("unknown key `{kid}`")));
+                }
+            }
+        }
+
+        let keys = self.fetch_jwks().await?;
+        let key = keys
+            .find(kid)
+            .map(|jwk| DecodingKey::from_jwk(jwk).map_err(|e| Error::JwtInvalid(e.to_string())));
+        *self.jwks.write().await = Some(CachedJwks {
+            keys,
+            fetched_at: Instant::now(),
+        });
+        key.unwrap_or_else(|| Err(Error::JwtInvalid(format!("unknown key `{kid}`"))))
+    }
+
+    async fn fetch_jwks(&self) -> Result<JwkSet> {
+        let url = self
+            .config
+            .jwks_url
+            .as_ref()
+            .ok_or_else(|| Error::JwtInvalid("RS256 is not enabled".to_string()))?;
+        self.http
+            .get(url)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|e| Error::JwksFetch(e.to_string()))?
+            .json::<JwkSet>()
+            .await
+            .map_err(|e| Error::JwksFetch(e.to_string()))
+    }
+}
+
+/// Roles found at the dotted `path`, either a single string or an array of strings
+pub(crate) fn extract_roles(claims: &Value, path: &str) -> Vec<String> {
+    let value = path
+        .split('.')
+        .try_fold(claims, |value, key| value.get(key));
+    match value {
+        Some(Value::String(role)) => vec![role.clone()],
+        Some(Value::Array(roles)) => roles
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header, encode};
+    use serde_json::json;
+
+    fn hs256_validator() -> JwtValidator {
+        JwtValidator::new(JwtConfig {
+            issuer: Some("https://issuer.example.com/".to_string()),
+            audience: Some("embedding-server".to_string()),
+            jwks_url: None,
+            hs256_secret: Some("secret".to_string()),
+            role_claim: "realm_access.roles".to_string(),
+            tenant_claim: "tenant_id".to_string(),
+            jwks_ttl: Duration::from_secs(600),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_validate_hs256() -> Result<()> {
+        let claims = json!({
+            "sub": "user-1",
+            "iss": "https://issuer.example.com/",
+            "aud": "embedding-server",
+            "exp": 4_102_444_800u64,
+            "realm_access": {"roles": ["admin", "offline_access"]},
+            "tenant_id": "acme",
+        });
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert!(JwtValidator::looks_like_jwt(&token));
+
+        let validated = hs256_validator().validate(&token).await?;
+        assert_eq!(validated.sub, "user-1");
+        assert_eq!(validated.roles, vec!["admin", "offline_access"]);
+        assert_eq!(validated.tenant.as_deref(), Some("acme"));
+
+        let wrong_key = encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(b"other"),
+        )
+        .unwrap();
+        assert!(hs256_validator().validate(&wrong_key).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_roles() {
+        let claims = json!({"role": "viewer"});
+        assert_eq!(extract_roles(&claims, "role"), vec!["viewer"]);
+        assert!(extract_roles(&claims, "missing.path").is_empty());
+    }
+}
+// endregion: Unit Test
@@ -1,4 +1,6 @@
 pub mod bearer;
 mod config;
 pub mod error;
+pub mod introspection;
+pub mod jwt;
 pub mod token;
@@ -15,12 +15,78 @@ pub fn config() -> &'static Config {
 
 pub struct AuthConfig {
     pub db_url: String,
+    /// Read replica of the searches and listings (`DATABASE_READ_URL`)
+    pub db_read_url: Option<String>,
+    /// Connections of the pool (`DB_MAX_CONNECTIONS`), kept open at least (`DB_MIN_CONNECTIONS`)
+    pub db_max_connections: u32,
+    pub db_min_connections: u32,
+    /// Wait for a free connection before a query fails (`DB_ACQUIRE_TIMEOUT_SEC`)
+    pub db_acquire_timeout_sec: u64,
+    /// `statement_timeout` of every connection (`DB_STATEMENT_TIMEOUT_MS`), 0 keeps the server one
+    pub db_statement_timeout_ms: u64,
+    /// Idle connections above the minimum are closed after (`DB_IDLE_TIMEOUT_SEC`), 0 never
+    pub db_idle_timeout_sec: u64,
+    /// Connections are recycled after (`DB_MAX_LIFETIME_SEC`), 0 never
+    pub db_max_lifetime_sec: u64,
+    /// Attempts to connect at startup, with a growing backoff (`DB_CONNECT_RETRIES`)
+    pub db_connect_retries: u32,
+    /// Store the chunk text zstd compressed (`CHUNK_COMPRESSION=true`), defaults to plain text
+    pub chunk_compression: bool,
+    /// Bucket holding the chunk text (`CHUNK_CONTENT_BUCKET`), when set the DB keeps only offsets
+    pub chunk_content_bucket: Option<String>,
+    /// Number of chunk texts kept in the in-memory LRU cache (`CHUNK_CONTENT_CACHE_SIZE`)
+    pub chunk_content_cache_size: usize,
+    /// Vector index method of the chunk embeddings, `hnsw` or `ivfflat` (`VECTOR_INDEX_METHOD`)
+    pub vector_index_method: String,
+    /// HNSW `m` (`VECTOR_INDEX_M`)
+    pub vector_index_m: u32,
+    /// HNSW `ef_construction` (`VECTOR_INDEX_EF_CONSTRUCTION`)
+    pub vector_index_ef_construction: u32,
+    /// IVFFlat `lists` (`VECTOR_INDEX_LISTS`)
+    pub vector_index_lists: u32,
+    /// HNSW `ef_search` set on every search (`VECTOR_EF_SEARCH`)
+    pub vector_ef_search: u32,
+    /// IVFFlat `probes` set on every search (`VECTOR_IVFFLAT_PROBES`)
+    pub vector_ivfflat_probes: u32,
+    /// Whether the stored embeddings are normalized (`EMBEDDINGS_NORMALIZED`), picks the distance
+    pub embeddings_normalized: bool,
+    /// Distance override, `cosine`, `ip` or `l2` (`VECTOR_DISTANCE`)
+    pub vector_distance: Option<String>,
+    /// Column type of the chunk embeddings, `vector`, `halfvec` or `bit` (`EMBEDDING_STORAGE`)
+    pub embedding_storage: String,
 }
 
 impl AuthConfig {
     pub fn load_from_env() -> lib_utils::error::Result<AuthConfig> {
         let db_url = get_env("DATABASE_URL")?;
-        Ok(AuthConfig { db_url })
+        let chunk_compression = get_env("CHUNK_COMPRESSION").unwrap_or(false);
+        let chunk_content_bucket = get_env("CHUNK_CONTENT_BUCKET").ok();
+        let chunk_content_cache_size = get_env("CHUNK_CONTENT_CACHE_SIZE").unwrap_or(10_000);
+        Ok(AuthConfig {
+            db_url,
+            db_read_url: get_env("DATABASE_READ_URL").ok(),
+            db_max_connections: get_env("DB_MAX_CONNECTIONS").unwrap_or(5),
+            db_min_connections: get_env("DB_MIN_CONNECTIONS").unwrap_or(0),
+            db_acquire_timeout_sec: get_env("DB_ACQUIRE_TIMEOUT_SEC").unwrap_or(30),
+            db_statement_timeout_ms: get_env("DB_STATEMENT_TIMEOUT_MS").unwrap_or(0),
+            db_idle_timeout_sec: get_env("DB_IDLE_TIMEOUT_SEC").unwrap_or(600),
+            db_max_lifetime_sec: get_env("DB_MAX_LIFETIME_SEC").unwrap_or(1800),
+            db_connect_retries: get_env("DB_CONNECT_RETRIES").unwrap_or(5),
+            chunk_compression,
+            chunk_content_bucket,
+            chunk_content_cache_size,
+            vector_index_method: get_env("VECTOR_INDEX_METHOD")
+                .unwrap_or_else(|_| "hnsw".to_string()),
+            vector_index_m: get_env("VECTOR_INDEX_M").unwrap_or(16),
+            vector_index_ef_construction: get_env("VECTOR_INDEX_EF_CONSTRUCTION").unwrap_or(64),
+            vector_index_lists: get_env("VECTOR_INDEX_LISTS").unwrap_or(100),
+            vector_ef_search: get_env("VECTOR_EF_SEARCH").unwrap_or(40),
+            vector_ivfflat_probes: get_env("VECTOR_IVFFLAT_PROBES").unwrap_or(10),
+            embeddings_normalized: get_env("EMBEDDINGS_NORMALIZED").unwrap_or(true),
+            vector_distance: get_env("VECTOR_DISTANCE").ok(),
+            embedding_storage: get_env("EMBEDDING_STORAGE")
+                .unwrap_or_else(|_| "vector".to_string()),
+        })
     }
 }
 
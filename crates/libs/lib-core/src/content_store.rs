@@ -0,0 +1,236 @@
+//! Chunk text stored in S3 instead of Postgres.
+//!
+//! Every file gets one object (`chunks/{file_id}.md`) holding the text of all its chunks back to
+//! back, the `file_chunks` rows only keep the byte range (`content_offset`, `content_length`).
+//! The text of chunks added to a file is written after the bytes of its existing chunks, whose
+//! ranges stay valid. Ranges are fetched on demand and kept in a small LRU cache.
+
+use crate::error::{Error, Result};
+use aws_sdk_s3::Client;
+use lib_storage::config::encryption;
+use lib_storage::functions::file::{delete_file, download_file, download_range, upload_file};
+use lru::LruCache;
+#[cfg(test)]
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+/// Byte range of a chunk text in the file content object
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContentRange {
+    pub offset: i64,
+    pub length: i32,
+}
+
+enum Backend {
+    S3 {
+        client: Arc<Client>,
+        bucket: String,
+    },
+    /// Objects kept in memory, for the tests
+    #[cfg(test)]
+    Memory(Mutex<HashMap<String, Vec<u8>>>),
+}
+
+impl Backend {
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        match self {
+            Backend::S3 { client, bucket } => download_file(client, bucket, key)
+                .await
+                .map_err(|e| Error::Storage(e.to_string())),
+            #[cfg(test)]
+            Backend::Memory(objects) => objects
+                .lock()
+                .unwrap()
+                .get(key)
+                .cloned()
+                .ok_or_else(|| Error::Storage(format!("no object {key}"))),
+        }
+    }
+
+    async fn get_range(&self, key: &str, offset: u64, length: u64) -> Result<Vec<u8>> {
+        match self {
+            Backend::S3 { client, bucket } => download_range(client, bucket, key, offset, length)
+                .await
+                .map_err(|e| Error::Storage(e.to_string())),
+            #[cfg(test)]
+            Backend::Memory(_) => {
+                let data = self.get(key).await?;
+                data.get(offset as usize..(offset + length) as usize)
+                    .map(<[u8]>::to_vec)
+                    .ok_or_else(|| Error::Storage(format!("range out of object {key}")))
+            }
+        }
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        match self {
+            Backend::S3 { client, bucket } => upload_file(client, bucket, key, data, encryption())
+                .await
+                .map(|_| ())
+                .map_err(|e| Error::Storage(e.to_string())),
+            #[cfg(test)]
+            Backend::Memory(objects) => {
+                objects.lock().unwrap().insert(key.to_string(), data);
+                Ok(())
+            }
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match self {
+            Backend::S3 { client, bucket } => delete_file(client, bucket, key)
+                .await
+                .map_err(|e| Error::Storage(e.to_string())),
+            #[cfg(test)]
+            Backend::Memory(objects) => {
+                objects.lock().unwrap().remove(key);
+                Ok(())
+            }
+        }
+    }
+}
+
+pub struct ContentStore {
+    backend: Backend,
+    cache: Mutex<LruCache<(i64, i64), Arc<String>>>,
+}
+
+impl std::fmt::Debug for ContentStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bucket = match &self.backend {
+            Backend::S3 { bucket, .. } => bucket.as_str(),
+            #[cfg(test)]
+            Backend::Memory(_) => "memory",
+        };
+        f.debug_struct("ContentStore")
+            .field("bucket", &bucket)
+            .finish()
+    }
+}
+
+impl ContentStore {
+    pub fn new(client: Arc<Client>, bucket: String, cache_size: usize) -> Self {
+        Self::with_backend(Backend::S3 { client, bucket }, cache_size)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn memory() -> Self {
+        Self::with_backend(Backend::Memory(Mutex::default()), 16)
+    }
+
+    fn with_backend(backend: Backend, cache_size: usize) -> Self {
+        let cache_size = NonZeroUsize::new(cache_size).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            backend,
+            cache: Mutex::new(LruCache::new(cache_size)),
+        }
+    }
+
+    pub fn object_key(file_id: i64) -> String {
+        format!("chunks/{file_id}.md")
+    }
+
+    /// Write `data` after the first `end` bytes of the object of the file, the ranges below `end`
+    /// stay valid. With an `end` of 0 the object is replaced.
+    pub async fn write_file(&self, file_id: i64, end: i64, data: Vec<u8>) -> Result<()> {
+        let key = Self::object_key(file_id);
+        let data = match end {
+            0 => data,
+            end => {
+                let mut object = self.backend.get(&key).await?;
+                if (object.len() as i64) < end {
+                    return Err(Error::Storage(format!(
+                        "object {key} is shorter than the text of its chunks"
+                    )));
+                }
+                object.truncate(end as usize);
+                object.extend(data);
+                object
+            }
+        };
+        self.backend.put(&key, data).await?;
+
+        // The offsets of a replaced object are reused by the new chunks
+        if end == 0 {
+            self.evict_file(file_id);
+        }
+        Ok(())
+    }
+
+    pub async fn read(&self, file_id: i64, range: ContentRange) -> Result<String> {
+        let key = (file_id, range.offset);
+        if let Some(content) = self.cache.lock().unwrap().get(&key) {
+            return Ok(content.to_string());
+        }
+
+        let data = self
+            .backend
+            .get_range(
+                &Self::object_key(file_id),
+                range.offset as u64,
+                range.length as u64,
+            )
+            .await?;
+        let content = String::from_utf8(data)
+            .map_err(|_| Error::Custom(format!("chunk content of file {file_id} is not utf-8")))?;
+
+        self.cache
+            .lock()
+            .unwrap()
+            .put(key, Arc::new(content.clone()));
+        Ok(content)
+    }
+
+    pub async fn delete_file(&self, file_id: i64) -> Result<()> {
+        self.evict_file(file_id);
+        self.backend.delete(&Self::object_key(file_id)).await
+    }
+
+    fn evict_file(&self, file_id: i64) {
+        let mut cache = self.cache.lock().unwrap();
+        let keys: Vec<(i64, i64)> = cache
+            .iter()
+            .filter(|((id, _), _)| *id == file_id)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in keys {
+            cache.pop(&key);
+        }
+    }
+}
+
+/// Concatenate the chunk texts and compute the byte range of each one, once written after the
+/// first `end` bytes of the object
+pub fn pack_contents(end: i64, contents: &[&str]) -> (Vec<u8>, Vec<ContentRange>) {
+    let mut data = Vec::with_capacity(contents.iter().map(|c| c.len()).sum());
+    let mut ranges = Vec::with_capacity(contents.len());
+    for content in contents {
+        ranges.push(ContentRange {
+            offset: end + data.len() as i64,
+            length: content.len() as i32,
+        });
+        data.extend_from_slice(content.as_bytes());
+    }
+    (data, ranges)
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_contents() {
+        let (data, ranges) = pack_contents(4, &["Hello ", "wörld", ""]);
+        assert_eq!(ranges.len(), 3);
+        assert_eq!(ranges[0].offset, 4);
+        for (range, expected) in ranges.iter().zip(["Hello ", "wörld", ""]) {
+            let start = range.offset as usize - 4;
+            let end = start + range.length as usize;
+            assert_eq!(&data[start..end], expected.as_bytes());
+        }
+    }
+}
+
+// endregion: Unit Test
@@ -1,11 +1,18 @@
 use crate::error::{Error, Result};
 use crate::model::user::Role;
 
+/// Tenant of the users, files and chunks created before multi-tenancy or without a tenant claim
+pub const DEFAULT_TENANT: &str = "default";
+
 #[derive(Clone, Debug)]
 pub struct Ctx {
     user_id: String,
     /// Note: For the future ACS (Access Control System via API_KEY or Token)
     role: Option<Role>,
+    /// Every model query of the request is scoped to this tenant
+    tenant_id: String,
+    /// Only set for the root key, which acts on every tenant
+    root: bool,
 }
 
 // Constructors.
@@ -14,6 +21,19 @@ impl Ctx {
         Ctx {
             user_id: "roots".to_string(),
             role: None,
+            tenant_id: DEFAULT_TENANT.to_string(),
+            root: false,
+        }
+    }
+
+    /// Context of the `--api-key` root key, an admin of every tenant. Never built from the
+    /// subject of a token, a user named `root` is not the root key.
+    pub fn root_key() -> Self {
+        Ctx {
+            user_id: "root".to_string(),
+            role: Some(Role::Admin),
+            tenant_id: DEFAULT_TENANT.to_string(),
+            root: true,
         }
     }
 
@@ -21,7 +41,12 @@ impl Ctx {
         if user_id == "roots" {
             Err(Error::CtxCannotNewRootCtx)
         } else {
-            Ok(Self { user_id, role })
+            Ok(Self {
+                user_id,
+                role,
+                tenant_id: DEFAULT_TENANT.to_string(),
+                root: false,
+            })
         }
     }
 
@@ -31,6 +56,12 @@ impl Ctx {
         ctx.role = Some(role);
         ctx
     }
+
+    pub fn with_tenant(&self, tenant_id: impl Into<String>) -> Ctx {
+        let mut ctx = self.clone();
+        ctx.tenant_id = tenant_id.into();
+        ctx
+    }
 }
 
 // Property Accessors.
@@ -43,4 +74,13 @@ impl Ctx {
     pub fn role(&self) -> Option<Role> {
         self.role.clone()
     }
+
+    pub fn tenant_id(&self) -> String {
+        self.tenant_id.clone()
+    }
+
+    /// Whether the request comes with the root key, allowed to cross tenants
+    pub fn is_root(&self) -> bool {
+        self.root
+    }
 }
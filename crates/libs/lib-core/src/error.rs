@@ -9,6 +9,18 @@ pub enum Error {
     CtxCannotNewRootCtx,
     SQLXFailed(String),
     FileNotFound,
+    Storage(String),
+    ContentStoreNotConfigured,
+    MigrationFailed(String),
+    /// Chunk repeating the text of a chunk of its file (`idx_chunk_file_content_hash`)
+    DuplicateContent,
+    /// Embedding whose length is not the dimension of its collection
+    DimensionMismatch {
+        collection: String,
+        expected: i32,
+        actual: usize,
+        model: Option<String>,
+    },
 }
 
 // region:    --- Error Boilerplate
@@ -32,3 +44,8 @@ impl From<sqlx::Error> for Error {
         Error::SQLXFailed(err.to_string())
     }
 }
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Custom(err.to_string())
+    }
+}
@@ -1,6 +1,8 @@
 pub mod _dev_utils;
 mod config;
+pub mod content_store;
 pub mod ctx;
 pub mod database;
 pub mod error;
 pub mod model;
+pub mod vector_index;
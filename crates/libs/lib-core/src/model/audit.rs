@@ -0,0 +1,199 @@
+use crate::ctx::Ctx;
+use crate::database::ModelManager;
+use crate::error::Result;
+use crate::model::pagination::HistoryFilter;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use sqlx::types::chrono::NaiveDateTime;
+
+// region: Structs
+
+/// Mutation of the admin API or of the cron schedule, `before` and `after` are the JSON of its
+/// target, `null` when it did not exist
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct AuditEntry {
+    pub audit_id: i64,
+    pub tenant_id: String,
+    /// User id of the caller
+    pub actor: String,
+    pub kind: String,
+    /// e.g. `user.role` or `cron.add`
+    pub action: String,
+    /// Id of the user, job, model, route or collection changed
+    pub target: String,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub created_at: NaiveDateTime,
+}
+
+/// What an audit entry changed, the `status` of the audit history
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditKind {
+    User,
+    Cron,
+    Model,
+    /// Limits, rate limit tiers, sampling, disabled routes and collection settings
+    Setting,
+}
+
+impl AuditKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditKind::User => "user",
+            AuditKind::Cron => "cron",
+            AuditKind::Model => "model",
+            AuditKind::Setting => "setting",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditEntryForCreate {
+    pub kind: AuditKind,
+    pub action: String,
+    pub target: String,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+impl AuditEntryForCreate {
+    pub fn new(kind: AuditKind, action: &str, target: impl Into<String>) -> Self {
+        Self {
+            kind,
+            action: action.to_string(),
+            target: target.into(),
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn before<T: Serialize>(mut self, before: &T) -> Self {
+        self.before = serde_json::to_value(before).ok();
+        self
+    }
+
+    pub fn after<T: Serialize>(mut self, after: &T) -> Self {
+        self.after = serde_json::to_value(after).ok();
+        self
+    }
+}
+
+// endregion: Structs
+
+// region: CRUD
+
+/// Entries are written with the user and tenant of the `Ctx` and listed per tenant
+pub struct AuditMac;
+
+impl AuditMac {
+    pub async fn record(
+        mm: &ModelManager,
+        ctx: &Ctx,
+        entry: AuditEntryForCreate,
+    ) -> Result<AuditEntry> {
+        let db = mm.db();
+        let entry = sqlx::query_as::<_, AuditEntry>(
+            r#"
+            INSERT INTO audit_log (tenant_id, actor, kind, action, target, before, after)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+            "#,
+        )
+        .bind(ctx.tenant_id())
+        .bind(ctx.user_id())
+        .bind(entry.kind.as_str())
+        .bind(entry.action)
+        .bind(entry.target)
+        .bind(entry.before)
+        .bind(entry.after)
+        .fetch_one(db)
+        .await?;
+
+        Ok(entry)
+    }
+
+    /// Page of the audit log, latest entries first, see `HistoryFilter`
+    pub async fn find_entries(
+        mm: &ModelManager,
+        tenant_id: &str,
+        filter: &HistoryFilter<AuditKind>,
+    ) -> Result<Vec<AuditEntry>> {
+        let db = mm.db();
+        let (from, to) = filter.naive_range();
+        let entries = sqlx::query_as::<_, AuditEntry>(
+            r#"
+            SELECT * FROM audit_log
+            WHERE tenant_id = $1
+                AND ($2::BIGINT IS NULL OR audit_id < $2)
+                AND ($3::TIMESTAMP IS NULL OR created_at >= $3)
+                AND ($4::TIMESTAMP IS NULL OR created_at < $4)
+                AND ($5::TEXT IS NULL OR kind = $5)
+            ORDER BY audit_id DESC
+            LIMIT $6
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(filter.cursor)
+        .bind(from)
+        .bind(to)
+        .bind(filter.status.map(AuditKind::as_str))
+        .bind(filter.limit())
+        .fetch_all(db)
+        .await?;
+
+        Ok(entries)
+    }
+}
+
+// endregion: CRUD
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils::init_dev;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_record_and_find_entries() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev(db);
+        let ctx = Ctx::new("audit-admin".to_string(), None)?.with_tenant("tenant-audit");
+
+        let role = AuditEntryForCreate::new(AuditKind::User, "user.role", "audit-user")
+            .before(&json!({"role": "viewer"}))
+            .after(&json!({"role": "admin"}));
+        let recorded = AuditMac::record(&mm, &ctx, role).await?;
+        assert_eq!(recorded.actor, "audit-admin");
+        assert_eq!(recorded.before, Some(json!({"role": "viewer"})));
+        let job = AuditEntryForCreate::new(AuditKind::Cron, "cron.delete", "job-1");
+        let latest = AuditMac::record(&mm, &ctx, job).await?;
+        assert_eq!(latest.after, None);
+
+        let entries =
+            AuditMac::find_entries(&mm, "tenant-audit", &HistoryFilter::default()).await?;
+        assert_eq!(entries[0].audit_id, latest.audit_id);
+        let filter = HistoryFilter {
+            status: Some(AuditKind::User),
+            ..Default::default()
+        };
+        let users = AuditMac::find_entries(&mm, "tenant-audit", &filter).await?;
+        assert!(users.iter().all(|entry| entry.kind == "user"));
+        assert!(
+            users
+                .iter()
+                .any(|entry| entry.audit_id == recorded.audit_id)
+        );
+
+        // Other tenants do not see the entries
+        assert!(
+            AuditMac::find_entries(&mm, "other-tenant", &HistoryFilter::default())
+                .await?
+                .iter()
+                .all(|entry| entry.audit_id != recorded.audit_id)
+        );
+        Ok(())
+    }
+}
+// endregion: Unit Test
@@ -0,0 +1,327 @@
+use crate::database::ModelManager;
+use crate::error::Result;
+use crate::model::pagination::HistoryFilter;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use sqlx::types::chrono::NaiveDateTime;
+use sqlx::{Connection, FromRow, PgConnection};
+
+// region: Structs
+
+/// Scheduled job, restored into the scheduler when the server starts
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct CronJob {
+    pub job_id: Uuid,
+    pub job_type: String,
+    pub cron: String,
+    /// IANA timezone `cron` is evaluated in, e.g. `Europe/Berlin`
+    pub timezone: String,
+    /// `skip`, `queue` or `allow` a run while another run of the job type is going
+    pub concurrency: String,
+    /// User id of the tenant that scheduled the job
+    pub owner: String,
+    /// Tenant of `owner`, the job is only visible within it
+    pub tenant_id: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CronJobForCreate {
+    pub job_id: Uuid,
+    pub job_type: String,
+    pub cron: String,
+    pub timezone: String,
+    pub concurrency: String,
+    pub owner: String,
+    pub tenant_id: String,
+}
+
+/// Run of a scheduled job, skipped runs are recorded too
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct JobRun {
+    pub run_id: i64,
+    pub job_id: Uuid,
+    pub job_type: String,
+    pub status: String,
+    pub error: Option<String>,
+    pub started_at: NaiveDateTime,
+    /// `None` for skipped runs
+    pub duration_ms: Option<i64>,
+}
+
+/// `status` of the run history
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobRunStatus {
+    Succeeded,
+    Failed,
+    /// Another run of the job type was still going
+    Skipped,
+}
+
+impl JobRunStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobRunStatus::Succeeded => "succeeded",
+            JobRunStatus::Failed => "failed",
+            JobRunStatus::Skipped => "skipped",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JobRunForCreate {
+    pub job_id: Uuid,
+    pub job_type: String,
+    pub status: JobRunStatus,
+    pub error: Option<String>,
+    pub started_at: NaiveDateTime,
+    pub duration_ms: Option<i64>,
+}
+
+/// Session advisory lock of a job type, held by one replica at a time. The lock lives on a
+/// connection detached from the pool: when the replica holding it dies, Postgres closes the
+/// connection and releases the lock.
+pub struct JobTypeLock {
+    conn: PgConnection,
+    job_type: String,
+}
+
+impl JobTypeLock {
+    /// Release the lock, closing its connection releases it as well
+    pub async fn unlock(mut self) -> Result<()> {
+        sqlx::query("SELECT pg_advisory_unlock(hashtextextended('cron:' || $1, 0))")
+            .bind(&self.job_type)
+            .execute(&mut self.conn)
+            .await?;
+        self.conn.close().await?;
+        Ok(())
+    }
+}
+
+// endregion: Structs
+
+// region: CRUD
+
+pub struct CronJobMac;
+
+impl CronJobMac {
+    pub async fn create_job(mm: &ModelManager, job: CronJobForCreate) -> Result<CronJob> {
+        let db = mm.db();
+        let job = sqlx::query_as::<_, CronJob>(
+            r#"
+            INSERT INTO cron_jobs (job_id, job_type, cron, timezone, concurrency, owner, tenant_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+            "#,
+        )
+        .bind(job.job_id)
+        .bind(job.job_type)
+        .bind(job.cron)
+        .bind(job.timezone)
+        .bind(job.concurrency)
+        .bind(job.owner)
+        .bind(job.tenant_id)
+        .fetch_one(db)
+        .await?;
+
+        Ok(job)
+    }
+
+    pub async fn get_all_jobs(mm: &ModelManager) -> Result<Vec<CronJob>> {
+        let db = mm.db();
+        let jobs = sqlx::query_as::<_, CronJob>(
+            r#"
+            SELECT * FROM cron_jobs ORDER BY created_at
+            "#,
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(jobs)
+    }
+
+    pub async fn delete_job(mm: &ModelManager, job_id: Uuid) -> Result<u64> {
+        let res = sqlx::query(
+            r#"
+            DELETE FROM cron_jobs WHERE job_id = $1
+            "#,
+        )
+        .bind(job_id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    pub async fn record_run(mm: &ModelManager, run: JobRunForCreate) -> Result<JobRun> {
+        let db = mm.db();
+        let run = sqlx::query_as::<_, JobRun>(
+            r#"
+            INSERT INTO job_runs (job_id, job_type, status, error, started_at, duration_ms)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+        )
+        .bind(run.job_id)
+        .bind(run.job_type)
+        .bind(run.status.as_str())
+        .bind(run.error)
+        .bind(run.started_at)
+        .bind(run.duration_ms)
+        .fetch_one(db)
+        .await?;
+
+        Ok(run)
+    }
+
+    /// Lock of `job_type`, `None` while another replica holds it
+    pub async fn try_lock_job_type(
+        mm: &ModelManager,
+        job_type: &str,
+    ) -> Result<Option<JobTypeLock>> {
+        // Not returned to the pool, a lock left behind would be held by the next user
+        let mut conn = mm.db().acquire().await?.detach();
+        let locked: bool =
+            sqlx::query_scalar("SELECT pg_try_advisory_lock(hashtextextended('cron:' || $1, 0))")
+                .bind(job_type)
+                .fetch_one(&mut conn)
+                .await?;
+        if !locked {
+            conn.close().await?;
+            return Ok(None);
+        }
+
+        Ok(Some(JobTypeLock {
+            conn,
+            job_type: job_type.to_string(),
+        }))
+    }
+
+    /// Page of the run history of the job, latest runs first, see `HistoryFilter`
+    pub async fn find_runs(
+        mm: &ModelManager,
+        job_id: Uuid,
+        filter: &HistoryFilter<JobRunStatus>,
+    ) -> Result<Vec<JobRun>> {
+        let db = mm.db();
+        let (from, to) = filter.naive_range();
+        let runs = sqlx::query_as::<_, JobRun>(
+            r#"
+            SELECT * FROM job_runs
+            WHERE job_id = $1
+                AND ($2::BIGINT IS NULL OR run_id < $2)
+                AND ($3::TIMESTAMP IS NULL OR started_at >= $3)
+                AND ($4::TIMESTAMP IS NULL OR started_at < $4)
+                AND ($5::TEXT IS NULL OR status = $5)
+            ORDER BY run_id DESC
+            LIMIT $6
+            "#,
+        )
+        .bind(job_id)
+        .bind(filter.cursor)
+        .bind(from)
+        .bind(to)
+        .bind(filter.status.map(JobRunStatus::as_str))
+        .bind(filter.limit())
+        .fetch_all(db)
+        .await?;
+
+        Ok(runs)
+    }
+}
+
+// endregion: CRUD
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils::init_dev;
+
+    #[tokio::test]
+    async fn test_create_and_delete_job() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev(db);
+
+        let job_id = Uuid::new_v4();
+        CronJobMac::create_job(
+            &mm,
+            CronJobForCreate {
+                job_id,
+                job_type: "sync_s3_files".to_string(),
+                cron: "0 */10 * * * *".to_string(),
+                timezone: "Europe/Berlin".to_string(),
+                concurrency: "skip".to_string(),
+                owner: "tenant-a".to_string(),
+                tenant_id: "tenant-a".to_string(),
+            },
+        )
+        .await?;
+        let jobs = CronJobMac::get_all_jobs(&mm).await?;
+        assert!(
+            jobs.iter()
+                .any(|job| job.job_id == job_id && job.timezone == "Europe/Berlin")
+        );
+
+        let run = |status| JobRunForCreate {
+            job_id,
+            job_type: "sync_s3_files".to_string(),
+            status,
+            error: None,
+            started_at: chrono::Utc::now().naive_utc(),
+            duration_ms: None,
+        };
+        CronJobMac::record_run(&mm, run(JobRunStatus::Succeeded)).await?;
+        let skipped = CronJobMac::record_run(&mm, run(JobRunStatus::Skipped)).await?;
+        let runs = CronJobMac::find_runs(&mm, job_id, &HistoryFilter::default()).await?;
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].run_id, skipped.run_id);
+        let filter = HistoryFilter {
+            status: Some(JobRunStatus::Succeeded),
+            ..Default::default()
+        };
+        let succeeded = CronJobMac::find_runs(&mm, job_id, &filter).await?;
+        assert_eq!(succeeded.len(), 1);
+        assert_eq!(succeeded[0].status, "succeeded");
+
+        assert_eq!(CronJobMac::delete_job(&mm, job_id).await?, 1);
+        let jobs = CronJobMac::get_all_jobs(&mm).await?;
+        assert!(jobs.iter().all(|job| job.job_id != job_id));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_try_lock_job_type() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev(db);
+
+        let lock = CronJobMac::try_lock_job_type(&mm, "lock_test").await?;
+        assert!(lock.is_some());
+        // Held by another session, as by another replica
+        assert!(
+            CronJobMac::try_lock_job_type(&mm, "lock_test")
+                .await?
+                .is_none()
+        );
+        assert!(
+            CronJobMac::try_lock_job_type(&mm, "other_lock_test")
+                .await?
+                .is_some()
+        );
+
+        lock.unwrap().unlock().await?;
+        let relocked = CronJobMac::try_lock_job_type(&mm, "lock_test").await?;
+        assert!(relocked.is_some());
+        // Dropping the lock closes its connection, as when the replica dies
+        drop(relocked);
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(
+            CronJobMac::try_lock_job_type(&mm, "lock_test")
+                .await?
+                .is_some()
+        );
+        Ok(())
+    }
+}
+// endregion: Unit Test
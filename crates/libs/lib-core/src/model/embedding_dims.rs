@@ -0,0 +1,108 @@
+//! Dimension expected of the embeddings of a collection (file applicant). A vector of another
+//! length is rejected before it reaches the database, with the expected and actual dimensions and
+//! the model that produced it, instead of the pgvector error of the insert.
+
+use crate::database::ModelManager;
+use crate::error::{Error, Result};
+use crate::model::settings::SettingMac;
+use std::collections::HashMap;
+
+/// Settings key of the per collection dimensions, a map of collection (file applicant) to
+/// dimension. Collections without one take the dimension of the `file_chunks.embedding` column.
+pub const EMBEDDING_DIMENSION_SETTING: &str = "embedding_dimensions";
+
+/// Expected dimensions read once for a batch of chunks
+#[derive(Debug, Clone, Default)]
+pub struct ExpectedDims {
+    pub by_collection: HashMap<String, i32>,
+    /// Dimension declared by the column, `None` for a `vector` column without one
+    pub column: Option<i32>,
+}
+
+impl ExpectedDims {
+    pub fn for_collection(&self, collection: &str) -> Option<i32> {
+        self.by_collection.get(collection).copied().or(self.column)
+    }
+
+    /// Fails with `Error::DimensionMismatch` when `actual` is not the dimension of the collection
+    pub fn check(&self, collection: &str, actual: usize, model: Option<&str>) -> Result<()> {
+        match self.for_collection(collection) {
+            Some(expected) if expected as usize != actual => Err(Error::DimensionMismatch {
+                collection: collection.to_string(),
+                expected,
+                actual,
+                model: model.map(str::to_string),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct EmbeddingDimsMac;
+
+impl EmbeddingDimsMac {
+    pub async fn expected(mm: &ModelManager) -> Result<ExpectedDims> {
+        let by_collection = SettingMac::get_value(mm, EMBEDDING_DIMENSION_SETTING)
+            .await?
+            .unwrap_or_default();
+        Ok(ExpectedDims {
+            by_collection,
+            column: Self::column_dimension(mm).await?,
+        })
+    }
+
+    /// Dimension of `file_chunks.embedding`, pgvector stores it as the type modifier
+    pub async fn column_dimension(mm: &ModelManager) -> Result<Option<i32>> {
+        let (typmod,): (i32,) = sqlx::query_as(
+            r#"
+            SELECT atttypmod FROM pg_attribute
+            WHERE attrelid = 'file_chunks'::regclass AND attname = 'embedding'
+            "#,
+        )
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok((typmod > 0).then_some(typmod))
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils::init_dev;
+
+    #[test]
+    fn test_check() {
+        let dims = ExpectedDims {
+            by_collection: HashMap::from([("small".to_string(), 384)]),
+            column: Some(768),
+        };
+        assert!(dims.check("small", 384, None).is_ok());
+        assert!(dims.check("other", 768, None).is_ok());
+        match dims.check("small", 768, Some("bge-base")) {
+            Err(Error::DimensionMismatch {
+                collection,
+                expected,
+                actual,
+                model,
+            }) => {
+                assert_eq!(collection, "small");
+                assert_eq!(expected, 384);
+                assert_eq!(actual, 768);
+                assert_eq!(model.as_deref(), Some("bge-base"));
+            }
+            other => panic!("expected a dimension mismatch, got {other:?}"),
+        }
+        assert!(ExpectedDims::default().check("any", 3, None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_column_dimension() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev(db);
+        assert_eq!(EmbeddingDimsMac::column_dimension(&mm).await?, Some(768));
+        Ok(())
+    }
+}
+// endregion: Unit Test
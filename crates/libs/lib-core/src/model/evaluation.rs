@@ -0,0 +1,329 @@
+use crate::database::ModelManager;
+use crate::error::Result;
+use crate::model::pagination::HistoryFilter;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use sqlx::types::chrono::NaiveDateTime;
+
+// region: Structs
+
+/// Query of the golden set with the files a good search returns for it
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct GoldenQuery {
+    pub query_id: i64,
+    pub tenant_id: String,
+    pub query: String,
+    /// Instruction of instruct-style models, as given to `/search`
+    pub instruction: Option<String>,
+    pub relevant_files: Vec<i64>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GoldenQueryForCreate {
+    pub query: String,
+    #[serde(default)]
+    pub instruction: Option<String>,
+    pub relevant_files: Vec<i64>,
+}
+
+/// Metrics of one evaluation of the golden set of a tenant
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct EvaluationRun {
+    pub run_id: i64,
+    pub tenant_id: String,
+    pub model: String,
+    pub k: i32,
+    pub queries: i32,
+    /// Mean Recall@k
+    pub recall: f64,
+    /// Mean reciprocal rank of the first relevant file within the top k
+    pub mrr: f64,
+    /// Recall dropped beyond the threshold since the previous run
+    pub regression: bool,
+    pub created_at: NaiveDateTime,
+}
+
+/// `status` of the run history: the runs which raised a regression alert or the others
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Ok,
+    Regression,
+}
+
+#[derive(Debug, Clone)]
+pub struct EvaluationRunForCreate {
+    pub model: String,
+    pub k: i32,
+    pub queries: i32,
+    pub recall: f64,
+    pub mrr: f64,
+    pub regression: bool,
+}
+
+// endregion: Structs
+
+// region: Metrics
+
+/// Share of the `relevant` files found among the files of the `hits`, in rank order
+pub fn recall_at_k(hits: &[i64], relevant: &[i64]) -> f64 {
+    if relevant.is_empty() {
+        return 0.0;
+    }
+    let found = relevant.iter().filter(|file| hits.contains(file)).count();
+    found as f64 / relevant.len() as f64
+}
+
+/// `1 / rank` of the first relevant hit, 0 when none is relevant
+pub fn reciprocal_rank(hits: &[i64], relevant: &[i64]) -> f64 {
+    hits.iter()
+        .position(|file| relevant.contains(file))
+        .map_or(0.0, |rank| 1.0 / (rank + 1) as f64)
+}
+
+// endregion: Metrics
+
+// region: CRUD
+
+/// Every query is scoped to `tenant_id`, except `get_tenants` used by the evaluation job
+pub struct EvaluationMac;
+
+impl EvaluationMac {
+    pub async fn create_query(
+        mm: &ModelManager,
+        tenant_id: &str,
+        query: GoldenQueryForCreate,
+    ) -> Result<GoldenQuery> {
+        let db = mm.db();
+        let query = sqlx::query_as::<_, GoldenQuery>(
+            r#"
+            INSERT INTO golden_queries (tenant_id, query, instruction, relevant_files)
+            VALUES ($1, $2, $3, $4)
+            RETURNING *
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(query.query)
+        .bind(query.instruction)
+        .bind(query.relevant_files)
+        .fetch_one(db)
+        .await?;
+
+        Ok(query)
+    }
+
+    pub async fn list_queries(mm: &ModelManager, tenant_id: &str) -> Result<Vec<GoldenQuery>> {
+        let db = mm.db();
+        let queries = sqlx::query_as::<_, GoldenQuery>(
+            r#"
+            SELECT * FROM golden_queries WHERE tenant_id = $1 ORDER BY query_id
+            "#,
+        )
+        .bind(tenant_id)
+        .fetch_all(db)
+        .await?;
+
+        Ok(queries)
+    }
+
+    pub async fn delete_query(mm: &ModelManager, tenant_id: &str, query_id: i64) -> Result<u64> {
+        let res = sqlx::query(
+            r#"
+            DELETE FROM golden_queries WHERE query_id = $1 AND tenant_id = $2
+            "#,
+        )
+        .bind(query_id)
+        .bind(tenant_id)
+        .execute(mm.db())
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Tenants with a golden set
+    pub async fn get_tenants(mm: &ModelManager) -> Result<Vec<String>> {
+        let tenants = sqlx::query_scalar(
+            r#"
+            SELECT DISTINCT tenant_id FROM golden_queries ORDER BY tenant_id
+            "#,
+        )
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(tenants)
+    }
+
+    pub async fn record_run(
+        mm: &ModelManager,
+        tenant_id: &str,
+        run: EvaluationRunForCreate,
+    ) -> Result<EvaluationRun> {
+        let db = mm.db();
+        let run = sqlx::query_as::<_, EvaluationRun>(
+            r#"
+            INSERT INTO evaluation_runs (tenant_id, model, k, queries, recall, mrr, regression)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(run.model)
+        .bind(run.k)
+        .bind(run.queries)
+        .bind(run.recall)
+        .bind(run.mrr)
+        .bind(run.regression)
+        .fetch_one(db)
+        .await?;
+
+        Ok(run)
+    }
+
+    /// Latest runs first
+    pub async fn list_runs(
+        mm: &ModelManager,
+        tenant_id: &str,
+        limit: i64,
+    ) -> Result<Vec<EvaluationRun>> {
+        let db = mm.db();
+        let runs = sqlx::query_as::<_, EvaluationRun>(
+            r#"
+            SELECT * FROM evaluation_runs WHERE tenant_id = $1 ORDER BY run_id DESC LIMIT $2
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(limit)
+        .fetch_all(db)
+        .await?;
+
+        Ok(runs)
+    }
+
+    /// Page of the run history, latest runs first, see `HistoryFilter`
+    pub async fn find_runs(
+        mm: &ModelManager,
+        tenant_id: &str,
+        filter: &HistoryFilter<RunStatus>,
+    ) -> Result<Vec<EvaluationRun>> {
+        let db = mm.db();
+        let (from, to) = filter.naive_range();
+        let runs = sqlx::query_as::<_, EvaluationRun>(
+            r#"
+            SELECT * FROM evaluation_runs
+            WHERE tenant_id = $1
+                AND ($2::BIGINT IS NULL OR run_id < $2)
+                AND ($3::TIMESTAMP IS NULL OR created_at >= $3)
+                AND ($4::TIMESTAMP IS NULL OR created_at < $4)
+                AND ($5::BOOLEAN IS NULL OR regression = $5)
+            ORDER BY run_id DESC
+            LIMIT $6
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(filter.cursor)
+        .bind(from)
+        .bind(to)
+        .bind(filter.status.map(|status| status == RunStatus::Regression))
+        .bind(filter.limit())
+        .fetch_all(db)
+        .await?;
+
+        Ok(runs)
+    }
+}
+
+// endregion: CRUD
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils::init_dev;
+
+    #[test]
+    fn test_metrics() {
+        let hits = [5, 3, 8, 3];
+        assert_eq!(recall_at_k(&hits, &[3, 8]), 1.0);
+        assert_eq!(recall_at_k(&hits, &[3, 9]), 0.5);
+        assert_eq!(recall_at_k(&hits, &[]), 0.0);
+        assert_eq!(reciprocal_rank(&hits, &[8, 9]), 1.0 / 3.0);
+        assert_eq!(reciprocal_rank(&hits, &[5]), 1.0);
+        assert_eq!(reciprocal_rank(&hits, &[9]), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_golden_queries_and_runs() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev(db);
+        let tenant = "tenant-eval";
+
+        let query = EvaluationMac::create_query(
+            &mm,
+            tenant,
+            GoldenQueryForCreate {
+                query: "refund policy".to_string(),
+                instruction: None,
+                relevant_files: vec![1001],
+            },
+        )
+        .await?;
+        assert!(
+            EvaluationMac::get_tenants(&mm)
+                .await?
+                .contains(&tenant.to_string())
+        );
+        assert!(
+            EvaluationMac::list_queries(&mm, "other-tenant")
+                .await?
+                .is_empty()
+        );
+
+        let run = EvaluationMac::record_run(
+            &mm,
+            tenant,
+            EvaluationRunForCreate {
+                model: "test-model".to_string(),
+                k: 10,
+                queries: 1,
+                recall: 1.0,
+                mrr: 0.5,
+                regression: false,
+            },
+        )
+        .await?;
+        let runs = EvaluationMac::list_runs(&mm, tenant, 1).await?;
+        assert_eq!(runs[0].run_id, run.run_id);
+        let filter = HistoryFilter {
+            cursor: Some(run.run_id),
+            ..Default::default()
+        };
+        assert!(
+            EvaluationMac::find_runs(&mm, tenant, &filter)
+                .await?
+                .iter()
+                .all(|older| older.run_id < run.run_id)
+        );
+        let filter = HistoryFilter {
+            status: Some(RunStatus::Regression),
+            ..Default::default()
+        };
+        assert!(
+            EvaluationMac::find_runs(&mm, tenant, &filter)
+                .await?
+                .iter()
+                .all(|run| run.regression)
+        );
+
+        assert_eq!(
+            EvaluationMac::delete_query(&mm, "other-tenant", query.query_id).await?,
+            0
+        );
+        assert_eq!(
+            EvaluationMac::delete_query(&mm, tenant, query.query_id).await?,
+            1
+        );
+        Ok(())
+    }
+}
+// endregion: Unit Test
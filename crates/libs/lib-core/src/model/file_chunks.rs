@@ -1,17 +1,377 @@
+use crate::config::auth_config;
+use crate::content_store::{ContentRange, pack_contents};
 use crate::database::ModelManager;
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::model::embedding_dims::EmbeddingDimsMac;
+use crate::model::files::FileMac;
+use crate::model::pagination::ListOptions;
+use crate::model::text_search::TextSearchMac;
+use crate::vector_index::{
+    EmbeddingStorage, MmrParams, SearchParams, StoredEmbedding, VectorIndexConfig, mmr_rerank,
+};
+use lib_utils::compression::{zstd_compress, zstd_decompress_to_string};
 use pgvector::Vector;
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sha2::{Digest, Sha256};
+use sqlx::types::Json;
+use sqlx::{FromRow, PgConnection};
+use std::collections::HashMap;
 
-#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+const ENCODING_PLAIN: &str = "plain";
+const ENCODING_ZSTD: &str = "zstd";
+/// Text lives in the S3 object of the file, the row only keeps its byte range
+const ENCODING_S3: &str = "s3";
+
+/// Provenance of a chunk used to cite it, stored in the `metadata` JSONB column
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ChunkMetadata {
+    /// Page of the document the chunk starts on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<i32>,
+    /// Section headings enclosing the chunk, outermost first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub heading_path: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+    /// ISO 639-3 code of the detected language
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Hyperlinks found in the text of the chunk
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<String>,
+    /// Lowercase address of the `From` header, for chunks of an email
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender: Option<String>,
+    /// Lowercase addresses of the `To` and `Cc` headers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FileChunk {
     pub chunk_id: i64,
     pub file_id: i64,
+    pub tenant_id: String,
     pub chunk_index: i32,
     pub content_md: Option<String>,
     pub embedding: Option<Vector>,
+    /// Model that computed `embedding`
+    pub embedding_model: Option<String>,
+    pub embedding_dim: Option<i32>,
+    /// Whether `embedding` has a unit L2 norm, see `search_chunks_by_embedding`
+    pub embedding_normalized: bool,
     pub token_count: Option<i32>,
+    /// How an input exceeding the model limits was handled: `split`, `truncated` or `skipped`
+    pub oversize: Option<String>,
+    pub metadata: ChunkMetadata,
+    /// `content_hash` of the text, a file keeps a single chunk per hash
+    pub content_hash: Option<String>,
+}
+
+/// Raw `file_chunks` row. `content_md` is either stored as plain text, zstd compressed in
+/// `content_zstd` or as a byte range of the file content object in S3 depending on
+/// `content_encoding`, see `into_chunk`.
+#[derive(Debug, FromRow)]
+pub(crate) struct FileChunkRow {
+    chunk_id: i64,
+    file_id: i64,
+    tenant_id: String,
+    chunk_index: i32,
+    content_md: Option<String>,
+    content_zstd: Option<Vec<u8>>,
+    content_encoding: String,
+    content_offset: Option<i64>,
+    content_length: Option<i32>,
+    embedding: Option<StoredEmbedding>,
+    embedding_model: Option<String>,
+    embedding_dim: Option<i32>,
+    embedding_normalized: bool,
+    token_count: Option<i32>,
+    oversize: Option<String>,
+    metadata: Json<ChunkMetadata>,
+    content_hash: Option<String>,
+}
+
+impl FileChunkRow {
+    fn content_range(&self) -> Option<ContentRange> {
+        match (self.content_offset, self.content_length) {
+            (Some(offset), Some(length)) => Some(ContentRange { offset, length }),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<FileChunkRow> for FileChunk {
+    type Error = crate::error::Error;
+
+    fn try_from(row: FileChunkRow) -> Result<Self> {
+        let content_md = match (row.content_encoding.as_str(), row.content_zstd) {
+            (ENCODING_ZSTD, Some(data)) => Some(zstd_decompress_to_string(data)?),
+            _ => row.content_md,
+        };
+        Ok(FileChunk {
+            chunk_id: row.chunk_id,
+            file_id: row.file_id,
+            tenant_id: row.tenant_id,
+            chunk_index: row.chunk_index,
+            content_md,
+            embedding: row.embedding.map(|embedding| embedding.0),
+            embedding_model: row.embedding_model,
+            embedding_dim: row.embedding_dim,
+            embedding_normalized: row.embedding_normalized,
+            token_count: row.token_count,
+            oversize: row.oversize,
+            metadata: row.metadata.0,
+            content_hash: row.content_hash,
+        })
+    }
+}
+
+/// Same as `FileChunk::try_from` but reads the text of S3 backed rows through the content store
+pub(crate) async fn into_chunk(mm: &ModelManager, row: FileChunkRow) -> Result<FileChunk> {
+    if row.content_encoding != ENCODING_S3 {
+        return FileChunk::try_from(row);
+    }
+
+    let content_md = match row.content_range() {
+        Some(range) => {
+            let store = mm.content_store().ok_or(Error::ContentStoreNotConfigured)?;
+            Some(store.read(row.file_id, range).await?)
+        }
+        None => None,
+    };
+    let mut chunk = FileChunk::try_from(row)?;
+    chunk.content_md = content_md;
+    Ok(chunk)
+}
+
+pub(crate) async fn into_chunks(
+    mm: &ModelManager,
+    rows: Vec<FileChunkRow>,
+) -> Result<Vec<FileChunk>> {
+    let mut chunks = Vec::with_capacity(rows.len());
+    for row in rows {
+        chunks.push(into_chunk(mm, row).await?);
+    }
+    Ok(chunks)
+}
+
+/// Hex SHA-256 of the text with its whitespace runs collapsed, so re-parsing a file with
+/// different line breaks or indentation yields the same hash
+pub fn content_hash(content: &str) -> String {
+    let normalized = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+/// `content_hash` stored for the text, blank texts are not deduplicated and have none
+fn stored_hash(content: Option<&str>) -> Option<String> {
+    content
+        .filter(|content| !content.trim().is_empty())
+        .map(content_hash)
+}
+
+/// A concurrent insert of the same text of a file loses on the unique index of the hashes
+fn duplicate_content(err: sqlx::Error) -> Error {
+    match &err {
+        sqlx::Error::Database(db_err)
+            if db_err.is_unique_violation()
+                && db_err.constraint() == Some("idx_chunk_file_content_hash") =>
+        {
+            Error::DuplicateContent
+        }
+        _ => err.into(),
+    }
+}
+
+/// Content as it is bound to the query: (content_md, content_zstd, content_encoding)
+type EncodedContent = (Option<String>, Option<Vec<u8>>, &'static str);
+
+pub(crate) fn encode_content(content_md: Option<String>, compress: bool) -> Result<EncodedContent> {
+    match content_md {
+        Some(content) if compress => Ok((None, Some(zstd_compress(content)?), ENCODING_ZSTD)),
+        content => Ok((content, None, ENCODING_PLAIN)),
+    }
+}
+
+/// Chunk of a bulk insert with its encoded content and, for S3 backed text, its byte range
+type BulkRow = (FileChunkForCreate, EncodedContent, Option<ContentRange>);
+
+/// Shift the `chunk_index` of appended chunks past the last chunk of their file
+fn append_after(chunks: &mut [FileChunkForCreate], first_index: i32) {
+    for chunk in chunks {
+        chunk.chunk_index += first_index;
+    }
+}
+
+/// Rows keeping the text in the DB, compressed with `CHUNK_COMPRESSION`
+fn db_rows(chunks: Vec<FileChunkForCreate>) -> Result<Vec<BulkRow>> {
+    let compress = auth_config().chunk_compression;
+    chunks
+        .into_iter()
+        .map(|chunk| {
+            let content = encode_content(chunk.content_md.clone(), compress)?;
+            Ok((chunk, content, None))
+        })
+        .collect()
+}
+
+/// Rows pointing into the content object from `end`, along with the text appended to it
+fn s3_rows(end: i64, chunks: Vec<FileChunkForCreate>) -> (Vec<u8>, Vec<BulkRow>) {
+    let contents: Vec<&str> = chunks
+        .iter()
+        .map(|c| c.content_md.as_deref().unwrap_or_default())
+        .collect();
+    let (data, ranges) = pack_contents(end, &contents);
+    let rows = chunks
+        .into_iter()
+        .zip(ranges)
+        .map(|(chunk, range)| (chunk, (None, None, ENCODING_S3), Some(range)))
+        .collect();
+    (data, rows)
+}
+
+/// Chunks per statement of `create_chunks_bulk`, bounds the size of the bound arrays
+const BULK_INSERT_ROWS: usize = 1000;
+
+/// Chunk loaded by `import_chunks` with an embedding computed elsewhere
+#[derive(Debug, Clone)]
+pub struct ChunkForImport {
+    pub file_id: i64,
+    pub content_md: String,
+    pub embedding: Vector,
+    /// Model that computed `embedding`
+    pub embedding_model: Option<String>,
+    /// Whether `embedding` has a unit L2 norm
+    pub embedding_normalized: bool,
+    pub metadata: ChunkMetadata,
+}
+
+#[derive(Debug, Serialize, Default, Clone, PartialEq)]
+pub struct ImportSummary {
+    pub imported: u64,
+    /// Chunks repeating a text of their file, left out
+    pub skipped: u64,
+}
+
+/// Columns of the temporary table `import_chunks` copies the chunks to, in the order of
+/// `copy_row`
+const IMPORT_COLUMNS: &str = "ord, file_id, content_md, content_zstd, content_encoding, content_hash, embedding, embedding_model, embedding_normalized, metadata, search_text";
+
+/// Bytes per `CopyData` message of `import_chunks`
+const COPY_CHUNK_BYTES: usize = 1 << 20;
+
+/// Row of `COPY ... FROM STDIN` in text format: tab separated fields of `IMPORT_COLUMNS`
+fn copy_row(ord: usize, chunk: ChunkForImport, compress: bool) -> Result<String> {
+    let hash = content_hash(&chunk.content_md);
+    let metadata = serde_json::to_string(&chunk.metadata)
+        .map_err(|e| Error::Custom(format!("Invalid chunk metadata: {e}")))?;
+    let embedding = format!(
+        "[{}]",
+        chunk
+            .embedding
+            .as_slice()
+            .iter()
+            .map(f32::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    let (content_md, content_zstd, content_encoding) =
+        encode_content(Some(chunk.content_md.clone()), compress)?;
+    // bytea in its hex format, the backslash is escaped by `copy_field`
+    let content_zstd = content_zstd.map(|data| {
+        data.iter().fold(String::from("\\x"), |hex, byte| {
+            hex + &format!("{byte:02x}")
+        })
+    });
+
+    let mut row = String::new();
+    copy_field(&mut row, Some(&ord.to_string()));
+    copy_field(&mut row, Some(&chunk.file_id.to_string()));
+    copy_field(&mut row, content_md.as_deref());
+    copy_field(&mut row, content_zstd.as_deref());
+    copy_field(&mut row, Some(content_encoding));
+    copy_field(&mut row, Some(&hash));
+    copy_field(&mut row, Some(&embedding));
+    copy_field(&mut row, chunk.embedding_model.as_deref());
+    copy_field(
+        &mut row,
+        Some(if chunk.embedding_normalized { "t" } else { "f" }),
+    );
+    copy_field(&mut row, Some(&metadata));
+    copy_field(&mut row, Some(&chunk.content_md));
+    // The separator of the last field ends the row
+    row.pop();
+    row.push('\n');
+    Ok(row)
+}
+
+/// Field of a `COPY` text row followed by its separator, `\N` for NULL
+fn copy_field(row: &mut String, value: Option<&str>) {
+    match value {
+        None => row.push_str("\\N"),
+        Some(value) => {
+            for c in value.chars() {
+                match c {
+                    '\\' => row.push_str("\\\\"),
+                    '\n' => row.push_str("\\n"),
+                    '\r' => row.push_str("\\r"),
+                    '\t' => row.push_str("\\t"),
+                    c => row.push(c),
+                }
+            }
+        }
+    }
+    row.push('\t');
+}
+
+/// Bulk inserted chunks as one array per column, expanded back into rows with UNNEST
+#[derive(Default)]
+struct ChunkColumns {
+    chunk_index: Vec<i32>,
+    content_md: Vec<Option<String>>,
+    content_zstd: Vec<Option<Vec<u8>>>,
+    content_encoding: Vec<&'static str>,
+    content_offset: Vec<Option<i64>>,
+    content_length: Vec<Option<i32>>,
+    content_hash: Vec<Option<String>>,
+    embedding: Vec<Option<Vector>>,
+    embedding_model: Vec<Option<String>>,
+    embedding_normalized: Vec<bool>,
+    token_count: Vec<Option<i32>>,
+    oversize: Vec<Option<String>>,
+    metadata: Vec<Json<ChunkMetadata>>,
+    /// Text before it is compressed or moved to S3, the lexemes are computed from it
+    search_text: Vec<Option<String>>,
+}
+
+impl FromIterator<BulkRow> for ChunkColumns {
+    fn from_iter<I: IntoIterator<Item = BulkRow>>(rows: I) -> Self {
+        let mut columns = Self::default();
+        for (chunk, (content_md, content_zstd, content_encoding), range) in rows {
+            columns.chunk_index.push(chunk.chunk_index);
+            columns.content_md.push(content_md);
+            columns.content_zstd.push(content_zstd);
+            columns.content_encoding.push(content_encoding);
+            let (offset, length) = range.map(|r| (r.offset, r.length)).unzip();
+            columns.content_offset.push(offset);
+            columns.content_length.push(length);
+            columns
+                .content_hash
+                .push(stored_hash(chunk.content_md.as_deref()));
+            columns.embedding.push(chunk.embedding);
+            columns.embedding_model.push(chunk.embedding_model);
+            columns
+                .embedding_normalized
+                .push(chunk.embedding_normalized);
+            columns.token_count.push(chunk.token_count);
+            columns.oversize.push(chunk.oversize);
+            columns.metadata.push(Json(chunk.metadata));
+            columns.search_text.push(chunk.content_md);
+        }
+        columns
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -20,161 +380,965 @@ pub struct FileChunkForCreate {
     pub chunk_index: i32,
     pub content_md: Option<String>,
     pub embedding: Option<Vector>,
+    /// Model that computed `embedding`
+    #[serde(default)]
+    pub embedding_model: Option<String>,
+    /// Whether `embedding` has a unit L2 norm
+    #[serde(default = "default_embedding_normalized")]
+    pub embedding_normalized: bool,
     pub token_count: Option<i32>,
+    pub oversize: Option<String>,
+    #[serde(default)]
+    pub metadata: ChunkMetadata,
+}
+
+pub(crate) fn default_embedding_normalized() -> bool {
+    true
 }
 #[derive(Debug, Deserialize, Clone)]
 pub struct FileChunkForUpdate {
     pub chunk_index: Option<i32>,
     pub content_md: Option<String>,
     pub embedding: Option<Vector>,
+    /// Model that computed `embedding`, only stored along with it
+    #[serde(default)]
+    pub embedding_model: Option<String>,
+    /// Whether the updated `embedding` has a unit L2 norm, only stored along with it
+    #[serde(default)]
+    pub embedding_normalized: Option<bool>,
     pub token_count: Option<i32>,
 }
 
+/// Chunks sharing their text across the files of a tenant
+#[derive(Debug, Serialize)]
+pub struct DedupReport {
+    pub total_chunks: i64,
+    /// Chunks with a `content_hash`, chunks stored before hashing was introduced have none
+    pub hashed_chunks: i64,
+    pub distinct_hashes: i64,
+    /// Hashed chunks repeating the text of another chunk
+    pub duplicate_chunks: i64,
+    pub duplicates: Vec<DuplicateContent>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct DuplicateContent {
+    pub content_hash: String,
+    pub chunks: i64,
+    pub files: i64,
+}
+
+/// Every query is scoped to `tenant_id`. Chunks take the tenant of their file, they can only be
+/// created for a live file of the tenant. Chunks of soft deleted files are not read nor searched.
 pub struct FileChunkMac;
 
 impl FileChunkMac {
-    pub async fn create_chunk(mm: &ModelManager, chunk: FileChunkForCreate) -> Result<FileChunk> {
+    /// Single chunk inserts always keep the text in the DB, use `create_file_chunks` to store
+    /// it in S3.
+    pub async fn create_chunk(
+        mm: &ModelManager,
+        tenant_id: &str,
+        chunk: FileChunkForCreate,
+    ) -> Result<FileChunk> {
+        Self::check_dimensions(mm, tenant_id, chunk.file_id, std::slice::from_ref(&chunk)).await?;
+        Self::insert_chunk(mm, tenant_id, chunk).await
+    }
+
+    async fn insert_chunk(
+        mm: &ModelManager,
+        tenant_id: &str,
+        chunk: FileChunkForCreate,
+    ) -> Result<FileChunk> {
         let db = mm.db();
-        let query = sqlx::query_as::<_, FileChunk>(
+        let hash = stored_hash(chunk.content_md.as_deref());
+        // The lexemes are computed from the text before it is compressed
+        let search_text = chunk.content_md.clone();
+        let (content_md, content_zstd, content_encoding) =
+            encode_content(chunk.content_md, auth_config().chunk_compression)?;
+        let embedding = EmbeddingStorage::load()?.cast("$6");
+        let query = sqlx::query_as::<_, FileChunkRow>(&format!(
             r#"
-            INSERT INTO file_chunks (file_id, chunk_index, content_md, embedding, token_count)
-            VALUES ($1, $2, $3, $4, $5)
+            INSERT INTO file_chunks (file_id, tenant_id, chunk_index, content_md, content_zstd, content_encoding, content_hash, embedding, embedding_model, embedding_dim, embedding_normalized, token_count, oversize, metadata, search_tsv)
+            SELECT file_id, tenant_id, $2, $3, $4, $5, $12, {embedding}, $11, vector_dims($6), $14, $7, $8, $9, to_tsvector(chunk_search_config(file_id), $13)
+            FROM files WHERE file_id = $1 AND tenant_id = $10 AND deleted_at IS NULL
             RETURNING *
-            "#,
-        )
+            "#
+        ))
         .bind(chunk.file_id)
         .bind(chunk.chunk_index)
-        .bind(chunk.content_md)
+        .bind(content_md)
+        .bind(content_zstd)
+        .bind(content_encoding)
         .bind(chunk.embedding.map(Vector::from))
-        .bind(chunk.token_count);
+        .bind(chunk.token_count)
+        .bind(chunk.oversize)
+        .bind(Json(chunk.metadata))
+        .bind(tenant_id)
+        .bind(chunk.embedding_model)
+        .bind(hash)
+        .bind(search_text)
+        .bind(chunk.embedding_normalized);
 
-        let chunk = query.fetch_one(db).await?;
-        Ok(chunk)
+        let chunk = query.fetch_one(db).await.map_err(duplicate_content)?;
+        FileChunk::try_from(chunk)
     }
 
-    pub async fn get_chunk_by_id(mm: &ModelManager, chunk_id: i64) -> Result<FileChunk> {
+    /// Append chunks to a file at once, their `chunk_index` counts from the last chunk of the
+    /// file (see `create_chunks_bulk`). With a content store configured the text of the chunks is
+    /// written to the object of the file after the text of its existing chunks and the rows only
+    /// keep the offsets, otherwise this is the same as `create_chunks_bulk`.
+    pub async fn create_file_chunks(
+        mm: &ModelManager,
+        tenant_id: &str,
+        file_id: i64,
+        mut chunks: Vec<FileChunkForCreate>,
+    ) -> Result<Vec<FileChunk>> {
+        let Some(store) = mm.content_store() else {
+            return Self::create_chunks_bulk(mm, tenant_id, file_id, chunks).await;
+        };
+        Self::check_dimensions(mm, tenant_id, file_id, &chunks).await?;
+
+        let mut tx = mm.db().begin().await?;
+        // Checked before the upload, the object of another tenant's file must not be replaced
+        let end = Self::lock_content_end(&mut tx, tenant_id, file_id).await?;
+        let first_index = Self::next_index(&mut tx, tenant_id, file_id).await?;
+        append_after(&mut chunks, first_index);
+        let (data, rows) = s3_rows(end, chunks);
+        let created = Self::insert_rows(&mut tx, tenant_id, file_id, rows).await?;
+        // Uploaded last, the text of the existing chunks is kept when the insert fails
+        store.write_file(file_id, end, data).await?;
+        tx.commit().await?;
+        Ok(created)
+    }
+
+    /// Replace the chunks of a file processed again: the previous chunks are deleted and the new
+    /// ones created in one transaction, a failure keeps the previous chunks. Returns the number
+    /// of chunks replaced along with the new ones.
+    pub async fn replace_file_chunks(
+        mm: &ModelManager,
+        tenant_id: &str,
+        file_id: i64,
+        chunks: Vec<FileChunkForCreate>,
+    ) -> Result<(u64, Vec<FileChunk>)> {
+        Self::check_dimensions(mm, tenant_id, file_id, &chunks).await?;
+
+        let mut tx = mm.db().begin().await?;
+        Self::lock_file(&mut tx, tenant_id, file_id).await?;
+        let replaced = sqlx::query(
+            r#"
+            DELETE FROM file_chunks WHERE file_id = $1 AND tenant_id = $2
+            "#,
+        )
+        .bind(file_id)
+        .bind(tenant_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        let created = match mm.content_store() {
+            Some(store) => {
+                let (data, rows) = s3_rows(0, chunks);
+                let created = Self::insert_rows(&mut tx, tenant_id, file_id, rows).await?;
+                // The object is only replaced once the rows are in, right before the commit
+                store.write_file(file_id, 0, data).await?;
+                created
+            }
+            None => {
+                let rows = db_rows(chunks)?;
+                Self::insert_rows(&mut tx, tenant_id, file_id, rows).await?
+            }
+        };
+        tx.commit().await?;
+        Ok((replaced, created))
+    }
+
+    /// Lock the live file of the tenant until the end of the transaction, so that writes to its
+    /// chunks and content object do not interleave
+    async fn lock_file(conn: &mut PgConnection, tenant_id: &str, file_id: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            SELECT file_id FROM files
+            WHERE file_id = $1 AND tenant_id = $2 AND deleted_at IS NULL
+            FOR UPDATE
+            "#,
+        )
+        .bind(file_id)
+        .bind(tenant_id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or(Error::FileNotFound)?;
+        Ok(())
+    }
+
+    /// Lock the file like `lock_file` and return the end of the text of its S3 backed chunks
+    async fn lock_content_end(
+        conn: &mut PgConnection,
+        tenant_id: &str,
+        file_id: i64,
+    ) -> Result<i64> {
+        Self::lock_file(conn, tenant_id, file_id).await?;
+
+        let end: i64 = sqlx::query_scalar(
+            r#"
+            SELECT COALESCE(MAX(content_offset + content_length), 0)::BIGINT FROM file_chunks
+            WHERE file_id = $1 AND content_encoding = $2
+            "#,
+        )
+        .bind(file_id)
+        .bind(ENCODING_S3)
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(end)
+    }
+
+    /// Append the chunks to a file with one `INSERT ... SELECT FROM UNNEST` per
+    /// `BULK_INSERT_ROWS` chunks, in a single transaction. The `chunk_index` of the chunks is an
+    /// offset from the last chunk of the file, read under the lock of the file so that concurrent
+    /// appends do not share indexes. The text is kept in the DB like `create_chunk` does.
+    pub async fn create_chunks_bulk(
+        mm: &ModelManager,
+        tenant_id: &str,
+        file_id: i64,
+        mut chunks: Vec<FileChunkForCreate>,
+    ) -> Result<Vec<FileChunk>> {
+        Self::check_dimensions(mm, tenant_id, file_id, &chunks).await?;
+        let mut tx = mm.db().begin().await?;
+        Self::lock_file(&mut tx, tenant_id, file_id).await?;
+        let first_index = Self::next_index(&mut tx, tenant_id, file_id).await?;
+        append_after(&mut chunks, first_index);
+        let rows = db_rows(chunks)?;
+        let created = Self::insert_rows(&mut tx, tenant_id, file_id, rows).await?;
+        tx.commit().await?;
+        Ok(created)
+    }
+
+    async fn insert_rows(
+        conn: &mut PgConnection,
+        tenant_id: &str,
+        file_id: i64,
+        rows: Vec<BulkRow>,
+    ) -> Result<Vec<FileChunk>> {
+        if rows.is_empty() {
+            return Ok(Vec::new());
+        }
+        let embedding = EmbeddingStorage::load()?.cast("c.embedding");
+        // The ordinality keeps the chunk ids in the order of the input
+        let query = format!(
+            r#"
+            INSERT INTO file_chunks (file_id, tenant_id, chunk_index, content_md, content_zstd, content_encoding, content_offset, content_length, content_hash, embedding, embedding_model, embedding_dim, embedding_normalized, token_count, oversize, metadata, search_tsv)
+            SELECT $1, $2, c.chunk_index, c.content_md, c.content_zstd, c.content_encoding, c.content_offset, c.content_length, c.content_hash, {embedding}, c.embedding_model, vector_dims(c.embedding), c.embedding_normalized, c.token_count, c.oversize, c.metadata, to_tsvector(chunk_search_config($1), c.search_text)
+            FROM UNNEST($3::int[], $4::text[], $5::bytea[], $6::text[], $7::bigint[], $8::int[], $9::text[], $10::vector[], $11::text[], $12::int[], $13::text[], $14::jsonb[], $15::text[], $16::bool[])
+                WITH ORDINALITY AS c(chunk_index, content_md, content_zstd, content_encoding, content_offset, content_length, content_hash, embedding, embedding_model, token_count, oversize, metadata, search_text, embedding_normalized, ord)
+            WHERE EXISTS (SELECT 1 FROM files WHERE file_id = $1 AND tenant_id = $2 AND deleted_at IS NULL)
+            ORDER BY c.ord
+            RETURNING *
+            "#
+        );
+
+        let mut created = Vec::with_capacity(rows.len());
+        let mut rows = rows.into_iter().peekable();
+        while rows.peek().is_some() {
+            let batch: ChunkColumns = rows.by_ref().take(BULK_INSERT_ROWS).collect();
+            let texts = batch.search_text.clone();
+            let mut inserted = sqlx::query_as::<_, FileChunkRow>(&query)
+                .bind(file_id)
+                .bind(tenant_id)
+                .bind(batch.chunk_index)
+                .bind(batch.content_md)
+                .bind(batch.content_zstd)
+                .bind(batch.content_encoding)
+                .bind(batch.content_offset)
+                .bind(batch.content_length)
+                .bind(batch.content_hash)
+                .bind(batch.embedding)
+                .bind(batch.embedding_model)
+                .bind(batch.token_count)
+                .bind(batch.oversize)
+                .bind(batch.metadata)
+                .bind(batch.search_text)
+                .bind(batch.embedding_normalized)
+                .fetch_all(&mut *conn)
+                .await
+                .map_err(duplicate_content)?;
+            // Nothing is inserted when the file is not a live file of the tenant
+            if inserted.len() != texts.len() {
+                return Err(Error::FileNotFound);
+            }
+            inserted.sort_by_key(|row| row.chunk_id);
+            for (row, text) in inserted.into_iter().zip(texts) {
+                // Already in hand, S3 backed rows would otherwise read it back
+                let mut chunk = FileChunk::try_from(row)?;
+                chunk.content_md = text;
+                created.push(chunk);
+            }
+        }
+        Ok(created)
+    }
+
+    /// Load chunks with precomputed embeddings, e.g. migrated from another vector store: `COPY`
+    /// to a temporary table, then a single `INSERT ... SELECT` appending them after the chunks of
+    /// their file, in one transaction. The text is kept in the DB like `create_chunks_bulk` does,
+    /// and a chunk repeating a text of its file is skipped.
+    pub async fn import_chunks(
+        mm: &ModelManager,
+        tenant_id: &str,
+        chunks: Vec<ChunkForImport>,
+    ) -> Result<ImportSummary> {
+        if chunks.is_empty() {
+            return Ok(ImportSummary::default());
+        }
+        // The embeddings must have the dimension of the collection of their file
+        let expected = EmbeddingDimsMac::expected(mm).await?;
+        let mut collections = HashMap::new();
+        for chunk in &chunks {
+            if !collections.contains_key(&chunk.file_id) {
+                let file = FileMac::get_file_by_id(mm, tenant_id, &chunk.file_id).await?;
+                collections.insert(chunk.file_id, file.applicant);
+            }
+            expected.check(
+                &collections[&chunk.file_id],
+                chunk.embedding.as_slice().len(),
+                chunk.embedding_model.as_deref(),
+            )?;
+        }
+
+        let compress = auth_config().chunk_compression;
+        let mut data = String::new();
+        for (ord, chunk) in chunks.into_iter().enumerate() {
+            data.push_str(&copy_row(ord, chunk, compress)?);
+        }
+
+        let mut tx = mm.db().begin().await?;
+        sqlx::query(
+            r#"
+            CREATE TEMP TABLE chunk_import (
+                ord BIGINT, file_id BIGINT, content_md TEXT, content_zstd BYTEA,
+                content_encoding TEXT, content_hash TEXT, embedding vector, embedding_model TEXT,
+                embedding_normalized BOOLEAN, metadata JSONB, search_text TEXT
+            ) ON COMMIT DROP
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+        let mut copy = tx
+            .copy_in_raw(&format!("COPY chunk_import ({IMPORT_COLUMNS}) FROM STDIN"))
+            .await?;
+        for part in data.as_bytes().chunks(COPY_CHUNK_BYTES) {
+            copy.send(part).await?;
+        }
+        let staged = copy.finish().await?;
+
+        let embedding = EmbeddingStorage::load()?.cast("c.embedding");
+        // Every file continues after its last chunk, in the order of the import
+        let inserted = sqlx::query(&format!(
+            r#"
+            INSERT INTO file_chunks (file_id, tenant_id, chunk_index, content_md, content_zstd, content_encoding, content_hash, embedding, embedding_model, embedding_dim, embedding_normalized, metadata, search_tsv)
+            SELECT c.file_id, $1, n.next_index + (ROW_NUMBER() OVER (PARTITION BY c.file_id ORDER BY c.ord))::int - 1,
+                c.content_md, c.content_zstd, c.content_encoding, c.content_hash, {embedding}, c.embedding_model,
+                vector_dims(c.embedding), c.embedding_normalized, c.metadata, to_tsvector(chunk_search_config(c.file_id), c.search_text)
+            FROM chunk_import c
+            JOIN files f ON f.file_id = c.file_id AND f.tenant_id = $1 AND f.deleted_at IS NULL
+            CROSS JOIN LATERAL (
+                SELECT COALESCE(MAX(chunk_index) + 1, 0) AS next_index FROM file_chunks
+                WHERE file_id = c.file_id AND tenant_id = $1
+            ) n
+            ORDER BY c.ord
+            ON CONFLICT (file_id, content_hash) WHERE content_hash IS NOT NULL DO NOTHING
+            "#
+        ))
+        .bind(tenant_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        tx.commit().await?;
+
+        Ok(ImportSummary {
+            imported: inserted,
+            skipped: staged.saturating_sub(inserted),
+        })
+    }
+
+    /// The embeddings must have the dimension of the collection of the file, see `embedding_dims`
+    async fn check_dimensions(
+        mm: &ModelManager,
+        tenant_id: &str,
+        file_id: i64,
+        chunks: &[FileChunkForCreate],
+    ) -> Result<()> {
+        if chunks.iter().all(|chunk| chunk.embedding.is_none()) {
+            return Ok(());
+        }
+        let file = FileMac::get_file_by_id(mm, tenant_id, &file_id).await?;
+        let expected = EmbeddingDimsMac::expected(mm).await?;
+        for chunk in chunks {
+            if let Some(embedding) = &chunk.embedding {
+                expected.check(
+                    &file.applicant,
+                    embedding.as_slice().len(),
+                    chunk.embedding_model.as_deref(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn get_chunk_by_id(
+        mm: &ModelManager,
+        tenant_id: &str,
+        chunk_id: i64,
+    ) -> Result<FileChunk> {
         let db = mm.db();
-        let query = sqlx::query_as::<_, FileChunk>(
+        let query = sqlx::query_as::<_, FileChunkRow>(
             r#"
-            SELECT * FROM file_chunks WHERE chunk_id = $1
+            SELECT * FROM file_chunks
+            WHERE chunk_id = $1 AND tenant_id = $2 AND deleted_at IS NULL
             "#,
         )
-        .bind(chunk_id);
+        .bind(chunk_id)
+        .bind(tenant_id);
 
         let chunk = query.fetch_one(db).await?;
-        Ok(chunk)
+        into_chunk(mm, chunk).await
     }
 
+    /// Updated text is written back to the DB, an S3 backed chunk switches to the configured DB
+    /// encoding.
     pub async fn update_chunk(
         mm: &ModelManager,
+        tenant_id: &str,
         chunk_id: i64,
         update: FileChunkForUpdate,
     ) -> Result<FileChunk> {
         let db = mm.db();
-        let query = sqlx::query_as::<_, FileChunk>(
+        let hash = update.content_md.as_deref().map(content_hash);
+        let search_text = update.content_md.clone();
+        // The encoding is only bound when the content is updated, otherwise all content columns
+        // are left untouched
+        let (content_md, content_zstd, content_encoding) = match update.content_md {
+            Some(content) => {
+                let (md, zstd, encoding) =
+                    encode_content(Some(content), auth_config().chunk_compression)?;
+                (md, zstd, Some(encoding))
+            }
+            None => (None, None, None),
+        };
+        let embedding = EmbeddingStorage::load()?.cast("$6");
+        let query = sqlx::query_as::<_, FileChunkRow>(&format!(
             r#"
             UPDATE file_chunks
             SET
                 chunk_index = COALESCE($2, chunk_index),
-                content_md = COALESCE($3, content_md),
-                embedding = COALESCE($4, embedding),
-                token_count = COALESCE($5, token_count)
-            WHERE chunk_id = $1
+                content_md = CASE WHEN $5::TEXT IS NULL THEN content_md ELSE $3 END,
+                content_zstd = CASE WHEN $5::TEXT IS NULL THEN content_zstd ELSE $4 END,
+                content_encoding = COALESCE($5, content_encoding),
+                content_offset = CASE WHEN $5::TEXT IS NULL THEN content_offset ELSE NULL END,
+                content_length = CASE WHEN $5::TEXT IS NULL THEN content_length ELSE NULL END,
+                content_hash = CASE WHEN $5::TEXT IS NULL THEN content_hash ELSE $10 END,
+                search_tsv = CASE WHEN $5::TEXT IS NULL THEN search_tsv ELSE to_tsvector(search_config, $11) END,
+                embedding = COALESCE({embedding}, embedding),
+                embedding_model = CASE WHEN $6::vector IS NULL THEN embedding_model ELSE $9 END,
+                embedding_dim = COALESCE(vector_dims($6), embedding_dim),
+                embedding_normalized = CASE WHEN $6::vector IS NULL THEN embedding_normalized ELSE COALESCE($12, TRUE) END,
+                token_count = COALESCE($7, token_count)
+            WHERE chunk_id = $1 AND tenant_id = $8
             RETURNING *
-            "#,
-        )
+            "#
+        ))
         .bind(chunk_id)
         .bind(update.chunk_index)
-        .bind(update.content_md)
+        .bind(content_md)
+        .bind(content_zstd)
+        .bind(content_encoding)
         .bind(update.embedding.map(Vector::from))
-        .bind(update.token_count);
+        .bind(update.token_count)
+        .bind(tenant_id)
+        .bind(update.embedding_model)
+        .bind(hash)
+        .bind(search_text)
+        .bind(update.embedding_normalized);
 
         let chunk = query.fetch_one(db).await?;
-        Ok(chunk)
+        into_chunk(mm, chunk).await
+    }
+
+    /// Embedding, model and normalization of the embedded chunks of the file by `content_hash`,
+    /// reused for the unchanged chunks when the file is processed again
+    pub async fn get_embeddings_by_hash(
+        mm: &ModelManager,
+        tenant_id: &str,
+        file_id: i64,
+    ) -> Result<HashMap<String, (Vector, Option<String>, bool)>> {
+        let rows = sqlx::query_as::<_, (String, StoredEmbedding, Option<String>, bool)>(
+            r#"
+            SELECT content_hash, embedding, embedding_model, embedding_normalized FROM file_chunks
+            WHERE file_id = $1 AND tenant_id = $2
+                AND content_hash IS NOT NULL AND embedding IS NOT NULL
+            "#,
+        )
+        .bind(file_id)
+        .bind(tenant_id)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(hash, embedding, model, normalized)| (hash, (embedding.0, model, normalized)))
+            .collect())
     }
 
-    pub async fn delete_chunk(mm: &ModelManager, chunk_id: i64) -> Result<u64> {
+    /// The `hashes` already stored for the file
+    pub async fn existing_hashes(
+        mm: &ModelManager,
+        tenant_id: &str,
+        file_id: i64,
+        hashes: &[String],
+    ) -> Result<Vec<String>> {
+        let existing = sqlx::query_scalar(
+            r#"
+            SELECT content_hash FROM file_chunks
+            WHERE file_id = $1 AND tenant_id = $2 AND content_hash = ANY($3)
+            "#,
+        )
+        .bind(file_id)
+        .bind(tenant_id)
+        .bind(hashes)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(existing)
+    }
+
+    /// Texts stored more than once across the live files of the tenant, the most repeated first
+    pub async fn dedup_report(
+        mm: &ModelManager,
+        tenant_id: &str,
+        limit: i64,
+    ) -> Result<DedupReport> {
+        let (total_chunks, hashed_chunks, distinct_hashes) = sqlx::query_as::<_, (i64, i64, i64)>(
+            r#"
+            SELECT COUNT(*), COUNT(content_hash), COUNT(DISTINCT content_hash)
+            FROM file_chunks
+            WHERE tenant_id = $1 AND deleted_at IS NULL
+            "#,
+        )
+        .bind(tenant_id)
+        .fetch_one(mm.db())
+        .await?;
+        let duplicates = sqlx::query_as::<_, DuplicateContent>(
+            r#"
+            SELECT content_hash, COUNT(*) AS chunks, COUNT(DISTINCT file_id) AS files
+            FROM file_chunks
+            WHERE tenant_id = $1 AND deleted_at IS NULL AND content_hash IS NOT NULL
+            GROUP BY content_hash
+            HAVING COUNT(*) > 1
+            ORDER BY chunks DESC, content_hash
+            LIMIT $2
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(limit)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(DedupReport {
+            total_chunks,
+            hashed_chunks,
+            distinct_hashes,
+            duplicate_chunks: hashed_chunks - distinct_hashes,
+            duplicates,
+        })
+    }
+
+    pub async fn delete_chunk(mm: &ModelManager, tenant_id: &str, chunk_id: i64) -> Result<u64> {
         let res = sqlx::query(
             r#"
-            DELETE FROM file_chunks WHERE chunk_id = $1
+            DELETE FROM file_chunks WHERE chunk_id = $1 AND tenant_id = $2
             "#,
         )
         .bind(chunk_id)
+        .bind(tenant_id)
         .execute(mm.db())
         .await?;
 
         Ok(res.rows_affected())
     }
 
-    pub async fn get_chunks_by_file_id(mm: &ModelManager, file_id: &i64) -> Result<Vec<FileChunk>> {
+    pub async fn get_chunks_by_file_id(
+        mm: &ModelManager,
+        tenant_id: &str,
+        file_id: &i64,
+    ) -> Result<Vec<FileChunk>> {
         let db = mm.db();
-        let chunks = sqlx::query_as::<_, FileChunk>(
+        let chunks = sqlx::query_as::<_, FileChunkRow>(
             r#"
-            SELECT * FROM file_chunks WHERE file_id = $1
+            SELECT * FROM file_chunks
+            WHERE file_id = $1 AND tenant_id = $2 AND deleted_at IS NULL
             ORDER BY chunk_index
             "#,
         )
         .bind(file_id)
+        .bind(tenant_id)
         .fetch_all(db)
         .await?;
 
-        Ok(chunks)
+        into_chunks(mm, chunks).await
+    }
+
+    /// Index following the last chunk of the file, `0` for a file without chunks
+    pub async fn next_chunk_index(mm: &ModelManager, tenant_id: &str, file_id: i64) -> Result<i32> {
+        let mut conn = mm.db().acquire().await?;
+        Self::next_index(&mut conn, tenant_id, file_id).await
+    }
+
+    async fn next_index(conn: &mut PgConnection, tenant_id: &str, file_id: i64) -> Result<i32> {
+        let (next,): (i32,) = sqlx::query_as(
+            r#"
+            SELECT COALESCE(MAX(chunk_index) + 1, 0) FROM file_chunks
+            WHERE file_id = $1 AND tenant_id = $2
+            "#,
+        )
+        .bind(file_id)
+        .bind(tenant_id)
+        .fetch_one(conn)
+        .await?;
+        Ok(next)
+    }
+
+    /// A page of the chunks of the tenant, of the file `file_id` if given, see `ListOptions`
+    pub async fn list_chunks(
+        mm: &ModelManager,
+        tenant_id: &str,
+        file_id: Option<i64>,
+        options: &ListOptions<i64>,
+    ) -> Result<Vec<FileChunk>> {
+        let chunks = sqlx::query_as::<_, FileChunkRow>(&format!(
+            r#"
+            SELECT * FROM file_chunks
+            WHERE tenant_id = $1
+                AND deleted_at IS NULL
+                AND ($2::bigint IS NULL OR file_id = $2)
+                AND ($3::bigint IS NULL OR chunk_id {after} $3)
+            ORDER BY chunk_id {order}
+            LIMIT $4
+            "#,
+            after = options.sort.after_operator(),
+            order = options.sort.sql(),
+        ))
+        .bind(tenant_id)
+        .bind(file_id)
+        .bind(options.after_id)
+        .bind(options.limit())
+        .fetch_all(mm.db_read())
+        .await?;
+
+        into_chunks(mm, chunks).await
+    }
+
+    pub async fn count_chunks(
+        mm: &ModelManager,
+        tenant_id: &str,
+        file_id: Option<i64>,
+    ) -> Result<i64> {
+        let count = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM file_chunks
+            WHERE tenant_id = $1 AND deleted_at IS NULL AND ($2::bigint IS NULL OR file_id = $2)
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(file_id)
+        .fetch_one(mm.db_read())
+        .await?;
+
+        Ok(count)
     }
 
-    pub async fn get_chunks_without_embedding(mm: &ModelManager) -> Result<Vec<FileChunk>> {
+    /// Skipped oversized inputs have no content to embed and are left out
+    pub async fn get_chunks_without_embedding(
+        mm: &ModelManager,
+        tenant_id: &str,
+    ) -> Result<Vec<FileChunk>> {
         let db = mm.db();
-        let chunks = sqlx::query_as::<_, FileChunk>(
+        let chunks = sqlx::query_as::<_, FileChunkRow>(
             r#"
-            SELECT * FROM file_chunks WHERE embedding IS NULL
+            SELECT * FROM file_chunks
+            WHERE tenant_id = $1 AND embedding IS NULL AND oversize IS DISTINCT FROM 'skipped'
+                AND deleted_at IS NULL
             "#,
         )
+        .bind(tenant_id)
         .fetch_all(db)
         .await?;
 
-        Ok(chunks)
+        into_chunks(mm, chunks).await
+    }
+
+    /// Embedded chunks of every tenant whose embedding was not computed by `model`, in `chunk_id`
+    /// order after `after_id`. Chunks of soft deleted files are left out.
+    pub async fn get_outdated_chunks(
+        mm: &ModelManager,
+        model: &str,
+        after_id: i64,
+        limit: i64,
+    ) -> Result<Vec<FileChunk>> {
+        let chunks = sqlx::query_as::<_, FileChunkRow>(
+            r#"
+            SELECT * FROM file_chunks
+            WHERE embedding IS NOT NULL AND embedding_model IS DISTINCT FROM $1
+                AND deleted_at IS NULL AND chunk_id > $2
+            ORDER BY chunk_id
+            LIMIT $3
+            "#,
+        )
+        .bind(model)
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(mm.db())
+        .await?;
+
+        into_chunks(mm, chunks).await
+    }
+
+    pub async fn count_outdated_chunks(mm: &ModelManager, model: &str) -> Result<i64> {
+        let count = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM file_chunks
+            WHERE embedding IS NOT NULL AND embedding_model IS DISTINCT FROM $1
+                AND deleted_at IS NULL
+            "#,
+        )
+        .bind(model)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(count)
     }
 
+    /// Replace the embeddings of a batch of chunks in one transaction, searches see either the
+    /// old or the new vectors of the whole batch. Chunks embedded by `model` meanwhile are left
+    /// untouched. `normalized` tells whether the new vectors have a unit L2 norm. Returns the
+    /// number of swapped chunks.
+    pub async fn swap_embeddings(
+        mm: &ModelManager,
+        model: &str,
+        embeddings: Vec<(i64, Vec<f32>)>,
+        normalized: bool,
+    ) -> Result<u64> {
+        let stored = EmbeddingStorage::load()?.cast("$2");
+        let mut tx = mm.db().begin().await?;
+        let mut swapped = 0;
+        for (chunk_id, embedding) in embeddings {
+            let res = sqlx::query(&format!(
+                r#"
+                UPDATE file_chunks
+                SET embedding = {stored}, embedding_model = $3, embedding_dim = vector_dims($2),
+                    embedding_normalized = $4
+                WHERE chunk_id = $1 AND embedding_model IS DISTINCT FROM $3
+                "#
+            ))
+            .bind(chunk_id)
+            .bind(Vector::from(embedding))
+            .bind(model)
+            .bind(normalized)
+            .execute(&mut *tx)
+            .await?;
+            swapped += res.rows_affected();
+        }
+        tx.commit().await?;
+
+        Ok(swapped)
+    }
+
+    /// Full text search on the lexemes of the chunks (`search_tsv`), best ranked first. The query
+    /// (web search syntax: quoted phrases, `or`, `-word`) is parsed once per text search
+    /// configuration in use and matched against the chunks indexed with it, see `text_search`.
     pub async fn search_chunks_by_keyword(
         mm: &ModelManager,
+        tenant_id: &str,
         keyword: &str,
         limit: i64,
     ) -> Result<Vec<FileChunk>> {
-        let db = mm.db();
-        let pattern = format!("%{}%", keyword);
-        let chunks = sqlx::query_as::<_, FileChunk>(
+        let db = mm.db_read();
+        let configs = TextSearchMac::configs_in_use(mm).await?;
+        let chunks = sqlx::query_as::<_, FileChunkRow>(
             r#"
-            SELECT * FROM file_chunks
-            WHERE content_md ILIKE $1
+            WITH queries AS (
+                SELECT config, websearch_to_tsquery(config, $1) AS query
+                FROM unnest($4::regconfig[]) AS config
+            )
+            SELECT c.* FROM file_chunks c JOIN queries q ON c.search_config = q.config
+            WHERE c.tenant_id = $3 AND c.search_tsv @@ q.query AND c.deleted_at IS NULL
+            ORDER BY ts_rank(c.search_tsv, q.query) DESC
             LIMIT $2
             "#,
         )
-        .bind(pattern)
+        .bind(keyword)
         .bind(limit)
+        .bind(tenant_id)
+        .bind(configs)
         .fetch_all(db)
         .await?;
-        Ok(chunks)
+        into_chunks(mm, chunks).await
     }
 
+    /// Set the index search parameters for the current transaction of `conn`
+    pub async fn set_search_params(conn: &mut PgConnection, params: SearchParams) -> Result<()> {
+        sqlx::query(
+            "SELECT set_config('hnsw.ef_search', $1, true), set_config('ivfflat.probes', $2, true)",
+        )
+        .bind(params.ef_search.to_string())
+        .bind(params.probes.to_string())
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Whether some embedded chunks of `model` in the tenant do not have a unit L2 norm, answered
+    /// from the partial index of the unnormalized chunks
+    pub async fn has_unnormalized_embeddings(
+        mm: &ModelManager,
+        tenant_id: &str,
+        model: &str,
+    ) -> Result<bool> {
+        let exists = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM file_chunks
+                WHERE tenant_id = $1 AND embedding_model = $2
+                    AND embedding IS NOT NULL AND NOT embedding_normalized
+                    AND deleted_at IS NULL
+            )
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(model)
+        .fetch_one(mm.db_read())
+        .await?;
+
+        Ok(exists)
+    }
+
+    /// Nearest chunks by the configured distance, served by the vector index
+    /// (see `vector_index::migrate_vector_index`). Only the chunks embedded by `model`, the model
+    /// of the query `embedding`, are compared: the vectors of two models are not comparable.
+    /// When some of them are not normalized the chunks are ranked by cosine whatever the
+    /// distance, see `VectorIndexConfig::search_operator`.
+    /// With a `filter` only the chunks whose metadata contains it are returned, e.g.
+    /// `{"language": "eng"}` or `{"heading_path": ["Pricing"]}`. With the `bit` storage the query
+    /// is binarized and the chunks are ranked by Hamming distance.
     pub async fn search_chunks_by_embedding(
         mm: &ModelManager,
+        tenant_id: &str,
         embedding: Vec<f32>,
+        model: &str,
         limit: i64,
+        filter: Option<&serde_json::Value>,
     ) -> Result<Vec<FileChunk>> {
-        let db = mm.db();
-        let chunks = sqlx::query_as::<_, FileChunk>(
+        let config = VectorIndexConfig::load()?;
+        let normalized = !Self::has_unnormalized_embeddings(mm, tenant_id, model).await?;
+        let (operator, query) = (
+            config.search_operator(normalized),
+            config.storage.cast("$1"),
+        );
+        let mut tx = mm.db_read().begin().await?;
+        Self::set_search_params(&mut tx, SearchParams::default()).await?;
+        let chunks = sqlx::query_as::<_, FileChunkRow>(&format!(
             r#"
             SELECT *
             FROM file_chunks
-            WHERE embedding IS NOT NULL
-            ORDER BY embedding <-> $1
+            WHERE tenant_id = $4
+                AND embedding IS NOT NULL
+                AND embedding_model = $5
+                AND deleted_at IS NULL
+                AND ($3::jsonb IS NULL OR metadata @> $3)
+            ORDER BY embedding {operator} {query}
             LIMIT $2
-            "#,
-        )
+            "#
+        ))
         .bind(Vector::from(embedding))
         .bind(limit)
+        .bind(filter)
+        .bind(tenant_id)
+        .bind(model)
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        into_chunks(mm, chunks).await
+    }
+
+    /// Over-fetch the `fetch_k` nearest chunks and keep the `limit` selected by Maximal Marginal
+    /// Relevance on their stored embeddings, similar chunks are not all returned
+    pub async fn search_chunks_mmr(
+        mm: &ModelManager,
+        tenant_id: &str,
+        embedding: Vec<f32>,
+        model: &str,
+        limit: i64,
+        filter: Option<&serde_json::Value>,
+        mmr: MmrParams,
+    ) -> Result<Vec<FileChunk>> {
+        let candidates = Self::search_chunks_by_embedding(
+            mm,
+            tenant_id,
+            embedding.clone(),
+            model,
+            mmr.fetch_k.max(limit),
+            filter,
+        )
+        .await?;
+        let vectors: Vec<&[f32]> = candidates
+            .iter()
+            .map(|c| {
+                c.embedding
+                    .as_ref()
+                    .map(Vector::as_slice)
+                    .unwrap_or_default()
+            })
+            .collect();
+        let order = mmr_rerank(&embedding, &vectors, limit.max(0) as usize, mmr.lambda);
+
+        let mut candidates: Vec<Option<FileChunk>> = candidates.into_iter().map(Some).collect();
+        Ok(order
+            .into_iter()
+            .filter_map(|i| candidates[i].take())
+            .collect())
+    }
+
+    /// Compress up to `batch_size` chunks still stored as plain text, of every tenant.
+    /// Returns the number of migrated rows, `0` once every chunk is compressed.
+    pub async fn compress_plain_chunks(mm: &ModelManager, batch_size: i64) -> Result<u64> {
+        let db = mm.db();
+        let rows: Vec<(i64, String)> = sqlx::query_as(
+            r#"
+            SELECT chunk_id, content_md FROM file_chunks
+            WHERE content_encoding = 'plain' AND content_md IS NOT NULL
+            ORDER BY chunk_id
+            LIMIT $1
+            "#,
+        )
+        .bind(batch_size)
         .fetch_all(db)
         .await?;
-        Ok(chunks)
+
+        let mut tx = db.begin().await?;
+        let mut migrated = 0;
+        for (chunk_id, content_md) in rows {
+            let res = sqlx::query(
+                r#"
+                UPDATE file_chunks
+                SET content_md = NULL, content_zstd = $2, content_encoding = 'zstd'
+                WHERE chunk_id = $1 AND content_encoding = 'plain'
+                "#,
+            )
+            .bind(chunk_id)
+            .bind(zstd_compress(content_md)?)
+            .execute(&mut *tx)
+            .await?;
+            migrated += res.rows_affected();
+        }
+        tx.commit().await?;
+
+        Ok(migrated)
     }
 }
 
@@ -183,9 +1347,21 @@ impl FileChunkMac {
 mod tests {
     use super::*;
     use crate::_dev_utils::init_dev;
+    use crate::ctx::DEFAULT_TENANT;
     use crate::database::ModelManager;
+    use crate::model::files::FileForCreate;
     use pgvector::Vector;
 
+    #[test]
+    fn test_content_hash() {
+        assert_eq!(
+            content_hash("Hello  world\n"),
+            content_hash(" Hello\n\tworld")
+        );
+        assert_ne!(content_hash("Hello world"), content_hash("Hello World"));
+        assert_eq!(content_hash("").len(), 64);
+    }
+
     #[tokio::test]
     async fn test_create_and_get_chunk() -> Result<()> {
         let db = init_dev().await?;
@@ -197,16 +1373,22 @@ mod tests {
             chunk_index: 0,
             content_md: Some("Hello world".into()),
             embedding: Some(Vector::from(vec![0.1, 0.2, 0.3])),
+            embedding_model: Some("test-model".into()),
+            embedding_normalized: true,
             token_count: Some(3),
+            oversize: None,
+            metadata: ChunkMetadata::default(),
         };
-        let chunk = FileChunkMac::create_chunk(&mm, chunk_in.clone())
+        let chunk = FileChunkMac::create_chunk(&mm, DEFAULT_TENANT, chunk_in.clone())
             .await
             .unwrap();
         assert_eq!(chunk.file_id, 1001);
         assert_eq!(chunk.chunk_index, 0);
+        assert_eq!(chunk.embedding_model.as_deref(), Some("test-model"));
+        assert_eq!(chunk.embedding_dim, Some(3));
 
         // Get by ID
-        let fetched = FileChunkMac::get_chunk_by_id(&mm, chunk.chunk_id)
+        let fetched = FileChunkMac::get_chunk_by_id(&mm, DEFAULT_TENANT, chunk.chunk_id)
             .await
             .unwrap();
         assert_eq!(fetched.chunk_id, chunk.chunk_id);
@@ -214,6 +1396,287 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_create_chunks_bulk() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev(db);
+
+        let chunks: Vec<FileChunkForCreate> = (0..1500)
+            .map(|i| FileChunkForCreate {
+                file_id: 1001,
+                chunk_index: i,
+                content_md: Some(format!("Bulk chunk {i}")),
+                embedding: Some(Vector::from(vec![0.1, 0.2, 0.3])),
+                embedding_model: Some("test-model".into()),
+                embedding_normalized: true,
+                token_count: Some(3),
+                oversize: None,
+                metadata: ChunkMetadata::default(),
+            })
+            .collect();
+        let created = FileChunkMac::create_chunks_bulk(&mm, DEFAULT_TENANT, 1001, chunks).await?;
+        assert_eq!(created.len(), 1500);
+        // Appended after the chunks the file already has
+        let first_index = created[0].chunk_index;
+        assert_eq!(created[1200].chunk_index, first_index + 1200);
+        assert_eq!(created[1200].content_md.as_deref(), Some("Bulk chunk 1200"));
+        assert_eq!(created[0].embedding_dim, Some(3));
+
+        let fetched =
+            FileChunkMac::get_chunk_by_id(&mm, DEFAULT_TENANT, created[1200].chunk_id).await?;
+        assert_eq!(fetched.content_md, created[1200].content_md);
+        assert_eq!(
+            fetched.content_hash.as_deref(),
+            Some(content_hash("Bulk chunk 1200").as_str())
+        );
+
+        let missing = FileChunkMac::create_chunks_bulk(
+            &mm,
+            "other-tenant",
+            1001,
+            vec![FileChunkForCreate {
+                file_id: 1001,
+                chunk_index: 0,
+                content_md: Some("Other tenant".into()),
+                embedding: None,
+                embedding_model: None,
+                embedding_normalized: true,
+                token_count: None,
+                oversize: None,
+                metadata: ChunkMetadata::default(),
+            }],
+        )
+        .await;
+        assert!(missing.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_file_chunks_append() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev_with_content_store(db);
+        let file = FileMac::create_file(
+            &mm,
+            FileForCreate {
+                tenant_id: DEFAULT_TENANT.to_string(),
+                applicant: "applicant_123".to_string(),
+                filename: "append.md".to_string(),
+                file_type: "md".to_string(),
+                etag: None,
+                last_modified: None,
+            },
+        )
+        .await?;
+        let chunk = |chunk_index: i32, text: &str| FileChunkForCreate {
+            file_id: file.file_id,
+            chunk_index,
+            content_md: Some(text.to_string()),
+            embedding: None,
+            embedding_model: None,
+            embedding_normalized: true,
+            token_count: None,
+            oversize: None,
+            metadata: ChunkMetadata::default(),
+        };
+
+        let first = FileChunkMac::create_file_chunks(
+            &mm,
+            DEFAULT_TENANT,
+            file.file_id,
+            vec![chunk(0, "First chunk"), chunk(1, "Second chunk")],
+        )
+        .await?;
+        // Concurrent appends are numbered after each other, the indexes count from the file end
+        let (appended, other) = tokio::join!(
+            FileChunkMac::create_file_chunks(
+                &mm,
+                DEFAULT_TENANT,
+                file.file_id,
+                vec![chunk(0, "Appended chunk")],
+            ),
+            FileChunkMac::create_file_chunks(
+                &mm,
+                DEFAULT_TENANT,
+                file.file_id,
+                vec![chunk(0, "Concurrent chunk")],
+            ),
+        );
+        let (appended, other) = (appended?, other?);
+        let mut indexes = vec![appended[0].chunk_index, other[0].chunk_index];
+        indexes.sort_unstable();
+        assert_eq!(indexes, vec![2, 3]);
+
+        // The chunks of the first ingest still read their own text
+        for (chunk, expected) in [
+            (&first[0], "First chunk"),
+            (&first[1], "Second chunk"),
+            (&appended[0], "Appended chunk"),
+        ] {
+            let fetched =
+                FileChunkMac::get_chunk_by_id(&mm, DEFAULT_TENANT, chunk.chunk_id).await?;
+            assert_eq!(fetched.content_md.as_deref(), Some(expected));
+        }
+
+        // The unique index of the hashes is reported as a duplicate, blank texts are not hashed
+        let duplicate = FileChunkMac::create_file_chunks(
+            &mm,
+            DEFAULT_TENANT,
+            file.file_id,
+            vec![chunk(0, "First  chunk")],
+        )
+        .await;
+        assert!(matches!(duplicate, Err(Error::DuplicateContent)));
+        let blank = FileChunkMac::create_chunks_bulk(
+            &mm,
+            DEFAULT_TENANT,
+            file.file_id,
+            vec![chunk(0, ""), chunk(1, " ")],
+        )
+        .await?;
+        assert!(blank.iter().all(|chunk| chunk.content_hash.is_none()));
+
+        FileMac::delete_file(&mm, DEFAULT_TENANT, &file.file_id).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replace_file_chunks() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev_with_content_store(db);
+        let file = FileMac::create_file(
+            &mm,
+            FileForCreate {
+                tenant_id: DEFAULT_TENANT.to_string(),
+                applicant: "applicant_123".to_string(),
+                filename: "replace.md".to_string(),
+                file_type: "md".to_string(),
+                etag: None,
+                last_modified: None,
+            },
+        )
+        .await?;
+        let chunk = |chunk_index: i32, text: &str| FileChunkForCreate {
+            file_id: file.file_id,
+            chunk_index,
+            content_md: Some(text.to_string()),
+            embedding: None,
+            embedding_model: None,
+            embedding_normalized: true,
+            token_count: None,
+            oversize: None,
+            metadata: ChunkMetadata::default(),
+        };
+
+        let first = FileChunkMac::create_file_chunks(
+            &mm,
+            DEFAULT_TENANT,
+            file.file_id,
+            vec![chunk(0, "Old first chunk"), chunk(1, "Old second chunk")],
+        )
+        .await?;
+
+        // Another tenant cannot replace them, nothing is deleted
+        let denied = FileChunkMac::replace_file_chunks(
+            &mm,
+            "other-tenant",
+            file.file_id,
+            vec![chunk(0, "Foreign chunk")],
+        )
+        .await;
+        assert!(matches!(denied, Err(Error::FileNotFound)));
+        let kept = FileChunkMac::get_chunk_by_id(&mm, DEFAULT_TENANT, first[1].chunk_id).await?;
+        assert_eq!(kept.content_md.as_deref(), Some("Old second chunk"));
+
+        let (replaced, created) = FileChunkMac::replace_file_chunks(
+            &mm,
+            DEFAULT_TENANT,
+            file.file_id,
+            vec![chunk(0, "New chunk")],
+        )
+        .await?;
+        assert_eq!(replaced, 2);
+        let fetched =
+            FileChunkMac::get_chunk_by_id(&mm, DEFAULT_TENANT, created[0].chunk_id).await?;
+        assert_eq!(fetched.content_md.as_deref(), Some("New chunk"));
+        assert!(
+            FileChunkMac::get_chunk_by_id(&mm, DEFAULT_TENANT, first[0].chunk_id)
+                .await
+                .is_err()
+        );
+
+        FileMac::delete_file(&mm, DEFAULT_TENANT, &file.file_id).await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_row() -> Result<()> {
+        let chunk = ChunkForImport {
+            file_id: 1001,
+            content_md: "Tab\there\nand a \\ backslash".into(),
+            embedding: Vector::from(vec![0.5, -1.0]),
+            embedding_model: None,
+            embedding_normalized: false,
+            metadata: ChunkMetadata {
+                page: Some(2),
+                ..ChunkMetadata::default()
+            },
+        };
+        let row = copy_row(3, chunk.clone(), false)?;
+        let fields: Vec<&str> = row.trim_end_matches('\n').split('\t').collect();
+        assert_eq!(fields.len(), IMPORT_COLUMNS.split(", ").count());
+        assert_eq!(
+            fields[..3],
+            ["3", "1001", "Tab\\there\\nand a \\\\ backslash"]
+        );
+        assert_eq!(fields[3], "\\N");
+        assert_eq!(fields[6], "[0.5,-1]");
+        assert_eq!(fields[7..10], ["\\N", "f", r#"{"page":2}"#]);
+
+        let row = copy_row(0, chunk, true)?;
+        let fields: Vec<&str> = row.split('\t').collect();
+        assert_eq!(fields[2], "\\N");
+        assert!(fields[3].starts_with("\\\\x28b52ffd"));
+        assert_eq!(fields[4], ENCODING_ZSTD);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_chunks() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev(db);
+
+        let chunk = |text: &str| ChunkForImport {
+            file_id: 1001,
+            content_md: text.to_string(),
+            embedding: Vector::from(vec![0.6, 0.8, 0.0]),
+            embedding_model: Some("other-store".into()),
+            embedding_normalized: true,
+            metadata: ChunkMetadata::default(),
+        };
+        let next = FileChunkMac::next_chunk_index(&mm, DEFAULT_TENANT, 1001).await?;
+        let chunks = vec![
+            chunk("Imported\tone"),
+            chunk("Imported two"),
+            chunk("Imported two"),
+        ];
+        let summary = FileChunkMac::import_chunks(&mm, DEFAULT_TENANT, chunks).await?;
+        assert_eq!(
+            summary,
+            ImportSummary {
+                imported: 2,
+                skipped: 1
+            }
+        );
+        assert_eq!(
+            FileChunkMac::next_chunk_index(&mm, DEFAULT_TENANT, 1001).await?,
+            next + 2
+        );
+
+        let missing = FileChunkMac::import_chunks(&mm, "other-tenant", vec![chunk("Other")]).await;
+        assert!(missing.is_err());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_update_chunk() -> Result<()> {
         let db = init_dev().await?;
@@ -224,18 +1687,26 @@ mod tests {
             chunk_index: 1,
             content_md: Some("Original".into()),
             embedding: Some(Vector::from(vec![0.1, 0.1])),
+            embedding_model: None,
+            embedding_normalized: true,
             token_count: Some(2),
+            oversize: None,
+            metadata: ChunkMetadata::default(),
         };
-        let chunk = FileChunkMac::create_chunk(&mm, chunk_in).await.unwrap();
+        let chunk = FileChunkMac::create_chunk(&mm, DEFAULT_TENANT, chunk_in)
+            .await
+            .unwrap();
 
         let update = FileChunkForUpdate {
             chunk_index: Some(2),
             content_md: Some("Updated".into()),
             embedding: None,
+            embedding_model: None,
+            embedding_normalized: None,
             token_count: Some(4),
         };
 
-        let updated = FileChunkMac::update_chunk(&mm, chunk.chunk_id, update)
+        let updated = FileChunkMac::update_chunk(&mm, DEFAULT_TENANT, chunk.chunk_id, update)
             .await
             .unwrap();
         assert_eq!(updated.chunk_index, 2);
@@ -244,6 +1715,86 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_swap_embeddings() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev(db);
+
+        let chunk_in = FileChunkForCreate {
+            file_id: 1001,
+            chunk_index: 0,
+            content_md: Some("Outdated".into()),
+            embedding: Some(Vector::from(vec![0.1, 0.1])),
+            embedding_model: Some("old-model".into()),
+            embedding_normalized: true,
+            token_count: Some(1),
+            oversize: None,
+            metadata: ChunkMetadata::default(),
+        };
+        let chunk = FileChunkMac::create_chunk(&mm, DEFAULT_TENANT, chunk_in).await?;
+        let outdated = FileChunkMac::get_outdated_chunks(&mm, "new-model", 0, 1_000).await?;
+        assert!(outdated.iter().any(|c| c.chunk_id == chunk.chunk_id));
+
+        let embedding = vec![0.2, 0.2];
+        let swapped = FileChunkMac::swap_embeddings(
+            &mm,
+            "new-model",
+            vec![(chunk.chunk_id, embedding.clone())],
+            true,
+        )
+        .await?;
+        assert_eq!(swapped, 1);
+        let fetched = FileChunkMac::get_chunk_by_id(&mm, DEFAULT_TENANT, chunk.chunk_id).await?;
+        assert_eq!(fetched.embedding_model.as_deref(), Some("new-model"));
+        assert_eq!(fetched.embedding, Some(Vector::from(embedding.clone())));
+
+        // Already embedded by the model, left untouched
+        let swapped = FileChunkMac::swap_embeddings(
+            &mm,
+            "new-model",
+            vec![(chunk.chunk_id, embedding)],
+            true,
+        )
+        .await?;
+        assert_eq!(swapped, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search_model_affinity() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev(db);
+
+        let chunk_in = |model: &str| FileChunkForCreate {
+            file_id: 1001,
+            chunk_index: 0,
+            content_md: Some(format!("Embedded by {model}")),
+            embedding: Some(Vector::from(vec![0.3, 0.4])),
+            embedding_model: Some(model.to_string()),
+            embedding_normalized: true,
+            token_count: Some(3),
+            oversize: None,
+            metadata: ChunkMetadata::default(),
+        };
+        let chunk_a =
+            FileChunkMac::create_chunk(&mm, DEFAULT_TENANT, chunk_in("affinity-a")).await?;
+        let chunk_b =
+            FileChunkMac::create_chunk(&mm, DEFAULT_TENANT, chunk_in("affinity-b")).await?;
+
+        let results = FileChunkMac::search_chunks_by_embedding(
+            &mm,
+            DEFAULT_TENANT,
+            vec![0.3, 0.4],
+            "affinity-a",
+            100,
+            None,
+        )
+        .await?;
+        assert!(results.iter().any(|c| c.chunk_id == chunk_a.chunk_id));
+        assert!(results.iter().all(|c| c.chunk_id != chunk_b.chunk_id));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_delete_chunk() -> Result<()> {
         let db = init_dev().await?;
@@ -254,17 +1805,23 @@ mod tests {
             chunk_index: 0,
             content_md: Some("Delete me".into()),
             embedding: None,
+            embedding_model: None,
+            embedding_normalized: true,
             token_count: Some(2),
+            oversize: None,
+            metadata: ChunkMetadata::default(),
         };
-        let chunk = FileChunkMac::create_chunk(&mm, chunk_in).await.unwrap();
+        let chunk = FileChunkMac::create_chunk(&mm, DEFAULT_TENANT, chunk_in)
+            .await
+            .unwrap();
 
-        let deleted_rows = FileChunkMac::delete_chunk(&mm, chunk.chunk_id)
+        let deleted_rows = FileChunkMac::delete_chunk(&mm, DEFAULT_TENANT, chunk.chunk_id)
             .await
             .unwrap();
         assert_eq!(deleted_rows, 1);
 
         // Should fail to fetch
-        let result = FileChunkMac::get_chunk_by_id(&mm, chunk.chunk_id).await;
+        let result = FileChunkMac::get_chunk_by_id(&mm, DEFAULT_TENANT, chunk.chunk_id).await;
         assert!(result.is_err());
         Ok(())
     }
@@ -279,14 +1836,89 @@ mod tests {
             chunk_index: 0,
             content_md: Some("Searchable content".into()),
             embedding: None,
+            embedding_model: None,
+            embedding_normalized: true,
             token_count: Some(2),
+            oversize: None,
+            metadata: ChunkMetadata::default(),
         };
-        let _ = FileChunkMac::create_chunk(&mm, chunk_in).await.unwrap();
-
-        let results = FileChunkMac::search_chunks_by_keyword(&mm, "keyword_test", 10)
+        let _ = FileChunkMac::create_chunk(&mm, DEFAULT_TENANT, chunk_in)
             .await
             .unwrap();
+
+        let results =
+            FileChunkMac::search_chunks_by_keyword(&mm, DEFAULT_TENANT, "keyword_test", 10)
+                .await
+                .unwrap();
         assert!(!results.is_empty());
+        let results = FileChunkMac::search_chunks_by_keyword(&mm, "other", "keyword_test", 10)
+            .await
+            .unwrap();
+        assert!(results.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search_chunks_by_keyword_stemming() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev(db);
+
+        let chunk_in = FileChunkForCreate {
+            file_id: 1001,
+            chunk_index: 0,
+            content_md: Some("Running stemmed databases".into()),
+            embedding: None,
+            embedding_model: None,
+            embedding_normalized: true,
+            token_count: Some(3),
+            oversize: None,
+            metadata: ChunkMetadata::default(),
+        };
+        let chunk = FileChunkMac::create_chunk(&mm, DEFAULT_TENANT, chunk_in).await?;
+
+        let results =
+            FileChunkMac::search_chunks_by_keyword(&mm, DEFAULT_TENANT, "stemmed database run", 10)
+                .await?;
+        assert!(results.iter().any(|c| c.chunk_id == chunk.chunk_id));
+        let results =
+            FileChunkMac::search_chunks_by_keyword(&mm, DEFAULT_TENANT, "stemmed -databases", 10)
+                .await?;
+        assert!(results.iter().all(|c| c.chunk_id != chunk.chunk_id));
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_content() -> Result<()> {
+        let (md, zstd, encoding) = encode_content(Some("Compress me".into()), true)?;
+        assert!(md.is_none());
+        assert_eq!(encoding, ENCODING_ZSTD);
+
+        let row = FileChunkRow {
+            chunk_id: 1,
+            file_id: 1001,
+            tenant_id: DEFAULT_TENANT.to_string(),
+            chunk_index: 0,
+            content_md: md,
+            content_zstd: zstd,
+            content_encoding: encoding.to_string(),
+            content_offset: None,
+            content_length: None,
+            embedding: None,
+            embedding_model: None,
+            embedding_dim: None,
+            embedding_normalized: true,
+            token_count: None,
+            oversize: None,
+            metadata: Json(ChunkMetadata::default()),
+            content_hash: None,
+        };
+        let chunk = FileChunk::try_from(row)?;
+        assert_eq!(chunk.content_md.unwrap(), "Compress me");
+
+        let (md, zstd, encoding) = encode_content(Some("Keep me".into()), false)?;
+        assert_eq!(md.unwrap(), "Keep me");
+        assert!(zstd.is_none());
+        assert_eq!(encoding, ENCODING_PLAIN);
         Ok(())
     }
 }
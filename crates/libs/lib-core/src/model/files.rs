@@ -1,38 +1,62 @@
 use crate::database::ModelManager;
 use crate::error::Result;
+use crate::model::pagination::ListOptions;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use sqlx::types::chrono::NaiveDateTime;
+use std::collections::HashMap;
 
 // region: Structs
 
 #[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
 pub struct File {
     pub file_id: i64,
+    pub tenant_id: String,
     pub applicant: String,
     pub filename: String,
     pub file_type: String,
     pub created_at: NaiveDateTime,
     pub processed: bool,
+    /// Set when the file hit a chunking cap and was truncated or skipped
+    pub warning: Option<String>,
+    /// Set once the object left the bucket, the file is purged after the retention window
+    pub deleted_at: Option<NaiveDateTime>,
+    /// ETag of the S3 object when it was last synced
+    pub etag: Option<String>,
+    /// LastModified of the S3 object when it was last synced
+    pub last_modified: Option<NaiveDateTime>,
+    /// Version of the S3 object the chunks were parsed from, `None` for unversioned buckets
+    pub version_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FileForCreate {
+    pub tenant_id: String,
     pub applicant: String,
     pub filename: String,
     pub file_type: String,
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct FileForUpdate {
     pub filename: Option<String>,
     pub processed: Option<bool>,
+    pub warning: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<NaiveDateTime>,
+    pub version_id: Option<String>,
 }
 
 // endregion: Structs
 
 // region: CRUD + Search
 
+/// Every query is scoped to `tenant_id`, a file of another tenant is not found. Soft deleted
+/// files are not found either until they are restored.
 pub struct FileMac;
 
 impl FileMac {
@@ -40,37 +64,43 @@ impl FileMac {
         let db = mm.db();
         let query = sqlx::query_as::<_, File>(
             r#"
-            INSERT INTO files (applicant, filename, file_type)
-            VALUES ($1, $2, $3)
+            INSERT INTO files (tenant_id, applicant, filename, file_type, etag, last_modified)
+            VALUES ($1, $2, $3, $4, $5, $6)
             RETURNING *
             "#,
         )
+        .bind(file.tenant_id)
         .bind(file.applicant)
         .bind(file.filename)
-        .bind(file.file_type);
+        .bind(file.file_type)
+        .bind(file.etag)
+        .bind(file.last_modified);
 
         let file = query.fetch_one(db).await?;
         Ok(file)
     }
 
-    pub async fn get_file_by_id(mm: &ModelManager, file_id: &i64) -> Result<File> {
+    pub async fn get_file_by_id(mm: &ModelManager, tenant_id: &str, file_id: &i64) -> Result<File> {
         let db = mm.db();
         let query = sqlx::query_as::<_, File>(
             r#"
-            SELECT * FROM files WHERE file_id = $1
+            SELECT * FROM files WHERE file_id = $1 AND tenant_id = $2 AND deleted_at IS NULL
             "#,
         )
-        .bind(file_id);
+        .bind(file_id)
+        .bind(tenant_id);
 
         let file = query.fetch_one(db).await?;
         Ok(file)
     }
 
+    /// Files of every tenant, for the ingestion cron which stores the chunks under the tenant of
+    /// their file
     pub async fn get_unprocessed_files(mm: &ModelManager) -> Result<Vec<File>> {
         let db = mm.db();
         let files = sqlx::query_as::<_, File>(
             r#"
-            SELECT * FROM files WHERE processed = FALSE
+            SELECT * FROM files WHERE processed = FALSE AND deleted_at IS NULL
             "#,
         )
         .fetch_all(db)
@@ -81,6 +111,7 @@ impl FileMac {
 
     pub async fn update_file(
         mm: &ModelManager,
+        tenant_id: &str,
         file_id: &i64,
         update: FileForUpdate,
     ) -> Result<File> {
@@ -90,57 +121,272 @@ impl FileMac {
             UPDATE files
             SET
                 filename = COALESCE($2, filename),
-                processed = COALESCE($3, processed)
-            WHERE file_id = $1
+                processed = COALESCE($3, processed),
+                warning = COALESCE($4, warning),
+                etag = COALESCE($6, etag),
+                last_modified = COALESCE($7, last_modified),
+                version_id = COALESCE($8, version_id)
+            WHERE file_id = $1 AND tenant_id = $5
             RETURNING *
             "#,
         )
         .bind(file_id)
         .bind(update.filename)
-        .bind(update.processed);
+        .bind(update.processed)
+        .bind(update.warning)
+        .bind(tenant_id)
+        .bind(update.etag)
+        .bind(update.last_modified)
+        .bind(update.version_id);
 
         let file = query.fetch_one(db).await?;
         Ok(file)
     }
 
-    pub async fn delete_file(mm: &ModelManager, file_id: &i64) -> Result<u64> {
+    pub async fn delete_file(mm: &ModelManager, tenant_id: &str, file_id: &i64) -> Result<u64> {
         let res = sqlx::query(
             r#"
-            DELETE FROM files WHERE file_id = $1
+            DELETE FROM files WHERE file_id = $1 AND tenant_id = $2
             "#,
         )
         .bind(file_id)
+        .bind(tenant_id)
         .execute(mm.db())
         .await?;
 
+        if res.rows_affected() > 0 {
+            if let Some(store) = mm.content_store() {
+                store.delete_file(*file_id).await?;
+            }
+        }
         Ok(res.rows_affected())
     }
 
-    pub async fn delete_files_by_applicant(mm: &ModelManager, applicant: &str) -> Result<u64> {
+    /// Mark the file and its chunks as deleted, they stay restorable until
+    /// `purge_deleted_files` removes them
+    pub async fn soft_delete_file(
+        mm: &ModelManager,
+        tenant_id: &str,
+        file_id: &i64,
+    ) -> Result<u64> {
+        let mut tx = mm.db().begin().await?;
         let res = sqlx::query(
             r#"
-            DELETE FROM files WHERE applicant = $1
+            UPDATE files SET deleted_at = now()
+            WHERE file_id = $1 AND tenant_id = $2 AND deleted_at IS NULL
             "#,
         )
-        .bind(applicant)
-        .execute(mm.db())
+        .bind(file_id)
+        .bind(tenant_id)
+        .execute(&mut *tx)
         .await?;
 
+        if res.rows_affected() > 0 {
+            sqlx::query(
+                r#"
+                UPDATE file_chunks SET deleted_at = now() WHERE file_id = $1
+                "#,
+            )
+            .bind(file_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
         Ok(res.rows_affected())
     }
 
-    pub async fn get_all_files(mm: &ModelManager) -> Result<Vec<File>> {
+    /// Restore the last soft deleted file named `filename` with its chunks, `None` when the
+    /// tenant has no such file
+    pub async fn restore_file(
+        mm: &ModelManager,
+        tenant_id: &str,
+        filename: &str,
+    ) -> Result<Option<File>> {
+        let mut tx = mm.db().begin().await?;
+        let file = sqlx::query_as::<_, File>(
+            r#"
+            UPDATE files SET deleted_at = NULL
+            WHERE file_id = (
+                SELECT file_id FROM files
+                WHERE tenant_id = $1 AND filename = $2 AND deleted_at IS NOT NULL
+                ORDER BY deleted_at DESC
+                LIMIT 1
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(filename)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(file) = &file {
+            sqlx::query(
+                r#"
+                UPDATE file_chunks SET deleted_at = NULL WHERE file_id = $1
+                "#,
+            )
+            .bind(file.file_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(file)
+    }
+
+    /// Hard delete the files of every tenant soft deleted more than `retention_days` ago, their
+    /// chunks cascade
+    pub async fn purge_deleted_files(mm: &ModelManager, retention_days: u32) -> Result<u64> {
+        let file_ids: Vec<i64> = sqlx::query_scalar(
+            r#"
+            DELETE FROM files WHERE deleted_at < now() - make_interval(days => $1)
+            RETURNING file_id
+            "#,
+        )
+        .bind(retention_days as i32)
+        .fetch_all(mm.db())
+        .await?;
+
+        if let Some(store) = mm.content_store() {
+            for file_id in &file_ids {
+                store.delete_file(*file_id).await?;
+            }
+        }
+        Ok(file_ids.len() as u64)
+    }
+
+    pub async fn delete_files_by_applicant(
+        mm: &ModelManager,
+        tenant_id: &str,
+        applicant: &str,
+    ) -> Result<u64> {
+        let file_ids: Vec<i64> = sqlx::query_scalar(
+            r#"
+            DELETE FROM files WHERE applicant = $1 AND tenant_id = $2
+            RETURNING file_id
+            "#,
+        )
+        .bind(applicant)
+        .bind(tenant_id)
+        .fetch_all(mm.db())
+        .await?;
+
+        if let Some(store) = mm.content_store() {
+            for file_id in &file_ids {
+                store.delete_file(*file_id).await?;
+            }
+        }
+        Ok(file_ids.len() as u64)
+    }
+
+    pub async fn get_all_files(mm: &ModelManager, tenant_id: &str) -> Result<Vec<File>> {
         let db = mm.db();
         let files = sqlx::query_as::<_, File>(
             r#"
-            SELECT * FROM files
+            SELECT * FROM files WHERE tenant_id = $1 AND deleted_at IS NULL
             "#,
         )
+        .bind(tenant_id)
         .fetch_all(db)
         .await?;
 
         Ok(files)
     }
+
+    /// Ids of the live files of the tenant named `filenames`, the latest one when a name is
+    /// taken twice. Missing names are left out.
+    pub async fn get_file_ids_by_filename(
+        mm: &ModelManager,
+        tenant_id: &str,
+        filenames: &[String],
+    ) -> Result<HashMap<String, i64>> {
+        let rows: Vec<(String, i64)> = sqlx::query_as(
+            r#"
+            SELECT DISTINCT ON (filename) filename, file_id FROM files
+            WHERE tenant_id = $1 AND filename = ANY($2) AND deleted_at IS NULL
+            ORDER BY filename, file_id DESC
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(filenames)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(rows.into_iter().collect())
+    }
+
+    /// A page of the files of the tenant, see `ListOptions`
+    pub async fn list_files(
+        mm: &ModelManager,
+        tenant_id: &str,
+        options: &ListOptions<i64>,
+    ) -> Result<Vec<File>> {
+        let files = sqlx::query_as::<_, File>(&format!(
+            r#"
+            SELECT * FROM files
+            WHERE tenant_id = $1
+                AND deleted_at IS NULL
+                AND ($2::bigint IS NULL OR file_id {after} $2)
+            ORDER BY file_id {order}
+            LIMIT $3
+            "#,
+            after = options.sort.after_operator(),
+            order = options.sort.sql(),
+        ))
+        .bind(tenant_id)
+        .bind(options.after_id)
+        .bind(options.limit())
+        .fetch_all(mm.db_read())
+        .await?;
+
+        Ok(files)
+    }
+
+    pub async fn count_files(mm: &ModelManager, tenant_id: &str) -> Result<i64> {
+        let count = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM files WHERE tenant_id = $1 AND deleted_at IS NULL
+            "#,
+        )
+        .bind(tenant_id)
+        .fetch_one(mm.db_read())
+        .await?;
+
+        Ok(count)
+    }
+
+    /// Tenants owning at least one file
+    pub async fn get_tenants(mm: &ModelManager) -> Result<Vec<String>> {
+        let tenants = sqlx::query_scalar(
+            r#"
+            SELECT DISTINCT tenant_id FROM files WHERE deleted_at IS NULL
+            "#,
+        )
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(tenants)
+    }
+
+    /// Last update of the given files, the LastModified of their S3 object or their creation
+    pub async fn get_updated_at(
+        mm: &ModelManager,
+        tenant_id: &str,
+        file_ids: &[i64],
+    ) -> Result<HashMap<i64, NaiveDateTime>> {
+        let rows: Vec<(i64, NaiveDateTime)> = sqlx::query_as(
+            r#"
+            SELECT file_id, COALESCE(last_modified, created_at) FROM files
+            WHERE tenant_id = $1 AND file_id = ANY($2)
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(file_ids)
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(rows.into_iter().collect())
+    }
 }
 
 // endregion: CRUD + Search
@@ -150,6 +396,7 @@ impl FileMac {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ctx::DEFAULT_TENANT;
     use crate::database::ModelManager;
     use crate::error::Result;
 
@@ -158,9 +405,12 @@ mod tests {
         let mm = ModelManager::new().await?;
 
         let new_file = FileForCreate {
+            tenant_id: DEFAULT_TENANT.to_string(),
             applicant: "applicant_123".to_string(),
             filename: "example.pdf".to_string(),
             file_type: "pdf".to_string(),
+            etag: None,
+            last_modified: None,
         };
 
         let created_file = FileMac::create_file(&mm, new_file.clone()).await?;
@@ -171,13 +421,64 @@ mod tests {
         let update = FileForUpdate {
             filename: Some("updated_example.pdf".to_string()),
             processed: Some(true),
+            warning: None,
+            etag: None,
+            last_modified: None,
+            version_id: Some("v1".to_string()),
         };
-        let updated_file = FileMac::update_file(&mm, &created_file.file_id, update).await?;
+        let updated_file =
+            FileMac::update_file(&mm, DEFAULT_TENANT, &created_file.file_id, update).await?;
         assert_eq!(updated_file.filename, "updated_example.pdf");
         assert!(updated_file.processed);
+        assert_eq!(updated_file.version_id.as_deref(), Some("v1"));
+
+        // Other tenants do not see the file
+        assert!(
+            FileMac::get_file_by_id(&mm, "other", &created_file.file_id)
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            FileMac::delete_file(&mm, "other", &created_file.file_id).await?,
+            0
+        );
+
+        let updated_at =
+            FileMac::get_updated_at(&mm, DEFAULT_TENANT, &[created_file.file_id]).await?;
+        assert_eq!(
+            updated_at.get(&created_file.file_id),
+            Some(&created_file.created_at)
+        );
+
+        // The file is listed and counted
+        let files = FileMac::list_files(&mm, DEFAULT_TENANT, &ListOptions::default()).await?;
+        assert!(files.iter().any(|f| f.file_id == created_file.file_id));
+        assert!(FileMac::count_files(&mm, DEFAULT_TENANT).await? >= 1);
+        let after = ListOptions {
+            after_id: Some(created_file.file_id),
+            ..Default::default()
+        };
+        let files = FileMac::list_files(&mm, DEFAULT_TENANT, &after).await?;
+        assert!(files.iter().all(|f| f.file_id > created_file.file_id));
+
+        // Soft delete hides the file until it is restored
+        let deleted = FileMac::soft_delete_file(&mm, DEFAULT_TENANT, &created_file.file_id).await?;
+        assert_eq!(deleted, 1);
+        assert!(
+            FileMac::get_file_by_id(&mm, DEFAULT_TENANT, &created_file.file_id)
+                .await
+                .is_err()
+        );
+        let restored = FileMac::restore_file(&mm, DEFAULT_TENANT, "updated_example.pdf").await?;
+        assert_eq!(restored.map(|f| f.file_id), Some(created_file.file_id));
+
+        // Purge only removes files past the retention window
+        FileMac::soft_delete_file(&mm, DEFAULT_TENANT, &created_file.file_id).await?;
+        assert_eq!(FileMac::purge_deleted_files(&mm, 30).await?, 0);
+        FileMac::restore_file(&mm, DEFAULT_TENANT, "updated_example.pdf").await?;
 
         // Delete
-        let deleted = FileMac::delete_file(&mm, &created_file.file_id).await?;
+        let deleted = FileMac::delete_file(&mm, DEFAULT_TENANT, &created_file.file_id).await?;
         assert_eq!(deleted, 1);
 
         Ok(())
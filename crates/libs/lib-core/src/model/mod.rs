@@ -1,3 +1,12 @@
+pub mod audit;
+pub mod cron_jobs;
+pub mod embedding_dims;
+pub mod evaluation;
 pub mod file_chunks;
 pub mod files;
+pub mod pagination;
+pub mod replication;
+pub mod search_templates;
+pub mod settings;
+pub mod text_search;
 pub mod user;
@@ -0,0 +1,214 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_PAGE_LIMIT: i64 = 50;
+pub const MAX_PAGE_LIMIT: i64 = 1000;
+
+// region: Structs
+
+/// Order of the rows on their id, which is also the creation order of files and chunks
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub fn sql(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+
+    /// Comparison selecting the rows that come after the cursor
+    pub fn after_operator(self) -> &'static str {
+        match self {
+            SortOrder::Asc => ">",
+            SortOrder::Desc => "<",
+        }
+    }
+}
+
+/// Keyset pagination: the `limit` rows following the row `after_id` in `sort` order. Unlike an
+/// offset, the cursor stays stable while rows are inserted or deleted.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ListOptions<K> {
+    #[serde(default)]
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub after_id: Option<K>,
+    #[serde(default)]
+    pub sort: SortOrder,
+}
+
+impl<K> Default for ListOptions<K> {
+    fn default() -> Self {
+        Self {
+            limit: None,
+            after_id: None,
+            sort: SortOrder::default(),
+        }
+    }
+}
+
+impl<K> ListOptions<K> {
+    /// `limit` clamped to `1..=MAX_PAGE_LIMIT`, `DEFAULT_PAGE_LIMIT` when not given
+    pub fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_LIMIT)
+            .clamp(1, MAX_PAGE_LIMIT)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T, K> {
+    pub data: Vec<T>,
+    /// `after_id` of the next page, `None` on the last page
+    pub next_after_id: Option<K>,
+    /// Rows matching the listing, across all pages
+    pub total: i64,
+}
+
+impl<T, K> Page<T, K> {
+    /// A full page has a next one, its cursor is the id of the last row
+    pub fn new(data: Vec<T>, total: i64, limit: i64, id: impl Fn(&T) -> K) -> Self {
+        let next_after_id = match data.last() {
+            Some(last) if data.len() as i64 >= limit => Some(id(last)),
+            _ => None,
+        };
+        Self {
+            data,
+            next_after_id,
+            total,
+        }
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U, K> {
+        Page {
+            data: self.data.into_iter().map(f).collect(),
+            next_after_id: self.next_after_id,
+            total: self.total,
+        }
+    }
+}
+
+/// Filter of the history listings (evaluation runs, job runs, ...), latest rows first: `cursor`
+/// is the id of the last row of the previous page, `from` (inclusive) and `to` (exclusive) bound
+/// the creation time and `status` keeps the rows of one status of the listing.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(bound(deserialize = "S: Deserialize<'de>"))]
+pub struct HistoryFilter<S> {
+    #[serde(default)]
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub cursor: Option<i64>,
+    #[serde(default)]
+    pub from: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub status: Option<S>,
+}
+
+impl<S> Default for HistoryFilter<S> {
+    fn default() -> Self {
+        Self {
+            limit: None,
+            cursor: None,
+            from: None,
+            to: None,
+            status: None,
+        }
+    }
+}
+
+impl<S> HistoryFilter<S> {
+    /// `DEFAULT_PAGE_LIMIT` when not given, `validate` rejects the limits out of range
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_PAGE_LIMIT)
+    }
+
+    /// Rejects a limit outside of `1..=MAX_PAGE_LIMIT` and an empty time range, rather than
+    /// silently returning another listing than the one asked for
+    pub fn validate(&self) -> core::result::Result<(), String> {
+        if !(1..=MAX_PAGE_LIMIT).contains(&self.limit()) {
+            return Err(format!("`limit` must be between 1 and {MAX_PAGE_LIMIT}"));
+        }
+        if self.cursor.is_some_and(|cursor| cursor < 1) {
+            return Err("`cursor` must be a positive id".to_string());
+        }
+        match (self.from, self.to) {
+            (Some(from), Some(to)) if from >= to => Err("`from` must be before `to`".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Time bounds as stored in the `TIMESTAMP` columns, in UTC
+    pub fn naive_range(&self) -> (Option<NaiveDateTime>, Option<NaiveDateTime>) {
+        (
+            self.from.map(|from| from.naive_utc()),
+            self.to.map(|to| to.naive_utc()),
+        )
+    }
+
+    /// Cursor of the next page: the id of the last row of a full page
+    pub fn next_cursor<T>(&self, rows: &[T], id: impl Fn(&T) -> i64) -> Option<i64> {
+        match rows.last() {
+            Some(last) if rows.len() as i64 >= self.limit() => Some(id(last)),
+            _ => None,
+        }
+    }
+}
+
+// endregion: Structs
+
+// region: Unit Test
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_cursor() {
+        let options = ListOptions::<i64> {
+            limit: Some(5_000),
+            ..Default::default()
+        };
+        assert_eq!(options.limit(), MAX_PAGE_LIMIT);
+
+        let page = Page::new(vec![3_i64, 4, 5], 10, 3, |id| *id);
+        assert_eq!(page.next_after_id, Some(5));
+        let page = Page::new(vec![6_i64, 7], 10, 3, |id| *id);
+        assert_eq!(page.next_after_id, None);
+        assert_eq!(page.map(|id| id.to_string()).data, vec!["6", "7"]);
+    }
+
+    #[test]
+    fn test_history_filter() {
+        let filter: HistoryFilter<String> = serde_json::from_str(
+            r#"{"limit": 2, "from": "2025-01-01T00:00:00Z", "to": "2025-02-01T00:00:00Z"}"#,
+        )
+        .unwrap();
+        assert!(filter.validate().is_ok());
+        assert_eq!(filter.next_cursor(&[9_i64, 8], |id| *id), Some(8));
+        assert_eq!(filter.next_cursor(&[9_i64], |id| *id), None);
+
+        let reversed = HistoryFilter::<String> {
+            from: filter.to,
+            to: filter.from,
+            ..Default::default()
+        };
+        assert!(reversed.validate().is_err());
+        let too_many = HistoryFilter::<String> {
+            limit: Some(MAX_PAGE_LIMIT + 1),
+            ..Default::default()
+        };
+        assert!(too_many.validate().is_err());
+        assert!(HistoryFilter::<String>::default().validate().is_ok());
+    }
+}
+
+// endregion: Unit Test
@@ -0,0 +1,461 @@
+//! Primary to replica replication of the chunks and their vectors. Every write of a chunk takes
+//! the next value of `file_chunks_replication_seq` (hard deletes leave a tombstone with one), a
+//! replica pulls the changes after the last value it applied and resumes from it.
+//!
+//! Batches travel as frames: `FRAME_MAGIC`, the format version and the zstd compressed JSON of the
+//! batch, with the vectors packed as little endian `f32`.
+
+use crate::config::auth_config;
+use crate::database::ModelManager;
+use crate::error::{Error, Result};
+use crate::model::embedding_dims::EmbeddingDimsMac;
+use crate::model::file_chunks::{
+    ChunkMetadata, FileChunkRow, default_embedding_normalized, encode_content, into_chunk,
+};
+use crate::model::files::File;
+use crate::vector_index::EmbeddingStorage;
+use lib_utils::base64::{b64u_decode, b64u_encode};
+use lib_utils::compression::{zstd_compress, zstd_decompress};
+use pgvector::Vector;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use sqlx::types::Json;
+use sqlx::types::chrono::NaiveDateTime;
+use std::collections::HashMap;
+
+/// First bytes of a frame, followed by `FRAME_VERSION`
+const FRAME_MAGIC: &[u8; 4] = b"ESRF";
+const FRAME_VERSION: u8 = 1;
+/// Content type of the frames served to the replicas
+pub const FRAME_CONTENT_TYPE: &str = "application/vnd.embedding-server.replication+zstd";
+
+// region: Structs
+
+/// Chunk as sent to the replicas, the text is inline whatever its encoding on the primary
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ReplicatedChunk {
+    pub chunk_id: i64,
+    pub file_id: i64,
+    pub tenant_id: String,
+    pub chunk_index: i32,
+    pub content_md: Option<String>,
+    /// Little endian `f32` components, base64url encoded
+    pub embedding: Option<String>,
+    pub embedding_model: Option<String>,
+    /// Absent from the batches of primaries older than the column
+    #[serde(default = "default_embedding_normalized")]
+    pub embedding_normalized: bool,
+    pub token_count: Option<i32>,
+    pub oversize: Option<String>,
+    pub metadata: ChunkMetadata,
+    pub content_hash: Option<String>,
+    pub deleted_at: Option<NaiveDateTime>,
+}
+
+/// Changes of the chunks between two cursors
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ReplicationBatch {
+    /// Sequence value of the last change of the batch, the cursor to resume from
+    pub cursor: i64,
+    /// Current state of the files of `chunks`
+    pub files: Vec<File>,
+    pub chunks: Vec<ReplicatedChunk>,
+    /// Hard deleted chunks
+    pub deleted: Vec<i64>,
+}
+
+#[derive(FromRow)]
+struct ReplicatedRow {
+    #[sqlx(flatten)]
+    row: FileChunkRow,
+    deleted_at: Option<NaiveDateTime>,
+}
+
+// endregion: Structs
+
+fn encode_vector(vector: &Vector) -> String {
+    let bytes: Vec<u8> = vector
+        .as_slice()
+        .iter()
+        .flat_map(|component| component.to_le_bytes())
+        .collect();
+    b64u_encode(bytes)
+}
+
+fn decode_vector(data: &str) -> Result<Vector> {
+    let bytes = b64u_decode(data)?;
+    if bytes.len() % 4 != 0 {
+        return Err(Error::Custom("Invalid replicated embedding".to_string()));
+    }
+    let components: Vec<f32> = bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    Ok(Vector::from(components))
+}
+
+impl ReplicationBatch {
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty() && self.deleted.is_empty()
+    }
+
+    pub fn encode_frame(&self) -> Result<Vec<u8>> {
+        let body = zstd_compress(serde_json::to_vec(self)?)?;
+        let mut frame = Vec::with_capacity(FRAME_MAGIC.len() + 1 + body.len());
+        frame.extend_from_slice(FRAME_MAGIC);
+        frame.push(FRAME_VERSION);
+        frame.extend_from_slice(&body);
+        Ok(frame)
+    }
+
+    pub fn decode_frame(frame: &[u8]) -> Result<Self> {
+        let body = match frame.strip_prefix(FRAME_MAGIC.as_slice()) {
+            Some([FRAME_VERSION, body @ ..]) => body,
+            Some(_) => {
+                return Err(Error::Custom(
+                    "Unsupported replication frame version".to_string(),
+                ));
+            }
+            None => return Err(Error::Custom("Invalid replication frame".to_string())),
+        };
+        Ok(serde_json::from_slice(&zstd_decompress(body)?)?)
+    }
+}
+
+// region: CRUD
+
+/// Replication acts on every tenant, it is only exposed to admins. A replica only applies the
+/// batches of its primary, it must not ingest files itself since the ids come from the primary.
+pub struct ReplicationMac;
+
+impl ReplicationMac {
+    /// Sequence value of the last change, 0 before any
+    pub async fn current_cursor(mm: &ModelManager) -> Result<i64> {
+        let cursor = sqlx::query_scalar(
+            r#"
+            SELECT GREATEST(
+                (SELECT MAX(replication_seq) FROM file_chunks),
+                (SELECT MAX(replication_seq) FROM chunk_tombstones),
+                0
+            )
+            "#,
+        )
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(cursor)
+    }
+
+    /// Up to `limit` changes after `cursor`. A chunk changed again since is sent in its current
+    /// state, a chunk deleted since is left to its tombstone.
+    pub async fn read_batch(
+        mm: &ModelManager,
+        cursor: i64,
+        limit: i64,
+    ) -> Result<ReplicationBatch> {
+        let db = mm.db();
+        let changes = sqlx::query_as::<_, (i64, i64, bool)>(
+            r#"
+            SELECT replication_seq, chunk_id, deleted FROM (
+                SELECT replication_seq, chunk_id, FALSE AS deleted FROM file_chunks
+                WHERE replication_seq > $1
+                UNION ALL
+                SELECT replication_seq, chunk_id, TRUE AS deleted FROM chunk_tombstones
+                WHERE replication_seq > $1
+            ) changes
+            ORDER BY replication_seq
+            LIMIT $2
+            "#,
+        )
+        .bind(cursor)
+        .bind(limit)
+        .fetch_all(db)
+        .await?;
+
+        let Some(&(last_seq, _, _)) = changes.last() else {
+            return Ok(ReplicationBatch {
+                cursor,
+                ..Default::default()
+            });
+        };
+        let (deleted, written): (Vec<_>, Vec<_>) =
+            changes.into_iter().partition(|(_, _, deleted)| *deleted);
+        let written: Vec<i64> = written
+            .into_iter()
+            .map(|(_, chunk_id, _)| chunk_id)
+            .collect();
+
+        let rows = sqlx::query_as::<_, ReplicatedRow>(
+            r#"
+            SELECT * FROM file_chunks WHERE chunk_id = ANY($1) ORDER BY replication_seq
+            "#,
+        )
+        .bind(&written)
+        .fetch_all(db)
+        .await?;
+
+        let mut chunks = Vec::with_capacity(rows.len());
+        for ReplicatedRow { row, deleted_at } in rows {
+            let chunk = into_chunk(mm, row).await?;
+            chunks.push(ReplicatedChunk {
+                chunk_id: chunk.chunk_id,
+                file_id: chunk.file_id,
+                tenant_id: chunk.tenant_id,
+                chunk_index: chunk.chunk_index,
+                content_md: chunk.content_md,
+                embedding: chunk.embedding.as_ref().map(encode_vector),
+                embedding_model: chunk.embedding_model,
+                embedding_normalized: chunk.embedding_normalized,
+                token_count: chunk.token_count,
+                oversize: chunk.oversize,
+                metadata: chunk.metadata,
+                content_hash: chunk.content_hash,
+                deleted_at,
+            });
+        }
+
+        let mut file_ids: Vec<i64> = chunks.iter().map(|c| c.file_id).collect();
+        file_ids.sort_unstable();
+        file_ids.dedup();
+        let files = sqlx::query_as::<_, File>(
+            r#"
+            SELECT * FROM files WHERE file_id = ANY($1)
+            "#,
+        )
+        .bind(&file_ids)
+        .fetch_all(db)
+        .await?;
+
+        Ok(ReplicationBatch {
+            cursor: last_seq,
+            files,
+            chunks,
+            deleted: deleted
+                .into_iter()
+                .map(|(_, chunk_id, _)| chunk_id)
+                .collect(),
+        })
+    }
+
+    /// Apply a batch of the primary in one transaction and return the cursor to resume from. The
+    /// text is stored in the DB, compressed when `CHUNK_COMPRESSION` is set on the replica. A
+    /// vector which does not have the dimension of its collection on the replica fails the batch.
+    pub async fn apply_batch(mm: &ModelManager, batch: ReplicationBatch) -> Result<i64> {
+        let expected = EmbeddingDimsMac::expected(mm).await?;
+        let stored = EmbeddingStorage::load()?.cast("$9");
+        let collections: HashMap<i64, String> = batch
+            .files
+            .iter()
+            .map(|file| (file.file_id, file.applicant.clone()))
+            .collect();
+        let mut tx = mm.db().begin().await?;
+
+        for file in batch.files {
+            sqlx::query(
+                r#"
+                INSERT INTO files (file_id, tenant_id, applicant, filename, file_type, created_at, processed, warning, deleted_at, etag, last_modified, version_id)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+                ON CONFLICT (file_id) DO UPDATE SET
+                    tenant_id = EXCLUDED.tenant_id,
+                    applicant = EXCLUDED.applicant,
+                    filename = EXCLUDED.filename,
+                    file_type = EXCLUDED.file_type,
+                    processed = EXCLUDED.processed,
+                    warning = EXCLUDED.warning,
+                    deleted_at = EXCLUDED.deleted_at,
+                    etag = EXCLUDED.etag,
+                    last_modified = EXCLUDED.last_modified,
+                    version_id = EXCLUDED.version_id
+                "#,
+            )
+            .bind(file.file_id)
+            .bind(file.tenant_id)
+            .bind(file.applicant)
+            .bind(file.filename)
+            .bind(file.file_type)
+            .bind(file.created_at)
+            .bind(file.processed)
+            .bind(file.warning)
+            .bind(file.deleted_at)
+            .bind(file.etag)
+            .bind(file.last_modified)
+            .bind(file.version_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for chunk in batch.chunks {
+            // The primary replaced the chunk of this text, its tombstone may be in a later batch
+            if let Some(hash) = &chunk.content_hash {
+                sqlx::query(
+                    r#"
+                    DELETE FROM file_chunks
+                    WHERE file_id = $1 AND content_hash = $2 AND chunk_id <> $3
+                    "#,
+                )
+                .bind(chunk.file_id)
+                .bind(hash)
+                .bind(chunk.chunk_id)
+                .execute(&mut *tx)
+                .await?;
+            }
+
+            let embedding = chunk.embedding.as_deref().map(decode_vector).transpose()?;
+            if let (Some(embedding), Some(collection)) =
+                (&embedding, collections.get(&chunk.file_id))
+            {
+                expected.check(
+                    collection,
+                    embedding.as_slice().len(),
+                    chunk.embedding_model.as_deref(),
+                )?;
+            }
+            let search_text = chunk.content_md.clone();
+            let (content_md, content_zstd, content_encoding) =
+                encode_content(chunk.content_md, auth_config().chunk_compression)?;
+            sqlx::query(&format!(
+                r#"
+                INSERT INTO file_chunks (chunk_id, file_id, tenant_id, chunk_index, content_md, content_zstd, content_encoding, content_hash, embedding, embedding_model, embedding_dim, embedding_normalized, token_count, oversize, metadata, deleted_at, search_tsv)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, {stored}, $10, vector_dims($9), $16, $11, $12, $13, $14, to_tsvector(chunk_search_config($2), $15))
+                ON CONFLICT (chunk_id) DO UPDATE SET
+                    file_id = EXCLUDED.file_id,
+                    tenant_id = EXCLUDED.tenant_id,
+                    chunk_index = EXCLUDED.chunk_index,
+                    content_md = EXCLUDED.content_md,
+                    content_zstd = EXCLUDED.content_zstd,
+                    content_encoding = EXCLUDED.content_encoding,
+                    content_offset = NULL,
+                    content_length = NULL,
+                    content_hash = EXCLUDED.content_hash,
+                    embedding = EXCLUDED.embedding,
+                    embedding_model = EXCLUDED.embedding_model,
+                    embedding_dim = EXCLUDED.embedding_dim,
+                    embedding_normalized = EXCLUDED.embedding_normalized,
+                    token_count = EXCLUDED.token_count,
+                    oversize = EXCLUDED.oversize,
+                    metadata = EXCLUDED.metadata,
+                    deleted_at = EXCLUDED.deleted_at,
+                    search_config = EXCLUDED.search_config,
+                    search_tsv = EXCLUDED.search_tsv
+                "#
+            ))
+            .bind(chunk.chunk_id)
+            .bind(chunk.file_id)
+            .bind(chunk.tenant_id)
+            .bind(chunk.chunk_index)
+            .bind(content_md)
+            .bind(content_zstd)
+            .bind(content_encoding)
+            .bind(chunk.content_hash)
+            .bind(embedding)
+            .bind(chunk.embedding_model)
+            .bind(chunk.token_count)
+            .bind(chunk.oversize)
+            .bind(Json(chunk.metadata))
+            .bind(chunk.deleted_at)
+            .bind(search_text)
+            .bind(chunk.embedding_normalized)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if !batch.deleted.is_empty() {
+            sqlx::query(
+                r#"
+                DELETE FROM file_chunks WHERE chunk_id = ANY($1)
+                "#,
+            )
+            .bind(&batch.deleted)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(batch.cursor)
+    }
+}
+
+// endregion: CRUD
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils::init_dev;
+    use crate::ctx::DEFAULT_TENANT;
+    use crate::model::file_chunks::{FileChunkForCreate, FileChunkMac};
+
+    #[test]
+    fn test_frame_roundtrip() -> Result<()> {
+        let embedding = Vector::from(vec![0.25, -1.5, 3.0]);
+        let batch = ReplicationBatch {
+            cursor: 42,
+            files: vec![],
+            chunks: vec![ReplicatedChunk {
+                chunk_id: 7,
+                file_id: 1001,
+                tenant_id: DEFAULT_TENANT.to_string(),
+                chunk_index: 0,
+                content_md: Some("Hello world".to_string()),
+                embedding: Some(encode_vector(&embedding)),
+                embedding_model: Some("test-model".to_string()),
+                embedding_normalized: false,
+                token_count: Some(2),
+                oversize: None,
+                metadata: ChunkMetadata::default(),
+                content_hash: None,
+                deleted_at: None,
+            }],
+            deleted: vec![3],
+        };
+
+        let frame = batch.encode_frame()?;
+        assert!(frame.starts_with(FRAME_MAGIC));
+        let decoded = ReplicationBatch::decode_frame(&frame)?;
+        assert_eq!(decoded.cursor, 42);
+        assert_eq!(decoded.chunks, batch.chunks);
+        assert_eq!(decoded.deleted, vec![3]);
+        let vector = decode_vector(decoded.chunks[0].embedding.as_deref().unwrap_or_default())?;
+        assert_eq!(vector.as_slice(), embedding.as_slice());
+
+        assert!(ReplicationBatch::decode_frame(b"not a frame").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_batch_resumes_from_cursor() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev(db);
+
+        let start = ReplicationMac::current_cursor(&mm).await?;
+        let chunk = FileChunkMac::create_chunk(
+            &mm,
+            DEFAULT_TENANT,
+            FileChunkForCreate {
+                file_id: 1001,
+                chunk_index: 90,
+                content_md: Some("Replicated".into()),
+                embedding: Some(Vector::from(vec![0.1, 0.2, 0.3])),
+                embedding_model: Some("test-model".into()),
+                embedding_normalized: true,
+                token_count: Some(1),
+                oversize: None,
+                metadata: ChunkMetadata::default(),
+            },
+        )
+        .await?;
+
+        let batch = ReplicationMac::read_batch(&mm, start, 100).await?;
+        assert!(batch.cursor > start);
+        assert!(ReplicationMac::current_cursor(&mm).await? >= batch.cursor);
+        assert!(batch.chunks.iter().any(|c| c.chunk_id == chunk.chunk_id));
+        assert!(batch.files.iter().any(|f| f.file_id == 1001));
+
+        FileChunkMac::delete_chunk(&mm, DEFAULT_TENANT, chunk.chunk_id).await?;
+        let batch = ReplicationMac::read_batch(&mm, batch.cursor, 100).await?;
+        assert!(batch.deleted.contains(&chunk.chunk_id));
+        assert!(batch.chunks.iter().all(|c| c.chunk_id != chunk.chunk_id));
+        Ok(())
+    }
+}
+// endregion: Unit Test
@@ -0,0 +1,152 @@
+use crate::database::ModelManager;
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use sqlx::types::chrono::NaiveDateTime;
+
+// region: Structs
+
+/// Named search options of a tenant, `params` holds the options of `/search` except the query
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct SearchTemplate {
+    pub tenant_id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub params: serde_json::Value,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SearchTemplateForSave {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub params: serde_json::Value,
+}
+
+// endregion: Structs
+
+// region: CRUD
+
+/// Every query is scoped to `tenant_id`, templates of another tenant are not found
+pub struct SearchTemplateMac;
+
+impl SearchTemplateMac {
+    /// Create the template or replace the one with the same name
+    pub async fn save_template(
+        mm: &ModelManager,
+        tenant_id: &str,
+        template: SearchTemplateForSave,
+    ) -> Result<SearchTemplate> {
+        let db = mm.db();
+        let template = sqlx::query_as::<_, SearchTemplate>(
+            r#"
+            INSERT INTO search_templates (tenant_id, name, description, params)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (tenant_id, name) DO UPDATE
+            SET description = EXCLUDED.description, params = EXCLUDED.params, updated_at = now()
+            RETURNING *
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(template.name)
+        .bind(template.description)
+        .bind(template.params)
+        .fetch_one(db)
+        .await?;
+
+        Ok(template)
+    }
+
+    pub async fn get_template(
+        mm: &ModelManager,
+        tenant_id: &str,
+        name: &str,
+    ) -> Result<Option<SearchTemplate>> {
+        let db = mm.db();
+        let template = sqlx::query_as::<_, SearchTemplate>(
+            r#"
+            SELECT * FROM search_templates WHERE tenant_id = $1 AND name = $2
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(name)
+        .fetch_optional(db)
+        .await?;
+
+        Ok(template)
+    }
+
+    pub async fn list_templates(mm: &ModelManager, tenant_id: &str) -> Result<Vec<SearchTemplate>> {
+        let db = mm.db();
+        let templates = sqlx::query_as::<_, SearchTemplate>(
+            r#"
+            SELECT * FROM search_templates WHERE tenant_id = $1 ORDER BY name
+            "#,
+        )
+        .bind(tenant_id)
+        .fetch_all(db)
+        .await?;
+
+        Ok(templates)
+    }
+
+    pub async fn delete_template(mm: &ModelManager, tenant_id: &str, name: &str) -> Result<u64> {
+        let res = sqlx::query(
+            r#"
+            DELETE FROM search_templates WHERE tenant_id = $1 AND name = $2
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(name)
+        .execute(mm.db())
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+}
+
+// endregion: CRUD
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils::init_dev;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_save_and_delete_template() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev(db);
+
+        let template = |limit| SearchTemplateForSave {
+            name: "support-tickets".to_string(),
+            description: None,
+            params: json!({"limit": limit}),
+        };
+        SearchTemplateMac::save_template(&mm, "tenant-a", template(5)).await?;
+        SearchTemplateMac::save_template(&mm, "tenant-a", template(20)).await?;
+        let saved = SearchTemplateMac::get_template(&mm, "tenant-a", "support-tickets").await?;
+        assert_eq!(saved.map(|t| t.params), Some(json!({"limit": 20})));
+
+        // Other tenants do not see the template
+        assert!(
+            SearchTemplateMac::get_template(&mm, "tenant-b", "support-tickets")
+                .await?
+                .is_none()
+        );
+        assert_eq!(
+            SearchTemplateMac::delete_template(&mm, "tenant-a", "support-tickets").await?,
+            1
+        );
+        assert!(
+            SearchTemplateMac::list_templates(&mm, "tenant-a")
+                .await?
+                .is_empty()
+        );
+        Ok(())
+    }
+}
+// endregion: Unit Test
@@ -0,0 +1,106 @@
+use crate::database::ModelManager;
+use crate::error::Result;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sqlx::FromRow;
+use sqlx::types::chrono::NaiveDateTime;
+
+// region: Structs
+
+/// Runtime setting persisted as JSON, e.g. the batch limits changed through the admin API
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+pub struct Setting {
+    pub key: String,
+    pub value: serde_json::Value,
+    pub updated_at: NaiveDateTime,
+}
+
+// endregion: Structs
+
+// region: CRUD
+
+pub struct SettingMac;
+
+impl SettingMac {
+    pub async fn get_setting(mm: &ModelManager, key: &str) -> Result<Option<Setting>> {
+        let db = mm.db();
+        let setting = sqlx::query_as::<_, Setting>(
+            r#"
+            SELECT * FROM settings WHERE key = $1
+            "#,
+        )
+        .bind(key)
+        .fetch_optional(db)
+        .await?;
+
+        Ok(setting)
+    }
+
+    /// Get a setting deserialized into `T`, `None` when it was never set
+    pub async fn get_value<T: DeserializeOwned>(mm: &ModelManager, key: &str) -> Result<Option<T>> {
+        match Self::get_setting(mm, key).await? {
+            Some(setting) => Ok(Some(serde_json::from_value(setting.value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set_value<T: Serialize>(
+        mm: &ModelManager,
+        key: &str,
+        value: &T,
+    ) -> Result<Setting> {
+        let db = mm.db();
+        let setting = sqlx::query_as::<_, Setting>(
+            r#"
+            INSERT INTO settings (key, value)
+            VALUES ($1, $2)
+            ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value, updated_at = now()
+            RETURNING *
+            "#,
+        )
+        .bind(key)
+        .bind(serde_json::to_value(value)?)
+        .fetch_one(db)
+        .await?;
+
+        Ok(setting)
+    }
+
+    pub async fn delete_setting(mm: &ModelManager, key: &str) -> Result<u64> {
+        let res = sqlx::query(
+            r#"
+            DELETE FROM settings WHERE key = $1
+            "#,
+        )
+        .bind(key)
+        .execute(mm.db())
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+}
+
+// endregion: CRUD
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils::init_dev;
+
+    #[tokio::test]
+    async fn test_set_and_get_value() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev(db);
+
+        SettingMac::set_value(&mm, "test_setting", &vec![1, 2, 3]).await?;
+        SettingMac::set_value(&mm, "test_setting", &vec![4]).await?;
+        let value: Option<Vec<i32>> = SettingMac::get_value(&mm, "test_setting").await?;
+        assert_eq!(value, Some(vec![4]));
+
+        SettingMac::delete_setting(&mm, "test_setting").await?;
+        let value: Option<Vec<i32>> = SettingMac::get_value(&mm, "test_setting").await?;
+        assert!(value.is_none());
+        Ok(())
+    }
+}
+// endregion: Unit Test
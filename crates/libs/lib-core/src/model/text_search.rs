@@ -0,0 +1,162 @@
+//! Text search configuration (Postgres `regconfig`, e.g. `german` or `simple`) of the keyword
+//! search per collection (file applicant). The lexemes of a chunk (`search_tsv`) are computed with
+//! the configuration of its collection when it is inserted, the query is parsed with the
+//! configuration of every chunk it is matched against.
+
+use crate::database::ModelManager;
+use crate::error::{Error, Result};
+use crate::model::file_chunks::{FileChunkRow, into_chunks};
+use crate::model::settings::SettingMac;
+use std::collections::{BTreeSet, HashMap};
+
+/// Settings key of the per collection configurations, a map of collection to `regconfig`. Read by
+/// the `chunk_search_config` SQL function, see migration `0013`.
+pub const TEXT_SEARCH_CONFIG_SETTING: &str = "text_search_configs";
+/// Configuration of the collections without one
+pub const DEFAULT_TEXT_SEARCH_CONFIG: &str = "english";
+/// Chunks whose lexemes are recomputed per statement by `reindex_collection`
+const REINDEX_BATCH_SIZE: i64 = 500;
+
+pub struct TextSearchMac;
+
+impl TextSearchMac {
+    pub async fn configs(mm: &ModelManager) -> Result<HashMap<String, String>> {
+        Ok(SettingMac::get_value(mm, TEXT_SEARCH_CONFIG_SETTING)
+            .await?
+            .unwrap_or_default())
+    }
+
+    /// Distinct configurations the chunks may have been indexed with
+    pub async fn configs_in_use(mm: &ModelManager) -> Result<Vec<String>> {
+        let configs = Self::configs(mm).await?;
+        Ok(in_use(&configs))
+    }
+
+    /// Name of the configuration, `None` when Postgres does not have it
+    pub async fn find_config(mm: &ModelManager, name: &str) -> Result<Option<String>> {
+        let config: Option<(String,)> = sqlx::query_as(
+            r#"
+            SELECT cfgname::TEXT FROM pg_ts_config WHERE cfgname = $1
+            "#,
+        )
+        .bind(name)
+        .fetch_optional(mm.db())
+        .await?;
+
+        Ok(config.map(|(name,)| name))
+    }
+
+    /// Set the configuration of the collection, `None` to fall back to
+    /// `DEFAULT_TEXT_SEARCH_CONFIG`, and reindex its chunks. Returns the number of reindexed
+    /// chunks.
+    pub async fn set_config(
+        mm: &ModelManager,
+        collection: &str,
+        config: Option<&str>,
+    ) -> Result<u64> {
+        let mut configs = Self::configs(mm).await?;
+        match config {
+            Some(config) => {
+                if Self::find_config(mm, config).await?.is_none() {
+                    return Err(Error::Custom(format!(
+                        "Unknown text search configuration `{config}`"
+                    )));
+                }
+                configs.insert(collection.to_string(), config.to_string());
+            }
+            None => {
+                configs.remove(collection);
+            }
+        }
+        SettingMac::set_value(mm, TEXT_SEARCH_CONFIG_SETTING, &configs).await?;
+        Self::reindex_collection(mm, collection).await
+    }
+
+    /// Recompute the lexemes of every chunk of the collection with its current configuration, in
+    /// batches. Compressed and S3 backed texts are decoded first.
+    pub async fn reindex_collection(mm: &ModelManager, collection: &str) -> Result<u64> {
+        let db = mm.db();
+        let mut cursor = 0i64;
+        let mut reindexed = 0u64;
+        loop {
+            let rows = sqlx::query_as::<_, FileChunkRow>(
+                r#"
+                SELECT c.* FROM file_chunks c JOIN files f ON f.file_id = c.file_id
+                WHERE f.applicant = $1 AND c.chunk_id > $2
+                ORDER BY c.chunk_id
+                LIMIT $3
+                "#,
+            )
+            .bind(collection)
+            .bind(cursor)
+            .bind(REINDEX_BATCH_SIZE)
+            .fetch_all(db)
+            .await?;
+            let chunks = into_chunks(mm, rows).await?;
+            let Some(last) = chunks.last() else {
+                return Ok(reindexed);
+            };
+            cursor = last.chunk_id;
+            let (ids, texts): (Vec<i64>, Vec<Option<String>>) = chunks
+                .into_iter()
+                .map(|chunk| (chunk.chunk_id, chunk.content_md))
+                .unzip();
+            let res = sqlx::query(
+                r#"
+                UPDATE file_chunks c SET
+                    search_config = chunk_search_config(c.file_id),
+                    search_tsv = to_tsvector(chunk_search_config(c.file_id), t.text)
+                FROM unnest($1::BIGINT[], $2::TEXT[]) AS t(chunk_id, text)
+                WHERE c.chunk_id = t.chunk_id
+                "#,
+            )
+            .bind(ids)
+            .bind(texts)
+            .execute(db)
+            .await?;
+            reindexed += res.rows_affected();
+        }
+    }
+}
+
+fn in_use(configs: &HashMap<String, String>) -> Vec<String> {
+    configs
+        .values()
+        .map(String::as_str)
+        .chain([DEFAULT_TEXT_SEARCH_CONFIG])
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(str::to_string)
+        .collect()
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::_dev_utils::init_dev;
+
+    #[test]
+    fn test_in_use() {
+        let configs = HashMap::from([
+            ("de".to_string(), "german".to_string()),
+            ("at".to_string(), "german".to_string()),
+            ("codes".to_string(), "simple".to_string()),
+        ]);
+        assert_eq!(in_use(&configs), vec!["english", "german", "simple"]);
+        assert_eq!(in_use(&HashMap::new()), vec!["english"]);
+    }
+
+    #[tokio::test]
+    async fn test_find_config() -> Result<()> {
+        let db = init_dev().await?;
+        let mm = ModelManager::dev(db);
+        assert_eq!(
+            TextSearchMac::find_config(&mm, "german").await?.as_deref(),
+            Some("german")
+        );
+        assert!(TextSearchMac::find_config(&mm, "klingon").await?.is_none());
+        Ok(())
+    }
+}
+// endregion: Unit Test
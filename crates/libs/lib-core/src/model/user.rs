@@ -6,6 +6,7 @@ use sqlx::types::Uuid;
 use sqlx::FromRow;
 
 use crate::database::ModelManager;
+use crate::model::pagination::ListOptions;
 
 // region:  Structs
 
@@ -32,6 +33,7 @@ impl std::fmt::Display for Role {
 #[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
 pub struct User {
     pub user_id: String,
+    pub tenant_id: String,
     pub first_name: String,
     pub last_name: String,
     pub email: String,
@@ -40,6 +42,18 @@ pub struct User {
     pub api_key: Option<String>,
     #[serde_as(as = "chrono::DateTime<chrono::Utc>")]
     pub created_at: NaiveDateTime,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub rate_limit: UserRateLimit,
+}
+
+/// Rate limit of a user, every `None` field keeps the value of the tier of its role
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, FromRow)]
+pub struct UserRateLimit {
+    pub rate_limit_rps: Option<i32>,
+    pub rate_limit_burst: Option<i32>,
+    /// Maximum inputs per request
+    pub max_batch_size: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -62,26 +76,36 @@ pub struct UserForUpdate {
 #[derive(Debug, Clone, FromRow)]
 pub struct UserForAuthentication {
     pub user_id: String,
+    pub tenant_id: String,
     pub salt: Uuid,
     pub api_key: Option<String>,
     pub role: Role,
+    #[sqlx(flatten)]
+    pub rate_limit: UserRateLimit,
 }
 
 // endregion:  Structs
 
 // region: CRUD
+/// User ids are unique across tenants, every query but `get_user_for_auth` (which resolves the
+/// tenant of a key) is scoped to `tenant_id`
 pub struct UserBmc;
 impl UserBmc {
-    pub async fn create_user(mm: &ModelManager, user: UserForCreate) -> Result<User> {
+    pub async fn create_user(
+        mm: &ModelManager,
+        tenant_id: &str,
+        user: UserForCreate,
+    ) -> Result<User> {
         let db = mm.db();
         let query = sqlx::query_as::<_, User>(
             r#"
-            INSERT INTO users (user_id, first_name, last_name, email, role)
-            VALUES ($1, $2, $3, $4, $5)
+            INSERT INTO users (user_id, tenant_id, first_name, last_name, email, role)
+            VALUES ($1, $2, $3, $4, $5, $6)
             RETURNING *
             "#,
         )
         .bind(user.user_id)
+        .bind(tenant_id)
         .bind(user.first_name)
         .bind(user.last_name)
         .bind(user.email)
@@ -91,40 +115,83 @@ impl UserBmc {
         Ok(user)
     }
 
-    pub async fn get_user_by_id(mm: &ModelManager, user_id: &str) -> Result<User> {
+    /// Create the first user of the deployment as an admin, `None` when a user already exists. The
+    /// table is locked so concurrently starting replicas create a single admin.
+    pub async fn create_first_admin(
+        mm: &ModelManager,
+        tenant_id: &str,
+        user: UserForCreate,
+    ) -> Result<Option<User>> {
+        let mut tx = mm.db().begin().await?;
+        sqlx::query("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await?;
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            INSERT INTO users (user_id, tenant_id, first_name, last_name, email, role)
+            SELECT $1, $2, $3, $4, $5, $6
+            WHERE NOT EXISTS (SELECT 1 FROM users)
+            RETURNING *
+            "#,
+        )
+        .bind(user.user_id)
+        .bind(tenant_id)
+        .bind(user.first_name)
+        .bind(user.last_name)
+        .bind(user.email)
+        .bind(Role::Admin)
+        .fetch_optional(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    pub async fn get_user_by_id(mm: &ModelManager, tenant_id: &str, user_id: &str) -> Result<User> {
         let db = mm.db();
         let query = sqlx::query_as::<_, User>(
             r#"
-            SELECT * FROM users WHERE user_id = $1
+            SELECT * FROM users WHERE user_id = $1 AND tenant_id = $2
             "#,
         )
-        .bind(user_id);
+        .bind(user_id)
+        .bind(tenant_id);
 
         let user = query.fetch_one(db).await?;
         Ok(user)
     }
 
-    pub async fn get_user_by_email(mm: &ModelManager, email: &str) -> Result<User> {
+    pub async fn get_user_by_email(
+        mm: &ModelManager,
+        tenant_id: &str,
+        email: &str,
+    ) -> Result<User> {
         let db = mm.db();
         let query = sqlx::query_as::<_, User>(
             r#"
-            SELECT * FROM users WHERE email = $1
+            SELECT * FROM users WHERE email = $1 AND tenant_id = $2
             "#,
         )
-        .bind(email);
+        .bind(email)
+        .bind(tenant_id);
 
         let user = query.fetch_one(db).await?;
         Ok(user)
     }
 
-    pub async fn get_user_by_api_key(mm: &ModelManager, api_key: &str) -> Result<User> {
+    pub async fn get_user_by_api_key(
+        mm: &ModelManager,
+        tenant_id: &str,
+        api_key: &str,
+    ) -> Result<User> {
         let db = mm.db();
         let query = sqlx::query_as::<_, User>(
             r#"
-            SELECT * FROM users WHERE api_key = $1
+            SELECT * FROM users WHERE api_key = $1 AND tenant_id = $2
             "#,
         )
-        .bind(api_key);
+        .bind(api_key)
+        .bind(tenant_id);
 
         let user = query.fetch_one(db).await?;
         Ok(user)
@@ -132,6 +199,7 @@ impl UserBmc {
 
     pub async fn update_user(
         mm: &ModelManager,
+        tenant_id: &str,
         user_id: &str,
         user_update: UserForUpdate,
     ) -> Result<User> {
@@ -144,8 +212,8 @@ impl UserBmc {
                 last_name = COALESCE($3, last_name),
                 email = COALESCE($4, email),
                 role = COALESCE($5, role),
-                api_key = COALESCE($6, api_key),
-            WHERE user_id = $1
+                api_key = COALESCE($6, api_key)
+            WHERE user_id = $1 AND tenant_id = $7
             RETURNING *
             "#,
         )
@@ -154,37 +222,166 @@ impl UserBmc {
         .bind(user_update.last_name)
         .bind(user_update.email)
         .bind(user_update.role)
-        .bind(user_update.api_key);
+        .bind(user_update.api_key)
+        .bind(tenant_id);
+
+        let user = query.fetch_one(db).await?;
+        Ok(user)
+    }
+
+    pub async fn get_user_for_auth(
+        mm: &ModelManager,
+        user_id: &str,
+    ) -> Result<UserForAuthentication> {
+        let db = mm.db();
+        let query = sqlx::query_as::<_, UserForAuthentication>(
+            r#"
+            SELECT user_id, tenant_id, salt, api_key, role, rate_limit_rps, rate_limit_burst,
+                max_batch_size
+            FROM users WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id);
+
+        let user = query.fetch_one(db).await?;
+        Ok(user)
+    }
+
+    /// Store the hashed API key of a user, replacing the previous one
+    pub async fn set_api_key(
+        mm: &ModelManager,
+        tenant_id: &str,
+        user_id: &str,
+        hashed_key: &str,
+    ) -> Result<User> {
+        let db = mm.db();
+        let query = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users SET api_key = $2
+            WHERE user_id = $1 AND tenant_id = $3
+            RETURNING *
+            "#,
+        )
+        .bind(user_id)
+        .bind(hashed_key)
+        .bind(tenant_id);
 
         let user = query.fetch_one(db).await?;
         Ok(user)
     }
 
-    pub async fn delete_user(mm: &ModelManager, user_id: &str) -> Result<u64> {
+    /// Set the role to `Inactive` and revoke the API key, the row is kept for auditing
+    pub async fn deactivate_user(
+        mm: &ModelManager,
+        tenant_id: &str,
+        user_id: &str,
+    ) -> Result<User> {
+        let db = mm.db();
+        let query = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users SET role = $2, api_key = NULL
+            WHERE user_id = $1 AND tenant_id = $3
+            RETURNING *
+            "#,
+        )
+        .bind(user_id)
+        .bind(Role::Inactive)
+        .bind(tenant_id);
+
+        let user = query.fetch_one(db).await?;
+        Ok(user)
+    }
+
+    /// Replace the rate limit of a user, `UserRateLimit::default()` falls back to the role tier
+    pub async fn set_rate_limit(
+        mm: &ModelManager,
+        tenant_id: &str,
+        user_id: &str,
+        rate_limit: UserRateLimit,
+    ) -> Result<User> {
+        let db = mm.db();
+        let query = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users SET rate_limit_rps = $2, rate_limit_burst = $3, max_batch_size = $4
+            WHERE user_id = $1 AND tenant_id = $5
+            RETURNING *
+            "#,
+        )
+        .bind(user_id)
+        .bind(rate_limit.rate_limit_rps)
+        .bind(rate_limit.rate_limit_burst)
+        .bind(rate_limit.max_batch_size)
+        .bind(tenant_id);
+
+        let user = query.fetch_one(db).await?;
+        Ok(user)
+    }
+
+    pub async fn delete_user(mm: &ModelManager, tenant_id: &str, user_id: &str) -> Result<u64> {
         let user = sqlx::query(
             r#"
-            DELETE FROM users WHERE user_id = $1
+            DELETE FROM users WHERE user_id = $1 AND tenant_id = $2
             "#,
         )
         .bind(user_id)
+        .bind(tenant_id)
         .execute(mm.db())
         .await?;
 
         Ok(user.rows_affected())
     }
 
-    pub async fn get_all_users(mm: &ModelManager) -> Result<Vec<User>> {
+    pub async fn get_all_users(mm: &ModelManager, tenant_id: &str) -> Result<Vec<User>> {
         let db = mm.db();
         let users = sqlx::query_as::<_, User>(
             r#"
-            SELECT * FROM users
+            SELECT * FROM users WHERE tenant_id = $1
             "#,
         )
+        .bind(tenant_id)
         .fetch_all(db)
         .await?;
 
         Ok(users)
     }
+
+    /// A page of the users of the tenant ordered by `user_id`, see `ListOptions`
+    pub async fn list_users(
+        mm: &ModelManager,
+        tenant_id: &str,
+        options: &ListOptions<String>,
+    ) -> Result<Vec<User>> {
+        let users = sqlx::query_as::<_, User>(&format!(
+            r#"
+            SELECT * FROM users
+            WHERE tenant_id = $1 AND ($2::text IS NULL OR user_id {after} $2)
+            ORDER BY user_id {order}
+            LIMIT $3
+            "#,
+            after = options.sort.after_operator(),
+            order = options.sort.sql(),
+        ))
+        .bind(tenant_id)
+        .bind(options.after_id.as_deref())
+        .bind(options.limit())
+        .fetch_all(mm.db())
+        .await?;
+
+        Ok(users)
+    }
+
+    pub async fn count_users(mm: &ModelManager, tenant_id: &str) -> Result<i64> {
+        let count = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM users WHERE tenant_id = $1
+            "#,
+        )
+        .bind(tenant_id)
+        .fetch_one(mm.db())
+        .await?;
+
+        Ok(count)
+    }
 }
 
 // endregion: CRUD
@@ -194,6 +391,7 @@ impl UserBmc {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ctx::DEFAULT_TENANT;
     use crate::database::ModelManager;
     use crate::error::Result;
 
@@ -208,9 +406,22 @@ mod tests {
             last_name: "User".to_string(),
             email: "test@email.com".to_string(),
         };
-        let created_user = UserBmc::create_user(&mm, new_user.clone()).await?;
+        let created_user = UserBmc::create_user(&mm, DEFAULT_TENANT, new_user.clone()).await?;
         println!("Created User: {:?}", created_user);
         assert_eq!(created_user.user_id, new_user.user_id);
+        assert_eq!(created_user.tenant_id, DEFAULT_TENANT);
+        assert_eq!(created_user.rate_limit, UserRateLimit::default());
+
+        let rate_limit = UserRateLimit {
+            rate_limit_rps: Some(5),
+            max_batch_size: Some(8),
+            ..Default::default()
+        };
+        let user =
+            UserBmc::set_rate_limit(&mm, DEFAULT_TENANT, &new_user.user_id, rate_limit).await?;
+        assert_eq!(user.rate_limit, rate_limit);
+        let auth = UserBmc::get_user_for_auth(&mm, &new_user.user_id).await?;
+        assert_eq!(auth.rate_limit, rate_limit);
         Ok(())
     }
 
@@ -219,10 +430,28 @@ mod tests {
         let mm = ModelManager::new().await?;
 
         // Get all users
-        let users = UserBmc::get_all_users(&mm).await?;
+        let users = UserBmc::get_all_users(&mm, DEFAULT_TENANT).await?;
         println!("All Users: {:?}", users);
         assert!(!users.is_empty());
+
+        // A page of one user, the next page starts after it
+        let options = ListOptions {
+            limit: Some(1),
+            ..Default::default()
+        };
+        let page = UserBmc::list_users(&mm, DEFAULT_TENANT, &options).await?;
+        assert_eq!(page.len(), 1);
+        let options = ListOptions {
+            after_id: Some(page[0].user_id.clone()),
+            ..options
+        };
+        let next = UserBmc::list_users(&mm, DEFAULT_TENANT, &options).await?;
+        assert!(next.iter().all(|user| user.user_id > page[0].user_id));
+        assert_eq!(
+            UserBmc::count_users(&mm, DEFAULT_TENANT).await?,
+            users.len() as i64
+        );
         Ok(())
     }
 }
-// endregion: Unit Test
\ No newline at end of file
+// endregion: Unit Test
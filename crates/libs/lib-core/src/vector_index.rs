@@ -0,0 +1,556 @@
+//! pgvector storage and index of `file_chunks.embedding`.
+//!
+//! The column type (`vector`, `halfvec` or `bit`), the index method (`hnsw` or `ivfflat`), its
+//! build parameters and the distance are configured through the env (see `AuthConfig`).
+//! `migrate_embedding_storage` converts the column to the configured type, `migrate_vector_index`
+//! creates the matching index and drops the indexes built with other settings, the search query
+//! uses the operator of the same distance so the planner can actually use it.
+
+use crate::config::auth_config;
+use crate::database::ModelManager;
+use crate::error::{Error, Result};
+use pgvector::{Bit, HalfVector, Vector};
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgTypeInfo, PgValueRef};
+use sqlx::{Decode, Postgres, Type, TypeInfo};
+use std::str::FromStr;
+use tracing::info;
+
+const INDEX_PREFIX: &str = "idx_chunk_embedding";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorIndexMethod {
+    Hnsw,
+    IvfFlat,
+}
+
+impl FromStr for VectorIndexMethod {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "hnsw" => Ok(VectorIndexMethod::Hnsw),
+            "ivfflat" => Ok(VectorIndexMethod::IvfFlat),
+            other => Err(Error::Custom(format!(
+                "Unknown vector index method `{other}`"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorDistance {
+    Cosine,
+    /// Negative inner product, ranks like cosine for normalized embeddings but is cheaper
+    InnerProduct,
+    L2,
+}
+
+impl VectorDistance {
+    /// Inner product for normalized embeddings, cosine otherwise
+    pub fn for_normalization(normalized: bool) -> Self {
+        if normalized {
+            VectorDistance::InnerProduct
+        } else {
+            VectorDistance::Cosine
+        }
+    }
+
+    pub fn operator(&self) -> &'static str {
+        match self {
+            VectorDistance::Cosine => "<=>",
+            VectorDistance::InnerProduct => "<#>",
+            VectorDistance::L2 => "<->",
+        }
+    }
+
+    pub fn ops_class(&self) -> &'static str {
+        match self {
+            VectorDistance::Cosine => "vector_cosine_ops",
+            VectorDistance::InnerProduct => "vector_ip_ops",
+            VectorDistance::L2 => "vector_l2_ops",
+        }
+    }
+
+    fn short_name(&self) -> &'static str {
+        match self {
+            VectorDistance::Cosine => "cosine",
+            VectorDistance::InnerProduct => "ip",
+            VectorDistance::L2 => "l2",
+        }
+    }
+}
+
+impl FromStr for VectorDistance {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "cosine" => Ok(VectorDistance::Cosine),
+            "ip" | "inner_product" => Ok(VectorDistance::InnerProduct),
+            "l2" => Ok(VectorDistance::L2),
+            other => Err(Error::Custom(format!("Unknown vector distance `{other}`"))),
+        }
+    }
+}
+
+/// Whether the chunks are embedded normalized when the producer does not say
+/// (`EMBEDDINGS_NORMALIZED`), stored as `file_chunks.embedding_normalized`
+pub fn embeddings_normalized() -> bool {
+    auth_config().embeddings_normalized
+}
+
+/// Column type of `file_chunks.embedding`. Vectors are always bound as `vector` and converted by
+/// the query, see `EmbeddingStorage::cast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbeddingStorage {
+    /// float32 components
+    Vector,
+    /// float16 components, half the size of `vector` for a negligible recall loss
+    HalfVec,
+    /// Sign of every component, 32 times smaller than `vector`, searched with the Hamming distance
+    Bit,
+}
+
+impl EmbeddingStorage {
+    pub fn load() -> Result<Self> {
+        auth_config().embedding_storage.parse()
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            EmbeddingStorage::Vector => "vector",
+            EmbeddingStorage::HalfVec => "halfvec",
+            EmbeddingStorage::Bit => "bit",
+        }
+    }
+
+    /// SQL expression converting the `vector` parameter `param` to the column type
+    pub fn cast(&self, param: &str) -> String {
+        match self {
+            EmbeddingStorage::Vector => param.to_string(),
+            EmbeddingStorage::HalfVec => format!("{param}::halfvec"),
+            EmbeddingStorage::Bit => format!("binary_quantize({param})"),
+        }
+    }
+}
+
+impl FromStr for EmbeddingStorage {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "vector" | "float32" => Ok(EmbeddingStorage::Vector),
+            "halfvec" | "float16" => Ok(EmbeddingStorage::HalfVec),
+            "bit" | "binary" => Ok(EmbeddingStorage::Bit),
+            other => Err(Error::Custom(format!(
+                "Unknown embedding storage `{other}`"
+            ))),
+        }
+    }
+}
+
+/// Embedding read from `file_chunks.embedding` whatever its storage: `halfvec` components are
+/// widened to float32, the bits of a `bit` column are read as `1.0` and `-1.0`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredEmbedding(pub Vector);
+
+impl Type<Postgres> for StoredEmbedding {
+    fn type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("vector")
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        matches!(ty.name(), "vector" | "halfvec" | "bit" | "varbit")
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for StoredEmbedding {
+    fn decode(value: PgValueRef<'r>) -> std::result::Result<Self, BoxDynError> {
+        let type_name = value.type_info().name().to_string();
+        let vector = match type_name.as_str() {
+            "halfvec" => {
+                let half = HalfVector::decode(value)?;
+                Vector::from(
+                    half.as_slice()
+                        .iter()
+                        .map(|v| v.to_f32())
+                        .collect::<Vec<_>>(),
+                )
+            }
+            "bit" | "varbit" => sign_vector(&Bit::decode(value)?),
+            _ => Vector::decode(value)?,
+        };
+        Ok(Self(vector))
+    }
+}
+
+/// `1.0` for the set bits and `-1.0` for the others, their cosine ranks like the Hamming distance
+fn sign_vector(bits: &Bit) -> Vector {
+    let bytes = bits.as_bytes();
+    Vector::from(
+        (0..bits.len())
+            .map(|i| {
+                if bytes[i / 8] & (0x80 >> (i % 8)) != 0 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            })
+            .collect::<Vec<f32>>(),
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VectorIndexConfig {
+    pub method: VectorIndexMethod,
+    pub distance: VectorDistance,
+    /// Column type, `bit` columns always use the Hamming distance
+    pub storage: EmbeddingStorage,
+    /// HNSW max connections per layer
+    pub m: u32,
+    /// HNSW candidate list size while building
+    pub ef_construction: u32,
+    /// IVFFlat number of lists
+    pub lists: u32,
+}
+
+impl VectorIndexConfig {
+    /// Settings from the env, the distance follows `EMBEDDINGS_NORMALIZED` unless
+    /// `VECTOR_DISTANCE` is set
+    pub fn load() -> Result<Self> {
+        let config = auth_config();
+        let distance = match &config.vector_distance {
+            Some(distance) => distance.parse()?,
+            None => VectorDistance::for_normalization(config.embeddings_normalized),
+        };
+        Ok(Self {
+            method: config.vector_index_method.parse()?,
+            distance,
+            storage: config.embedding_storage.parse()?,
+            m: config.vector_index_m,
+            ef_construction: config.vector_index_ef_construction,
+            lists: config.vector_index_lists,
+        })
+    }
+
+    /// Distance operator of the search query, the one of the index operator class
+    pub fn operator(&self) -> &'static str {
+        self.search_operator(true)
+    }
+
+    /// Distance operator of a search over chunks that are not all `normalized`: the inner product
+    /// and L2 distances only rank like cosine on unit vectors, cosine is used instead
+    pub fn search_operator(&self, normalized: bool) -> &'static str {
+        match self.storage {
+            EmbeddingStorage::Bit => "<~>",
+            _ if !normalized => VectorDistance::Cosine.operator(),
+            _ => self.distance.operator(),
+        }
+    }
+
+    fn ops_class(&self) -> &'static str {
+        match (self.storage, self.distance) {
+            (EmbeddingStorage::Vector, distance) => distance.ops_class(),
+            (EmbeddingStorage::HalfVec, VectorDistance::Cosine) => "halfvec_cosine_ops",
+            (EmbeddingStorage::HalfVec, VectorDistance::InnerProduct) => "halfvec_ip_ops",
+            (EmbeddingStorage::HalfVec, VectorDistance::L2) => "halfvec_l2_ops",
+            (EmbeddingStorage::Bit, _) => "bit_hamming_ops",
+        }
+    }
+
+    /// Distance of the index name, prefixed by the storage unless it is `vector`
+    fn ops_name(&self) -> String {
+        match self.storage {
+            EmbeddingStorage::Vector => self.distance.short_name().to_string(),
+            EmbeddingStorage::HalfVec => format!("half_{}", self.distance.short_name()),
+            EmbeddingStorage::Bit => "bit_hamming".to_string(),
+        }
+    }
+
+    /// Name encoding the settings, an index with another name is stale
+    pub fn index_name(&self) -> String {
+        match self.method {
+            VectorIndexMethod::Hnsw => format!(
+                "{INDEX_PREFIX}_hnsw_{}_m{}_ef{}",
+                self.ops_name(),
+                self.m,
+                self.ef_construction
+            ),
+            VectorIndexMethod::IvfFlat => {
+                format!("{INDEX_PREFIX}_ivfflat_{}_l{}", self.ops_name(), self.lists)
+            }
+        }
+    }
+
+    fn create_sql(&self) -> String {
+        let (method, params) = match self.method {
+            VectorIndexMethod::Hnsw => (
+                "hnsw",
+                format!("m = {}, ef_construction = {}", self.m, self.ef_construction),
+            ),
+            VectorIndexMethod::IvfFlat => ("ivfflat", format!("lists = {}", self.lists)),
+        };
+        format!(
+            r#"CREATE INDEX CONCURRENTLY IF NOT EXISTS {} ON file_chunks USING {method} ("embedding" {}) WITH ({params})"#,
+            self.index_name(),
+            self.ops_class()
+        )
+    }
+}
+
+/// Query time parameters, only the one of the configured index method is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchParams {
+    /// HNSW candidate list size while searching, higher is more accurate and slower
+    pub ef_search: u32,
+    /// IVFFlat number of lists probed
+    pub probes: u32,
+}
+
+impl Default for SearchParams {
+    fn default() -> Self {
+        let config = auth_config();
+        Self {
+            ef_search: config.vector_ef_search,
+            probes: config.vector_ivfflat_probes,
+        }
+    }
+}
+
+/// Maximal Marginal Relevance re-ranking of the nearest chunks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MmrParams {
+    /// Trade-off between relevance (`1.0`) and diversity (`0.0`)
+    pub lambda: f32,
+    /// Number of nearest candidates re-ranked, at least the number of results
+    pub fetch_k: i64,
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+/// Select `k` of the `candidates`, each maximizing
+/// `lambda * sim(query, c) - (1 - lambda) * max(sim(c, selected))`. Returns the candidate indexes
+/// in selection order.
+pub fn mmr_rerank(query: &[f32], candidates: &[&[f32]], k: usize, lambda: f32) -> Vec<usize> {
+    let relevance: Vec<f32> = candidates
+        .iter()
+        .map(|c| cosine_similarity(query, c))
+        .collect();
+    // Highest similarity of every candidate to the already selected ones
+    let mut redundancy = vec![f32::MIN; candidates.len()];
+    let mut remaining: Vec<usize> = (0..candidates.len()).collect();
+    let mut selected = Vec::with_capacity(k.min(candidates.len()));
+
+    while selected.len() < k && !remaining.is_empty() {
+        let score = |i: usize| {
+            let penalty = if selected.is_empty() {
+                0.0
+            } else {
+                redundancy[i]
+            };
+            lambda * relevance[i] - (1.0 - lambda) * penalty
+        };
+        let (pos, &best) = remaining
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| score(**a).total_cmp(&score(**b)))
+            .expect("remaining is not empty");
+        remaining.swap_remove(pos);
+        for &i in &remaining {
+            redundancy[i] = redundancy[i].max(cosine_similarity(candidates[i], candidates[best]));
+        }
+        selected.push(best);
+    }
+    selected
+}
+
+/// Exponential recency decay blended into the similarity of a result, fresh documents outrank
+/// stale near-duplicates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecencyParams {
+    /// Age in days at which the recency of a document is halved
+    pub half_life_days: f64,
+    /// Share of the recency in the score, `0.0` ranks on similarity only
+    pub weight: f32,
+}
+
+impl RecencyParams {
+    /// `(1 - weight) * similarity + weight * 0.5^(age / half_life)`
+    pub fn score(&self, similarity: f32, age_days: f64) -> f32 {
+        let decay = 0.5_f64.powf(age_days.max(0.0) / self.half_life_days) as f32;
+        (1.0 - self.weight) * similarity + self.weight * decay
+    }
+}
+
+/// Convert `file_chunks.embedding` to the configured storage with the
+/// `set_chunk_embedding_storage` migration, a no-op when it already has this type. The table is
+/// rewritten under an exclusive lock, run it before `migrate_vector_index`.
+pub async fn migrate_embedding_storage(mm: &ModelManager, storage: EmbeddingStorage) -> Result<()> {
+    let (converted,): (bool,) = sqlx::query_as("SELECT set_chunk_embedding_storage($1)")
+        .bind(storage.type_name())
+        .fetch_one(mm.db())
+        .await?;
+    if converted {
+        info!("Chunk embeddings converted to {}", storage.type_name());
+    }
+    Ok(())
+}
+
+/// Create the configured index and drop the ones built with other settings. The index is built
+/// concurrently, writes are not blocked while it builds.
+pub async fn migrate_vector_index(mm: &ModelManager, config: &VectorIndexConfig) -> Result<()> {
+    let db = mm.db();
+    let name = config.index_name();
+    sqlx::query(&config.create_sql()).execute(db).await?;
+
+    let stale: Vec<(String,)> = sqlx::query_as(
+        r#"
+        SELECT indexname FROM pg_indexes
+        WHERE tablename = 'file_chunks' AND indexname LIKE $1 AND indexname <> $2
+        "#,
+    )
+    .bind(format!("{INDEX_PREFIX}%"))
+    .bind(&name)
+    .fetch_all(db)
+    .await?;
+    for (index,) in stale {
+        sqlx::query(&format!("DROP INDEX CONCURRENTLY IF EXISTS {index}"))
+            .execute(db)
+            .await?;
+        info!("Dropped stale vector index {index}");
+    }
+    info!("Vector index {name} ready");
+    Ok(())
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_config() {
+        let config = VectorIndexConfig {
+            method: VectorIndexMethod::Hnsw,
+            distance: VectorDistance::for_normalization(true),
+            storage: EmbeddingStorage::Vector,
+            m: 16,
+            ef_construction: 64,
+            lists: 100,
+        };
+        assert_eq!(config.index_name(), "idx_chunk_embedding_hnsw_ip_m16_ef64");
+        assert!(
+            config
+                .create_sql()
+                .contains("USING hnsw (\"embedding\" vector_ip_ops)")
+        );
+        assert_eq!(VectorDistance::for_normalization(false).operator(), "<=>");
+        assert_eq!(
+            "IVFFlat".parse::<VectorIndexMethod>().unwrap(),
+            VectorIndexMethod::IvfFlat
+        );
+    }
+
+    #[test]
+    fn test_search_operator() {
+        let l2 = VectorIndexConfig {
+            method: VectorIndexMethod::Hnsw,
+            distance: VectorDistance::L2,
+            storage: EmbeddingStorage::Vector,
+            m: 16,
+            ef_construction: 64,
+            lists: 100,
+        };
+        assert_eq!(l2.search_operator(true), "<->");
+        assert_eq!(l2.search_operator(false), "<=>");
+        let ip = VectorIndexConfig {
+            distance: VectorDistance::InnerProduct,
+            ..l2.clone()
+        };
+        assert_eq!(ip.search_operator(true), "<#>");
+        assert_eq!(ip.search_operator(false), "<=>");
+        let bit = VectorIndexConfig {
+            storage: EmbeddingStorage::Bit,
+            ..l2
+        };
+        assert_eq!(bit.search_operator(false), "<~>");
+    }
+
+    #[test]
+    fn test_embedding_storage() {
+        let half = VectorIndexConfig {
+            method: VectorIndexMethod::Hnsw,
+            distance: VectorDistance::Cosine,
+            storage: "float16".parse().unwrap(),
+            m: 16,
+            ef_construction: 64,
+            lists: 100,
+        };
+        assert_eq!(
+            half.index_name(),
+            "idx_chunk_embedding_hnsw_half_cosine_m16_ef64"
+        );
+        assert!(
+            half.create_sql()
+                .contains("(\"embedding\" halfvec_cosine_ops)")
+        );
+        assert_eq!(half.storage.cast("$1"), "$1::halfvec");
+
+        let bit = VectorIndexConfig {
+            storage: EmbeddingStorage::Bit,
+            ..half
+        };
+        assert_eq!(bit.operator(), "<~>");
+        assert!(bit.create_sql().contains("(\"embedding\" bit_hamming_ops)"));
+        assert_eq!(bit.storage.cast("$1"), "binary_quantize($1)");
+        assert!("int4".parse::<EmbeddingStorage>().is_err());
+
+        let signs = sign_vector(&Bit::new(&[
+            true, false, false, true, false, false, false, false, true,
+        ]));
+        assert_eq!(
+            signs.as_slice(),
+            &[1.0, -1.0, -1.0, 1.0, -1.0, -1.0, -1.0, -1.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn test_mmr_rerank() {
+        let query = [1.0, 0.0];
+        let near: &[f32] = &[1.0, 0.1];
+        let duplicate: &[f32] = &[1.0, 0.11];
+        let other: &[f32] = &[0.6, -0.8];
+        let candidates = [near, duplicate, other];
+
+        // Pure relevance keeps the similarity order
+        assert_eq!(mmr_rerank(&query, &candidates, 3, 1.0), vec![0, 1, 2]);
+        // Diversity picks the other direction before the near duplicate
+        assert_eq!(mmr_rerank(&query, &candidates, 2, 0.5), vec![0, 2]);
+        assert!(mmr_rerank(&query, &[], 2, 0.5).is_empty());
+    }
+
+    #[test]
+    fn test_recency_score() {
+        let recency = RecencyParams {
+            half_life_days: 30.0,
+            weight: 0.5,
+        };
+        assert_eq!(recency.score(0.5, 0.0), 0.75);
+        assert_eq!(recency.score(0.5, 30.0), 0.5);
+        // A fresh near-duplicate outranks a stale but slightly closer document
+        assert!(recency.score(0.80, 1.0) > recency.score(0.82, 365.0));
+    }
+}
+// endregion: Unit Test
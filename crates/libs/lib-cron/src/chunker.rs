@@ -0,0 +1,449 @@
+//! Split the parsed markdown of a file into chunks of at most `max_tokens` tokens.
+//!
+//! Tokens are estimated with whitespace separated words, the exact count is computed by the
+//! tokenizer at embedding time. Per-file caps (`ChunkLimits`) stop pathological documents, e.g.
+//! huge CSV exports, from producing hundreds of thousands of chunks: chunking stops as soon as a
+//! cap is hit instead of materializing the whole document.
+//!
+//! The word estimate is far off for text without whitespace (giant tables, base64 blobs, CJK), a
+//! chunk longer than `max_chunk_chars` would exceed the model limits and fail the embedding call.
+//! Such chunks are handled by the `OversizePolicy` of the collection, recorded on the chunk row.
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::str::FromStr;
+
+/// Settings key of the per collection `OversizePolicy`, a map of collection (file applicant) to
+/// policy
+pub const OVERSIZE_POLICY_SETTING: &str = "oversize_policies";
+
+/// What to do with a file exceeding one of the `ChunkLimits`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowStrategy {
+    /// Keep the chunks produced before the cap was reached
+    Truncate,
+    /// Drop every chunk of the file
+    Skip,
+}
+
+impl FromStr for OverflowStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "truncate" => Ok(OverflowStrategy::Truncate),
+            "skip" => Ok(OverflowStrategy::Skip),
+            other => Err(format!("unknown overflow strategy `{other}`")),
+        }
+    }
+}
+
+/// What to do with a single chunk longer than `ChunkLimits::max_chunk_chars`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizePolicy {
+    /// Split on character boundaries, preferably at a space, into chunks that fit
+    Split,
+    /// Keep the beginning of the chunk
+    Truncate,
+    /// Store the chunk without content, it is never embedded
+    Skip,
+}
+
+impl OversizePolicy {
+    /// Value recorded in `file_chunks.oversize`
+    pub fn recorded(&self) -> &'static str {
+        match self {
+            OversizePolicy::Split => "split",
+            OversizePolicy::Truncate => "truncated",
+            OversizePolicy::Skip => "skipped",
+        }
+    }
+}
+
+impl FromStr for OversizePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "split" => Ok(OversizePolicy::Split),
+            "truncate" => Ok(OversizePolicy::Truncate),
+            "skip" => Ok(OversizePolicy::Skip),
+            other => Err(format!("unknown oversize policy `{other}`")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkLimits {
+    pub max_tokens: usize,
+    pub max_chunks_per_file: usize,
+    pub max_tokens_per_file: usize,
+    pub overflow: OverflowStrategy,
+    pub max_chunk_chars: usize,
+    pub oversize: OversizePolicy,
+}
+
+/// Location of a segment in the source document
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SegmentMeta {
+    pub page: Option<i32>,
+    /// Section headings enclosing the segment, outermost first
+    pub heading_path: Vec<String>,
+    /// Hyperlinks of the segment, a chunk gathers the links of all its segments
+    pub links: Vec<String>,
+}
+
+/// Paragraph, heading or table of the document, chunks never mix the words of two segments
+/// without a chunk boundary being considered between them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment<'a> {
+    pub text: Cow<'a, str>,
+    pub meta: SegmentMeta,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub content: String,
+    pub token_count: usize,
+    /// Set when the chunk was longer than `max_chunk_chars`, empty `content` when skipped
+    pub oversize: Option<OversizePolicy>,
+    /// Location of the segment the chunk starts in
+    pub meta: SegmentMeta,
+}
+
+#[derive(Debug, Default)]
+pub struct ChunkOutcome {
+    pub chunks: Vec<Chunk>,
+    /// Set when a cap was hit, recorded on the file row
+    pub warning: Option<String>,
+}
+
+/// Chunk plain text, paragraphs are separated by a blank line
+pub fn chunk_text(text: &str, limits: &ChunkLimits) -> ChunkOutcome {
+    let segments = text.split("\n\n").map(|paragraph| Segment {
+        text: Cow::Borrowed(paragraph),
+        meta: SegmentMeta::default(),
+    });
+    chunk_segments(segments, limits)
+}
+
+/// Chunk the segments of a document, each chunk carries the location of its first word
+pub fn chunk_segments<'a>(
+    segments: impl IntoIterator<Item = Segment<'a>>,
+    limits: &ChunkLimits,
+) -> ChunkOutcome {
+    let max_tokens = limits.max_tokens.max(1);
+    let mut chunks = Vec::new();
+    let mut total_tokens = 0;
+    let segments: Vec<Segment<'a>> = segments.into_iter().collect();
+    let mut current: Vec<&str> = Vec::new();
+    let mut current_meta = SegmentMeta::default();
+
+    let mut warning = None;
+    'paragraphs: for segment in &segments {
+        let words: Vec<&str> = segment.text.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        // Start a new chunk rather than splitting a paragraph which would fit on its own
+        if !current.is_empty() && current.len() + words.len() > max_tokens {
+            if let Some(msg) = push_chunk(
+                &mut chunks,
+                &mut current,
+                &current_meta,
+                &mut total_tokens,
+                limits,
+            ) {
+                warning = Some(msg);
+                break 'paragraphs;
+            }
+        }
+        if !current.is_empty() {
+            for link in &segment.meta.links {
+                if !current_meta.links.contains(link) {
+                    current_meta.links.push(link.clone());
+                }
+            }
+        }
+        for word in words {
+            if current.is_empty() {
+                current_meta = segment.meta.clone();
+            }
+            current.push(word);
+            if current.len() == max_tokens {
+                if let Some(msg) = push_chunk(
+                    &mut chunks,
+                    &mut current,
+                    &current_meta,
+                    &mut total_tokens,
+                    limits,
+                ) {
+                    warning = Some(msg);
+                    break 'paragraphs;
+                }
+            }
+        }
+    }
+    if warning.is_none() && !current.is_empty() {
+        warning = push_chunk(
+            &mut chunks,
+            &mut current,
+            &current_meta,
+            &mut total_tokens,
+            limits,
+        );
+    }
+
+    let mut outcome = match (warning, limits.overflow) {
+        (Some(msg), OverflowStrategy::Skip) => {
+            return ChunkOutcome {
+                chunks: Vec::new(),
+                warning: Some(format!("{msg}, file skipped")),
+            };
+        }
+        (Some(msg), OverflowStrategy::Truncate) => {
+            let warning = Some(format!("{msg}, truncated to {} chunks", chunks.len()));
+            ChunkOutcome { chunks, warning }
+        }
+        (None, _) => ChunkOutcome {
+            chunks,
+            warning: None,
+        },
+    };
+
+    if let Some(msg) = oversize_warning(&outcome.chunks, limits) {
+        outcome.warning = Some(match outcome.warning {
+            Some(cap) => format!("{cap}; {msg}"),
+            None => msg,
+        });
+    }
+    outcome
+}
+
+fn oversize_warning(chunks: &[Chunk], limits: &ChunkLimits) -> Option<String> {
+    let oversized = chunks.iter().filter(|c| c.oversize.is_some()).count();
+    if oversized == 0 {
+        return None;
+    }
+    let max = limits.max_chunk_chars;
+    Some(match limits.oversize {
+        OversizePolicy::Split => {
+            format!("oversized inputs split into {oversized} chunks of at most {max} characters")
+        }
+        OversizePolicy::Truncate => {
+            format!("{oversized} oversized chunks truncated to {max} characters")
+        }
+        OversizePolicy::Skip => format!("{oversized} chunks over {max} characters skipped"),
+    })
+}
+
+/// Flush `current` into new chunks, returns the reason if one of the file caps is exceeded
+fn push_chunk(
+    chunks: &mut Vec<Chunk>,
+    current: &mut Vec<&str>,
+    meta: &SegmentMeta,
+    total_tokens: &mut usize,
+    limits: &ChunkLimits,
+) -> Option<String> {
+    let content = current.join(" ");
+    let token_count = current.len();
+    current.clear();
+
+    for chunk in fit_chunk(content, token_count, meta, limits) {
+        if chunks.len() >= limits.max_chunks_per_file {
+            return Some(format!(
+                "more than {} chunks per file",
+                limits.max_chunks_per_file
+            ));
+        }
+        if *total_tokens + chunk.token_count > limits.max_tokens_per_file {
+            return Some(format!(
+                "more than {} tokens per file",
+                limits.max_tokens_per_file
+            ));
+        }
+        *total_tokens += chunk.token_count;
+        chunks.push(chunk);
+    }
+    None
+}
+
+/// Apply the `OversizePolicy` to a chunk longer than `max_chunk_chars`
+fn fit_chunk(
+    content: String,
+    token_count: usize,
+    meta: &SegmentMeta,
+    limits: &ChunkLimits,
+) -> Vec<Chunk> {
+    // A character takes at most 4 bytes, every piece holds at least one
+    let max_chars = limits.max_chunk_chars.max(4);
+    if content.len() <= max_chars {
+        return vec![Chunk {
+            content,
+            token_count,
+            oversize: None,
+            meta: meta.clone(),
+        }];
+    }
+
+    let oversize = Some(limits.oversize);
+    match limits.oversize {
+        OversizePolicy::Split => split_content(&content, max_chars)
+            .into_iter()
+            .map(|piece| Chunk {
+                content: piece.to_string(),
+                token_count: piece.split_whitespace().count(),
+                oversize,
+                meta: meta.clone(),
+            })
+            .collect(),
+        OversizePolicy::Truncate => {
+            let piece = split_content(&content, max_chars)[0];
+            vec![Chunk {
+                content: piece.to_string(),
+                token_count: piece.split_whitespace().count(),
+                oversize,
+                meta: meta.clone(),
+            }]
+        }
+        OversizePolicy::Skip => vec![Chunk {
+            content: String::new(),
+            token_count: 0,
+            oversize,
+            meta: meta.clone(),
+        }],
+    }
+}
+
+/// Split into pieces of at most `max_chars` bytes, cutting at the last space of a piece when
+/// there is one
+fn split_content(content: &str, max_chars: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = content;
+    while rest.len() > max_chars {
+        let mut end = max_chars;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let cut = rest[..end].rfind(' ').filter(|&i| i > 0).unwrap_or(end);
+        pieces.push(&rest[..cut]);
+        rest = rest[cut..].trim_start();
+    }
+    if !rest.is_empty() {
+        pieces.push(rest);
+    }
+    pieces
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(overflow: OverflowStrategy) -> ChunkLimits {
+        ChunkLimits {
+            max_tokens: 4,
+            max_chunks_per_file: 3,
+            max_tokens_per_file: 100,
+            overflow,
+            max_chunk_chars: 1000,
+            oversize: OversizePolicy::Split,
+        }
+    }
+
+    #[test]
+    fn test_chunk_text() {
+        let outcome = chunk_text(
+            "one two\n\nthree four five\n\nsix",
+            &limits(OverflowStrategy::Skip),
+        );
+        assert!(outcome.warning.is_none());
+        let contents: Vec<&str> = outcome.chunks.iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(contents, vec!["one two", "three four five six"]);
+    }
+
+    #[test]
+    fn test_chunk_caps() {
+        let text = (0..100)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let outcome = chunk_text(&text, &limits(OverflowStrategy::Truncate));
+        assert_eq!(outcome.chunks.len(), 3);
+        assert!(outcome.warning.unwrap().contains("truncated"));
+
+        let outcome = chunk_text(&text, &limits(OverflowStrategy::Skip));
+        assert!(outcome.chunks.is_empty());
+        assert!(outcome.warning.unwrap().contains("skipped"));
+
+        let token_capped = ChunkLimits {
+            max_tokens_per_file: 10,
+            max_chunks_per_file: 1000,
+            ..limits(OverflowStrategy::Truncate)
+        };
+        let outcome = chunk_text(&text, &token_capped);
+        assert_eq!(
+            outcome.chunks.iter().map(|c| c.token_count).sum::<usize>(),
+            8
+        );
+        assert!(outcome.warning.unwrap().contains("tokens per file"));
+    }
+
+    #[test]
+    fn test_chunk_segments() {
+        let segment = |text, page, link: &str| Segment {
+            text: Cow::Borrowed(text),
+            meta: SegmentMeta {
+                page: Some(page),
+                heading_path: vec!["Intro".to_string()],
+                links: vec![link.to_string()],
+            },
+        };
+        let segments = vec![
+            segment("one two three", 1, "https://a.example"),
+            segment("four five six", 2, "https://b.example"),
+            segment("seven", 2, "https://c.example"),
+        ];
+        let outcome = chunk_segments(segments, &limits(OverflowStrategy::Skip));
+        let pages: Vec<Option<i32>> = outcome.chunks.iter().map(|c| c.meta.page).collect();
+        assert_eq!(pages, vec![Some(1), Some(2)]);
+        assert_eq!(outcome.chunks[1].meta.heading_path, vec!["Intro"]);
+        assert_eq!(
+            outcome.chunks[1].meta.links,
+            vec!["https://b.example", "https://c.example"]
+        );
+    }
+
+    #[test]
+    fn test_oversized_chunk() {
+        let text = format!("{}\n\nshort", "|cell".repeat(5));
+        let oversized = |oversize| ChunkLimits {
+            max_chunk_chars: 10,
+            oversize,
+            ..limits(OverflowStrategy::Truncate)
+        };
+
+        let outcome = chunk_text(&text, &oversized(OversizePolicy::Split));
+        let contents: Vec<&str> = outcome.chunks.iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(contents, vec!["|cell|cell", "|cell|cell", "|cell", "short"]);
+        assert!(
+            outcome
+                .chunks
+                .iter()
+                .all(|c| c.oversize == Some(OversizePolicy::Split))
+        );
+        assert!(outcome.warning.unwrap().contains("split into 4 chunks"));
+
+        let outcome = chunk_text(&text, &oversized(OversizePolicy::Truncate));
+        assert_eq!(outcome.chunks[0].content, "|cell|cell");
+
+        let outcome = chunk_text(&text, &oversized(OversizePolicy::Skip));
+        assert_eq!(outcome.chunks.len(), 1);
+        assert!(outcome.chunks[0].content.is_empty());
+        assert_eq!(outcome.chunks[0].oversize, Some(OversizePolicy::Skip));
+    }
+}
+
+// endregion: Unit Test
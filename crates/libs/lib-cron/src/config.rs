@@ -1,3 +1,6 @@
+use crate::chunker::{ChunkLimits, OverflowStrategy, OversizePolicy};
+use crate::language::LanguagePrompts;
+use crate::parser::ParserRouting;
 use lib_utils::envs::get_env;
 use std::sync::OnceLock;
 use tracing::error;
@@ -14,21 +17,142 @@ pub fn config() -> &'static Config {
 }
 
 pub struct AuthConfig {
-    pub parser: String,
+    /// docling endpoint (`PARSER_URL`), optional when `PARSERS` routes every file type elsewhere
+    pub parser: Option<String>,
+    /// Multipart endpoint of the parser (`PARSER_FILE_URL`, e.g. `.../v1/convert/file`). When set
+    /// the file bytes are uploaded to it, for parsers which cannot reach S3.
+    pub parser_file: Option<String>,
+    /// Apache Tika server (`TIKA_URL`)
+    pub tika_url: Option<String>,
+    /// unstructured.io API (`UNSTRUCTURED_URL`) and its key (`UNSTRUCTURED_API_KEY`)
+    pub unstructured_url: Option<String>,
+    pub unstructured_api_key: Option<String>,
+    /// Parser per file type (`PARSERS`, e.g. `pdf=native,docx=tika,*=docling`), every type goes to
+    /// docling when unset, or only PDFs to the native parser without `PARSER_URL`
+    pub parsers: ParserRouting,
+    /// CSV rows per segment of the native text parser (`CSV_ROWS_PER_CHUNK`)
+    pub csv_rows_per_chunk: usize,
+    /// Prompt prepended to a chunk of a detected language before it is embedded
+    /// (`LANGUAGE_PROMPTS`)
+    pub language_prompts: LanguagePrompts,
     pub bucket: String,
+    /// Files parsed and chunked concurrently by `process_new_files` (`FILE_PARALLELISM`), also
+    /// the number of requests in flight to the parser
+    pub file_parallelism: usize,
     pub max_tokens: i16,
+    /// Cap on the chunks of a single file (`MAX_CHUNKS_PER_FILE`)
+    pub max_chunks_per_file: usize,
+    /// Cap on the summed tokens of a single file (`MAX_TOKENS_PER_FILE`)
+    pub max_tokens_per_file: usize,
+    /// `truncate` or `skip` a file exceeding one of the caps (`CHUNK_OVERFLOW`)
+    pub chunk_overflow: OverflowStrategy,
+    /// Length above which a single chunk exceeds the model limits (`MAX_CHUNK_CHARS`), defaults
+    /// to 8 characters per token
+    pub max_chunk_chars: usize,
+    /// `split`, `truncate` or `skip` oversized chunks of collections without their own policy
+    /// (`CHUNK_OVERSIZE`)
+    pub chunk_oversize: OversizePolicy,
+    /// Days a file removed from the bucket stays restorable before it is purged
+    /// (`FILE_RETENTION_DAYS`)
+    pub file_retention_days: u32,
+    /// Chunks re-embedded and swapped per transaction by `reembed_chunks` (`REEMBED_BATCH_SIZE`)
+    pub reembed_batch_size: i64,
+    /// Base URL of the primary this instance replicates the chunks of (`REPLICATION_PRIMARY_URL`),
+    /// `replicate_chunks` is only registered when set
+    pub replication_primary: Option<String>,
+    /// Root API key of the primary (`REPLICATION_API_KEY`)
+    pub replication_api_key: Option<String>,
+    /// Changes pulled per frame (`REPLICATION_BATCH_SIZE`)
+    pub replication_batch_size: i64,
+    /// Results searched per golden query by `evaluate_golden_set` (`EVAL_K`)
+    pub eval_k: i64,
+    /// Drop of Recall@k since the previous run raising an alert (`EVAL_RECALL_DROP`)
+    pub eval_recall_drop: f64,
+    /// URL receiving a JSON POST on every regression (`EVAL_ALERT_WEBHOOK`)
+    pub eval_alert_webhook: Option<String>,
+    /// Take a Postgres advisory lock per job type around every run, so that replicas sharing the
+    /// database do not run the same job type at once (`CRON_DISTRIBUTED_LOCK`)
+    pub cron_distributed_lock: bool,
+    /// Tag the objects `processing-status=processed|failed` after they were processed, for
+    /// lifecycle rules expiring the processed originals (`TAG_PROCESSING_STATUS`)
+    pub tag_processing_status: bool,
 }
 
 impl AuthConfig {
     pub fn load_from_env() -> lib_utils::error::Result<AuthConfig> {
-        let parser = get_env("PARSER_URL")?;
+        let parser = get_env("PARSER_URL").ok();
+        let parser_file = get_env("PARSER_FILE_URL").ok();
+        let tika_url = get_env("TIKA_URL").ok();
+        let unstructured_url = get_env("UNSTRUCTURED_URL").ok();
+        let unstructured_api_key = get_env("UNSTRUCTURED_API_KEY").ok();
+        let parsers = match get_env("PARSERS") {
+            Err(lib_utils::error::Error::MissingEnv(_)) => None,
+            parsers => Some(parsers?),
+        };
+        let csv_rows_per_chunk = get_env("CSV_ROWS_PER_CHUNK").unwrap_or(20);
+        let language_prompts = match get_env("LANGUAGE_PROMPTS") {
+            Err(lib_utils::error::Error::MissingEnv(_)) => LanguagePrompts::default(),
+            language_prompts => language_prompts?,
+        };
         let bucket = get_env("UPLOAD_BUCKET")?;
-        let max_tokens = get_env("MAX_TOKENS")?;
-        Ok(AuthConfig {
+        let file_parallelism = get_env("FILE_PARALLELISM").unwrap_or(4);
+        let max_tokens: i16 = get_env("MAX_TOKENS")?;
+        let max_chunks_per_file = get_env("MAX_CHUNKS_PER_FILE").unwrap_or(10_000);
+        let max_tokens_per_file = get_env("MAX_TOKENS_PER_FILE").unwrap_or(2_000_000);
+        let chunk_overflow = get_env("CHUNK_OVERFLOW").unwrap_or(OverflowStrategy::Truncate);
+        let max_chunk_chars = get_env("MAX_CHUNK_CHARS").unwrap_or(max_tokens.max(1) as usize * 8);
+        let chunk_oversize = get_env("CHUNK_OVERSIZE").unwrap_or(OversizePolicy::Split);
+        let file_retention_days = get_env("FILE_RETENTION_DAYS").unwrap_or(30);
+        let reembed_batch_size = get_env("REEMBED_BATCH_SIZE").unwrap_or(32);
+        let replication_primary = get_env("REPLICATION_PRIMARY_URL").ok();
+        let replication_api_key = get_env("REPLICATION_API_KEY").ok();
+        let replication_batch_size = get_env("REPLICATION_BATCH_SIZE").unwrap_or(500);
+        let eval_k = get_env("EVAL_K").unwrap_or(10);
+        let eval_recall_drop = get_env("EVAL_RECALL_DROP").unwrap_or(0.05);
+        let eval_alert_webhook = get_env("EVAL_ALERT_WEBHOOK").ok();
+        let cron_distributed_lock = get_env("CRON_DISTRIBUTED_LOCK").unwrap_or(true);
+        let tag_processing_status = get_env("TAG_PROCESSING_STATUS").unwrap_or(false);
+        let mut config = AuthConfig {
             parser,
+            parser_file,
+            tika_url,
+            unstructured_url,
+            unstructured_api_key,
+            parsers: ParserRouting::default(),
+            csv_rows_per_chunk,
+            language_prompts,
             bucket,
+            file_parallelism,
             max_tokens,
-        })
+            max_chunks_per_file,
+            max_tokens_per_file,
+            chunk_overflow,
+            max_chunk_chars,
+            chunk_oversize,
+            file_retention_days,
+            reembed_batch_size,
+            replication_primary,
+            replication_api_key,
+            replication_batch_size,
+            eval_k,
+            eval_recall_drop,
+            eval_alert_webhook,
+            cron_distributed_lock,
+            tag_processing_status,
+        };
+        config.parsers = parsers.unwrap_or_else(|| ParserRouting::default_for(&config));
+        Ok(config)
+    }
+
+    pub fn chunk_limits(&self) -> ChunkLimits {
+        ChunkLimits {
+            max_tokens: self.max_tokens.max(1) as usize,
+            max_chunks_per_file: self.max_chunks_per_file,
+            max_tokens_per_file: self.max_tokens_per_file,
+            overflow: self.chunk_overflow,
+            max_chunk_chars: self.max_chunk_chars,
+            oversize: self.chunk_oversize,
+        }
     }
 }
 
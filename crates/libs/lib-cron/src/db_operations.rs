@@ -1,37 +1,67 @@
+use crate::chunker::{OVERSIZE_POLICY_SETTING, OversizePolicy, chunk_segments, chunk_text};
 use crate::config::auth_config;
+use crate::docling::{document_segments, email_headers};
+use crate::embedder::ChunkEmbedder;
 use crate::error::{Error, Result};
-use aws_sdk_s3::Client;
+use crate::language::detect_language;
+use crate::parser::{ParserInput, Parsers};
+use futures_util::future::join_all;
 use lib_core::{
+    ctx::DEFAULT_TENANT,
     database::ModelManager,
-    model::file_chunks::{FileChunkForCreate, FileChunkMac},
-    model::files::{FileForCreate, FileForUpdate, FileMac},
+    model::file_chunks::{ChunkMetadata, FileChunkForCreate, FileChunkMac, content_hash},
+    model::files::{File, FileForCreate, FileForUpdate, FileMac},
+    model::settings::SettingMac,
 };
-use lib_storage::functions::file::{generate_presigned_url, list_files_in_bucket};
-use serde::Deserialize;
-use serde_json::json;
-use tokio::time::{Duration, sleep};
-use tracing::info;
-
-#[derive(Debug, Deserialize)]
-pub struct DoclingResponse {
-    pub document: Document,
-    pub status: String,
-    pub errors: Vec<String>,
-    pub processing_time: f64,
-    pub timings: serde_json::Value,
+use lib_storage::functions::file::ProcessingStatus;
+use lib_storage::store::{ObjectMeta, ObjectStore};
+use pgvector::Vector;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+use tokio::sync::Semaphore;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+const COMPRESS_BATCH_SIZE: i64 = 500;
+
+/// Outcome of `sync_s3_files`
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct SyncSummary {
+    /// Objects listed in the bucket
+    pub objects: usize,
+    /// Files registered for the new objects
+    pub created: usize,
+    /// Soft deleted files whose object came back
+    pub restored: usize,
+}
+
+/// Outcome of `process_new_files`, failed files are left unprocessed for the next run
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ProcessSummary {
+    pub processed: usize,
+    pub failed: usize,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Document {
-    pub filename: String,
-    pub md_content: String,
-    pub json_content: Option<String>,
-    pub html_content: Option<String>,
-    pub text_content: Option<String>,
-    pub doctags_content: Option<String>,
+/// Outcome of `backfill_bucket`
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct BackfillSummary {
+    pub sync: SyncSummary,
+    pub process: ProcessSummary,
+    pub duration_ms: u128,
 }
 
-pub async fn process_new_files(mm: &ModelManager, storage: &Client) -> Result<()> {
+/// Ingest the unprocessed files of every tenant, chunks are stored under the tenant of their file.
+/// A file processed again after its object was overwritten replaces its chunks, the chunks whose
+/// text did not change keep their embedding instead of being embedded again. In a versioned bucket
+/// the parser reads the version current when the file is picked, which is stored with the file.
+/// With an `embedder` (the inference queue of the server) the new chunks are embedded before they
+/// are stored, without one they are stored without embedding.
+pub async fn process_new_files(
+    mm: &ModelManager,
+    storage: &dyn ObjectStore,
+    embedder: Option<&dyn ChunkEmbedder>,
+) -> Result<ProcessSummary> {
     let config = auth_config();
     let http = reqwest::Client::builder()
         .pool_idle_timeout(Some(Duration::from_secs(30)))
@@ -41,136 +71,498 @@ pub async fn process_new_files(mm: &ModelManager, storage: &Client) -> Result<()
     let new_files = FileMac::get_unprocessed_files(mm)
         .await
         .map_err(|e| Error::Custom(format!("failed to get unprocessed files: {}", e)))?;
+    let oversize_policies = oversize_policies(mm).await;
+    let parsers = Parsers::from_config(config, &http)?;
 
-    for file in new_files {
-        let presigned_url = generate_presigned_url(storage, &config.bucket, &file.filename, 600)
-            .await
-            .map_err(|e| Error::Custom(format!("presign url failed for {}: {e}", file.filename)))?;
-
-        let content_md = fetch_markdown_with_retry(
-            &http,
-            &config.parser,
-            &file.filename,
-            &presigned_url,
-            3,
-            Duration::from_millis(400),
-        )
+    // Files run concurrently up to `FILE_PARALLELISM`, a failed file is left unprocessed for the
+    // next run without stopping the others
+    let total = new_files.len();
+    let pending = metrics::gauge!("te_ingest_files_pending");
+    let in_progress = metrics::gauge!("te_ingest_files_in_progress");
+    pending.set(total as f64);
+    let semaphore = Semaphore::new(config.file_parallelism.max(1));
+    let results = join_all(new_files.into_iter().map(|file| {
+        let (semaphore, parsers, oversize_policies) = (&semaphore, &parsers, &oversize_policies);
+        let (pending, in_progress) = (pending.clone(), in_progress.clone());
+        async move {
+            let _permit = semaphore
+                .acquire()
+                .await
+                .map_err(|e| Error::Custom(format!("file semaphore closed: {e}")))?;
+            in_progress.increment(1.0);
+            let filename = file.filename.clone();
+            let res = process_file(mm, storage, embedder, parsers, oversize_policies, file).await;
+            in_progress.decrement(1.0);
+            pending.decrement(1.0);
+            let status = if res.is_ok() {
+                ProcessingStatus::Processed
+            } else {
+                ProcessingStatus::Failed
+            };
+            metrics::counter!("te_ingest_files", "status" => status.as_str()).increment(1);
+            if config.tag_processing_status {
+                let tagged = storage.tag_processing_status(&filename, status).await;
+                if let Err(e) = tagged {
+                    warn!("Could not tag file {filename} as {}: {e}", status.as_str());
+                }
+            }
+            res.inspect_err(|e| warn!("File {filename} failed, retried at the next run: {e}"))
+        }
+    }))
+    .await;
+
+    let failed = results.iter().filter(|res| res.is_err()).count();
+    let processed = total - failed;
+    if total > 0 {
+        info!("process_new_files: {processed} of {total} files processed");
+    }
+    Ok(ProcessSummary { processed, failed })
+}
+
+/// Ingest the whole bucket in one pass, for a first start on an existing bucket instead of waiting
+/// for the cron ticks: every object is listed (paginated by the store) and registered, then every
+/// unprocessed file is parsed, chunked and embedded, `FILE_PARALLELISM` files at a time.
+pub async fn backfill_bucket(
+    mm: &ModelManager,
+    storage: &dyn ObjectStore,
+    embedder: Option<&dyn ChunkEmbedder>,
+) -> Result<BackfillSummary> {
+    let started = Instant::now();
+    let sync = sync_s3_files(mm, storage).await?;
+    let process = process_new_files(mm, storage, embedder).await?;
+    let summary = BackfillSummary {
+        sync,
+        process,
+        duration_ms: started.elapsed().as_millis(),
+    };
+    info!(
+        "Backfill: {} objects listed, {} files registered, {} restored, {} processed, {} failed in {} ms",
+        sync.objects,
+        sync.created,
+        sync.restored,
+        process.processed,
+        process.failed,
+        summary.duration_ms
+    );
+    Ok(summary)
+}
+
+/// Parse, chunk and store one file, then mark it processed
+async fn process_file(
+    mm: &ModelManager,
+    storage: &dyn ObjectStore,
+    embedder: Option<&dyn ChunkEmbedder>,
+    parsers: &Parsers,
+    oversize_policies: &HashMap<String, OversizePolicy>,
+    file: File,
+) -> Result<()> {
+    let config = auth_config();
+    let version = storage
+        .head(&file.filename)
+        .await
+        .map_err(|e| Error::Custom(format!("head object failed for {}: {e}", file.filename)))?;
+    let parser = parsers.for_file(&file.file_type)?;
+    let document = parser
+        .parse(ParserInput {
+            filename: &file.filename,
+            storage,
+            version_id: version.version_id.as_deref(),
+        })
         .await?;
-        // Extract text_content and filter out image markdown like [Image](data:image/png;base64,...)
-        let mut text_content = content_md.text_content.unwrap_or_default();
-        // Remove image markdown patterns
-        let image_pattern = regex::Regex::new(r"\[Image\]\(data:image/[^)]+\)").unwrap();
-        text_content = image_pattern.replace_all(&text_content, "").to_string();
-
-        let max_tokens = auth_config().max_tokens as usize;
-        /*
-        let threshold = 0.85_f32;
-        let semantic_chunks =
-            semantic_compression(embedder, raw_chunks, threshold, max_tokens).await?; // Can be implemented if enougth ram is there
-         */
-
-        let file_update = FileForUpdate {
-            filename: Some(file.filename.clone()),
-            processed: Some(true),
-        };
-        FileMac::update_file(mm, &file.file_id, file_update)
+
+    let mut limits = config.chunk_limits();
+    if let Some(policy) = oversize_policies.get(&file.applicant) {
+        limits.oversize = *policy;
+    }
+    // The structured document locates every chunk, the plain text is the fallback
+    let segments = match document.segments {
+        Some(segments) => Some(segments),
+        None => document.json_content.as_ref().and_then(document_segments),
+    };
+    let email = document
+        .json_content
+        .as_ref()
+        .and_then(email_headers)
+        .unwrap_or_default();
+    let outcome = match segments {
+        Some(segments) => chunk_segments(segments, &limits),
+        None => {
+            /*
+            let threshold = 0.85_f32;
+            let semantic_chunks =
+                semantic_compression(embedder, raw_chunks, threshold, max_tokens).await?; // Can be implemented if enougth ram is there
+             */
+            chunk_text(&document.text, &limits)
+        }
+    };
+    if let Some(warning) = &outcome.warning {
+        warn!("File {}: {}", file.filename, warning);
+    }
+
+    let previous = FileChunkMac::get_embeddings_by_hash(mm, &file.tenant_id, file.file_id)
+        .await
+        .map_err(|e| {
+            Error::Custom(format!(
+                "failed to get the chunks of file {}: {}",
+                file.filename, e
+            ))
+        })?;
+    let source_url = storage.url(&file.filename);
+    // A file keeps a single chunk per text, empty and skipped chunks are kept where they are
+    let mut hashes = HashSet::new();
+    let mut reused = 0;
+    let mut chunks: Vec<FileChunkForCreate> = outcome
+        .chunks
+        .into_iter()
+        .map(|chunk| (content_hash(&chunk.content), chunk))
+        .filter(|(hash, chunk)| {
+            let skipped = chunk.oversize == Some(OversizePolicy::Skip);
+            skipped || chunk.content.trim().is_empty() || hashes.insert(hash.clone())
+        })
+        .enumerate()
+        .map(|(index, (hash, chunk))| {
+            let skipped = chunk.oversize == Some(OversizePolicy::Skip);
+            let (embedding, embedding_model, embedding_normalized) = match previous.get(&hash) {
+                Some((embedding, model, normalized)) if !skipped => {
+                    reused += 1;
+                    (Some(embedding.clone()), model.clone(), *normalized)
+                }
+                _ => (None, None, true),
+            };
+            FileChunkForCreate {
+                file_id: file.file_id,
+                chunk_index: index as i32,
+                metadata: ChunkMetadata {
+                    page: chunk.meta.page,
+                    heading_path: chunk.meta.heading_path,
+                    source_url: Some(source_url.clone()),
+                    language: detect_language(&chunk.content),
+                    links: chunk.meta.links,
+                    sender: email.sender.clone(),
+                    recipients: email.recipients.clone(),
+                    subject: email.subject.clone(),
+                },
+                content_md: (!skipped).then_some(chunk.content),
+                embedding,
+                embedding_model,
+                embedding_normalized,
+                token_count: Some(chunk.token_count as i32),
+                oversize: chunk.oversize.map(|policy| policy.recorded().to_string()),
+            }
+        })
+        .collect();
+    // Embedded before the previous chunks are replaced, a failure leaves them for the next run
+    if let Some(embedder) = embedder {
+        embed_new_chunks(embedder, &mut chunks).await?;
+    }
+    // The previous chunks are only deleted along with the creation of the new ones
+    let chunk_count = chunks.len();
+    let (replaced, _) =
+        FileChunkMac::replace_file_chunks(mm, &file.tenant_id, file.file_id, chunks)
             .await
             .map_err(|e| {
                 Error::Custom(format!(
-                    "failed to update file {} as processed: {}",
+                    "failed to store chunks of file {}: {}",
                     file.filename, e
                 ))
             })?;
+    if replaced > 0 {
+        info!(
+            "File {} processed again: {} chunks replaced by {}, {} embeddings reused",
+            file.filename, replaced, chunk_count, reused
+        );
     }
 
+    let file_update = FileForUpdate {
+        filename: Some(file.filename.clone()),
+        processed: Some(true),
+        warning: outcome.warning,
+        // ETag of the parsed version, a later overwrite still differs from it at the next sync
+        etag: version.etag,
+        last_modified: None,
+        version_id: version.version_id,
+    };
+    FileMac::update_file(mm, &file.tenant_id, &file.file_id, file_update)
+        .await
+        .map_err(|e| {
+            Error::Custom(format!(
+                "failed to update file {} as processed: {}",
+                file.filename, e
+            ))
+        })?;
+
     Ok(())
 }
 
-async fn fetch_markdown_with_retry(
-    http: &reqwest::Client,
-    parser_url: &str,
-    filename: &str,
-    presigned_url: &str,
-    max_retries: usize,
-    base_backoff: Duration,
-) -> Result<Document> {
-    let body = json!({"http_sources":[{
-        "url": presigned_url,
-        "filename": filename,
-    }]});
-    info!("Requesting parser at {} with body: {:?}", parser_url, body);
-    let mut attempt = 0usize;
-    loop {
-        attempt += 1;
-        let resp = http
-            .post(parser_url)
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| {
-                Error::Custom(format!("parser request failed (attempt {attempt}): {e}"))
-            })?;
-
-        if resp.status().is_success() {
-            let parsed = resp
-                .json::<DoclingResponse>()
-                .await
-                .map_err(|e| Error::Custom(format!("parser json decode failed: {e}")))?;
-            return Ok(parsed.document);
-        }
-
-        if attempt >= max_retries {
+/// Embed the chunks with a text and without a reused embedding, `REEMBED_BATCH_SIZE` texts per
+/// request so a large file shares the inference queue with the API traffic
+async fn embed_new_chunks(
+    embedder: &dyn ChunkEmbedder,
+    chunks: &mut [FileChunkForCreate],
+) -> Result<()> {
+    let batch_size = auth_config().reembed_batch_size.max(1) as usize;
+    let prompts = &auth_config().language_prompts;
+    let mut pending: Vec<&mut FileChunkForCreate> = chunks
+        .iter_mut()
+        .filter(|chunk| chunk.embedding.is_none())
+        .filter(|chunk| matches!(chunk.content_md.as_deref(), Some(text) if !text.is_empty()))
+        .collect();
+    for batch in pending.chunks_mut(batch_size) {
+        let texts = batch
+            .iter()
+            .map(|chunk| {
+                let text = chunk.content_md.clone().unwrap_or_default();
+                prompts.apply(chunk.metadata.language.as_deref(), text)
+            })
+            .collect::<Vec<_>>();
+        let embeddings = embedder.embed(texts).await?;
+        if embeddings.len() != batch.len() {
             return Err(Error::Custom(format!(
-                "parser returned status {} after {attempt} attempts with body: {:?}",
-                resp.status(),
-                body
+                "embedder returned {} embeddings for {} chunks",
+                embeddings.len(),
+                batch.len()
             )));
         }
+        for (chunk, embedding) in batch.iter_mut().zip(embeddings) {
+            chunk.embedding = Some(Vector::from(embedding));
+            chunk.embedding_model = Some(embedder.model_id());
+            chunk.embedding_normalized = embedder.normalized();
+        }
+    }
+    Ok(())
+}
 
-        let jitter = Duration::from_millis(fastrand::u64(0..100));
-        let wait = base_backoff * (1u32 << (attempt - 1)) + jitter;
-        sleep(wait).await;
+/// `OversizePolicy` per collection (file applicant), set through the admin API. Collections
+/// without a policy use `CHUNK_OVERSIZE`.
+async fn oversize_policies(mm: &ModelManager) -> HashMap<String, OversizePolicy> {
+    match SettingMac::get_value(mm, OVERSIZE_POLICY_SETTING).await {
+        Ok(policies) => policies.unwrap_or_default(),
+        Err(e) => {
+            warn!(
+                "Could not load the oversize policies, using the default: {}",
+                e
+            );
+            HashMap::new()
+        }
     }
 }
 
-pub async fn sync_s3_files(mm: &ModelManager, client: &Client) -> Result<()> {
-    let config = auth_config();
-    let s3_files = list_files_in_bucket(client, &config.bucket, None)
+/// Tenant of an S3 key, its first path segment (`acme/report.pdf` -> `acme`). Keys at the root of
+/// the bucket belong to the default tenant.
+pub fn tenant_from_key(key: &str) -> &str {
+    match key.split_once('/') {
+        Some((tenant, _)) if !tenant.is_empty() => tenant,
+        _ => DEFAULT_TENANT,
+    }
+}
+
+/// Mirror the bucket in the `files` table, tenant by tenant. Files whose object left the bucket
+/// are soft deleted and restored with their chunks if the object comes back within the retention
+/// window, see `purge_deleted_files`. Files whose object was overwritten (new ETag) are processed
+/// again, a new LastModified alone is only recorded.
+pub async fn sync_s3_files(mm: &ModelManager, storage: &dyn ObjectStore) -> Result<SyncSummary> {
+    let s3_objects = storage.list(None).await.map_err(|e| {
+        Error::Custom(format!(
+            "failed to list files in bucket {}: {}",
+            storage.url(""),
+            e
+        ))
+    })?;
+    let objects: HashMap<&String, &ObjectMeta> = s3_objects
+        .iter()
+        .map(|object| (&object.key, object))
+        .collect();
+    let s3_files: Vec<String> = s3_objects.iter().map(|object| object.key.clone()).collect();
+    let mut s3_by_tenant: HashMap<&str, Vec<&String>> = HashMap::new();
+    for s3_file in &s3_files {
+        s3_by_tenant
+            .entry(tenant_from_key(s3_file))
+            .or_default()
+            .push(s3_file);
+    }
+    // Tenants whose objects were all removed still have files to delete
+    let mut tenants: HashSet<String> = FileMac::get_tenants(mm)
         .await
-        .map_err(|e| {
+        .map_err(|e| Error::Custom(format!("failed to get the tenants from DB: {}", e)))?
+        .into_iter()
+        .collect();
+    tenants.extend(s3_by_tenant.keys().map(|tenant| tenant.to_string()));
+    let mut summary = SyncSummary {
+        objects: s3_objects.len(),
+        ..Default::default()
+    };
+
+    for tenant in tenants {
+        let tenant_files = s3_by_tenant
+            .get(tenant.as_str())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let db_files = FileMac::get_all_files(mm, &tenant).await.map_err(|e| {
             Error::Custom(format!(
-                "failed to list files in S3 bucket {}: {}",
-                config.bucket, e
+                "failed to get the files of tenant {} from DB: {}",
+                tenant, e
             ))
         })?;
-    let db_files = FileMac::get_all_files(mm)
+
+        for s3_file in tenant_files {
+            if !db_files.iter().any(|f| &f.filename == *s3_file) {
+                let restored = FileMac::restore_file(mm, &tenant, s3_file)
+                    .await
+                    .map_err(|e| {
+                        Error::Custom(format!("failed to restore file {} in DB: {}", s3_file, e))
+                    })?;
+                if restored.is_some() {
+                    info!("Restored file {} of tenant {}", s3_file, tenant);
+                    summary.restored += 1;
+                    continue;
+                }
+                let file = FileForCreate {
+                    tenant_id: tenant.clone(),
+                    applicant: "default_applicant".to_string(),
+                    filename: s3_file.to_string(),
+                    file_type: s3_file.split('.').last().unwrap_or("unknown").to_string(),
+                    etag: objects.get(s3_file).and_then(|object| object.etag.clone()),
+                    last_modified: objects
+                        .get(s3_file)
+                        .and_then(|object| object.last_modified)
+                        .map(|date| date.naive_utc()),
+                };
+                FileMac::create_file(mm, file).await.map_err(|e| {
+                    Error::Custom(format!("failed to create file {} in DB: {}", s3_file, e))
+                })?;
+                summary.created += 1;
+            }
+        }
+        for db_file in db_files {
+            if let Some(object) = objects.get(&db_file.filename) {
+                let last_modified = object.last_modified.map(|date| date.naive_utc());
+                let etag_changed = object.etag.is_some() && db_file.etag != object.etag;
+                if etag_changed
+                    || (last_modified.is_some() && db_file.last_modified != last_modified)
+                {
+                    // Files synced before the ETags were recorded only record it
+                    let overwritten = etag_changed && db_file.etag.is_some();
+                    let update = FileForUpdate {
+                        filename: None,
+                        processed: overwritten.then_some(false),
+                        warning: None,
+                        etag: object.etag.clone(),
+                        last_modified,
+                        version_id: None,
+                    };
+                    FileMac::update_file(mm, &tenant, &db_file.file_id, update)
+                        .await
+                        .map_err(|e| {
+                            Error::Custom(format!(
+                                "failed to update file {} in DB: {}",
+                                db_file.filename, e
+                            ))
+                        })?;
+                    if overwritten {
+                        info!(
+                            "File {} was overwritten, processing it again",
+                            db_file.filename
+                        );
+                    }
+                }
+            }
+            if !tenant_files.contains(&&db_file.filename) {
+                FileMac::soft_delete_file(mm, &tenant, &db_file.file_id)
+                    .await
+                    .map_err(|e| {
+                        Error::Custom(format!(
+                            "failed to soft delete file {} from DB: {}",
+                            db_file.filename, e
+                        ))
+                    })?;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Hard delete the files soft deleted more than `FILE_RETENTION_DAYS` ago, with their chunks and
+/// content objects
+pub async fn purge_deleted_files(mm: &ModelManager) -> Result<()> {
+    let retention_days = auth_config().file_retention_days;
+    let purged = FileMac::purge_deleted_files(mm, retention_days)
         .await
-        .map_err(|e| Error::Custom(format!("failed to get all files from DB: {}", e)))?;
-
-    for s3_file in s3_files.clone() {
-        if !db_files.iter().any(|f| f.filename == s3_file) {
-            let file = FileForCreate {
-                applicant: "default_applicant".to_string(),
-                filename: s3_file.clone(),
-                file_type: s3_file.split('.').last().unwrap_or("unknown").to_string(),
-            };
-            FileMac::create_file(mm, file).await.map_err(|e| {
-                Error::Custom(format!("failed to create file {} in DB: {}", s3_file, e))
-            })?;
+        .map_err(|e| Error::Custom(format!("failed to purge deleted files: {}", e)))?;
+    info!(
+        "Purged {} files deleted more than {} days ago",
+        purged, retention_days
+    );
+    Ok(())
+}
+
+pub async fn compress_chunks(mm: &ModelManager) -> Result<()> {
+    let mut total = 0;
+    loop {
+        let migrated = FileChunkMac::compress_plain_chunks(mm, COMPRESS_BATCH_SIZE)
+            .await
+            .map_err(|e| Error::Custom(format!("failed to compress chunks: {}", e)))?;
+        if migrated == 0 {
+            break;
         }
+        total += migrated;
     }
-    for db_file in db_files {
-        if !s3_files.contains(&db_file.filename) {
-            FileMac::delete_file(mm, &db_file.file_id)
-                .await
-                .map_err(|e| {
-                    Error::Custom(format!(
-                        "failed to delete file {} from DB: {}",
-                        db_file.filename, e
-                    ))
-                })?;
+    info!("Compressed {} chunks", total);
+    Ok(())
+}
+
+/// Re-embed the chunks embedded by another model than the served one, `REEMBED_BATCH_SIZE` at a
+/// time, with the `LANGUAGE_PROMPTS` prompt of their language. The vectors of a batch are swapped
+/// in one transaction, so an interrupted run leaves every chunk with a consistent model and the
+/// next run resumes with the remaining ones.
+pub async fn reembed_chunks(mm: &ModelManager, embedder: &dyn ChunkEmbedder) -> Result<()> {
+    let model = &embedder.model_id();
+    let batch_size = auth_config().reembed_batch_size.max(1);
+    let prompts = &auth_config().language_prompts;
+    let outdated = FileChunkMac::count_outdated_chunks(mm, model)
+        .await
+        .map_err(|e| Error::Custom(format!("failed to count outdated chunks: {}", e)))?;
+    if outdated == 0 {
+        return Ok(());
+    }
+    info!("Re-embedding {} chunks with {}", outdated, model);
+
+    let mut after_id = 0;
+    let mut total = 0;
+    loop {
+        let chunks = FileChunkMac::get_outdated_chunks(mm, model, after_id, batch_size)
+            .await
+            .map_err(|e| Error::Custom(format!("failed to get outdated chunks: {}", e)))?;
+        let Some(last) = chunks.last() else {
+            break;
+        };
+        // Chunks without text cannot be re-embedded, the cursor moves past them
+        after_id = last.chunk_id;
+        let (ids, texts): (Vec<i64>, Vec<String>) = chunks
+            .into_iter()
+            .filter_map(|chunk| {
+                let text = chunk.content_md.filter(|text| !text.is_empty())?;
+                let text = prompts.apply(chunk.metadata.language.as_deref(), text);
+                Some((chunk.chunk_id, text))
+            })
+            .unzip();
+        if texts.is_empty() {
+            continue;
         }
+
+        let embeddings = embedder.embed(texts).await?;
+        if embeddings.len() != ids.len() {
+            return Err(Error::Custom(format!(
+                "embedder returned {} embeddings for {} chunks",
+                embeddings.len(),
+                ids.len()
+            )));
+        }
+        let embeddings = ids.into_iter().zip(embeddings).collect();
+        total += FileChunkMac::swap_embeddings(mm, model, embeddings, embedder.normalized())
+            .await
+            .map_err(|e| Error::Custom(format!("failed to swap embeddings: {}", e)))?;
     }
+    info!("Re-embedded {} chunks with {}", total, model);
     Ok(())
 }
 
@@ -181,7 +573,7 @@ mod tests {
     use candle_core::Device;
     use lib_core::_dev_utils::init_dev;
     use lib_core::database::ModelManager;
-    use lib_storage::create_aws_client;
+    use lib_storage::store::create_object_store;
 
     #[tokio::test]
     async fn test_process_new_files() -> Result<()> {
@@ -189,23 +581,27 @@ mod tests {
             .await
             .map_err(|e| Error::Custom(format!("Failed to initialize dev database: {}", e)))?;
         let mm = ModelManager::dev(db);
-        let client = create_aws_client().await;
+        let storage = create_object_store(&auth_config().bucket)
+            .await
+            .map_err(|e| Error::Custom(format!("Failed to create the object store: {}", e)))?;
         let device = Device::Cpu;
         let model_id = "intfloat/multilingual-e5-base";
 
         // Run the sync_s3_files function
-        sync_s3_files(&mm, &client).await?;
+        let sync = sync_s3_files(&mm, storage.as_ref()).await?;
+        assert!(sync.objects > 0);
         // Verify that files were processed and updated correctly
-        let files = FileMac::get_all_files(&mm)
+        let files = FileMac::get_all_files(&mm, DEFAULT_TENANT)
             .await
             .map_err(|e| Error::Custom(format!("Failed to get all files: {}", e)))?;
         assert!(!files.is_empty());
 
         // Run the process_new_files function
-        process_new_files(&mm, &client).await?;
+        let process = process_new_files(&mm, storage.as_ref(), None).await?;
+        assert!(process.processed + process.failed > 0 || sync.created == 0);
 
         // Verify that files were processed and updated correctly
-        let file_chunks = FileChunkMac::search_chunks_by_keyword(&mm, "data", 10)
+        let file_chunks = FileChunkMac::search_chunks_by_keyword(&mm, DEFAULT_TENANT, "data", 10)
             .await
             .map_err(|e| Error::Custom(format!("Failed to get all file chunks: {}", e)))?;
         assert!(!file_chunks.is_empty());
@@ -213,5 +609,12 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_tenant_from_key() {
+        assert_eq!(tenant_from_key("acme/reports/q1.pdf"), "acme");
+        assert_eq!(tenant_from_key("q1.pdf"), DEFAULT_TENANT);
+        assert_eq!(tenant_from_key("/q1.pdf"), DEFAULT_TENANT);
+    }
 }
 // endregion: Unit Test
@@ -0,0 +1,231 @@
+//! Segments of a `DoclingDocument` (the `json_content` of the parser response) in reading order,
+//! with the page, the heading path and the hyperlink of every text item and table, and the headers
+//! of emails.
+
+use crate::chunker::{Segment, SegmentMeta};
+use serde_json::Value;
+use std::borrow::Cow;
+
+/// Walk the `body` tree of the document, `None` when it is not a `DoclingDocument`
+pub fn document_segments(doc: &Value) -> Option<Vec<Segment<'_>>> {
+    let body = doc.get("body")?;
+    let mut walker = Walker {
+        doc,
+        headings: Vec::new(),
+        segments: Vec::new(),
+    };
+    walker.visit(body, 0);
+    Some(walker.segments)
+}
+
+struct Walker<'a> {
+    doc: &'a Value,
+    /// (level, text) of the enclosing section headers
+    headings: Vec<(u64, String)>,
+    segments: Vec<Segment<'a>>,
+}
+
+impl<'a> Walker<'a> {
+    fn visit(&mut self, node: &'a Value, depth: usize) {
+        // Guards against reference cycles in malformed documents
+        if depth > 64 {
+            return;
+        }
+        let Some(children) = node.get("children").and_then(Value::as_array) else {
+            return;
+        };
+        for child in children {
+            let Some(item) = child
+                .get("$ref")
+                .and_then(Value::as_str)
+                .and_then(|reference| self.resolve(reference))
+            else {
+                continue;
+            };
+            self.item(item);
+            self.visit(item, depth + 1);
+        }
+    }
+
+    /// `#/texts/3` -> `doc["texts"][3]`
+    fn resolve(&self, reference: &str) -> Option<&'a Value> {
+        reference
+            .strip_prefix("#/")?
+            .split('/')
+            .try_fold(self.doc, |value, key| match key.parse::<usize>() {
+                Ok(index) => value.get(index),
+                Err(_) => value.get(key),
+            })
+    }
+
+    fn item(&mut self, item: &'a Value) {
+        let label = item
+            .get("label")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let text = match label {
+            "table" => Cow::Owned(table_text(item)),
+            _ => match item.get("text").and_then(Value::as_str) {
+                Some(text) => Cow::Borrowed(text),
+                None => return,
+            },
+        };
+        if label == "section_header" || label == "title" {
+            let level = match label {
+                "title" => 0,
+                _ => item.get("level").and_then(Value::as_u64).unwrap_or(1),
+            };
+            self.headings.retain(|(parent, _)| *parent < level);
+            self.headings.push((level, text.trim().to_string()));
+        }
+        let page = item
+            .get("prov")
+            .and_then(|prov| prov.get(0))
+            .and_then(|prov| prov.get("page_no"))
+            .and_then(Value::as_i64)
+            .map(|page| page as i32);
+        // Relative links of the source (anchors, local paths) cannot be followed from a result
+        let links = item
+            .get("hyperlink")
+            .and_then(Value::as_str)
+            .filter(|link| link.starts_with("http://") || link.starts_with("https://"))
+            .map(|link| vec![link.to_string()])
+            .unwrap_or_default();
+        self.segments.push(Segment {
+            text,
+            meta: SegmentMeta {
+                page,
+                heading_path: self.headings.iter().map(|(_, text)| text.clone()).collect(),
+                links,
+            },
+        });
+    }
+}
+
+/// Headers of an email, read from the header lines (`From: ...`) the document starts with
+#[derive(Debug, Default, PartialEq)]
+pub struct EmailHeaders {
+    pub sender: Option<String>,
+    pub recipients: Vec<String>,
+    pub subject: Option<String>,
+}
+
+/// `None` unless the first text items of the document are email headers with a `From` line
+pub fn email_headers(doc: &Value) -> Option<EmailHeaders> {
+    let texts = doc.get("texts").and_then(Value::as_array)?;
+    let mut headers = EmailHeaders::default();
+    'texts: for text in texts.iter().filter_map(|item| item.get("text")?.as_str()) {
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let Some((name, value)) = line.split_once(':') else {
+                break 'texts;
+            };
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "from" => headers.sender = addresses(value).into_iter().next(),
+                "to" | "cc" => headers.recipients.extend(addresses(value)),
+                "subject" => headers.subject = Some(value.to_string()),
+                "date" | "sent" | "reply-to" | "bcc" => {}
+                _ => break 'texts,
+            }
+        }
+    }
+    headers.sender.is_some().then_some(headers)
+}
+
+/// Lowercase addresses of a header value, `Jane <jane@acme.io>, bob@acme.io` ->
+/// `[jane@acme.io, bob@acme.io]`
+fn addresses(value: &str) -> Vec<String> {
+    value
+        .split([',', ';'])
+        .filter_map(|mailbox| {
+            let address = match mailbox.rsplit_once('<') {
+                Some((_, address)) => address.trim_end().trim_end_matches('>'),
+                None => mailbox,
+            };
+            let address = address.trim();
+            address.contains('@').then(|| address.to_ascii_lowercase())
+        })
+        .collect()
+}
+
+/// Rows of the table grid, cells separated by ` | `
+fn table_text(table: &Value) -> String {
+    let Some(grid) = table
+        .get("data")
+        .and_then(|data| data.get("grid"))
+        .and_then(Value::as_array)
+    else {
+        return String::new();
+    };
+    grid.iter()
+        .filter_map(Value::as_array)
+        .map(|row| {
+            row.iter()
+                .map(|cell| cell.get("text").and_then(Value::as_str).unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join(" | ")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_document_segments() {
+        let doc = json!({
+            "body": {"children": [{"$ref": "#/texts/0"}, {"$ref": "#/groups/0"}, {"$ref": "#/tables/0"}]},
+            "groups": [{"children": [{"$ref": "#/texts/1"}, {"$ref": "#/texts/2"}]}],
+            "texts": [
+                {"label": "section_header", "level": 1, "text": "Pricing", "prov": [{"page_no": 1}]},
+                {"label": "section_header", "level": 2, "text": "Plans", "prov": [{"page_no": 2}]},
+                {"label": "text", "text": "Basic plan", "prov": [{"page_no": 2}]},
+            ],
+            "tables": [{"label": "table", "data": {"grid": [[{"text": "a"}, {"text": "b"}]]}}],
+        });
+        let segments = document_segments(&doc).unwrap();
+        assert_eq!(segments.len(), 4);
+        assert_eq!(segments[2].text, "Basic plan");
+        assert_eq!(segments[2].meta.page, Some(2));
+        assert_eq!(segments[2].meta.heading_path, vec!["Pricing", "Plans"]);
+        assert_eq!(segments[3].text, "a | b");
+        assert!(document_segments(&json!("markdown")).is_none());
+    }
+
+    #[test]
+    fn test_hyperlinks() {
+        let doc = json!({
+            "body": {"children": [{"$ref": "#/texts/0"}, {"$ref": "#/texts/1"}]},
+            "texts": [
+                {"label": "text", "text": "Docs", "hyperlink": "https://docs.acme.io/"},
+                {"label": "text", "text": "Top", "hyperlink": "#top"},
+            ],
+        });
+        let segments = document_segments(&doc).unwrap();
+        assert_eq!(segments[0].meta.links, vec!["https://docs.acme.io/"]);
+        assert!(segments[1].meta.links.is_empty());
+    }
+
+    #[test]
+    fn test_email_headers() {
+        let doc = json!({
+            "texts": [
+                {"label": "text", "text": "From: Jane Doe <Jane@Acme.io>\nTo: bob@acme.io, Eve <eve@acme.io>"},
+                {"label": "text", "text": "Subject: Q3 report"},
+                {"label": "text", "text": "Hi Bob, see the figures: attached"},
+            ],
+        });
+        let headers = email_headers(&doc).unwrap();
+        assert_eq!(headers.sender.as_deref(), Some("jane@acme.io"));
+        assert_eq!(headers.recipients, vec!["bob@acme.io", "eve@acme.io"]);
+        assert_eq!(headers.subject.as_deref(), Some("Q3 report"));
+
+        let doc = json!({"texts": [{"label": "text", "text": "Note: not an email"}]});
+        assert!(email_headers(&doc).is_none());
+    }
+}
+// endregion: Unit Test
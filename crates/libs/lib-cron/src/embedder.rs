@@ -0,0 +1,27 @@
+//! Embedding of chunk texts by the jobs, implemented by the server on top of its inference queue
+//! so that jobs share the batching and backpressure of the API.
+
+use crate::error::Result;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait ChunkEmbedder: Send + Sync {
+    /// Id of the served model, stored on every chunk it embeds. The served model may be switched
+    /// at runtime, a job reads it once per run.
+    fn model_id(&self) -> String;
+
+    /// Whether the embeddings have a unit L2 norm, stored on every chunk it embeds
+    fn normalized(&self) -> bool {
+        true
+    }
+
+    /// One embedding per text, in order
+    async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>>;
+
+    /// Embed search queries like `/search`, wrapped with `instruction` on instruct-style models
+    async fn embed_queries(
+        &self,
+        queries: Vec<String>,
+        instruction: Option<&str>,
+    ) -> Result<Vec<Vec<f32>>>;
+}
@@ -4,6 +4,12 @@ pub type Result<T> = core::result::Result<T, Error>;
 pub enum Error {
     MissingEnv(&'static str),
     ChronFails(String),
+    /// Job type missing from the registry of this node
+    UnknownJobType(String),
+    /// Custom job type that cannot be registered
+    InvalidJobType(String),
+    /// Cron expression or timezone that cannot be scheduled
+    InvalidSchedule(String),
     Custom(String),
 }
 
@@ -23,3 +29,9 @@ impl From<lib_utils::error::Error> for Error {
         }
     }
 }
+
+impl From<lib_core::error::Error> for Error {
+    fn from(err: lib_core::error::Error) -> Self {
+        Error::Custom(err.to_string())
+    }
+}
@@ -0,0 +1,118 @@
+//! Scheduled retrieval evaluation: searches the golden queries of every tenant like `/search`,
+//! records Recall@k and MRR in `evaluation_runs` and raises an alert when recall dropped beyond
+//! `EVAL_RECALL_DROP` since the previous run of the tenant.
+
+use crate::config::auth_config;
+use crate::embedder::ChunkEmbedder;
+use crate::error::{Error, Result};
+use lib_core::database::ModelManager;
+use lib_core::model::evaluation::{
+    EvaluationMac, EvaluationRun, EvaluationRunForCreate, recall_at_k, reciprocal_rank,
+};
+use lib_core::model::file_chunks::FileChunkMac;
+use serde_json::json;
+use tracing::{info, warn};
+
+/// Evaluate the golden set of every tenant with the served model
+pub async fn evaluate_golden_set(
+    mm: &ModelManager,
+    embedder: &dyn ChunkEmbedder,
+) -> Result<Vec<EvaluationRun>> {
+    let mut runs = Vec::new();
+    for tenant_id in EvaluationMac::get_tenants(mm).await? {
+        let run = evaluate_tenant(mm, embedder, &tenant_id).await?;
+        info!(
+            "Evaluation of {tenant_id}: Recall@{} {:.3}, MRR {:.3} over {} queries",
+            run.k, run.recall, run.mrr, run.queries
+        );
+        runs.push(run);
+    }
+    Ok(runs)
+}
+
+async fn evaluate_tenant(
+    mm: &ModelManager,
+    embedder: &dyn ChunkEmbedder,
+    tenant_id: &str,
+) -> Result<EvaluationRun> {
+    let config = auth_config();
+    let k = config.eval_k;
+    let model = embedder.model_id();
+    let queries = EvaluationMac::list_queries(mm, tenant_id).await?;
+
+    let (mut recall, mut mrr) = (0.0, 0.0);
+    for query in &queries {
+        let embedding = embedder
+            .embed_queries(vec![query.query.clone()], query.instruction.as_deref())
+            .await?
+            .pop()
+            .ok_or_else(|| Error::Custom("No embedding returned for the query".to_string()))?;
+        let chunks =
+            FileChunkMac::search_chunks_by_embedding(mm, tenant_id, embedding, &model, k, None)
+                .await?;
+        let hits: Vec<i64> = chunks.iter().map(|chunk| chunk.file_id).collect();
+        recall += recall_at_k(&hits, &query.relevant_files);
+        mrr += reciprocal_rank(&hits, &query.relevant_files);
+    }
+    let count = queries.len().max(1) as f64;
+    let (recall, mrr) = (recall / count, mrr / count);
+
+    let previous = EvaluationMac::list_runs(mm, tenant_id, 1).await?.pop();
+    let regression = previous
+        .as_ref()
+        .is_some_and(|previous| previous.recall - recall > config.eval_recall_drop);
+
+    let run = EvaluationMac::record_run(
+        mm,
+        tenant_id,
+        EvaluationRunForCreate {
+            model,
+            k: k as i32,
+            queries: queries.len() as i32,
+            recall,
+            mrr,
+            regression,
+        },
+    )
+    .await?;
+
+    metrics::gauge!("te_eval_recall", "tenant" => tenant_id.to_string()).set(recall);
+    metrics::gauge!("te_eval_mrr", "tenant" => tenant_id.to_string()).set(mrr);
+    if let Some(previous) = previous.filter(|_| regression) {
+        alert_regression(&previous, &run).await;
+    }
+    Ok(run)
+}
+
+/// Count the regression and post it to `EVAL_ALERT_WEBHOOK`, a failed post is only logged
+async fn alert_regression(previous: &EvaluationRun, run: &EvaluationRun) {
+    warn!(
+        "Recall@{} of {} dropped from {:.3} to {:.3}",
+        run.k, run.tenant_id, previous.recall, run.recall
+    );
+    metrics::counter!("te_eval_regressions", "tenant" => run.tenant_id.clone()).increment(1);
+
+    let Some(webhook) = &auth_config().eval_alert_webhook else {
+        return;
+    };
+    let body = json!({
+        "event": "recall_regression",
+        "tenant_id": run.tenant_id,
+        "model": run.model,
+        "k": run.k,
+        "previous_recall": previous.recall,
+        "recall": run.recall,
+        "mrr": run.mrr,
+        "previous_run_id": previous.run_id,
+        "run_id": run.run_id,
+    });
+    let res = reqwest::Client::new()
+        .post(webhook)
+        .json(&body)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status());
+    if let Err(e) = res {
+        warn!("Failed to post the evaluation alert: {e}");
+    }
+}
@@ -0,0 +1,362 @@
+//! Cleanup of the Hugging Face hub cache.
+//!
+//! The hub cache stores every repository as `models--{org}--{name}/` with `blobs/`, `refs/` and
+//! `snapshots/{sha}/`, snapshot files being symlinks into `blobs/`. Long-lived nodes accumulate
+//! snapshots of old revisions and models that are no longer served, this job removes them:
+//!
+//! - snapshots not referenced by `refs/` and unused for longer than `max_age`
+//! - whole repositories unused for longer than `max_age`
+//! - the least recently used repositories while the cache exceeds `max_size_bytes`
+//! - blobs no longer referenced by any snapshot
+//!
+//! The repositories of the loaded model and of the warm standby models, and their current
+//! snapshots, are never removed.
+
+use crate::error::{Error, Result};
+use lib_utils::envs::get_env;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone)]
+pub struct CacheCleanup {
+    pub cache_dir: PathBuf,
+    /// Model served by this node, e.g. `BAAI/bge-small-en-v1.5`
+    pub model_id: Option<String>,
+    /// Revision of the served model, a branch, tag or commit sha (`main` when unset)
+    pub revision: Option<String>,
+    /// Models downloaded next to the served one to switch over to them (`--standby-model-id`)
+    pub standby_model_ids: Vec<String>,
+    /// Snapshots and repositories unused for longer are removed (`HF_CACHE_MAX_AGE_DAYS`)
+    pub max_age: Duration,
+    /// Size the cache is brought back under (`HF_CACHE_MAX_SIZE_GB`)
+    pub max_size_bytes: Option<u64>,
+}
+
+/// Outcome of a cleanup run
+#[derive(Debug, Default, Clone, Serialize, PartialEq, Eq)]
+pub struct CleanupReport {
+    pub removed_repos: Vec<String>,
+    pub removed_snapshots: usize,
+    pub removed_blobs: usize,
+    pub reclaimed_bytes: u64,
+    pub remaining_bytes: u64,
+}
+
+struct RepoEntry {
+    name: String,
+    path: PathBuf,
+    last_used: SystemTime,
+    size: u64,
+}
+
+impl CacheCleanup {
+    /// `cache_dir` falls back to `HUGGINGFACE_HUB_CACHE`, `HF_HOME/hub` and then
+    /// `~/.cache/huggingface/hub`, as resolved by the hub client
+    pub fn from_env(
+        cache_dir: Option<String>,
+        model_id: Option<String>,
+        revision: Option<String>,
+    ) -> Self {
+        let cache_dir = cache_dir
+            .or_else(|| get_env("HUGGINGFACE_HUB_CACHE").ok())
+            .map(PathBuf::from)
+            .or_else(|| {
+                get_env::<String>("HF_HOME")
+                    .ok()
+                    .map(|home| Path::new(&home).join("hub"))
+            })
+            .unwrap_or_else(|| {
+                let home = get_env("HOME").unwrap_or_else(|_| ".".to_string());
+                Path::new(&home).join(".cache/huggingface/hub")
+            });
+        let max_age_days: u64 = get_env("HF_CACHE_MAX_AGE_DAYS").unwrap_or(30);
+        let max_size_bytes = get_env::<f64>("HF_CACHE_MAX_SIZE_GB")
+            .ok()
+            .map(|gb| (gb * 1024.0 * 1024.0 * 1024.0) as u64);
+
+        Self {
+            cache_dir,
+            model_id,
+            revision,
+            standby_model_ids: Vec::new(),
+            max_age: Duration::from_secs(max_age_days * DAY),
+            max_size_bytes,
+        }
+    }
+
+    /// Directory names of the loaded and standby models in the cache
+    fn protected_repos(&self) -> HashSet<String> {
+        self.model_id
+            .iter()
+            .chain(&self.standby_model_ids)
+            .map(|model_id| format!("models--{}", model_id.replace('/', "--")))
+            .collect()
+    }
+
+    /// Blocking, run it with `spawn_blocking`
+    pub fn run(&self) -> Result<CleanupReport> {
+        let mut report = CleanupReport::default();
+        if !self.cache_dir.is_dir() {
+            return Ok(report);
+        }
+        let now = SystemTime::now();
+        let protected = self.protected_repos();
+        let expired = |last_used: SystemTime| {
+            now.duration_since(last_used).unwrap_or_default() > self.max_age
+        };
+
+        let mut repos = Vec::new();
+        for entry in read_dir(&self.cache_dir)? {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !path.is_dir() || !name.starts_with("models--") {
+                continue;
+            }
+            let is_protected = protected.contains(&name);
+
+            // Old revisions first, the repository itself may still be in use
+            let kept = self.referenced_snapshots(&path, is_protected);
+            for snapshot in read_dir(&path.join("snapshots")).unwrap_or_default() {
+                let sha = snapshot.file_name().to_string_lossy().to_string();
+                if kept.contains(&sha) || !expired(last_used(&snapshot.path())) {
+                    continue;
+                }
+                remove_dir(&snapshot.path())?;
+                report.removed_snapshots += 1;
+            }
+            let (blobs, reclaimed) = remove_unreferenced_blobs(&path)?;
+            report.removed_blobs += blobs;
+            report.reclaimed_bytes += reclaimed;
+
+            let repo = RepoEntry {
+                last_used: last_used(&path.join("snapshots")),
+                size: dir_size(&path),
+                name,
+                path,
+            };
+            if !is_protected && expired(repo.last_used) {
+                self.remove_repo(repo, &mut report)?;
+            } else if !is_protected {
+                repos.push(repo);
+            } else {
+                report.remaining_bytes += repo.size;
+            }
+        }
+
+        // Least recently used repositories go first until the cache fits
+        repos.sort_by_key(|repo| repo.last_used);
+        let mut total = report.remaining_bytes + repos.iter().map(|repo| repo.size).sum::<u64>();
+        let mut repos = repos.into_iter();
+        if let Some(max_size) = self.max_size_bytes {
+            while total > max_size {
+                let Some(repo) = repos.next() else {
+                    warn!(
+                        "Model cache is {total} bytes, above the {max_size} bytes limit, \
+                         with only the loaded and standby models left"
+                    );
+                    break;
+                };
+                total -= repo.size;
+                self.remove_repo(repo, &mut report)?;
+            }
+        }
+        report.remaining_bytes = total;
+        Ok(report)
+    }
+
+    /// Snapshots pointed to by `refs/`, plus the revision of the loaded model
+    fn referenced_snapshots(&self, repo: &Path, is_protected: bool) -> HashSet<String> {
+        let mut kept: HashSet<String> = walk_files(&repo.join("refs"))
+            .iter()
+            .filter_map(|path| fs::read_to_string(path).ok())
+            .map(|sha| sha.trim().to_string())
+            .collect();
+        if is_protected {
+            let revision = self.revision.as_deref().unwrap_or("main");
+            match fs::read_to_string(repo.join("refs").join(revision)) {
+                Ok(sha) => kept.insert(sha.trim().to_string()),
+                Err(_) => kept.insert(revision.to_string()),
+            };
+        }
+        kept
+    }
+
+    fn remove_repo(&self, repo: RepoEntry, report: &mut CleanupReport) -> Result<()> {
+        remove_dir(&repo.path)?;
+        info!("Removed cached model {} ({} bytes)", repo.name, repo.size);
+        report.reclaimed_bytes += repo.size;
+        report.removed_repos.push(repo.name);
+        Ok(())
+    }
+}
+
+/// Remove the blobs no snapshot links to anymore, returns the count and the reclaimed bytes
+fn remove_unreferenced_blobs(repo: &Path) -> Result<(usize, u64)> {
+    let referenced: HashSet<String> = walk_files(&repo.join("snapshots"))
+        .iter()
+        .filter_map(|path| fs::read_link(path).ok())
+        .filter_map(|target| target.file_name().map(|n| n.to_string_lossy().to_string()))
+        .collect();
+
+    let mut removed = 0;
+    let mut reclaimed = 0;
+    for blob in read_dir(&repo.join("blobs")).unwrap_or_default() {
+        let name = blob.file_name().to_string_lossy().to_string();
+        // `.incomplete` blobs belong to a download in progress
+        if referenced.contains(&name) || name.ends_with(".incomplete") {
+            continue;
+        }
+        let size = blob.metadata().map(|m| m.len()).unwrap_or(0);
+        fs::remove_file(blob.path())
+            .map_err(|e| Error::Custom(format!("Failed to remove {:?}: {e}", blob.path())))?;
+        removed += 1;
+        reclaimed += size;
+    }
+    Ok((removed, reclaimed))
+}
+
+fn read_dir(path: &Path) -> Result<Vec<fs::DirEntry>> {
+    fs::read_dir(path)
+        .and_then(|entries| entries.collect())
+        .map_err(|e| Error::Custom(format!("Failed to read {path:?}: {e}")))
+}
+
+fn remove_dir(path: &Path) -> Result<()> {
+    fs::remove_dir_all(path).map_err(|e| Error::Custom(format!("Failed to remove {path:?}: {e}")))
+}
+
+/// Every file below `path`, symlinks are not followed
+fn walk_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => dirs.push(entry.path()),
+                Ok(_) => files.push(entry.path()),
+                Err(_) => {}
+            }
+        }
+    }
+    files
+}
+
+/// Most recent access or modification of the files below `path`, following the snapshot links
+/// so that loading a model counts as a use
+fn last_used(path: &Path) -> SystemTime {
+    walk_files(path)
+        .iter()
+        .filter_map(|file| fs::metadata(file).ok())
+        .filter_map(|meta| {
+            let modified = meta.modified().ok()?;
+            Some(
+                meta.accessed()
+                    .map_or(modified, |accessed| accessed.max(modified)),
+            )
+        })
+        .max()
+        .or_else(|| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// Size on disk of the files below `path`, symlinks count for nothing
+fn dir_size(path: &Path) -> u64 {
+    walk_files(path)
+        .iter()
+        .filter_map(|file| fs::symlink_metadata(file).ok())
+        .filter(|meta| meta.is_file())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+// region: Unit Test
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn write_repo(cache: &Path, repo: &str, snapshots: &[(&str, &str)], main: &str) {
+        let repo = cache.join(repo);
+        fs::create_dir_all(repo.join("blobs")).unwrap();
+        fs::create_dir_all(repo.join("refs")).unwrap();
+        fs::write(repo.join("refs/main"), main).unwrap();
+        for (sha, blob) in snapshots {
+            fs::write(repo.join("blobs").join(blob), vec![0u8; 1024]).unwrap();
+            let snapshot = repo.join("snapshots").join(sha);
+            fs::create_dir_all(&snapshot).unwrap();
+            symlink(
+                format!("../../blobs/{blob}"),
+                snapshot.join("model.safetensors"),
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_cleanup_keeps_loaded_model() {
+        let cache = std::env::temp_dir().join(format!("hf-cache-{}", uuid::Uuid::new_v4()));
+        write_repo(
+            &cache,
+            "models--org--loaded",
+            &[("old", "blob-old"), ("current", "blob-current")],
+            "current",
+        );
+        write_repo(
+            &cache,
+            "models--org--unused",
+            &[("sha", "blob-unused")],
+            "sha",
+        );
+
+        let cleanup = CacheCleanup {
+            cache_dir: cache.clone(),
+            model_id: Some("org/loaded".to_string()),
+            revision: None,
+            standby_model_ids: Vec::new(),
+            max_age: Duration::ZERO,
+            max_size_bytes: None,
+        };
+        let report = cleanup.run().unwrap();
+
+        assert_eq!(report.removed_repos, vec!["models--org--unused"]);
+        assert_eq!(report.removed_snapshots, 1);
+        assert_eq!(report.removed_blobs, 1);
+        // blob of the old snapshot, unused repository with its `refs/main`
+        assert_eq!(report.reclaimed_bytes, 1024 + 1024 + "sha".len() as u64);
+        assert_eq!(report.remaining_bytes, 1024 + "current".len() as u64);
+        assert!(cache.join("models--org--loaded/snapshots/current").exists());
+        assert!(!cache.join("models--org--loaded/blobs/blob-old").exists());
+
+        fs::remove_dir_all(cache).unwrap();
+    }
+
+    #[test]
+    fn test_cleanup_keeps_standby_models() {
+        let cache = std::env::temp_dir().join(format!("hf-cache-{}", uuid::Uuid::new_v4()));
+        write_repo(&cache, "models--org--loaded", &[("a", "blob-a")], "a");
+        write_repo(&cache, "models--org--standby", &[("b", "blob-b")], "b");
+        write_repo(&cache, "models--org--unused", &[("c", "blob-c")], "c");
+
+        let cleanup = CacheCleanup {
+            cache_dir: cache.clone(),
+            model_id: Some("org/loaded".to_string()),
+            revision: None,
+            standby_model_ids: vec!["org/standby".to_string()],
+            max_age: Duration::ZERO,
+            max_size_bytes: Some(0),
+        };
+        let report = cleanup.run().unwrap();
+
+        assert_eq!(report.removed_repos, vec!["models--org--unused"]);
+        assert!(cache.join("models--org--standby/snapshots/b").exists());
+
+        fs::remove_dir_all(cache).unwrap();
+    }
+}
+// endregion: Unit Test
@@ -0,0 +1,94 @@
+//! Language of the chunks, detected with `whatlang` when they are ingested and stored in their
+//! metadata, and the prompts prepended to a chunk of a language before it is embedded, e.g. the
+//! `passage: ` prefix of the e5 models in the language of the corpus.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// ISO 639-3 code of the language of the text, `None` when the detection is not reliable
+pub fn detect_language(text: &str) -> Option<String> {
+    whatlang::detect(text)
+        .filter(|info| info.is_reliable())
+        .map(|info| info.lang().code().to_string())
+}
+
+/// Prompt per language (`LANGUAGE_PROMPTS`), a JSON object of ISO 639-3 code to prompt where the
+/// key `*` gives the prompt of the other languages and of the chunks without a detected language,
+/// e.g. `{"deu": "Passage: ", "*": "passage: "}`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(from = "HashMap<String, String>")]
+pub struct LanguagePrompts {
+    by_language: HashMap<String, String>,
+    fallback: Option<String>,
+}
+
+impl From<HashMap<String, String>> for LanguagePrompts {
+    fn from(mut by_language: HashMap<String, String>) -> Self {
+        let fallback = by_language.remove("*");
+        let by_language = by_language
+            .into_iter()
+            .map(|(language, prompt)| (language.to_ascii_lowercase(), prompt))
+            .collect();
+        Self {
+            by_language,
+            fallback,
+        }
+    }
+}
+
+impl FromStr for LanguagePrompts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s).map_err(|e| format!("invalid language prompts: {e}"))
+    }
+}
+
+impl LanguagePrompts {
+    pub fn is_empty(&self) -> bool {
+        self.by_language.is_empty() && self.fallback.is_none()
+    }
+
+    pub fn prompt_for(&self, language: Option<&str>) -> Option<&str> {
+        language
+            .and_then(|language| self.by_language.get(&language.to_ascii_lowercase()))
+            .or(self.fallback.as_ref())
+            .map(String::as_str)
+    }
+
+    /// Text to embed for a chunk of `language`
+    pub fn apply(&self, language: Option<&str>, text: String) -> String {
+        match self.prompt_for(language) {
+            Some(prompt) => format!("{prompt}{text}"),
+            None => text,
+        }
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language() {
+        let text = "Die Rechnung wurde am Montag an die Buchhaltung geschickt und dort geprüft.";
+        assert_eq!(detect_language(text).as_deref(), Some("deu"));
+        assert_eq!(detect_language(""), None);
+    }
+
+    #[test]
+    fn test_language_prompts() {
+        let prompts: LanguagePrompts = r#"{"DEU": "Passage: ", "*": "passage: "}"#.parse().unwrap();
+        assert_eq!(prompts.apply(Some("deu"), "Text".into()), "Passage: Text");
+        assert_eq!(prompts.apply(Some("eng"), "Text".into()), "passage: Text");
+        assert_eq!(prompts.apply(None, "Text".into()), "passage: Text");
+
+        let prompts: LanguagePrompts = r#"{"fra": "passage : "}"#.parse().unwrap();
+        assert_eq!(prompts.apply(Some("eng"), "Text".into()), "Text");
+        assert!(LanguagePrompts::default().is_empty());
+        assert!("fra=passage".parse::<LanguagePrompts>().is_err());
+    }
+}
+// endregion: Unit Test
@@ -1,115 +1,338 @@
+pub mod chunker;
 pub mod config;
 pub mod db_operations;
+pub mod docling;
+pub mod embedder;
 pub mod error;
-
-use crate::db_operations::{process_new_files, sync_s3_files};
+pub mod evaluation;
+pub mod hf_cache;
+pub mod language;
+pub mod parser;
+pub mod replication;
+
+use crate::db_operations::{
+    backfill_bucket, compress_chunks, process_new_files, purge_deleted_files, reembed_chunks,
+    sync_s3_files,
+};
+use crate::embedder::ChunkEmbedder;
 use crate::error::{Error, Result};
-use aws_sdk_s3::Client;
+use crate::evaluation::evaluate_golden_set;
+use crate::hf_cache::CacheCleanup;
+use crate::replication::replicate_chunks;
 use chrono::Utc;
+use chrono_tz::Tz;
+use lib_core::ctx::DEFAULT_TENANT;
 use lib_core::database::ModelManager;
+use lib_core::model::cron_jobs::{
+    CronJob, CronJobForCreate, CronJobMac, JobRunForCreate, JobRunStatus,
+};
 use lib_embedding::Embeddings;
+use lib_storage::store::ObjectStore;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
 use tokio::sync::Mutex;
 use tokio_cron_scheduler::{JobBuilder, JobScheduler};
 use tracing::info;
 use uuid::Uuid;
 
-const JOBS_FILE: &str = "jobs.json";
+/// Owner of the jobs created by the static root key and of jobs persisted before ownership
+pub const ROOT_OWNER: &str = "root";
+/// Timezone of the jobs scheduled without one
+pub const DEFAULT_TIMEZONE: &str = "UTC";
+
+/// Job types any tenant may schedule, the others act on the whole node and need the root key
+pub const TENANT_JOB_TYPES: [&str; 2] = ["sync_s3_files", "process_new_files"];
+
+/// What a run does while another run of the same job type is still going, e.g. a
+/// `process_new_files` run taking longer than its interval
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConcurrencyPolicy {
+    /// The run is skipped and recorded as such in the run history
+    #[default]
+    Skip,
+    /// The run waits for the running one, at most one run waits per job type, the others are
+    /// skipped
+    Queue,
+    /// The runs overlap, on every replica: the job type is not locked across replicas
+    Allow,
+}
+
+impl ConcurrencyPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConcurrencyPolicy::Skip => "skip",
+            ConcurrencyPolicy::Queue => "queue",
+            ConcurrencyPolicy::Allow => "allow",
+        }
+    }
+
+    /// Policy stored in the `cron_jobs` table, unknown values fall back to the default
+    fn from_column(value: &str) -> Self {
+        match value {
+            "queue" => ConcurrencyPolicy::Queue,
+            "allow" => ConcurrencyPolicy::Allow,
+            _ => ConcurrencyPolicy::Skip,
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct JobRecord {
     pub id: String,
     pub job_type: String,
     pub cron: String,
+    /// IANA timezone `cron` is evaluated in
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(default)]
+    pub concurrency: ConcurrencyPolicy,
+    /// User id of the tenant that scheduled the job
+    #[serde(default = "root_owner")]
+    pub owner: String,
+    /// Tenant of `owner`, the job is only visible within it
+    #[serde(default = "default_tenant")]
+    pub tenant_id: String,
+}
+
+fn root_owner() -> String {
+    ROOT_OWNER.to_string()
+}
+
+fn default_tenant() -> String {
+    DEFAULT_TENANT.to_string()
+}
+
+fn default_timezone() -> String {
+    DEFAULT_TIMEZONE.to_string()
+}
+
+impl JobRecord {
+    /// Whether the job is of `tenant_id` and `owner`, any tenant or owner when `None`
+    pub fn belongs_to(&self, tenant_id: Option<&str>, owner: Option<&str>) -> bool {
+        tenant_id.is_none_or(|tenant_id| self.tenant_id == tenant_id)
+            && owner.is_none_or(|owner| self.owner == owner)
+    }
+}
+
+impl From<CronJob> for JobRecord {
+    fn from(job: CronJob) -> Self {
+        Self {
+            id: job.job_id.to_string(),
+            job_type: job.job_type,
+            cron: job.cron,
+            timezone: job.timezone,
+            concurrency: ConcurrencyPolicy::from_column(&job.concurrency),
+            owner: job.owner,
+            tenant_id: job.tenant_id,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct JobsCache {
-    pub jobs: Arc<Mutex<HashMap<Uuid, (String, String)>>>,
+    pub jobs: Arc<Mutex<HashMap<Uuid, JobRecord>>>,
+    mm: Arc<ModelManager>,
 }
 
 impl JobsCache {
     /// Returns a serializable Vec of JobRecord for external use.
     pub async fn serializable_jobs(&self) -> Vec<JobRecord> {
         let jobs = self.jobs.lock().await;
-        jobs.iter()
-            .map(|(id, (job_type, cron))| JobRecord {
-                id: id.to_string(),
-                job_type: job_type.clone(),
-                cron: cron.clone(),
-            })
-            .collect()
+        jobs.values().cloned().collect()
     }
 }
 
 impl JobsCache {
-    pub fn new() -> Self {
+    pub fn new(mm: Arc<ModelManager>) -> Self {
         Self {
             jobs: Arc::new(Mutex::new(HashMap::new())),
+            mm,
         }
     }
 
-    pub async fn add_job(&self, job_type: String, id: Uuid, cron: String) -> Result<()> {
+    pub async fn add_job(&self, record: JobRecord, id: Uuid) -> Result<()> {
         let mut jobs = self.jobs.lock().await;
-        jobs.insert(id, (job_type, cron));
-        save_jobs_to_file(&jobs).await?;
+        let job = CronJobForCreate {
+            job_id: id,
+            job_type: record.job_type.clone(),
+            cron: record.cron.clone(),
+            timezone: record.timezone.clone(),
+            concurrency: record.concurrency.as_str().to_string(),
+            owner: record.owner.clone(),
+            tenant_id: record.tenant_id.clone(),
+        };
+        CronJobMac::create_job(&self.mm, job).await?;
+        jobs.insert(id, record);
         Ok(())
     }
 
     pub async fn remove_job(&self, id: Uuid) -> Result<()> {
         let mut jobs = self.jobs.lock().await;
+        CronJobMac::delete_job(&self.mm, id).await?;
         jobs.remove(&id);
-        save_jobs_to_file(&jobs).await?;
         Ok(())
     }
 
+    /// Jobs persisted in the `cron_jobs` table
+    pub async fn load_jobs(&self) -> Result<HashMap<Uuid, JobRecord>> {
+        let jobs = CronJobMac::get_all_jobs(&self.mm).await?;
+        Ok(jobs
+            .into_iter()
+            .map(|job| (job.job_id, JobRecord::from(job)))
+            .collect())
+    }
+
     pub async fn get_jobs(&self) -> Vec<JobRecord> {
         let jobs = self.serializable_jobs().await;
         jobs
     }
 
-    pub async fn set_jobs(&self, map: HashMap<Uuid, (String, String)>) {
+    /// Jobs of `tenant_id` and `owner`, every tenant or owner when `None`
+    pub async fn get_jobs_for(
+        &self,
+        tenant_id: Option<&str>,
+        owner: Option<&str>,
+    ) -> Vec<JobRecord> {
+        let jobs = self.jobs.lock().await;
+        jobs.values()
+            .filter(|job| job.belongs_to(tenant_id, owner))
+            .cloned()
+            .collect()
+    }
+
+    pub async fn get_job(&self, id: Uuid) -> Option<JobRecord> {
+        self.jobs.lock().await.get(&id).cloned()
+    }
+
+    pub async fn set_jobs(&self, map: HashMap<Uuid, JobRecord>) {
         let mut jobs = self.jobs.lock().await;
         *jobs = map;
     }
 }
 
 type BoxFutureUnit = Pin<Box<dyn Future<Output = ()> + Send>>;
-type JobFn = Arc<dyn Fn() -> BoxFutureUnit + Send + Sync + 'static>;
+/// Outcome of a run, the error is recorded in the run history
+type BoxFutureRun = Pin<Box<dyn Future<Output = std::result::Result<(), String>> + Send>>;
+type JobFn = Arc<dyn Fn() -> BoxFutureRun + Send + Sync + 'static>;
+
+/// Runs of one job type, shared by every job of the type
+#[derive(Default)]
+struct JobGuard {
+    running: Mutex<()>,
+    /// A run waits for the running one, see `ConcurrencyPolicy::Queue`
+    queued: AtomicBool,
+}
 
 #[derive(Clone)]
 pub struct ChronJobs {
     pub scheduler: Arc<Mutex<JobScheduler>>,
     pub cache: JobsCache,
     registry: Arc<HashMap<String, JobFn>>,
+    /// By job type
+    guards: Arc<HashMap<String, Arc<JobGuard>>>,
 }
 
-impl ChronJobs {
-    /// Build the scheduler + job registry from owned deps.
-    pub async fn new(mm: Arc<ModelManager>, s3_client: Arc<Client>) -> Result<Self> {
+/// Registry of a `ChronJobs` under construction, the built-in jobs plus the ones `register`ed by
+/// the binary or a downstream crate
+pub struct ChronJobsBuilder {
+    mm: Arc<ModelManager>,
+    registry: HashMap<String, JobFn>,
+}
+
+impl ChronJobsBuilder {
+    /// Register a custom job under `job_type`, with the dependencies it captures. Custom job
+    /// types are only scheduled by admins, they are not `TENANT_JOB_TYPES`. A job type made of
+    /// other characters than lowercase ASCII letters, digits and `_`, or already registered, is
+    /// refused.
+    pub fn register<F, Fut, E>(mut self, job_type: impl Into<String>, job: F) -> Result<Self>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::result::Result<(), E>> + Send + 'static,
+        E: std::fmt::Debug + 'static,
+    {
+        let job_type = job_type.into();
+        let valid = !job_type.is_empty()
+            && job_type
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !valid {
+            return Err(Error::InvalidJobType(format!(
+                "'{}' must be lowercase letters, digits and '_'",
+                job_type
+            )));
+        }
+        if self.registry.contains_key(&job_type) {
+            return Err(Error::InvalidJobType(format!(
+                "'{}' is already registered",
+                job_type
+            )));
+        }
+        let f: JobFn = Arc::new(move || {
+            let run = job();
+            Box::pin(async move { run.await.map_err(|e| format!("{:?}", e)) })
+        });
+        info!("Custom job type {} registered", job_type);
+        self.registry.insert(job_type, f);
+        Ok(self)
+    }
+
+    /// Create the scheduler, jobs are only restored by `ChronJobs::start`
+    pub async fn build(self) -> Result<ChronJobs> {
         let scheduler = Arc::new(Mutex::new(JobScheduler::new().await.map_err(|e| {
             Error::ChronFails(format!("Failed to create JobScheduler: {}", e))
         })?));
-        let cache = JobsCache::new();
-
-        // Build the registry with 'static closures that own Arcs.
-        let registry = JobRegistry::build(mm, s3_client);
+        let cache = JobsCache::new(self.mm);
+        let guards = self
+            .registry
+            .keys()
+            .map(|job_type| (job_type.clone(), Arc::default()))
+            .collect();
 
-        Ok(Self {
+        Ok(ChronJobs {
             scheduler,
             cache,
-            registry: Arc::new(registry),
+            registry: Arc::new(self.registry),
+            guards: Arc::new(guards),
         })
     }
+}
+
+impl ChronJobs {
+    /// Builder starting from the built-in jobs, for registering custom ones.
+    /// `reembed_chunks` and `evaluate_golden_set` are only registered with an `embedder`.
+    pub fn builder(
+        mm: Arc<ModelManager>,
+        storage: Arc<dyn ObjectStore>,
+        cache_cleanup: CacheCleanup,
+        embedder: Option<Arc<dyn ChunkEmbedder>>,
+    ) -> ChronJobsBuilder {
+        // Build the registry with 'static closures that own Arcs.
+        let registry = JobRegistry::build(mm.clone(), storage, cache_cleanup, embedder);
+        ChronJobsBuilder { mm, registry }
+    }
+
+    /// Build the scheduler + job registry from owned deps, with the built-in jobs only.
+    pub async fn new(
+        mm: Arc<ModelManager>,
+        storage: Arc<dyn ObjectStore>,
+        cache_cleanup: CacheCleanup,
+        embedder: Option<Arc<dyn ChunkEmbedder>>,
+    ) -> Result<Self> {
+        Self::builder(mm, storage, cache_cleanup, embedder)
+            .build()
+            .await
+    }
 
-    /// Start the scheduler and restore jobs from disk.
+    /// Start the scheduler and restore the persisted jobs.
     pub async fn start(&self) -> Result<()> {
-        let job_map = load_jobs_from_file().await.unwrap_or_default();
+        let job_map = self.cache.load_jobs().await?;
         self.cache.set_jobs(job_map.clone()).await;
 
-        for (job_id, (job_type, cron_expr)) in job_map {
-            self.add_cron_job(job_id, job_type, cron_expr).await?;
+        for (job_id, job) in job_map {
+            self.add_cron_job(job_id, &job).await?;
         }
 
         let sched = self.scheduler.lock().await;
@@ -121,14 +344,52 @@ impl ChronJobs {
         Ok(())
     }
 
-    /// Add & persist a new job.
-    pub async fn add_job(&self, job_type: String, cron: String) -> Result<Uuid> {
+    /// Job types of the registry, sorted. Some are only registered with an embedder or on
+    /// replicas.
+    pub fn job_types(&self) -> Vec<&str> {
+        let mut types: Vec<&str> = self.registry.keys().map(String::as_str).collect();
+        types.sort_unstable();
+        types
+    }
+
+    /// Run a job type once, now, outside of the schedule and of the run history.
+    pub async fn run_once(&self, job_type: &str) -> Result<()> {
+        let job_fn = self
+            .registry
+            .get(job_type)
+            .cloned()
+            .ok_or_else(|| Error::UnknownJobType(job_type.to_string()))?;
+        info!("Job {} is running once", job_type);
+        (job_fn)()
+            .await
+            .map_err(|e| Error::ChronFails(format!("{} failed: {}", job_type, e)))
+    }
+
+    /// Add & persist a new job owned by `owner` of `tenant_id`, `cron` is evaluated in
+    /// `timezone`.
+    pub async fn add_job(
+        &self,
+        job_type: String,
+        cron: String,
+        timezone: String,
+        concurrency: ConcurrencyPolicy,
+        owner: String,
+        tenant_id: String,
+    ) -> Result<JobRecord> {
         let id = Uuid::new_v4();
-        self.cache
-            .add_job(job_type.clone(), id, cron.clone())
-            .await?;
-        self.add_cron_job(id, job_type, cron).await?;
-        Ok(id)
+        let record = JobRecord {
+            id: id.to_string(),
+            job_type,
+            cron,
+            timezone,
+            concurrency,
+            owner,
+            tenant_id,
+        };
+        // Register first so an unknown type, invalid cron or timezone is not persisted
+        self.add_cron_job(id, &record).await?;
+        self.cache.add_job(record.clone(), id).await?;
+        Ok(record)
     }
 
     /// Remove a job by id.
@@ -142,22 +403,30 @@ impl ChronJobs {
     }
 
     /// Internal: create the scheduled task from the registry entry.
-    pub async fn add_cron_job(&self, id: Uuid, job_type: String, cron: String) -> Result<()> {
+    pub async fn add_cron_job(&self, id: Uuid, record: &JobRecord) -> Result<()> {
+        let job_type = record.job_type.clone();
+        let cron = record.cron.clone();
+        let concurrency = record.concurrency;
         let job_fn = self
             .registry
             .get(&job_type)
             .cloned()
-            .ok_or_else(|| Error::ChronFails(format!("No job found for type {}", job_type)))?;
+            .ok_or_else(|| Error::UnknownJobType(job_type.clone()))?;
+        let guard = self.guards.get(&job_type).cloned().unwrap_or_default();
+        let timezone: Tz = record.timezone.parse().map_err(|_| {
+            Error::InvalidSchedule(format!("Unknown timezone '{}'", record.timezone))
+        })?;
 
         // Async run logic
         let job_id = id;
+        let mm = self.cache.mm.clone();
         let job_logic = Box::new(move |_jid: uuid::Uuid, mut sched: JobScheduler| {
             let job_type = job_type.clone();
             let job_fn = job_fn.clone();
+            let guard = guard.clone();
+            let mm = mm.clone();
             Box::pin(async move {
-                info!("Job {} is running", job_type);
-                // run & log errors yourself inside job_fn (we capture none here)
-                (job_fn)().await;
+                run_job(&mm, job_id, &job_type, concurrency, &guard, job_fn).await;
 
                 match sched.next_tick_for_job(job_id).await {
                     Ok(Some(ts)) => info!("Next time for job {} is {:?}", job_type, ts),
@@ -167,11 +436,11 @@ impl ChronJobs {
         });
 
         let job = JobBuilder::new()
-            .with_timezone(Utc)
+            .with_timezone(timezone)
             .with_job_id(id.into())
             .with_cron_job_type()
             .with_schedule(cron.clone())
-            .map_err(|e| Error::ChronFails(format!("Invalid cron '{}': {}", cron, e)))?
+            .map_err(|e| Error::InvalidSchedule(format!("Invalid cron '{}': {}", cron, e)))?
             .with_run_async(job_logic)
             .build()
             .map_err(|e| Error::ChronFails(format!("Failed to build job: {}", e)))?;
@@ -185,83 +454,275 @@ impl ChronJobs {
     }
 }
 
+/// Run the job unless `concurrency` skips it, and record the run in the history of the job
+async fn run_job(
+    mm: &ModelManager,
+    job_id: Uuid,
+    job_type: &str,
+    concurrency: ConcurrencyPolicy,
+    guard: &JobGuard,
+    job_fn: JobFn,
+) {
+    let started_at = Utc::now().naive_utc();
+    let skipped = |reason: &str| JobRunForCreate {
+        job_id,
+        job_type: job_type.to_string(),
+        status: JobRunStatus::Skipped,
+        error: Some(reason.to_string()),
+        started_at,
+        duration_ms: None,
+    };
+    let _running = match concurrency {
+        ConcurrencyPolicy::Allow => None,
+        ConcurrencyPolicy::Skip => match guard.running.try_lock() {
+            Ok(running) => Some(running),
+            Err(_) => {
+                info!("Job {} skipped, a run is still going", job_type);
+                record_run(mm, skipped("a run of the job type was still going")).await;
+                return;
+            }
+        },
+        ConcurrencyPolicy::Queue => {
+            if guard.queued.swap(true, Ordering::SeqCst) {
+                info!("Job {} skipped, a run is already queued", job_type);
+                record_run(mm, skipped("a run of the job type was already queued")).await;
+                return;
+            }
+            let running = guard.running.lock().await;
+            guard.queued.store(false, Ordering::SeqCst);
+            Some(running)
+        }
+    };
+    // Replicas sharing the database run a job type one at a time, `allow` opts out
+    let lock_job_type =
+        concurrency != ConcurrencyPolicy::Allow && config::auth_config().cron_distributed_lock;
+    let lock = if lock_job_type {
+        match CronJobMac::try_lock_job_type(mm, job_type).await {
+            Ok(Some(lock)) => Some(lock),
+            Ok(None) => {
+                info!("Job {} skipped, running on another replica", job_type);
+                let reason = "a run of the job type was going on another replica";
+                record_run(mm, skipped(reason)).await;
+                return;
+            }
+            Err(e) => {
+                tracing::error!("{} could not lock its job type: {}", job_type, e);
+                let run = JobRunForCreate {
+                    job_id,
+                    job_type: job_type.to_string(),
+                    status: JobRunStatus::Failed,
+                    error: Some(format!("could not lock the job type: {}", e)),
+                    started_at,
+                    duration_ms: None,
+                };
+                record_run(mm, run).await;
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
+    info!("Job {} is running", job_type);
+    let started = Instant::now();
+    let outcome = (job_fn)().await;
+    let duration_ms = Some(started.elapsed().as_millis() as i64);
+    let unlocked = match lock {
+        Some(lock) => lock.unlock().await,
+        None => Ok(()),
+    };
+    if let Err(e) = unlocked {
+        tracing::warn!("Could not unlock the job type {}: {}", job_type, e);
+    }
+    let (status, error) = match outcome {
+        Ok(()) => (JobRunStatus::Succeeded, None),
+        Err(e) => {
+            tracing::error!("{} failed: {}", job_type, e);
+            (JobRunStatus::Failed, Some(e))
+        }
+    };
+    let run = JobRunForCreate {
+        job_id,
+        job_type: job_type.to_string(),
+        status,
+        error,
+        started_at,
+        duration_ms,
+    };
+    record_run(mm, run).await;
+}
+
+async fn record_run(mm: &ModelManager, run: JobRunForCreate) {
+    metrics::counter!(
+        "te_cron_job_runs",
+        "job_type" => run.job_type.clone(),
+        "status" => run.status.as_str()
+    )
+    .increment(1);
+    if let Err(e) = CronJobMac::record_run(mm, run).await {
+        tracing::warn!("Could not record the job run: {}", e);
+    }
+}
+
 struct JobRegistry;
 
 impl JobRegistry {
-    fn build(mm: Arc<ModelManager>, client: Arc<Client>) -> HashMap<String, JobFn> {
+    fn build(
+        mm: Arc<ModelManager>,
+        storage: Arc<dyn ObjectStore>,
+        cache_cleanup: CacheCleanup,
+        embedder: Option<Arc<dyn ChunkEmbedder>>,
+    ) -> HashMap<String, JobFn> {
         let mut m: HashMap<String, JobFn> = HashMap::new();
 
         // sync_s3_files
         {
             let mm = Arc::clone(&mm);
-            let client = Arc::clone(&client);
+            let storage = Arc::clone(&storage);
             let f: JobFn = Arc::new(move || {
                 let mm = Arc::clone(&mm);
-                let client = Arc::clone(&client);
+                let storage = Arc::clone(&storage);
                 Box::pin(async move {
-                    if let Err(e) = sync_s3_files(&mm, &client).await {
-                        tracing::error!("sync_s3_files failed: {:?}", e);
-                    }
+                    sync_s3_files(&mm, storage.as_ref())
+                        .await
+                        .map(|_| ())
+                        .map_err(|e| format!("{:?}", e))
                 })
             });
             m.insert("sync_s3_files".to_string(), f);
         }
 
-        // process_new_files
+        // process_new_files: chunks the new files and embeds them through the shared embedder
         {
             let mm = Arc::clone(&mm);
-            let client = Arc::clone(&client);
+            let storage = Arc::clone(&storage);
+            let embedder = embedder.clone();
             let f: JobFn = Arc::new(move || {
                 let mm = Arc::clone(&mm);
-                let client = Arc::clone(&client);
+                let storage = Arc::clone(&storage);
+                let embedder = embedder.clone();
                 Box::pin(async move {
-                    // Lock only around the call that needs the embedder; keep critical section short
-                    //let emb_guard = embedder.lock().await;
-                    //if let Err(e) = process_new_files(&mm, &client, &*emb_guard).await {
-                    //    tracing::error!("process_new_files failed: {:?}", e);
-                    //}
+                    process_new_files(&mm, storage.as_ref(), embedder.as_deref())
+                        .await
+                        .map(|_| ())
+                        .map_err(|e| format!("{:?}", e))
                 })
             });
             m.insert("process_new_files".to_string(), f);
         }
 
-        m
-    }
-}
+        // sync_and_process: registers and ingests the whole bucket in one pass, see `--run-job`
+        {
+            let mm = Arc::clone(&mm);
+            let storage = Arc::clone(&storage);
+            let embedder = embedder.clone();
+            let f: JobFn = Arc::new(move || {
+                let mm = Arc::clone(&mm);
+                let storage = Arc::clone(&storage);
+                let embedder = embedder.clone();
+                Box::pin(async move {
+                    backfill_bucket(&mm, storage.as_ref(), embedder.as_deref())
+                        .await
+                        .map(|_| ())
+                        .map_err(|e| format!("{:?}", e))
+                })
+            });
+            m.insert("sync_and_process".to_string(), f);
+        }
 
-async fn save_jobs_to_file(jobs: &HashMap<Uuid, (String, String)>) -> Result<()> {
-    let job_list: Vec<JobRecord> = jobs
-        .iter()
-        .map(|(id, (job_type, cron))| JobRecord {
-            id: id.to_string(),
-            job_type: job_type.clone(),
-            cron: cron.clone(),
-        })
-        .collect();
+        // compress_chunks: migrates plain text chunks to zstd in small transactions, only when
+        // `CHUNK_COMPRESSION` is set, otherwise new chunks are stored in plain text
+        if lib_core::config::auth_config().chunk_compression {
+            let mm = Arc::clone(&mm);
+            let f: JobFn = Arc::new(move || {
+                let mm = Arc::clone(&mm);
+                Box::pin(async move { compress_chunks(&mm).await.map_err(|e| format!("{:?}", e)) })
+            });
+            m.insert("compress_chunks".to_string(), f);
+        }
 
-    let json = serde_json::to_string_pretty(&job_list)
-        .map_err(|e| Error::Custom(format!("Failed to serialize jobs: {}", e)))?;
-    tokio::fs::write(JOBS_FILE, json)
-        .await
-        .map_err(|e| Error::Custom(format!("Failed to write jobs file: {}", e)))?;
-    Ok(())
-}
+        // purge_deleted_files: hard deletes the files past the retention window
+        {
+            let mm = Arc::clone(&mm);
+            let f: JobFn = Arc::new(move || {
+                let mm = Arc::clone(&mm);
+                Box::pin(async move {
+                    purge_deleted_files(&mm)
+                        .await
+                        .map_err(|e| format!("{:?}", e))
+                })
+            });
+            m.insert("purge_deleted_files".to_string(), f);
+        }
+
+        // reembed_chunks: re-embeds the chunks of an outdated model with the served one
+        if let Some(embedder) = &embedder {
+            let mm = Arc::clone(&mm);
+            let embedder = Arc::clone(embedder);
+            let f: JobFn = Arc::new(move || {
+                let mm = Arc::clone(&mm);
+                let embedder = Arc::clone(&embedder);
+                Box::pin(async move {
+                    reembed_chunks(&mm, embedder.as_ref())
+                        .await
+                        .map_err(|e| format!("{:?}", e))
+                })
+            });
+            m.insert("reembed_chunks".to_string(), f);
+        }
+
+        // evaluate_golden_set: Recall@k of the golden queries, alerting on regressions
+        if let Some(embedder) = embedder {
+            let mm = Arc::clone(&mm);
+            let f: JobFn = Arc::new(move || {
+                let mm = Arc::clone(&mm);
+                let embedder = Arc::clone(&embedder);
+                Box::pin(async move {
+                    evaluate_golden_set(&mm, embedder.as_ref())
+                        .await
+                        .map_err(|e| format!("{:?}", e))
+                })
+            });
+            m.insert("evaluate_golden_set".to_string(), f);
+        }
+
+        // replicate_chunks: applies the chunk changes of the primary, only on replicas
+        if config::auth_config().replication_primary.is_some() {
+            let mm = Arc::clone(&mm);
+            let f: JobFn = Arc::new(move || {
+                let mm = Arc::clone(&mm);
+                Box::pin(async move { replicate_chunks(&mm).await.map_err(|e| format!("{:?}", e)) })
+            });
+            m.insert("replicate_chunks".to_string(), f);
+        }
 
-async fn load_jobs_from_file() -> Result<HashMap<Uuid, (String, String)>> {
-    match tokio::fs::read_to_string(JOBS_FILE).await {
-        Ok(content) => {
-            let job_list: Vec<JobRecord> = serde_json::from_str(&content)
-                .map_err(|e| Error::Custom(format!("Failed to deserialize jobs: {}", e)))?;
-            Ok(job_list
-                .into_iter()
-                .filter_map(|j| {
-                    Uuid::parse_str(&j.id)
-                        .ok()
-                        .map(|id| (id, (j.job_type, j.cron)))
+        // cleanup_model_cache: prunes stale snapshots and unused models from the hub cache
+        {
+            let cache_cleanup = Arc::new(cache_cleanup);
+            let f: JobFn = Arc::new(move || {
+                let cache_cleanup = Arc::clone(&cache_cleanup);
+                Box::pin(async move {
+                    match tokio::task::spawn_blocking(move || cache_cleanup.run()).await {
+                        Ok(Ok(report)) => {
+                            info!(
+                                "cleanup_model_cache reclaimed {} bytes ({} repos, {} snapshots, {} blobs removed), {} bytes left",
+                                report.reclaimed_bytes,
+                                report.removed_repos.len(),
+                                report.removed_snapshots,
+                                report.removed_blobs,
+                                report.remaining_bytes
+                            );
+                            Ok(())
+                        }
+                        Ok(Err(e)) => Err(format!("{:?}", e)),
+                        Err(e) => Err(format!("panicked: {:?}", e)),
+                    }
                 })
-                .collect())
+            });
+            m.insert("cleanup_model_cache".to_string(), f);
         }
-        Err(_) => Ok(HashMap::new()),
+
+        m
     }
 }
 
@@ -271,7 +732,7 @@ mod tests {
     use super::*;
     use candle_core::Device;
     use lib_core::_dev_utils;
-    use lib_storage::create_aws_client;
+    use lib_storage::store::create_object_store;
     use tokio::time::Duration;
     use tracing::Level;
     use tracing_subscriber::FmtSubscriber;
@@ -286,24 +747,78 @@ mod tests {
         let db = _dev_utils::init_dev().await.unwrap();
         let mm = Arc::new(ModelManager::dev(db));
         let device = Device::Cpu;
-        let s3_client = Arc::new(create_aws_client().await);
+        let storage = create_object_store(&config::auth_config().bucket)
+            .await
+            .unwrap();
 
-        let cache_job = ChronJobs::new(mm, s3_client)
+        let cache_cleanup = CacheCleanup::from_env(None, None, None);
+        let cache_job = ChronJobs::new(mm, storage, cache_cleanup, None)
             .await
             .map_err(|_| Error::ChronFails("Failed to create ChronJobs instance".to_string()))
             .unwrap();
         cache_job
-            .add_job("sync_s3_files".to_string(), "0 */10 * * * *".to_string())
+            .add_job(
+                "sync_s3_files".to_string(),
+                "0 */10 * * * *".to_string(),
+                "Europe/Berlin".to_string(),
+                ConcurrencyPolicy::Skip,
+                "tenant-a".to_string(),
+                "tenant-a".to_string(),
+            )
             .await
             .unwrap();
         cache_job
             .add_job(
                 "process_new_files".to_string(),
                 "0 */15 * * * *".to_string(),
+                DEFAULT_TIMEZONE.to_string(),
+                ConcurrencyPolicy::Skip,
+                ROOT_OWNER.to_string(),
+                DEFAULT_TENANT.to_string(),
             )
             .await
             .unwrap();
+        let unknown = cache_job
+            .add_job(
+                "unknown".to_string(),
+                "0 */15 * * * *".to_string(),
+                DEFAULT_TIMEZONE.to_string(),
+                ConcurrencyPolicy::Skip,
+                ROOT_OWNER.to_string(),
+                DEFAULT_TENANT.to_string(),
+            )
+            .await;
+        assert!(matches!(unknown, Err(Error::UnknownJobType(_))));
+        let invalid = cache_job
+            .add_job(
+                "sync_s3_files".to_string(),
+                "0 */15 * * * *".to_string(),
+                "Mars/Olympus".to_string(),
+                ConcurrencyPolicy::Skip,
+                ROOT_OWNER.to_string(),
+                DEFAULT_TENANT.to_string(),
+            )
+            .await;
+        assert!(matches!(invalid, Err(Error::InvalidSchedule(_))));
+        assert!(cache_job.job_types().contains(&"sync_s3_files"));
         cache_job.start().await.unwrap();
+        assert_eq!(
+            cache_job
+                .cache
+                .get_jobs_for(None, Some("tenant-a"))
+                .await
+                .len(),
+            1
+        );
+        assert_eq!(
+            cache_job
+                .cache
+                .get_jobs_for(Some("tenant-a"), None)
+                .await
+                .len(),
+            1
+        );
+        assert_eq!(cache_job.cache.get_jobs_for(None, None).await.len(), 2);
 
         let serialized = cache_job.cache.serializable_jobs().await;
         println!("Serialized jobs: {:?}", serialized);
@@ -320,5 +835,122 @@ mod tests {
         println!("Serialized jobs: {:?}", serialized);
         tokio::time::sleep(Duration::from_secs(30)).await;
     }
+
+    async fn builder() -> ChronJobsBuilder {
+        let db = _dev_utils::init_dev().await.unwrap();
+        let storage = create_object_store(&config::auth_config().bucket)
+            .await
+            .unwrap();
+        let mm = Arc::new(ModelManager::dev(db));
+        let cache_cleanup = CacheCleanup::from_env(None, None, None);
+        ChronJobs::builder(mm, storage, cache_cleanup, None)
+    }
+
+    #[tokio::test]
+    async fn test_register_custom_job() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = calls.clone();
+        let chron_jobs = builder()
+            .await
+            .register("count_calls", move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Ok::<(), Error>(())
+                }
+            })
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        assert!(chron_jobs.job_types().contains(&"count_calls"));
+        assert!(chron_jobs.guards.contains_key("count_calls"));
+        (chron_jobs.registry["count_calls"])().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let failing = builder()
+            .await
+            .register("failing", || async {
+                Err(Error::Custom("boom".to_string()))
+            })
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let error = (failing.registry["failing"])().await.unwrap_err();
+        assert!(error.contains("boom"));
+
+        let duplicate = builder()
+            .await
+            .register("sync_s3_files", || async { Ok::<(), Error>(()) });
+        assert!(matches!(duplicate, Err(Error::InvalidJobType(_))));
+        let invalid = builder()
+            .await
+            .register("Count Calls", || async { Ok::<(), Error>(()) });
+        assert!(matches!(invalid, Err(Error::InvalidJobType(_))));
+    }
+
+    #[tokio::test]
+    async fn test_run_job_concurrency() {
+        let db = _dev_utils::init_dev().await.unwrap();
+        let mm = ModelManager::dev(db);
+        let job_id = Uuid::new_v4();
+        CronJobMac::create_job(
+            &mm,
+            CronJobForCreate {
+                job_id,
+                job_type: "slow".to_string(),
+                cron: "0 0 * * * *".to_string(),
+                timezone: DEFAULT_TIMEZONE.to_string(),
+                concurrency: ConcurrencyPolicy::Skip.as_str().to_string(),
+                owner: ROOT_OWNER.to_string(),
+                tenant_id: DEFAULT_TENANT.to_string(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let job_fn: JobFn = {
+            let calls = calls.clone();
+            Arc::new(move || {
+                let calls = calls.clone();
+                Box::pin(async move {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    Ok(())
+                })
+            })
+        };
+        let runs = |concurrency| {
+            let guard = JobGuard::default();
+            let (mm, job_fn) = (&mm, job_fn.clone());
+            async move {
+                let overlapping = (0..3)
+                    .map(|_| run_job(mm, job_id, "slow", concurrency, &guard, job_fn.clone()));
+                futures_util::future::join_all(overlapping).await;
+            }
+        };
+
+        runs(ConcurrencyPolicy::Skip).await;
+        assert_eq!(calls.swap(0, Ordering::SeqCst), 1);
+        // One run waits for the running one, the third is skipped
+        runs(ConcurrencyPolicy::Queue).await;
+        assert_eq!(calls.swap(0, Ordering::SeqCst), 2);
+        runs(ConcurrencyPolicy::Allow).await;
+        assert_eq!(calls.swap(0, Ordering::SeqCst), 3);
+
+        let filter = lib_core::model::pagination::HistoryFilter {
+            status: Some(JobRunStatus::Skipped),
+            ..Default::default()
+        };
+        let skipped = CronJobMac::find_runs(&mm, job_id, &filter).await.unwrap();
+        assert_eq!(skipped.len(), 3);
+        assert_eq!(
+            ConcurrencyPolicy::from_column("unknown"),
+            ConcurrencyPolicy::Skip
+        );
+        CronJobMac::delete_job(&mm, job_id).await.unwrap();
+    }
 }
 // endregion: Unit Test
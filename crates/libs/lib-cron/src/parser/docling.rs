@@ -0,0 +1,95 @@
+//! docling-serve: the file is downloaded by docling through a presigned URL (`PARSER_URL`), or
+//! streamed to its multipart endpoint (`PARSER_FILE_URL`) when it cannot reach the store.
+
+use super::{DocumentParser, ParsedDocument, ParserInput, ParserKind, send_with_retry};
+use crate::error::{Error, Result};
+use async_trait::async_trait;
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::info;
+
+const TO_FORMATS: [&str; 3] = ["md", "json", "text"];
+
+#[derive(Debug, Deserialize)]
+pub struct DoclingResponse {
+    pub document: Document,
+    pub status: String,
+    pub errors: Vec<String>,
+    pub processing_time: f64,
+    pub timings: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Document {
+    pub filename: String,
+    pub md_content: String,
+    /// `DoclingDocument`, source of the page and heading of every chunk
+    pub json_content: Option<serde_json::Value>,
+    pub html_content: Option<String>,
+    pub text_content: Option<String>,
+    pub doctags_content: Option<String>,
+}
+
+pub struct Docling {
+    pub http: reqwest::Client,
+    /// `PARSER_URL`
+    pub url: String,
+    /// `PARSER_FILE_URL`
+    pub file_url: Option<String>,
+}
+
+#[async_trait]
+impl DocumentParser for Docling {
+    fn kind(&self) -> ParserKind {
+        ParserKind::Docling
+    }
+
+    async fn parse(&self, input: ParserInput<'_>) -> Result<ParsedDocument> {
+        let presigned_url = match &self.file_url {
+            Some(_) => None,
+            None => Some(input.presign().await?),
+        };
+        let presigned_url = presigned_url.as_deref();
+        let resp = send_with_retry(self.kind(), input.filename, || async move {
+            match (&self.file_url, presigned_url) {
+                (Some(file_url), _) => {
+                    let part =
+                        Part::stream(input.body().await?).file_name(input.filename.to_string());
+                    let form = TO_FORMATS
+                        .iter()
+                        .fold(Form::new(), |form, format| form.text("to_formats", *format))
+                        .part("files", part);
+                    info!("Uploading {} to parser at {}", input.filename, file_url);
+                    Ok(self.http.post(file_url).multipart(form))
+                }
+                (None, presigned_url) => {
+                    let body = json!({
+                        "options": {"to_formats": TO_FORMATS},
+                        "http_sources": [{
+                            "url": presigned_url,
+                            "filename": input.filename,
+                        }],
+                    });
+                    info!("Requesting parser at {} with body: {:?}", self.url, body);
+                    Ok(self.http.post(&self.url).json(&body))
+                }
+            }
+        })
+        .await?;
+        let document = resp
+            .json::<DoclingResponse>()
+            .await
+            .map_err(|e| Error::Custom(format!("parser json decode failed: {e}")))?
+            .document;
+
+        // Filter out image markdown like [Image](data:image/png;base64,...)
+        let image_pattern = regex::Regex::new(r"\[Image\]\(data:image/[^)]+\)").unwrap();
+        let text = document.text_content.unwrap_or_default();
+        Ok(ParsedDocument {
+            text: image_pattern.replace_all(&text, "").to_string(),
+            json_content: document.json_content,
+            segments: None,
+        })
+    }
+}
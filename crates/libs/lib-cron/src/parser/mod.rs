@@ -0,0 +1,372 @@
+//! Parsers turning a stored file into text for the chunker. The parser of a file is selected by its
+//! type through `PARSERS` (e.g. `pdf=native,docx=tika,*=docling`): the docling sidecar, Apache
+//! Tika, unstructured.io, or the in-process PDF extraction of deployments without a sidecar.
+
+pub mod docling;
+pub mod pdf;
+pub mod text;
+pub mod tika;
+pub mod unstructured;
+
+use crate::chunker::{Segment, SegmentMeta};
+use crate::config::AuthConfig;
+use crate::error::{Error, Result};
+use async_trait::async_trait;
+use lib_storage::store::ObjectStore;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::str::FromStr;
+use tokio::io::AsyncReadExt;
+use tokio::time::{Duration, sleep};
+use tokio_util::io::ReaderStream;
+
+const MAX_ATTEMPTS: usize = 3;
+const BASE_BACKOFF: Duration = Duration::from_millis(400);
+
+/// Text of a parsed file
+#[derive(Debug, Default)]
+pub struct ParsedDocument {
+    /// Plain text, paragraphs separated by blank lines, chunked when there is no structure
+    pub text: String,
+    /// `DoclingDocument` returned by docling, source of the page and heading of every chunk
+    pub json_content: Option<Value>,
+    /// Located segments of the other structured parsers
+    pub segments: Option<Vec<Segment<'static>>>,
+}
+
+/// File handed to a parser, read from the object store at the version picked for processing
+#[derive(Clone, Copy)]
+pub struct ParserInput<'a> {
+    pub filename: &'a str,
+    pub storage: &'a dyn ObjectStore,
+    pub version_id: Option<&'a str>,
+}
+
+impl ParserInput<'_> {
+    /// Stream of the file, an attempt consumes it so every attempt downloads the file again
+    pub async fn body(&self) -> Result<reqwest::Body> {
+        let reader = self.open().await?;
+        Ok(reqwest::Body::wrap_stream(ReaderStream::new(reader)))
+    }
+
+    pub async fn bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.open()
+            .await?
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(|e| Error::Custom(format!("download of {} failed: {e}", self.filename)))?;
+        Ok(bytes)
+    }
+
+    /// URL the parser downloads the file from
+    pub async fn presign(&self) -> Result<String> {
+        self.storage
+            .presign(self.filename, self.version_id, Duration::from_secs(600))
+            .await
+            .map_err(|e| Error::Custom(format!("presign url failed for {}: {e}", self.filename)))
+    }
+
+    async fn open(&self) -> Result<Box<dyn tokio::io::AsyncRead + Send + Unpin>> {
+        self.storage
+            .get(self.filename, self.version_id)
+            .await
+            .map_err(|e| Error::Custom(format!("download of {} failed: {e}", self.filename)))
+    }
+}
+
+#[async_trait]
+pub trait DocumentParser: Send + Sync {
+    fn kind(&self) -> ParserKind;
+
+    async fn parse(&self, input: ParserInput<'_>) -> Result<ParsedDocument>;
+}
+
+/// Send the request built for every attempt, failed statuses are retried with an exponential
+/// backoff. A request which cannot be sent at all is not retried.
+async fn send_with_retry<F, Fut>(
+    kind: ParserKind,
+    filename: &str,
+    mut request: F,
+) -> Result<reqwest::Response>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<reqwest::RequestBuilder>>,
+{
+    let mut attempt = 0usize;
+    loop {
+        attempt += 1;
+        let resp = request().await?.send().await.map_err(|e| {
+            Error::Custom(format!("{kind} request failed (attempt {attempt}): {e}"))
+        })?;
+        if resp.status().is_success() {
+            return Ok(resp);
+        }
+        if attempt >= MAX_ATTEMPTS {
+            return Err(Error::Custom(format!(
+                "{kind} returned status {} for {filename} after {attempt} attempts",
+                resp.status(),
+            )));
+        }
+
+        let jitter = Duration::from_millis(fastrand::u64(0..100));
+        sleep(BASE_BACKOFF * (1u32 << (attempt - 1)) + jitter).await;
+    }
+}
+
+/// Split plain text in paragraph segments, located on `page` when known
+fn text_segments(text: &str, page: Option<i32>) -> impl Iterator<Item = Segment<'static>> + '_ {
+    text.split("\n\n")
+        .filter(|paragraph| !paragraph.trim().is_empty())
+        .map(move |paragraph| Segment {
+            text: paragraph.to_string().into(),
+            meta: SegmentMeta {
+                page,
+                ..Default::default()
+            },
+        })
+}
+
+// region: Routing
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParserKind {
+    Docling,
+    Tika,
+    Unstructured,
+    /// PDF text extraction in process, see `pdf`
+    Native,
+    /// Text formats read in process, see `text`
+    Text,
+}
+
+impl std::fmt::Display for ParserKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParserKind::Docling => write!(f, "docling"),
+            ParserKind::Tika => write!(f, "tika"),
+            ParserKind::Unstructured => write!(f, "unstructured"),
+            ParserKind::Native => write!(f, "native"),
+            ParserKind::Text => write!(f, "text"),
+        }
+    }
+}
+
+impl FromStr for ParserKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "docling" => Ok(ParserKind::Docling),
+            "tika" => Ok(ParserKind::Tika),
+            "unstructured" => Ok(ParserKind::Unstructured),
+            "native" | "pdf" => Ok(ParserKind::Native),
+            "text" => Ok(ParserKind::Text),
+            other => Err(format!("unknown parser `{other}`")),
+        }
+    }
+}
+
+/// Parser per file type (`PARSERS`), comma separated `type=parser` pairs where the type `*`
+/// selects the parser of the other types. The types of `text::TEXT_TYPES` are read natively unless
+/// they are listed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParserRouting {
+    by_type: HashMap<String, ParserKind>,
+    fallback: Option<ParserKind>,
+}
+
+impl ParserRouting {
+    /// Every file type goes to `kind`
+    pub fn all(kind: ParserKind) -> Self {
+        Self {
+            by_type: HashMap::new(),
+            fallback: Some(kind),
+        }
+    }
+
+    /// Routing without `PARSERS`: docling when `PARSER_URL` is set, the native PDF parser otherwise
+    pub fn default_for(config: &AuthConfig) -> Self {
+        match config.parser {
+            Some(_) => Self::all(ParserKind::Docling),
+            None => Self {
+                by_type: HashMap::from([("pdf".to_string(), ParserKind::Native)]),
+                fallback: None,
+            },
+        }
+    }
+
+    pub fn parser_for(&self, file_type: &str) -> Option<ParserKind> {
+        let file_type = file_type.to_ascii_lowercase();
+        match self.by_type.get(&file_type) {
+            Some(kind) => Some(*kind),
+            None if text::TEXT_TYPES.contains(&file_type.as_str()) => Some(ParserKind::Text),
+            None => self.fallback,
+        }
+    }
+
+    fn kinds(&self) -> impl Iterator<Item = ParserKind> + '_ {
+        self.by_type.values().copied().chain(self.fallback)
+    }
+
+    /// Endpoints of the remote parsers the routing selects by their env variable, `None` when
+    /// it is not set
+    pub fn endpoints<'a>(&self, config: &'a AuthConfig) -> Vec<(&'static str, Option<&'a str>)> {
+        let mut endpoints = Vec::new();
+        for kind in self.kinds() {
+            let endpoint = match kind {
+                ParserKind::Docling => ("PARSER_URL", config.parser.as_deref()),
+                ParserKind::Tika => ("TIKA_URL", config.tika_url.as_deref()),
+                ParserKind::Unstructured => {
+                    ("UNSTRUCTURED_URL", config.unstructured_url.as_deref())
+                }
+                ParserKind::Native | ParserKind::Text => continue,
+            };
+            if !endpoints.contains(&endpoint) {
+                endpoints.push(endpoint);
+            }
+        }
+        endpoints
+    }
+}
+
+/// Problems of the parser endpoints selected by `PARSERS`: missing, or not answering within
+/// `timeout`. Any HTTP response counts as reachable, the parsers have no common health route.
+pub async fn check_endpoints(config: &AuthConfig, timeout: Duration) -> Vec<String> {
+    let http = &reqwest::Client::new();
+    let checks = config
+        .parsers
+        .endpoints(config)
+        .into_iter()
+        .map(|(env, url)| async move {
+            let url = match url {
+                Some(url) => url,
+                None => return Some(format!("{env} is required by the parser routing (PARSERS)")),
+            };
+            match http.get(url).timeout(timeout).send().await {
+                Ok(_) => None,
+                Err(e) => Some(format!("{env}: parser {url} is not reachable: {e}")),
+            }
+        });
+    futures_util::future::join_all(checks)
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+impl FromStr for ParserRouting {
+    type Err = String;
+
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        let mut routing = ParserRouting::default();
+        for pair in s.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (file_type, kind) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected `type=parser`, got `{pair}`"))?;
+            let kind = kind.parse()?;
+            match file_type.trim().trim_start_matches('.') {
+                "*" => routing.fallback = Some(kind),
+                file_type => {
+                    routing.by_type.insert(file_type.to_ascii_lowercase(), kind);
+                }
+            }
+        }
+        Ok(routing)
+    }
+}
+
+/// Parsers of the routing, built once per ingestion run
+pub struct Parsers {
+    routing: ParserRouting,
+    parsers: HashMap<ParserKind, Box<dyn DocumentParser>>,
+}
+
+impl Parsers {
+    /// Fails when the routing selects a parser whose endpoint is not configured
+    pub fn from_config(config: &AuthConfig, http: &reqwest::Client) -> Result<Self> {
+        let mut parsers: HashMap<ParserKind, Box<dyn DocumentParser>> = HashMap::new();
+        for kind in config.parsers.kinds().chain([ParserKind::Text]) {
+            if parsers.contains_key(&kind) {
+                continue;
+            }
+            let parser: Box<dyn DocumentParser> = match kind {
+                ParserKind::Docling => Box::new(docling::Docling {
+                    http: http.clone(),
+                    url: config
+                        .parser
+                        .clone()
+                        .ok_or(Error::MissingEnv("PARSER_URL"))?,
+                    file_url: config.parser_file.clone(),
+                }),
+                ParserKind::Tika => Box::new(tika::Tika {
+                    http: http.clone(),
+                    url: config
+                        .tika_url
+                        .clone()
+                        .ok_or(Error::MissingEnv("TIKA_URL"))?,
+                }),
+                ParserKind::Unstructured => Box::new(unstructured::Unstructured {
+                    http: http.clone(),
+                    url: config
+                        .unstructured_url
+                        .clone()
+                        .ok_or(Error::MissingEnv("UNSTRUCTURED_URL"))?,
+                    api_key: config.unstructured_api_key.clone(),
+                }),
+                ParserKind::Native => Box::new(pdf::NativePdf),
+                ParserKind::Text => Box::new(text::PlainText {
+                    csv_rows: config.csv_rows_per_chunk,
+                }),
+            };
+            parsers.insert(kind, parser);
+        }
+        Ok(Self {
+            routing: config.parsers.clone(),
+            parsers,
+        })
+    }
+
+    pub fn for_file(&self, file_type: &str) -> Result<&dyn DocumentParser> {
+        self.routing
+            .parser_for(file_type)
+            .and_then(|kind| self.parsers.get(&kind))
+            .map(Box::as_ref)
+            .ok_or_else(|| Error::Custom(format!("no parser configured for `{file_type}` files")))
+    }
+}
+
+// endregion: Routing
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parser_routing() {
+        let routing: ParserRouting = "pdf=native, .DOCX=tika,*=docling".parse().unwrap();
+        assert_eq!(routing.parser_for("pdf"), Some(ParserKind::Native));
+        assert_eq!(routing.parser_for("docx"), Some(ParserKind::Tika));
+        assert_eq!(routing.parser_for("PDF"), Some(ParserKind::Native));
+        assert_eq!(routing.parser_for("pptx"), Some(ParserKind::Docling));
+        assert_eq!(routing.parser_for("md"), Some(ParserKind::Text));
+        let routing: ParserRouting = "csv=docling".parse().unwrap();
+        assert_eq!(routing.parser_for("csv"), Some(ParserKind::Docling));
+
+        let routing: ParserRouting = "html=unstructured".parse().unwrap();
+        assert_eq!(routing.parser_for("pdf"), None);
+        assert!("pdf".parse::<ParserRouting>().is_err());
+        assert!("pdf=word".parse::<ParserRouting>().is_err());
+    }
+
+    #[test]
+    fn test_text_segments() {
+        let segments: Vec<_> = text_segments("First\n\n \n\nSecond", Some(3)).collect();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1].text, "Second");
+        assert_eq!(segments[1].meta.page, Some(3));
+    }
+}
+// endregion: Unit Test
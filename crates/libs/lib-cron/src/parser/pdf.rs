@@ -0,0 +1,38 @@
+//! PDF text extraction in process with `pdf-extract`, for deployments without a parser sidecar.
+//! Scanned pages have no text layer and yield nothing, there is no OCR.
+
+use super::{DocumentParser, ParsedDocument, ParserInput, ParserKind, text_segments};
+use crate::error::{Error, Result};
+use async_trait::async_trait;
+
+pub struct NativePdf;
+
+#[async_trait]
+impl DocumentParser for NativePdf {
+    fn kind(&self) -> ParserKind {
+        ParserKind::Native
+    }
+
+    async fn parse(&self, input: ParserInput<'_>) -> Result<ParsedDocument> {
+        let bytes = input.bytes().await?;
+        let filename = input.filename.to_string();
+        // The extraction is CPU bound, it must not hold a runtime worker
+        let pages = tokio::task::spawn_blocking(move || {
+            pdf_extract::extract_text_from_mem_by_pages(&bytes)
+                .map_err(|e| Error::Custom(format!("pdf extraction of {filename} failed: {e}")))
+        })
+        .await
+        .map_err(|e| Error::Custom(format!("pdf extraction task failed: {e}")))??;
+
+        let segments: Vec<_> = pages
+            .iter()
+            .enumerate()
+            .flat_map(|(index, page)| text_segments(page, Some(index as i32 + 1)))
+            .collect();
+        Ok(ParsedDocument {
+            text: pages.join("\n\n"),
+            json_content: None,
+            segments: Some(segments),
+        })
+    }
+}
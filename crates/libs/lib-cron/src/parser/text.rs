@@ -0,0 +1,217 @@
+//! Text formats read without a parser service: the object is downloaded, decoded and split with a
+//! chunker of its type. Markdown is split at its headings, CSV in batches of rows repeating the
+//! header line, JSON arrays by element and plain text by paragraph.
+
+use super::{DocumentParser, ParsedDocument, ParserInput, ParserKind, text_segments};
+use crate::chunker::{Segment, SegmentMeta};
+use crate::error::Result;
+use async_trait::async_trait;
+use serde_json::Value;
+
+/// File types read natively unless `PARSERS` routes them elsewhere
+pub const TEXT_TYPES: [&str; 5] = ["txt", "md", "markdown", "json", "csv"];
+
+pub struct PlainText {
+    /// Rows of a CSV segment (`CSV_ROWS_PER_CHUNK`)
+    pub csv_rows: usize,
+}
+
+#[async_trait]
+impl DocumentParser for PlainText {
+    fn kind(&self) -> ParserKind {
+        ParserKind::Text
+    }
+
+    async fn parse(&self, input: ParserInput<'_>) -> Result<ParsedDocument> {
+        let bytes = input.bytes().await?;
+        let text = String::from_utf8_lossy(&bytes);
+        let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+        let file_type = input
+            .filename
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_ascii_lowercase())
+            .unwrap_or_default();
+        let segments = match file_type.as_str() {
+            "md" | "markdown" => markdown_segments(&text),
+            "csv" => csv_segments(&text, self.csv_rows),
+            "json" => json_segments(&text),
+            _ => text_segments(&text, None).collect(),
+        };
+        Ok(ParsedDocument {
+            text,
+            json_content: None,
+            segments: Some(segments),
+        })
+    }
+}
+
+/// Paragraphs under the path of the ATX headings (`## Title`) enclosing them, fenced code blocks
+/// are kept whole
+fn markdown_segments(text: &str) -> Vec<Segment<'static>> {
+    let mut headings: Vec<(usize, String)> = Vec::new();
+    let mut segments = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut fence: Option<&str> = None;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if let Some(marker) = fence {
+            paragraph.push(line);
+            if trimmed.starts_with(marker) {
+                fence = None;
+                flush(&mut paragraph, &headings, &mut segments);
+            }
+            continue;
+        }
+        if let Some(marker) = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m)) {
+            flush(&mut paragraph, &headings, &mut segments);
+            fence = Some(marker);
+            paragraph.push(line);
+            continue;
+        }
+        if let Some((level, title)) = heading(trimmed) {
+            flush(&mut paragraph, &headings, &mut segments);
+            headings.retain(|(parent, _)| *parent < level);
+            headings.push((level, title.to_string()));
+            paragraph.push(line);
+            continue;
+        }
+        if trimmed.is_empty() {
+            flush(&mut paragraph, &headings, &mut segments);
+        } else {
+            paragraph.push(line);
+        }
+    }
+    flush(&mut paragraph, &headings, &mut segments);
+    segments
+}
+
+/// Close the paragraph as a segment under the current headings
+fn flush(
+    paragraph: &mut Vec<&str>,
+    headings: &[(usize, String)],
+    segments: &mut Vec<Segment<'static>>,
+) {
+    let content = paragraph.join("\n");
+    paragraph.clear();
+    if content.trim().is_empty() {
+        return;
+    }
+    segments.push(Segment {
+        text: content.into(),
+        meta: SegmentMeta {
+            heading_path: headings.iter().map(|(_, text)| text.clone()).collect(),
+            ..Default::default()
+        },
+    });
+}
+
+/// Level and title of an ATX heading line
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    let rest = &line[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with(' ')) {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim()))
+}
+
+/// Batches of `rows` records, each starting with the header line so a chunk stays readable alone
+fn csv_segments(text: &str, rows: usize) -> Vec<Segment<'static>> {
+    let records = csv_records(text);
+    let Some((header, records)) = records.split_first() else {
+        return Vec::new();
+    };
+    records
+        .chunks(rows.max(1))
+        .map(|batch| Segment {
+            text: format!("{header}\n{}", batch.join("\n")).into(),
+            meta: SegmentMeta::default(),
+        })
+        .collect()
+}
+
+/// Non-empty records of the CSV, a quoted field may span several lines
+fn csv_records(text: &str) -> Vec<&str> {
+    let mut records = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '\n' if !quoted => {
+                records.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    records.push(&text[start..]);
+    records.retain(|record| !record.trim().is_empty());
+    records
+}
+
+/// One segment per element of a top-level array, the whole document otherwise. Invalid JSON is
+/// read as plain text.
+fn json_segments(text: &str) -> Vec<Segment<'static>> {
+    let segment = |text: String| Segment {
+        text: text.into(),
+        meta: SegmentMeta::default(),
+    };
+    match serde_json::from_str::<Value>(text) {
+        Ok(Value::Array(elements)) => elements
+            .iter()
+            .map(|element| segment(element.to_string()))
+            .collect(),
+        Ok(value) => serde_json::to_string_pretty(&value)
+            .map(|text| vec![segment(text)])
+            .unwrap_or_default(),
+        Err(_) => text_segments(text, None).collect(),
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_segments() {
+        let text = "Intro\n\n# Guide\n\nSetup text.\n\n## Install #\n\n```sh\n# not a heading\n\nrun\n```\n\n# Next\nBody";
+        let segments = markdown_segments(text);
+        let texts: Vec<&str> = segments.iter().map(|s| s.text.as_ref()).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "Intro",
+                "# Guide",
+                "Setup text.",
+                "## Install #",
+                "```sh\n# not a heading\n\nrun\n```",
+                "# Next\nBody",
+            ]
+        );
+        assert!(segments[0].meta.heading_path.is_empty());
+        assert_eq!(segments[4].meta.heading_path, vec!["Guide", "Install"]);
+        assert_eq!(segments[5].meta.heading_path, vec!["Next"]);
+        assert_eq!(heading("#hashtag"), None);
+    }
+
+    #[test]
+    fn test_csv_segments() {
+        let text = "id,note\n1,a\n2,\"multi\nline\"\n\n3,c\n";
+        let segments = csv_segments(text, 2);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].text, "id,note\n1,a\n2,\"multi\nline\"");
+        assert_eq!(segments[1].text, "id,note\n3,c");
+        assert!(csv_segments("", 2).is_empty());
+    }
+
+    #[test]
+    fn test_json_segments() {
+        assert_eq!(json_segments(r#"[{"a":1},{"b":2}]"#).len(), 2);
+        assert_eq!(json_segments(r#"{"a":1}"#)[0].text, "{\n  \"a\": 1\n}");
+        assert_eq!(json_segments("not json\n\nat all").len(), 2);
+    }
+}
+// endregion: Unit Test
@@ -0,0 +1,45 @@
+//! Apache Tika server (`TIKA_URL`): the file is streamed to `PUT /tika`, which answers with its
+//! plain text. Tika locates nothing, the text is chunked by paragraph.
+
+use super::{DocumentParser, ParsedDocument, ParserInput, ParserKind, send_with_retry};
+use crate::error::{Error, Result};
+use async_trait::async_trait;
+use reqwest::header::{ACCEPT, CONTENT_DISPOSITION};
+
+pub struct Tika {
+    pub http: reqwest::Client,
+    pub url: String,
+}
+
+#[async_trait]
+impl DocumentParser for Tika {
+    fn kind(&self) -> ParserKind {
+        ParserKind::Tika
+    }
+
+    async fn parse(&self, input: ParserInput<'_>) -> Result<ParsedDocument> {
+        let url = &format!("{}/tika", self.url.trim_end_matches('/'));
+        let resp = send_with_retry(self.kind(), input.filename, || async move {
+            // The file name helps Tika detect the type of the content
+            let name = input.filename.rsplit('/').next().unwrap_or(input.filename);
+            Ok(self
+                .http
+                .put(url)
+                .header(ACCEPT, "text/plain")
+                .header(
+                    CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{name}\""),
+                )
+                .body(input.body().await?))
+        })
+        .await?;
+        let text = resp
+            .text()
+            .await
+            .map_err(|e| Error::Custom(format!("tika response read failed: {e}")))?;
+        Ok(ParsedDocument {
+            text,
+            ..Default::default()
+        })
+    }
+}
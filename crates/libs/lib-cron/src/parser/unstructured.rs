@@ -0,0 +1,137 @@
+//! unstructured.io partition API (`UNSTRUCTURED_URL`, key `UNSTRUCTURED_API_KEY`): the file is
+//! uploaded to `POST /general/v0/general`, which answers with its elements. Titles give the heading
+//! path of the elements following them, the page and links come from the element metadata.
+
+use super::{DocumentParser, ParsedDocument, ParserInput, ParserKind, send_with_retry};
+use crate::chunker::{Segment, SegmentMeta};
+use crate::error::{Error, Result};
+use async_trait::async_trait;
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+
+pub struct Unstructured {
+    pub http: reqwest::Client,
+    pub url: String,
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Element {
+    #[serde(rename = "type")]
+    pub element_type: String,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub metadata: ElementMetadata,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ElementMetadata {
+    pub page_number: Option<i32>,
+    /// Nesting of titles, 0 for the outermost
+    pub category_depth: Option<usize>,
+    #[serde(default)]
+    pub link_urls: Vec<String>,
+}
+
+#[async_trait]
+impl DocumentParser for Unstructured {
+    fn kind(&self) -> ParserKind {
+        ParserKind::Unstructured
+    }
+
+    async fn parse(&self, input: ParserInput<'_>) -> Result<ParsedDocument> {
+        let url = &format!("{}/general/v0/general", self.url.trim_end_matches('/'));
+        let resp = send_with_retry(self.kind(), input.filename, || async move {
+            let part = Part::stream(input.body().await?).file_name(input.filename.to_string());
+            let mut request = self
+                .http
+                .post(url)
+                .multipart(Form::new().part("files", part));
+            if let Some(api_key) = &self.api_key {
+                request = request.header("unstructured-api-key", api_key);
+            }
+            Ok(request)
+        })
+        .await?;
+        let elements = resp
+            .json::<Vec<Element>>()
+            .await
+            .map_err(|e| Error::Custom(format!("unstructured json decode failed: {e}")))?;
+
+        let segments = element_segments(elements);
+        let text = segments
+            .iter()
+            .map(|segment| segment.text.as_ref())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        Ok(ParsedDocument {
+            text,
+            json_content: None,
+            segments: Some(segments),
+        })
+    }
+}
+
+fn element_segments(elements: Vec<Element>) -> Vec<Segment<'static>> {
+    let mut headings: Vec<(usize, String)> = Vec::new();
+    let mut segments = Vec::with_capacity(elements.len());
+    for element in elements {
+        let text = element.text.trim();
+        if text.is_empty() || element.element_type == "PageBreak" {
+            continue;
+        }
+        if element.element_type == "Title" {
+            let depth = element.metadata.category_depth.unwrap_or(0);
+            headings.retain(|(parent, _)| *parent < depth);
+            headings.push((depth, text.to_string()));
+        }
+        // Relative links of the source cannot be followed from a result
+        let links = element
+            .metadata
+            .link_urls
+            .into_iter()
+            .filter(|link| link.starts_with("http://") || link.starts_with("https://"))
+            .collect();
+        segments.push(Segment {
+            text: text.to_string().into(),
+            meta: SegmentMeta {
+                page: element.metadata.page_number,
+                heading_path: headings.iter().map(|(_, text)| text.clone()).collect(),
+                links,
+            },
+        });
+    }
+    segments
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_element_segments() {
+        let elements: Vec<Element> = serde_json::from_value(serde_json::json!([
+            {"type": "Title", "text": "Report", "metadata": {"page_number": 1, "category_depth": 0}},
+            {"type": "Title", "text": "Costs", "metadata": {"page_number": 1, "category_depth": 1}},
+            {"type": "NarrativeText", "text": "Costs rose.", "metadata": {
+                "page_number": 2,
+                "link_urls": ["https://example.com/costs", "#anchor"],
+            }},
+            {"type": "PageBreak", "text": ""},
+            {"type": "Title", "text": "Outlook", "metadata": {"category_depth": 1}},
+            {"type": "NarrativeText", "text": "Stable."},
+        ]))
+        .unwrap();
+
+        let segments = element_segments(elements);
+        assert_eq!(segments.len(), 5);
+        assert_eq!(segments[2].text, "Costs rose.");
+        assert_eq!(segments[2].meta.page, Some(2));
+        assert_eq!(segments[2].meta.heading_path, vec!["Report", "Costs"]);
+        assert_eq!(segments[2].meta.links, vec!["https://example.com/costs"]);
+        assert_eq!(segments[4].meta.heading_path, vec!["Report", "Outlook"]);
+    }
+}
+// endregion: Unit Test
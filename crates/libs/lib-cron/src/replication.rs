@@ -0,0 +1,62 @@
+//! Replica side of the chunk replication: pulls the frames of the primary
+//! (`GET /api/v1/admin/replication/changes`) and applies them until it has caught up.
+
+use crate::config::auth_config;
+use crate::error::{Error, Result};
+use lib_core::database::ModelManager;
+use lib_core::model::replication::{ReplicationBatch, ReplicationMac};
+use lib_core::model::settings::SettingMac;
+use tracing::info;
+
+/// Settings key of the cursor of the last batch applied by the replica
+pub const REPLICATION_CURSOR_SETTING: &str = "replication_cursor";
+
+/// Apply the changes of the primary since the stored cursor, returns the number of changes. The
+/// cursor is stored after every batch so an interrupted run resumes where it stopped, applying a
+/// batch twice is harmless.
+pub async fn replicate_chunks(mm: &ModelManager) -> Result<u64> {
+    let config = auth_config();
+    let primary = config
+        .replication_primary
+        .as_deref()
+        .ok_or(Error::MissingEnv("REPLICATION_PRIMARY_URL"))?;
+    let url = format!(
+        "{}/api/v1/admin/replication/changes",
+        primary.trim_end_matches('/')
+    );
+    let http = reqwest::Client::new();
+
+    let mut cursor: i64 = SettingMac::get_value(mm, REPLICATION_CURSOR_SETTING)
+        .await?
+        .unwrap_or(0);
+    let mut applied = 0;
+    loop {
+        let mut request = http
+            .get(&url)
+            .query(&[("cursor", cursor), ("limit", config.replication_batch_size)]);
+        if let Some(api_key) = &config.replication_api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let frame = request
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| Error::Custom(format!("replication request failed: {e}")))?
+            .bytes()
+            .await
+            .map_err(|e| Error::Custom(format!("replication frame read failed: {e}")))?;
+
+        let batch = ReplicationBatch::decode_frame(&frame)?;
+        if batch.is_empty() {
+            break;
+        }
+        applied += (batch.chunks.len() + batch.deleted.len()) as u64;
+        cursor = ReplicationMac::apply_batch(mm, batch).await?;
+        SettingMac::set_value(mm, REPLICATION_CURSOR_SETTING, &cursor).await?;
+    }
+
+    if applied > 0 {
+        info!("replicate_chunks applied {applied} changes, cursor {cursor}");
+    }
+    Ok(applied)
+}
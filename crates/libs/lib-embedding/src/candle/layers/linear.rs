@@ -1,6 +1,29 @@
 use crate::candle::layers::cublaslt::get_cublas_lt_wrapper;
-use candle_core::{Device, Result, Tensor};
+use candle_core::quantized::{GgmlDType, QMatMul, QTensor};
+use candle_core::{Device, Module, Result, Tensor};
 use serde::Deserialize;
+use std::cell::Cell;
+
+thread_local! {
+    /// Quantization of the `Linear` layers built on this thread, see `QuantizationScope`
+    static QUANTIZATION: Cell<Option<GgmlDType>> = const { Cell::new(None) };
+}
+
+/// Quantizes the weights of every `Linear` built on this thread until it is dropped, which keeps
+/// the quantization out of the constructors of the models
+pub struct QuantizationScope(Option<GgmlDType>);
+
+impl QuantizationScope {
+    pub fn enter(quantization: Option<GgmlDType>) -> Self {
+        Self(QUANTIZATION.replace(quantization))
+    }
+}
+
+impl Drop for QuantizationScope {
+    fn drop(&mut self) {
+        QUANTIZATION.set(self.0);
+    }
+}
 
 #[derive(Debug, Deserialize, PartialEq, Clone)]
 #[serde(rename_all = "lowercase")]
@@ -26,6 +49,8 @@ impl HiddenAct {
 #[derive(Debug)]
 pub struct Linear {
     weight: Tensor,
+    /// Replaces `weight` when the layer is quantized
+    quantized: Option<QMatMul>,
     bias: Option<Tensor>,
     act: Option<HiddenAct>,
     span: tracing::Span,
@@ -34,9 +59,18 @@ pub struct Linear {
 impl Linear {
     pub fn new(weight: Tensor, bias: Option<Tensor>, act: Option<HiddenAct>) -> Self {
         let span = tracing::span!(tracing::Level::TRACE, "linear");
+        let quantized = QUANTIZATION
+            .get()
+            .and_then(|dtype| quantize(&weight, dtype));
+        // The float weights are dropped with the tensor once the layer is quantized
+        let weight = match quantized {
+            Some(_) => Tensor::zeros((), weight.dtype(), weight.device()).unwrap_or(weight),
+            None => weight,
+        };
 
         Self {
             weight,
+            quantized,
             bias,
             act,
             span,
@@ -46,6 +80,18 @@ impl Linear {
     pub fn forward(&self, x: &Tensor) -> Result<Tensor> {
         let _enter = self.span.enter();
 
+        if let Some(quantized) = &self.quantized {
+            let x = quantized.forward(&x.contiguous()?)?;
+            let x = match &self.bias {
+                None => x,
+                Some(bias) => x.broadcast_add(bias)?,
+            };
+            return match &self.act {
+                Some(act) => act.forward(&x),
+                None => Ok(x),
+            };
+        }
+
         #[allow(unused)]
         if let (Device::Cuda(_), Some(cublaslt)) = (x.device(), get_cublas_lt_wrapper()) {
             match x.dims() {
@@ -95,3 +141,17 @@ impl Linear {
         }
     }
 }
+
+/// `None` when the input features (the columns of the weight) are not a multiple of
+/// `dtype.block_size()`, the layer then stays in float
+fn quantize(weight: &Tensor, dtype: GgmlDType) -> Option<QMatMul> {
+    let (_, in_features) = weight.dims2().ok()?;
+    if in_features % dtype.block_size() != 0 {
+        tracing::debug!("Keeping a linear layer of {in_features} input features unquantized");
+        return None;
+    }
+    QTensor::quantize(weight, dtype)
+        .and_then(QMatMul::from_qtensor)
+        .inspect_err(|err| tracing::warn!("Could not quantize a linear layer: {err}"))
+        .ok()
+}
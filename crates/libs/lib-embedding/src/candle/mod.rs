@@ -4,10 +4,12 @@ mod compute_cap;
 #[cfg(feature = "cuda")]
 mod flash_attn;
 mod layers;
-mod models;
+pub(crate) mod models;
 
 use crate::core::{Batch, Embedding, Embeddings, InferenceBackend, ModelType, Predictions};
+use crate::candle::layers::QuantizationScope;
 use crate::error::{Error as BackendError, Result};
+use crate::quantization::Quantization;
 use candle_core::{DType, Device};
 use candle_nn::VarBuilder;
 use nohash_hasher::BuildNoHashHasher;
@@ -115,6 +117,7 @@ impl CandleBackend {
     pub fn new(
         model_path: &Path,
         dtype: String,
+        quantization: Option<Quantization>,
         model_type: ModelType,
         dense_paths: Option<Vec<String>>,
     ) -> Result<Self> {
@@ -210,6 +213,17 @@ impl CandleBackend {
             )))
         }?;
 
+        if let Some(quantization) = quantization {
+            if dtype != DType::F32 || matches!(device, Device::Cuda(_)) {
+                return Err(BackendError::Start(format!(
+                    "Quantization {quantization} requires float32 weights on CPU or Metal"
+                )));
+            }
+            tracing::info!("Quantizing the linear layers to {quantization}");
+        }
+        // Every `Linear` built while loading the model is quantized
+        let _quantization = QuantizationScope::enter(quantization.map(Into::into));
+
         let vb = if model_files.len() == 1 && model_files[0].extension().unwrap() == "bin" {
             VarBuilder::from_pth(&model_files[0], dtype, &device)
         } else {
@@ -0,0 +1,309 @@
+//! Offline bundle of an embedding pipeline: the ONNX graph of the transformer, its tokenizer, the
+//! pooling and the Dense modules, described by `pipeline.json`. Before the manifest is written the
+//! bundle is run through the ORT backend and compared with the candle pipeline on sample inputs,
+//! a bundle whose embeddings drift from the served ones is never produced.
+
+use crate::candle::CandleBackend;
+use crate::candle::models::{Dense, DenseConfig, DenseLayer};
+use crate::core::{Batch, Embedding, Embeddings, InferenceBackend, ModelType, Pool};
+use crate::error::{Error as BackendError, Result};
+use crate::ort::OrtBackend;
+use crate::{download_onnx, download_safetensors, resolve_dense_modules};
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use hf_hub::api::tokio::ApiRepo;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use tokenizers::Tokenizer;
+
+/// Name of the manifest describing the bundle
+pub const PIPELINE_FILE: &str = "pipeline.json";
+
+const FORMAT_VERSION: u32 = 1;
+const MODEL_FILES: [&str; 4] = [
+    "config.json",
+    "tokenizer.json",
+    "tokenizer_config.json",
+    "special_tokens_map.json",
+];
+const DENSE_WEIGHTS: [&str; 2] = ["model.safetensors", "pytorch_model.bin"];
+
+/// Agreement of the candle pipeline and the exported bundle on the sample inputs
+#[derive(Debug, Clone, Serialize)]
+pub struct Parity {
+    pub samples: usize,
+    /// Lowest cosine similarity between the two embeddings of a sample
+    pub min_cosine: f32,
+    /// Largest difference between two components of the embeddings
+    pub max_abs_diff: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PipelineManifest {
+    pub format: u32,
+    pub onnx: String,
+    pub pooling: String,
+    /// Dense modules to apply in order on the pooled embedding, relative to the bundle
+    pub dense: Vec<String>,
+    pub dimensions: usize,
+    pub parity: Parity,
+}
+
+/// Export the pipeline of the model in `model_path` to `output`. Hub models fetch their ONNX
+/// weights through `api_repo`, local models must already hold `model.onnx` (or `onnx/model.onnx`).
+/// Fails when the cosine similarity of a sample falls below `min_cosine`.
+pub async fn export_onnx(
+    model_path: &Path,
+    api_repo: Option<&ApiRepo>,
+    pool: Pool,
+    dense_paths: Vec<String>,
+    output: &Path,
+    samples: &[String],
+    min_cosine: f32,
+) -> Result<PipelineManifest> {
+    if pool == Pool::Splade {
+        return Err(BackendError::Custom(
+            "SPLADE models cannot be exported, their pooling is not supported by `ort`".to_string(),
+        ));
+    }
+    if samples.is_empty() {
+        return Err(BackendError::Custom(
+            "At least one parity sample is required".to_string(),
+        ));
+    }
+
+    if let Some(api_repo) = api_repo {
+        download_onnx(api_repo).await?;
+        if download_safetensors(api_repo).await.is_err() {
+            tracing::info!("Downloading `pytorch_model.bin`");
+            api_repo
+                .get("pytorch_model.bin")
+                .await
+                .map_err(|err| BackendError::WeightsNotFound(err.to_string()))?;
+        }
+        let _ = api_repo.get("tokenizer_config.json").await;
+        let _ = api_repo.get("special_tokens_map.json").await;
+    }
+    let dense_paths = resolve_dense_modules(api_repo, dense_paths)
+        .await?
+        .unwrap_or_default();
+
+    // ONNX graph, with its external data when the weights exceed the protobuf limit
+    let onnx_dir = [model_path.to_path_buf(), model_path.join("onnx")]
+        .into_iter()
+        .find(|dir| dir.join("model.onnx").exists())
+        .ok_or_else(|| {
+            BackendError::WeightsNotFound(
+                "Model ONNX files not found, export them first with https://huggingface.co/spaces/sentence-transformers/backend-export".to_string(),
+            )
+        })?;
+    fs::create_dir_all(output)?;
+    for name in ["model.onnx", "model.onnx_data"] {
+        let source = onnx_dir.join(name);
+        if source.exists() {
+            fs::copy(&source, output.join(name))?;
+        }
+    }
+    for name in MODEL_FILES {
+        let source = model_path.join(name);
+        if source.exists() {
+            fs::copy(&source, output.join(name))?;
+        }
+    }
+    let mut dense = Vec::with_capacity(dense_paths.len());
+    for (i, dense_path) in dense_paths.iter().enumerate() {
+        let name = format!("dense/{i}");
+        copy_dense_module(&model_path.join(dense_path), &output.join(&name))?;
+        dense.push(name);
+    }
+
+    let (parity, dimensions) = check_parity(
+        model_path,
+        pool.clone(),
+        dense_paths,
+        output,
+        &dense,
+        samples,
+    )?;
+    if parity.min_cosine < min_cosine {
+        return Err(BackendError::Custom(format!(
+            "Exported pipeline diverges from candle: cosine {:.5} < {min_cosine}, max difference {:.5}",
+            parity.min_cosine, parity.max_abs_diff
+        )));
+    }
+
+    let manifest = PipelineManifest {
+        format: FORMAT_VERSION,
+        onnx: "model.onnx".to_string(),
+        pooling: pool.to_string(),
+        dense,
+        dimensions,
+        parity,
+    };
+    fs::write(
+        output.join(PIPELINE_FILE),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    Ok(manifest)
+}
+
+fn copy_dense_module(source: &Path, target: &Path) -> Result<()> {
+    let weights = DENSE_WEIGHTS
+        .iter()
+        .find(|name| source.join(name).exists())
+        .ok_or_else(|| {
+            BackendError::WeightsNotFound(format!(
+                "Dense module `{}` has no weights",
+                source.display()
+            ))
+        })?;
+    fs::create_dir_all(target)?;
+    fs::copy(source.join("config.json"), target.join("config.json"))?;
+    fs::copy(source.join(weights), target.join(weights))?;
+    Ok(())
+}
+
+/// Embed `samples` with the candle pipeline and with the bundle, the Dense modules of the bundle
+/// are applied on the ORT output as an edge runtime would. Also returns the embedding dimensions.
+fn check_parity(
+    model_path: &Path,
+    pool: Pool,
+    dense_paths: Vec<String>,
+    output: &Path,
+    dense: &[String],
+    samples: &[String],
+) -> Result<(Parity, usize)> {
+    let mut tokenizer = Tokenizer::from_file(model_path.join("tokenizer.json"))?;
+    tokenizer.with_padding(None);
+    let encodings = tokenizer.encode_batch(samples.to_vec(), true)?;
+    let batch = || sample_batch(encodings.iter().map(|e| e.get_ids()).collect());
+
+    let candle = CandleBackend::new(
+        model_path,
+        "float32".to_string(),
+        None,
+        ModelType::Embedding(pool.clone()),
+        Some(dense_paths),
+    )?;
+    let expected = pooled(candle.embed(batch())?, samples.len())?;
+
+    let ort = OrtBackend::new(output, "float32".to_string(), ModelType::Embedding(pool))?;
+    let pooled_ort = pooled(ort.embed(batch())?, samples.len())?;
+    let hidden_size = pooled_ort[0].len();
+    let mut actual = Tensor::from_vec(
+        pooled_ort.concat(),
+        (samples.len(), hidden_size),
+        &Device::Cpu,
+    )?;
+    for dense in dense {
+        actual = load_dense(&output.join(dense))?.forward(&actual)?;
+    }
+    let actual: Vec<Vec<f32>> = actual.to_vec2()?;
+
+    Ok((compare(&expected, &actual), expected[0].len()))
+}
+
+fn sample_batch(ids: Vec<&[u32]>) -> Batch {
+    let mut batch = Batch {
+        input_ids: Vec::new(),
+        token_type_ids: Vec::new(),
+        position_ids: Vec::new(),
+        cumulative_seq_lengths: vec![0],
+        max_length: 0,
+        pooled_indices: (0..ids.len() as u32).collect(),
+        raw_indices: vec![],
+    };
+    for ids in ids {
+        batch.input_ids.extend_from_slice(ids);
+        batch.token_type_ids.extend(vec![0; ids.len()]);
+        batch.position_ids.extend(0..ids.len() as u32);
+        batch
+            .cumulative_seq_lengths
+            .push(batch.input_ids.len() as u32);
+        batch.max_length = batch.max_length.max(ids.len() as u32);
+    }
+    batch
+}
+
+fn pooled(mut embeddings: Embeddings, len: usize) -> Result<Vec<Vec<f32>>> {
+    (0..len)
+        .map(|i| match embeddings.remove(&i) {
+            Some(Embedding::Pooled(embedding)) => Ok(embedding),
+            _ => Err(BackendError::Inference(format!(
+                "No pooled embedding for sample {i}"
+            ))),
+        })
+        .collect()
+}
+
+fn load_dense(dir: &Path) -> Result<Dense> {
+    let config: DenseConfig = serde_json::from_str(&fs::read_to_string(dir.join("config.json"))?)?;
+    let safetensors = dir.join(DENSE_WEIGHTS[0]);
+    let vb = if safetensors.exists() {
+        unsafe { VarBuilder::from_mmaped_safetensors(&[safetensors], DType::F32, &Device::Cpu) }?
+    } else {
+        VarBuilder::from_pth(dir.join(DENSE_WEIGHTS[1]), DType::F32, &Device::Cpu)?
+    };
+    Ok(Dense::load(vb, &config)?)
+}
+
+fn compare(expected: &[Vec<f32>], actual: &[Vec<f32>]) -> Parity {
+    let mut parity = Parity {
+        samples: expected.len(),
+        min_cosine: if expected.len() == actual.len() {
+            1.0
+        } else {
+            0.0
+        },
+        max_abs_diff: 0.0,
+    };
+    for (expected, actual) in expected.iter().zip(actual) {
+        if expected.len() != actual.len() {
+            parity.min_cosine = 0.0;
+            continue;
+        }
+        let dot: f32 = expected.iter().zip(actual).map(|(a, b)| a * b).sum();
+        let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let cosine = dot / (norm(expected) * norm(actual)).max(f32::EPSILON);
+        parity.min_cosine = parity.min_cosine.min(cosine);
+        for (a, b) in expected.iter().zip(actual) {
+            parity.max_abs_diff = parity.max_abs_diff.max((a - b).abs());
+        }
+    }
+    parity
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let expected = vec![vec![1.0, 0.0], vec![0.6, 0.8]];
+        let parity = compare(&expected, &expected);
+        assert_eq!(parity.samples, 2);
+        assert!((parity.min_cosine - 1.0).abs() < 1e-6);
+        assert_eq!(parity.max_abs_diff, 0.0);
+
+        let parity = compare(&expected, &[vec![1.0, 0.0], vec![0.8, 0.6]]);
+        assert!((parity.min_cosine - 0.96).abs() < 1e-6);
+        assert!((parity.max_abs_diff - 0.2).abs() < 1e-6);
+
+        // A missing or truncated embedding never passes
+        assert_eq!(compare(&expected, &[vec![1.0, 0.0]]).min_cosine, 0.0);
+        assert_eq!(compare(&expected, &[vec![1.0], vec![0.6]]).min_cosine, 0.0);
+    }
+
+    #[test]
+    fn test_sample_batch() {
+        let batch = sample_batch(vec![&[101, 7, 102], &[101, 102]]);
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.cumulative_seq_lengths, vec![0, 3, 5]);
+        assert_eq!(batch.position_ids, vec![0, 1, 2, 0, 1]);
+        assert_eq!(batch.max_length, 3);
+        assert_eq!(batch.pooled_indices, vec![0, 1]);
+    }
+}
+// endregion: Unit Test
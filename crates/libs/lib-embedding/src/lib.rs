@@ -19,7 +19,10 @@ pub mod candle;
 pub mod core;
 mod dtype;
 pub mod error;
+#[cfg(feature = "candle")]
+pub mod export;
 mod ort;
+mod quantization;
 
 use crate::core::{InferenceBackend as CoreBackend, Predictions};
 use hf_hub::api::tokio::ApiRepo;
@@ -38,6 +41,7 @@ use tracing::{Span, instrument};
 pub use crate::core::{Batch, Embedding, Embeddings, ModelType, Pool};
 pub use crate::dtype::DType;
 pub use crate::error::{Error as BackendError, Result};
+pub use crate::quantization::Quantization;
 
 fn powers_of_two(max_value: usize) -> Vec<usize> {
     let mut result = Vec::new();
@@ -76,6 +80,40 @@ fn is_hpu() -> bool {
     }
 }
 
+/// Backend actually running the model, `ort` falls back to `candle` when it fails to start
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    Ort,
+    Candle,
+}
+
+impl std::fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendKind::Ort => write!(f, "ort"),
+            BackendKind::Candle => write!(f, "candle"),
+        }
+    }
+}
+
+/// Cargo features this crate was compiled with
+pub fn enabled_features() -> Vec<&'static str> {
+    [
+        ("candle", cfg!(feature = "candle")),
+        ("ort", cfg!(feature = "ort")),
+        ("cuda", cfg!(feature = "cuda")),
+        ("mkl", cfg!(feature = "mkl")),
+        ("accelerate", cfg!(feature = "accelerate")),
+        ("metal", cfg!(feature = "metal")),
+        ("flash-attn", cfg!(feature = "flash-attn")),
+        ("flash-attn-v1", cfg!(feature = "flash-attn-v1")),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect()
+}
+
 #[derive(Debug, Clone)]
 pub struct InferenceBackend {
     /// Channel to communicate with the background thread
@@ -86,6 +124,7 @@ pub struct InferenceBackend {
     pub padded_model: bool,
     pub max_batch_size: Option<usize>,
     pub model_type: ModelType,
+    pub kind: BackendKind,
 }
 
 impl InferenceBackend {
@@ -94,20 +133,22 @@ impl InferenceBackend {
         model_path: PathBuf,
         api_repo: Option<ApiRepo>,
         dtype: DType,
+        quantization: Option<Quantization>,
         model_type: ModelType,
-        dense_path: Option<String>,
+        dense_paths: Vec<String>,
         uds_path: String,
         otlp_endpoint: Option<String>,
         otlp_service_name: String,
     ) -> Result<Self> {
         let (backend_sender, backend_receiver) = mpsc::channel(8);
 
-        let backend = init_backend(
+        let (backend, kind) = init_backend(
             model_path,
             api_repo,
             dtype,
+            quantization,
             model_type.clone(),
-            dense_path,
+            dense_paths,
             uds_path,
             otlp_endpoint,
             otlp_service_name,
@@ -127,6 +168,7 @@ impl InferenceBackend {
             padded_model,
             max_batch_size,
             model_type,
+            kind,
         })
     }
 
@@ -366,15 +408,17 @@ async fn init_backend(
     model_path: PathBuf,
     api_repo: Option<ApiRepo>,
     dtype: DType,
+    quantization: Option<Quantization>,
     model_type: ModelType,
-    dense_path: Option<String>,
+    dense_paths: Vec<String>,
     uds_path: String,
     otlp_endpoint: Option<String>,
     otlp_service_name: String,
-) -> Result<Box<dyn CoreBackend + Send>> {
+) -> Result<(Box<dyn CoreBackend + Send>, BackendKind)> {
     let mut backend_start_failed = false;
 
-    if cfg!(feature = "ort") {
+    // Quantization is applied by the candle backend when it loads the weights
+    if cfg!(feature = "ort") && quantization.is_none() {
         #[cfg(feature = "ort")]
         {
             if let Some(api_repo) = api_repo.as_ref() {
@@ -408,7 +452,7 @@ async fn init_backend(
 
             let backend = ort::OrtBackend::new(&model_path, dtype.to_string(), model_type.clone());
             match backend {
-                Ok(b) => return Ok(Box::new(b)),
+                Ok(b) => return Ok((Box::new(b), BackendKind::Ort)),
                 Err(err) => {
                     tracing::error!("Could not start ORT backend: {err}");
                     backend_start_failed = true;
@@ -438,25 +482,23 @@ async fn init_backend(
     if cfg!(feature = "candle") {
         #[cfg(feature = "candle")]
         {
-            let dense_paths = if let Some(api_repo) = api_repo.as_ref() {
-                let start = std::time::Instant::now();
-                let dense_paths = download_dense_modules(api_repo, dense_path)
-                    .await
-                    .map_err(|err| BackendError::WeightsNotFound(err.to_string()))?;
-                tracing::info!("Dense modules downloaded in {:?}", start.elapsed());
-                Some(dense_paths)
-            } else {
-                None
-            };
+            let start = std::time::Instant::now();
+            let dense_paths = resolve_dense_modules(api_repo.as_ref(), dense_paths)
+                .await
+                .map_err(|err| BackendError::WeightsNotFound(err.to_string()))?;
+            if dense_paths.is_some() {
+                tracing::info!("Dense modules resolved in {:?}", start.elapsed());
+            }
 
             let backend = candle::CandleBackend::new(
                 &model_path,
                 dtype.to_string(),
+                quantization,
                 model_type.clone(),
                 dense_paths,
             );
             match backend {
-                Ok(b) => return Ok(Box::new(b)),
+                Ok(b) => return Ok((Box::new(b), BackendKind::Candle)),
                 Err(err) => {
                     tracing::error!("Could not start Candle backend: {err}");
                     backend_start_failed = true;
@@ -530,7 +572,8 @@ enum BackendCommand {
     ),
 }
 
-async fn download_safetensors(api: &ApiRepo) -> Result<Vec<PathBuf>> {
+/// Weights of the model, a single `model.safetensors` or the shards of its index
+pub async fn download_safetensors(api: &ApiRepo) -> Result<Vec<PathBuf>> {
     // Single file
     tracing::info!("Downloading `model.safetensors`");
     match api.get("model.safetensors").await {
@@ -569,7 +612,7 @@ async fn download_safetensors(api: &ApiRepo) -> Result<Vec<PathBuf>> {
     Ok(safetensors_files)
 }
 
-#[cfg(feature = "ort")]
+#[cfg(any(feature = "ort", feature = "candle"))]
 async fn download_onnx(api: &ApiRepo) -> Result<Vec<PathBuf>> {
     let mut model_files: Vec<PathBuf> = Vec::new();
 
@@ -734,6 +777,43 @@ pub async fn download_dense_modules(
     }
 }
 
+/// Dense modules to load, in order. `dense_paths` may mix paths inside the model repository
+/// (e.g. `2_Dense`) with absolute directories of adapters kept outside the Hub. A single repository
+/// path only overrides the module of `modules.json`, see `download_dense_modules`.
+#[cfg(feature = "candle")]
+pub async fn resolve_dense_modules(
+    api: Option<&ApiRepo>,
+    dense_paths: Vec<String>,
+) -> Result<Option<Vec<String>>> {
+    let is_external = |path: &String| std::path::Path::new(path).is_absolute();
+    if dense_paths.len() <= 1 && !dense_paths.iter().any(is_external) {
+        return match api {
+            Some(api) => Ok(Some(
+                download_dense_modules(api, dense_paths.into_iter().next()).await?,
+            )),
+            None => Ok(None),
+        };
+    }
+
+    for dense_path in &dense_paths {
+        if is_external(dense_path) {
+            if !std::path::Path::new(dense_path).join("config.json").exists() {
+                return Err(BackendError::WeightsNotFound(format!(
+                    "Dense adapter `{dense_path}` has no `config.json`"
+                )));
+            }
+        } else {
+            let api = api.ok_or_else(|| {
+                BackendError::WeightsNotFound(format!(
+                    "Dense module `{dense_path}` can only be downloaded for Hub models"
+                ))
+            })?;
+            download_dense_module(api, dense_path).await?;
+        }
+    }
+    Ok(Some(dense_paths))
+}
+
 #[cfg(feature = "candle")]
 async fn download_dense_module(api: &ApiRepo, dense_path: &str) -> Result<PathBuf> {
     // Download `config.json` for the Dense module
@@ -0,0 +1,59 @@
+use std::{fmt, str::FromStr};
+
+/// Quantization of the weights of the linear layers, applied by the candle backend when the
+/// float32 safetensors weights are loaded. Embeddings, norms and activations stay in float32.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Quantization {
+    /// 8 bit weights in blocks of 32, close to float32 quality at a quarter of the memory
+    Q8_0,
+    /// 4 bit weights in blocks of 32, smallest and fastest, with a measurable quality loss
+    Q4_0,
+}
+
+impl fmt::Display for Quantization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Quantization::Q8_0 => write!(f, "q8_0"),
+            Quantization::Q4_0 => write!(f, "q4_0"),
+        }
+    }
+}
+
+impl FromStr for Quantization {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "q8_0" | "int8" => Ok(Quantization::Q8_0),
+            "q4_0" | "int4" => Ok(Quantization::Q4_0),
+            _ => Err(format!(
+                "Unknown quantization: {s}, expected `q8_0` or `q4_0`"
+            )),
+        }
+    }
+}
+
+impl From<Quantization> for candle_core::quantized::GgmlDType {
+    fn from(quantization: Quantization) -> Self {
+        match quantization {
+            Quantization::Q8_0 => Self::Q8_0,
+            Quantization::Q4_0 => Self::Q4_0,
+        }
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantization_from_str() {
+        assert_eq!("q8_0".parse(), Ok(Quantization::Q8_0));
+        assert_eq!("INT8".parse(), Ok(Quantization::Q8_0));
+        assert_eq!("q4_0".parse(), Ok(Quantization::Q4_0));
+        assert_eq!(Quantization::Q8_0.to_string(), "q8_0");
+        assert!("q2_k".parse::<Quantization>().is_err());
+    }
+}
+// endregion: Unit Test
@@ -1,4 +1,5 @@
 use lib_utils::envs::get_env;
+use std::str::FromStr;
 use std::sync::OnceLock;
 use tracing::error;
 
@@ -9,24 +10,114 @@ pub fn config() -> &'static Config {
             Ok(cfg) => cfg,
             Err(e) => {
                 error!("Failed while loading configuration - Cause: {e:?}");
-                Config::default() // <- fallback (requires implementing Default)
+                Config::default() // <- fallback to the default credential chain
             }
         }
     })
 }
 
+/// AWS settings of the clients, every one is optional: the region and the credentials come from
+/// the default chain (env, profile, web identity of IRSA, instance or task role) when unset
+#[derive(Debug, Default)]
 pub struct Config {
-    pub aws_region: String,
-    pub aws_access_key: String,
-    pub aws_access_key_id: String,
+    /// `AM_REGION`
+    pub aws_region: Option<String>,
+    /// Static keys overriding the chain, `AM_ACCESS_KEY` and `AM_ACCESS_KEY_ID` set together
+    pub aws_access_key: Option<String>,
+    pub aws_access_key_id: Option<String>,
+    /// Role assumed with the credentials above (`AM_ROLE_ARN`), e.g. in another account
+    pub aws_role_arn: Option<String>,
+    /// External id required by the trust policy of the role (`AM_EXTERNAL_ID`)
+    pub aws_external_id: Option<String>,
+    /// Session name of the assumed role (`AM_ROLE_SESSION_NAME`)
+    pub aws_role_session_name: String,
 }
 
 impl Config {
-    fn load_from_env() -> lib_utils::error::Result<Config> {
+    pub fn load_from_env() -> lib_utils::error::Result<Config> {
+        let aws_access_key = get_env("AM_ACCESS_KEY").ok();
+        let aws_access_key_id = get_env("AM_ACCESS_KEY_ID").ok();
+        match (&aws_access_key, &aws_access_key_id) {
+            (Some(_), None) => return Err(lib_utils::error::Error::MissingEnv("AM_ACCESS_KEY_ID")),
+            (None, Some(_)) => return Err(lib_utils::error::Error::MissingEnv("AM_ACCESS_KEY")),
+            _ => {}
+        }
         Ok(Config {
-            aws_region: get_env("AM_REGION")?,
-            aws_access_key: get_env("AM_ACCESS_KEY")?,
-            aws_access_key_id: get_env("AM_ACCESS_KEY_ID")?,
+            aws_region: get_env("AM_REGION").ok(),
+            aws_access_key,
+            aws_access_key_id,
+            aws_role_arn: get_env("AM_ROLE_ARN").ok(),
+            aws_external_id: get_env("AM_EXTERNAL_ID").ok(),
+            aws_role_session_name: get_env("AM_ROLE_SESSION_NAME")
+                .unwrap_or_else(|_| "embedding-server".to_string()),
         })
     }
 }
+
+/// Provider of the object store of the ingest pipeline (`STORAGE_BACKEND`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    S3,
+    Gcs,
+    Azure,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "s3" => Ok(StorageBackend::S3),
+            "gcs" => Ok(StorageBackend::Gcs),
+            "azure" => Ok(StorageBackend::Azure),
+            other => Err(format!("Unknown storage backend `{other}`")),
+        }
+    }
+}
+
+/// Defaults to S3
+pub fn storage_backend() -> StorageBackend {
+    get_env("STORAGE_BACKEND").unwrap_or(StorageBackend::S3)
+}
+
+/// Server-side encryption of the objects written to S3 (`S3_SSE`), for buckets whose policy
+/// mandates encryption at rest
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Encryption {
+    #[default]
+    None,
+    /// SSE-S3, keys managed by S3 (`S3_SSE=aes256`)
+    S3,
+    /// SSE-KMS (`S3_SSE=kms`) with the customer key `S3_SSE_KMS_KEY_ID`, the AWS managed key when
+    /// unset. `S3_SSE_BUCKET_KEY=true` enables S3 Bucket Keys to reduce the KMS requests.
+    Kms {
+        key_id: Option<String>,
+        bucket_key: bool,
+    },
+}
+
+impl Encryption {
+    pub fn load_from_env() -> lib_utils::error::Result<Encryption> {
+        let mode: String = get_env("S3_SSE").unwrap_or_else(|_| "none".to_string());
+        match mode.to_ascii_lowercase().as_str() {
+            "none" => Ok(Encryption::None),
+            "aes256" => Ok(Encryption::S3),
+            "kms" | "aws:kms" => Ok(Encryption::Kms {
+                key_id: get_env("S3_SSE_KMS_KEY_ID").ok(),
+                bucket_key: get_env("S3_SSE_BUCKET_KEY").unwrap_or(false),
+            }),
+            _ => Err(lib_utils::error::Error::WrongFormat("S3_SSE")),
+        }
+    }
+}
+
+pub fn encryption() -> &'static Encryption {
+    static INSTANCE: OnceLock<Encryption> = OnceLock::new();
+    INSTANCE.get_or_init(|| match Encryption::load_from_env() {
+        Ok(encryption) => encryption,
+        Err(e) => {
+            error!("Failed while loading the S3 encryption - Cause: {e:?}");
+            Encryption::None
+        }
+    })
+}
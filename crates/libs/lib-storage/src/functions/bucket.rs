@@ -1,6 +1,227 @@
 use crate::error::{Error, Result};
+use crate::functions::file::{PROCESSING_STATUS_TAG, ProcessingStatus};
 use aws_sdk_s3::Client;
-use aws_sdk_s3::types::{BucketLocationConstraint, CreateBucketConfiguration};
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::types::{
+    BucketLifecycleConfiguration, BucketLocationConstraint, CreateBucketConfiguration, Event,
+    ExpirationStatus, FilterRule, FilterRuleName, LambdaFunctionConfiguration, LifecycleExpiration,
+    LifecycleRuleAndOperator, LifecycleRuleFilter, NotificationConfiguration,
+    NotificationConfigurationFilter, QueueConfiguration, S3KeyFilter, Tag, TopicConfiguration,
+    Transition, TransitionStorageClass,
+};
+
+/// Lifecycle rule of the objects under `prefix`, see `set_lifecycle_rules`
+#[derive(Debug, Clone)]
+pub struct LifecycleRule {
+    pub id: String,
+    pub prefix: Option<String>,
+    /// Only the objects with this processing status tag
+    pub status: Option<ProcessingStatus>,
+    /// Days after creation an object moves to a storage class
+    pub transitions: Vec<(i32, StorageClass)>,
+    /// Days after creation an object is deleted
+    pub expiration_days: Option<i32>,
+}
+
+impl LifecycleRule {
+    /// Delete the originals `days` after their upload once they were processed, their chunks
+    /// stay searchable
+    pub fn expire_processed(prefix: Option<&str>, days: i32) -> Self {
+        Self {
+            id: "expire-processed-originals".to_string(),
+            prefix: prefix.map(String::from),
+            status: Some(ProcessingStatus::Processed),
+            transitions: Vec::new(),
+            expiration_days: Some(days),
+        }
+    }
+
+    /// Move the objects to a cheaper storage class `days` after their upload
+    pub fn transition(prefix: Option<&str>, days: i32, class: StorageClass) -> Self {
+        Self {
+            id: format!("transition-{}", class.as_str().to_lowercase()),
+            prefix: prefix.map(String::from),
+            status: None,
+            transitions: vec![(days, class)],
+            expiration_days: None,
+        }
+    }
+
+    fn to_s3(&self) -> Result<aws_sdk_s3::types::LifecycleRule> {
+        let tag = match self.status {
+            Some(status) => Some(
+                Tag::builder()
+                    .key(PROCESSING_STATUS_TAG)
+                    .value(status.as_str())
+                    .build()
+                    .map_err(|e| Error::ProcessFail(format!("Invalid lifecycle tag: {e}")))?,
+            ),
+            None => None,
+        };
+        // A prefix and a tag are combined with an `And` filter
+        let filter = match (&self.prefix, tag) {
+            (Some(prefix), Some(tag)) => LifecycleRuleFilter::builder()
+                .and(
+                    LifecycleRuleAndOperator::builder()
+                        .prefix(prefix)
+                        .tags(tag)
+                        .build(),
+                )
+                .build(),
+            (prefix, tag) => LifecycleRuleFilter::builder()
+                .set_prefix(Some(prefix.clone().unwrap_or_default()))
+                .set_tag(tag)
+                .build(),
+        };
+        let transitions = self
+            .transitions
+            .iter()
+            .map(|(days, class)| {
+                Transition::builder()
+                    .days(*days)
+                    .storage_class(TransitionStorageClass::from(class.as_str()))
+                    .build()
+            })
+            .collect();
+        aws_sdk_s3::types::LifecycleRule::builder()
+            .id(&self.id)
+            .status(ExpirationStatus::Enabled)
+            .filter(filter)
+            .set_transitions(Some(transitions))
+            .set_expiration(
+                self.expiration_days
+                    .map(|days| LifecycleExpiration::builder().days(days).build()),
+            )
+            .build()
+            .map_err(|e| Error::ProcessFail(format!("Invalid lifecycle rule {}: {e}", self.id)))
+    }
+}
+
+/// Storage classes objects transition to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageClass {
+    StandardIa,
+    GlacierInstantRetrieval,
+    Glacier,
+    DeepArchive,
+}
+
+impl StorageClass {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StorageClass::StandardIa => "STANDARD_IA",
+            StorageClass::GlacierInstantRetrieval => "GLACIER_IR",
+            StorageClass::Glacier => "GLACIER",
+            StorageClass::DeepArchive => "DEEP_ARCHIVE",
+        }
+    }
+}
+
+/// Replace the lifecycle configuration of the bucket with `rules`, no rule removes it
+pub async fn set_lifecycle_rules(
+    client: &Client,
+    bucket: &str,
+    rules: &[LifecycleRule],
+) -> Result<()> {
+    if rules.is_empty() {
+        client
+            .delete_bucket_lifecycle()
+            .bucket(bucket)
+            .send()
+            .await
+            .map_err(|err| Error::ProcessFail(format!("Failed to remove the lifecycle: {err}")))?;
+        return Ok(());
+    }
+    let rules = rules
+        .iter()
+        .map(LifecycleRule::to_s3)
+        .collect::<Result<Vec<_>>>()?;
+    let configuration = BucketLifecycleConfiguration::builder()
+        .set_rules(Some(rules))
+        .build()
+        .map_err(|e| Error::ProcessFail(format!("Invalid lifecycle configuration: {e}")))?;
+    client
+        .put_bucket_lifecycle_configuration()
+        .bucket(bucket)
+        .lifecycle_configuration(configuration)
+        .send()
+        .await
+        .map_err(|err| Error::ProcessFail(format!("Failed to set the lifecycle: {err}")))?;
+
+    Ok(())
+}
+
+/// Destination of the bucket events, by ARN
+#[derive(Debug, Clone)]
+pub enum NotificationTarget {
+    Queue(String),
+    Topic(String),
+    Lambda(String),
+}
+
+/// Send the object created and removed events of the keys under `prefix` to `target`, e.g. an
+/// SQS queue of an ingest worker. Replaces the notification configuration of the bucket.
+pub async fn set_bucket_notifications(
+    client: &Client,
+    bucket: &str,
+    target: &NotificationTarget,
+    prefix: Option<&str>,
+) -> Result<()> {
+    let filter = prefix.map(|prefix| {
+        let rule = FilterRule::builder()
+            .name(FilterRuleName::Prefix)
+            .value(prefix)
+            .build();
+        NotificationConfigurationFilter::builder()
+            .key(S3KeyFilter::builder().filter_rules(rule).build())
+            .build()
+    });
+    let events = vec![Event::S3ObjectCreated, Event::S3ObjectRemoved];
+    let invalid = |e: aws_sdk_s3::error::BuildError| {
+        Error::ProcessFail(format!("Invalid notification target: {e}"))
+    };
+    let configuration = match target {
+        NotificationTarget::Queue(arn) => NotificationConfiguration::builder()
+            .queue_configurations(
+                QueueConfiguration::builder()
+                    .queue_arn(arn)
+                    .set_events(Some(events))
+                    .set_filter(filter)
+                    .build()
+                    .map_err(invalid)?,
+            )
+            .build(),
+        NotificationTarget::Topic(arn) => NotificationConfiguration::builder()
+            .topic_configurations(
+                TopicConfiguration::builder()
+                    .topic_arn(arn)
+                    .set_events(Some(events))
+                    .set_filter(filter)
+                    .build()
+                    .map_err(invalid)?,
+            )
+            .build(),
+        NotificationTarget::Lambda(arn) => NotificationConfiguration::builder()
+            .lambda_function_configurations(
+                LambdaFunctionConfiguration::builder()
+                    .lambda_function_arn(arn)
+                    .set_events(Some(events))
+                    .set_filter(filter)
+                    .build()
+                    .map_err(invalid)?,
+            )
+            .build(),
+    };
+    client
+        .put_bucket_notification_configuration()
+        .bucket(bucket)
+        .notification_configuration(configuration)
+        .send()
+        .await
+        .map_err(|err| Error::ProcessFail(format!("Failed to set the notifications: {err}")))?;
+
+    Ok(())
+}
 
 pub async fn create_s3_bucket(client: &Client, bucket: &str) -> Result<()> {
     let region = client
@@ -32,6 +253,18 @@ pub async fn create_s3_bucket(client: &Client, bucket: &str) -> Result<()> {
     Ok(())
 }
 
+/// Whether the bucket exists and the credentials of `client` can access it, a cheap request for
+/// startup checks
+pub async fn check_bucket(client: &Client, bucket: &str) -> Result<()> {
+    client
+        .head_bucket()
+        .bucket(bucket)
+        .send()
+        .await
+        .map_err(|err| Error::AWSClientError(format!("{}", DisplayErrorContext(&err))))?;
+    Ok(())
+}
+
 pub async fn get_total_bucket_size(client: &Client, bucket: &str) -> Result<u64> {
     let mut total_size: u64 = 0;
     let mut continuation_token: Option<String> = None;
@@ -78,6 +311,27 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_lifecycle_rule_to_s3() {
+        let rule = LifecycleRule::expire_processed(Some("acme/"), 30)
+            .to_s3()
+            .unwrap();
+        assert_eq!(rule.expiration().and_then(|e| e.days()), Some(30));
+        let and = rule.filter().and_then(|filter| filter.and()).unwrap();
+        assert_eq!(and.prefix(), Some("acme/"));
+        assert_eq!(and.tags()[0].value(), "processed");
+
+        let rule = LifecycleRule::transition(None, 90, StorageClass::Glacier)
+            .to_s3()
+            .unwrap();
+        assert_eq!(rule.id(), Some("transition-glacier"));
+        assert_eq!(
+            rule.transitions()[0].storage_class(),
+            Some(&TransitionStorageClass::Glacier)
+        );
+        assert!(rule.expiration().is_none());
+    }
+
     #[tokio::test]
     async fn test_create_s3_bucket() -> Result<()> {
         let client = create_aws_client().await;
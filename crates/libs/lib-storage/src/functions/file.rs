@@ -1,20 +1,56 @@
+use crate::config::Encryption;
 use crate::error::{Error, Result};
+use crate::store::{ObjectMeta, ObjectVersion};
+use aws_sdk_s3::Client;
+use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output;
 use aws_sdk_s3::presigning::PresigningConfig;
 use aws_sdk_s3::primitives::ByteStream;
-use aws_sdk_s3::Client;
+use aws_sdk_s3::types::{ServerSideEncryption, Tag, Tagging};
+use chrono::DateTime;
+use futures::stream::{self, Stream, TryStreamExt};
+use serde::Serialize;
 use std::time::Duration;
+use tokio::io::AsyncRead;
+
+/// Presigned PUT, the uploader must send `headers` with the body (encryption headers required by
+/// the bucket policy are part of the signature)
+#[derive(Debug, Clone, Serialize)]
+pub struct PresignedUpload {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+}
+
+/// `x-amz-server-side-encryption`, `...-aws-kms-key-id` and `...-bucket-key-enabled` of a write
+fn sse_params(
+    encryption: &Encryption,
+) -> (Option<ServerSideEncryption>, Option<String>, Option<bool>) {
+    match encryption {
+        Encryption::None => (None, None, None),
+        Encryption::S3 => (Some(ServerSideEncryption::Aes256), None, None),
+        Encryption::Kms { key_id, bucket_key } => (
+            Some(ServerSideEncryption::AwsKms),
+            key_id.clone(),
+            bucket_key.then_some(true),
+        ),
+    }
+}
 
 pub async fn upload_file(
     client: &Client,
     bucket: &str,
     key: &str,
     data: Vec<u8>,
+    encryption: &Encryption,
 ) -> Result<String> {
+    let (sse, kms_key_id, bucket_key) = sse_params(encryption);
     client
         .put_object()
         .bucket(bucket)
         .key(key)
         .body(ByteStream::from(data))
+        .set_server_side_encryption(sse)
+        .set_ssekms_key_id(kms_key_id)
+        .set_bucket_key_enabled(bucket_key)
         .send()
         .await
         .map_err(|_| Error::ProcessFail("Failed to upload file".into()))?;
@@ -22,29 +58,266 @@ pub async fn upload_file(
     Ok(key.to_string())
 }
 
-pub async fn list_files_in_bucket(
+pub async fn download_file(client: &Client, bucket: &str, key: &str) -> Result<Vec<u8>> {
+    let resp = client
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|_| Error::ErrorDownloadingFiles)?;
+
+    let data = resp
+        .body
+        .collect()
+        .await
+        .map_err(|_| Error::ErrorDownloadingFiles)?;
+
+    Ok(data.into_bytes().to_vec())
+}
+
+/// Stream the object, pinned to `version_id` when given, instead of buffering it in memory
+pub async fn download_file_stream(
     client: &Client,
     bucket: &str,
-    prefix: Option<&str>,
-) -> Result<Vec<String>> {
-    let mut keys = Vec::new();
+    key: &str,
+    version_id: Option<&str>,
+) -> Result<impl AsyncRead + Send + Unpin + 'static> {
+    let resp = client
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .set_version_id(version_id.map(String::from))
+        .send()
+        .await
+        .map_err(|_| Error::ErrorDownloadingFiles)?;
+
+    Ok(resp.body.into_async_read())
+}
+
+/// Download `length` bytes of the object starting at `offset` (HTTP range request)
+pub async fn download_range(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    offset: u64,
+    length: u64,
+) -> Result<Vec<u8>> {
+    if length == 0 {
+        return Ok(Vec::new());
+    }
 
     let resp = client
-        .list_objects_v2()
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .range(format!("bytes={}-{}", offset, offset + length - 1))
+        .send()
+        .await
+        .map_err(|_| Error::ErrorDownloadingFiles)?;
+
+    let data = resp
+        .body
+        .collect()
+        .await
+        .map_err(|_| Error::ErrorDownloadingFiles)?;
+
+    Ok(data.into_bytes().to_vec())
+}
+
+/// Object tag recording where the ingest pipeline is with the object, lifecycle rules can select
+/// the processed originals by it
+pub const PROCESSING_STATUS_TAG: &str = "processing-status";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessingStatus {
+    Processed,
+    Failed,
+}
+
+impl ProcessingStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ProcessingStatus::Processed => "processed",
+            ProcessingStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Set the processing status tag of the object, its other tags are kept
+pub async fn tag_processing_status(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    status: ProcessingStatus,
+) -> Result<()> {
+    let current = client
+        .get_object_tagging()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| Error::ProcessFail(format!("Failed to get the tags of {key}: {e}")))?;
+    let status_tag = Tag::builder()
+        .key(PROCESSING_STATUS_TAG)
+        .value(status.as_str())
+        .build()
+        .map_err(|e| Error::ProcessFail(format!("Invalid tag: {e}")))?;
+    let tags = current
+        .tag_set()
+        .iter()
+        .filter(|tag| tag.key() != PROCESSING_STATUS_TAG)
+        .cloned()
+        .chain([status_tag])
+        .collect();
+    let tagging = Tagging::builder()
+        .set_tag_set(Some(tags))
+        .build()
+        .map_err(|e| Error::ProcessFail(format!("Invalid tags: {e}")))?;
+    client
+        .put_object_tagging()
         .bucket(bucket)
-        .set_prefix(prefix.map(String::from))
+        .key(key)
+        .tagging(tagging)
         .send()
         .await
-        .map_err(|_| Error::ProcessFail("Failed to list files".into()))?;
+        .map_err(|e| Error::ProcessFail(format!("Failed to tag {key}: {e}")))?;
+
+    Ok(())
+}
 
-    let data = resp.clone().contents.unwrap_or([].into());
-    for obj in data {
-        if let Some(key) = obj.key() {
-            keys.push(key.to_string());
+/// What `list_objects_stream` lists
+#[derive(Debug, Clone)]
+pub struct ListOptions {
+    /// Only the keys starting with it, e.g. `acme/`
+    pub prefix: Option<String>,
+    /// Every key under `prefix`. Otherwise only the objects directly under it, deeper keys are
+    /// grouped into one `ListEntry::Prefix` per "directory" (`/` delimiter).
+    pub recursive: bool,
+    /// Size and LastModified of every object, `None` otherwise
+    pub with_metadata: bool,
+}
+
+impl Default for ListOptions {
+    fn default() -> Self {
+        Self {
+            prefix: None,
+            recursive: true,
+            with_metadata: false,
         }
     }
+}
+
+#[derive(Debug, Clone)]
+pub enum ListEntry {
+    Object(ObjectMeta),
+    /// Common prefix of the keys below the listed level, only when not `recursive`
+    Prefix(String),
+}
+
+/// Objects of a page of `ListObjectsV2`, then its common prefixes
+fn page_entries(page: &ListObjectsV2Output, with_metadata: bool) -> Vec<ListEntry> {
+    let objects = page.contents().iter().filter_map(|obj| {
+        let key = obj.key()?;
+        Some(ListEntry::Object(ObjectMeta {
+            key: key.to_string(),
+            etag: obj.e_tag().map(String::from),
+            size: obj
+                .size()
+                .filter(|_| with_metadata)
+                .and_then(|size| u64::try_from(size).ok()),
+            last_modified: obj
+                .last_modified()
+                .filter(|_| with_metadata)
+                .and_then(|date| DateTime::from_timestamp(date.secs(), date.subsec_nanos())),
+        }))
+    });
+    let prefixes = page
+        .common_prefixes()
+        .iter()
+        .filter_map(|prefix| prefix.prefix())
+        .map(|prefix| ListEntry::Prefix(prefix.to_string()));
+    objects.chain(prefixes).collect()
+}
 
-    Ok(keys)
+/// Every entry of the listing, page by page: the next page is only requested once the entries
+/// of the previous one are consumed
+pub fn list_objects_stream(
+    client: &Client,
+    bucket: &str,
+    options: ListOptions,
+) -> impl Stream<Item = Result<ListEntry>> + Send + 'static {
+    let (client, bucket) = (client.clone(), bucket.to_string());
+    // `None` once the last page was listed, the continuation token of the next page otherwise
+    let pages = stream::try_unfold(Some(None::<String>), move |next| {
+        let (client, bucket, options) = (client.clone(), bucket.clone(), options.clone());
+        async move {
+            let Some(continuation_token) = next else {
+                return Ok(None);
+            };
+            let page = client
+                .list_objects_v2()
+                .bucket(bucket)
+                .set_prefix(options.prefix)
+                .set_delimiter((!options.recursive).then(|| "/".to_string()))
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .map_err(|e| Error::ProcessFail(format!("Failed to list files: {e}")))?;
+            let entries = page_entries(&page, options.with_metadata);
+            let next = page
+                .next_continuation_token()
+                .map(|token| Some(token.to_string()));
+            Ok(Some((entries, next)))
+        }
+    });
+    pages
+        .map_ok(|entries| stream::iter(entries.into_iter().map(Ok)))
+        .try_flatten()
+}
+
+/// Every key under `prefix`
+pub async fn list_files_in_bucket(
+    client: &Client,
+    bucket: &str,
+    prefix: Option<&str>,
+) -> Result<Vec<String>> {
+    let options = ListOptions {
+        prefix: prefix.map(String::from),
+        ..Default::default()
+    };
+    list_objects_stream(client, bucket, options)
+        .try_filter_map(|entry| async move {
+            Ok(match entry {
+                ListEntry::Object(object) => Some(object.key),
+                ListEntry::Prefix(_) => None,
+            })
+        })
+        .try_collect()
+        .await
+}
+
+/// Every object under `prefix` with its ETag, size and LastModified, the ETag changes whenever an
+/// object is overwritten
+pub async fn list_objects_in_bucket(
+    client: &Client,
+    bucket: &str,
+    prefix: Option<&str>,
+) -> Result<Vec<ObjectMeta>> {
+    let options = ListOptions {
+        prefix: prefix.map(String::from),
+        with_metadata: true,
+        ..Default::default()
+    };
+    list_objects_stream(client, bucket, options)
+        .try_filter_map(|entry| async move {
+            Ok(match entry {
+                ListEntry::Object(object) => Some(object),
+                ListEntry::Prefix(_) => None,
+            })
+        })
+        .try_collect()
+        .await
 }
 
 pub async fn delete_file(client: &Client, bucket: &str, key: &str) -> Result<()> {
@@ -59,10 +332,32 @@ pub async fn delete_file(client: &Client, bucket: &str, key: &str) -> Result<()>
     Ok(())
 }
 
+pub async fn head_object_version(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<ObjectVersion> {
+    let resp = client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|_| Error::ProcessFail("Failed to get object metadata".into()))?;
+
+    Ok(ObjectVersion {
+        etag: resp.e_tag().map(String::from),
+        version_id: resp.version_id().map(String::from),
+    })
+}
+
+/// Presigned GET of `key`, pinned to `version_id` when given so that an overwrite after signing
+/// does not change what is downloaded
 pub async fn generate_presigned_url(
     client: &Client,
     bucket: &str,
     key: &str,
+    version_id: Option<&str>,
     expiration_in_seconds: i64,
 ) -> Result<String> {
     let presigning_config =
@@ -73,6 +368,7 @@ pub async fn generate_presigned_url(
         .get_object()
         .bucket(bucket)
         .key(key)
+        .set_version_id(version_id.map(String::from))
         .presigned(presigning_config)
         .await
         .map_err(|_| Error::ProcessFail("Failed to generate presigned URL".into()))?;
@@ -80,18 +376,56 @@ pub async fn generate_presigned_url(
     Ok(presigned_url.uri().to_string())
 }
 
+/// Presigned PUT of `key` encrypted with `encryption`
+pub async fn generate_presigned_upload_url(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    expiration_in_seconds: i64,
+    encryption: &Encryption,
+) -> Result<PresignedUpload> {
+    let presigning_config =
+        PresigningConfig::expires_in(Duration::from_secs(expiration_in_seconds as u64))
+            .map_err(|_| Error::ErrorSigningUrl)?;
+
+    let (sse, kms_key_id, bucket_key) = sse_params(encryption);
+    let presigned = client
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .set_server_side_encryption(sse)
+        .set_ssekms_key_id(kms_key_id)
+        .set_bucket_key_enabled(bucket_key)
+        .presigned(presigning_config)
+        .await
+        .map_err(|_| Error::ErrorCreatingUploadUrl)?;
+
+    Ok(PresignedUpload {
+        url: presigned.uri().to_string(),
+        headers: presigned
+            .headers()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+    })
+}
+
 pub async fn rename_file(
     client: &Client,
     bucket: &str,
     old_key: &str,
     new_key: &str,
+    encryption: &Encryption,
 ) -> Result<()> {
-    // Copy the object to the new key
+    // Copy the object to the new key, the copy is encrypted on its own
+    let (sse, kms_key_id, bucket_key) = sse_params(encryption);
     client
         .copy_object()
         .copy_source(format!("{}/{}", bucket, old_key))
         .bucket(bucket)
         .key(new_key)
+        .set_server_side_encryption(sse)
+        .set_ssekms_key_id(kms_key_id)
+        .set_bucket_key_enabled(bucket_key)
         .send()
         .await
         .map_err(|_| Error::ProcessFail("Failed to copy file".into()))?;
@@ -100,4 +434,46 @@ pub async fn rename_file(
     delete_file(client, bucket, old_key).await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::primitives::DateTime as S3DateTime;
+    use aws_sdk_s3::types::{CommonPrefix, Object};
+
+    #[test]
+    fn test_page_entries() {
+        let page = ListObjectsV2Output::builder()
+            .contents(
+                Object::builder()
+                    .key("acme/report.pdf")
+                    .e_tag("\"v1\"")
+                    .size(42)
+                    .last_modified(S3DateTime::from_secs(1_700_000_000))
+                    .build(),
+            )
+            .common_prefixes(CommonPrefix::builder().prefix("acme/2024/").build())
+            .build();
+
+        let entries = page_entries(&page, true);
+        assert_eq!(entries.len(), 2);
+        let ListEntry::Object(object) = &entries[0] else {
+            panic!("expected an object, got {:?}", entries[0]);
+        };
+        assert_eq!(object.key, "acme/report.pdf");
+        assert_eq!(object.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(object.size, Some(42));
+        assert!(object.last_modified.is_some());
+        assert!(matches!(&entries[1], ListEntry::Prefix(prefix) if prefix == "acme/2024/"));
+
+        // The ETag is always listed, the metadata only on request
+        let ListEntry::Object(object) = &page_entries(&page, false)[0] else {
+            panic!("expected an object");
+        };
+        assert!(object.etag.is_some());
+        assert_eq!((object.size, object.last_modified), (None, None));
+        assert!(ListOptions::default().recursive);
+    }
+}
+// endregion: Unit Test
@@ -1,13 +1,16 @@
 pub mod config;
 pub mod error;
 pub mod functions;
+pub mod secrets;
+pub mod store;
 
 use crate::config::config;
 use aws_config::meta::region::RegionProviderChain;
-use aws_credential_types::provider::ProvideCredentials;
+use aws_config::sts::AssumeRoleProvider;
+use aws_credential_types::provider::{ProvideCredentials, SharedCredentialsProvider};
 use aws_sdk_s3::{
-    config::{BehaviorVersion, Credentials, Region},
     Client,
+    config::{BehaviorVersion, Credentials, Region},
 };
 
 #[derive(Debug)]
@@ -17,11 +20,12 @@ struct StaticCredentials {
 }
 
 impl StaticCredentials {
-    pub fn new() -> Self {
-        Self {
-            access_key_id: config().aws_access_key_id.to_string(),
-            secret_access_key: config().aws_access_key.to_string(),
-        }
+    /// `AM_ACCESS_KEY_ID` and `AM_ACCESS_KEY` when both are set
+    pub fn from_config() -> Option<Self> {
+        Some(Self {
+            access_key_id: config().aws_access_key_id.clone()?,
+            secret_access_key: config().aws_access_key.clone()?,
+        })
     }
 
     async fn load_credentials(&self) -> aws_credential_types::provider::Result {
@@ -45,15 +49,33 @@ impl ProvideCredentials for StaticCredentials {
     }
 }
 
+/// S3 client with the credentials of the default chain (env, profile, web identity of IRSA,
+/// instance or task role), the static keys of the config when set, then the role of
+/// `AM_ROLE_ARN` assumed with them
 pub async fn create_aws_client() -> Client {
+    let config = config();
     let region_provider =
-        RegionProviderChain::first_try(Region::new(config().aws_region.to_string()));
-    let cred = StaticCredentials::new();
-    let shared_config = aws_config::defaults(BehaviorVersion::v2025_01_17())
-        .region(region_provider)
-        .credentials_provider(cred)
-        .load()
-        .await;
+        RegionProviderChain::first_try(config.aws_region.clone().map(Region::new))
+            .or_default_provider();
+    let mut loader = aws_config::defaults(BehaviorVersion::v2025_01_17()).region(region_provider);
+    if let Some(cred) = StaticCredentials::from_config() {
+        loader = loader.credentials_provider(cred);
+    }
+    let mut shared_config = loader.load().await;
+
+    if let Some(role_arn) = &config.aws_role_arn {
+        let mut role = AssumeRoleProvider::builder(role_arn)
+            .session_name(&config.aws_role_session_name)
+            .configure(&shared_config);
+        if let Some(external_id) = &config.aws_external_id {
+            role = role.external_id(external_id);
+        }
+        let provider = SharedCredentialsProvider::new(role.build().await);
+        shared_config = shared_config
+            .into_builder()
+            .credentials_provider(provider)
+            .build();
+    }
 
     Client::new(&shared_config)
-}
\ No newline at end of file
+}
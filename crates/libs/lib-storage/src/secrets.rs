@@ -0,0 +1,171 @@
+//! Settings referencing a secret instead of holding it: `secretsmanager://<secret id>` (AWS
+//! Secrets Manager) or `ssm://<parameter name>` (SSM Parameter Store, decrypted). `#<key>` picks
+//! a key of a JSON secret, e.g. `secretsmanager://prod/db#url`.
+//!
+//! The secrets are fetched with the default AWS credential chain (instance or task role,
+//! `AWS_*` env), not `AM_ACCESS_KEY_ID`/`AM_ACCESS_KEY` which can be secrets themselves.
+
+use crate::error::{Error, Result};
+use aws_config::BehaviorVersion;
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::config::Region;
+use aws_sdk_s3::error::DisplayErrorContext;
+
+const SECRETS_MANAGER_SCHEME: &str = "secretsmanager://";
+const SSM_SCHEME: &str = "ssm://";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretSource {
+    SecretsManager,
+    Ssm,
+}
+
+/// Secret referenced by a setting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretRef {
+    pub source: SecretSource,
+    /// Secret id (name or ARN) or parameter name
+    pub name: String,
+    /// Key of a JSON secret, the whole secret otherwise
+    pub key: Option<String>,
+}
+
+impl SecretRef {
+    /// `None` when `value` is not a secret reference
+    pub fn parse(value: &str) -> Option<Self> {
+        let (source, reference) = if let Some(rest) = value.strip_prefix(SECRETS_MANAGER_SCHEME) {
+            (SecretSource::SecretsManager, rest)
+        } else if let Some(rest) = value.strip_prefix(SSM_SCHEME) {
+            (SecretSource::Ssm, rest)
+        } else {
+            return None;
+        };
+        let (name, key) = match reference.split_once('#') {
+            Some((name, key)) => (name, Some(key.to_string())),
+            None => (reference, None),
+        };
+        Some(Self {
+            source,
+            name: name.to_string(),
+            key,
+        })
+    }
+}
+
+/// Env variables whose value is a secret reference
+pub fn env_references() -> Vec<(String, SecretRef)> {
+    let mut references: Vec<_> = std::env::vars()
+        .filter_map(|(env, value)| Some((env, SecretRef::parse(&value)?)))
+        .collect();
+    references.sort_by(|a, b| a.0.cmp(&b.0));
+    references
+}
+
+/// Clients of Secrets Manager and SSM
+#[derive(Debug, Clone)]
+pub struct SecretResolver {
+    secrets_manager: aws_sdk_secretsmanager::Client,
+    ssm: aws_sdk_ssm::Client,
+}
+
+impl SecretResolver {
+    /// In the region of `AM_REGION` when it is a plain value, of the default chain otherwise
+    pub async fn new() -> Self {
+        let region = std::env::var("AM_REGION")
+            .ok()
+            .filter(|region| SecretRef::parse(region).is_none())
+            .map(Region::new);
+        let shared_config = aws_config::defaults(BehaviorVersion::v2025_01_17())
+            .region(RegionProviderChain::first_try(region).or_default_provider())
+            .load()
+            .await;
+        Self {
+            secrets_manager: aws_sdk_secretsmanager::Client::new(&shared_config),
+            ssm: aws_sdk_ssm::Client::new(&shared_config),
+        }
+    }
+
+    pub async fn resolve(&self, reference: &SecretRef) -> Result<String> {
+        let secret = match reference.source {
+            SecretSource::SecretsManager => {
+                self.secrets_manager
+                    .get_secret_value()
+                    .secret_id(&reference.name)
+                    .send()
+                    .await
+                    .map_err(|err| {
+                        Error::AWSClientError(format!(
+                            "Failed to get secret {}: {}",
+                            reference.name,
+                            DisplayErrorContext(&err)
+                        ))
+                    })?
+                    .secret_string
+            }
+            SecretSource::Ssm => self
+                .ssm
+                .get_parameter()
+                .name(&reference.name)
+                .with_decryption(true)
+                .send()
+                .await
+                .map_err(|err| {
+                    Error::AWSClientError(format!(
+                        "Failed to get parameter {}: {}",
+                        reference.name,
+                        DisplayErrorContext(&err)
+                    ))
+                })?
+                .parameter
+                .and_then(|parameter| parameter.value),
+        };
+        let secret = secret.ok_or_else(|| {
+            Error::Custom(format!("Secret {} has no string value", reference.name))
+        })?;
+        match &reference.key {
+            Some(key) => json_key(&secret, key).ok_or_else(|| {
+                Error::Custom(format!("Secret {} has no key {key}", reference.name))
+            }),
+            None => Ok(secret),
+        }
+    }
+}
+
+/// String (or number) value of `key` in a JSON object
+fn json_key(secret: &str, key: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(secret).ok()?;
+    match value.get(key)? {
+        serde_json::Value::String(value) => Some(value.clone()),
+        serde_json::Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_ref() {
+        assert_eq!(
+            SecretRef::parse("secretsmanager://prod/db#url"),
+            Some(SecretRef {
+                source: SecretSource::SecretsManager,
+                name: "prod/db".to_string(),
+                key: Some("url".to_string()),
+            })
+        );
+        let ssm = SecretRef::parse("ssm:///embedding/api-key").unwrap();
+        assert_eq!(ssm.source, SecretSource::Ssm);
+        assert_eq!(ssm.name, "/embedding/api-key");
+        assert_eq!(ssm.key, None);
+        assert_eq!(SecretRef::parse("postgres://localhost/db"), None);
+
+        let secret = r#"{"username": "app", "port": 5432}"#;
+        assert_eq!(json_key(secret, "username").as_deref(), Some("app"));
+        assert_eq!(json_key(secret, "port").as_deref(), Some("5432"));
+        assert_eq!(json_key(secret, "password"), None);
+    }
+}
+// endregion: Unit Test
@@ -0,0 +1,148 @@
+//! Google Cloud Storage and Azure Blob backends, through the `object_store` crate. Credentials are
+//! read from the environment: `GOOGLE_SERVICE_ACCOUNT` (or `GOOGLE_APPLICATION_CREDENTIALS`) for
+//! GCS, `AZURE_STORAGE_ACCOUNT_NAME` and `AZURE_STORAGE_ACCOUNT_KEY` for Azure.
+
+use crate::error::{Error, Result};
+use crate::store::{ObjectMeta, ObjectStore, ObjectVersion};
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use http::Method;
+use object_store::azure::{MicrosoftAzure, MicrosoftAzureBuilder};
+use object_store::gcp::{GoogleCloudStorage, GoogleCloudStorageBuilder};
+use object_store::path::Path;
+use object_store::signer::Signer;
+use object_store::{GetOptions, PutPayload};
+use std::time::Duration;
+use tokio::io::AsyncRead;
+use tokio_util::io::StreamReader;
+
+pub struct CloudStore<T> {
+    store: T,
+    /// Scheme of the object URLs, `gs` or `az`
+    scheme: &'static str,
+    bucket: String,
+}
+
+impl CloudStore<GoogleCloudStorage> {
+    pub fn gcs(bucket: &str) -> Result<Self> {
+        let store = GoogleCloudStorageBuilder::from_env()
+            .with_bucket_name(bucket)
+            .build()
+            .map_err(|e| Error::Custom(format!("Failed to create GCS client: {e}")))?;
+        Ok(Self {
+            store,
+            scheme: "gs",
+            bucket: bucket.to_string(),
+        })
+    }
+}
+
+impl CloudStore<MicrosoftAzure> {
+    /// `container` of the storage account
+    pub fn azure(container: &str) -> Result<Self> {
+        let store = MicrosoftAzureBuilder::from_env()
+            .with_container_name(container)
+            .build()
+            .map_err(|e| Error::Custom(format!("Failed to create Azure client: {e}")))?;
+        Ok(Self {
+            store,
+            scheme: "az",
+            bucket: container.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl<T> ObjectStore for CloudStore<T>
+where
+    T: object_store::ObjectStore + Signer,
+{
+    fn url(&self, key: &str) -> String {
+        format!("{}://{}/{}", self.scheme, self.bucket, key)
+    }
+
+    async fn list(&self, prefix: Option<&str>) -> Result<Vec<ObjectMeta>> {
+        let prefix = prefix.map(Path::from);
+        self.store
+            .list(prefix.as_ref())
+            .map_ok(|meta| ObjectMeta {
+                key: meta.location.to_string(),
+                etag: meta.e_tag,
+                size: Some(meta.size),
+                last_modified: Some(meta.last_modified),
+            })
+            .try_collect()
+            .await
+            .map_err(|e| Error::ProcessFail(format!("Failed to list files: {e}")))
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectVersion> {
+        let meta = self
+            .store
+            .head(&Path::from(key))
+            .await
+            .map_err(|e| Error::ProcessFail(format!("Failed to get object metadata: {e}")))?;
+        Ok(ObjectVersion {
+            etag: meta.e_tag,
+            version_id: meta.version,
+        })
+    }
+
+    async fn get(
+        &self,
+        key: &str,
+        version_id: Option<&str>,
+    ) -> Result<Box<dyn AsyncRead + Send + Unpin>> {
+        let options = GetOptions {
+            version: version_id.map(String::from),
+            ..Default::default()
+        };
+        let result = self
+            .store
+            .get_opts(&Path::from(key), options)
+            .await
+            .map_err(|_| Error::ErrorDownloadingFiles)?;
+        let stream = result.into_stream().map_err(std::io::Error::other);
+        Ok(Box::new(StreamReader::new(stream)))
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        self.store
+            .put(&Path::from(key), PutPayload::from(data))
+            .await
+            .map_err(|_| Error::ProcessFail("Failed to upload file".into()))?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.store
+            .delete(&Path::from(key))
+            .await
+            .map_err(|_| Error::ProcessFail("Failed to delete file".into()))
+    }
+
+    /// Lists the first page of the bucket, there is no bucket metadata request in `object_store`
+    async fn check(&self) -> Result<()> {
+        self.store
+            .list(None)
+            .try_next()
+            .await
+            .map_err(|e| Error::ProcessFail(format!("Failed to access {}: {e}", self.bucket)))?;
+        Ok(())
+    }
+
+    /// Signed URLs of GCS and Azure always serve the latest version, `version_id` is ignored
+    async fn presign(
+        &self,
+        key: &str,
+        _version_id: Option<&str>,
+        expires_in: Duration,
+    ) -> Result<String> {
+        let url = self
+            .store
+            .signed_url(Method::GET, &Path::from(key), expires_in)
+            .await
+            .map_err(|_| Error::ErrorSigningUrl)?;
+        Ok(url.to_string())
+    }
+}
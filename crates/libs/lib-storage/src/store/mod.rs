@@ -0,0 +1,84 @@
+//! Object storage of the ingest pipeline, independent of the cloud provider. The backend is
+//! selected by `STORAGE_BACKEND`: `s3` (default), `gcs` or `azure`.
+
+pub mod cloud;
+pub mod s3;
+
+use crate::config::{StorageBackend, storage_backend};
+use crate::create_aws_client;
+use crate::error::Result;
+use crate::functions::file::ProcessingStatus;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncRead;
+
+/// Object listed in a bucket
+#[derive(Debug, Clone)]
+pub struct ObjectMeta {
+    pub key: String,
+    pub etag: Option<String>,
+    /// Bytes of the object
+    pub size: Option<u64>,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// Current version of an object, `version_id` is `None` when the bucket is not versioned
+#[derive(Debug, Clone)]
+pub struct ObjectVersion {
+    pub etag: Option<String>,
+    pub version_id: Option<String>,
+}
+
+/// Bucket (or container) of a storage backend, keys are relative to it
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    /// URL of the object used to cite it, e.g. `s3://bucket/key`
+    fn url(&self, key: &str) -> String;
+
+    /// Every object under `prefix`
+    async fn list(&self, prefix: Option<&str>) -> Result<Vec<ObjectMeta>>;
+
+    async fn head(&self, key: &str) -> Result<ObjectVersion>;
+
+    /// Stream the object, pinned to `version_id` when given
+    async fn get(
+        &self,
+        key: &str,
+        version_id: Option<&str>,
+    ) -> Result<Box<dyn AsyncRead + Send + Unpin>>;
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()>;
+
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    /// URL downloading the object without credentials until it expires
+    async fn presign(
+        &self,
+        key: &str,
+        version_id: Option<&str>,
+        expires_in: Duration,
+    ) -> Result<String>;
+
+    /// Reach the bucket with the credentials of the store, checked at startup
+    async fn check(&self) -> Result<()>;
+
+    /// Tag the object with its processing status, a no-op on backends without object tags
+    async fn tag_processing_status(&self, _key: &str, _status: ProcessingStatus) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Store of the configured backend for `bucket`
+pub async fn create_object_store(bucket: &str) -> Result<Arc<dyn ObjectStore>> {
+    let store: Arc<dyn ObjectStore> = match storage_backend() {
+        StorageBackend::S3 => Arc::new(s3::S3Store::new(
+            Arc::new(create_aws_client().await),
+            bucket,
+        )),
+        StorageBackend::Gcs => Arc::new(cloud::CloudStore::gcs(bucket)?),
+        StorageBackend::Azure => Arc::new(cloud::CloudStore::azure(bucket)?),
+    };
+    Ok(store)
+}
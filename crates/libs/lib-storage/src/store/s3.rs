@@ -0,0 +1,85 @@
+use crate::config::encryption;
+use crate::error::Result;
+use crate::functions::bucket::check_bucket;
+use crate::functions::file::{
+    ProcessingStatus, delete_file, download_file_stream, generate_presigned_url,
+    head_object_version, list_objects_in_bucket, tag_processing_status, upload_file,
+};
+use crate::store::{ObjectMeta, ObjectStore, ObjectVersion};
+use async_trait::async_trait;
+use aws_sdk_s3::Client;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncRead;
+
+/// S3 bucket, also used for S3 compatible stores (MinIO, R2)
+pub struct S3Store {
+    client: Arc<Client>,
+    bucket: String,
+}
+
+impl S3Store {
+    pub fn new(client: Arc<Client>, bucket: &str) -> Self {
+        Self {
+            client,
+            bucket: bucket.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectStore for S3Store {
+    fn url(&self, key: &str) -> String {
+        format!("s3://{}/{}", self.bucket, key)
+    }
+
+    async fn list(&self, prefix: Option<&str>) -> Result<Vec<ObjectMeta>> {
+        list_objects_in_bucket(&self.client, &self.bucket, prefix).await
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectVersion> {
+        head_object_version(&self.client, &self.bucket, key).await
+    }
+
+    async fn get(
+        &self,
+        key: &str,
+        version_id: Option<&str>,
+    ) -> Result<Box<dyn AsyncRead + Send + Unpin>> {
+        let reader = download_file_stream(&self.client, &self.bucket, key, version_id).await?;
+        Ok(Box::new(reader))
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        upload_file(&self.client, &self.bucket, key, data, encryption()).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        delete_file(&self.client, &self.bucket, key).await
+    }
+
+    async fn presign(
+        &self,
+        key: &str,
+        version_id: Option<&str>,
+        expires_in: Duration,
+    ) -> Result<String> {
+        generate_presigned_url(
+            &self.client,
+            &self.bucket,
+            key,
+            version_id,
+            expires_in.as_secs() as i64,
+        )
+        .await
+    }
+
+    async fn check(&self) -> Result<()> {
+        check_bucket(&self.client, &self.bucket).await
+    }
+
+    async fn tag_processing_status(&self, key: &str, status: ProcessingStatus) -> Result<()> {
+        tag_processing_status(&self.client, &self.bucket, key, status).await
+    }
+}
@@ -5,6 +5,11 @@ pub fn b64u_encode(data: impl AsRef<[u8]>) -> String {
     general_purpose::URL_SAFE_NO_PAD.encode(data)
 }
 
+/// Standard alphabet with padding, as expected by the OpenAI clients decoding embeddings
+pub fn b64_encode(data: impl AsRef<[u8]>) -> String {
+    general_purpose::STANDARD.encode(data)
+}
+
 pub fn b64u_decode(data: &str) -> Result<Vec<u8>> {
     general_purpose::URL_SAFE_NO_PAD
         .decode(data)
@@ -30,6 +35,12 @@ mod tests {
         assert_eq!(encoded, "AAECAwQFBgcICQ");
     }
 
+    #[test]
+    fn test_b64_encode() {
+        assert_eq!(b64_encode([0xfb, 0xff]), "+/8=");
+        assert_eq!(b64u_encode([0xfb, 0xff]), "-_8");
+    }
+
     #[test]
     fn test_b64u_decode() {
         let data = "AAECAwQFBgcICQ";
@@ -0,0 +1,39 @@
+use crate::error::{Error, Result};
+
+/// Default zstd level, a good trade-off between ratio and speed for markdown text
+pub const ZSTD_LEVEL: i32 = 3;
+
+pub fn zstd_compress(data: impl AsRef<[u8]>) -> Result<Vec<u8>> {
+    zstd::stream::encode_all(data.as_ref(), ZSTD_LEVEL).map_err(|_| Error::FailToCompress)
+}
+
+pub fn zstd_decompress(data: impl AsRef<[u8]>) -> Result<Vec<u8>> {
+    zstd::stream::decode_all(data.as_ref()).map_err(|_| Error::FailToDecompress)
+}
+
+pub fn zstd_decompress_to_string(data: impl AsRef<[u8]>) -> Result<String> {
+    zstd_decompress(data).and_then(|v| String::from_utf8(v).map_err(|_| Error::FailToDecompress))
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zstd_roundtrip() {
+        let text = "# Title\n\nSome markdown content. ".repeat(64);
+        let compressed = zstd_compress(&text).unwrap();
+        assert!(compressed.len() < text.len());
+        let decompressed = zstd_decompress_to_string(&compressed).unwrap();
+        assert_eq!(decompressed, text);
+    }
+
+    #[test]
+    fn test_zstd_decompress_invalid() {
+        let res = zstd_decompress(b"not zstd");
+        assert!(res.is_err());
+    }
+}
+
+// endregion: Unit Test
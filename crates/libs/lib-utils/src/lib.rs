@@ -1,4 +1,5 @@
 pub mod base64;
+pub mod compression;
 pub mod envs;
 pub mod error;
 pub mod time;
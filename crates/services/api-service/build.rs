@@ -0,0 +1,43 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Git sha of the build, reported by `/version` and the startup banner
+    if let Ok(output) = Command::new("git").args(["rev-parse", "HEAD"]).output() {
+        if output.status.success() {
+            let sha = String::from_utf8_lossy(&output.stdout);
+            println!("cargo:rustc-env=VERGEN_GIT_SHA={}", sha.trim());
+        }
+    }
+
+    // Honor `SOURCE_DATE_EPOCH` for reproducible builds
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        });
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={timestamp}");
+
+    // A commit moves the branch HEAD points to, not HEAD itself; after `git gc` the branch only
+    // lives in `packed-refs`. Missing paths would rerun the script on every build
+    let git_dir = Path::new("../../../.git");
+    println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+    if let Ok(head) = std::fs::read_to_string(git_dir.join("HEAD")) {
+        if let Some(reference) = head.trim().strip_prefix("ref: ") {
+            let ref_path = git_dir.join(reference);
+            if ref_path.exists() {
+                println!("cargo:rerun-if-changed={}", ref_path.display());
+            }
+        }
+    }
+    let packed_refs = git_dir.join("packed-refs");
+    if packed_refs.exists() {
+        println!("cargo:rerun-if-changed={}", packed_refs.display());
+    }
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
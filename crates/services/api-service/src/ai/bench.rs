@@ -0,0 +1,196 @@
+//! `bench` subcommand: drives the local `Infer` with synthetic inputs of given token lengths at a
+//! target concurrency and reports the latency percentiles and the throughput, for capacity
+//! planning without an HTTP load generator.
+
+use crate::ai::infer::Infer;
+use crate::error::{Error, Result};
+use crate::types::TruncationDirection;
+use futures::StreamExt;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Source of the token ids of the synthetic inputs, repeated up to the requested length
+const SEED_TEXT: &str = "Embedding servers turn documents and queries into vectors. Similar \
+    meanings end up close to each other, so a search compares the vector of a query with the \
+    vectors of every chunk of the indexed files and returns the nearest ones. Batching many \
+    requests together keeps the accelerator busy, while the queue bounds the latency of each.";
+
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// Tokens of the inputs of every run, before the special tokens
+    pub token_lengths: Vec<usize>,
+    /// Requests in flight
+    pub concurrency: usize,
+    /// Measured requests of every run
+    pub requests: usize,
+    /// Requests before the measure of every run, not reported
+    pub warmup: usize,
+}
+
+/// Result of the run of one token length
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchReport {
+    pub token_length: usize,
+    pub requests: usize,
+    pub failures: usize,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub requests_per_sec: f64,
+    pub tokens_per_sec: f64,
+}
+
+impl BenchReport {
+    /// From the latencies and prompt tokens of the successful requests and the wall time
+    fn new(
+        token_length: usize,
+        mut samples: Vec<(Duration, usize)>,
+        failures: usize,
+        elapsed: Duration,
+    ) -> Self {
+        samples.sort_by_key(|(latency, _)| *latency);
+        let latencies: Vec<Duration> = samples.iter().map(|(latency, _)| *latency).collect();
+        let tokens: usize = samples.iter().map(|(_, tokens)| tokens).sum();
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        Self {
+            token_length,
+            requests: samples.len(),
+            failures,
+            p50: percentile(&latencies, 50.0),
+            p95: percentile(&latencies, 95.0),
+            p99: percentile(&latencies, 99.0),
+            requests_per_sec: samples.len() as f64 / secs,
+            tokens_per_sec: tokens as f64 / secs,
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted latencies, zero without any
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+pub async fn bench(infer: Infer, options: BenchOptions) -> Result<Vec<BenchReport>> {
+    if infer.is_classifier() {
+        return Err(Error::Custom(
+            "`bench` only drives embedding models".to_string(),
+        ));
+    }
+    if options.token_lengths.is_empty() || options.requests == 0 {
+        return Err(Error::Custom(
+            "`bench` needs at least one token length and one request".to_string(),
+        ));
+    }
+    let (_, seed) = infer.tokenize(SEED_TEXT.to_string(), false, None).await?;
+    let seed = seed.get_ids().to_vec();
+
+    let mut reports = Vec::new();
+    for &token_length in &options.token_lengths {
+        let ids = seed
+            .iter()
+            .copied()
+            .cycle()
+            .take(token_length.max(1))
+            .collect();
+        let text = infer.decode(ids, true).await?;
+        for _ in 0..options.warmup {
+            embed(&infer, &text).await?;
+        }
+
+        info!(
+            "Benchmarking {token_length} tokens: {} requests, {} in flight",
+            options.requests, options.concurrency
+        );
+        let start = Instant::now();
+        let results: Vec<Result<(Duration, usize)>> = futures::stream::iter(0..options.requests)
+            .map(|_| embed(&infer, &text))
+            .buffer_unordered(options.concurrency.max(1))
+            .collect()
+            .await;
+        let elapsed = start.elapsed();
+        let failures = results.iter().filter(|result| result.is_err()).count();
+        if let Some(Err(e)) = results.iter().find(|result| result.is_err()) {
+            tracing::warn!("{failures} requests failed, first error: {e:?}");
+        }
+        let samples = results.into_iter().filter_map(Result::ok).collect();
+        reports.push(BenchReport::new(token_length, samples, failures, elapsed));
+    }
+    Ok(reports)
+}
+
+/// Latency and prompt tokens of one request, waiting for a permit like the HTTP routes
+async fn embed(infer: &Infer, text: &str) -> Result<(Duration, usize)> {
+    let start = Instant::now();
+    let permit = infer.acquire_permit().await;
+    let response = infer
+        .embed_pooled(
+            text.to_string(),
+            true,
+            TruncationDirection::default().into(),
+            None,
+            !infer.is_splade(),
+            None,
+            permit,
+        )
+        .await?;
+    Ok((start.elapsed(), response.metadata.prompt_tokens))
+}
+
+/// Table of the reports, one run per line
+pub fn format_reports(reports: &[BenchReport]) -> String {
+    let mut table = format!(
+        "{:>8} {:>9} {:>8} {:>10} {:>10} {:>10} {:>10} {:>12}\n",
+        "tokens", "requests", "failed", "p50 ms", "p95 ms", "p99 ms", "req/s", "tokens/s"
+    );
+    for report in reports {
+        table.push_str(&format!(
+            "{:>8} {:>9} {:>8} {:>10.1} {:>10.1} {:>10.1} {:>10.1} {:>12.0}\n",
+            report.token_length,
+            report.requests,
+            report.failures,
+            report.p50.as_secs_f64() * 1000.0,
+            report.p95.as_secs_f64() * 1000.0,
+            report.p99.as_secs_f64() * 1000.0,
+            report.requests_per_sec,
+            report.tokens_per_sec,
+        ));
+    }
+    table
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_report() {
+        let latencies: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&latencies, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&latencies, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&[], 95.0), Duration::ZERO);
+
+        let samples = latencies
+            .iter()
+            .rev()
+            .map(|latency| (*latency, 130))
+            .collect();
+        let report = BenchReport::new(128, samples, 2, Duration::from_secs(2));
+        assert_eq!(report.requests, 100);
+        assert_eq!(report.p95, Duration::from_millis(95));
+        assert_eq!(report.requests_per_sec, 50.0);
+        assert_eq!(report.tokens_per_sec, 6500.0);
+        assert!(
+            format_reports(&[report])
+                .lines()
+                .nth(1)
+                .unwrap()
+                .contains("6500")
+        );
+    }
+}
+// endregion: Unit Test
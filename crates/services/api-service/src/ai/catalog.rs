@@ -0,0 +1,259 @@
+//! Models known to the server: the served one and the warm standby models of
+//! `--standby-model-id`. Standby models are downloaded at startup without being loaded, switching
+//! over to one only loads it from the hub cache and swaps it with the served model, which is kept
+//! as a standby model.
+
+use crate::ai::Info;
+use crate::ai::infer::Infer;
+use crate::error::{Error, Result};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Loads a downloaded model with the settings of the served one, the `usize` tells apart the Unix
+/// sockets of the backends
+pub type ModelLoader =
+    Arc<dyn Fn(String, usize) -> BoxFuture<'static, Result<(Infer, Info)>> + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelStatus {
+    Downloading,
+    Downloaded,
+    Loading,
+    /// Served model, only one at a time
+    Loaded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CatalogEntry {
+    pub model_id: String,
+    pub status: ModelStatus,
+    /// Error of the last download or load of a `failed` model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CatalogEntry {
+    fn new(model_id: String, status: ModelStatus) -> Self {
+        Self {
+            model_id,
+            status,
+            error: None,
+        }
+    }
+}
+
+/// Inference queue of the served model with its info, swapped together on a switchover
+pub struct ServedModel {
+    pub infer: Arc<Infer>,
+    pub info: Arc<Info>,
+}
+
+pub struct ModelCatalog {
+    served: RwLock<Arc<ServedModel>>,
+    standby: Mutex<Vec<CatalogEntry>>,
+    loader: ModelLoader,
+    /// Held for the duration of a switchover, one at a time
+    switching: tokio::sync::Mutex<usize>,
+}
+
+impl ModelCatalog {
+    pub fn new(infer: Arc<Infer>, info: Arc<Info>, loader: ModelLoader) -> Self {
+        Self {
+            served: RwLock::new(Arc::new(ServedModel { infer, info })),
+            standby: Mutex::new(Vec::new()),
+            loader,
+            switching: tokio::sync::Mutex::new(0),
+        }
+    }
+
+    pub fn served(&self) -> Arc<ServedModel> {
+        self.served.read().unwrap().clone()
+    }
+
+    /// Served model first, then the standby models in the order of `--standby-model-id`
+    pub fn list(&self) -> Vec<CatalogEntry> {
+        let served = self.served().info.model_id.clone();
+        let mut entries = vec![CatalogEntry::new(served, ModelStatus::Loaded)];
+        entries.extend(self.standby.lock().unwrap().iter().cloned());
+        entries
+    }
+
+    /// Download the standby models one after the other, in the background. `download` fetches the
+    /// artifacts and weights of a model into the hub cache.
+    pub fn spawn_downloads<F>(self: &Arc<Self>, model_ids: Vec<String>, download: F)
+    where
+        F: Fn(String) -> BoxFuture<'static, Result<()>> + Send + 'static,
+    {
+        let served = self.served().info.model_id.clone();
+        let model_ids: Vec<String> = model_ids
+            .into_iter()
+            .filter(|model_id| *model_id != served)
+            .collect();
+        {
+            let mut standby = self.standby.lock().unwrap();
+            for model_id in &model_ids {
+                if !standby.iter().any(|entry| entry.model_id == *model_id) {
+                    standby.push(CatalogEntry::new(
+                        model_id.clone(),
+                        ModelStatus::Downloading,
+                    ));
+                }
+            }
+        }
+
+        let catalog = self.clone();
+        tokio::spawn(async move {
+            for model_id in model_ids {
+                let start = std::time::Instant::now();
+                match download(model_id.clone()).await {
+                    Ok(()) => {
+                        tracing::info!(
+                            "Standby model {model_id} downloaded in {:?}",
+                            start.elapsed()
+                        );
+                        catalog.set_status(&model_id, ModelStatus::Downloaded, None);
+                    }
+                    Err(err) => {
+                        tracing::error!("Could not download standby model {model_id}: {err}");
+                        catalog.set_status(&model_id, ModelStatus::Failed, Some(err.to_string()));
+                    }
+                }
+            }
+        });
+    }
+
+    /// Load the downloaded standby model and serve it instead of the current model, which becomes
+    /// a standby model. Requests in flight finish on the previous model.
+    pub async fn switch_to(&self, model_id: &str) -> Result<Arc<Info>> {
+        let Ok(mut switches) = self.switching.try_lock() else {
+            return Err(Error::Conflict("Another model is being loaded".to_string()));
+        };
+        if self.served().info.model_id == model_id {
+            return Ok(self.served().info.clone());
+        }
+        match self.status(model_id) {
+            None => return Err(Error::NotFound(format!("Standby model {model_id}"))),
+            Some(ModelStatus::Downloaded | ModelStatus::Failed) => {}
+            Some(status) => {
+                return Err(Error::Conflict(format!(
+                    "Standby model {model_id} is {status:?}, it cannot be loaded yet"
+                )));
+            }
+        }
+
+        self.set_status(model_id, ModelStatus::Loading, None);
+        *switches += 1;
+        let start = std::time::Instant::now();
+        let (infer, info) = match (self.loader)(model_id.to_string(), *switches).await {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                tracing::error!("Could not load standby model {model_id}: {err}");
+                self.set_status(model_id, ModelStatus::Failed, Some(err.to_string()));
+                return Err(err);
+            }
+        };
+        let info = Arc::new(info);
+        let previous = std::mem::replace(
+            &mut *self.served.write().unwrap(),
+            Arc::new(ServedModel {
+                infer: Arc::new(infer),
+                info: info.clone(),
+            }),
+        );
+        tracing::info!(
+            "Switched from {} to {model_id} in {:?}",
+            previous.info.model_id,
+            start.elapsed()
+        );
+
+        let mut standby = self.standby.lock().unwrap();
+        standby.retain(|entry| entry.model_id != model_id);
+        standby.insert(
+            0,
+            CatalogEntry::new(previous.info.model_id.clone(), ModelStatus::Downloaded),
+        );
+        Ok(info)
+    }
+
+    fn status(&self, model_id: &str) -> Option<ModelStatus> {
+        self.standby
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|entry| entry.model_id == model_id)
+            .map(|entry| entry.status)
+    }
+
+    fn set_status(&self, model_id: &str, status: ModelStatus, error: Option<String>) {
+        let mut standby = self.standby.lock().unwrap();
+        if let Some(entry) = standby.iter_mut().find(|entry| entry.model_id == model_id) {
+            entry.status = status;
+            entry.error = error;
+        }
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::run;
+    use lib_embedding::{DType, Pool};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_switch_to() -> Result<()> {
+        let (infer, info, _) = run(
+            "./Qwen3-Embedding-0.6B".to_string(),
+            None,
+            Some(2),
+            Some(DType::Float32),
+            None,
+            Some(Pool::Mean),
+            2,
+            512,
+            Some(4),
+            16,
+            true,
+            None,
+            None,
+            vec![],
+            None,
+            None,
+            None,
+            None,
+            "text-embeddings-inference-test".to_string(),
+        )
+        .await?;
+        let loader: ModelLoader = Arc::new(|model_id, _| {
+            Box::pin(async move { Err(Error::Custom(format!("{model_id} cannot be loaded"))) })
+        });
+        let catalog = Arc::new(ModelCatalog::new(Arc::new(infer), Arc::new(info), loader));
+        catalog.spawn_downloads(
+            vec![
+                "./Qwen3-Embedding-0.6B".to_string(),
+                "org/standby".to_string(),
+            ],
+            |_| Box::pin(async { Ok(()) }),
+        );
+        while catalog.status("org/standby") != Some(ModelStatus::Downloaded) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let entries = catalog.list();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].status, ModelStatus::Loaded);
+        assert!(matches!(
+            catalog.switch_to("org/unknown").await,
+            Err(Error::NotFound(_))
+        ));
+        assert!(catalog.switch_to("org/standby").await.is_err());
+        assert_eq!(catalog.status("org/standby"), Some(ModelStatus::Failed));
+        assert_eq!(catalog.served().info.model_id, "./Qwen3-Embedding-0.6B");
+        Ok(())
+    }
+}
+// endregion: Unit Test
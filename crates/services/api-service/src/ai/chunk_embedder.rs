@@ -0,0 +1,74 @@
+//! `ChunkEmbedder` of the jobs, running through the inference queue of the API.
+
+use crate::ai::catalog::ModelCatalog;
+use crate::routes::embed::embed_batch;
+use crate::types::{InputType, TruncationDirection};
+use async_trait::async_trait;
+use lib_core::vector_index::embeddings_normalized;
+use lib_cron::embedder::ChunkEmbedder;
+use lib_cron::error::{Error, Result};
+use std::sync::Arc;
+
+/// Embeds with the served model of the catalog, the model switched to with
+/// `POST /api/v1/models/{id}/load` after a switchover
+pub struct InferChunkEmbedder {
+    models: Arc<ModelCatalog>,
+}
+
+impl InferChunkEmbedder {
+    pub fn new(models: Arc<ModelCatalog>) -> Self {
+        Self { models }
+    }
+}
+
+#[async_trait]
+impl ChunkEmbedder for InferChunkEmbedder {
+    fn model_id(&self) -> String {
+        self.models.served().info.model_id.clone()
+    }
+
+    fn normalized(&self) -> bool {
+        embeddings_normalized()
+    }
+
+    /// Normalized unless `EMBEDDINGS_NORMALIZED=false` like the chunks of the ingest API, chunks
+    /// above the model limits are truncated
+    async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let inputs = texts.into_iter().map(InputType::String).collect();
+        let infer = self.models.served().infer.clone();
+        let results = embed_batch(
+            &infer,
+            inputs,
+            true,
+            TruncationDirection::default(),
+            None,
+            self.normalized(),
+            None,
+        )
+        .await
+        .map_err(|err| Error::Custom(format!("Failed to embed chunks: {err}")))?;
+        Ok(results.into_iter().map(|result| result.results).collect())
+    }
+
+    async fn embed_queries(
+        &self,
+        queries: Vec<String>,
+        instruction: Option<&str>,
+    ) -> Result<Vec<Vec<f32>>> {
+        let info = self.models.served().info.clone();
+        let queries = match (instruction, info.instruction_format) {
+            (None, _) => queries,
+            (Some(instruction), Some(format)) => queries
+                .iter()
+                .map(|query| format.apply(instruction, query))
+                .collect(),
+            (Some(_), None) => {
+                return Err(Error::Custom(format!(
+                    "`instruction` is not supported by model `{}`",
+                    info.model_id
+                )));
+            }
+        };
+        self.embed(queries).await
+    }
+}
@@ -0,0 +1,210 @@
+//! Dense adapters given through `--dense-path`.
+//!
+//! Every entry is either a path inside the model repository (`2_Dense`), a local directory
+//! (absolute, or relative starting with `./`) or an S3 URI (`s3://bucket/prefix`) holding
+//! `config.json` and `model.safetensors` (or `pytorch_model.bin`). S3 adapters are downloaded once
+//! next to the hub cache. An expected digest can be pinned with a `#sha256=<hex>` suffix, the
+//! weights digest of every external adapter is reported in `/info`.
+
+use crate::error::{Error, Result};
+use lib_storage::create_aws_client;
+use lib_storage::functions::file::download_file;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const S3_SCHEME: &str = "s3://";
+const CHECKSUM_SUFFIX: &str = "#sha256=";
+const WEIGHT_FILES: [&str; 2] = ["model.safetensors", "pytorch_model.bin"];
+
+/// Dense adapter loaded from outside the model repository
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct DenseAdapter {
+    /// Path or URI as given to `--dense-path`, without the checksum suffix
+    pub source: String,
+    /// SHA-256 of the adapter weights
+    pub sha256: String,
+}
+
+enum DenseSource {
+    Repository(String),
+    Local(PathBuf),
+    S3 { bucket: String, prefix: String },
+}
+
+fn parse_source(path: &str) -> Result<DenseSource> {
+    if let Some(uri) = path.strip_prefix(S3_SCHEME) {
+        let (bucket, prefix) = uri
+            .split_once('/')
+            .filter(|(bucket, prefix)| !bucket.is_empty() && !prefix.is_empty())
+            .ok_or_else(|| Error::Custom(format!("Invalid dense adapter URI `{path}`")))?;
+        return Ok(DenseSource::S3 {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_end_matches('/').to_string(),
+        });
+    }
+    let local = Path::new(path);
+    if local.is_absolute() || path.starts_with("./") || path.starts_with("../") {
+        let local = local
+            .canonicalize()
+            .map_err(|e| Error::Custom(format!("Dense adapter `{path}` not found: {e}")))?;
+        return Ok(DenseSource::Local(local));
+    }
+    Ok(DenseSource::Repository(path.to_string()))
+}
+
+/// Split a `#sha256=<hex>` suffix off the path
+fn split_checksum(path: &str) -> (&str, Option<String>) {
+    match path.rsplit_once(CHECKSUM_SUFFIX) {
+        Some((path, checksum)) => (path, Some(checksum.trim().to_ascii_lowercase())),
+        None => (path, None),
+    }
+}
+
+/// Resolve the `--dense-path` entries into the paths handed to the backend: repository paths are
+/// kept as is, external adapters become absolute local directories
+pub async fn resolve_dense_paths(
+    dense_paths: Vec<String>,
+    cache_dir: Option<&str>,
+) -> Result<(Vec<String>, Vec<DenseAdapter>)> {
+    let mut resolved = Vec::with_capacity(dense_paths.len());
+    let mut adapters = Vec::new();
+    let mut client = None;
+
+    for entry in dense_paths {
+        let (path, expected) = split_checksum(&entry);
+        let local = match parse_source(path)? {
+            DenseSource::Repository(path) => {
+                if expected.is_some() {
+                    return Err(Error::Custom(format!(
+                        "Checksums are only supported for external dense adapters, got `{entry}`"
+                    )));
+                }
+                resolved.push(path);
+                continue;
+            }
+            DenseSource::Local(local) => local,
+            DenseSource::S3 { bucket, prefix } => {
+                if client.is_none() {
+                    client = Some(create_aws_client().await);
+                }
+                let target = adapter_cache_dir(cache_dir).join(&bucket).join(&prefix);
+                download_adapter(client.as_ref().unwrap(), &bucket, &prefix, &target).await?;
+                target
+            }
+        };
+
+        let sha256 = weights_sha256(&local)?;
+        if let Some(expected) = expected {
+            if expected != sha256 {
+                return Err(Error::Custom(format!(
+                    "Checksum mismatch for dense adapter `{path}`: expected {expected}, got {sha256}"
+                )));
+            }
+        }
+        tracing::info!("Dense adapter `{path}` sha256 {sha256}");
+        adapters.push(DenseAdapter {
+            source: path.to_string(),
+            sha256,
+        });
+        resolved.push(local.to_string_lossy().to_string());
+    }
+    Ok((resolved, adapters))
+}
+
+fn adapter_cache_dir(cache_dir: Option<&str>) -> PathBuf {
+    cache_dir
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("dense-adapters")
+}
+
+/// Download `config.json` and the weights, files already present are reused
+async fn download_adapter(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    prefix: &str,
+    target: &Path,
+) -> Result<()> {
+    fs::create_dir_all(target)
+        .map_err(|e| Error::Custom(format!("Failed to create {target:?}: {e}")))?;
+
+    let config = target.join("config.json");
+    if !config.exists() {
+        let key = format!("{prefix}/config.json");
+        let data = download_file(client, bucket, &key)
+            .await
+            .map_err(|e| Error::Custom(format!("Failed to download s3://{bucket}/{key}: {e}")))?;
+        write_file(&config, data)?;
+    }
+
+    if WEIGHT_FILES.iter().any(|file| target.join(file).exists()) {
+        return Ok(());
+    }
+    // `model.safetensors` is preferred, `pytorch_model.bin` is the fallback
+    for file in WEIGHT_FILES {
+        if let Ok(data) = download_file(client, bucket, &format!("{prefix}/{file}")).await {
+            return write_file(&target.join(file), data);
+        }
+    }
+    Err(Error::Custom(format!(
+        "No dense adapter weights found under s3://{bucket}/{prefix}"
+    )))
+}
+
+fn write_file(path: &Path, data: Vec<u8>) -> Result<()> {
+    fs::write(path, data).map_err(|e| Error::Custom(format!("Failed to write {path:?}: {e}")))
+}
+
+/// SHA-256 of the weights file of the adapter, hex encoded
+fn weights_sha256(dir: &Path) -> Result<String> {
+    let weights = WEIGHT_FILES
+        .iter()
+        .map(|file| dir.join(file))
+        .find(|path| path.exists())
+        .ok_or_else(|| Error::Custom(format!("No dense adapter weights found in {dir:?}")))?;
+    let mut file = fs::File::open(&weights)
+        .map_err(|e| Error::Custom(format!("Failed to open {weights:?}: {e}")))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .map_err(|e| Error::Custom(format!("Failed to read {weights:?}: {e}")))?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resolve_local_adapter() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dense-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("config.json"), "{}").unwrap();
+        fs::write(dir.join("model.safetensors"), "abc").unwrap();
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let local = dir.to_string_lossy().to_string();
+
+        let (paths, adapters) = resolve_dense_paths(
+            vec!["2_Dense".to_string(), format!("{local}#sha256={sha256}")],
+            None,
+        )
+        .await?;
+        assert_eq!(paths[0], "2_Dense");
+        assert_eq!(paths[1], dir.canonicalize().unwrap().to_string_lossy());
+        assert_eq!(adapters[0].sha256, sha256);
+
+        let wrong = format!("{local}#sha256={}", "0".repeat(64));
+        assert!(resolve_dense_paths(vec![wrong], None).await.is_err());
+        assert!(parse_source("s3://bucket").is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+}
+// endregion: Unit Test
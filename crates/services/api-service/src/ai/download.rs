@@ -1,3 +1,4 @@
+use crate::ai::splade::IDF_FILE;
 use crate::error::{Error, Result};
 use hf_hub::api::tokio::ApiRepo;
 use std::path::PathBuf;
@@ -39,6 +40,11 @@ async fn download_st_config_legacy(api: &ApiRepo) -> Result<PathBuf> {
     Err(err)
 }
 
+/// Query weights of inference-free SPLADE models, most SPLADE models ship none
+pub async fn download_splade_idf(api: &ApiRepo) -> Option<PathBuf> {
+    download_file(api, IDF_FILE).await.ok()
+}
+
 #[instrument(skip_all)]
 pub async fn download_artifacts(api: &ApiRepo, pool_config: bool) -> Result<PathBuf> {
     let start = std::time::Instant::now();
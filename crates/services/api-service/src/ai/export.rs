@@ -0,0 +1,78 @@
+//! `export` subcommand: bundles the configured model, pooling and Dense modules as an ONNX pipeline
+//! for edge deployments running ORT offline, see `lib_embedding::export`.
+
+use crate::ai::{dense, get_backend_model_type, load_model_config, resolve_model};
+use crate::error::{Error, Result};
+use lib_embedding::Pool;
+use lib_embedding::export::{PIPELINE_FILE, export_onnx};
+use std::path::Path;
+use tracing::info;
+
+/// Embedded by both pipelines when no `--parity-sample` is given
+const DEFAULT_SAMPLES: [&str; 4] = [
+    "What is the capital of France?",
+    "The quick brown fox jumps over the lazy dog.",
+    "Embeddings map text to vectors, similar meanings end up close to each other.",
+    "a",
+];
+
+#[allow(clippy::too_many_arguments)]
+pub async fn export(
+    model_id: String,
+    revision: Option<String>,
+    pooling: Option<Pool>,
+    dense_paths: Vec<String>,
+    hf_token: Option<String>,
+    huggingface_hub_cache: Option<String>,
+    output: &Path,
+    samples: Vec<String>,
+    min_cosine: f32,
+) -> Result<()> {
+    let (model_root, api_repo) = resolve_model(
+        &model_id,
+        revision,
+        pooling.is_none(),
+        hf_token,
+        huggingface_hub_cache.as_deref(),
+    )
+    .await?;
+    let config = load_model_config(&model_root)?;
+    let pool = match get_backend_model_type(&config, &model_root, pooling)? {
+        lib_embedding::ModelType::Embedding(pool) => pool,
+        lib_embedding::ModelType::Classifier => {
+            return Err(Error::Custom(
+                "Only embedding models can be exported".to_string(),
+            ));
+        }
+    };
+    let (dense_paths, _) =
+        dense::resolve_dense_paths(dense_paths, huggingface_hub_cache.as_deref()).await?;
+    let samples = match samples.is_empty() {
+        true => DEFAULT_SAMPLES.map(String::from).to_vec(),
+        false => samples,
+    };
+
+    info!("Exporting {model_id} to {}", output.display());
+    let manifest = export_onnx(
+        &model_root,
+        api_repo.as_ref(),
+        pool,
+        dense_paths,
+        output,
+        &samples,
+        min_cosine,
+    )
+    .await
+    .map_err(|err| Error::Custom(format!("Export of {model_id} failed: {err}")))?;
+    info!(
+        "Wrote {} ({} dimensions, {} pooling, {} Dense modules), parity over {} samples: cosine {:.5}, max difference {:.5}",
+        output.join(PIPELINE_FILE).display(),
+        manifest.dimensions,
+        manifest.pooling,
+        manifest.dense.len(),
+        manifest.parity.samples,
+        manifest.parity.min_cosine,
+        manifest.parity.max_abs_diff
+    );
+    Ok(())
+}
@@ -1,13 +1,14 @@
-use crate::ai::queue::{Entry, Metadata, NextBatch, Queue};
+use crate::ai::limits::BatchLimits;
+use crate::ai::queue::{Entry, Metadata, NextBatch, PendingEntry, Queue, QueueLoad};
 use crate::ai::tokenization::{EncodingInput, RawEncoding, Tokenization};
 use crate::error::{Error, Result};
 use lib_embedding::InferenceBackend as Backend;
 use lib_embedding::core::{Embedding, ModelType};
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use std::time::{Duration, Instant};
 use tokenizers::TruncationDirection;
 use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore, mpsc, oneshot, watch};
-use tracing::instrument;
+use tracing::{Instrument, Span, info_span, instrument};
 
 /// Inference struct
 #[derive(Debug, Clone)]
@@ -18,15 +19,21 @@ pub struct Infer {
     notify_batching_task: Arc<Notify>,
     /// Inference limit
     limit_concurrent_requests: Arc<Semaphore>,
+    /// Requests whose estimated wait is longer are rejected, `None` queues every request
+    max_queue_wait: Option<Duration>,
     backend: Backend,
 }
 
+/// Period of the queue saturation gauges
+const SATURATION_INTERVAL: Duration = Duration::from_secs(1);
+
 impl Infer {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         tokenization: Tokenization,
         queue: Queue,
         max_concurrent_requests: usize,
+        max_queue_wait: Option<Duration>,
         backend: Backend,
     ) -> Self {
         let notify_batching_task = Arc::new(Notify::new());
@@ -42,20 +49,49 @@ impl Infer {
         ));
 
         // Create embed task to communicate with backend
-        tokio::spawn(backend_task(backend.clone(), embed_receiver));
+        tokio::spawn(backend_task(
+            backend.clone(),
+            queue.load().clone(),
+            embed_receiver,
+        ));
 
         // Inference limit with a semaphore
         let semaphore = Arc::new(Semaphore::new(max_concurrent_requests));
 
+        tokio::spawn(saturation_task(
+            Arc::downgrade(&semaphore),
+            queue.load().clone(),
+        ));
+
         Self {
             tokenization,
             queue,
             notify_batching_task,
             limit_concurrent_requests: semaphore,
+            max_queue_wait,
             backend,
         }
     }
 
+    /// Load-shedding: `Error::Overloaded` when the queued and in-flight tokens would make the
+    /// request wait longer than `max_queue_wait`
+    fn shed_load(&self) -> Result<()> {
+        let (Some(max_wait), Some(wait)) =
+            (self.max_queue_wait, self.queue.load().estimated_wait())
+        else {
+            return Ok(());
+        };
+        if wait <= max_wait {
+            return Ok(());
+        }
+        metrics::counter!("te_request_failure", "err" => "load_shed").increment(1);
+        tracing::warn!(
+            "Shedding request, estimated queue wait of {}ms",
+            wait.as_millis()
+        );
+        Err(Error::Overloaded)
+    }
+
     #[instrument(skip(self, inputs))]
     pub async fn tokenize<I: Into<EncodingInput> + std::fmt::Debug>(
         &self,
@@ -97,7 +133,7 @@ impl Infer {
                 let counter = metrics::counter!("te_request_failure", "err" => "overloaded");
                 counter.increment(1);
                 tracing::error!("{err}");
-                Error::Custom(err.to_string())
+                Error::Overloaded
             })
     }
 
@@ -127,7 +163,7 @@ impl Infer {
             counter.increment(1);
             let message = "`embed_all` is not available for SPLADE models".to_string();
             tracing::error!("{message}");
-            return Err(Error::Custom(message));
+            return Err(Error::BadRequest(message));
         }
 
         let results = self
@@ -176,7 +212,7 @@ impl Infer {
             counter.increment(1);
             let message = "Model is not an embedding model with SPLADE pooling".to_string();
             tracing::error!("{message}");
-            return Err(Error::Custom(message));
+            return Err(Error::BadRequest(message));
         }
 
         let results = self
@@ -233,7 +269,7 @@ impl Infer {
 
             let message = "`normalize` is not available for SPLADE models".to_string();
             tracing::error!("{message}");
-            return Err(Error::Custom(message));
+            return Err(Error::BadRequest(message));
         }
 
         if let Some(dimensions) = dimensions {
@@ -241,7 +277,7 @@ impl Infer {
                 metrics::counter!("te_request_failure", "err" => "validation").increment(1);
                 let message = "`dimensions` should be positive".to_string();
                 tracing::error!("{message}");
-                return Err(Error::Custom(message));
+                return Err(Error::BadRequest(message));
             }
         }
 
@@ -270,7 +306,7 @@ impl Infer {
                         .to_string();
                 tracing::error!("{message}");
 
-                return Err(Error::Custom(message));
+                return Err(Error::BadRequest(message));
             }
 
             response.results.truncate(mrl_dimensions);
@@ -292,6 +328,7 @@ impl Infer {
                 *v *= scale;
             }
         }
+        response.stats = Some(EmbeddingStats::of(&response.results));
 
         // Timings
         let total_time = start_time.elapsed();
@@ -327,8 +364,9 @@ impl Infer {
             counter.increment(1);
             let message = "Model is not an embedding model".to_string();
             tracing::error!("{message}");
-            return Err(Error::Custom(message));
+            return Err(Error::BadRequest(message));
         }
+        self.shed_load()?;
 
         let counter = metrics::counter!("te_embed_count");
         counter.increment(1);
@@ -337,6 +375,7 @@ impl Infer {
         let encoding = self
             .tokenization
             .encode(inputs.into(), truncate, truncation_direction, prompt_name)
+            .instrument(info_span!("tokenization"))
             .await
             .map_err(|err| {
                 let counter = metrics::counter!("te_request_failure", "err" => "tokenization");
@@ -349,18 +388,19 @@ impl Infer {
         let (response_tx, response_rx) = oneshot::channel();
 
         // Append the request to the queue
-        if let Err(_) = self.queue.append(Entry {
+        if let Err(err) = self.queue.append(Entry {
             metadata: Metadata {
                 response_tx,
                 tokenization: start_time.elapsed(),
                 queue_time: Instant::now(),
                 prompt_tokens: encoding.input_ids.len(),
                 pooling,
+                span: info_span!("queue"),
             },
             encoding,
         }) {
             metrics::counter!("te_request_failure", "err" => "queue_full").increment(1);
-            return Err(Error::Custom("Queue is full. Please retry.".to_string()));
+            return Err(err);
         }
 
         self.notify_batching_task.notify_one();
@@ -391,8 +431,9 @@ impl Infer {
             let counter = metrics::counter!("te_request_failure", "err" => "model_type");
             counter.increment(1);
             let message = "Model is not a classifier model".to_string();
-            return Err(Error::Custom(message));
+            return Err(Error::BadRequest(message));
         }
+        self.shed_load()?;
 
         let start_time = Instant::now();
         let counter = metrics::counter!("te_predict_count");
@@ -402,6 +443,7 @@ impl Infer {
         let encoding = self
             .tokenization
             .encode(inputs.into(), truncate, truncation_direction, None)
+            .instrument(info_span!("tokenization"))
             .await
             .map_err(|err| {
                 let counter = metrics::counter!("te_request_failure", "err" => "tokenization");
@@ -414,18 +456,19 @@ impl Infer {
         let (response_tx, response_rx) = oneshot::channel();
 
         // Append the request to the queue
-        if let Err(_) = self.queue.append(Entry {
+        if let Err(err) = self.queue.append(Entry {
             metadata: Metadata {
                 response_tx,
                 tokenization: start_time.elapsed(),
                 queue_time: Instant::now(),
                 prompt_tokens: encoding.input_ids.len(),
                 pooling: true,
+                span: info_span!("queue"),
             },
             encoding,
         }) {
             metrics::counter!("te_request_failure", "err" => "queue_full").increment(1);
-            return Err(Error::Custom("Queue is full. Please retry.".to_string()));
+            return Err(err);
         }
 
         self.notify_batching_task.notify_one();
@@ -501,6 +544,27 @@ impl Infer {
         )
     }
 
+    pub fn limits(&self) -> &Arc<BatchLimits> {
+        self.queue.limits()
+    }
+
+    /// Entries waiting in the queue, oldest first
+    pub async fn pending(&self) -> Vec<PendingEntry> {
+        self.queue.inspect().await
+    }
+
+    /// Reject every entry waiting in the queue with `Error::QueueFlushed`, returns how many were
+    /// rejected. Batches already handed to the backend are not affected.
+    pub async fn flush_queue(&self) -> usize {
+        let drained = self.queue.drain_pending().await;
+        let flushed = drained.len();
+        for entry in drained {
+            let _ = entry.metadata.response_tx.send(Err(Error::QueueFlushed));
+        }
+        metrics::counter!("te_request_failure", "err" => "flushed").increment(flushed as u64);
+        flushed
+    }
+
     #[instrument(skip(self))]
     pub async fn health(&self) -> bool {
         self.backend.health().await.is_ok()
@@ -517,6 +581,12 @@ async fn batching_task(queue: Queue, notify: Arc<Notify>, embed_sender: mpsc::Se
     loop {
         notify.notified().await;
 
+        // Give concurrent requests a chance to join the batch
+        let batch_wait = queue.limits().max_batch_wait();
+        if !batch_wait.is_zero() {
+            tokio::time::sleep(batch_wait).await;
+        }
+
         {
             // Try to reserve capacity in embed_sender
             let mut permit = match embed_sender.reserve().await {
@@ -553,10 +623,7 @@ async fn batching_task(queue: Queue, notify: Arc<Notify>, embed_sender: mpsc::Se
                         // Drain waiting items and return an error immediately
                         let drained = queue.drain_pending().await;
                         for entry in drained {
-                            let _ = entry
-                                .metadata
-                                .response_tx
-                                .send(Err(Error::Custom("Queue is full".to_string())));
+                            let _ = entry.metadata.response_tx.send(Err(Error::QueueFull));
                         }
 
                         // Break so we don’t keep looping endlessly
@@ -568,12 +635,43 @@ async fn batching_task(queue: Queue, notify: Arc<Notify>, embed_sender: mpsc::Se
     }
 }
 
+/// Queue length, tokens in flight and available permits, until the `Infer` is dropped
+async fn saturation_task(semaphore: Weak<Semaphore>, load: Arc<QueueLoad>) {
+    let mut interval = tokio::time::interval(SATURATION_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(semaphore) = semaphore.upgrade() else {
+            return;
+        };
+        metrics::gauge!("te_available_permits").set(semaphore.available_permits() as f64);
+        metrics::gauge!("te_queue_tokens").set(load.queued_tokens() as f64);
+        metrics::gauge!("te_inflight_tokens").set(load.inflight_tokens() as f64);
+        metrics::gauge!("te_backend_throughput").set(load.throughput());
+        if let Some(wait) = load.estimated_wait() {
+            metrics::gauge!("te_queue_estimated_wait").set(wait.as_secs_f64());
+        }
+    }
+}
+
 #[instrument(skip_all)]
-async fn backend_task(backend: Backend, mut embed_receiver: mpsc::Receiver<NextBatch>) {
-    while let Some(batch) = embed_receiver.recv().await {
+async fn backend_task(
+    backend: Backend,
+    load: Arc<QueueLoad>,
+    mut embed_receiver: mpsc::Receiver<NextBatch>,
+) {
+    while let Some(mut batch) = embed_receiver.recv().await {
+        let batch_tokens = batch.1.input_ids.len();
+        // Close the `queue` span of every request and link them to the batch `inference` span
+        let inference_span = info_span!("inference", batch_size = batch.0.len());
+        for m in batch.0.iter_mut() {
+            let queue_span = std::mem::replace(&mut m.span, Span::none());
+            inference_span.follows_from(&queue_span);
+        }
+
         match &backend.model_type {
             ModelType::Classifier => {
-                let results = backend.predict(batch.1).await;
+                let results = backend.predict(batch.1).instrument(inference_span).await;
+                load.finish_batch(batch_tokens, results.as_ref().ok().map(|(_, d)| *d));
 
                 // Handle sending responses in another thread to avoid starving the backend
                 std::thread::spawn(move || match results {
@@ -597,14 +695,16 @@ async fn backend_task(backend: Backend, mut embed_receiver: mpsc::Receiver<NextB
                         });
                     }
                     Err(err) => {
+                        let err = batch_error(err);
                         batch.0.into_iter().for_each(|m| {
-                            let _ = m.response_tx.send(Err(Error::Custom(err.to_string())));
+                            let _ = m.response_tx.send(Err(err.clone()));
                         });
                     }
                 });
             }
             ModelType::Embedding(_) => {
-                let results = backend.embed(batch.1).await;
+                let results = backend.embed(batch.1).instrument(inference_span).await;
+                load.finish_batch(batch_tokens, results.as_ref().ok().map(|(_, d)| *d));
 
                 // Handle sending responses in another thread to avoid starving the backend
                 std::thread::spawn(move || match results {
@@ -625,6 +725,7 @@ async fn backend_task(backend: Backend, mut embed_receiver: mpsc::Receiver<NextB
                                     InferResult::PooledEmbedding(PooledEmbeddingsInferResponse {
                                         results: e,
                                         metadata,
+                                        stats: None,
                                     })
                                 }
                                 Embedding::All(e) => {
@@ -639,8 +740,9 @@ async fn backend_task(backend: Backend, mut embed_receiver: mpsc::Receiver<NextB
                         })
                     }
                     Err(err) => {
+                        let err = batch_error(err);
                         batch.0.into_iter().for_each(|m| {
-                            let _ = m.response_tx.send(Err(Error::Custom(err.to_string())));
+                            let _ = m.response_tx.send(Err(err.clone()));
                         });
                     }
                 });
@@ -649,6 +751,14 @@ async fn backend_task(backend: Backend, mut embed_receiver: mpsc::Receiver<NextB
     }
 }
 
+/// Error of every request of a failed batch: `424 Failed Dependency` unless the backend is down
+fn batch_error(err: lib_embedding::error::Error) -> Error {
+    match err {
+        lib_embedding::error::Error::Unhealthy => Error::BackendUnhealthy(err.to_string()),
+        err => Error::Backend(err.to_string()),
+    }
+}
+
 #[derive(Debug)]
 pub struct InferMetadata {
     pub prompt_tokens: usize,
@@ -674,6 +784,59 @@ pub struct ClassificationInferResponse {
 pub struct PooledEmbeddingsInferResponse {
     pub results: Vec<f32>,
     pub metadata: InferMetadata,
+    /// Set by `embed_pooled` once the embedding is truncated and normalized
+    pub stats: Option<EmbeddingStats>,
+}
+
+/// Shape of pooled embeddings, lets clients spot a double normalization or a dimension mismatch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmbeddingStats {
+    /// L2 norm, the mean norm of a batch once aggregated
+    pub norm: f32,
+    pub min: f32,
+    pub max: f32,
+    pub dimension: usize,
+}
+
+impl EmbeddingStats {
+    pub fn of(embedding: &[f32]) -> Self {
+        let mut squares = 0.0_f64;
+        let mut min = f32::INFINITY;
+        let mut max = f32::NEG_INFINITY;
+        for v in embedding {
+            squares += (*v as f64) * (*v as f64);
+            min = min.min(*v);
+            max = max.max(*v);
+        }
+        Self {
+            norm: squares.sqrt() as f32,
+            min,
+            max,
+            dimension: embedding.len(),
+        }
+    }
+
+    /// Mean norm and extreme components of a batch, `None` when it is empty
+    pub fn aggregate(stats: impl IntoIterator<Item = EmbeddingStats>) -> Option<Self> {
+        let mut count = 0;
+        let mut total: Option<Self> = None;
+        for s in stats {
+            count += 1;
+            total = Some(match total {
+                None => s,
+                Some(t) => Self {
+                    norm: t.norm + s.norm,
+                    min: t.min.min(s.min),
+                    max: t.max.max(s.max),
+                    dimension: t.dimension.max(s.dimension),
+                },
+            });
+        }
+        total.map(|t| Self {
+            norm: t.norm / count as f32,
+            ..t
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -681,3 +844,23 @@ pub struct AllEmbeddingsInferResponse {
     pub results: Vec<Vec<f32>>,
     pub metadata: InferMetadata,
 }
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedding_stats() {
+        let stats = EmbeddingStats::of(&[3.0, -4.0, 0.0]);
+        assert_eq!(stats.norm, 5.0);
+        assert_eq!((stats.min, stats.max, stats.dimension), (-4.0, 3.0, 3));
+
+        let batch = EmbeddingStats::aggregate([stats, EmbeddingStats::of(&[1.0, 0.0, 0.5])]);
+        let batch = batch.unwrap();
+        assert!((batch.norm - (5.0 + 1.25_f32.sqrt()) / 2.0).abs() < 1e-6);
+        assert_eq!((batch.min, batch.max, batch.dimension), (-4.0, 3.0, 3));
+        assert!(EmbeddingStats::aggregate([]).is_none());
+    }
+}
+// endregion: Unit Test
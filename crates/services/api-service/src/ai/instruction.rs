@@ -0,0 +1,163 @@
+//! Instruction formatting of instruct-style embedding models.
+//!
+//! Qwen3-Embedding (and gte-Qwen2-instruct) is trained with queries wrapped as
+//! `Instruct: {task}\nQuery:{query}` while documents are embedded as is. Both rely on last-token
+//! pooling over the `<|endoftext|>` token appended by the tokenizer post processor.
+
+use crate::error::{Error, Result};
+use crate::types::{EmbedInputType, Input, InputType};
+use serde::Serialize;
+use std::collections::HashMap;
+use tokenizers::Tokenizer;
+
+pub const QWEN_EOS_TOKEN: &str = "<|endoftext|>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstructionFormat {
+    /// `Instruct: {instruction}\nQuery:{text}`
+    Qwen,
+}
+
+impl InstructionFormat {
+    /// Format expected by the model family, `None` when the model takes no instruction
+    pub fn for_model_type(model_type: &str) -> Option<Self> {
+        match model_type {
+            "qwen2" | "qwen3" => Some(InstructionFormat::Qwen),
+            _ => None,
+        }
+    }
+
+    pub fn apply(&self, instruction: &str, text: &str) -> String {
+        match self {
+            InstructionFormat::Qwen => format!("Instruct: {}\nQuery:{text}", instruction.trim()),
+        }
+    }
+
+    /// Wrap every text input of the request, token id inputs cannot be templated
+    pub fn apply_input(&self, instruction: &str, input: Input) -> Result<Input> {
+        let wrap = |input: InputType| match input {
+            InputType::String(text) => Ok(InputType::String(self.apply(instruction, &text))),
+            InputType::Ids(_) => Err(Error::BadRequest(
+                "`instruction` cannot be used with token id inputs".to_string(),
+            )),
+        };
+        match input {
+            Input::Single(input) => Ok(Input::Single(wrap(input)?)),
+            Input::Batch(inputs) => Ok(Input::Batch(
+                inputs.into_iter().map(wrap).collect::<Result<_>>()?,
+            )),
+        }
+    }
+}
+
+/// Sentence Transformers prompt names applied for the `input_type` of a request. Asymmetric models
+/// embed queries and passages with different prompts, e.g. `query: ` and `passage: ` for E5 or
+/// the `query` instruction of Qwen3-Embedding and no prompt for its documents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InputTypePrompts {
+    pub query: String,
+    pub passage: String,
+}
+
+const QUERY_PROMPT_NAMES: [&str; 1] = ["query"];
+const PASSAGE_PROMPT_NAMES: [&str; 2] = ["passage", "document"];
+
+impl InputTypePrompts {
+    /// Names of the query and passage prompts of the model. A prompt missing from the
+    /// `config_sentence_transformers.json` prompts is added: the E5 prefixes for the E5 models, an
+    /// empty prompt otherwise so that an `input_type` never falls back to the default prompt.
+    pub fn resolve(
+        prompts: Option<HashMap<String, String>>,
+        model_id: &str,
+    ) -> (HashMap<String, String>, Self) {
+        let mut prompts = prompts.unwrap_or_default();
+        let model_id = model_id.to_ascii_lowercase();
+        // e5-mistral is instruct-style, its passages take no prefix
+        let e5 = model_id.contains("e5-") && !model_id.contains("instruct");
+        let mut pick = |names: &[&str], e5_prefix: &str| {
+            if let Some(name) = names.iter().find(|name| prompts.contains_key(**name)) {
+                return name.to_string();
+            }
+            let prompt = if e5 { e5_prefix } else { "" };
+            prompts.insert(names[0].to_string(), prompt.to_string());
+            names[0].to_string()
+        };
+        let query = pick(&QUERY_PROMPT_NAMES, "query: ");
+        let passage = pick(&PASSAGE_PROMPT_NAMES, "passage: ");
+        (prompts, Self { query, passage })
+    }
+
+    pub fn prompt_name(&self, input_type: EmbedInputType) -> &str {
+        match input_type {
+            EmbedInputType::Query => &self.query,
+            EmbedInputType::Passage => &self.passage,
+        }
+    }
+}
+
+/// Whether the post processor already ends single sequences with the EOS token, older revisions
+/// of the Qwen3-Embedding `tokenizer.json` do not
+pub fn appends_eos(tokenizer: &Tokenizer) -> bool {
+    let Some(eos_id) = tokenizer.token_to_id(QWEN_EOS_TOKEN) else {
+        return true;
+    };
+    tokenizer
+        .encode("a", true)
+        .map(|encoding| encoding.get_ids().last() == Some(&eos_id))
+        .unwrap_or(true)
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qwen_instruction() -> Result<()> {
+        let format = InstructionFormat::for_model_type("qwen3").unwrap();
+        assert_eq!(
+            format.apply(
+                "Given a web search query, retrieve relevant passages ",
+                "rust"
+            ),
+            "Instruct: Given a web search query, retrieve relevant passages\nQuery:rust"
+        );
+        assert!(InstructionFormat::for_model_type("bert").is_none());
+
+        let input = Input::Batch(vec![InputType::String("a".to_string())]);
+        match format.apply_input("Find", input)? {
+            Input::Batch(inputs) => match &inputs[0] {
+                InputType::String(text) => assert_eq!(text, "Instruct: Find\nQuery:a"),
+                InputType::Ids(_) => panic!("expected a string input"),
+            },
+            Input::Single(_) => panic!("expected a batch"),
+        }
+        assert!(
+            format
+                .apply_input("Find", Input::Single(InputType::Ids(vec![1, 2])))
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_type_prompts() {
+        let (prompts, names) = InputTypePrompts::resolve(None, "intfloat/multilingual-e5-base");
+        assert_eq!(prompts[names.prompt_name(EmbedInputType::Query)], "query: ");
+        assert_eq!(prompts[&names.passage], "passage: ");
+
+        let qwen = HashMap::from([
+            ("query".to_string(), "Instruct: Find\nQuery:".to_string()),
+            ("document".to_string(), String::new()),
+        ]);
+        let (prompts, names) = InputTypePrompts::resolve(Some(qwen), "Qwen/Qwen3-Embedding-0.6B");
+        assert_eq!(names.prompt_name(EmbedInputType::Passage), "document");
+        assert_eq!(prompts.len(), 2);
+
+        let (prompts, names) = InputTypePrompts::resolve(None, "intfloat/e5-mistral-7b-instruct");
+        assert_eq!(prompts[&names.query], "");
+        assert_eq!(prompts[&names.passage], "");
+    }
+}
+// endregion: Unit Test
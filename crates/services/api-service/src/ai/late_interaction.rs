@@ -0,0 +1,66 @@
+//! Late-interaction (ColBERT-style) scoring over token embeddings: every query token is matched
+//! with its most similar document token and the similarities are summed (MaxSim). Token vectors
+//! are L2 normalized first, the similarities are cosines.
+
+/// L2 normalize every token vector in place, zero vectors are left as they are
+pub fn normalize(tokens: &mut [Vec<f32>]) {
+    for token in tokens {
+        let norm = token.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            token.iter_mut().for_each(|v| *v /= norm);
+        }
+    }
+}
+
+/// Sum over the query tokens of their best dot product with a document token
+pub fn max_sim(query: &[Vec<f32>], document: &[Vec<f32>]) -> f32 {
+    query
+        .iter()
+        .map(|q| {
+            document
+                .iter()
+                .map(|d| q.iter().zip(d).map(|(a, b)| a * b).sum::<f32>())
+                .fold(f32::NEG_INFINITY, f32::max)
+        })
+        .filter(|score| score.is_finite())
+        .sum()
+}
+
+/// `(index, score)` of the documents, best first, at most `top_n`
+pub fn rank(
+    query: &[Vec<f32>],
+    documents: &[Vec<Vec<f32>>],
+    top_n: Option<usize>,
+) -> Vec<(usize, f32)> {
+    let mut scores: Vec<(usize, f32)> = documents
+        .iter()
+        .map(|document| max_sim(query, document))
+        .enumerate()
+        .collect();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scores.truncate(top_n.unwrap_or(scores.len()));
+    scores
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_sim() {
+        let mut query = vec![vec![2.0, 0.0], vec![0.0, 3.0]];
+        normalize(&mut query);
+        assert_eq!(query, vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+
+        let exact = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        let partial = vec![vec![1.0, 0.0], vec![1.0, 0.0]];
+        assert_eq!(max_sim(&query, &exact), 2.0);
+        assert_eq!(max_sim(&query, &partial), 1.0);
+        assert_eq!(max_sim(&query, &[]), 0.0);
+
+        let ranked = rank(&query, &[partial, vec![], exact], Some(2));
+        assert_eq!(ranked, vec![(2, 2.0), (0, 1.0)]);
+    }
+}
+// endregion: Unit Test
@@ -0,0 +1,221 @@
+//! Queue and batch limits adjustable at runtime through `PATCH /api/v1/admin/limits`.
+//!
+//! The values given at startup are validated by the backend warmup and act as ceilings, runtime
+//! updates can only lower them (or raise them back up to the ceiling).
+
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Upper bound of the time the batching task waits for more entries before building a batch
+pub const MAX_BATCH_WAIT_CEILING_MS: u64 = 1_000;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LimitsSnapshot {
+    pub max_batch_tokens: usize,
+    pub max_batch_requests: Option<usize>,
+    pub max_client_batch_size: usize,
+    pub max_batch_wait_ms: u64,
+}
+
+/// Partial update, missing fields are left unchanged
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LimitsUpdate {
+    pub max_batch_tokens: Option<usize>,
+    pub max_batch_requests: Option<usize>,
+    pub max_client_batch_size: Option<usize>,
+    pub max_batch_wait_ms: Option<u64>,
+}
+
+impl From<LimitsSnapshot> for LimitsUpdate {
+    fn from(snapshot: LimitsSnapshot) -> Self {
+        Self {
+            max_batch_tokens: Some(snapshot.max_batch_tokens),
+            max_batch_requests: snapshot.max_batch_requests,
+            max_client_batch_size: Some(snapshot.max_client_batch_size),
+            max_batch_wait_ms: Some(snapshot.max_batch_wait_ms),
+        }
+    }
+}
+
+/// Live limits shared by the queue, the batching task and the handlers
+#[derive(Debug)]
+pub struct BatchLimits {
+    ceilings: LimitsSnapshot,
+    max_batch_tokens: AtomicUsize,
+    /// `0` when the number of requests per batch is unbounded
+    max_batch_requests: AtomicUsize,
+    max_client_batch_size: AtomicUsize,
+    max_batch_wait_ms: AtomicU64,
+}
+
+impl BatchLimits {
+    /// `max_client_batch_size` is capped by `max_concurrent_requests`, every input of a client
+    /// batch holds a permit
+    pub fn new(
+        max_batch_tokens: usize,
+        max_batch_requests: Option<usize>,
+        max_client_batch_size: usize,
+        max_concurrent_requests: usize,
+    ) -> Self {
+        if max_client_batch_size > max_concurrent_requests {
+            tracing::warn!(
+                "`max_client_batch_size` {max_client_batch_size} is above `max_concurrent_requests`, capped to {max_concurrent_requests}"
+            );
+        }
+        let max_client_batch_size = max_client_batch_size.min(max_concurrent_requests);
+        let ceilings = LimitsSnapshot {
+            max_batch_tokens,
+            max_batch_requests,
+            max_client_batch_size,
+            max_batch_wait_ms: MAX_BATCH_WAIT_CEILING_MS,
+        };
+        Self {
+            ceilings,
+            max_batch_tokens: AtomicUsize::new(max_batch_tokens),
+            max_batch_requests: AtomicUsize::new(max_batch_requests.unwrap_or(0)),
+            max_client_batch_size: AtomicUsize::new(max_client_batch_size),
+            max_batch_wait_ms: AtomicU64::new(0),
+        }
+    }
+
+    pub fn ceilings(&self) -> LimitsSnapshot {
+        self.ceilings
+    }
+
+    pub fn max_batch_tokens(&self) -> usize {
+        self.max_batch_tokens.load(Ordering::Relaxed)
+    }
+
+    pub fn max_batch_requests(&self) -> Option<usize> {
+        match self.max_batch_requests.load(Ordering::Relaxed) {
+            0 => None,
+            n => Some(n),
+        }
+    }
+
+    pub fn max_client_batch_size(&self) -> usize {
+        self.max_client_batch_size.load(Ordering::Relaxed)
+    }
+
+    pub fn max_batch_wait(&self) -> Duration {
+        Duration::from_millis(self.max_batch_wait_ms.load(Ordering::Relaxed))
+    }
+
+    pub fn snapshot(&self) -> LimitsSnapshot {
+        LimitsSnapshot {
+            max_batch_tokens: self.max_batch_tokens(),
+            max_batch_requests: self.max_batch_requests(),
+            max_client_batch_size: self.max_client_batch_size(),
+            max_batch_wait_ms: self.max_batch_wait().as_millis() as u64,
+        }
+    }
+
+    /// Validate the whole update against the ceilings before applying any of it
+    pub fn apply(&self, update: &LimitsUpdate) -> Result<LimitsSnapshot> {
+        let ceilings = self.ceilings;
+        if let Some(v) = update.max_batch_tokens {
+            check_bound("max_batch_tokens", v, ceilings.max_batch_tokens)?;
+        }
+        if let Some(v) = update.max_batch_requests {
+            check_bound(
+                "max_batch_requests",
+                v,
+                ceilings.max_batch_requests.unwrap_or(usize::MAX),
+            )?;
+        }
+        if let Some(v) = update.max_client_batch_size {
+            check_bound("max_client_batch_size", v, ceilings.max_client_batch_size)?;
+        }
+        if let Some(v) = update.max_batch_wait_ms {
+            if v > ceilings.max_batch_wait_ms {
+                return Err(Error::BadRequest(format!(
+                    "`max_batch_wait_ms` must be <= {}",
+                    ceilings.max_batch_wait_ms
+                )));
+            }
+        }
+
+        if let Some(v) = update.max_batch_tokens {
+            self.max_batch_tokens.store(v, Ordering::Relaxed);
+        }
+        if let Some(v) = update.max_batch_requests {
+            self.max_batch_requests.store(v, Ordering::Relaxed);
+        }
+        if let Some(v) = update.max_client_batch_size {
+            self.max_client_batch_size.store(v, Ordering::Relaxed);
+        }
+        if let Some(v) = update.max_batch_wait_ms {
+            self.max_batch_wait_ms.store(v, Ordering::Relaxed);
+        }
+        Ok(self.snapshot())
+    }
+}
+
+fn check_bound(name: &str, value: usize, ceiling: usize) -> Result<()> {
+    if value == 0 || value > ceiling {
+        return Err(Error::BadRequest(format!(
+            "`{name}` must be between 1 and {ceiling}"
+        )));
+    }
+    Ok(())
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_limits() {
+        let limits = BatchLimits::new(1024, Some(8), 4, 16);
+
+        let update = LimitsUpdate {
+            max_batch_tokens: Some(512),
+            max_batch_wait_ms: Some(5),
+            ..Default::default()
+        };
+        let snapshot = limits.apply(&update).unwrap();
+        assert_eq!(snapshot.max_batch_tokens, 512);
+        assert_eq!(snapshot.max_batch_requests, Some(8));
+        assert_eq!(snapshot.max_client_batch_size, 4);
+        assert_eq!(limits.max_batch_wait(), Duration::from_millis(5));
+
+        // Above the warmed up ceiling, nothing is applied
+        let update = LimitsUpdate {
+            max_batch_tokens: Some(256),
+            max_batch_requests: Some(9),
+            ..Default::default()
+        };
+        assert!(matches!(limits.apply(&update), Err(Error::BadRequest(_))));
+        assert_eq!(limits.max_batch_tokens(), 512);
+
+        // The startup value is the ceiling, it can be lowered and raised back
+        let update = LimitsUpdate {
+            max_client_batch_size: Some(16),
+            ..Default::default()
+        };
+        assert!(limits.apply(&update).is_err());
+        let update = LimitsUpdate {
+            max_client_batch_size: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(limits.apply(&update).unwrap().max_client_batch_size, 2);
+        let update = LimitsUpdate {
+            max_client_batch_size: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(limits.apply(&update).unwrap().max_client_batch_size, 4);
+    }
+
+    #[test]
+    fn test_client_batch_size_capped() {
+        // Every input of a client batch holds a permit
+        let limits = BatchLimits::new(1024, None, 32, 8);
+        assert_eq!(limits.max_client_batch_size(), 8);
+        assert_eq!(limits.ceilings().max_client_batch_size, 8);
+    }
+}
+
+// endregion: Unit Test
@@ -0,0 +1,132 @@
+//! Inputs longer than `max_input_length`, requested with `long_input` when they are not truncated.
+//! The text is split into overlapping windows of tokens, every window is embedded and the
+//! embeddings are pooled into a single vector.
+
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+/// Tokens kept free in every window for the boundaries of the window, a slice of the text is not
+/// always tokenized as the same tokens as in the whole text
+const WINDOW_SLACK: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LongInputPooling {
+    /// Mean of the embeddings of the windows
+    Mean,
+    /// Mean weighted by the number of tokens of every window, the last window is often shorter
+    Weighted,
+}
+
+/// Tokens of the text in a window, once the special tokens and the prompt are added
+pub(crate) fn window_size(max_input_length: usize, added_tokens: usize) -> usize {
+    max_input_length.saturating_sub(added_tokens + WINDOW_SLACK)
+}
+
+/// Overlap of the windows when the request does not set it, an eighth of the window
+pub(crate) fn default_overlap(window_size: usize) -> usize {
+    window_size / 8
+}
+
+/// Byte ranges of the windows in the text and their number of tokens, from the offsets of its
+/// tokens. Consecutive windows share `overlap` tokens, `overlap` is smaller than `size`.
+pub(crate) fn windows(
+    offsets: &[(usize, usize)],
+    size: usize,
+    overlap: usize,
+) -> Vec<(usize, usize, usize)> {
+    let step = size - overlap;
+    let mut windows = Vec::new();
+    let mut start = 0;
+    while start < offsets.len() {
+        let end = (start + size).min(offsets.len());
+        windows.push((offsets[start].0, offsets[end - 1].1, end - start));
+        if end == offsets.len() {
+            break;
+        }
+        start += step;
+    }
+    windows
+}
+
+/// Text of a window, widened to the closest characters: byte-level tokens can split a character
+pub(crate) fn window_text(text: &str, mut start: usize, mut stop: usize) -> &str {
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    while !text.is_char_boundary(stop) {
+        stop += 1;
+    }
+    &text[start..stop]
+}
+
+/// Embedding of the whole text from the embeddings of its windows and their number of tokens
+pub(crate) fn pool(pooling: LongInputPooling, embeddings: &[(Vec<f32>, usize)]) -> Vec<f32> {
+    let dimension = embeddings.first().map_or(0, |(e, _)| e.len());
+    let mut pooled = vec![0.0_f64; dimension];
+    let mut total = 0.0_f64;
+    for (embedding, tokens) in embeddings {
+        let weight = match pooling {
+            LongInputPooling::Mean => 1.0,
+            LongInputPooling::Weighted => *tokens as f64,
+        };
+        for (p, v) in pooled.iter_mut().zip(embedding) {
+            *p += weight * *v as f64;
+        }
+        total += weight;
+    }
+    pooled.into_iter().map(|p| (p / total) as f32).collect()
+}
+
+/// Scale `embedding` to a unit L2 norm
+pub(crate) fn normalize(embedding: &mut [f32]) {
+    let norm = embedding
+        .iter()
+        .map(|v| (*v as f64) * (*v as f64))
+        .sum::<f64>()
+        .sqrt();
+    if norm > 0.0 {
+        for v in embedding.iter_mut() {
+            *v = (*v as f64 / norm) as f32;
+        }
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows() {
+        // Tokens of 2 bytes separated by a space
+        let offsets: Vec<_> = (0..10).map(|i| (i * 3, i * 3 + 2)).collect();
+        assert_eq!(
+            windows(&offsets, 4, 1),
+            vec![(0, 11, 4), (9, 20, 4), (18, 29, 4)]
+        );
+        assert_eq!(windows(&offsets, 5, 0), vec![(0, 14, 5), (15, 29, 5)]);
+        assert_eq!(windows(&offsets, 20, 2), vec![(0, 29, 10)]);
+        assert!(windows(&[], 4, 1).is_empty());
+
+        // `é` is 2 bytes, split by the byte-level tokens
+        assert_eq!(window_text("café au lait", 4, 8), "é au");
+    }
+
+    #[test]
+    fn test_pool() {
+        let embeddings = vec![(vec![1.0, 0.0], 3), (vec![0.0, 1.0], 1)];
+        assert_eq!(pool(LongInputPooling::Mean, &embeddings), vec![0.5, 0.5]);
+        assert_eq!(
+            pool(LongInputPooling::Weighted, &embeddings),
+            vec![0.75, 0.25]
+        );
+
+        let mut pooled = pool(LongInputPooling::Mean, &embeddings);
+        normalize(&mut pooled);
+        assert!((pooled[0] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        assert_eq!(window_size(512, 2), 508);
+        assert_eq!(default_overlap(508), 63);
+    }
+}
+// endregion: Unit Test
@@ -1,91 +1,102 @@
+pub mod bench;
+pub mod catalog;
+pub mod chunk_embedder;
+pub mod dense;
 pub mod download;
+pub mod export;
 pub mod infer;
+pub mod instruction;
+pub mod late_interaction;
+pub mod limits;
+pub mod long_input;
+pub mod offline;
+pub mod output_dtype;
 pub mod queue;
+pub mod splade;
 pub mod tokenization;
 
-use crate::ai::download::{ST_CONFIG_NAMES, download_artifacts};
-use crate::ai::infer::Infer;
+use crate::ai::download::{ST_CONFIG_NAMES, download_artifacts, download_splade_idf};
+use crate::ai::infer::{EmbeddingStats, Infer};
+use crate::ai::instruction::{InputTypePrompts, InstructionFormat};
+use crate::ai::limits::BatchLimits;
+use crate::ai::output_dtype::Int8Scale;
 use crate::ai::queue::Queue;
+use crate::ai::splade::{IdfWeights, SpladeQueryEncoder, SpladeQueryInfo};
 use crate::ai::tokenization::Tokenization;
 use crate::error::{self, Error, Result};
 use axum::http::HeaderMap;
-use hf_hub::api::tokio::ApiBuilder;
+use hf_hub::api::tokio::{ApiBuilder, ApiRepo};
 use hf_hub::{Repo, RepoType};
-use lib_embedding::{DType, Pool};
+use lib_embedding::{DType, Pool, Quantization};
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokenizers::processors::sequence::Sequence;
 use tokenizers::processors::template::TemplateProcessing;
 use tokenizers::{PostProcessorWrapper, Tokenizer};
 use tracing::{Span, error, info, instrument};
 
-/// Create entrypoint
+/// Create entrypoint. SPLADE models shipping IDF query weights also return their query encoder.
 #[allow(clippy::too_many_arguments)]
 pub async fn run(
     model_id: String,
     revision: Option<String>,
     tokenization_workers: Option<usize>,
     dtype: Option<DType>,
+    quantization: Option<Quantization>,
     pooling: Option<Pool>,
     max_concurrent_requests: usize,
     max_batch_tokens: usize,
     max_batch_requests: Option<usize>,
     max_client_batch_size: usize,
+    max_queue_wait: Option<Duration>,
     auto_truncate: bool,
     default_prompt: Option<String>,
     default_prompt_name: Option<String>,
-    dense_path: Option<String>,
+    dense_paths: Vec<String>,
     hf_token: Option<String>,
     uds_path: Option<String>,
     huggingface_hub_cache: Option<String>,
     otlp_endpoint: Option<String>,
     otlp_service_name: String,
-) -> Result<(Infer, Info)> {
-    let model_id_path = Path::new(&model_id);
-    let (model_root, api_repo) = if model_id_path.exists() && model_id_path.is_dir() {
-        // Using a local model
-        (model_id_path.to_path_buf(), None)
-    } else {
-        let mut builder = ApiBuilder::from_env()
-            .with_progress(false)
-            .with_token(hf_token);
-
-        if let Some(cache_dir) = huggingface_hub_cache {
-            builder = builder.with_cache_dir(cache_dir.into());
-        }
-
-        if let Ok(origin) = std::env::var("HF_HUB_USER_AGENT_ORIGIN") {
-            builder = builder.with_user_agent("origin", origin.as_str());
-        }
-
-        let api = builder.build().unwrap();
-        let api_repo = api.repo(Repo::with_revision(
-            model_id.clone(),
-            RepoType::Model,
-            revision.clone().unwrap_or("main".to_string()),
-        ));
-
-        // Download model from the Hub
-        (
-            download_artifacts(&api_repo, pooling.is_none()).await?,
-            Some(api_repo),
-        )
-    };
+) -> Result<(Infer, Info, Option<SpladeQueryEncoder>)> {
+    let (model_root, api_repo) = resolve_model(
+        &model_id,
+        revision.clone(),
+        pooling.is_none(),
+        hf_token,
+        huggingface_hub_cache.as_deref(),
+    )
+    .await?;
 
     // Load config
-    let config_path = model_root.join("config.json");
-    let config = fs::read_to_string(config_path)
-        .map_err(|_err| Error::Custom("`config.json` not found".into()))?;
-    let config: ModelConfig = serde_json::from_str(&config)
-        .map_err(|_err| Error::Custom("Failed to parse `config.json`".into()))?;
+    let config = load_model_config(&model_root)?;
 
     // Set model type from config
     let backend_model_type = get_backend_model_type(&config, &model_root, pooling)?;
 
+    // Inference-free SPLADE models weight the query tokens with the IDF of `idf.json`
+    let is_splade = matches!(
+        backend_model_type,
+        lib_embedding::ModelType::Embedding(Pool::Splade)
+    );
+    let query_encoder = if is_splade {
+        if let Some(api_repo) = &api_repo {
+            download_splade_idf(api_repo).await;
+        }
+        IdfWeights::load(&model_root)?.map(SpladeQueryEncoder::Idf)
+    } else {
+        None
+    };
+    let splade_query_encoder = match &query_encoder {
+        Some(encoder) => Some(encoder.info()),
+        None => is_splade.then_some(SpladeQueryInfo::Shared),
+    };
+
     // Info model type
     let model_type = match &backend_model_type {
         lib_embedding::ModelType::Classifier => {
@@ -119,7 +130,10 @@ pub async fn run(
 
     // Qwen2 updates the post processor manually instead of into the tokenizer.json...
     // https://huggingface.co/Alibaba-NLP/gte-Qwen2-1.5B-instruct/blob/main/tokenization_qwen.py#L246
-    if config.model_type == "qwen2" {
+    // Qwen3 last-token pooling needs the same EOS, only older tokenizer.json revisions lack it
+    if config.model_type == "qwen2"
+        || (config.model_type == "qwen3" && !instruction::appends_eos(&tokenizer))
+    {
         let template = TemplateProcessing::builder()
             .try_single("$A:0 <|endoftext|>:0")
             .unwrap()
@@ -205,6 +219,7 @@ pub async fn run(
     } else {
         default_prompt
     };
+    let (prompts, input_type_prompts) = InputTypePrompts::resolve(prompts, &model_id);
 
     // Tokenization logic
     let tokenization = Tokenization::new(
@@ -213,7 +228,7 @@ pub async fn run(
         max_input_length,
         position_offset,
         default_prompt,
-        prompts,
+        Some(prompts),
     );
 
     // NOTE: `gemma3_text` won't support Float16 but only Float32, given that with `candle-cuda`
@@ -224,6 +239,18 @@ pub async fn run(
     } else {
         dtype.unwrap_or_default()
     };
+    // Quantized layers compute in float32, the other weights are loaded as float32 as well
+    let dtype = match quantization {
+        Some(quantization) if dtype != DType::Float32 => {
+            tracing::warn!("`--quantization {quantization}` loads the model in float32");
+            DType::Float32
+        }
+        _ => dtype,
+    };
+
+    // External Dense adapters are fetched and checksummed before the backend loads them
+    let (dense_paths, dense_adapters) =
+        dense::resolve_dense_paths(dense_paths, huggingface_hub_cache.as_deref()).await?;
 
     // Create backend
     tracing::info!("Starting model backend");
@@ -231,8 +258,9 @@ pub async fn run(
         model_root,
         api_repo,
         dtype.clone(),
+        quantization,
         backend_model_type,
-        dense_path,
+        dense_paths,
         uds_path.unwrap_or("/tmp/text-embeddings-inference-server".to_string()),
         otlp_endpoint.clone(),
         otlp_service_name.clone(),
@@ -268,21 +296,30 @@ pub async fn run(
         .or(max_batch_requests);
 
     // Queue logic
-    let queue = Queue::new(
-        backend.padded_model,
+    let limits = Arc::new(BatchLimits::new(
         max_batch_tokens,
         max_batch_requests,
+        max_client_batch_size,
         max_concurrent_requests,
-    );
+    ));
+    let queue = Queue::new(backend.padded_model, limits, max_concurrent_requests);
 
+    let backend_kind = backend.kind;
     // Create infer task
-    let infer = Infer::new(tokenization, queue, max_concurrent_requests, backend);
+    let infer = Infer::new(
+        tokenization,
+        queue,
+        max_concurrent_requests,
+        max_queue_wait,
+        backend,
+    );
 
     // Endpoint info
     let info = Info {
         model_id,
         model_sha: revision,
         model_dtype: dtype.to_string(),
+        quantization: quantization.map(|quantization| quantization.to_string()),
         model_type,
         max_concurrent_requests,
         max_input_length,
@@ -294,8 +331,104 @@ pub async fn run(
         version: env!("CARGO_PKG_VERSION"),
         sha: option_env!("VERGEN_GIT_SHA"),
         docker_label: option_env!("DOCKER_LABEL"),
+        build_timestamp: option_env!("BUILD_TIMESTAMP"),
+        features: enabled_features(),
+        backend_kind,
+        instruction_format: InstructionFormat::for_model_type(&config.model_type),
+        input_type_prompts,
+        dense_adapters,
+        splade_query_encoder,
     };
-    Ok((infer, info))
+    Ok((infer, info, query_encoder))
+}
+
+/// Cargo features of this service merged with the ones of `lib-embedding`
+fn enabled_features() -> Vec<&'static str> {
+    let mut features = lib_embedding::enabled_features();
+    for (name, enabled) in [
+        ("candle", cfg!(feature = "candle")),
+        ("ort", cfg!(feature = "ort")),
+        ("cuda", cfg!(feature = "cuda")),
+        ("metal", cfg!(feature = "metal")),
+        ("flash-attn", cfg!(feature = "flash-attn")),
+        ("flash-attn-v1", cfg!(feature = "flash-attn-v1")),
+    ] {
+        if enabled && !features.contains(&name) {
+            features.push(name);
+        }
+    }
+    features
+}
+
+/// Download the artifacts and weights of `model_id` into the hub cache without loading it, see
+/// `catalog::ModelCatalog`
+pub async fn download_model(
+    model_id: String,
+    pool_config: bool,
+    hf_token: Option<String>,
+    huggingface_hub_cache: Option<String>,
+) -> Result<()> {
+    let (_, api_repo) = resolve_model(
+        &model_id,
+        None,
+        pool_config,
+        hf_token,
+        huggingface_hub_cache.as_deref(),
+    )
+    .await?;
+    if let Some(api_repo) = api_repo {
+        lib_embedding::download_safetensors(&api_repo)
+            .await
+            .map_err(|err| Error::Custom(format!("Failed to download the weights: {err}")))?;
+    }
+    Ok(())
+}
+
+/// Local model directory, or the Hub snapshot of `model_id` with its repository
+async fn resolve_model(
+    model_id: &str,
+    revision: Option<String>,
+    pool_config: bool,
+    hf_token: Option<String>,
+    huggingface_hub_cache: Option<&str>,
+) -> Result<(PathBuf, Option<ApiRepo>)> {
+    let model_id_path = Path::new(model_id);
+    if model_id_path.exists() && model_id_path.is_dir() {
+        // Using a local model
+        return Ok((model_id_path.to_path_buf(), None));
+    }
+
+    let mut builder = ApiBuilder::from_env()
+        .with_progress(false)
+        .with_token(hf_token);
+
+    if let Some(cache_dir) = huggingface_hub_cache {
+        builder = builder.with_cache_dir(cache_dir.into());
+    }
+
+    if let Ok(origin) = std::env::var("HF_HUB_USER_AGENT_ORIGIN") {
+        builder = builder.with_user_agent("origin", origin.as_str());
+    }
+
+    let api = builder.build().unwrap();
+    let api_repo = api.repo(Repo::with_revision(
+        model_id.to_string(),
+        RepoType::Model,
+        revision.unwrap_or("main".to_string()),
+    ));
+
+    // Download model from the Hub
+    Ok((
+        download_artifacts(&api_repo, pool_config).await?,
+        Some(api_repo),
+    ))
+}
+
+fn load_model_config(model_root: &Path) -> Result<ModelConfig> {
+    let config = fs::read_to_string(model_root.join("config.json"))
+        .map_err(|_err| Error::Custom("`config.json` not found".into()))?;
+    serde_json::from_str(&config)
+        .map_err(|_err| Error::Custom("Failed to parse `config.json`".into()))
 }
 
 fn get_backend_model_type(
@@ -450,6 +583,9 @@ pub struct Info {
     pub model_sha: Option<String>,
     #[cfg_attr(feature = "http", schema(example = "float16"))]
     pub model_dtype: String,
+    /// Quantization of the linear layers, `None` when the model runs in `model_dtype`
+    #[cfg_attr(feature = "http", schema(nullable = true, example = "q8_0"))]
+    pub quantization: Option<String>,
     pub model_type: ModelType,
     /// Router Parameters
     #[cfg_attr(feature = "http", schema(example = "128"))]
@@ -475,6 +611,19 @@ pub struct Info {
     pub sha: Option<&'static str>,
     #[cfg_attr(feature = "http", schema(nullable = true, example = "null"))]
     pub docker_label: Option<&'static str>,
+    /// Unix timestamp of the build
+    pub build_timestamp: Option<&'static str>,
+    /// Cargo features of the service and the embedding backend
+    pub features: Vec<&'static str>,
+    pub backend_kind: lib_embedding::BackendKind,
+    /// Template applied to the `instruction` request field, `None` when the model takes none
+    pub instruction_format: Option<InstructionFormat>,
+    /// Prompt names applied for the `input_type` request field
+    pub input_type_prompts: InputTypePrompts,
+    /// Dense adapters loaded from outside the model repository, with their weights digest
+    pub dense_adapters: Vec<dense::DenseAdapter>,
+    /// Encoder of the `/embed_sparse` queries, `None` unless the model uses SPLADE pooling
+    pub splade_query_encoder: Option<SpladeQueryInfo>,
 }
 
 pub struct ResponseMetadata {
@@ -484,6 +633,8 @@ pub struct ResponseMetadata {
     tokenization_time: Duration,
     queue_time: Duration,
     inference_time: Duration,
+    embedding_stats: Option<EmbeddingStats>,
+    int8_scale: Option<Int8Scale>,
 }
 
 impl ResponseMetadata {
@@ -502,9 +653,43 @@ impl ResponseMetadata {
             tokenization_time,
             queue_time,
             inference_time,
+            embedding_stats: None,
+            int8_scale: None,
         }
     }
 
+    /// Returned as the `x-embedding-*` headers
+    pub fn with_embedding_stats(mut self, stats: Option<EmbeddingStats>) -> Self {
+        self.embedding_stats = stats;
+        self
+    }
+
+    /// Returned as the `x-embedding-scale` and `x-embedding-offset` headers of an `int8` output
+    pub fn with_int8_scale(mut self, scale: Option<Int8Scale>) -> Self {
+        self.int8_scale = scale;
+        self
+    }
+
+    pub fn compute_tokens(&self) -> usize {
+        self.compute_tokens
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.start_time.elapsed()
+    }
+
+    pub fn tokenization_time(&self) -> Duration {
+        self.tokenization_time
+    }
+
+    pub fn queue_time(&self) -> Duration {
+        self.queue_time
+    }
+
+    pub fn inference_time(&self) -> Duration {
+        self.inference_time
+    }
+
     pub fn record_span(&self, span: &Span) {
         // Tracing metadata
         span.record("compute_chars", self.compute_chars);
@@ -583,6 +768,28 @@ impl From<ResponseMetadata> for HeaderMap {
                 .parse()
                 .unwrap(),
         );
+        if let Some(stats) = value.embedding_stats {
+            headers.insert(
+                "x-embedding-mean-norm",
+                stats.norm.to_string().parse().unwrap(),
+            );
+            headers.insert("x-embedding-min", stats.min.to_string().parse().unwrap());
+            headers.insert("x-embedding-max", stats.max.to_string().parse().unwrap());
+            headers.insert(
+                "x-embedding-dimension",
+                stats.dimension.to_string().parse().unwrap(),
+            );
+        }
+        if let Some(scale) = value.int8_scale {
+            headers.insert(
+                "x-embedding-scale",
+                scale.scale.to_string().parse().unwrap(),
+            );
+            headers.insert(
+                "x-embedding-offset",
+                scale.offset.to_string().parse().unwrap(),
+            );
+        }
         headers
     }
 }
@@ -596,11 +803,12 @@ mod tests {
 
     #[tokio::test]
     async fn test_infer_embedding_and_info() -> Result<()> {
-        let (infer, info) = run(
+        let (infer, info, _) = run(
             "./Qwen3-Embedding-0.6B".to_string(),
             None,
             Some(2),
             Some(DType::Float32),
+            None,
             Some(Pool::Mean),
             2,
             512,
@@ -609,7 +817,7 @@ mod tests {
             true,
             None,
             None,
-            None,
+            vec![],
             None,
             None,
             None,
@@ -0,0 +1,376 @@
+//! `embed` subcommand: embeds newline-delimited text or a CSV column of a file or stdin with the
+//! local `Infer`, without the HTTP server, and writes the embeddings as JSONL, Parquet or NPY.
+//! Meant for backfills and air-gapped environments.
+
+use crate::ai::infer::Infer;
+use crate::error::{Error, Result};
+use crate::types::TruncationDirection;
+use arrow_array::{
+    ArrayRef, FixedSizeListArray, Float32Array, RecordBatch, StringArray, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use futures::{StreamExt, TryStreamExt};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// One `{"record", "text", "embedding"}` object per line
+    Jsonl,
+    /// `record`, `text` and a fixed size `embedding` list column
+    Parquet,
+    /// `float32` matrix of one row per record, in the input order
+    Npy,
+}
+
+impl OutputFormat {
+    /// Format of the `--output` extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "jsonl" | "ndjson" => Some(Self::Jsonl),
+            "parquet" => Some(Self::Parquet),
+            "npy" => Some(Self::Npy),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OfflineOptions {
+    /// Read from stdin when `None` or `-`
+    pub input: Option<PathBuf>,
+    /// CSV column holding the text, the input is newline-delimited text when `None`
+    pub column: Option<String>,
+    pub output: PathBuf,
+    pub format: OutputFormat,
+    /// Records read and written at once
+    pub batch_size: usize,
+    /// Requests in flight, above `--max-concurrent-requests` they wait for a permit
+    pub concurrency: usize,
+    pub truncate: bool,
+    pub prompt_name: Option<String>,
+    pub normalize: bool,
+    pub dimensions: Option<usize>,
+}
+
+/// Text of the input with its record number: the line, or the CSV row after the header, from 1
+type Record = (u64, String);
+
+pub async fn embed_offline(infer: Infer, options: OfflineOptions) -> Result<()> {
+    if infer.is_classifier() {
+        return Err(Error::Custom(
+            "`embed` only runs embedding models".to_string(),
+        ));
+    }
+    let mut records = read_records(options.input.as_deref(), options.column.as_deref())?;
+    let mut writer = EmbeddingWriter::create(&options.output, options.format)?;
+
+    let (infer, options) = (&infer, &options);
+    let mut total = 0;
+    loop {
+        let batch: Vec<Record> = records
+            .by_ref()
+            .take(options.batch_size.max(1))
+            .collect::<Result<_>>()?;
+        if batch.is_empty() {
+            break;
+        }
+        let embeddings: Vec<Vec<f32>> = futures::stream::iter(&batch)
+            .map(|(record, text)| async move {
+                embed(infer, text, options)
+                    .await
+                    .map_err(|e| Error::Custom(format!("Record {record}: {e}")))
+            })
+            .buffered(options.concurrency.max(1))
+            .try_collect()
+            .await?;
+        writer.write(&batch, &embeddings)?;
+        total += batch.len();
+        info!("Embedded {total} records");
+    }
+    writer.finish()?;
+    info!("Wrote {total} embeddings to {}", options.output.display());
+    Ok(())
+}
+
+/// Embedding of one record, waiting for a permit like the HTTP routes
+async fn embed(infer: &Infer, text: &str, options: &OfflineOptions) -> Result<Vec<f32>> {
+    let permit = infer.acquire_permit().await;
+    let response = infer
+        .embed_pooled(
+            text.to_string(),
+            options.truncate,
+            TruncationDirection::default().into(),
+            options.prompt_name.clone(),
+            // `normalize` is rejected for SPLADE models
+            options.normalize && !infer.is_splade(),
+            options.dimensions,
+            permit,
+        )
+        .await?;
+    Ok(response.results)
+}
+
+/// Non empty records of the input, the empty lines or cells are skipped
+fn read_records(
+    input: Option<&Path>,
+    column: Option<&str>,
+) -> Result<Box<dyn Iterator<Item = Result<Record>>>> {
+    let reader: Box<dyn BufRead> = match input {
+        Some(path) if path != Path::new("-") => {
+            Box::new(BufReader::new(File::open(path).map_err(|e| {
+                Error::Custom(format!("Cannot open {}: {e}", path.display()))
+            })?))
+        }
+        _ => Box::new(BufReader::new(std::io::stdin())),
+    };
+    let records: Box<dyn Iterator<Item = Result<Record>>> = match column {
+        None => Box::new(reader.lines().zip(1..).map(|(line, number)| {
+            line.map(|text| (number, text))
+                .map_err(|e| Error::Custom(format!("Cannot read line {number}: {e}")))
+        })),
+        Some(column) => {
+            let mut csv = csv::Reader::from_reader(reader);
+            let index = csv
+                .headers()
+                .map_err(|e| Error::Custom(format!("Cannot read the CSV header: {e}")))?
+                .iter()
+                .position(|header| header == column)
+                .ok_or_else(|| Error::Custom(format!("No CSV column `{column}`")))?;
+            Box::new(csv.into_records().zip(1..).map(move |(row, number)| {
+                row.map(|row| (number, row.get(index).unwrap_or_default().to_string()))
+                    .map_err(|e| Error::Custom(format!("Cannot read CSV row {number}: {e}")))
+            }))
+        }
+    };
+    Ok(Box::new(records.filter(
+        |record| !matches!(record, Ok((_, text)) if text.trim().is_empty()),
+    )))
+}
+
+#[derive(Serialize)]
+struct JsonlRecord<'a> {
+    record: u64,
+    text: &'a str,
+    embedding: &'a [f32],
+}
+
+/// Length of the NPY preamble, a multiple of 64 as the format requires. The header is written
+/// again with the final shape once every row is written.
+const NPY_PREAMBLE_LEN: usize = 128;
+
+enum EmbeddingWriter {
+    Jsonl(BufWriter<File>),
+    Parquet {
+        file: Option<File>,
+        writer: Option<ArrowWriter<File>>,
+        dimensions: Option<usize>,
+    },
+    Npy {
+        file: BufWriter<File>,
+        rows: usize,
+        dimensions: Option<usize>,
+    },
+}
+
+impl EmbeddingWriter {
+    fn create(path: &Path, format: OutputFormat) -> Result<Self> {
+        let file = File::create(path)
+            .map_err(|e| Error::Custom(format!("Cannot create {}: {e}", path.display())))?;
+        Ok(match format {
+            OutputFormat::Jsonl => Self::Jsonl(BufWriter::new(file)),
+            // The schema holds the dimensions, the writer is created with the first batch
+            OutputFormat::Parquet => Self::Parquet {
+                file: Some(file),
+                writer: None,
+                dimensions: None,
+            },
+            OutputFormat::Npy => {
+                let mut file = BufWriter::new(file);
+                file.write_all(&npy_header(0, 0)).map_err(write_error)?;
+                Self::Npy {
+                    file,
+                    rows: 0,
+                    dimensions: None,
+                }
+            }
+        })
+    }
+
+    fn write(&mut self, records: &[Record], embeddings: &[Vec<f32>]) -> Result<()> {
+        match self {
+            Self::Jsonl(file) => {
+                for ((record, text), embedding) in records.iter().zip(embeddings) {
+                    let line = JsonlRecord {
+                        record: *record,
+                        text,
+                        embedding,
+                    };
+                    serde_json::to_writer(&mut *file, &line)?;
+                    file.write_all(b"\n").map_err(write_error)?;
+                }
+            }
+            Self::Parquet {
+                file,
+                writer,
+                dimensions,
+            } => {
+                let dimensions = *dimensions.insert(same_dimensions(embeddings, *dimensions)?);
+                let schema = parquet_schema(dimensions);
+                let batch = parquet_batch(schema.clone(), dimensions, records, embeddings)?;
+                if writer.is_none() {
+                    let properties = WriterProperties::builder()
+                        .set_compression(Compression::SNAPPY)
+                        .build();
+                    let file = file.take().expect("file of the Parquet writer");
+                    *writer = Some(
+                        ArrowWriter::try_new(file, schema, Some(properties))
+                            .map_err(write_error)?,
+                    );
+                }
+                if let Some(writer) = writer {
+                    writer.write(&batch).map_err(write_error)?;
+                }
+            }
+            Self::Npy {
+                file,
+                rows,
+                dimensions,
+            } => {
+                *dimensions = Some(same_dimensions(embeddings, *dimensions)?);
+                for embedding in embeddings {
+                    for value in embedding {
+                        file.write_all(&value.to_le_bytes()).map_err(write_error)?;
+                    }
+                }
+                *rows += embeddings.len();
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Self::Jsonl(mut file) => file.flush().map_err(write_error),
+            Self::Parquet { writer, .. } => match writer {
+                Some(writer) => writer.close().map(|_| ()).map_err(write_error),
+                None => Ok(()),
+            },
+            Self::Npy {
+                mut file,
+                rows,
+                dimensions,
+            } => {
+                file.seek(SeekFrom::Start(0)).map_err(write_error)?;
+                file.write_all(&npy_header(rows, dimensions.unwrap_or(0)))
+                    .map_err(write_error)?;
+                file.flush().map_err(write_error)
+            }
+        }
+    }
+}
+
+fn write_error(err: impl std::fmt::Display) -> Error {
+    Error::Custom(format!("Cannot write the embeddings: {err}"))
+}
+
+/// Dimensions shared by every embedding, and `expected` when set
+fn same_dimensions(embeddings: &[Vec<f32>], expected: Option<usize>) -> Result<usize> {
+    let dimensions = expected.unwrap_or_else(|| embeddings.first().map_or(0, Vec::len));
+    match embeddings
+        .iter()
+        .find(|embedding| embedding.len() != dimensions)
+    {
+        Some(embedding) => Err(Error::Custom(format!(
+            "Embeddings of {} and {dimensions} dimensions cannot be written to the same file",
+            embedding.len()
+        ))),
+        None => Ok(dimensions),
+    }
+}
+
+fn parquet_schema(dimensions: usize) -> SchemaRef {
+    let item = Arc::new(Field::new("item", DataType::Float32, false));
+    Arc::new(Schema::new(vec![
+        Field::new("record", DataType::UInt64, false),
+        Field::new("text", DataType::Utf8, false),
+        Field::new(
+            "embedding",
+            DataType::FixedSizeList(item, dimensions as i32),
+            false,
+        ),
+    ]))
+}
+
+fn parquet_batch(
+    schema: SchemaRef,
+    dimensions: usize,
+    records: &[Record],
+    embeddings: &[Vec<f32>],
+) -> Result<RecordBatch> {
+    let numbers = UInt64Array::from_iter_values(records.iter().map(|(record, _)| *record));
+    let texts = StringArray::from_iter_values(records.iter().map(|(_, text)| text));
+    let values = Float32Array::from_iter_values(embeddings.iter().flatten().copied());
+    let item = Arc::new(Field::new("item", DataType::Float32, false));
+    let embeddings = FixedSizeListArray::try_new(item, dimensions as i32, Arc::new(values), None)
+        .map_err(write_error)?;
+    let columns: Vec<ArrayRef> = vec![Arc::new(numbers), Arc::new(texts), Arc::new(embeddings)];
+    RecordBatch::try_new(schema, columns).map_err(write_error)
+}
+
+/// NPY 1.0 preamble of a little endian `float32` matrix, padded to `NPY_PREAMBLE_LEN`
+fn npy_header(rows: usize, dimensions: usize) -> Vec<u8> {
+    let dict =
+        format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({rows}, {dimensions}), }}");
+    let header_len = NPY_PREAMBLE_LEN - 10;
+    let mut header = b"\x93NUMPY\x01\x00".to_vec();
+    header.extend_from_slice(&(header_len as u16).to_le_bytes());
+    header.extend_from_slice(dict.as_bytes());
+    header.resize(NPY_PREAMBLE_LEN - 1, b' ');
+    header.push(b'\n');
+    header
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_npy_header() {
+        let header = npy_header(3, 768);
+        assert_eq!(header.len(), NPY_PREAMBLE_LEN);
+        assert_eq!(&header[..8], b"\x93NUMPY\x01\x00");
+        assert_eq!(u16::from_le_bytes([header[8], header[9]]), 118);
+        let dict = String::from_utf8_lossy(&header[10..]);
+        assert!(dict.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (3, 768), }"));
+        assert!(dict.ends_with(" \n"));
+    }
+
+    #[test]
+    fn test_output_format() {
+        assert_eq!(
+            OutputFormat::from_path(Path::new("out/embeddings.Parquet")),
+            Some(OutputFormat::Parquet)
+        );
+        assert_eq!(
+            OutputFormat::from_path(Path::new("embeddings.ndjson")),
+            Some(OutputFormat::Jsonl)
+        );
+        assert_eq!(OutputFormat::from_path(Path::new("embeddings")), None);
+
+        assert_eq!(
+            same_dimensions(&[vec![0.0; 4], vec![1.0; 4]], None).unwrap(),
+            4
+        );
+        assert!(same_dimensions(&[vec![0.0; 4]], Some(8)).is_err());
+    }
+}
+// endregion: Unit Test
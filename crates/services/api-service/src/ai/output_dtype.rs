@@ -0,0 +1,184 @@
+//! Server-side quantization of pooled embeddings, requested with `output_dtype`. `int8` maps every
+//! component to `q = round((v - offset) / scale) - 128`, `binary` keeps the sign of every
+//! component, packed 8 per byte: 4x and 32x smaller than float32 to store.
+
+use crate::error::{Error, Result};
+use crate::types::{Embedding, EmbeddingMatrix, EncodingFormat};
+use lib_utils::base64::b64_encode;
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OutputDtype {
+    #[default]
+    Float32,
+    /// float32 values rounded to float16, base64 encodes the 2 bytes of every component
+    Float16,
+    /// One signed byte per component, with the `scale` and `offset` of `Int8Scale`
+    Int8,
+    /// One bit per component, set when it is positive, most significant bit first
+    Binary,
+}
+
+/// Affine map of the `int8` output, a component is restored as `(q + 128) * scale + offset`.
+/// Returned as the `x-embedding-scale` and `x-embedding-offset` headers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Int8Scale {
+    pub scale: f32,
+    pub offset: f32,
+}
+
+impl Int8Scale {
+    /// Range `[-1, 1]` of normalized embeddings, the same for every request so that the stored
+    /// vectors of several requests stay comparable
+    pub const NORMALIZED: Self = Self {
+        scale: 2.0 / 255.0,
+        offset: -1.0,
+    };
+
+    /// Range of the components of the response, for embeddings that are not normalized
+    pub(crate) fn fit(embeddings: &EmbeddingMatrix) -> Self {
+        let (min, max) = embeddings
+            .rows()
+            .flatten()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| {
+                (min.min(*v), max.max(*v))
+            });
+        if !min.is_finite() || !max.is_finite() {
+            return Self::NORMALIZED;
+        }
+        if max == min {
+            return Self {
+                scale: 1.0,
+                offset: min - 128.0,
+            };
+        }
+        Self {
+            scale: (max - min) / 255.0,
+            offset: min,
+        }
+    }
+
+    pub fn quantize(&self, row: &[f32]) -> Vec<i8> {
+        row.iter()
+            .map(|v| (((v - self.offset) / self.scale).round() - 128.0).clamp(-128.0, 127.0) as i8)
+            .collect()
+    }
+}
+
+/// Sign bits of the components, `ceil(dim / 8)` bytes
+pub fn binarize(row: &[f32]) -> Vec<u8> {
+    row.chunks(8)
+        .map(|bits| {
+            bits.iter()
+                .enumerate()
+                .filter(|(_, v)| **v > 0.0)
+                .fold(0u8, |byte, (i, _)| byte | (0x80 >> i))
+        })
+        .collect()
+}
+
+impl OutputDtype {
+    /// `base64_f16` already is the float16 output of the `base64` encoding, it cannot be combined
+    /// with another dtype
+    pub(crate) fn check_format(self, format: EncodingFormat) -> Result<()> {
+        match (self, format) {
+            (OutputDtype::Float32, _) | (_, EncodingFormat::Float | EncodingFormat::Base64) => {
+                Ok(())
+            }
+            (_, EncodingFormat::Base64F16) => Err(Error::BadRequest(
+                "`base64_f16` only encodes float32 embeddings, use `base64` with `output_dtype`"
+                    .to_string(),
+            )),
+        }
+    }
+
+    /// `embedding` of one row in this dtype: JSON numbers with `float`, the base64 text of the
+    /// little-endian bytes of the dtype with `base64`. `int8` is required by `OutputDtype::Int8`.
+    pub(crate) fn encode(
+        self,
+        row: &[f32],
+        format: EncodingFormat,
+        int8: Option<Int8Scale>,
+    ) -> Embedding {
+        let base64 = format != EncodingFormat::Float;
+        match self {
+            OutputDtype::Float32 => format.encode(row),
+            OutputDtype::Float16 if base64 => EncodingFormat::Base64F16.encode(row),
+            OutputDtype::Float16 => Embedding::Float(
+                row.iter()
+                    .map(|v| half::f16::from_f32(*v).to_f32())
+                    .collect(),
+            ),
+            OutputDtype::Int8 => {
+                let values = int8.unwrap_or(Int8Scale::NORMALIZED).quantize(row);
+                if base64 {
+                    Embedding::Base64(b64_encode(
+                        values.iter().map(|v| *v as u8).collect::<Vec<_>>(),
+                    ))
+                } else {
+                    Embedding::Int8(values)
+                }
+            }
+            OutputDtype::Binary if base64 => Embedding::Base64(b64_encode(binarize(row))),
+            OutputDtype::Binary => Embedding::Binary(binarize(row)),
+        }
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_int8_scale() -> Result<()> {
+        let scale = Int8Scale::NORMALIZED;
+        assert_eq!(
+            scale.quantize(&[-1.0, 0.0, 1.0, 2.0]),
+            vec![-128, 0, 127, 127]
+        );
+        let restored = (scale.quantize(&[0.5])[0] as f32 + 128.0) * scale.scale + scale.offset;
+        assert!((restored - 0.5).abs() < scale.scale);
+
+        let embeddings = EmbeddingMatrix::try_from(vec![vec![-4.0, 0.0], vec![6.0, 1.0]])?;
+        let fitted = Int8Scale::fit(&embeddings);
+        assert_eq!(fitted.offset, -4.0);
+        assert_eq!(fitted.quantize(&[-4.0, 6.0]), vec![-128, 127]);
+        let constant = Int8Scale::fit(&EmbeddingMatrix::try_from(vec![vec![3.0, 3.0]])?);
+        assert_eq!(constant.quantize(&[3.0]), vec![0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(
+            binarize(&[0.1, -0.2, 0.0, 0.3, 0.0, 0.0, 0.0, 0.0, 1.0]),
+            vec![0b1001_0000, 0b1000_0000]
+        );
+        let Embedding::Binary(bits) =
+            OutputDtype::Binary.encode(&[1.0; 9], EncodingFormat::Float, None)
+        else {
+            panic!("expected bits");
+        };
+        assert_eq!(bits, vec![0xff, 0x80]);
+        let Embedding::Base64(encoded) =
+            OutputDtype::Int8.encode(&[1.0, -1.0], EncodingFormat::Base64, None)
+        else {
+            panic!("expected base64");
+        };
+        assert_eq!(encoded, "f4A=");
+        assert!(
+            OutputDtype::Binary
+                .check_format(EncodingFormat::Base64F16)
+                .is_err()
+        );
+        assert!(
+            OutputDtype::Float32
+                .check_format(EncodingFormat::Base64F16)
+                .is_ok()
+        );
+    }
+}
+// endregion: Unit Test
@@ -1,12 +1,16 @@
 use crate::ai::infer::InferResult;
+use crate::ai::limits::BatchLimits;
 use crate::ai::tokenization::ValidEncoding;
 use crate::error::Result;
 use lib_embedding::core::Batch;
+use serde::Serialize;
 use std::cmp::max;
 use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot};
-use tracing::{Span, error, instrument};
+use tracing::{Span, debug, error, instrument};
 
 /// Queue entry
 #[derive(Debug)]
@@ -30,6 +34,109 @@ pub struct Metadata {
     pub(crate) prompt_tokens: usize,
     /// Pooled embedding
     pub(crate) pooling: bool,
+    /// `queue` span of the request, closed when the entry is handed to the backend
+    pub(crate) span: Span,
+}
+
+/// Entry waiting in the queue, as reported to operators
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PendingEntry {
+    /// `pooled` or `raw`, the queue is a single FIFO without other priorities
+    pub kind: &'static str,
+    pub prompt_tokens: usize,
+    /// Time spent in the queue so far
+    pub age_ms: u64,
+}
+
+impl PendingEntry {
+    fn of(metadata: &Metadata, now: Instant) -> Self {
+        Self {
+            kind: if metadata.pooling { "pooled" } else { "raw" },
+            prompt_tokens: metadata.prompt_tokens,
+            age_ms: now
+                .saturating_duration_since(metadata.queue_time)
+                .as_millis() as u64,
+        }
+    }
+}
+
+/// Weight of the last batch in the throughput of `QueueLoad`
+const THROUGHPUT_SMOOTHING: f64 = 0.2;
+
+/// Tokens waiting in the queue and running on the backend, with the throughput of the backend,
+/// to estimate how long a new request would wait
+#[derive(Debug, Default)]
+pub struct QueueLoad {
+    queued_tokens: AtomicUsize,
+    /// Tokens of the batches taken from the queue and not answered by the backend yet
+    inflight_tokens: AtomicUsize,
+    /// Tokens per second of the last batches, `f64` bits, `0` before the first batch
+    throughput: AtomicU64,
+}
+
+impl QueueLoad {
+    pub fn queued_tokens(&self) -> usize {
+        self.queued_tokens.load(Ordering::Relaxed)
+    }
+
+    pub fn inflight_tokens(&self) -> usize {
+        self.inflight_tokens.load(Ordering::Relaxed)
+    }
+
+    pub fn throughput(&self) -> f64 {
+        f64::from_bits(self.throughput.load(Ordering::Relaxed))
+    }
+
+    /// Time to run every queued and in-flight token at the current throughput, `None` until the
+    /// backend ran a batch
+    pub fn estimated_wait(&self) -> Option<Duration> {
+        let throughput = self.throughput();
+        (throughput > 0.0).then(|| {
+            let tokens = self.queued_tokens() + self.inflight_tokens();
+            Duration::from_secs_f64(tokens as f64 / throughput)
+        })
+    }
+
+    fn enqueue(&self, tokens: usize) {
+        self.queued_tokens.fetch_add(tokens, Ordering::Relaxed);
+    }
+
+    fn dequeue(&self, tokens: usize) {
+        let _ = self
+            .queued_tokens
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |queued| {
+                Some(queued.saturating_sub(tokens))
+            });
+    }
+
+    fn start_batch(&self, tokens: usize) {
+        self.dequeue(tokens);
+        self.inflight_tokens.fetch_add(tokens, Ordering::Relaxed);
+    }
+
+    /// `inference` is the duration of the batch, `None` when it failed
+    pub(crate) fn finish_batch(&self, tokens: usize, inference: Option<Duration>) {
+        let _ =
+            self.inflight_tokens
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |inflight| {
+                    Some(inflight.saturating_sub(tokens))
+                });
+        let Some(inference) = inference.filter(|d| !d.is_zero() && tokens > 0) else {
+            return;
+        };
+        let batch_throughput = tokens as f64 / inference.as_secs_f64();
+        let _ = self
+            .throughput
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                let current = f64::from_bits(bits);
+                let next = if current > 0.0 {
+                    current + THROUGHPUT_SMOOTHING * (batch_throughput - current)
+                } else {
+                    batch_throughput
+                };
+                Some(next.to_bits())
+            });
+    }
 }
 
 /// Request Queue
@@ -37,30 +144,48 @@ pub struct Metadata {
 pub struct Queue {
     /// Channel to communicate with the background queue task
     queue_sender: mpsc::Sender<QueueCommand>,
+    /// Batch limits, read by the background task for every batch
+    limits: Arc<BatchLimits>,
+    /// Updated by the background task and the backend task of `Infer`
+    load: Arc<QueueLoad>,
 }
 
 impl Queue {
     pub fn new(
         padded_model: bool,
-        max_batch_tokens: usize,
-        max_batch_requests: Option<usize>,
+        limits: Arc<BatchLimits>,
         max_concurrent_requests: usize,
     ) -> Self {
         // Create channels
         let (queue_sender, queue_receiver) = mpsc::channel(max_concurrent_requests);
 
         // Launch background queue task
+        let task_limits = limits.clone();
+        let load = Arc::new(QueueLoad::default());
+        let task_load = load.clone();
         std::thread::spawn(move || {
             queue_blocking_task(
                 padded_model,
-                max_batch_tokens,
-                max_batch_requests,
+                task_limits,
+                task_load,
                 max_concurrent_requests,
                 queue_receiver,
             )
         });
 
-        Self { queue_sender }
+        Self {
+            queue_sender,
+            limits,
+            load,
+        }
+    }
+
+    pub fn limits(&self) -> &Arc<BatchLimits> {
+        &self.limits
+    }
+
+    pub fn load(&self) -> &Arc<QueueLoad> {
+        &self.load
     }
 
     /// Append an entry to the queue
@@ -74,12 +199,12 @@ impl Queue {
             Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => {
                 // Queue full — backpressure or 429
                 error!("Queue is full");
-                Err(crate::error::Error::Custom("Too many requests".into()))
+                Err(crate::error::Error::QueueFull)
             }
             Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => {
                 error!("Queue background task terminated unexpectedly");
                 // Receiver dropped — real bug
-                Err(crate::error::Error::Custom(
+                Err(crate::error::Error::BackendUnhealthy(
                     "Queue background task terminated unexpectedly".into(),
                 ))
             }
@@ -106,6 +231,20 @@ impl Queue {
         response_receiver.await.unwrap_or_default()
     }
 
+    /// Entries waiting for a batch, oldest first
+    pub async fn inspect(&self) -> Vec<PendingEntry> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        let _ = self
+            .queue_sender
+            .send(QueueCommand::Inspect {
+                response_sender,
+                span: Span::current(),
+            })
+            .await;
+
+        response_receiver.await.unwrap_or_default()
+    }
+
     /// Get the next batch from the queue
     #[instrument(skip(self))]
     pub async fn next_batch(&self) -> Result<Option<NextBatch>> {
@@ -116,10 +255,10 @@ impl Queue {
         }) {
             Ok(_) => (),
             Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => {
-                return Err(crate::error::Error::Custom("Too many requests".into()));
+                return Err(crate::error::Error::QueueFull);
             }
             Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => {
-                return Err(crate::error::Error::Custom(
+                return Err(crate::error::Error::BackendUnhealthy(
                     "Queue background task terminated unexpectedly".into(),
                 ));
             }
@@ -136,19 +275,18 @@ impl Queue {
 // Background task responsible of the queue state
 fn queue_blocking_task(
     padded_model: bool,
-    max_batch_tokens: usize,
-    max_batch_requests: Option<usize>,
+    limits: Arc<BatchLimits>,
+    load: Arc<QueueLoad>,
     max_concurrent_requests: usize,
     mut queue_receiver: mpsc::Receiver<QueueCommand>,
 ) {
-    let capacity = max_batch_requests.unwrap_or(max_concurrent_requests);
-
     let mut entries: VecDeque<Entry> = VecDeque::with_capacity(max_concurrent_requests);
 
     while let Some(cmd) = queue_receiver.blocking_recv() {
         match cmd {
             QueueCommand::Append(entry, span) => {
                 let _span = span.entered();
+                load.enqueue(entry.encoding.input_ids.len());
                 entries.push_back(*entry);
                 let gauge = metrics::gauge!("te_queue_size");
                 gauge.increment(1.0);
@@ -159,16 +297,39 @@ fn queue_blocking_task(
             } => {
                 let _span = span.entered();
                 let drained: Vec<Entry> = entries.drain(..).collect();
+                load.dequeue(
+                    drained
+                        .iter()
+                        .map(|entry| entry.encoding.input_ids.len())
+                        .sum(),
+                );
                 let _ = response_sender.send(drained);
                 let gauge = metrics::gauge!("te_queue_size");
                 gauge.set(entries.len() as f64);
             }
+            QueueCommand::Inspect {
+                response_sender,
+                span,
+            } => {
+                let _span = span.entered();
+                let now = Instant::now();
+                let pending = entries
+                    .iter()
+                    .map(|entry| PendingEntry::of(&entry.metadata, now))
+                    .collect();
+                let _ = response_sender.send(pending);
+            }
             QueueCommand::NextBatch {
                 response_sender,
                 span,
             } => {
                 let _span = span.entered();
 
+                // Limits can be changed at runtime, read them once per batch
+                let max_batch_tokens = limits.max_batch_tokens();
+                let max_batch_requests = limits.max_batch_requests();
+                let capacity = max_batch_requests.unwrap_or(max_concurrent_requests);
+
                 let mut input_ids = Vec::with_capacity(max_batch_tokens);
                 let mut token_type_ids = Vec::with_capacity(max_batch_tokens);
                 let mut position_ids = Vec::with_capacity(max_batch_tokens);
@@ -188,6 +349,7 @@ fn queue_blocking_task(
                     // Filter entries where the response receiver was dropped (== entries where the request
                     // was dropped by the client)
                     if entry.metadata.response_tx.is_closed() {
+                        load.dequeue(entry.encoding.input_ids.len());
                         let counter = metrics::counter!("te_request_failure", "err" => "dropped");
                         counter.increment(1);
                         continue;
@@ -230,6 +392,14 @@ fn queue_blocking_task(
                 }
 
                 let batch_size = metadata.len();
+                load.start_batch(current_tokens);
+                let composition = BatchComposition::of(
+                    &metadata,
+                    current_tokens,
+                    max_length as usize,
+                    padded_model,
+                    Instant::now(),
+                );
                 let next_batch = if metadata.is_empty() {
                     None
                 } else {
@@ -254,7 +424,10 @@ fn queue_blocking_task(
                 let histogram = metrics::histogram!("te_batch_next_tokens");
                 histogram.record(current_tokens as f64);
                 let gauge = metrics::gauge!("te_queue_size");
-                gauge.set(entries.len() as f64)
+                gauge.set(entries.len() as f64);
+                if batch_size > 0 {
+                    composition.record(entries.len());
+                }
             }
         }
     }
@@ -262,6 +435,68 @@ fn queue_blocking_task(
 
 pub type NextBatch = (Vec<Metadata>, Batch);
 
+/// What a scheduled batch is made of, to check the batching limits against real traffic
+#[derive(Debug, PartialEq)]
+struct BatchComposition {
+    requests: usize,
+    /// Tokens of the inputs
+    tokens: usize,
+    /// Tokens computed by the backend, every input is padded to the longest one on padded models
+    padded_tokens: usize,
+    /// Time the oldest request of the batch waited in the queue
+    oldest_wait: Duration,
+    /// Requests returning the pooled embedding
+    pooled: usize,
+    /// Requests returning the embedding of every token
+    raw: usize,
+}
+
+impl BatchComposition {
+    fn of(
+        metadata: &[Metadata],
+        tokens: usize,
+        max_length: usize,
+        padded_model: bool,
+        now: Instant,
+    ) -> Self {
+        let pooled = metadata.iter().filter(|m| m.pooling).count();
+        Self {
+            requests: metadata.len(),
+            tokens,
+            padded_tokens: if padded_model {
+                max_length * metadata.len()
+            } else {
+                tokens
+            },
+            oldest_wait: metadata
+                .iter()
+                .map(|m| now.saturating_duration_since(m.queue_time))
+                .max()
+                .unwrap_or_default(),
+            pooled,
+            raw: metadata.len() - pooled,
+        }
+    }
+
+    /// Logged at DEBUG, the metrics complement `te_batch_next_size` and `te_batch_next_tokens`
+    fn record(&self, queued: usize) {
+        debug!(
+            requests = self.requests,
+            tokens = self.tokens,
+            padded_tokens = self.padded_tokens,
+            oldest_wait_ms = self.oldest_wait.as_millis() as u64,
+            pooled = self.pooled,
+            raw = self.raw,
+            queued,
+            "Batch scheduled"
+        );
+        metrics::histogram!("te_batch_next_padded_tokens").record(self.padded_tokens as f64);
+        metrics::histogram!("te_batch_next_oldest_wait").record(self.oldest_wait.as_secs_f64());
+        metrics::counter!("te_batch_requests", "kind" => "pooled").increment(self.pooled as u64);
+        metrics::counter!("te_batch_requests", "kind" => "raw").increment(self.raw as u64);
+    }
+}
+
 #[derive(Debug)]
 enum QueueCommand {
     Append(Box<Entry>, Span),
@@ -273,4 +508,98 @@ enum QueueCommand {
         response_sender: oneshot::Sender<Vec<Entry>>,
         span: Span,
     },
+    Inspect {
+        response_sender: oneshot::Sender<Vec<PendingEntry>>,
+        span: Span,
+    },
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(pooling: bool, queue_time: Instant) -> Metadata {
+        let (response_tx, _) = oneshot::channel();
+        Metadata {
+            response_tx,
+            tokenization: Duration::ZERO,
+            queue_time,
+            prompt_tokens: 0,
+            pooling,
+            span: Span::none(),
+        }
+    }
+
+    #[test]
+    fn test_batch_composition() {
+        let now = Instant::now();
+        let batch = vec![
+            metadata(true, now - Duration::from_millis(30)),
+            metadata(false, now - Duration::from_millis(120)),
+            metadata(true, now),
+        ];
+
+        let composition = BatchComposition::of(&batch, 40, 20, true, now);
+        assert_eq!(
+            composition,
+            BatchComposition {
+                requests: 3,
+                tokens: 40,
+                padded_tokens: 60,
+                oldest_wait: Duration::from_millis(120),
+                pooled: 2,
+                raw: 1,
+            }
+        );
+        assert_eq!(
+            BatchComposition::of(&batch, 40, 20, false, now).padded_tokens,
+            40
+        );
+    }
+
+    #[test]
+    fn test_queue_load() {
+        let load = QueueLoad::default();
+        load.enqueue(300);
+        assert_eq!(load.estimated_wait(), None);
+
+        load.start_batch(100);
+        load.finish_batch(100, Some(Duration::from_secs(1)));
+        assert_eq!(load.throughput(), 100.0);
+        assert_eq!(load.estimated_wait(), Some(Duration::from_secs(2)));
+
+        load.start_batch(200);
+        assert_eq!(load.inflight_tokens(), 200);
+        load.finish_batch(200, Some(Duration::from_millis(500)));
+        assert!((load.throughput() - 160.0).abs() < 1e-9);
+        assert_eq!(load.estimated_wait(), Some(Duration::ZERO));
+        // Failed batches leave the throughput as it is
+        load.enqueue(10);
+        load.start_batch(10);
+        load.finish_batch(10, None);
+        assert_eq!((load.queued_tokens(), load.inflight_tokens()), (0, 0));
+        assert!((load.throughput() - 160.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_pending_entry() {
+        let now = Instant::now();
+        let mut raw = metadata(false, now - Duration::from_millis(250));
+        raw.prompt_tokens = 12;
+
+        assert_eq!(
+            PendingEntry::of(&raw, now),
+            PendingEntry {
+                kind: "raw",
+                prompt_tokens: 12,
+                age_ms: 250,
+            }
+        );
+        // Entries queued after `now` report no age rather than underflowing
+        let pooled = metadata(true, now + Duration::from_millis(5));
+        assert_eq!(PendingEntry::of(&pooled, now).age_ms, 0);
+        assert_eq!(PendingEntry::of(&pooled, now).kind, "pooled");
+    }
 }
+// endregion: Unit Test
@@ -0,0 +1,128 @@
+//! Query side of SPLADE models.
+//!
+//! Documents are always encoded by the served model. Queries are encoded by the same model unless
+//! a separate query encoder is loaded (`--splade-query-model-id`), or, for inference-free models
+//! shipping an `idf.json`, weighted by the IDF of their tokens without running the model.
+
+use crate::ai::infer::Infer;
+use crate::error::{Error, Result};
+use crate::types::InputType;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// File of the inference-free models mapping every token to its query weight
+pub const IDF_FILE: &str = "idf.json";
+
+pub enum SpladeQueryEncoder {
+    /// Separate `ForMaskedLM` query model, sharing the vocabulary of the document model
+    Model { model_id: String, infer: Arc<Infer> },
+    /// IDF weight of every query token
+    Idf(IdfWeights),
+}
+
+impl SpladeQueryEncoder {
+    pub fn info(&self) -> SpladeQueryInfo {
+        match self {
+            SpladeQueryEncoder::Model { model_id, .. } => SpladeQueryInfo::Model {
+                model_id: model_id.clone(),
+            },
+            SpladeQueryEncoder::Idf(_) => SpladeQueryInfo::Idf,
+        }
+    }
+}
+
+/// How `input_type: query` is encoded, reported on `/info`
+#[derive(Clone, Debug, Serialize, PartialEq)]
+#[cfg_attr(feature = "http", derive(utoipa::ToSchema))]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SpladeQueryInfo {
+    /// Queries and documents use the served model
+    Shared,
+    Model {
+        model_id: String,
+    },
+    Idf,
+}
+
+/// Sparse query of the IDF weighting, with the tokenization metadata
+pub struct IdfEncoding {
+    /// (token id, weight), sorted by token id
+    pub values: Vec<(u32, f32)>,
+    pub prompt_tokens: usize,
+    pub tokenization: Duration,
+}
+
+pub struct IdfWeights {
+    weights: HashMap<String, f32>,
+}
+
+impl IdfWeights {
+    /// `None` when the model ships no `idf.json`
+    pub fn load(model_root: &Path) -> Result<Option<Self>> {
+        let Ok(content) = fs::read_to_string(model_root.join(IDF_FILE)) else {
+            return Ok(None);
+        };
+        let weights = serde_json::from_str(&content)
+            .map_err(|err| Error::Custom(format!("Failed to parse `{IDF_FILE}`: {err}")))?;
+        Ok(Some(Self { weights }))
+    }
+
+    /// Every distinct token of the input weighted by its IDF, special tokens are not added
+    pub async fn encode(
+        &self,
+        infer: &Infer,
+        input: InputType,
+        prompt_name: Option<String>,
+    ) -> Result<IdfEncoding> {
+        let start = Instant::now();
+        let (_, encoding) = infer.tokenize(input, false, prompt_name).await?;
+        let tokenization = start.elapsed();
+        Ok(IdfEncoding {
+            values: self.weigh(encoding.get_ids(), encoding.get_tokens()),
+            prompt_tokens: encoding.len(),
+            tokenization,
+        })
+    }
+
+    fn weigh(&self, ids: &[u32], tokens: &[String]) -> Vec<(u32, f32)> {
+        let mut values: Vec<(u32, f32)> = ids
+            .iter()
+            .zip(tokens)
+            .filter_map(|(id, token)| {
+                let weight = *self.weights.get(token)?;
+                (weight > 0.0).then_some((*id, weight))
+            })
+            .collect();
+        values.sort_unstable_by_key(|(id, _)| *id);
+        values.dedup_by_key(|(id, _)| *id);
+        values
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idf_weigh() {
+        let idf = IdfWeights {
+            weights: HashMap::from([
+                ("rust".to_string(), 3.5),
+                ("the".to_string(), 0.0),
+                ("lang".to_string(), 1.25),
+            ]),
+        };
+        let tokens: Vec<String> = ["rust", "the", "lang", "rust", "unknown"]
+            .iter()
+            .map(|token| token.to_string())
+            .collect();
+        let values = idf.weigh(&[42, 7, 13, 42, 99], &tokens);
+        assert_eq!(values, vec![(13, 1.25), (42, 3.5)]);
+    }
+}
+// endregion: Unit Test
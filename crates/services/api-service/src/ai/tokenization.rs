@@ -72,7 +72,7 @@ impl Tokenization {
     ) -> Result<ValidEncoding> {
         // Check if inputs is empty
         if inputs.is_empty() {
-            return Err(Error::Custom("`inputs` cannot be empty".to_string()));
+            return Err(Error::EmptyInput("`inputs` cannot be empty".to_string()));
         }
 
         // Create response channel
@@ -105,7 +105,7 @@ impl Tokenization {
     ) -> Result<(Option<String>, RawEncoding)> {
         // Check if inputs is empty
         if inputs.is_empty() {
-            return Err(Error::Custom("`inputs` cannot be empty".to_string()));
+            return Err(Error::EmptyInput("`inputs` cannot be empty".to_string()));
         }
 
         // Create response channel
@@ -132,7 +132,7 @@ impl Tokenization {
     pub async fn decode(&self, ids: Vec<u32>, skip_special_tokens: bool) -> Result<String> {
         // Check if inputs is empty
         if ids.is_empty() {
-            return Err(Error::Custom("`input_ids` cannot be empty".to_string()));
+            return Err(Error::EmptyInput("`input_ids` cannot be empty".to_string()));
         }
 
         // Create response channel
@@ -259,12 +259,12 @@ fn prepare_pre_prompt(
     let pre_prompt = if let Some(prompt_name) = prompt_name.as_ref() {
         match prompts {
             None => {
-                return Err(Error::Custom(format!(
+                return Err(Error::BadRequest(format!(
                     "`default-prompt-name` is set to `{prompt_name}` but no prompts were found in the Sentence Transformers configuration"
                 )));
             }
             Some(prompts) if !prompts.contains_key(prompt_name) => {
-                return Err(Error::Custom(format!(
+                return Err(Error::BadRequest(format!(
                     "`default-prompt-name` is set to `{prompt_name}` but it was not found in the Sentence Transformers prompts. Available prompts: {:?}",
                     prompts.keys()
                 )));
@@ -294,7 +294,7 @@ fn tokenize_input(
     let limit = max_input_length * MAX_CHAR_MULTIPLIER;
     if input_chars > limit {
         if truncate_params.is_none() {
-            return Err(Error::Custom(format!(
+            return Err(Error::InputTooLong(format!(
                 "`inputs` must have less than {limit} characters. Given: {input_chars}"
             )));
         }
@@ -319,7 +319,7 @@ fn tokenize_input(
         }
         EncodingInput::Dual(s1, s2) => {
             if pre_prompt.is_some() {
-                return Err(Error::Custom(
+                return Err(Error::BadRequest(
                     "`prompt_name` cannot be set with dual inputs".to_string(),
                 ));
             }
@@ -390,7 +390,7 @@ fn encode_input(
     let seq_len = encoding.len();
 
     if seq_len > max_input_length {
-        return Err(Error::Custom(format!(
+        return Err(Error::InputTooLong(format!(
             "`inputs` must have less than {max_input_length} tokens. Given: {seq_len}"
         )));
     }
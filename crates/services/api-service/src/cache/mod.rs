@@ -1,45 +1,116 @@
+use crate::ai::catalog::ModelCatalog;
+use crate::ai::chunk_embedder::InferChunkEmbedder;
+use crate::ai::limits::BatchLimits;
+use crate::ai::splade::SpladeQueryEncoder;
 use crate::ai::{Info, infer::Infer};
 use crate::error::Result;
+use crate::log::sampling::{RequestSampler, SamplingConfig};
+use crate::middleware::mw_governor::{RateLimitKey, RateLimits, RateTier};
+use crate::middleware::mw_kill_switch::RouteToggles;
 use aws_sdk_s3::Client;
 use lib_core::database::ModelManager;
-use lib_core::model::user::Role;
+use lib_core::model::user::{Role, UserRateLimit};
 use lib_cron::ChronJobs;
+use lib_cron::config::auth_config;
+use lib_cron::embedder::ChunkEmbedder;
+use lib_cron::hf_cache::CacheCleanup;
 use lib_storage::create_aws_client;
+use lib_storage::store::create_object_store;
 use moka::future::Cache;
 use serde::Serialize;
 use std::sync::Arc;
+use uuid::Uuid;
 
 #[derive(Clone)]
 pub struct AppState {
     pub aws_client: Arc<Client>,
     pub cache_user: Cache<String, UserCacheData>,
     pub cron_jobs: ChronJobs,
-    pub infer: Arc<Infer>,
-    pub info: Arc<Info>,
+    /// Served model and the warm standby models, see `infer` and `info`
+    pub models: Arc<ModelCatalog>,
     pub mm: Arc<ModelManager>,
+    /// Query encoder of a SPLADE model, when it is not the served model itself
+    pub splade_query: Option<Arc<SpladeQueryEncoder>>,
+    /// Set when request sampling is enabled (`SAMPLING_RATE` / `SAMPLING_BUCKET`)
+    pub sampler: Option<Arc<RequestSampler>>,
+    /// Bytes above which array responses are streamed (`--stream-response-threshold`)
+    pub stream_threshold: usize,
+    /// Routes disabled through the admin kill-switch
+    pub route_toggles: Arc<RouteToggles>,
+    /// Rate limit tiers of the roles and the limiter state
+    pub rate_limits: Arc<RateLimits>,
+    /// Inputs per request of the rate limit tier of the caller, set per request by `rate_limit`
+    pub client_batch_limit: Option<usize>,
 }
 
 #[derive(Clone, Serialize, Debug)]
 pub struct UserCacheData {
     pub user_id: String,
+    pub tenant_id: String,
     pub role: Role, // Adjust as needed for tokens usage, requests limits ect.
+    #[serde(skip)]
+    pub salt: Uuid,
+    /// Salted hash of the API key
+    #[serde(skip)]
+    pub api_key: Option<String>,
+    /// Overrides of the rate limit tier of the role
+    pub rate_limit: UserRateLimit,
 }
 
 impl AppState {
-    pub async fn new(mm: Arc<ModelManager>, info: Arc<Info>, infer: Arc<Infer>) -> Result<Self> {
+    pub async fn new(
+        mm: Arc<ModelManager>,
+        models: Arc<ModelCatalog>,
+        splade_query: Option<Arc<SpladeQueryEncoder>>,
+        cache_cleanup: CacheCleanup,
+        stream_threshold: usize,
+        rate_limit_key: RateLimitKey,
+        default_rate_tier: RateTier,
+    ) -> Result<Self> {
         let client = create_aws_client().await;
         let aws_client = Arc::new(client);
         let cache_user = Cache::builder()
             .time_to_live(std::time::Duration::from_secs(600))
             .build(); //short term cache for user data
-        let cron_jobs = ChronJobs::new(mm.clone(), aws_client.clone()).await?;
+        let embedder: Arc<dyn ChunkEmbedder> = Arc::new(InferChunkEmbedder::new(models.clone()));
+        // Store of the ingested files, S3, GCS or Azure depending on `STORAGE_BACKEND`
+        let storage = create_object_store(&auth_config().bucket).await?;
+        let cron_jobs = ChronJobs::new(mm.clone(), storage, cache_cleanup, Some(embedder)).await?;
+        let sampler = match SamplingConfig::load_from_env()? {
+            Some(config) => Some(RequestSampler::start(config, &mm, aws_client.clone()).await),
+            None => None,
+        };
+        let route_toggles = Arc::new(RouteToggles::load(&mm).await);
+        let rate_limits = Arc::new(RateLimits::load(&mm, rate_limit_key, default_rate_tier).await);
         Ok(AppState {
             aws_client,
             cache_user,
             cron_jobs,
-            infer,
-            info,
+            models,
             mm,
+            splade_query,
+            sampler,
+            stream_threshold,
+            route_toggles,
+            rate_limits,
+            client_batch_limit: None,
         })
     }
+
+    /// `max_client_batch_size` of the model, capped by the rate limit tier of the caller
+    pub fn max_client_batch_size(&self, limits: &BatchLimits) -> usize {
+        let max = limits.max_client_batch_size();
+        self.client_batch_limit.map_or(max, |limit| limit.min(max))
+    }
+
+    /// Inference queue of the served model, read once per request so that a request runs on a
+    /// single model during a switchover
+    pub fn infer(&self) -> Arc<Infer> {
+        self.models.served().infer.clone()
+    }
+
+    /// Info of the served model
+    pub fn info(&self) -> Arc<Info> {
+        self.models.served().info.clone()
+    }
 }
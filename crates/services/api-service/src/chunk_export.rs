@@ -0,0 +1,218 @@
+//! Columnar encoding of chunks for `GET /export/chunks`: Arrow IPC streams or Parquet files with
+//! the ids, the text and the embedding of every chunk, written page after page so that an export
+//! is never held in memory.
+
+use crate::error::{Error, Result};
+use arrow_array::builder::{Float32Builder, ListBuilder};
+use arrow_array::{ArrayRef, Int32Array, Int64Array, RecordBatch, StringArray};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use lib_core::model::file_chunks::FileChunk;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Parquet,
+    /// Arrow IPC stream
+    Arrow,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+            ExportFormat::Arrow => "application/vnd.apache.arrow.stream",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::Arrow => "arrows",
+        }
+    }
+}
+
+/// `chunk_id`, `file_id`, `chunk_index`, `content_md`, `embedding_model` and `embedding`, a list
+/// since the chunks of different collections have different dimensions
+pub fn chunk_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("chunk_id", DataType::Int64, false),
+        Field::new("file_id", DataType::Int64, false),
+        Field::new("chunk_index", DataType::Int32, false),
+        Field::new("content_md", DataType::Utf8, true),
+        Field::new("embedding_model", DataType::Utf8, true),
+        Field::new(
+            "embedding",
+            DataType::List(Arc::new(Field::new_list_field(DataType::Float32, true))),
+            true,
+        ),
+    ]))
+}
+
+fn chunk_batch(schema: SchemaRef, chunks: &[FileChunk]) -> Result<RecordBatch> {
+    let mut embeddings = ListBuilder::new(Float32Builder::new());
+    for chunk in chunks {
+        match &chunk.embedding {
+            Some(embedding) => {
+                embeddings.values().append_slice(embedding.as_slice());
+                embeddings.append(true);
+            }
+            None => embeddings.append(false),
+        }
+    }
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(Int64Array::from_iter_values(
+            chunks.iter().map(|chunk| chunk.chunk_id),
+        )),
+        Arc::new(Int64Array::from_iter_values(
+            chunks.iter().map(|chunk| chunk.file_id),
+        )),
+        Arc::new(Int32Array::from_iter_values(
+            chunks.iter().map(|chunk| chunk.chunk_index),
+        )),
+        Arc::new(StringArray::from_iter(
+            chunks.iter().map(|chunk| chunk.content_md.as_deref()),
+        )),
+        Arc::new(StringArray::from_iter(
+            chunks.iter().map(|chunk| chunk.embedding_model.as_deref()),
+        )),
+        Arc::new(embeddings.finish()),
+    ];
+    RecordBatch::try_new(schema, columns).map_err(encode_error)
+}
+
+enum Writer {
+    Arrow(StreamWriter<Vec<u8>>),
+    Parquet(ArrowWriter<Vec<u8>>),
+}
+
+/// Encodes the pages of an export. Every call returns the bytes written since the previous one,
+/// which are sent right away.
+pub struct ChunkEncoder {
+    schema: SchemaRef,
+    writer: Writer,
+}
+
+impl ChunkEncoder {
+    pub fn new(format: ExportFormat) -> Result<Self> {
+        let schema = chunk_schema();
+        let writer = match format {
+            ExportFormat::Arrow => {
+                Writer::Arrow(StreamWriter::try_new(Vec::new(), &schema).map_err(encode_error)?)
+            }
+            ExportFormat::Parquet => {
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                Writer::Parquet(
+                    ArrowWriter::try_new(Vec::new(), schema.clone(), Some(properties))
+                        .map_err(encode_error)?,
+                )
+            }
+        };
+        Ok(Self { schema, writer })
+    }
+
+    /// Write `chunks` as one record batch, one row group in Parquet
+    pub fn encode(&mut self, chunks: &[FileChunk]) -> Result<Vec<u8>> {
+        if chunks.is_empty() {
+            return Ok(Vec::new());
+        }
+        let batch = chunk_batch(self.schema.clone(), chunks)?;
+        match &mut self.writer {
+            Writer::Arrow(writer) => {
+                writer.write(&batch).map_err(encode_error)?;
+                Ok(std::mem::take(writer.get_mut()))
+            }
+            Writer::Parquet(writer) => {
+                writer.write(&batch).map_err(encode_error)?;
+                writer.flush().map_err(encode_error)?;
+                // Bytes still buffered by the writer come with the next call
+                Ok(std::mem::take(writer.inner_mut()))
+            }
+        }
+    }
+
+    /// Remaining bytes: the end of the stream, or the Parquet footer
+    pub fn finish(self) -> Result<Vec<u8>> {
+        match self.writer {
+            Writer::Arrow(mut writer) => {
+                writer.finish().map_err(encode_error)?;
+                writer.into_inner().map_err(encode_error)
+            }
+            Writer::Parquet(writer) => writer.into_inner().map_err(encode_error),
+        }
+    }
+}
+
+fn encode_error(err: impl std::fmt::Display) -> Error {
+    Error::Custom(format!("Cannot encode the chunks: {err}"))
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::Array;
+    use arrow_array::cast::AsArray;
+    use arrow_ipc::reader::StreamReader;
+    use lib_core::model::file_chunks::ChunkMetadata;
+    use pgvector::Vector;
+
+    fn chunk(chunk_id: i64, embedding: Option<Vec<f32>>) -> FileChunk {
+        FileChunk {
+            chunk_id,
+            file_id: 7,
+            tenant_id: "tenant".to_string(),
+            chunk_index: chunk_id as i32,
+            content_md: Some(format!("chunk {chunk_id}")),
+            embedding: embedding.map(Vector::from),
+            embedding_model: Some("bge-small".to_string()),
+            embedding_dim: Some(3),
+            embedding_normalized: true,
+            token_count: None,
+            oversize: None,
+            metadata: ChunkMetadata::default(),
+            content_hash: None,
+        }
+    }
+
+    #[test]
+    fn test_chunk_encoder() -> Result<()> {
+        let mut encoder = ChunkEncoder::new(ExportFormat::Arrow)?;
+        let mut bytes = encoder.encode(&[chunk(1, Some(vec![0.1, 0.2, 0.3])), chunk(2, None)])?;
+        bytes.extend(encoder.encode(&[])?);
+        bytes.extend(encoder.encode(&[chunk(3, Some(vec![1.0, 0.0, 0.0]))])?);
+        bytes.extend(encoder.finish()?);
+
+        let batches = StreamReader::try_new(bytes.as_slice(), None)
+            .unwrap()
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].schema(), chunk_schema());
+        let embeddings = batches[0].column(5).as_list::<i32>();
+        assert!(embeddings.is_null(1));
+        assert_eq!(
+            embeddings
+                .value(0)
+                .as_primitive::<arrow_array::types::Float32Type>()
+                .values(),
+            &[0.1, 0.2, 0.3]
+        );
+
+        let mut encoder = ChunkEncoder::new(ExportFormat::Parquet)?;
+        let mut bytes = encoder.encode(&[chunk(1, Some(vec![0.1, 0.2, 0.3]))])?;
+        bytes.extend(encoder.finish()?);
+        assert!(bytes.starts_with(b"PAR1") && bytes.ends_with(b"PAR1"));
+        Ok(())
+    }
+}
+// endregion: Unit Test
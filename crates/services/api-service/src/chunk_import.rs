@@ -0,0 +1,229 @@
+//! Records of `POST /import/chunks`: chunks with an embedding computed elsewhere, as JSONL or as an
+//! Arrow IPC stream with the same columns.
+
+use crate::error::{Error, Result};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float32Type, Int64Type};
+use arrow_array::{Array, ArrayRef, RecordBatch};
+use arrow_ipc::reader::StreamReader;
+use arrow_schema::DataType;
+use lib_core::model::file_chunks::ChunkMetadata;
+use serde::Deserialize;
+
+/// Largest distance of the L2 norm to 1 of an embedding considered normalized
+const UNIT_NORM_TOLERANCE: f32 = 1e-3;
+
+/// Chunk to import, its file is given by id or by name
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct ImportRecord {
+    #[serde(default)]
+    pub file_id: Option<i64>,
+    #[serde(default)]
+    pub filename: Option<String>,
+    pub text: String,
+    pub embedding: Vec<f32>,
+    #[serde(default)]
+    pub metadata: ChunkMetadata,
+    /// Model that computed `embedding`, the served model when missing
+    #[serde(default)]
+    pub embedding_model: Option<String>,
+    /// Whether `embedding` has a unit L2 norm, computed when missing
+    #[serde(default)]
+    pub normalized: Option<bool>,
+}
+
+impl ImportRecord {
+    pub fn is_normalized(&self) -> bool {
+        self.normalized.unwrap_or_else(|| {
+            let norm = self.embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+            (norm - 1.0).abs() <= UNIT_NORM_TOLERANCE
+        })
+    }
+}
+
+/// One record per non blank line
+pub fn parse_jsonl(body: &[u8]) -> Result<Vec<ImportRecord>> {
+    body.split(|byte| *byte == b'\n')
+        .enumerate()
+        .filter(|(_, line)| !line.trim_ascii().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_slice(line)
+                .map_err(|e| Error::BadRequest(format!("Line {}: {e}", index + 1)))
+        })
+        .collect()
+}
+
+/// Columns `text` and `embedding` (list or fixed size list of `float32`), `file_id` (`int64`)
+/// or `filename`, and optionally `metadata` (JSON text), `embedding_model` and `normalized`
+pub fn parse_arrow(body: &[u8]) -> Result<Vec<ImportRecord>> {
+    let reader = StreamReader::try_new(body, None)
+        .map_err(|e| Error::BadRequest(format!("Invalid Arrow IPC stream: {e}")))?;
+    let mut records = Vec::new();
+    for batch in reader {
+        let batch =
+            batch.map_err(|e| Error::BadRequest(format!("Invalid Arrow record batch: {e}")))?;
+        records.extend(batch_records(&batch, records.len())?);
+    }
+    Ok(records)
+}
+
+fn batch_records(batch: &RecordBatch, offset: usize) -> Result<Vec<ImportRecord>> {
+    let column = |name: &str| batch.column_by_name(name);
+    let file_ids = column("file_id")
+        .map(|array| {
+            array
+                .as_primitive_opt::<Int64Type>()
+                .ok_or_else(|| column_error("file_id", "int64"))
+        })
+        .transpose()?;
+    let filenames = column("filename")
+        .map(|a| utf8(a, "filename"))
+        .transpose()?;
+    let texts = utf8(
+        column("text").ok_or_else(|| column_error("text", "utf8"))?,
+        "text",
+    )?;
+    let metadata = column("metadata")
+        .map(|a| utf8(a, "metadata"))
+        .transpose()?;
+    let models = column("embedding_model")
+        .map(|a| utf8(a, "embedding_model"))
+        .transpose()?;
+    let normalized = column("normalized")
+        .map(|array| {
+            array
+                .as_boolean_opt()
+                .ok_or_else(|| column_error("normalized", "bool"))
+        })
+        .transpose()?;
+    let embeddings = column("embedding").ok_or_else(|| column_error("embedding", "list"))?;
+
+    (0..batch.num_rows())
+        .map(|row| {
+            let record = offset + row + 1;
+            let value = |array: Option<&arrow_array::StringArray>| {
+                array
+                    .filter(|array| array.is_valid(row))
+                    .map(|array| array.value(row).to_string())
+            };
+            let metadata = match value(metadata) {
+                Some(json) => serde_json::from_str(&json).map_err(|e| {
+                    Error::BadRequest(format!("Record {record}: invalid `metadata`: {e}"))
+                })?,
+                None => ChunkMetadata::default(),
+            };
+            Ok(ImportRecord {
+                file_id: file_ids
+                    .filter(|array| array.is_valid(row))
+                    .map(|array| array.value(row)),
+                filename: value(filenames),
+                text: value(Some(texts)).unwrap_or_default(),
+                embedding: embedding(embeddings, row).ok_or_else(|| {
+                    Error::BadRequest(format!("Record {record}: `embedding` is missing"))
+                })??,
+                metadata,
+                embedding_model: value(models),
+                normalized: normalized
+                    .filter(|array| array.is_valid(row))
+                    .map(|array| array.value(row)),
+            })
+        })
+        .collect()
+}
+
+/// `float32` values of the list at `row`, `None` when it is null
+fn embedding(array: &ArrayRef, row: usize) -> Option<Result<Vec<f32>>> {
+    if array.is_null(row) {
+        return None;
+    }
+    let values = match array.data_type() {
+        DataType::List(_) => array.as_list::<i32>().value(row),
+        DataType::LargeList(_) => array.as_list::<i64>().value(row),
+        DataType::FixedSizeList(_, _) => array.as_fixed_size_list().value(row),
+        _ => return Some(Err(column_error("embedding", "list of float32"))),
+    };
+    Some(
+        values
+            .as_primitive_opt::<Float32Type>()
+            .map(|values| values.values().to_vec())
+            .ok_or_else(|| column_error("embedding", "list of float32")),
+    )
+}
+
+fn utf8<'a>(array: &'a ArrayRef, name: &str) -> Result<&'a arrow_array::StringArray> {
+    array
+        .as_string_opt::<i32>()
+        .ok_or_else(|| column_error(name, "utf8"))
+}
+
+fn column_error(name: &str, expected: &str) -> Error {
+    Error::BadRequest(format!("Column `{name}` must be {expected}"))
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::builder::{FixedSizeListBuilder, Float32Builder};
+    use arrow_array::{Int64Array, StringArray};
+    use arrow_ipc::writer::StreamWriter;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_parse_jsonl() -> Result<()> {
+        let body = br#"{"file_id": 3, "text": "One", "embedding": [0.6, 0.8]}
+
+{"filename": "docs/a.md", "text": "Two", "embedding": [1, 1], "metadata": {"page": 4}}
+"#;
+        let records = parse_jsonl(body)?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].file_id, Some(3));
+        assert!(records[0].is_normalized());
+        assert_eq!(records[1].filename.as_deref(), Some("docs/a.md"));
+        assert_eq!(records[1].metadata.page, Some(4));
+        assert!(!records[1].is_normalized());
+
+        let err = parse_jsonl(b"{\"text\": \"no embedding\"}").unwrap_err();
+        assert!(err.to_string().contains("Line 1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_arrow() -> Result<()> {
+        let mut embeddings = FixedSizeListBuilder::new(Float32Builder::new(), 2);
+        for values in [[0.6, 0.8], [1.0, 1.0]] {
+            embeddings.values().append_slice(&values);
+            embeddings.append(true);
+        }
+        let batch = RecordBatch::try_from_iter([
+            (
+                "file_id",
+                Arc::new(Int64Array::from(vec![Some(3), None])) as ArrayRef,
+            ),
+            (
+                "filename",
+                Arc::new(StringArray::from(vec![None, Some("docs/a.md")])),
+            ),
+            ("text", Arc::new(StringArray::from(vec!["One", "Two"]))),
+            ("embedding", Arc::new(embeddings.finish())),
+            (
+                "metadata",
+                Arc::new(StringArray::from(vec![None, Some(r#"{"page": 4}"#)])),
+            ),
+        ])
+        .unwrap();
+        let mut writer = StreamWriter::try_new(Vec::new(), &batch.schema()).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+
+        let records = parse_arrow(&writer.into_inner().unwrap())?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].file_id, Some(3));
+        assert_eq!(records[0].embedding, vec![0.6, 0.8]);
+        assert_eq!(records[1].filename.as_deref(), Some("docs/a.md"));
+        assert_eq!(records[1].metadata.page, Some(4));
+        assert!(parse_arrow(b"not arrow").is_err());
+        Ok(())
+    }
+}
+// endregion: Unit Test
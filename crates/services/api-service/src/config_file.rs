@@ -0,0 +1,403 @@
+//! `--config server.toml` (or `.yaml`): the command line arguments and the settings of every
+//! crate in one typed file, instead of the env variables each crate reads on its own.
+//!
+//! The file only fills the env variables that are not set, so the precedence is command line,
+//! then env, then file. The crates keep reading their settings with `get_env`, the sections map
+//! readable keys to their variables, e.g. `storage.aws_region` to `AM_REGION` or
+//! `auth.password_key` to `AUTH_PWD_KEY`. `[server]` takes the arguments by their long name,
+//! e.g. `model_id` or `max_batch_tokens`.
+
+use crate::error::{Error, Result};
+use clap::Command;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Settings of the crates as `section.key => ENV_VAR`, all of them optional in the file
+macro_rules! config_sections {
+    ($(
+        $(#[$doc:meta])*
+        $section:ident: $name:ident {
+            $($key:ident: $ty:ty => $env:literal,)*
+        }
+    )*) => {
+        $(
+            $(#[$doc])*
+            #[derive(Debug, Default, Deserialize)]
+            #[serde(deny_unknown_fields)]
+            pub struct $name {
+                $(pub $key: Option<$ty>,)*
+            }
+
+            impl $name {
+                fn entries(&self) -> Vec<EnvEntry> {
+                    let mut entries = Vec::new();
+                    $(
+                        if let Some(value) = &self.$key {
+                            entries.push(EnvEntry {
+                                key: concat!(stringify!($section), ".", stringify!($key))
+                                    .to_string(),
+                                env: $env.to_string(),
+                                value: value.to_string(),
+                            });
+                        }
+                    )*
+                    entries
+                }
+            }
+        )*
+
+        /// Keys of the file by env variable, to name the missing ones
+        const FILE_KEYS: &[(&str, &str)] = &[
+            $($(($env, concat!(stringify!($section), ".", stringify!($key))),)*)*
+        ];
+
+        #[derive(Debug, Default, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        pub struct ServerConfig {
+            /// Command line arguments by their long name
+            #[serde(default)]
+            pub server: BTreeMap<String, ArgValue>,
+            $(
+                #[serde(default)]
+                pub $section: $name,
+            )*
+        }
+
+        impl ServerConfig {
+            fn section_entries(&self) -> Vec<EnvEntry> {
+                let mut entries = Vec::new();
+                $(entries.extend(self.$section.entries());)*
+                entries
+            }
+        }
+    };
+}
+
+config_sections! {
+    /// Postgres and the chunk text (lib-core)
+    database: DatabaseSection {
+        url: String => "DATABASE_URL",
+        read_url: String => "DATABASE_READ_URL",
+        max_connections: u64 => "DB_MAX_CONNECTIONS",
+        min_connections: u64 => "DB_MIN_CONNECTIONS",
+        acquire_timeout_sec: u64 => "DB_ACQUIRE_TIMEOUT_SEC",
+        statement_timeout_ms: u64 => "DB_STATEMENT_TIMEOUT_MS",
+        idle_timeout_sec: u64 => "DB_IDLE_TIMEOUT_SEC",
+        max_lifetime_sec: u64 => "DB_MAX_LIFETIME_SEC",
+        connect_retries: u64 => "DB_CONNECT_RETRIES",
+        chunk_compression: bool => "CHUNK_COMPRESSION",
+        chunk_content_bucket: String => "CHUNK_CONTENT_BUCKET",
+        chunk_content_cache_size: u64 => "CHUNK_CONTENT_CACHE_SIZE",
+    }
+
+    /// pgvector storage, index and distance of the chunk embeddings (lib-core)
+    vector: VectorSection {
+        storage: String => "EMBEDDING_STORAGE",
+        index_method: String => "VECTOR_INDEX_METHOD",
+        index_m: u64 => "VECTOR_INDEX_M",
+        index_ef_construction: u64 => "VECTOR_INDEX_EF_CONSTRUCTION",
+        index_lists: u64 => "VECTOR_INDEX_LISTS",
+        ef_search: u64 => "VECTOR_EF_SEARCH",
+        ivfflat_probes: u64 => "VECTOR_IVFFLAT_PROBES",
+        embeddings_normalized: bool => "EMBEDDINGS_NORMALIZED",
+        distance: String => "VECTOR_DISTANCE",
+    }
+
+    /// Object store of the uploaded files (lib-storage)
+    storage: StorageSection {
+        backend: String => "STORAGE_BACKEND",
+        upload_bucket: String => "UPLOAD_BUCKET",
+        aws_region: String => "AM_REGION",
+        aws_access_key_id: String => "AM_ACCESS_KEY_ID",
+        aws_secret_access_key: String => "AM_ACCESS_KEY",
+        aws_role_arn: String => "AM_ROLE_ARN",
+        aws_external_id: String => "AM_EXTERNAL_ID",
+        aws_role_session_name: String => "AM_ROLE_SESSION_NAME",
+        sse: String => "S3_SSE",
+        sse_kms_key_id: String => "S3_SSE_KMS_KEY_ID",
+        sse_bucket_key: bool => "S3_SSE_BUCKET_KEY",
+    }
+
+    /// API keys, passwords and tokens (lib-auth and the server)
+    auth: AuthSection {
+        api_key: String => "API_KEY",
+        hash_salt: String => "HASH_SALT",
+        password_key: String => "AUTH_PWD_KEY",
+        token_key: String => "AUTH_TOKEN_KEY",
+        token_duration_sec: u64 => "TOKEN_DURATION_SEC",
+        validation_duration_sec: u64 => "VALIDATION_DURATION_SEC",
+        jwt_hs256_secret: String => "JWT_HS256_SECRET",
+        jwt_jwks_url: String => "JWT_JWKS_URL",
+        jwt_jwks_ttl_sec: u64 => "JWT_JWKS_TTL_SEC",
+        jwt_issuer: String => "JWT_ISSUER",
+        jwt_audience: String => "JWT_AUDIENCE",
+        jwt_tenant_claim: String => "JWT_TENANT_CLAIM",
+        jwt_role_claim: String => "JWT_ROLE_CLAIM",
+        introspection_url: String => "AUTH_INTROSPECTION_URL",
+        introspection_client_id: String => "AUTH_INTROSPECTION_CLIENT_ID",
+        introspection_client_secret: String => "AUTH_INTROSPECTION_CLIENT_SECRET",
+        introspection_cache_sec: u64 => "AUTH_INTROSPECTION_CACHE_SEC",
+        introspection_tenant_claim: String => "AUTH_INTROSPECTION_TENANT_CLAIM",
+        introspection_role_claim: String => "AUTH_INTROSPECTION_ROLE_CLAIM",
+    }
+
+    /// Parsing and chunking of the files (lib-cron)
+    ingest: IngestSection {
+        parser_url: String => "PARSER_URL",
+        parser_file_url: String => "PARSER_FILE_URL",
+        tika_url: String => "TIKA_URL",
+        unstructured_url: String => "UNSTRUCTURED_URL",
+        unstructured_api_key: String => "UNSTRUCTURED_API_KEY",
+        parsers: String => "PARSERS",
+        csv_rows_per_chunk: u64 => "CSV_ROWS_PER_CHUNK",
+        language_prompts: String => "LANGUAGE_PROMPTS",
+        file_parallelism: u64 => "FILE_PARALLELISM",
+        max_tokens: u64 => "MAX_TOKENS",
+        max_chunks_per_file: u64 => "MAX_CHUNKS_PER_FILE",
+        max_tokens_per_file: u64 => "MAX_TOKENS_PER_FILE",
+        chunk_overflow: String => "CHUNK_OVERFLOW",
+        max_chunk_chars: u64 => "MAX_CHUNK_CHARS",
+        chunk_oversize: String => "CHUNK_OVERSIZE",
+        file_retention_days: u64 => "FILE_RETENTION_DAYS",
+        reembed_batch_size: u64 => "REEMBED_BATCH_SIZE",
+        tag_processing_status: bool => "TAG_PROCESSING_STATUS",
+    }
+
+    /// Scheduled jobs (lib-cron)
+    cron: CronSection {
+        distributed_lock: bool => "CRON_DISTRIBUTED_LOCK",
+        hf_cache_max_age_days: u64 => "HF_CACHE_MAX_AGE_DAYS",
+        hf_cache_max_size_gb: f64 => "HF_CACHE_MAX_SIZE_GB",
+    }
+
+    /// Chunks pulled from a primary (lib-cron)
+    replication: ReplicationSection {
+        primary_url: String => "REPLICATION_PRIMARY_URL",
+        api_key: String => "REPLICATION_API_KEY",
+        batch_size: u64 => "REPLICATION_BATCH_SIZE",
+    }
+
+    /// Golden set evaluation (lib-cron)
+    evaluation: EvaluationSection {
+        k: u64 => "EVAL_K",
+        recall_drop: f64 => "EVAL_RECALL_DROP",
+        alert_webhook: String => "EVAL_ALERT_WEBHOOK",
+    }
+
+    /// Request sampling of the server
+    sampling: SamplingSection {
+        rate: f64 => "SAMPLING_RATE",
+        bucket: String => "SAMPLING_BUCKET",
+        prefix: String => "SAMPLING_PREFIX",
+        fields: String => "SAMPLING_FIELDS",
+        batch_size: u64 => "SAMPLING_BATCH_SIZE",
+        flush_sec: u64 => "SAMPLING_FLUSH_SEC",
+        hash_key: String => "SAMPLING_HASH_KEY",
+    }
+}
+
+/// Variables without a default, a crate fails on its first use when one is missing
+const REQUIRED_ENV: &[&str] = &[
+    "DATABASE_URL",
+    "UPLOAD_BUCKET",
+    "MAX_TOKENS",
+    "API_KEY",
+    "HASH_SALT",
+    "AUTH_PWD_KEY",
+    "AUTH_TOKEN_KEY",
+    "TOKEN_DURATION_SEC",
+    "VALIDATION_DURATION_SEC",
+];
+
+/// Value of a command line argument, lists are joined with the delimiter of the argument
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ArgValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    List(Vec<ArgValue>),
+}
+
+impl ArgValue {
+    fn to_env(&self, delimiter: char) -> String {
+        match self {
+            ArgValue::Bool(value) => value.to_string(),
+            ArgValue::Integer(value) => value.to_string(),
+            ArgValue::Float(value) => value.to_string(),
+            ArgValue::String(value) => value.clone(),
+            ArgValue::List(values) => values
+                .iter()
+                .map(|value| value.to_env(delimiter))
+                .collect::<Vec<_>>()
+                .join(&delimiter.to_string()),
+        }
+    }
+}
+
+/// Key of the file and the env variable it sets
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvEntry {
+    pub key: String,
+    pub env: String,
+    pub value: String,
+}
+
+impl ServerConfig {
+    /// TOML or YAML by the extension of `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            Error::Custom(format!("Cannot read config file {}: {e}", path.display()))
+        })?;
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        match extension.to_ascii_lowercase().as_str() {
+            "toml" => toml::from_str(&content).map_err(|e| e.to_string()),
+            "yaml" | "yml" => serde_yaml::from_str(&content).map_err(|e| e.to_string()),
+            _ => Err("the extension must be `.toml`, `.yaml` or `.yml`".to_string()),
+        }
+        .map_err(|e| Error::Custom(format!("Invalid config file {}: {e}", path.display())))
+    }
+
+    /// Env variables set by the file. `[server]` keys are checked against the arguments of
+    /// `command`, all the unknown ones are reported at once.
+    pub fn entries(&self, command: &Command) -> Result<Vec<EnvEntry>> {
+        let mut entries = self.section_entries();
+        let mut unknown = Vec::new();
+        for (key, value) in &self.server {
+            let arg = command
+                .get_arguments()
+                .find(|arg| arg.get_id().as_str() == key);
+            let Some((arg, env)) = arg.and_then(|arg| Some((arg, arg.get_env()?))) else {
+                unknown.push(format!("server.{key}"));
+                continue;
+            };
+            entries.push(EnvEntry {
+                key: format!("server.{key}"),
+                env: env.to_string_lossy().to_string(),
+                value: value.to_env(arg.get_value_delimiter().unwrap_or(',')),
+            });
+        }
+        if !unknown.is_empty() {
+            return Err(Error::Custom(format!(
+                "Unknown config keys: {}",
+                unknown.join(", ")
+            )));
+        }
+        Ok(entries)
+    }
+
+    /// Set the variables of the file that are not set in the env, returns how many were set.
+    ///
+    /// # Safety
+    /// No other thread may read or write the env meanwhile, call it before spawning any task.
+    pub unsafe fn apply(&self, command: &Command) -> Result<usize> {
+        let mut applied = 0;
+        for entry in self.entries(command)? {
+            if std::env::var_os(&entry.env).is_none() {
+                // SAFETY: single threaded access to the env, see the caller contract
+                unsafe { std::env::set_var(&entry.env, &entry.value) };
+                applied += 1;
+            }
+        }
+        Ok(applied)
+    }
+}
+
+/// Required variables set neither in the env nor in the config file, with their key in the file
+pub fn missing_keys(get: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    REQUIRED_ENV
+        .iter()
+        .filter(|env| get(env).is_none())
+        .map(|env| match FILE_KEYS.iter().find(|(e, _)| e == env) {
+            Some((_, key)) => format!("{key} ({env})"),
+            None => env.to_string(),
+        })
+        .collect()
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    fn command() -> Command {
+        Command::new("test")
+            .arg(Arg::new("port").long("port").env("PORT"))
+            .arg(
+                Arg::new("route_timeout")
+                    .long("route-timeout")
+                    .env("ROUTE_TIMEOUT")
+                    .value_delimiter(';'),
+            )
+    }
+
+    #[test]
+    fn test_entries() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            [server]
+            port = 8080
+            route_timeout = ["/api/v1/embed=5s", "/api/v1/search=2s"]
+
+            [storage]
+            aws_region = "eu-west-1"
+
+            [auth]
+            token_duration_sec = 3600
+            "#,
+        )
+        .unwrap();
+        let entries = config.entries(&command()).unwrap();
+        let env = |name: &str| {
+            entries
+                .iter()
+                .find(|entry| entry.env == name)
+                .map(|entry| entry.value.clone())
+        };
+        assert_eq!(env("AM_REGION").as_deref(), Some("eu-west-1"));
+        assert_eq!(env("TOKEN_DURATION_SEC").as_deref(), Some("3600"));
+        assert_eq!(env("PORT").as_deref(), Some("8080"));
+        assert_eq!(
+            env("ROUTE_TIMEOUT").as_deref(),
+            Some("/api/v1/embed=5s;/api/v1/search=2s")
+        );
+
+        // The same settings in YAML
+        let yaml: ServerConfig =
+            serde_yaml::from_str("server:\n  port: 8080\nstorage:\n  aws_region: eu-west-1\n")
+                .unwrap();
+        assert_eq!(yaml.entries(&command()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let config: ServerConfig = toml::from_str("[server]\nprot = 8080\nmodel = \"x\"").unwrap();
+        let err = config.entries(&command()).unwrap_err().to_string();
+        assert!(err.contains("server.model") && err.contains("server.prot"));
+        // Typos in the sections are rejected while parsing
+        assert!(toml::from_str::<ServerConfig>("[storage]\naws_regoin = \"x\"").is_err());
+    }
+
+    #[test]
+    fn test_missing_keys() {
+        let set = [("DATABASE_URL", "postgres://"), ("STORAGE_BACKEND", "fs")];
+        let get = |env: &str| {
+            set.iter()
+                .find(|(e, _)| *e == env)
+                .map(|(_, value)| value.to_string())
+        };
+        let missing = missing_keys(get);
+        assert!(missing.contains(&"auth.password_key (AUTH_PWD_KEY)".to_string()));
+        assert!(!missing.iter().any(|key| key.contains("DATABASE_URL")));
+        // The AWS region and credentials come from the default chain when unset
+        assert!(!missing_keys(|_| None).iter().any(|key| key.contains("AM_")));
+        assert!(missing_keys(|_| Some("s3".to_string())).is_empty());
+    }
+}
+// endregion: Unit Test
@@ -1,7 +1,7 @@
 pub type Result<T> = core::result::Result<T, Error>;
-use axum::body::Body;
+use crate::types::ErrorResponse;
 use axum::http::StatusCode;
-use axum::response::{IntoResponse, Response};
+use axum::response::{IntoResponse, Json, Response};
 use serde::Serialize;
 
 #[derive(Debug, Clone, utoipa::ToSchema, Serialize)]
@@ -10,6 +10,38 @@ pub enum Error {
     InvalidTokenFromCtx,
     UnableToExtractKey,
     AuthenticationFails(String),
+    /// Malformed or out of range request parameters
+    BadRequest(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    /// Embedding whose dimension is not the one of its collection
+    DimensionMismatch(String),
+
+    // -- Inference, see `ErrorType` for the values returned to clients
+    /// Empty `inputs`, `texts` or `chunks`
+    EmptyInput(String),
+    /// Input longer than the character or token limit of the model
+    InputTooLong(String),
+    /// Input the tokenizer could not encode
+    Tokenizer(String),
+    /// Failure of the model backend while running a batch
+    Backend(String),
+    /// No permit left to run the request
+    Overloaded,
+    /// Caller over the requests per second of its rate limit tier
+    RateLimited(String),
+    QueueFull,
+    /// Pending request rejected by an operator flushing the queue
+    QueueFlushed,
+    /// Request still running at the deadline of its route
+    Timeout(String),
+    /// Request body not received before the deadline of its route
+    RequestTimeout(String),
+    BatchTooLarge(String),
+    BackendUnhealthy(String),
+    /// Route turned off through the admin kill-switch, with the reason
+    RouteDisabled(String),
     MissingEnv(&'static str),
     WrongFormat(&'static str),
     FailToDateParse(String),
@@ -24,14 +56,48 @@ impl core::fmt::Display for Error {
     }
 }
 
+impl Error {
+    /// Status of the error response, the `error_type` of its body is `ErrorType::from`
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::UnableToExtractKey
+            | Error::InvalidTokenFromCtx
+            | Error::FailToB64uDecode
+            | Error::AuthenticationFails(_) => StatusCode::UNAUTHORIZED,
+            Error::BadRequest(_) | Error::EmptyInput(_) => StatusCode::BAD_REQUEST,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::BatchTooLarge(_) | Error::InputTooLong(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::DimensionMismatch(_) | Error::Tokenizer(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::Backend(_) => StatusCode::FAILED_DEPENDENCY,
+            Error::QueueFull | Error::Overloaded | Error::RateLimited(_) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            Error::BackendUnhealthy(_) | Error::RouteDisabled(_) | Error::QueueFlushed => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Error::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Error::RequestTimeout(_) => StatusCode::REQUEST_TIMEOUT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// `ErrorResponse` body, the error is also stored in the response extensions for the request log
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        let status = StatusCode::INTERNAL_SERVER_ERROR;
-
-        Response::builder()
-            .status(status)
-            .body(Body::from(format!("{self:?}")))
-            .unwrap()
+        let status = self.status();
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => tracing::warn!("Returning 429: {self}"),
+            _ if status.is_server_error() || status == StatusCode::FAILED_DEPENDENCY => {
+                tracing::error!("Handler error: {self}")
+            }
+            _ => {}
+        }
+        let mut response = (status, Json(ErrorResponse::from(self.clone()))).into_response();
+        response.extensions_mut().insert(self);
+        response
     }
 }
 
@@ -45,18 +111,48 @@ impl From<lib_utils::error::Error> for Error {
 
 impl From<lib_embedding::error::Error> for Error {
     fn from(err: lib_embedding::error::Error) -> Self {
-        Error::Custom(err.to_string())
+        match err {
+            lib_embedding::error::Error::Unhealthy => Error::BackendUnhealthy(err.to_string()),
+            lib_embedding::error::Error::TokenizerError(msg) => Error::Tokenizer(msg),
+            _ => Error::Custom(err.to_string()),
+        }
     }
 }
 
 impl From<lib_core::error::Error> for Error {
     fn from(err: lib_core::error::Error) -> Self {
-        Error::Custom(err.to_string())
+        match err {
+            lib_core::error::Error::DimensionMismatch {
+                collection,
+                expected,
+                actual,
+                model,
+            } => Error::DimensionMismatch(format!(
+                "collection `{collection}` expects embeddings of dimension {expected}, got {actual} from model `{}`",
+                model.as_deref().unwrap_or("unknown")
+            )),
+            lib_core::error::Error::DuplicateContent => {
+                Error::Conflict("A chunk repeats the text of a chunk of its file".to_string())
+            }
+            _ => Error::Custom(err.to_string()),
+        }
     }
 }
 
 impl From<lib_cron::error::Error> for Error {
     fn from(err: lib_cron::error::Error) -> Self {
+        match err {
+            lib_cron::error::Error::UnknownJobType(job_type) => {
+                Error::BadRequest(format!("Unknown job type `{job_type}`"))
+            }
+            lib_cron::error::Error::InvalidSchedule(msg) => Error::BadRequest(msg),
+            _ => Error::Custom(err.to_string()),
+        }
+    }
+}
+
+impl From<lib_storage::error::Error> for Error {
+    fn from(err: lib_storage::error::Error) -> Self {
         Error::Custom(err.to_string())
     }
 }
@@ -87,7 +183,7 @@ impl From<candle_core::Error> for Error {
 
 impl From<tokenizers::Error> for Error {
     fn from(err: tokenizers::Error) -> Self {
-        Error::Custom(err.to_string())
+        Error::Tokenizer(err.to_string())
     }
 }
 
@@ -1,3 +1,6 @@
+pub mod sampling;
+pub mod subscriber;
+
 use crate::error::{Error, Result};
 use axum::http::{Method, Uri};
 use chrono::prelude::*;
@@ -12,6 +15,7 @@ struct RequestLogLine {
     timestamp: String,
 
     user_id: Option<String>,
+    tenant_id: Option<String>,
     http_method: String,
     http_path: String,
 
@@ -25,7 +29,8 @@ pub async fn log_request(
     ctx: Option<Ctx>,
     web_error: Option<Error>,
 ) -> Result<()> {
-    let user_id = ctx.map(|c| c.user_id());
+    let user_id = ctx.as_ref().map(|c| c.user_id());
+    let tenant_id = ctx.as_ref().map(|c| c.tenant_id());
     let http_method = http_method.to_string();
     let http_path = uri.path().to_string();
 
@@ -35,6 +40,7 @@ pub async fn log_request(
         uuid,
         timestamp: Utc::now().to_rfc3339(),
         user_id,
+        tenant_id,
         http_method,
         http_path,
         error,
@@ -0,0 +1,384 @@
+//! Privacy-gated sampling of requests for offline workload modeling.
+//!
+//! Disabled unless `SAMPLING_RATE` is above zero and `SAMPLING_BUCKET` is set. A sampled request
+//! is reduced to a `SampleRecord`: texts and tenants are only stored as keyed hashes
+//! (`SAMPLING_HASH_KEY`), never in clear. `SAMPLING_FIELDS` further restricts the optional fields
+//! written, and tenants opted out through the admin API are never sampled.
+//!
+//! Records are buffered and written to
+//! `s3://{SAMPLING_BUCKET}/{SAMPLING_PREFIX}{date}/{uuid}.jsonl`.
+
+use crate::ai::ResponseMetadata;
+use crate::error::{Error, Result};
+use crate::types::{Input, InputType};
+use aws_sdk_s3::Client;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use lib_core::database::ModelManager;
+use lib_core::model::settings::SettingMac;
+use lib_storage::config::encryption;
+use lib_storage::functions::file::upload_file;
+use lib_utils::base64::b64u_encode;
+use lib_utils::envs::get_env;
+use serde::Serialize;
+use serde_with::skip_serializing_none;
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Settings key of the tenants opted out of sampling
+const OPT_OUT_SETTING: &str = "sampling_opt_out";
+/// Records waiting for upload, samples are dropped when the writer falls behind
+const CHANNEL_CAPACITY: usize = 10_000;
+
+/// Optional fields of a `SampleRecord`, `SAMPLING_FIELDS` lists the ones written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleField {
+    Tenant,
+    InputHashes,
+    TokenCount,
+    Latency,
+    ResultIds,
+}
+
+impl SampleField {
+    const ALL: [SampleField; 5] = [
+        SampleField::Tenant,
+        SampleField::InputHashes,
+        SampleField::TokenCount,
+        SampleField::Latency,
+        SampleField::ResultIds,
+    ];
+}
+
+impl FromStr for SampleField {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "tenant" => Ok(SampleField::Tenant),
+            "input_hashes" => Ok(SampleField::InputHashes),
+            "token_count" => Ok(SampleField::TokenCount),
+            "latency" => Ok(SampleField::Latency),
+            "result_ids" => Ok(SampleField::ResultIds),
+            other => Err(Error::Custom(format!("Unknown sampling field `{other}`"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SamplingConfig {
+    /// Fraction of the requests sampled, between 0 and 1 (`SAMPLING_RATE`)
+    pub rate: f64,
+    pub bucket: String,
+    pub prefix: String,
+    /// Key of the HMAC used to anonymize texts and tenants (`SAMPLING_HASH_KEY`)
+    pub hash_key: Vec<u8>,
+    pub fields: HashSet<SampleField>,
+    /// Records per uploaded object (`SAMPLING_BATCH_SIZE`)
+    pub batch_size: usize,
+    /// Upload of a partial batch (`SAMPLING_FLUSH_SEC`)
+    pub flush_interval: Duration,
+}
+
+impl SamplingConfig {
+    /// `None` when sampling is disabled
+    pub fn load_from_env() -> Result<Option<SamplingConfig>> {
+        let rate: f64 = get_env("SAMPLING_RATE").unwrap_or(0.0);
+        let Ok(bucket) = get_env::<String>("SAMPLING_BUCKET") else {
+            return Ok(None);
+        };
+        if rate <= 0.0 {
+            return Ok(None);
+        }
+        if rate > 1.0 {
+            return Err(Error::Custom(
+                "`SAMPLING_RATE` must be between 0 and 1".to_string(),
+            ));
+        }
+
+        // Without a key the hashes are only stable for the lifetime of the process
+        let hash_key = get_env::<String>("SAMPLING_HASH_KEY")
+            .map(String::into_bytes)
+            .unwrap_or_else(|_| uuid::Uuid::new_v4().as_bytes().to_vec());
+        let fields = match get_env::<String>("SAMPLING_FIELDS") {
+            Ok(fields) => fields
+                .split(',')
+                .filter(|field| !field.trim().is_empty())
+                .map(SampleField::from_str)
+                .collect::<Result<HashSet<_>>>()?,
+            Err(_) => SampleField::ALL.into_iter().collect(),
+        };
+
+        Ok(Some(SamplingConfig {
+            rate,
+            bucket,
+            prefix: get_env("SAMPLING_PREFIX").unwrap_or_else(|_| "samples/".to_string()),
+            hash_key,
+            fields,
+            batch_size: get_env("SAMPLING_BATCH_SIZE").unwrap_or(1_000),
+            flush_interval: Duration::from_secs(get_env("SAMPLING_FLUSH_SEC").unwrap_or(60)),
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SampleLatency {
+    pub total_ms: f64,
+    pub tokenization_ms: f64,
+    pub queue_ms: f64,
+    pub inference_ms: f64,
+}
+
+impl From<&ResponseMetadata> for SampleLatency {
+    fn from(metadata: &ResponseMetadata) -> Self {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        Self {
+            total_ms: ms(metadata.total_time()),
+            tokenization_ms: ms(metadata.tokenization_time()),
+            queue_ms: ms(metadata.queue_time()),
+            inference_ms: ms(metadata.inference_time()),
+        }
+    }
+}
+
+/// The only shape a sample is written in, nothing outside these fields leaves the process
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SampleRecord {
+    pub timestamp: String,
+    pub route: &'static str,
+    pub status: u16,
+    pub input_count: usize,
+    pub tenant: Option<String>,
+    pub input_hashes: Option<Vec<String>>,
+    pub token_count: Option<usize>,
+    pub latency: Option<SampleLatency>,
+    pub result_ids: Option<Vec<String>>,
+}
+
+impl SampleRecord {
+    pub fn new(route: &'static str, status: u16, input_count: usize) -> Self {
+        Self {
+            timestamp: Utc::now().to_rfc3339(),
+            route,
+            status,
+            input_count,
+            tenant: None,
+            input_hashes: None,
+            token_count: None,
+            latency: None,
+            result_ids: None,
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: &ResponseMetadata) -> Self {
+        self.token_count = Some(metadata.compute_tokens());
+        self.latency = Some(SampleLatency::from(metadata));
+        self
+    }
+
+    /// Drop every optional field outside the allowlist
+    fn restrict(mut self, fields: &HashSet<SampleField>) -> Self {
+        if !fields.contains(&SampleField::Tenant) {
+            self.tenant = None;
+        }
+        if !fields.contains(&SampleField::InputHashes) {
+            self.input_hashes = None;
+        }
+        if !fields.contains(&SampleField::TokenCount) {
+            self.token_count = None;
+        }
+        if !fields.contains(&SampleField::Latency) {
+            self.latency = None;
+        }
+        if !fields.contains(&SampleField::ResultIds) {
+            self.result_ids = None;
+        }
+        self
+    }
+}
+
+pub struct RequestSampler {
+    config: SamplingConfig,
+    opt_out: RwLock<HashSet<String>>,
+    tx: mpsc::Sender<SampleRecord>,
+}
+
+impl RequestSampler {
+    /// Start the writer task and restore the persisted opt-outs
+    pub async fn start(
+        config: SamplingConfig,
+        mm: &ModelManager,
+        client: Arc<Client>,
+    ) -> Arc<Self> {
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let opt_out = match SettingMac::get_value::<HashSet<String>>(mm, OPT_OUT_SETTING).await {
+            Ok(opt_out) => opt_out.unwrap_or_default(),
+            Err(err) => {
+                tracing::warn!("Could not load sampling opt-outs: {err}");
+                HashSet::new()
+            }
+        };
+        tokio::spawn(write_samples(config.clone(), client, rx));
+        tracing::info!(
+            "Request sampling enabled: rate {}, fields {:?}",
+            config.rate,
+            config.fields
+        );
+
+        Arc::new(Self {
+            config,
+            opt_out: RwLock::new(opt_out),
+            tx,
+        })
+    }
+
+    pub fn should_sample(&self, tenant: &str) -> bool {
+        !self.opt_out.read().unwrap().contains(tenant) && fastrand::f64() < self.config.rate
+    }
+
+    /// Keyed hash, b64u encoded
+    pub fn hash(&self, content: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.config.hash_key)
+            .expect("HMAC accepts keys of any size");
+        mac.update(content);
+        b64u_encode(mac.finalize().into_bytes())
+    }
+
+    pub fn hash_input(&self, input: &InputType) -> String {
+        match input {
+            InputType::String(text) => self.hash(text.as_bytes()),
+            InputType::Ids(ids) => {
+                let bytes: Vec<u8> = ids.iter().flat_map(|id| id.to_le_bytes()).collect();
+                self.hash(&bytes)
+            }
+        }
+    }
+
+    pub fn hash_inputs(&self, inputs: &Input) -> Vec<String> {
+        match inputs {
+            Input::Single(input) => vec![self.hash_input(input)],
+            Input::Batch(inputs) => inputs.iter().map(|input| self.hash_input(input)).collect(),
+        }
+    }
+
+    /// Queue a record for upload, the tenant is hashed here
+    pub fn record(&self, tenant: &str, mut record: SampleRecord) {
+        record.tenant = Some(self.hash(tenant.as_bytes()));
+        let record = record.restrict(&self.config.fields);
+        if self.tx.try_send(record).is_err() {
+            metrics::counter!("te_sampling_dropped").increment(1);
+        }
+    }
+
+    pub fn opted_out(&self) -> Vec<String> {
+        let mut tenants: Vec<String> = self.opt_out.read().unwrap().iter().cloned().collect();
+        tenants.sort();
+        tenants
+    }
+
+    /// Opt a tenant in or out, persisted in the settings
+    pub async fn set_opt_out(&self, mm: &ModelManager, tenant: &str, opt_out: bool) -> Result<()> {
+        let tenants = {
+            let mut tenants = self.opt_out.write().unwrap();
+            if opt_out {
+                tenants.insert(tenant.to_string());
+            } else {
+                tenants.remove(tenant);
+            }
+            tenants.clone()
+        };
+        SettingMac::set_value(mm, OPT_OUT_SETTING, &tenants).await?;
+        Ok(())
+    }
+
+    pub fn config(&self) -> &SamplingConfig {
+        &self.config
+    }
+}
+
+/// Upload the records as JSON lines, one object per `batch_size` records or `flush_interval`
+async fn write_samples(
+    config: SamplingConfig,
+    client: Arc<Client>,
+    mut rx: mpsc::Receiver<SampleRecord>,
+) {
+    let mut buffer = Vec::with_capacity(config.batch_size);
+    let mut interval = tokio::time::interval(config.flush_interval);
+    loop {
+        let (flush, closed) = tokio::select! {
+            record = rx.recv() => match record {
+                Some(record) => {
+                    buffer.push(record);
+                    (buffer.len() >= config.batch_size, false)
+                }
+                None => (true, true),
+            },
+            _ = interval.tick() => (true, false),
+        };
+
+        if flush && !buffer.is_empty() {
+            let records = std::mem::take(&mut buffer);
+            if let Err(err) = upload_samples(&config, &client, &records).await {
+                tracing::warn!("Dropped {} request samples: {err}", records.len());
+            }
+        }
+        if closed {
+            break;
+        }
+    }
+}
+
+async fn upload_samples(
+    config: &SamplingConfig,
+    client: &Client,
+    records: &[SampleRecord],
+) -> Result<()> {
+    let mut body = Vec::new();
+    for record in records {
+        serde_json::to_writer(&mut body, record)?;
+        body.push(b'\n');
+    }
+    let key = format!(
+        "{}{}/{}.jsonl",
+        config.prefix,
+        Utc::now().format("%Y-%m-%d"),
+        uuid::Uuid::new_v4()
+    );
+    upload_file(client, &config.bucket, &key, body, encryption())
+        .await
+        .map_err(|err| Error::Custom(err.to_string()))?;
+    Ok(())
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restrict_fields() {
+        let mut record = SampleRecord::new("/embed", 200, 2);
+        record.tenant = Some("tenant-hash".to_string());
+        record.input_hashes = Some(vec!["a".to_string(), "b".to_string()]);
+        record.token_count = Some(12);
+
+        let fields = HashSet::from([SampleField::TokenCount]);
+        let record = record.restrict(&fields);
+        assert_eq!(record.tenant, None);
+        assert_eq!(record.input_hashes, None);
+        assert_eq!(record.token_count, Some(12));
+
+        let json = serde_json::to_value(&record).unwrap();
+        assert!(json.get("input_hashes").is_none());
+        assert_eq!(json["route"], "/embed");
+
+        assert!("prompt".parse::<SampleField>().is_err());
+    }
+}
+// endregion: Unit Test
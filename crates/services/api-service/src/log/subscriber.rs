@@ -0,0 +1,90 @@
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::Sampler;
+use opentelemetry::sdk::{Resource, trace};
+use opentelemetry::{KeyValue, global};
+use opentelemetry_otlp::WithExportConfig;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Init the global tracing subscriber.
+///
+/// With `json_output` every event is emitted as a single flattened JSON object, which is what
+/// log shippers (CloudWatch, Loki, Datadog...) expect. `disable_spans` removes the span context
+/// (`span` / `spans` fields in JSON, span close events in plain text) from the output.
+///
+/// When `otlp_endpoint` is set, spans are batch exported over OTLP/gRPC and the W3C
+/// `traceparent` propagator is installed. Returns whether the OTLP exporter is active.
+///
+/// The log level is read from `RUST_LOG` and defaults to `info`.
+pub fn init_logging(
+    otlp_endpoint: Option<&String>,
+    otlp_service_name: String,
+    json_output: bool,
+    disable_spans: bool,
+) -> bool {
+    let mut layers = Vec::new();
+
+    // STDOUT/STDERR layer
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .with_file(true)
+        .with_line_number(true);
+
+    let fmt_layer = match json_output {
+        true => fmt_layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(!disable_spans)
+            .with_span_list(!disable_spans)
+            .boxed(),
+        false => {
+            let fmt_layer = fmt_layer.with_target(false);
+            match disable_spans {
+                true => fmt_layer.with_span_events(FmtSpan::NONE).boxed(),
+                false => fmt_layer.with_span_events(FmtSpan::CLOSE).boxed(),
+            }
+        }
+    };
+    layers.push(fmt_layer);
+
+    // OpenTelemetry tracing layer
+    let mut otlp_enabled = false;
+    if let Some(otlp_endpoint) = otlp_endpoint {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(otlp_endpoint),
+            )
+            .with_trace_config(
+                trace::config()
+                    .with_resource(Resource::new(vec![KeyValue::new(
+                        "service.name",
+                        otlp_service_name,
+                    )]))
+                    .with_sampler(Sampler::AlwaysOn),
+            )
+            .install_batch(opentelemetry::runtime::Tokio);
+
+        match tracer {
+            Ok(tracer) => {
+                layers.push(tracing_opentelemetry::layer().with_tracer(tracer).boxed());
+                otlp_enabled = true;
+            }
+            Err(err) => eprintln!("Failed to install the OTLP tracer: {err}"),
+        }
+    }
+
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    tracing_subscriber::registry()
+        .with(env_filter)
+        .with(layers)
+        .init();
+
+    otlp_enabled
+}
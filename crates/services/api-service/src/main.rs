@@ -1,33 +1,59 @@
 pub mod ai;
 mod cache;
+mod chunk_export;
+mod chunk_import;
 pub mod config;
+mod config_file;
 pub mod error;
 mod log;
 mod middleware;
 mod routes;
+mod secrets;
 pub mod types;
+mod validate;
 
 pub use self::error::{Error, Result};
+use crate::ai::Info;
+use crate::ai::catalog::{ModelCatalog, ModelLoader};
+use crate::ai::infer::Infer;
+use crate::ai::splade::SpladeQueryEncoder;
 use crate::cache::AppState;
-use crate::middleware::mw_auth::{UserToken, ctx_resolver, request_auth};
+use crate::middleware::auth_provider::AuthProviders;
+use crate::middleware::mw_auth::{ctx_resolver, request_auth, require_admin};
+use crate::middleware::mw_cache::{CachePolicy, cache_headers};
+use crate::middleware::mw_client_ip::{ProxyConfig, client_info};
+use crate::middleware::mw_cors::cors_layer;
+use crate::middleware::mw_governor::{self, RateLimitKey, RateTier, rate_limit};
+use crate::middleware::mw_idempotency::{IdempotencyCache, idempotency_keys};
+use crate::middleware::mw_kill_switch::route_kill_switch;
+use crate::middleware::mw_openai_errors::openai_errors;
 use crate::middleware::mw_response::mw_response_map;
+use crate::middleware::mw_timeout::{RouteDeadlines, route_deadlines};
+use crate::middleware::mw_trace::trace_context;
 use axum::middleware::from_fn;
 use axum::{Router, extract::Extension, serve};
-use clap::Parser;
-use lib_core::database::ModelManager;
-use lib_embedding::DType;
+use clap::{CommandFactory, Parser, Subcommand};
+use futures::future::BoxFuture;
+use lib_core::database::{ModelManager, run_migrations};
+use lib_core::vector_index::{VectorIndexConfig, migrate_embedding_storage, migrate_vector_index};
+use lib_cron::hf_cache::CacheCleanup;
+use lib_embedding::{DType, Quantization};
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::net::TcpListener;
+use tokio::sync::watch;
 use tokio::time::Duration;
 use tower_cookies::CookieManagerLayer;
-use tower_governor::{GovernorLayer, governor::GovernorConfigBuilder};
 use tracing::info;
-use tracing_subscriber::EnvFilter;
 
-// Use mimalloc as the global allocator on non-linux platforms for better memory usage on long running jobs
-#[cfg(not(target_os = "linux"))]
+// Use mimalloc as the global allocator outside of glibc (macOS, Windows, musl static builds) for
+// better memory usage on long running jobs, glibc relies on the trim loop instead
+#[cfg(all(
+    feature = "mimalloc",
+    not(all(target_os = "linux", target_env = "gnu"))
+))]
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
@@ -58,6 +84,11 @@ struct Args {
     #[clap(default_value = "float16", long, env, value_enum)]
     dtype: Option<DType>,
 
+    /// Quantize the weights of the linear layers when the model is loaded, `q8_0` or `q4_0`.
+    /// Runs the candle backend on CPU or Metal in float32 and reduces the memory of the model.
+    #[clap(long, env)]
+    quantization: Option<Quantization>,
+
     /// Optionally control the pooling method for embedding models.
     ///
     /// If `pooling` is not set, the pooling configuration will be parsed from the
@@ -97,6 +128,17 @@ struct Args {
     #[clap(default_value = "2", long, env)]
     max_client_batch_size: usize,
 
+    /// Load-shedding: requests are answered `429` at once when the tokens already queued and
+    /// running would make them wait longer than this many milliseconds, at the throughput of the
+    /// last batches. Unset, every request waits for its turn.
+    #[clap(long, env)]
+    max_queue_wait_ms: Option<u64>,
+
+    /// Responses whose serialized size may exceed this many bytes are streamed as a chunked body
+    /// instead of being serialized in memory at once
+    #[clap(default_value = "16777216", long, env)]
+    stream_response_threshold: usize,
+
     /// Automatically truncate inputs that are longer than the maximum supported size
     ///
     /// Unused for gRPC servers
@@ -141,8 +183,24 @@ struct Args {
     /// Note that this argument is optional, only required to be set if there is no `modules.json`
     /// file or when you want to override a single Dense module path, only when running with the
     /// `candle` backend.
+    ///
+    /// Several comma separated paths are applied in order. Besides paths of the model repository,
+    /// local directories (absolute or `./relative`) and S3 URIs (`s3://bucket/prefix`) holding
+    /// adapter weights outside the Hub are accepted, optionally pinned with `#sha256=<hex>`.
+    #[clap(long, env, value_delimiter = ',')]
+    dense_path: Vec<String>,
+
+    /// Separate query encoder of a SPLADE model (`--pooling splade`), e.g. the query model of a
+    /// document/query pair. It encodes the `/embed_sparse` inputs with `input_type: query` and
+    /// must share the vocabulary of `--model-id`.
     #[clap(long, env)]
-    dense_path: Option<String>,
+    splade_query_model_id: Option<String>,
+
+    /// Warm standby models, comma separated. They are downloaded into the hub cache at startup
+    /// without being loaded, listed on `GET /api/v1/models` and served instead of `--model-id`
+    /// with `POST /api/v1/models/{id}/load`, which only has to load their weights.
+    #[clap(long, env, value_delimiter = ',')]
+    standby_model_id: Vec<String>,
 
     /// [DEPRECATED IN FAVOR OF `--hf-token`] Your Hugging Face Hub token
     #[clap(long, env, hide = true)]
@@ -186,10 +244,15 @@ struct Args {
     #[clap(long, env)]
     json_output: bool,
 
-    // Whether or not to include the log trace through spans
+    /// Whether or not to include the log trace through spans
     #[clap(long, env)]
     disable_spans: bool,
 
+    /// Do not return the freed memory to the OS every 100ms with `malloc_trim`. Only glibc builds
+    /// run the trim loop, other targets ignore the flag.
+    #[clap(long, env)]
+    no_trim_loop: bool,
+
     /// The grpc endpoint for opentelemetry. Telemetry is sent to this endpoint as OTLP over gRPC.
     /// e.g. `http://localhost:4317`
     #[clap(long, env)]
@@ -200,9 +263,212 @@ struct Args {
     #[clap(default_value = "s3-embedding.server", long, env)]
     otlp_service_name: String,
 
-    /// Unused for gRPC servers
-    #[clap(long, env)]
+    /// Origins allowed by CORS, comma separated: `*`, exact origins or `regex:<pattern>`
+    #[clap(long, env, value_delimiter = ',')]
     cors_allow_origin: Option<Vec<String>>,
+
+    /// `Cache-Control` of the deterministic `GET` endpoints, `;` separated `path=value` rules, e.g.
+    /// `/info=public, max-age=60`. Covered responses also get an `ETag` and answer `If-None-Match`
+    /// with `304`. Defaults to `/info` and `/version`, an empty value turns it off.
+    #[clap(long, env, value_delimiter = ';')]
+    cache_control: Option<Vec<String>>,
+
+    /// Seconds the response of a `POST` request with an `Idempotency-Key` header is replayed to
+    /// the retries of the same user with that key, `0` turns idempotency keys off
+    #[clap(default_value = "3600", long, env)]
+    idempotency_ttl_sec: u64,
+
+    /// Deadlines of the routes, `;` separated `path=seconds` rules, e.g. `/api/v1/embed*=10`. A
+    /// request still running at its deadline gets `504`, or `408` when its body was not received,
+    /// and its queued work is dropped. Defaults to the inference routes, an empty value turns it
+    /// off.
+    #[clap(long, env, value_delimiter = ';')]
+    route_timeout: Option<Vec<String>>,
+
+    /// Seconds between two cleanups of the rate limiter state, lower it when many distinct keys
+    /// hit the server
+    #[clap(default_value = "60", long, env)]
+    rate_limit_cleanup_interval_sec: u64,
+
+    /// Key of the rate limiter: `api-key` (the authenticated caller) or `ip` (the client address)
+    #[clap(default_value = "api-key", long, env)]
+    rate_limit_key: RateLimitKey,
+
+    /// Requests per second of the callers whose role has no rate limit tier, tiers are set with
+    /// `PUT /api/v1/admin/rate-limits/{role}` and per user in the users table
+    #[clap(default_value = "80", long, env)]
+    rate_limit_per_second: u32,
+
+    /// Burst of the callers whose role has no rate limit tier
+    #[clap(default_value = "50", long, env)]
+    rate_limit_burst: u32,
+
+    /// Reverse proxies allowed to set the forwarding headers, comma separated CIDRs or addresses.
+    /// Forwarding headers of any other peer are ignored.
+    #[clap(long, env, value_delimiter = ',')]
+    trusted_proxies: Vec<String>,
+
+    /// Forwarding headers honored from the trusted proxies, comma separated:
+    /// `x-forwarded-for`, `x-forwarded-proto` and/or `forwarded`
+    #[clap(
+        default_value = "x-forwarded-for,x-forwarded-proto",
+        long,
+        env,
+        value_delimiter = ','
+    )]
+    forwarded_headers: Vec<String>,
+
+    /// Apply the pending database migrations before serving. Without it the schema must already be
+    /// up to date.
+    #[clap(long, env)]
+    migrate: bool,
+
+    /// On first start, with no user in the database, create an `admin` user and mint its API key
+    #[clap(long, env)]
+    bootstrap_admin: bool,
+
+    /// Email of the bootstrap admin
+    #[clap(default_value = "admin@localhost", long, env)]
+    bootstrap_admin_email: String,
+
+    /// File the bootstrap admin key is written to, readable by the owner only. The key is printed
+    /// once to stdout otherwise.
+    #[clap(long, env)]
+    bootstrap_secrets_file: Option<PathBuf>,
+
+    /// Run a job type once at startup, e.g. `sync_and_process` to ingest an existing bucket in one
+    /// pass instead of waiting for the cron ticks. It runs in the background while serving.
+    #[clap(long, env)]
+    run_job: Option<String>,
+
+    /// Exit after `--run-job` instead of serving, with a failure status when the job failed
+    #[clap(long, env, requires = "run_job")]
+    exit: bool,
+
+    /// Seconds between two fetches of a `DATABASE_URL` read from Secrets Manager or SSM, to follow
+    /// password rotations. 0 only fetches it at startup.
+    #[clap(default_value = "300", long, env)]
+    secrets_refresh_sec: u64,
+
+    /// Skip the startup checks of the database, the buckets and the parsers, the settings are
+    /// still validated
+    #[clap(long, env)]
+    skip_startup_checks: bool,
+
+    /// TOML or YAML file with the arguments (`[server]`) and the settings of every crate, it only
+    /// sets what is not already passed on the command line or in the env
+    #[clap(long, env)]
+    config: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+/// Without a subcommand the server starts
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Export the model, pooling and Dense modules configured above as an ONNX bundle for offline
+    /// inference with ORT. The bundle is only written when its embeddings match the candle ones.
+    Export {
+        /// Directory the bundle is written to
+        #[clap(long)]
+        output: PathBuf,
+
+        /// Text embedded by both pipelines for the parity check, can be repeated
+        #[clap(long = "parity-sample")]
+        parity_samples: Vec<String>,
+
+        /// Lowest cosine similarity accepted between the candle and the ONNX embedding of a sample
+        #[clap(default_value = "0.999", long)]
+        min_cosine: f32,
+    },
+    /// Load the model configured above and drive it with synthetic inputs, then print the latency
+    /// percentiles and the throughput of every token length. No database is needed.
+    Bench {
+        /// Tokens of the synthetic inputs, one run per length
+        #[clap(default_value = "32,128,512", long, value_delimiter = ',')]
+        token_lengths: Vec<usize>,
+
+        /// Requests in flight, above `--max-concurrent-requests` they wait for a permit
+        #[clap(default_value = "8", long)]
+        concurrency: usize,
+
+        /// Measured requests of every run
+        #[clap(default_value = "200", long)]
+        requests: usize,
+
+        /// Requests of every run before the measure
+        #[clap(default_value = "8", long)]
+        warmup: usize,
+    },
+    /// Embed newline-delimited text or a CSV column with the model configured above, without the
+    /// HTTP server nor a database, and write the embeddings to a JSONL, Parquet or NPY file.
+    /// `--auto-truncate` applies, empty lines are skipped.
+    Embed {
+        /// File to read, stdin when missing or `-`
+        #[clap(long)]
+        input: Option<PathBuf>,
+
+        /// Read the input as CSV with a header and embed this column
+        #[clap(long)]
+        column: Option<String>,
+
+        /// File the embeddings are written to
+        #[clap(long)]
+        output: PathBuf,
+
+        /// Format of the output, from the `--output` extension when missing
+        #[clap(long, value_enum)]
+        format: Option<ai::offline::OutputFormat>,
+
+        /// Records read, embedded and written at once
+        #[clap(default_value = "256", long)]
+        batch_size: usize,
+
+        /// Requests in flight, above `--max-concurrent-requests` they wait for a permit
+        #[clap(default_value = "32", long)]
+        concurrency: usize,
+
+        /// Prompt of the model prepended to every record, e.g. `query` or `passage`
+        #[clap(long)]
+        prompt_name: Option<String>,
+
+        /// Keep the embeddings unnormalized
+        #[clap(long)]
+        no_normalize: bool,
+
+        /// Truncate the embeddings to their first dimensions, for Matryoshka models
+        #[clap(long)]
+        dimensions: Option<usize>,
+    },
+}
+
+/// Start the configured model for the subcommands running it without the HTTP server
+async fn local_infer(args: Args) -> Result<(Infer, Info)> {
+    let (infer, info, _) = ai::run(
+        args.model_id,
+        args.revision,
+        args.tokenization_workers,
+        args.dtype,
+        args.quantization,
+        args.pooling,
+        args.max_concurrent_requests,
+        args.max_batch_tokens,
+        args.max_batch_requests,
+        args.max_client_batch_size,
+        args.max_queue_wait_ms.map(Duration::from_millis),
+        args.auto_truncate,
+        args.default_prompt,
+        args.default_prompt_name,
+        args.dense_path,
+        args.hf_token.or(args.hf_api_token),
+        Some(args.uds_path),
+        args.huggingface_hub_cache,
+        args.otlp_endpoint,
+        args.otlp_service_name,
+    )
+    .await?;
+    Ok((infer, info))
 }
 
 // endregion: Arguments
@@ -210,56 +476,316 @@ struct Args {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_target(false)
-        .with_env_filter(EnvFilter::from_default_env())
-        .init();
-
-    info!("Parsing Args");
     // Pattern match configuration
-    let args: Args = Args::parse();
+    let mut args: Args = Args::parse();
+    let mut config_keys = 0;
+    if let Some(path) = &args.config {
+        let config = config_file::ServerConfig::load(path)?;
+        // SAFETY: nothing reads the env concurrently, no task is spawned before the arguments
+        config_keys = unsafe { config.apply(&Args::command())? };
+    }
+    // Settings referencing Secrets Manager or SSM are replaced by the secret
+    // SAFETY: as above
+    let secret_refs = unsafe { secrets::resolve_env().await? };
+    if args.config.is_some() || !secret_refs.is_empty() {
+        // Parsed again with the env of the file and the secrets, the command line still wins
+        args = Args::parse();
+    }
+
+    // Logging has to be initialized after parsing, it depends on `--json-output`
+    let otlp_enabled = log::subscriber::init_logging(
+        args.otlp_endpoint.as_ref(),
+        args.otlp_service_name.clone(),
+        args.json_output,
+        args.disable_spans,
+    );
 
     tracing::info!("{:?}", args);
+    if let Some(path) = &args.config {
+        info!("{config_keys} settings loaded from {}", path.display());
+    }
+    for (env, reference) in &secret_refs {
+        info!("{env} read from the secret {}", reference.name);
+    }
+
+    match args.command.take() {
+        Some(Command::Export {
+            output,
+            parity_samples,
+            min_cosine,
+        }) => {
+            return ai::export::export(
+                args.model_id,
+                args.revision,
+                args.pooling,
+                args.dense_path,
+                args.hf_token.or(args.hf_api_token),
+                args.huggingface_hub_cache,
+                &output,
+                parity_samples,
+                min_cosine,
+            )
+            .await;
+        }
+        Some(Command::Bench {
+            token_lengths,
+            concurrency,
+            requests,
+            warmup,
+        }) => {
+            info!("Starting AI Inference for the benchmark");
+            let (infer, info) = local_infer(args).await?;
+            let options = ai::bench::BenchOptions {
+                token_lengths,
+                concurrency,
+                requests,
+                warmup,
+            };
+            let reports = ai::bench::bench(infer, options).await?;
+            println!("{}", info.model_id);
+            print!("{}", ai::bench::format_reports(&reports));
+            return Ok(());
+        }
+        Some(Command::Embed {
+            input,
+            column,
+            output,
+            format,
+            batch_size,
+            concurrency,
+            prompt_name,
+            no_normalize,
+            dimensions,
+        }) => {
+            let format = match format.or_else(|| ai::offline::OutputFormat::from_path(&output)) {
+                Some(format) => format,
+                None => {
+                    return Err(Error::Custom(format!(
+                        "Cannot tell the format of {} from its extension, set `--format`",
+                        output.display()
+                    )));
+                }
+            };
+            let options = ai::offline::OfflineOptions {
+                input,
+                column,
+                output,
+                format,
+                batch_size,
+                concurrency,
+                truncate: args.auto_truncate,
+                prompt_name,
+                normalize: !no_normalize,
+                dimensions,
+            };
+            info!("Starting AI Inference for the offline embeddings");
+            let (infer, _) = local_infer(args).await?;
+            return ai::offline::embed_offline(infer, options).await;
+        }
+        None => {}
+    }
+
+    // Every invalid setting at once rather than the first one a crate reads
+    validate::validate_config(args.skip_startup_checks).await?;
 
     // Hack to trim pages regularly
     // see: https://www.algolia.com/blog/engineering/when-allocators-are-hoarding-your-precious-memory/
     // and: https://github.com/huggingface/text-embeddings-inference/issues/156
-    #[cfg(target_os = "linux")]
-    tokio::spawn(async move {
-        use tokio::time::Duration;
-        loop {
-            tokio::time::sleep(Duration::from_millis(100)).await;
-            unsafe {
-                libc::malloc_trim(0);
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    if !args.no_trim_loop {
+        tokio::spawn(async move {
+            use tokio::time::Duration;
+            loop {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                unsafe {
+                    libc::malloc_trim(0);
+                }
             }
-        }
-    });
+        });
+    }
 
     let token = args.hf_token.or(args.hf_api_token);
     let api_key = args.api_key.clone();
+    let cors = cors_layer(args.cors_allow_origin.clone())?;
+    let cache_policy = Arc::new(CachePolicy::from_rules(args.cache_control.clone())?);
+    // Imports are buffered up to their own body limit
+    let idempotency = IdempotencyCache::new(Duration::from_secs(args.idempotency_ttl_sec))
+        .with_body_limit(
+            format!("/api/v1{}", routes::import::IMPORT_PATH),
+            routes::import::MAX_IMPORT_BYTES,
+        );
+    let deadlines = Arc::new(RouteDeadlines::from_rules(args.route_timeout.clone())?);
+    let proxy_config = Arc::new(ProxyConfig::new(
+        args.trusted_proxies.clone(),
+        args.forwarded_headers.clone(),
+    )?);
+    // Local model directories are not part of the hub cache, nothing to protect
+    let cached_model_id =
+        (!std::path::Path::new(&args.model_id).is_dir()).then(|| args.model_id.clone());
+    let mut cache_cleanup = CacheCleanup::from_env(
+        args.huggingface_hub_cache.clone(),
+        cached_model_id,
+        args.revision.clone(),
+    );
+    cache_cleanup.standby_model_ids = args.standby_model_id.clone();
+    // Standby models are loaded with the settings of `--model-id`, on their own Unix socket
+    let standby_loader: ModelLoader = {
+        let (workers, dtype, quantization) = (
+            args.tokenization_workers,
+            args.dtype.clone(),
+            args.quantization,
+        );
+        let pooling = args.pooling.clone();
+        let (concurrent, batch_tokens, batch_requests, client_batch, auto_truncate) = (
+            args.max_concurrent_requests,
+            args.max_batch_tokens,
+            args.max_batch_requests,
+            args.max_client_batch_size,
+            args.auto_truncate,
+        );
+        let max_queue_wait = args.max_queue_wait_ms.map(Duration::from_millis);
+        let (token, uds_path, hub_cache) = (
+            token.clone(),
+            args.uds_path.clone(),
+            args.huggingface_hub_cache.clone(),
+        );
+        let (otlp_endpoint, otlp_service_name) =
+            (args.otlp_endpoint.clone(), args.otlp_service_name.clone());
+        Arc::new(move |model_id, switch| {
+            let run = ai::run(
+                model_id,
+                None,
+                workers,
+                dtype.clone(),
+                quantization,
+                pooling.clone(),
+                concurrent,
+                batch_tokens,
+                batch_requests,
+                client_batch,
+                max_queue_wait,
+                auto_truncate,
+                None,
+                None,
+                Vec::new(),
+                token.clone(),
+                Some(format!("{uds_path}-standby-{switch}")),
+                hub_cache.clone(),
+                otlp_endpoint.clone(),
+                otlp_service_name.clone(),
+            );
+            Box::pin(async move {
+                match run.await? {
+                    (infer, info, None) => Ok((infer, info)),
+                    (_, info, Some(_)) => Err(Error::Conflict(format!(
+                        "{} needs a SPLADE query encoder, it cannot be switched to",
+                        info.model_id
+                    ))),
+                }
+            })
+        })
+    };
+    let standby_downloads = {
+        let (pool_config, token, hub_cache) = (
+            args.pooling.is_none(),
+            token.clone(),
+            args.huggingface_hub_cache.clone(),
+        );
+        move |model_id| -> BoxFuture<'static, Result<()>> {
+            Box::pin(ai::download_model(
+                model_id,
+                pool_config,
+                token.clone(),
+                hub_cache.clone(),
+            ))
+        }
+    };
+    let max_queue_wait = args.max_queue_wait_ms.map(Duration::from_millis);
     info!("Starting AI Inference");
-    let (infer, info) = ai::run(
+    let (infer, mut info, mut splade_query) = ai::run(
         args.model_id,
         args.revision,
         args.tokenization_workers,
-        args.dtype,
+        args.dtype.clone(),
+        args.quantization,
         args.pooling,
         args.max_concurrent_requests,
         args.max_batch_tokens,
         args.max_batch_requests,
         args.max_client_batch_size,
+        max_queue_wait,
         args.auto_truncate,
         args.default_prompt,
         args.default_prompt_name,
         args.dense_path,
-        token,
-        Some(args.uds_path),
-        args.huggingface_hub_cache,
-        args.otlp_endpoint,
-        args.otlp_service_name,
+        token.clone(),
+        Some(args.uds_path.clone()),
+        args.huggingface_hub_cache.clone(),
+        args.otlp_endpoint.clone(),
+        args.otlp_service_name.clone(),
     )
     .await?;
 
+    // The query encoder runs its own backend and queue next to the document model
+    if let Some(query_model_id) = args.splade_query_model_id {
+        if info.splade_query_encoder.is_none() {
+            return Err(Error::Custom(
+                "`--splade-query-model-id` requires a SPLADE model (`--pooling splade`)"
+                    .to_string(),
+            ));
+        }
+        info!("Starting SPLADE query encoder {query_model_id}");
+        let (query_infer, _, _) = ai::run(
+            query_model_id.clone(),
+            None,
+            args.tokenization_workers,
+            args.dtype,
+            args.quantization,
+            Some(lib_embedding::Pool::Splade),
+            args.max_concurrent_requests,
+            args.max_batch_tokens,
+            args.max_batch_requests,
+            args.max_client_batch_size,
+            max_queue_wait,
+            args.auto_truncate,
+            None,
+            None,
+            Vec::new(),
+            token,
+            Some(format!("{}-query", args.uds_path)),
+            args.huggingface_hub_cache,
+            args.otlp_endpoint,
+            args.otlp_service_name,
+        )
+        .await?;
+        let encoder = SpladeQueryEncoder::Model {
+            model_id: query_model_id,
+            infer: Arc::new(query_infer),
+        };
+        info.splade_query_encoder = Some(encoder.info());
+        splade_query = Some(encoder);
+    }
+
+    // Standby models are only downloaded, `POST /api/v1/models/{id}/load` loads them
+    let models = Arc::new(ModelCatalog::new(
+        Arc::new(infer),
+        Arc::new(info),
+        standby_loader,
+    ));
+    models.spawn_downloads(args.standby_model_id, standby_downloads);
+
+    // Startup banner, the same information is served on `/version`
+    let version = routes::version::version_response(&models.served().info);
+    info!(
+        version = version.version,
+        git_sha = version.git_sha.unwrap_or("unknown"),
+        build_timestamp = version.build_timestamp.as_deref().unwrap_or("unknown"),
+        features = ?version.features,
+        backend = %version.backend,
+        model_id = %version.model_id,
+        "Embedding server build"
+    );
+
     info!("Initializing Environment");
     let ip_addr: Ipv4Addr = args
         .hostname
@@ -270,48 +796,210 @@ async fn main() -> Result<()> {
 
     // Initialize the model manager for database access
     let mm = ModelManager::new().await?;
-    // Create application context
-    let app_state = AppState::new(Arc::new(mm.clone()), Arc::new(info), Arc::new(infer)).await?;
-
-    // Rate limiting Configuration, limits are tied to the provided API key (can be switched to IP address or userId)
-    let governor_conf = Arc::new(
-        GovernorConfigBuilder::default()
-            .per_second(80) // Increase the rate limit to 40 requests per second
-            .burst_size(50) // Allow a burst of up to 50 requests
-            .key_extractor(UserToken)
-            .finish()
-            .unwrap(),
-    );
-    let governor_limiter = governor_conf.limiter().clone();
-
-    // A separate background task to clean up rate limiting storage
-    std::thread::spawn(move || {
+    // Connection gauges of the pool
+    let metrics_mm = mm.clone();
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(Duration::from_secs(15));
         loop {
-            std::thread::sleep(Duration::from_secs(60));
-            governor_limiter.retain_recent();
+            ticks.tick().await;
+            metrics_mm.record_pool_metrics();
+        }
+    });
+    if let Some((_, reference)) = secret_refs.iter().find(|(env, _)| env == "DATABASE_URL") {
+        if args.secrets_refresh_sec > 0 {
+            secrets::spawn_db_url_refresh(
+                mm.clone(),
+                reference.clone(),
+                Duration::from_secs(args.secrets_refresh_sec),
+            );
+        }
+    }
+    if args.migrate {
+        info!("Applying database migrations");
+        run_migrations(mm.db()).await?;
+    }
+    // The vector index can take a while to build on large tables, the server starts meanwhile.
+    // A change of `EMBEDDING_STORAGE` first converts the column, which drops the index.
+    let index_config = VectorIndexConfig::load()?;
+    let index_mm = mm.clone();
+    tokio::spawn(async move {
+        if let Err(e) = migrate_embedding_storage(&index_mm, index_config.storage).await {
+            tracing::error!("Embedding storage migration failed: {e:?}");
+            return;
+        }
+        if let Err(e) = migrate_vector_index(&index_mm, &index_config).await {
+            tracing::error!("Vector index migration failed: {e:?}");
         }
     });
+    // Tier of the roles without their own, limits are tied to the caller or to the client address
+    let default_rate_tier = RateTier {
+        requests_per_sec: args.rate_limit_per_second,
+        burst: args.rate_limit_burst,
+        max_batch_size: None,
+    };
+    default_rate_tier.validate()?;
+    // Create application context
+    let app_state = AppState::new(
+        Arc::new(mm.clone()),
+        models,
+        splade_query.map(Arc::new),
+        cache_cleanup,
+        args.stream_response_threshold,
+        args.rate_limit_key,
+        default_rate_tier,
+    )
+    .await?;
+    routes::admin::restore_limits(&app_state).await;
+    if args.bootstrap_admin {
+        routes::admin::bootstrap_admin(
+            &app_state,
+            &args.bootstrap_admin_email,
+            args.bootstrap_secrets_file.as_deref(),
+        )
+        .await?;
+    }
+    if let Some(job_type) = args.run_job {
+        if args.exit {
+            app_state.cron_jobs.run_once(&job_type).await?;
+            info!("Job {job_type} done, exiting");
+            return Ok(());
+        }
+        let cron_jobs = app_state.cron_jobs.clone();
+        tokio::spawn(async move {
+            if let Err(e) = cron_jobs.run_once(&job_type).await {
+                tracing::error!("Startup job failed: {e:?}");
+            }
+        });
+    }
+    let auth_providers = AuthProviders::from_env(api_key, &app_state);
+
+    // Clean up rate limiting storage until the server is shut down
+    let rate_limits = app_state.rate_limits.clone();
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let governor_cleanup = mw_governor::spawn_cleanup(
+        move || {
+            let keys = rate_limits.retain_recent();
+            tracing::debug!("Rate limiter keys after cleanup: {keys}");
+        },
+        Duration::from_secs(args.rate_limit_cleanup_interval_sec.max(1)),
+        shutdown_rx,
+    );
 
     // API Routes tied with rate limiting and authentication middleware
+    let routes_admin = routes::admin::serve_admin().route_layer(from_fn(require_admin));
     let routes_api = Router::new()
         .merge(routes::embed::serve_embed())
+        .merge(routes::search::serve_search())
+        .merge(routes::ingest::serve_ingest())
+        .merge(routes::files::serve_files())
+        .merge(routes::export::serve_export())
+        .merge(routes::import::serve_import())
+        .merge(routes::models::serve_models())
+        .merge(routes::score::serve_score())
+        .merge(routes::ws::serve_ws())
+        .nest("/evaluation", routes::evaluation::serve_evaluation())
+        .nest("/cron", routes::cron::serve_cron())
+        .nest("/admin", routes_admin)
         .route_layer(from_fn(request_auth))
-        .layer(GovernorLayer {
-            config: governor_conf,
-        });
+        .layer(from_fn(rate_limit));
+
+    // Probes of the hosting platforms carry no credentials, they are merged outside the
+    // authentication, idempotency and kill switch layers
+    let routes_probes = Router::new()
+        .merge(routes::vertex::serve_vertex_health())
+        .merge(routes::sagemaker::serve_sagemaker_ping())
+        .merge(routes::version::serve_version())
+        .layer(axum::middleware::from_fn_with_state(
+            cache_policy.clone(),
+            cache_headers,
+        ));
 
     // Global routes with CORS, cookies, file serving routes should be implemented here
-    let global_routes = Router::new()
+    let mut global_routes = Router::new()
         .nest("/api/v1", routes_api)
-        .layer(axum::middleware::from_fn_with_state(api_key, ctx_resolver))
+        // Hosting protocol routes, their paths are fixed by the platform
+        .merge(routes::vertex::serve_vertex())
+        .merge(routes::sagemaker::serve_sagemaker())
+        // Innermost, the deadline only covers the handler
+        .layer(axum::middleware::from_fn_with_state(
+            deadlines,
+            route_deadlines,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            cache_policy,
+            cache_headers,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            app_state.route_toggles.clone(),
+            route_kill_switch,
+        ))
+        // Inside `ctx_resolver`, the keys are scoped to the caller
+        .layer(axum::middleware::from_fn_with_state(
+            idempotency,
+            idempotency_keys,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            auth_providers,
+            ctx_resolver,
+        ))
+        .merge(routes_probes)
+        // Outside the layers failing requests, so that all the errors of `/embeddings` are reshaped
+        .layer(from_fn(openai_errors))
         .layer(axum::middleware::map_response(mw_response_map))
         .layer(CookieManagerLayer::new())
-        .layer(Extension(app_state.clone()));
+        .layer(Extension(app_state.clone()))
+        .layer(from_fn(trace_context))
+        // Resolves the client address used by the rate limiter and the logs
+        .layer(axum::middleware::from_fn_with_state(
+            proxy_config,
+            client_info,
+        ));
+    // Outermost so preflight requests are answered before authentication
+    if let Some(cors) = cors {
+        global_routes = global_routes.layer(cors);
+    }
 
     info!("Server started on: http://{}", addr);
-    serve(listener, global_routes.into_make_service())
-        .await
-        .unwrap();
+    serve(
+        listener,
+        global_routes.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await
+    .unwrap();
+
+    // Connections are drained, stop the background tasks
+    let _ = shutdown_tx.send(true);
+    let _ = governor_cleanup.await;
+
+    if otlp_enabled {
+        // Flush the remaining spans of the batch exporter
+        opentelemetry::global::shutdown_tracer_provider();
+    }
 
     Ok(())
 }
+
+/// Resolves on Ctrl+C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install the Ctrl+C handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install the SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    info!("Shutdown signal received, draining connections");
+}
@@ -0,0 +1,308 @@
+//! Pluggable authentication of the bearer token resolved by `ctx_resolver`.
+//!
+//! Providers are tried in order, the first one recognizing the token decides: it either returns
+//! the `Ctx` of the caller, tenant included, or rejects the request. Built-in providers cover the
+//! static root key, JWTs, the per-user keys stored in the DB and an OAuth 2.0 introspection
+//! endpoint, other auth services plug in by implementing `AuthProvider` and adding it with
+//! `AuthProviders::with`.
+
+use crate::cache::{AppState, UserCacheData};
+use crate::error::{Error, Result};
+use async_trait::async_trait;
+use lib_auth::bearer::{ContentToHash, validate_key};
+use lib_auth::introspection::{IntrospectionConfig, TokenIntrospector};
+use lib_auth::jwt::{JwtConfig, JwtValidator};
+use lib_core::ctx::{Ctx, DEFAULT_TENANT};
+use lib_core::database::ModelManager;
+use lib_core::model::user::{Role, UserBmc};
+use moka::future::Cache;
+use std::sync::Arc;
+
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// `Ok(None)` when the token is not handled by this provider, the next one is tried
+    async fn validate(&self, token: &str) -> Result<Option<Ctx>>;
+}
+
+/// Ordered chain of providers, authentication is disabled when it is empty
+#[derive(Clone, Default)]
+pub struct AuthProviders {
+    providers: Vec<Arc<dyn AuthProvider>>,
+}
+
+impl AuthProviders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a provider, tried after the ones already added
+    pub fn with(mut self, provider: impl AuthProvider + 'static) -> Self {
+        self.providers.push(Arc::new(provider));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+
+    /// Static key, JWT, user keys and introspection, in this order. User keys are only accepted
+    /// when one of the others is configured, as before with `--api-key`.
+    pub fn from_env(api_key: Option<String>, app_state: &AppState) -> Self {
+        let jwt = JwtConfig::load_from_env();
+        let introspection = IntrospectionConfig::load_from_env();
+        if api_key.is_none() && jwt.is_none() && introspection.is_none() {
+            return Self::new();
+        }
+
+        let mut providers = Self::new();
+        if let Some(key) = api_key {
+            providers = providers.with(StaticKeyProvider { key });
+        }
+        if let Some(config) = jwt {
+            providers = providers.with(JwtProvider {
+                validator: JwtValidator::new(config),
+            });
+        }
+        providers = providers.with(UserKeyProvider {
+            mm: app_state.mm.clone(),
+            cache: app_state.cache_user.clone(),
+        });
+        if let Some(config) = introspection {
+            providers = providers.with(IntrospectionProvider::new(config));
+        }
+        providers
+    }
+
+    pub async fn authenticate(&self, token: &str) -> Result<Ctx> {
+        for provider in &self.providers {
+            if let Some(ctx) = provider.validate(token).await? {
+                tracing::debug!("Authenticated {} with {}", ctx.user_id(), provider.name());
+                return Ok(ctx);
+            }
+        }
+        Err(Error::AuthenticationFails("Invalid API Key".to_string()))
+    }
+}
+
+/// Any `admin` role (case insensitive) grants `Role::Admin`, everything else is a `Role::Viewer`
+fn role_from_claims(roles: &[String]) -> Role {
+    if roles.iter().any(|role| role.eq_ignore_ascii_case("admin")) {
+        Role::Admin
+    } else {
+        Role::Viewer
+    }
+}
+
+/// The `--api-key` root key, in the default tenant
+pub struct StaticKeyProvider {
+    key: String,
+}
+
+#[async_trait]
+impl AuthProvider for StaticKeyProvider {
+    fn name(&self) -> &'static str {
+        "static_key"
+    }
+
+    async fn validate(&self, token: &str) -> Result<Option<Ctx>> {
+        if token != self.key {
+            return Ok(None);
+        }
+        Ok(Some(Ctx::root_key()))
+    }
+}
+
+/// JWTs of the identity provider (`JWT_JWKS_URL` / `JWT_HS256_SECRET`)
+pub struct JwtProvider {
+    validator: JwtValidator,
+}
+
+#[async_trait]
+impl AuthProvider for JwtProvider {
+    fn name(&self) -> &'static str {
+        "jwt"
+    }
+
+    async fn validate(&self, token: &str) -> Result<Option<Ctx>> {
+        if !JwtValidator::looks_like_jwt(token) {
+            return Ok(None);
+        }
+        let claims = self
+            .validator
+            .validate(token)
+            .await
+            .map_err(|err| Error::AuthenticationFails(err.to_string()))?;
+        let ctx = Ctx::new(claims.sub, Some(role_from_claims(&claims.roles)))?;
+        let tenant = claims.tenant.as_deref().unwrap_or(DEFAULT_TENANT);
+        Ok(Some(ctx.with_tenant(tenant)))
+    }
+}
+
+/// Per-user API keys (`{user_id}.{secret}`) validated against the salted hash of the user.
+/// Users are cached, the admin routes invalidate the entry on every change.
+pub struct UserKeyProvider {
+    mm: Arc<ModelManager>,
+    cache: Cache<String, UserCacheData>,
+}
+
+#[async_trait]
+impl AuthProvider for UserKeyProvider {
+    fn name(&self) -> &'static str {
+        "user_key"
+    }
+
+    async fn validate(&self, token: &str) -> Result<Option<Ctx>> {
+        let invalid = || Error::AuthenticationFails("Invalid API Key".to_string());
+        let Some((user_id, secret)) = split_api_key(token) else {
+            return Ok(None);
+        };
+
+        let user = match self.cache.get(user_id).await {
+            Some(user) => user,
+            None => {
+                // Unknown users may be tokens of another provider
+                let Ok(user) = UserBmc::get_user_for_auth(&self.mm, user_id).await else {
+                    return Ok(None);
+                };
+                let user = UserCacheData {
+                    user_id: user.user_id,
+                    tenant_id: user.tenant_id,
+                    role: user.role,
+                    salt: user.salt,
+                    api_key: user.api_key,
+                    rate_limit: user.rate_limit,
+                };
+                self.cache.insert(user_id.to_string(), user.clone()).await;
+                user
+            }
+        };
+
+        let hashed_key = user.api_key.clone().ok_or_else(invalid)?;
+        let content = ContentToHash {
+            content: secret.to_string(),
+            salt: user.salt,
+        };
+        validate_key(content, hashed_key).map_err(|_| invalid())?;
+        if user.role == Role::Inactive {
+            return Err(invalid());
+        }
+        Ok(Some(
+            Ctx::new(user.user_id, Some(user.role))?.with_tenant(user.tenant_id),
+        ))
+    }
+}
+
+/// Splits a user API key (`{user_id}.{secret}`) into its user id and secret.
+/// The secret is b64u encoded and never contains a `.`, the user id may.
+pub fn split_api_key(key: &str) -> Option<(&str, &str)> {
+    key.rsplit_once('.')
+        .filter(|(user_id, secret)| !user_id.is_empty() && !secret.is_empty())
+}
+
+/// (user id, role, tenant) of an active introspected token
+type Identity = (String, Role, String);
+
+/// Opaque tokens of an external auth service (`AUTH_INTROSPECTION_URL`), results are cached for
+/// `AUTH_INTROSPECTION_CACHE_SEC` to avoid a callout per request
+pub struct IntrospectionProvider {
+    introspector: TokenIntrospector,
+    cache: Cache<String, Option<Identity>>,
+}
+
+impl IntrospectionProvider {
+    pub fn new(config: IntrospectionConfig) -> Self {
+        let cache = Cache::builder()
+            .time_to_live(config.cache_ttl)
+            .max_capacity(100_000)
+            .build();
+        Self {
+            introspector: TokenIntrospector::new(config),
+            cache,
+        }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for IntrospectionProvider {
+    fn name(&self) -> &'static str {
+        "introspection"
+    }
+
+    async fn validate(&self, token: &str) -> Result<Option<Ctx>> {
+        let identity = match self.cache.get(token).await {
+            Some(identity) => identity,
+            None => {
+                let identity = self
+                    .introspector
+                    .introspect(token)
+                    .await
+                    .map_err(|err| Error::AuthenticationFails(err.to_string()))?
+                    .map(|token| {
+                        let role = role_from_claims(&token.roles);
+                        let tenant = token.tenant.unwrap_or_else(|| DEFAULT_TENANT.to_string());
+                        (token.sub, role, tenant)
+                    });
+                self.cache.insert(token.to_string(), identity.clone()).await;
+                identity
+            }
+        };
+        // Inactive tokens are left to the next providers, rejected when none is left
+        match identity {
+            Some((user_id, role, tenant)) => {
+                Ok(Some(Ctx::new(user_id, Some(role))?.with_tenant(tenant)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(&'static str);
+
+    #[async_trait]
+    impl AuthProvider for Fixed {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        async fn validate(&self, token: &str) -> Result<Option<Ctx>> {
+            if token != self.0 {
+                return Ok(None);
+            }
+            Ok(Some(Ctx::new(token.to_string(), Some(Role::Viewer))?))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_provider_chain() -> Result<()> {
+        let providers = AuthProviders::new()
+            .with(StaticKeyProvider {
+                key: "root-key".to_string(),
+            })
+            .with(Fixed("tenant-token"));
+
+        assert_eq!(
+            providers.authenticate("root-key").await?.role(),
+            Some(Role::Admin)
+        );
+        assert_eq!(
+            providers.authenticate("tenant-token").await?.user_id(),
+            "tenant-token"
+        );
+        assert!(providers.authenticate("root-key").await?.is_root());
+        assert!(!providers.authenticate("tenant-token").await?.is_root());
+        // A token whose subject is `root` is not the root key
+        assert!(!Ctx::new("root".to_string(), Some(Role::Admin))?.is_root());
+        assert!(providers.authenticate("unknown").await.is_err());
+        assert!(AuthProviders::new().is_empty());
+        assert_eq!(split_api_key("user.1.secret"), Some(("user.1", "secret")));
+        Ok(())
+    }
+}
+// endregion: Unit Test
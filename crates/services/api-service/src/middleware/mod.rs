@@ -1,2 +1,13 @@
+pub mod auth_provider;
 pub mod mw_auth;
+pub mod mw_cache;
+pub mod mw_client_ip;
+pub mod mw_cors;
+pub mod mw_governor;
+pub mod mw_history;
+pub mod mw_idempotency;
+pub mod mw_kill_switch;
+pub mod mw_openai_errors;
 pub mod mw_response;
+pub mod mw_timeout;
+pub mod mw_trace;
@@ -1,14 +1,13 @@
 //! This module provides middleware functions and utility functions for
 //! authentication and authorization in an Axum application.
 
-use crate::config::auth_config;
 use crate::error::{Error, Result};
+use crate::middleware::auth_provider::AuthProviders;
 use axum::extract::{FromRequestParts, State};
 use axum::http::{Request, request::Parts};
 use axum::{body::Body, middleware::Next, response::Response};
-use lib_auth::bearer::{ContentToHash, hash_key};
+use lib_core::ctx::Ctx;
 use lib_core::model::user::Role;
-use lib_core::{ctx::Ctx, database::ModelManager};
 use serde::{Deserialize, Serialize};
 use tower_governor::{errors::GovernorError, key_extractor::KeyExtractor};
 
@@ -64,53 +63,31 @@ pub async fn request_auth(ctx: Result<Ctm>, req: Request<Body>, next: Next) -> R
     Ok(next.run(req).await)
 }
 
+/// Restricts the routes to contexts with `Role::Admin`, must run after `ctx_resolver`.
+pub async fn require_admin(ctx: Result<Ctm>, req: Request<Body>, next: Next) -> Result<Response> {
+    match ctx?.0.role() {
+        Some(Role::Admin) => Ok(next.run(req).await),
+        _ => Err(Error::Forbidden("Admin role required".to_string())),
+    }
+}
+
+/// Resolve the `Ctm` of the request with the `AuthProviders`, every request is `root` when no
+/// provider is configured
 pub async fn ctx_resolver(
-    State(api_key): State<Option<String>>,
+    State(auth): State<AuthProviders>,
     mut req: Request<Body>,
     next: Next,
 ) -> Result<Response> {
-    if let Some(stored_key) = api_key {
+    let ctx = if auth.is_empty() {
+        Ctx::root_key()
+    } else {
         // Extract API Key from Header
         let provided_key = UserToken
             .extract(&req)
             .map_err(|_| Error::UnableToExtractKey)?;
-
-        /* // Use userId, if additional permission levels are required
-        let user_id = user_extractor(&req).map_err(|_| Error::UnableToExtractKey);
-        if let Ok(user_id) = user_id {
-            let salt = auth_config().hash_salt;
-            let content = ContentToHash {
-                content: key.to_string(),
-                salt,
-            };
-            let hashed_key = hash_key(content)?;
-            let stored_key = auth_config().api_key.to_string();
-            if hashed_key != stored_key {
-                //return Err(Error::AuthenticationFails("Invalid API Key".to_string()));
-            }
-
-            // Store signature in context
-            req.extensions_mut().insert(Ok::<Ctm, Error>(Ctm(Ctx::new(
-                user_id.to_string(),
-                Some(Role::Admin),
-            )?)));
-        } else {
-            //return Err(Error::AuthenticationFails("Invalid API Key".to_string()));
-        }
-        */
-        if provided_key != stored_key {
-            return Err(Error::AuthenticationFails("Invalid API Key".to_string()));
-        }
-        req.extensions_mut().insert(Ok::<Ctm, Error>(Ctm(Ctx::new(
-            "root".to_string(),
-            Some(Role::Admin),
-        )?)));
-    } else {
-        req.extensions_mut().insert(Ok::<Ctm, Error>(Ctm(Ctx::new(
-            "root".to_string(),
-            Some(Role::Admin),
-        )?)));
-    }
+        auth.authenticate(&provided_key).await?
+    };
+    req.extensions_mut().insert(Ok::<Ctm, Error>(Ctm(ctx)));
     Ok(next.run(req).await)
 }
 
@@ -0,0 +1,154 @@
+//! HTTP caching of deterministic `GET` endpoints polled by dashboards, e.g. `/info`.
+//!
+//! Every rule of `--cache-control` is `path=Cache-Control value`, separated by `;`:
+//! `/info=public, max-age=60;/version=public, max-age=3600`. A path ending with `*` covers the
+//! paths starting with it. Successful responses of a covered path get the `Cache-Control` value of
+//! the rule and an `ETag` computed from the body, a request whose `If-None-Match` matches it is
+//! answered `304 Not Modified` without a body.
+
+use crate::error::{Error, Result};
+use axum::body::{Body, Bytes};
+use axum::extract::State;
+use axum::http::{HeaderValue, Method, Request, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Rules applied without `--cache-control`
+pub const DEFAULT_CACHE_CONTROL: [&str; 2] =
+    ["/info=public, max-age=60", "/version=public, max-age=3600"];
+/// Responses above this size are not buffered to compute their `ETag`
+const MAX_CACHED_BODY: usize = 1 << 20;
+
+#[derive(Debug, Default)]
+pub struct CachePolicy {
+    rules: Vec<(String, HeaderValue)>,
+}
+
+impl CachePolicy {
+    /// `None` uses `DEFAULT_CACHE_CONTROL`, an empty rule list turns caching off
+    pub fn from_rules(rules: Option<Vec<String>>) -> Result<Self> {
+        let rules = rules
+            .unwrap_or_else(|| DEFAULT_CACHE_CONTROL.map(str::to_string).to_vec())
+            .into_iter()
+            .map(|rule| rule.trim().to_string())
+            .filter(|rule| !rule.is_empty())
+            .map(|rule| {
+                let (path, value) = rule.split_once('=').ok_or_else(|| {
+                    Error::Custom(format!("expected `path=Cache-Control`, got `{rule}`"))
+                })?;
+                let value = HeaderValue::from_str(value.trim()).map_err(|_| {
+                    Error::Custom(format!("invalid Cache-Control value in `{rule}`"))
+                })?;
+                Ok((path.trim().to_string(), value))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    fn rule_for(&self, path: &str) -> Option<&HeaderValue> {
+        self.rules
+            .iter()
+            .find(|(rule, _)| match rule.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => rule == path,
+            })
+            .map(|(_, value)| value)
+    }
+}
+
+pub async fn cache_headers(
+    State(policy): State<Arc<CachePolicy>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let cacheable = matches!(*req.method(), Method::GET | Method::HEAD);
+    let Some(cache_control) = policy
+        .rule_for(req.uri().path())
+        .filter(|_| cacheable)
+        .cloned()
+    else {
+        return next.run(req).await;
+    };
+    let if_none_match = req.headers().get(header::IF_NONE_MATCH).cloned();
+
+    let response = next.run(req).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, MAX_CACHED_BODY).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            tracing::warn!("Could not buffer the response to compute its ETag: {err}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let etag = etag(&bytes);
+    parts.headers.insert(header::CACHE_CONTROL, cache_control);
+    parts.headers.insert(header::ETAG, etag.clone());
+
+    if if_none_match.is_some_and(|value| matches_etag(&value, &etag)) {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.remove(header::CONTENT_TYPE);
+        return Response::from_parts(parts, Body::empty());
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// Strong validator of the body
+fn etag(body: &Bytes) -> HeaderValue {
+    let digest = format!("{:x}", Sha256::digest(body));
+    HeaderValue::from_str(&format!("\"{}\"", &digest[..32])).unwrap()
+}
+
+/// `If-None-Match` uses the weak comparison: `W/` prefixes are ignored, `*` matches any tag
+fn matches_etag(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let (Ok(candidates), Ok(etag)) = (if_none_match.to_str(), etag.to_str()) else {
+        return false;
+    };
+    candidates.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.trim_start_matches("W/") == etag.trim_start_matches("W/")
+    })
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_policy() -> Result<()> {
+        let policy = CachePolicy::from_rules(None)?;
+        assert_eq!(policy.rule_for("/info").unwrap(), "public, max-age=60");
+        assert!(policy.rule_for("/info/extra").is_none());
+
+        let policy = CachePolicy::from_rules(Some(vec![
+            "/api/v1/admin/limits*=private, no-cache".to_string(),
+            " ".to_string(),
+        ]))?;
+        assert!(policy.rule_for("/api/v1/admin/limits").is_some());
+        assert!(policy.rule_for("/info").is_none());
+        assert!(
+            CachePolicy::from_rules(Some(vec![String::new()]))?
+                .rules
+                .is_empty()
+        );
+        assert!(CachePolicy::from_rules(Some(vec!["/info".to_string()])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_etag() {
+        let etag = etag(&Bytes::from_static(b"{\"model_id\":\"bge\"}"));
+        assert_eq!(etag.len(), 34);
+        let weak =
+            HeaderValue::from_str(&format!("\"other\", W/{}", etag.to_str().unwrap())).unwrap();
+        assert!(matches_etag(&weak, &etag));
+        assert!(matches_etag(&HeaderValue::from_static("*"), &etag));
+        assert!(!matches_etag(&HeaderValue::from_static("\"other\""), &etag));
+    }
+}
+// endregion: Unit Test
@@ -0,0 +1,220 @@
+//! Client address and scheme of a request behind reverse proxies.
+//!
+//! Forwarding headers are only honored when the peer is one of the trusted proxies
+//! (`--trusted-proxies`), anyone else could spoof them. `X-Forwarded-For` and `Forwarded` chains
+//! are walked from the right, skipping trusted hops: the first untrusted address is the client.
+
+use crate::error::{Error, Result};
+use axum::extract::{ConnectInfo, State};
+use axum::http::{HeaderMap, Request};
+use axum::{body::Body, middleware::Next, response::Response};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardedHeader {
+    XForwardedFor,
+    XForwardedProto,
+    /// RFC 7239 `Forwarded`, both `for=` and `proto=`
+    Forwarded,
+}
+
+impl FromStr for ForwardedHeader {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "x-forwarded-for" => Ok(ForwardedHeader::XForwardedFor),
+            "x-forwarded-proto" => Ok(ForwardedHeader::XForwardedProto),
+            "forwarded" => Ok(ForwardedHeader::Forwarded),
+            other => Err(Error::Custom(format!(
+                "Unknown forwarding header `{other}`"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProxyConfig {
+    pub trusted: Vec<IpNet>,
+    pub headers: Vec<ForwardedHeader>,
+}
+
+impl ProxyConfig {
+    /// Parse the `--trusted-proxies` CIDRs (a bare address is a single host) and the
+    /// `--forwarded-headers` names
+    pub fn new(trusted: Vec<String>, headers: Vec<String>) -> Result<Self> {
+        let trusted = trusted
+            .iter()
+            .map(|net| {
+                let net = net.trim();
+                net.parse::<IpNet>()
+                    .or_else(|_| net.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| Error::Custom(format!("Invalid trusted proxy `{net}`")))
+            })
+            .collect::<Result<_>>()?;
+        let headers = headers
+            .iter()
+            .map(|header| header.parse())
+            .collect::<Result<_>>()?;
+        Ok(Self { trusted, headers })
+    }
+
+    fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted.iter().any(|net| net.contains(ip))
+    }
+
+    fn honors(&self, header: ForwardedHeader) -> bool {
+        self.headers.contains(&header)
+    }
+
+    /// Client of a request received from `peer`
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> ClientInfo {
+        let mut client = ClientInfo {
+            ip: peer,
+            scheme: "http".to_string(),
+        };
+        if !self.is_trusted(&peer) {
+            return client;
+        }
+
+        let mut chain = Vec::new();
+        if self.honors(ForwardedHeader::Forwarded) {
+            chain.extend(forwarded_params(headers, "for").filter_map(|value| parse_node(&value)));
+        }
+        if chain.is_empty() && self.honors(ForwardedHeader::XForwardedFor) {
+            chain.extend(
+                header_values(headers, "x-forwarded-for").filter_map(|value| parse_node(&value)),
+            );
+        }
+        // Rightmost untrusted hop, every address when all of them are proxies
+        if let Some(ip) = chain
+            .iter()
+            .rev()
+            .find(|ip| !self.is_trusted(ip))
+            .or(chain.first())
+        {
+            client.ip = *ip;
+        }
+
+        let proto = self
+            .honors(ForwardedHeader::Forwarded)
+            .then(|| forwarded_params(headers, "proto").next())
+            .flatten()
+            .or_else(|| {
+                self.honors(ForwardedHeader::XForwardedProto)
+                    .then(|| header_values(headers, "x-forwarded-proto").next())
+                    .flatten()
+            });
+        if let Some(proto) = proto.filter(|proto| proto == "http" || proto == "https") {
+            client.scheme = proto;
+        }
+        client
+    }
+}
+
+/// Client of the request, inserted in the request extensions by `client_info`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub ip: IpAddr,
+    pub scheme: String,
+}
+
+/// Comma separated values of every occurrence of the header, in order
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = String> + 'a {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim().to_ascii_lowercase())
+        .filter(|value| !value.is_empty())
+}
+
+/// Values of the `key` parameter of every `Forwarded` element, in order
+fn forwarded_params<'a>(headers: &'a HeaderMap, key: &'a str) -> impl Iterator<Item = String> + 'a {
+    header_values(headers, "forwarded").filter_map(move |element| {
+        element.split(';').find_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            (name.trim() == key).then(|| value.trim().trim_matches('"').to_string())
+        })
+    })
+}
+
+/// `1.2.3.4`, `1.2.3.4:80`, `[2001:db8::1]:80` or `2001:db8::1`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            node.strip_prefix('[')
+                .and_then(|node| node.split(']').next())
+                .and_then(|ip| ip.parse().ok())
+        })
+}
+
+/// Resolve the `ClientInfo` of every request, requires the connect info of the server
+pub async fn client_info(
+    State(config): State<Arc<ProxyConfig>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    let client = config.resolve(peer.ip(), req.headers());
+    req.extensions_mut().insert(client);
+    next.run(req).await
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(headers: &[&str]) -> ProxyConfig {
+        ProxyConfig::new(
+            vec!["10.0.0.0/8".to_string(), "192.168.1.1".to_string()],
+            headers.iter().map(|h| h.to_string()).collect(),
+        )
+        .unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_resolve_client() {
+        let proxy: IpAddr = "10.0.0.5".parse().unwrap();
+        let spoofed = headers(&[
+            ("x-forwarded-for", "6.6.6.6, 203.0.113.7, 10.0.0.9"),
+            ("x-forwarded-proto", "https"),
+        ]);
+        let xff = config(&["x-forwarded-for", "x-forwarded-proto"]);
+
+        // The leftmost address is client supplied, the rightmost untrusted one is the client
+        let client = xff.resolve(proxy, &spoofed);
+        assert_eq!(client.ip, "203.0.113.7".parse::<IpAddr>().unwrap());
+        assert_eq!(client.scheme, "https");
+
+        // Headers of untrusted peers are ignored
+        let direct: IpAddr = "198.51.100.1".parse().unwrap();
+        assert_eq!(xff.resolve(direct, &spoofed).ip, direct);
+        assert_eq!(xff.resolve(direct, &spoofed).scheme, "http");
+
+        // Only the configured headers are honored
+        assert_eq!(config(&["forwarded"]).resolve(proxy, &spoofed).ip, proxy);
+        let forwarded = headers(&[("forwarded", "for=\"[2001:db8::1]:4711\";proto=https")]);
+        let client = config(&["forwarded"]).resolve(proxy, &forwarded);
+        assert_eq!(client.ip, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(client.scheme, "https");
+
+        assert!(ProxyConfig::new(vec!["nope".to_string()], Vec::new()).is_err());
+    }
+}
+// endregion: Unit Test
@@ -0,0 +1,146 @@
+//! CORS policy built from `--cors-allow-origin`.
+//!
+//! Every entry of the flag is either `*` (any origin), an exact origin such as
+//! `https://app.example.com`, or a regex prefixed with `regex:` matched against the whole origin,
+//! e.g. `regex:^https://[a-z0-9-]+\.example\.com$`.
+
+use crate::error::{Error, Result};
+use axum::http::{HeaderName, HeaderValue, Method, header, request::Parts};
+use regex::Regex;
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+const REGEX_PREFIX: &str = "regex:";
+
+/// Response headers exposed to browsers, the timings and usage set by the inference routes
+const EXPOSED_HEADERS: [&str; 8] = [
+    "x-compute-type",
+    "x-compute-time",
+    "x-compute-characters",
+    "x-compute-tokens",
+    "x-total-time",
+    "x-tokenization-time",
+    "x-queue-time",
+    "x-inference-time",
+];
+
+/// `None` when no origin is configured, browsers then block cross-origin calls
+pub fn cors_layer(origins: Option<Vec<String>>) -> Result<Option<CorsLayer>> {
+    let origins: Vec<String> = origins
+        .unwrap_or_default()
+        .into_iter()
+        .map(|origin| origin.trim().to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+    if origins.is_empty() {
+        return Ok(None);
+    }
+
+    let allow_origin = allow_origin(&origins)?;
+    let layer = CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PATCH,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
+        // Preflight of authenticated requests carries `Authorization` in the requested headers
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::ACCEPT,
+            HeaderName::from_static("traceparent"),
+            HeaderName::from_static("tracestate"),
+        ])
+        .expose_headers(EXPOSED_HEADERS.map(HeaderName::from_static))
+        .max_age(Duration::from_secs(3600));
+    Ok(Some(layer))
+}
+
+fn allow_origin(origins: &[String]) -> Result<AllowOrigin> {
+    if origins.iter().any(|origin| origin == "*") {
+        return Ok(AllowOrigin::any());
+    }
+
+    let mut exact = Vec::new();
+    let mut patterns = Vec::new();
+    for origin in origins {
+        match origin.strip_prefix(REGEX_PREFIX) {
+            Some(pattern) => patterns.push(Regex::new(pattern).map_err(|e| {
+                Error::Custom(format!("Invalid CORS origin regex `{pattern}`: {e}"))
+            })?),
+            None => exact.push(
+                HeaderValue::from_str(origin)
+                    .map_err(|_| Error::Custom(format!("Invalid CORS origin `{origin}`")))?,
+            ),
+        }
+    }
+
+    if patterns.is_empty() {
+        return Ok(AllowOrigin::list(exact));
+    }
+    Ok(AllowOrigin::predicate(
+        move |origin: &HeaderValue, _parts: &Parts| {
+            exact.contains(origin)
+                || origin
+                    .to_str()
+                    .is_ok_and(|origin| patterns.iter().any(|pattern| pattern.is_match(origin)))
+        },
+    ))
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, http::Request, routing::get};
+    use tower::ServiceExt;
+
+    async fn preflight(layer: CorsLayer, origin: &str) -> Option<HeaderValue> {
+        let app = Router::new().route("/embed", get(|| async {})).layer(layer);
+        let req = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/embed")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        res.headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .cloned()
+    }
+
+    #[tokio::test]
+    async fn test_cors_origins() {
+        assert!(cors_layer(None).unwrap().is_none());
+        assert!(cors_layer(Some(vec!["regex:(".to_string()])).is_err());
+
+        let layer = cors_layer(Some(vec![
+            "https://app.example.com".to_string(),
+            r"regex:^https://[a-z]+\.preview\.example\.com$".to_string(),
+        ]))
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            preflight(layer.clone(), "https://app.example.com").await,
+            Some(HeaderValue::from_static("https://app.example.com"))
+        );
+        assert!(
+            preflight(layer.clone(), "https://pr.preview.example.com")
+                .await
+                .is_some()
+        );
+        assert!(preflight(layer, "https://evil.com").await.is_none());
+
+        let layer = cors_layer(Some(vec!["*".to_string()])).unwrap().unwrap();
+        assert_eq!(
+            preflight(layer, "https://any.com").await,
+            Some(HeaderValue::from_static("*"))
+        );
+    }
+}
+// endregion: Unit Test
@@ -0,0 +1,384 @@
+//! Rate limiting of the API routes, and the lifecycle of the limiter state cleanup.
+//!
+//! Every caller gets the tier of its role, stored in the settings and edited through
+//! `PUT /api/v1/admin/rate-limits/{role}`, or `--rate-limit-per-second` / `--rate-limit-burst`
+//! when its role has none. The limits of a user in the users table override the tier field by
+//! field, they are read through the user cache with the rest of the user. A tier may also cap
+//! the inputs per request below `max_client_batch_size`.
+//!
+//! Requests are keyed by caller (default) or by client address (`--rate-limit-key ip`), the
+//! address being resolved by `mw_client_ip` from the trusted proxy headers. Callers of the same
+//! quota share a keyed limiter, a caller moved to another tier starts with a full burst. The
+//! cleanup drops the keys that are back to a full burst, it runs as a tokio task on the server
+//! runtime and stops once the shutdown is signaled (or the sender dropped), after the connections
+//! were drained.
+
+use crate::cache::AppState;
+use crate::error::{Error, Result};
+use crate::middleware::mw_auth::Ctm;
+use crate::middleware::mw_client_ip::ClientInfo;
+use axum::body::Body;
+use axum::extract::Extension;
+use axum::http::{HeaderValue, Request, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use lib_core::database::ModelManager;
+use lib_core::model::settings::SettingMac;
+use lib_core::model::user::{Role, UserRateLimit};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroU32;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, MissedTickBehavior};
+use tracing::debug;
+
+/// Settings key of the tiers of the roles
+pub const RATE_LIMIT_TIERS_SETTING: &str = "rate_limit_tiers";
+
+/// Key of the rate limiter buckets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitKey {
+    ApiKey,
+    ClientIp,
+}
+
+impl FromStr for RateLimitKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "api-key" | "api_key" => Ok(RateLimitKey::ApiKey),
+            "ip" => Ok(RateLimitKey::ClientIp),
+            other => Err(format!(
+                "unknown rate limit key `{other}`, use api-key or ip"
+            )),
+        }
+    }
+}
+
+impl RateLimitKey {
+    /// Tenant and user of an authenticated caller, or the client address. `None` leaves the
+    /// request to `request_auth`.
+    fn extract(&self, req: &Request<Body>) -> Option<String> {
+        match self {
+            RateLimitKey::ApiKey => match req.extensions().get::<Result<Ctm>>() {
+                Some(Ok(Ctm(ctx))) => Some(format!("{}/{}", ctx.tenant_id(), ctx.user_id())),
+                _ => None,
+            },
+            RateLimitKey::ClientIp => req
+                .extensions()
+                .get::<ClientInfo>()
+                .map(|client| client.ip.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateTier {
+    pub requests_per_sec: u32,
+    pub burst: u32,
+    /// Inputs per request, below `max_client_batch_size`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_batch_size: Option<usize>,
+}
+
+impl RateTier {
+    pub fn validate(&self) -> Result<()> {
+        if self.requests_per_sec == 0 || self.burst == 0 || self.max_batch_size == Some(0) {
+            return Err(Error::BadRequest(
+                "`requests_per_sec`, `burst` and `max_batch_size` must be positive".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The fields set on the user replace the ones of the tier
+    pub fn with_user(self, user: &UserRateLimit) -> Self {
+        let positive = |value: Option<i32>| {
+            value
+                .and_then(|value| u32::try_from(value).ok())
+                .filter(|value| *value > 0)
+        };
+        Self {
+            requests_per_sec: positive(user.rate_limit_rps).unwrap_or(self.requests_per_sec),
+            burst: positive(user.rate_limit_burst).unwrap_or(self.burst),
+            max_batch_size: positive(user.max_batch_size)
+                .map(|size| size as usize)
+                .or(self.max_batch_size),
+        }
+    }
+
+    fn quota(&self) -> Quota {
+        Quota::per_second(NonZeroU32::new(self.requests_per_sec).unwrap_or(NonZeroU32::MIN))
+            .allow_burst(NonZeroU32::new(self.burst).unwrap_or(NonZeroU32::MIN))
+    }
+}
+
+/// Tiers of the roles and the limiters of their quotas
+pub struct RateLimits {
+    key: RateLimitKey,
+    default_tier: RateTier,
+    roles: RwLock<BTreeMap<String, RateTier>>,
+    /// By `(requests_per_sec, burst)`
+    limiters: RwLock<HashMap<(u32, u32), Arc<DefaultKeyedRateLimiter<String>>>>,
+}
+
+impl RateLimits {
+    pub fn new(key: RateLimitKey, default_tier: RateTier) -> Self {
+        Self {
+            key,
+            default_tier,
+            roles: RwLock::new(BTreeMap::new()),
+            limiters: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Restore the tiers of the roles, every role gets `default_tier` when they cannot be loaded
+    pub async fn load(mm: &ModelManager, key: RateLimitKey, default_tier: RateTier) -> Self {
+        let limits = Self::new(key, default_tier);
+        match SettingMac::get_value(mm, RATE_LIMIT_TIERS_SETTING).await {
+            Ok(roles) => *limits.roles.write().unwrap() = roles.unwrap_or_default(),
+            Err(err) => {
+                tracing::warn!("Could not load the rate limit tiers, using the default: {err}")
+            }
+        }
+        limits
+    }
+
+    pub fn default_tier(&self) -> RateTier {
+        self.default_tier
+    }
+
+    pub fn role_tiers(&self) -> BTreeMap<String, RateTier> {
+        self.roles.read().unwrap().clone()
+    }
+
+    /// Set the tier of `role`, or fall back to the default tier when `tier` is `None`. Persisted in
+    /// the settings, returns the tiers of the roles.
+    pub async fn set_role_tier(
+        &self,
+        mm: &ModelManager,
+        role: &Role,
+        tier: Option<RateTier>,
+    ) -> Result<BTreeMap<String, RateTier>> {
+        let roles = {
+            let mut roles = self.roles.write().unwrap();
+            match tier {
+                Some(tier) => {
+                    tier.validate()?;
+                    roles.insert(role.to_string(), tier);
+                }
+                None => {
+                    if roles.remove(&role.to_string()).is_none() {
+                        return Err(Error::NotFound(format!("Rate limit tier of {role}")));
+                    }
+                }
+            }
+            roles.clone()
+        };
+        SettingMac::set_value(mm, RATE_LIMIT_TIERS_SETTING, &roles).await?;
+        Ok(roles)
+    }
+
+    /// Tier of the role, or the default one, with the limits of the user
+    pub fn tier_for(&self, role: Option<&Role>, user: Option<&UserRateLimit>) -> RateTier {
+        let tier = role
+            .and_then(|role| self.roles.read().unwrap().get(&role.to_string()).copied())
+            .unwrap_or(self.default_tier);
+        match user {
+            Some(user) => tier.with_user(user),
+            None => tier,
+        }
+    }
+
+    /// Take one request of the bucket of `key`, the wait until the next one otherwise
+    fn check(&self, key: &str, tier: &RateTier) -> std::result::Result<(), Duration> {
+        let quota = (tier.requests_per_sec, tier.burst);
+        let limiter = self.limiters.read().unwrap().get(&quota).cloned();
+        let limiter = limiter.unwrap_or_else(|| {
+            self.limiters
+                .write()
+                .unwrap()
+                .entry(quota)
+                .or_insert_with(|| Arc::new(RateLimiter::keyed(tier.quota())))
+                .clone()
+        });
+        limiter
+            .check_key(&key.to_string())
+            .map_err(|not_until| not_until.wait_time_from(DefaultClock::default().now()))
+    }
+
+    /// Drop the keys back to a full burst, returns the keys left
+    pub fn retain_recent(&self) -> usize {
+        let limiters = self.limiters.read().unwrap();
+        limiters
+            .values()
+            .map(|limiter| {
+                limiter.retain_recent();
+                limiter.len()
+            })
+            .sum()
+    }
+}
+
+/// Rate limit of the caller, requests over its tier get `429 Too Many Requests` with
+/// `Retry-After`. The `AppState` of the request is given the batch size cap of the tier.
+pub async fn rate_limit(
+    Extension(app_state): Extension<AppState>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    let limits = app_state.rate_limits.clone();
+    let Some(key) = limits.key.extract(&req) else {
+        return next.run(req).await;
+    };
+    let ctx = match req.extensions().get::<Result<Ctm>>() {
+        Some(Ok(Ctm(ctx))) => Some(ctx.clone()),
+        _ => None,
+    };
+    let user = match &ctx {
+        Some(ctx) => app_state
+            .cache_user
+            .get(&ctx.user_id())
+            .await
+            .map(|user| user.rate_limit),
+        None => None,
+    };
+    let role = ctx.as_ref().and_then(|ctx| ctx.role());
+    let tier = limits.tier_for(role.as_ref(), user.as_ref());
+
+    if let Err(wait) = limits.check(&key, &tier) {
+        metrics::counter!("te_request_failure", "err" => "rate_limited").increment(1);
+        let mut response = Error::RateLimited(format!(
+            "Rate limit of {} requests per second exceeded, retry in {}ms",
+            tier.requests_per_sec,
+            wait.as_millis()
+        ))
+        .into_response();
+        let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        return response;
+    }
+    if tier.max_batch_size.is_some() {
+        req.extensions_mut().insert(AppState {
+            client_batch_limit: tier.max_batch_size,
+            ..app_state
+        });
+    }
+    next.run(req).await
+}
+
+/// Call `retain_recent` every `interval` until `shutdown` changes
+pub fn spawn_cleanup<F>(
+    retain_recent: F,
+    interval: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) -> JoinHandle<()>
+where
+    F: Fn() + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick completes immediately, nothing to clean yet
+        ticker.tick().await;
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => retain_recent(),
+                _ = shutdown.changed() => break,
+            }
+        }
+        debug!("Rate limiter cleanup stopped");
+    })
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_cleanup_stops_on_shutdown() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let counter = calls.clone();
+        let handle = spawn_cleanup(
+            move || {
+                counter.fetch_add(1, Ordering::Relaxed);
+            },
+            Duration::from_millis(10),
+            shutdown_rx,
+        );
+
+        tokio::time::sleep(Duration::from_millis(55)).await;
+        shutdown_tx.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .expect("cleanup task did not stop")
+            .unwrap();
+
+        let stopped_at = calls.load(Ordering::Relaxed);
+        assert!(stopped_at >= 1);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(calls.load(Ordering::Relaxed), stopped_at);
+    }
+
+    #[test]
+    fn test_rate_tiers() {
+        let default_tier = RateTier {
+            requests_per_sec: 80,
+            burst: 50,
+            max_batch_size: None,
+        };
+        let limits = RateLimits::new(RateLimitKey::ApiKey, default_tier);
+        limits.roles.write().unwrap().insert(
+            Role::Viewer.to_string(),
+            RateTier {
+                requests_per_sec: 1,
+                burst: 2,
+                max_batch_size: Some(4),
+            },
+        );
+
+        assert_eq!(limits.tier_for(Some(&Role::Admin), None), default_tier);
+        let user = UserRateLimit {
+            rate_limit_burst: Some(3),
+            max_batch_size: Some(-1),
+            ..Default::default()
+        };
+        let tier = limits.tier_for(Some(&Role::Viewer), Some(&user));
+        assert_eq!(
+            tier,
+            RateTier {
+                requests_per_sec: 1,
+                burst: 3,
+                max_batch_size: Some(4),
+            }
+        );
+
+        // The burst, then the callers are limited separately
+        for _ in 0..3 {
+            assert!(limits.check("default/a", &tier).is_ok());
+        }
+        assert!(limits.check("default/a", &tier).is_err());
+        assert!(limits.check("default/b", &tier).is_ok());
+        assert_eq!(limits.retain_recent(), 2);
+        assert!(
+            RateTier {
+                burst: 0,
+                ..default_tier
+            }
+            .validate()
+            .is_err()
+        );
+    }
+}
+// endregion: Unit Test
@@ -0,0 +1,68 @@
+//! Query parameters shared by the history listings: `?limit=&cursor=&from=&to=&status=`, parsed
+//! and validated once so every listing pages and filters the same way, see `HistoryFilter`.
+
+use crate::error::{Error, Result};
+use axum::extract::{FromRequestParts, Query};
+use axum::http::request::Parts;
+use lib_core::model::pagination::HistoryFilter;
+use serde::de::DeserializeOwned;
+
+/// Validated `HistoryFilter` of the request, `S` is the status of the listing. Malformed or out of
+/// range parameters are rejected with `400 Bad Request`.
+#[derive(Debug, Clone)]
+pub struct History<S>(pub HistoryFilter<S>);
+
+impl<S, St> FromRequestParts<St> for History<S>
+where
+    S: DeserializeOwned + Send,
+    St: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &St) -> Result<Self> {
+        let Query(filter) = Query::<HistoryFilter<S>>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| Error::BadRequest(rejection.body_text()))?;
+        filter.validate().map_err(Error::BadRequest)?;
+        Ok(History(filter))
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+    use lib_core::model::evaluation::RunStatus;
+
+    async fn extract(query: &str) -> Result<History<RunStatus>> {
+        let (mut parts, _) = Request::get(format!("/runs?{query}"))
+            .body(())
+            .unwrap()
+            .into_parts();
+        History::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn test_history_extractor() {
+        let History(filter) =
+            extract("limit=10&cursor=42&status=regression&from=2025-01-01T00:00:00Z")
+                .await
+                .unwrap();
+        assert_eq!(filter.limit(), 10);
+        assert_eq!(filter.cursor, Some(42));
+        assert_eq!(filter.status, Some(RunStatus::Regression));
+
+        for query in [
+            "limit=0",
+            "limit=100000",
+            "status=unknown",
+            "from=yesterday",
+        ] {
+            assert!(matches!(extract(query).await, Err(Error::BadRequest(_))));
+        }
+        let range = "from=2025-02-01T00:00:00Z&to=2025-01-01T00:00:00Z";
+        assert!(matches!(extract(range).await, Err(Error::BadRequest(_))));
+    }
+}
+// endregion: Unit Test
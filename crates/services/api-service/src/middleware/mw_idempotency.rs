@@ -0,0 +1,329 @@
+//! `Idempotency-Key` header of the `POST` routes, so that a client retrying a request whose
+//! response was lost (e.g. a load balancer timing out) does not get its inputs embedded and
+//! counted twice.
+//!
+//! The first request with a key runs and its response is kept for `--idempotency-ttl-sec`, a retry
+//! with the same key by the same user gets the stored response with `Idempotent-Replayed: true`.
+//! A retry while the first request is still running is answered `409 Conflict`, a key reused with
+//! another body `400 Bad Request`. Server errors, `408`, `424`, `429` and responses too large to
+//! keep are not stored, their retries run again. Request bodies are buffered up to
+//! `MAX_REQUEST_BODY`, or the limit of their route given to `with_body_limit`.
+
+use crate::error::{Error, Result};
+use crate::middleware::mw_auth::Ctm;
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::State;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use moka::future::Cache;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+/// Longest key accepted, UUIDs and hashes fit easily
+const MAX_KEY_LEN: usize = 255;
+/// Request bodies are buffered to fingerprint them, up to this size unless their route allows more
+const MAX_REQUEST_BODY: usize = 64 << 20;
+/// Larger responses, and streamed ones, are not stored
+const MAX_STORED_BODY: usize = 16 << 20;
+/// Bytes of all the stored responses
+const MAX_STORED_BYTES: u64 = 256 << 20;
+
+#[derive(Clone)]
+enum Entry {
+    /// The first request with the key is running
+    InFlight { fingerprint: String },
+    Done {
+        fingerprint: String,
+        response: Arc<StoredResponse>,
+    },
+}
+
+impl Entry {
+    fn fingerprint(&self) -> &str {
+        match self {
+            Entry::InFlight { fingerprint } | Entry::Done { fingerprint, .. } => fingerprint,
+        }
+    }
+
+    fn weight(&self) -> u32 {
+        match self {
+            Entry::InFlight { .. } => 1,
+            Entry::Done { response, .. } => response.body.len().try_into().unwrap_or(u32::MAX),
+        }
+    }
+}
+
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl StoredResponse {
+    fn replay(&self) -> Response {
+        let mut response = (self.status, self.headers.clone(), self.body.clone()).into_response();
+        response
+            .headers_mut()
+            .insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+        response
+    }
+}
+
+/// Responses of the idempotency keys, `None` when `--idempotency-ttl-sec` is `0`
+#[derive(Clone)]
+pub struct IdempotencyCache {
+    entries: Option<Cache<String, Entry>>,
+    /// Request body limits of the routes accepting more than `MAX_REQUEST_BODY`, by path
+    body_limits: Arc<Vec<(String, usize)>>,
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl: Duration) -> Self {
+        let entries = (!ttl.is_zero()).then(|| {
+            Cache::builder()
+                .time_to_live(ttl)
+                .weigher(|key: &String, entry: &Entry| {
+                    entry.weight().saturating_add(key.len() as u32)
+                })
+                .max_capacity(MAX_STORED_BYTES)
+                .build()
+        });
+        Self {
+            entries,
+            body_limits: Arc::default(),
+        }
+    }
+
+    /// Buffer up to `limit` bytes of the requests to `path`, the body limit of its route
+    pub fn with_body_limit(mut self, path: impl Into<String>, limit: usize) -> Self {
+        Arc::make_mut(&mut self.body_limits).push((path.into(), limit));
+        self
+    }
+
+    fn body_limit(&self, path: &str) -> usize {
+        self.body_limits
+            .iter()
+            .find(|(route, _)| route == path)
+            .map_or(MAX_REQUEST_BODY, |(_, limit)| *limit)
+    }
+}
+
+/// Releases the key of a request that did not complete, e.g. when the client disconnected, so
+/// that its retry runs instead of getting `409 Conflict` until the key expires
+struct InFlightGuard {
+    entries: Cache<String, Entry>,
+    key: Option<String>,
+}
+
+impl InFlightGuard {
+    async fn finish(mut self, entry: Option<Entry>) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        match entry {
+            Some(entry) => self.entries.insert(key, entry).await,
+            None => self.entries.invalidate(&key).await,
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            let entries = self.entries.clone();
+            tokio::spawn(async move { entries.invalidate(&key).await });
+        }
+    }
+}
+
+pub async fn idempotency_keys(
+    State(cache): State<IdempotencyCache>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response> {
+    let body_limit = cache.body_limit(req.uri().path());
+    let Some(entries) = cache.entries.filter(|_| req.method() == Method::POST) else {
+        return Ok(next.run(req).await);
+    };
+    let Some(key) = req.headers().get(&IDEMPOTENCY_KEY).cloned() else {
+        return Ok(next.run(req).await);
+    };
+    let key = key
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LEN)
+        .ok_or_else(|| {
+            Error::BadRequest(format!(
+                "`Idempotency-Key` must be 1 to {MAX_KEY_LEN} visible ASCII characters"
+            ))
+        })?
+        .to_string();
+    // Keys are scoped to the caller and the route
+    let user = match req.extensions().get::<Result<Ctm>>() {
+        Some(Ok(Ctm(ctx))) => ctx.user_id(),
+        _ => return Ok(next.run(req).await),
+    };
+    let key = format!("{user}\n{}\n{key}", req.uri().path());
+
+    let (parts, body) = req.into_parts();
+    let body = axum::body::to_bytes(body, body_limit)
+        .await
+        .map_err(|err| Error::BadRequest(format!("Could not read the request body: {err}")))?;
+    let fingerprint = format!("{:x}", Sha256::digest(&body));
+
+    let entry = entries
+        .entry(key.clone())
+        .or_insert(Entry::InFlight {
+            fingerprint: fingerprint.clone(),
+        })
+        .await;
+    if !entry.is_fresh() {
+        let entry = entry.into_value();
+        if entry.fingerprint() != fingerprint {
+            return Err(Error::BadRequest(
+                "`Idempotency-Key` was already used with another request body".to_string(),
+            ));
+        }
+        return match entry {
+            Entry::Done { response, .. } => {
+                metrics::counter!("te_idempotent_replays").increment(1);
+                Ok(response.replay())
+            }
+            Entry::InFlight { .. } => Err(Error::Conflict(
+                "A request with this `Idempotency-Key` is still running".to_string(),
+            )),
+        };
+    }
+
+    let guard = InFlightGuard {
+        entries,
+        key: Some(key),
+    };
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    let status = response.status();
+    // Failures of the model and timeouts are retried like server errors
+    let storable = !status.is_server_error()
+        && status != StatusCode::REQUEST_TIMEOUT
+        && status != StatusCode::FAILED_DEPENDENCY
+        && status != StatusCode::TOO_MANY_REQUESTS
+        && response
+            .body()
+            .size_hint()
+            .exact()
+            .is_some_and(|len| len <= MAX_STORED_BODY as u64);
+    if !storable {
+        guard.finish(None).await;
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_STORED_BODY).await {
+        Ok(body) => body,
+        Err(err) => {
+            tracing::warn!("Could not buffer the response of an idempotent request: {err}");
+            guard.finish(None).await;
+            return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+    let stored = Arc::new(StoredResponse {
+        status: parts.status,
+        headers: parts.headers.clone(),
+        body: body.clone(),
+    });
+    guard
+        .finish(Some(Entry::Done {
+            fingerprint,
+            response: stored,
+        }))
+        .await;
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, middleware::from_fn_with_state, routing::post};
+    use lib_core::ctx::Ctx;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    fn app(calls: Arc<AtomicUsize>) -> Router {
+        let cache = IdempotencyCache::new(Duration::from_secs(60)).with_body_limit("/import", 64);
+        let handler = move |body: String| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            async move { format!("{body}:{call}") }
+        };
+        Router::new()
+            .route("/embed", post(handler.clone()))
+            .route("/import", post(handler))
+            .layer(from_fn_with_state(cache, idempotency_keys))
+            .layer(axum::middleware::from_fn(
+                |mut req: Request<Body>, next: Next| async move {
+                    let ctx = Ctx::new("user".to_string(), None).map(Ctm);
+                    req.extensions_mut()
+                        .insert(ctx.map_err(|err| Error::Custom(err.to_string())));
+                    next.run(req).await
+                },
+            ))
+    }
+
+    async fn send(app: &Router, key: Option<&str>, body: &str) -> (StatusCode, String, bool) {
+        send_to(app, "/embed", key, body).await
+    }
+
+    async fn send_to(
+        app: &Router,
+        path: &str,
+        key: Option<&str>,
+        body: &str,
+    ) -> (StatusCode, String, bool) {
+        let mut req = Request::post(path);
+        if let Some(key) = key {
+            req = req.header(IDEMPOTENCY_KEY, key);
+        }
+        let response = app
+            .clone()
+            .oneshot(req.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap();
+        let replayed = response.headers().contains_key(IDEMPOTENT_REPLAYED);
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap(), replayed)
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_keys() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = app(calls.clone());
+
+        assert_eq!(
+            send(&app, Some("k1"), "a").await,
+            (StatusCode::OK, "a:0".to_string(), false)
+        );
+        assert_eq!(
+            send(&app, Some("k1"), "a").await,
+            (StatusCode::OK, "a:0".to_string(), true)
+        );
+        assert_eq!(send(&app, Some("k1"), "b").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(send(&app, Some("k2"), "a").await.1, "a:1");
+        assert_eq!(send(&app, None, "a").await.1, "a:2");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // The body limit of the route applies instead of `MAX_REQUEST_BODY`
+        let large = "x".repeat(65);
+        assert_eq!(
+            send_to(&app, "/import", Some("k3"), &large).await.0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(send_to(&app, "/import", Some("k3"), "a").await.1, "a:3");
+    }
+}
+// endregion: Unit Test
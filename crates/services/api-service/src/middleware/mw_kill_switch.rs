@@ -0,0 +1,144 @@
+//! Runtime kill-switch of routes.
+//!
+//! Operators disable optional functionality during incidents through
+//! `PUT /api/v1/admin/disabled-routes/{*path}` without redeploying. A disabled path also disables
+//! every route below it (`/api/v1/files` covers `/api/v1/files/42/chunks`) and answers
+//! `503 Service Unavailable` with the reason. The admin API itself cannot be disabled.
+
+use crate::error::{Error, Result};
+use crate::types::ErrorResponse;
+use axum::extract::State;
+use axum::http::{Request, StatusCode};
+use axum::{
+    body::Body,
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use lib_core::database::ModelManager;
+use lib_core::model::settings::SettingMac;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+/// Settings key of the disabled paths and their reason
+pub const DISABLED_ROUTES_SETTING: &str = "disabled_routes";
+const ADMIN_PREFIX: &str = "/api/v1/admin";
+
+#[derive(Debug, Default)]
+pub struct RouteToggles {
+    disabled: RwLock<BTreeMap<String, String>>,
+}
+
+impl RouteToggles {
+    /// Restore the paths disabled before the restart, none when they cannot be loaded
+    pub async fn load(mm: &ModelManager) -> Self {
+        let disabled = match SettingMac::get_value(mm, DISABLED_ROUTES_SETTING).await {
+            Ok(disabled) => disabled.unwrap_or_default(),
+            Err(err) => {
+                tracing::warn!("Could not load the disabled routes, all routes enabled: {err}");
+                BTreeMap::new()
+            }
+        };
+        Self {
+            disabled: RwLock::new(disabled),
+        }
+    }
+
+    /// Reason of the disabled path covering `path`
+    pub fn disabled_reason(&self, path: &str) -> Option<String> {
+        let disabled = self.disabled.read().unwrap();
+        disabled
+            .iter()
+            .find(|(prefix, _)| covers(prefix, path))
+            .map(|(_, reason)| reason.clone())
+    }
+
+    pub fn disabled(&self) -> BTreeMap<String, String> {
+        self.disabled.read().unwrap().clone()
+    }
+
+    /// Disable `path` with `reason`, or enable it again when `reason` is `None`. Persisted in the
+    /// settings, returns the disabled paths.
+    pub async fn set(
+        &self,
+        mm: &ModelManager,
+        path: &str,
+        reason: Option<String>,
+    ) -> Result<BTreeMap<String, String>> {
+        let path = normalize(path)?;
+        let disabled = {
+            let mut disabled = self.disabled.write().unwrap();
+            match reason {
+                Some(reason) => {
+                    disabled.insert(path, reason);
+                }
+                None => {
+                    if disabled.remove(&path).is_none() {
+                        return Err(Error::NotFound(format!("Disabled route {path}")));
+                    }
+                }
+            }
+            disabled.clone()
+        };
+        SettingMac::set_value(mm, DISABLED_ROUTES_SETTING, &disabled).await?;
+        Ok(disabled)
+    }
+}
+
+/// `path` is `prefix` or one of its sub-paths
+fn covers(prefix: &str, path: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Leading `/`, no trailing `/`, and neither the admin API nor one of its parents
+fn normalize(path: &str) -> Result<String> {
+    let path = format!("/{}", path.trim().trim_matches('/'));
+    if path == "/" || covers(&path, ADMIN_PREFIX) || covers(ADMIN_PREFIX, &path) {
+        return Err(Error::Custom(format!("Route {path} cannot be disabled")));
+    }
+    Ok(path)
+}
+
+pub async fn route_kill_switch(
+    State(toggles): State<Arc<RouteToggles>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    match toggles.disabled_reason(req.uri().path()) {
+        Some(reason) => {
+            let error = ErrorResponse::from(Error::RouteDisabled(reason));
+            (StatusCode::SERVICE_UNAVAILABLE, Json(error)).into_response()
+        }
+        None => next.run(req).await,
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_paths() {
+        assert_eq!(normalize("api/v1/files/").unwrap(), "/api/v1/files");
+        assert!(normalize("/").is_err());
+        assert!(normalize("/api/v1").is_err());
+        assert!(normalize("/api/v1/admin/limits").is_err());
+
+        let toggles = RouteToggles::default();
+        toggles
+            .disabled
+            .write()
+            .unwrap()
+            .insert("/api/v1/files".to_string(), "incident".to_string());
+        assert_eq!(
+            toggles
+                .disabled_reason("/api/v1/files/42/chunks")
+                .as_deref(),
+            Some("incident")
+        );
+        assert!(toggles.disabled_reason("/api/v1/files-archive").is_none());
+        assert!(toggles.disabled_reason("/embed").is_none());
+    }
+}
+// endregion: Unit Test
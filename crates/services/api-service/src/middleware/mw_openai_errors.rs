@@ -0,0 +1,146 @@
+//! Errors of the OpenAI compatible `/embeddings` route in the shape of the OpenAI API,
+//! `{"error": {"message", "type", "code"}}`, whichever layer failed the request (authentication,
+//! rate limit, deadline, kill-switch, malformed body...).
+//!
+//! The OpenAI SDKs retry `408`, `409`, `429` and `5xx` with backoff and honor `Retry-After`, the
+//! statuses are kept for them. The others are folded into the ones of the OpenAI API: `413`,
+//! `415` and `422` into `400`, and `424` (the model failed to run the batch) into `500` so that
+//! the SDKs retry it.
+
+use crate::error::Error;
+use crate::types::{ErrorResponse, ErrorType, OpenAICompatError, OpenAICompatErrorResponse};
+use axum::body::Body;
+use axum::http::{Request, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Json, Response};
+
+/// Paths answering errors in the shape of the OpenAI API
+pub const OPENAI_COMPAT_PATHS: [&str; 1] = ["/api/v1/embeddings"];
+/// Plain text rejections of the extractors are short
+const MAX_REJECTION_BODY: usize = 64 << 10;
+
+/// Status of the OpenAI API for `status`, with its error category
+fn openai_status(status: StatusCode) -> (StatusCode, &'static str) {
+    match status {
+        StatusCode::UNAUTHORIZED => (status, "authentication_error"),
+        StatusCode::FORBIDDEN => (status, "permission_error"),
+        StatusCode::NOT_FOUND => (status, "not_found_error"),
+        StatusCode::CONFLICT => (status, "conflict_error"),
+        StatusCode::TOO_MANY_REQUESTS => (status, "rate_limit_error"),
+        StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => (status, "timeout_error"),
+        StatusCode::FAILED_DEPENDENCY => (StatusCode::INTERNAL_SERVER_ERROR, "server_error"),
+        status if status.is_server_error() => (status, "server_error"),
+        _ => (StatusCode::BAD_REQUEST, "invalid_request_error"),
+    }
+}
+
+pub async fn openai_errors(req: Request<Body>, next: Next) -> Response {
+    if !OPENAI_COMPAT_PATHS.contains(&req.uri().path()) {
+        return next.run(req).await;
+    }
+    let response = next.run(req).await;
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let (message, code) = match parts.extensions.get::<Error>() {
+        Some(err) => (
+            ErrorResponse::from(err.clone()).error,
+            Some(ErrorType::from(err)),
+        ),
+        // Rejections of the extractors, e.g. a malformed body, are plain text
+        None => {
+            let body = axum::body::to_bytes(body, MAX_REJECTION_BODY)
+                .await
+                .unwrap_or_default();
+            let message = String::from_utf8_lossy(&body).trim().to_string();
+            (message, None)
+        }
+    };
+    let (status, error_type) = openai_status(status);
+    parts.status = status;
+    // Set again for the new body, `Retry-After` and the other headers are kept
+    parts.headers.remove(header::CONTENT_TYPE);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let body = OpenAICompatErrorResponse {
+        error: OpenAICompatError {
+            message,
+            error_type,
+            code,
+        },
+    };
+    (parts, Json(body)).into_response()
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, middleware::from_fn, routing::post};
+    use serde_json::{Value, json};
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/api/v1/embeddings",
+                post(|Json(body): Json<Value>| async move {
+                    match body["fail"].as_str() {
+                        Some("queue") => Err(Error::QueueFull),
+                        Some("backend") => Err(Error::Backend("Inference failed".to_string())),
+                        _ => Ok(Json(body)),
+                    }
+                }),
+            )
+            .route(
+                "/api/v1/embed",
+                post(|| async { Err::<(), _>(Error::QueueFull) }),
+            )
+            .layer(from_fn(openai_errors))
+    }
+
+    async fn send(path: &str, body: &str) -> (StatusCode, Value) {
+        let req = Request::post(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app().oneshot(req).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_openai_errors() {
+        let (status, body) = send("/api/v1/embeddings", r#"{"fail": "queue"}"#).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["error"]["type"], "rate_limit_error");
+        assert_eq!(body["error"]["code"], "queue_full");
+        assert_eq!(body["error"]["message"], "Queue is full. Please retry.");
+
+        // Failures of the model are retried by the SDKs
+        let (status, body) = send("/api/v1/embeddings", r#"{"fail": "backend"}"#).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["error"]["code"], "backend");
+
+        // Rejected by the `Json` extractor, before the handler
+        let (status, body) = send("/api/v1/embeddings", "{").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        assert_eq!(body["error"]["code"], Value::Null);
+        assert!(!body["error"]["message"].as_str().unwrap().is_empty());
+
+        let (status, body) = send("/api/v1/embeddings", r#"{"input": "ok"}"#).await;
+        assert_eq!((status, body), (StatusCode::OK, json!({"input": "ok"})));
+
+        // Other routes keep their error body
+        let (status, body) = send("/api/v1/embed", "{}").await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["error_type"], "queue_full");
+    }
+}
+// endregion: Unit Test
@@ -0,0 +1,177 @@
+//! Per-route deadlines, so that requests stuck behind a slow or hung backend are answered instead
+//! of piling up.
+//!
+//! Every rule of `--route-timeout` is `path=seconds`, separated by `;`:
+//! `/api/v1/embed*=10;/api/v1/score/*=60`. A path ending with `*` covers the paths starting with
+//! it, the first matching rule applies and `0` removes the deadline of a path. A request still
+//! running at its deadline is answered `408 Request Timeout` when the client had not sent its
+//! whole body yet, `504 Gateway Timeout` otherwise. The handler is dropped with it: its permit is
+//! released and its entries still in the inference queue are skipped, like the ones of a client
+//! that disconnected. Streamed bodies only have to start before the deadline.
+
+use crate::error::{Error, Result};
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::State;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use futures::StreamExt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::Poll;
+use std::time::Duration;
+
+/// Rules applied without `--route-timeout`
+pub const DEFAULT_ROUTE_TIMEOUTS: [&str; 4] = [
+    "/api/v1/embed*=10",
+    "/api/v1/search=30",
+    "/api/v1/score/*=60",
+    "/invocations=60",
+];
+
+#[derive(Debug, Default)]
+pub struct RouteDeadlines {
+    rules: Vec<(String, Option<Duration>)>,
+}
+
+impl RouteDeadlines {
+    /// `None` uses `DEFAULT_ROUTE_TIMEOUTS`, an empty rule list turns deadlines off
+    pub fn from_rules(rules: Option<Vec<String>>) -> Result<Self> {
+        let rules = rules
+            .unwrap_or_else(|| DEFAULT_ROUTE_TIMEOUTS.map(str::to_string).to_vec())
+            .into_iter()
+            .map(|rule| rule.trim().to_string())
+            .filter(|rule| !rule.is_empty())
+            .map(|rule| {
+                let (path, secs) = rule.split_once('=').ok_or_else(|| {
+                    Error::Custom(format!("expected `path=seconds`, got `{rule}`"))
+                })?;
+                let secs: u64 = secs
+                    .trim()
+                    .parse()
+                    .map_err(|_| Error::Custom(format!("invalid number of seconds in `{rule}`")))?;
+                let deadline = (secs > 0).then(|| Duration::from_secs(secs));
+                Ok((path.trim().to_string(), deadline))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    fn deadline_for(&self, path: &str) -> Option<Duration> {
+        self.rules
+            .iter()
+            .find(|(rule, _)| match rule.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => rule == path,
+            })
+            .and_then(|(_, deadline)| *deadline)
+    }
+}
+
+pub async fn route_deadlines(
+    State(deadlines): State<Arc<RouteDeadlines>>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response> {
+    let Some(deadline) = deadlines.deadline_for(req.uri().path()) else {
+        return Ok(next.run(req).await);
+    };
+
+    // Set once the handler has read the whole body, to tell a slow client from a slow backend
+    let (parts, body) = req.into_parts();
+    let received = Arc::new(AtomicBool::new(body.is_end_stream()));
+    let end = {
+        let received = received.clone();
+        futures::stream::poll_fn(move |_| {
+            received.store(true, Ordering::Relaxed);
+            Poll::<Option<core::result::Result<Bytes, axum::Error>>>::Ready(None)
+        })
+    };
+    let body = Body::from_stream(body.into_data_stream().chain(end));
+
+    match tokio::time::timeout(deadline, next.run(Request::from_parts(parts, body))).await {
+        Ok(response) => Ok(response),
+        Err(_) => {
+            let secs = deadline.as_secs();
+            if received.load(Ordering::Relaxed) {
+                metrics::counter!("te_request_timeout", "cause" => "backend").increment(1);
+                Err(Error::Timeout(format!(
+                    "The request did not complete within its {secs}s deadline"
+                )))
+            } else {
+                metrics::counter!("te_request_timeout", "cause" => "client").increment(1);
+                Err(Error::RequestTimeout(format!(
+                    "The request body was not received within {secs}s"
+                )))
+            }
+        }
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum::{Router, middleware::from_fn_with_state, routing::post};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_route_deadlines() -> Result<()> {
+        let deadlines = RouteDeadlines::from_rules(None)?;
+        assert_eq!(
+            deadlines.deadline_for("/api/v1/embed_sparse"),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(deadlines.deadline_for("/api/v1/files"), None);
+
+        let deadlines = RouteDeadlines::from_rules(Some(vec![
+            "/api/v1/search=0".to_string(),
+            "/api/v1/*= 5".to_string(),
+            " ".to_string(),
+        ]))?;
+        assert_eq!(deadlines.deadline_for("/api/v1/search"), None);
+        assert_eq!(
+            deadlines.deadline_for("/api/v1/files"),
+            Some(Duration::from_secs(5))
+        );
+        assert!(RouteDeadlines::from_rules(Some(vec!["/embed".to_string()])).is_err());
+        assert!(RouteDeadlines::from_rules(Some(vec!["/embed=1s".to_string()])).is_err());
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_route_deadline_status() {
+        let deadlines =
+            Arc::new(RouteDeadlines::from_rules(Some(vec!["/slow=1".to_string()])).unwrap());
+        let app = Router::new()
+            .route(
+                "/slow",
+                post(|body: String| async move {
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                    body
+                }),
+            )
+            .layer(from_fn_with_state(deadlines, route_deadlines));
+
+        let response = app
+            .clone()
+            .oneshot(Request::post("/slow").body(Body::from("a")).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        // The body never ends
+        let pending = futures::stream::pending::<core::result::Result<Bytes, axum::Error>>();
+        let response = app
+            .oneshot(
+                Request::post("/slow")
+                    .body(Body::from_stream(pending))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+    }
+}
+// endregion: Unit Test
@@ -0,0 +1,44 @@
+//! Propagation of the W3C trace context (`traceparent` / `tracestate`) from incoming requests.
+
+use axum::http::{HeaderMap, Request};
+use axum::{body::Body, middleware::Next, response::Response};
+use opentelemetry::global;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TraceContextExt;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Read-only view over the request headers for the OpenTelemetry propagator.
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// This middleware opens the root `http_request` span of every request, parented to the remote
+/// trace context if the caller sent a `traceparent` header. The trace id is recorded on the span
+/// so it shows up in the JSON logs of every nested event.
+pub async fn trace_context(req: Request<Body>, next: Next) -> Response {
+    let parent_context = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(req.headers()))
+    });
+
+    let span = tracing::info_span!(
+        "http_request",
+        http.method = %req.method(),
+        http.route = %req.uri().path(),
+        trace_id = tracing::field::Empty,
+    );
+    span.set_parent(parent_context);
+
+    let trace_id = span.context().span().span_context().trace_id();
+    span.record("trace_id", trace_id.to_string());
+
+    next.run(req).instrument(span).await
+}
@@ -0,0 +1,961 @@
+//! Admin API, nested under `/api/v1/admin` and restricted to `Role::Admin` by `require_admin`.
+//!
+//! Users are managed within the tenant of the admin, only the root key creates users of other
+//! tenants. Limits, rate limit tiers, the inference queue, disabled routes and the settings of the
+//! collections apply to the whole deployment and are only changed with the root key, which alone
+//! reads the replication changes of every tenant. Every mutation is recorded in the audit log of
+//! the tenant of the admin, listed by `GET /audit`.
+
+use crate::ai::limits::{LimitsSnapshot, LimitsUpdate};
+use crate::ai::queue::PendingEntry;
+use crate::cache::AppState;
+use crate::error::{Error, Result};
+use crate::log::sampling::SampleField;
+use crate::middleware::mw_auth::Ctm;
+use crate::middleware::mw_governor::RateTier;
+use crate::middleware::mw_history::History;
+use axum::{
+    Router,
+    extract::{Extension, Path, Query},
+    http::{StatusCode, header},
+    response::{IntoResponse, Json, Response},
+    routing::{get, patch, post, put},
+};
+use chrono::NaiveDateTime;
+use lib_auth::bearer::{ContentToHash, hash_key, new_api_secret};
+use lib_core::ctx::{Ctx, DEFAULT_TENANT};
+use lib_core::model::audit::{AuditEntry, AuditEntryForCreate, AuditKind, AuditMac};
+use lib_core::model::embedding_dims::{EMBEDDING_DIMENSION_SETTING, EmbeddingDimsMac};
+use lib_core::model::pagination::{ListOptions, Page};
+use lib_core::model::replication::{FRAME_CONTENT_TYPE, ReplicationMac};
+use lib_core::model::settings::SettingMac;
+use lib_core::model::text_search::{DEFAULT_TEXT_SEARCH_CONFIG, TextSearchMac};
+use lib_core::model::user::{
+    Role, User, UserBmc, UserForAuthentication, UserForCreate, UserForUpdate, UserRateLimit,
+};
+use lib_cron::chunker::{OVERSIZE_POLICY_SETTING, OversizePolicy};
+use lib_cron::replication::REPLICATION_CURSOR_SETTING;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+pub fn serve_admin() -> Router {
+    Router::new()
+        .route("/users", get(list_users).post(create_user))
+        .route("/users/{user_id}/role", patch(update_role))
+        .route("/users/{user_id}/deactivate", post(deactivate_user))
+        .route("/users/{user_id}/api-key", post(rotate_api_key))
+        .route(
+            "/users/{user_id}/rate-limit",
+            put(set_user_rate_limit).delete(delete_user_rate_limit),
+        )
+        .route("/rate-limits", get(get_rate_limits))
+        .route(
+            "/rate-limits/{role}",
+            put(set_rate_limit_tier).delete(delete_rate_limit_tier),
+        )
+        .route("/limits", get(get_limits).patch(update_limits))
+        .route("/audit", get(list_audit))
+        .route("/queue", get(get_queue))
+        .route("/queue/flush", post(flush_queue))
+        .route("/sampling", get(get_sampling))
+        .route(
+            "/sampling/opt-out/{user_id}",
+            put(opt_out_sampling).delete(opt_in_sampling),
+        )
+        .route("/disabled-routes", get(get_disabled_routes))
+        .route(
+            "/disabled-routes/{*path}",
+            put(disable_route).delete(enable_route),
+        )
+        .route("/replication", get(get_replication))
+        .route("/replication/changes", get(get_replication_changes))
+        .route("/oversize-policies", get(get_oversize_policies))
+        .route(
+            "/oversize-policies/{collection}",
+            put(set_oversize_policy).delete(delete_oversize_policy),
+        )
+        .route("/embedding-dimensions", get(get_embedding_dimensions))
+        .route(
+            "/embedding-dimensions/{collection}",
+            put(set_embedding_dimension).delete(delete_embedding_dimension),
+        )
+        .route("/text-search-configs", get(get_text_search_configs))
+        .route(
+            "/text-search-configs/{collection}",
+            put(set_text_search_config).delete(delete_text_search_config),
+        )
+}
+
+/// Settings key of the persisted batch limits
+const LIMITS_SETTING: &str = "batch_limits";
+/// Changes served per frame when the replica does not ask for a limit, and their maximum
+const REPLICATION_DEFAULT_LIMIT: i64 = 500;
+const REPLICATION_MAX_LIMIT: i64 = 5_000;
+/// User id of the admin created by `--bootstrap-admin`
+pub const BOOTSTRAP_ADMIN_ID: &str = "admin";
+
+/// User as returned by the admin API, the hashed API key is never exposed
+#[derive(Serialize)]
+struct UserResponse {
+    user_id: String,
+    tenant_id: String,
+    first_name: String,
+    last_name: String,
+    email: String,
+    role: Role,
+    has_api_key: bool,
+    created_at: NaiveDateTime,
+    /// Overrides of the rate limit tier of the role, `null` fields keep the tier value
+    #[serde(flatten)]
+    rate_limit: UserRateLimit,
+}
+
+impl From<User> for UserResponse {
+    fn from(user: User) -> Self {
+        Self {
+            user_id: user.user_id,
+            tenant_id: user.tenant_id,
+            first_name: user.first_name,
+            last_name: user.last_name,
+            email: user.email,
+            role: user.role,
+            has_api_key: user.api_key.is_some(),
+            created_at: user.created_at,
+            rate_limit: user.rate_limit,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateUserRequest {
+    #[serde(flatten)]
+    user: UserForCreate,
+    /// Tenant of the new user, the tenant of the admin by default
+    #[serde(default)]
+    tenant_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RoleUpdate {
+    role: Role,
+}
+
+#[derive(Serialize)]
+struct RateLimitsResponse {
+    /// Tier of the roles without their own
+    default: RateTier,
+    roles: BTreeMap<String, RateTier>,
+}
+
+#[derive(Serialize)]
+struct LimitsResponse {
+    current: LimitsSnapshot,
+    ceilings: LimitsSnapshot,
+}
+
+#[derive(Serialize)]
+struct QueueResponse {
+    pending: usize,
+    /// Pending entries and the age of the oldest one per kind
+    kinds: BTreeMap<&'static str, QueueKindSummary>,
+    /// Oldest first
+    entries: Vec<PendingEntry>,
+}
+
+#[derive(Serialize, Default)]
+struct QueueKindSummary {
+    pending: usize,
+    oldest_age_ms: u64,
+}
+
+#[derive(Serialize)]
+struct QueueFlushResponse {
+    /// Entries rejected with `503 Service Unavailable`
+    flushed: usize,
+}
+
+#[derive(Serialize)]
+struct SamplingResponse {
+    enabled: bool,
+    rate: f64,
+    fields: Vec<SampleField>,
+    opted_out: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct DisableRouteRequest {
+    /// Returned to the clients of the route
+    reason: String,
+}
+
+#[derive(Deserialize)]
+struct OversizePolicyUpdate {
+    policy: OversizePolicy,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingDimensionUpdate {
+    dimension: i32,
+}
+
+#[derive(Serialize)]
+struct EmbeddingDimensionsResponse {
+    /// Dimension of the collections without their own, `None` when the column has none
+    column: Option<i32>,
+    collections: HashMap<String, i32>,
+}
+
+#[derive(Deserialize)]
+struct TextSearchConfigUpdate {
+    /// Postgres text search configuration, e.g. `german` or `simple`
+    config: String,
+}
+
+#[derive(Serialize)]
+struct TextSearchReindexResponse {
+    collection: String,
+    config: String,
+    reindexed_chunks: u64,
+}
+
+#[derive(Deserialize)]
+struct ReplicationQuery {
+    /// Cursor of the last batch applied by the replica
+    #[serde(default)]
+    cursor: i64,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct ReplicationStatus {
+    /// Cursor of the last change written on this instance
+    cursor: i64,
+    /// Cursor of the last batch applied from the primary, on replicas
+    applied: Option<i64>,
+}
+
+/// Plaintext key, only returned once when it is minted
+#[derive(Serialize)]
+struct ApiKeyResponse {
+    user_id: String,
+    api_key: String,
+}
+
+#[derive(Serialize)]
+struct AuditResponse {
+    data: Vec<AuditEntry>,
+    next_cursor: Option<i64>,
+}
+
+/// Record a mutation in the audit log. It already happened, so a failure to record it is logged
+/// rather than returned to the caller.
+pub async fn audit(app_state: &AppState, ctx: &Ctx, entry: AuditEntryForCreate) {
+    let action = entry.action.clone();
+    if let Err(err) = AuditMac::record(&app_state.mm, ctx, entry).await {
+        tracing::error!(
+            "Could not record `{action}` by {} in the audit log: {err}",
+            ctx.user_id()
+        );
+    }
+}
+
+/// Mutations of the tenant of the admin, latest first. Paged and filtered with the history
+/// parameters, `status` is the kind of the mutation: `user`, `cron`, `model` or `setting`.
+async fn list_audit(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    History(filter): History<AuditKind>,
+) -> Result<Json<AuditResponse>> {
+    let data = AuditMac::find_entries(&app_state.mm, &ctx.tenant_id(), &filter).await?;
+    let next_cursor = filter.next_cursor(&data, |entry| entry.audit_id);
+    Ok(Json(AuditResponse { data, next_cursor }))
+}
+
+/// Keyset paginated on `user_id`, see `ListOptions`
+async fn list_users(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Query(options): Query<ListOptions<String>>,
+) -> Result<Json<Page<UserResponse, String>>> {
+    let tenant_id = ctx.tenant_id();
+    let users = UserBmc::list_users(&app_state.mm, &tenant_id, &options).await?;
+    let total = UserBmc::count_users(&app_state.mm, &tenant_id).await?;
+    let page = Page::new(users, total, options.limit(), |user| user.user_id.clone());
+    Ok(Json(page.map(UserResponse::from)))
+}
+
+/// Settings of the whole deployment are only changed with the root key, not by the admins of
+/// a tenant
+pub fn require_root(ctx: &Ctx) -> Result<()> {
+    match ctx.is_root() {
+        true => Ok(()),
+        false => Err(Error::Forbidden("Root key required".to_string())),
+    }
+}
+
+/// Tenant a user is created in, admins other than root cannot leave their own tenant
+fn target_tenant(ctx: &Ctx, requested: Option<String>) -> Result<String> {
+    let Some(tenant) = requested else {
+        return Ok(ctx.tenant_id());
+    };
+    if tenant.is_empty() {
+        return Err(Error::BadRequest("`tenant_id` cannot be empty".to_string()));
+    }
+    if tenant != ctx.tenant_id() && !ctx.is_root() {
+        return Err(Error::Forbidden(format!(
+            "Cannot create users of tenant {tenant}"
+        )));
+    }
+    Ok(tenant)
+}
+
+async fn create_user(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Json(req): Json<CreateUserRequest>,
+) -> Result<Response> {
+    let user = req.user;
+    if user.user_id.is_empty() || user.user_id == "root" {
+        return Err(Error::BadRequest(format!(
+            "Invalid user id `{}`",
+            user.user_id
+        )));
+    }
+    let tenant_id = target_tenant(&ctx, req.tenant_id)?;
+    let user = UserResponse::from(UserBmc::create_user(&app_state.mm, &tenant_id, user).await?);
+    let entry =
+        AuditEntryForCreate::new(AuditKind::User, "user.create", &user.user_id).after(&user);
+    audit(&app_state, &ctx, entry).await;
+    Ok((StatusCode::CREATED, Json(user)).into_response())
+}
+
+async fn update_role(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(user_id): Path<String>,
+    Json(update): Json<RoleUpdate>,
+) -> Result<Json<UserResponse>> {
+    let before = find_user(&app_state, &ctx, &user_id).await?;
+    let update = UserForUpdate {
+        first_name: None,
+        last_name: None,
+        email: None,
+        role: Some(update.role),
+        api_key: None,
+    };
+    let user = UserBmc::update_user(&app_state.mm, &ctx.tenant_id(), &user_id, update)
+        .await
+        .map_err(|_| Error::NotFound(format!("User {user_id}")))?;
+    app_state.cache_user.invalidate(&user_id).await;
+    let user = UserResponse::from(user);
+    let entry = AuditEntryForCreate::new(AuditKind::User, "user.role", &user_id)
+        .before(&before)
+        .after(&user);
+    audit(&app_state, &ctx, entry).await;
+    Ok(Json(user))
+}
+
+async fn deactivate_user(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(user_id): Path<String>,
+) -> Result<Json<UserResponse>> {
+    let before = find_user(&app_state, &ctx, &user_id).await?;
+    let user = UserBmc::deactivate_user(&app_state.mm, &ctx.tenant_id(), &user_id)
+        .await
+        .map_err(|_| Error::NotFound(format!("User {user_id}")))?;
+    app_state.cache_user.invalidate(&user_id).await;
+    let user = UserResponse::from(user);
+    let entry = AuditEntryForCreate::new(AuditKind::User, "user.deactivate", &user_id)
+        .before(&before)
+        .after(&user);
+    audit(&app_state, &ctx, entry).await;
+    Ok(Json(user))
+}
+
+/// User of the tenant of the admin, as it is before a change
+async fn find_user(app_state: &AppState, ctx: &Ctx, user_id: &str) -> Result<UserResponse> {
+    UserBmc::get_user_by_id(&app_state.mm, &ctx.tenant_id(), user_id)
+        .await
+        .map(UserResponse::from)
+        .map_err(|_| Error::NotFound(format!("User {user_id}")))
+}
+
+/// Mint a new API key for the user, the previous key stops working immediately
+async fn rotate_api_key(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(user_id): Path<String>,
+) -> Result<Json<ApiKeyResponse>> {
+    let tenant_id = ctx.tenant_id();
+    let user = UserBmc::get_user_for_auth(&app_state.mm, &user_id)
+        .await
+        .ok()
+        .filter(|user| user.tenant_id == tenant_id)
+        .ok_or_else(|| Error::NotFound(format!("User {user_id}")))?;
+    if user.role == Role::Inactive {
+        return Err(Error::Custom(format!("User {user_id} is inactive")));
+    }
+
+    let api_key = mint_api_key(&app_state, &user).await?;
+    // Neither the key nor its hash is recorded
+    audit(
+        &app_state,
+        &ctx,
+        AuditEntryForCreate::new(AuditKind::User, "user.api_key", &user_id),
+    )
+    .await;
+    Ok(Json(ApiKeyResponse { api_key, user_id }))
+}
+
+/// Store the hash of a new secret for the user, returns the plaintext `<user_id>.<secret>` key
+async fn mint_api_key(app_state: &AppState, user: &UserForAuthentication) -> Result<String> {
+    let secret = new_api_secret();
+    let hashed_key = hash_key(ContentToHash {
+        content: secret.clone(),
+        salt: user.salt,
+    })?;
+    UserBmc::set_api_key(&app_state.mm, &user.tenant_id, &user.user_id, &hashed_key).await?;
+    app_state.cache_user.invalidate(&user.user_id).await;
+    Ok(format!("{}.{secret}", user.user_id))
+}
+
+/// Override the rate limit tier of the role for the user, missing fields keep the tier value
+async fn set_user_rate_limit(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(user_id): Path<String>,
+    Json(rate_limit): Json<UserRateLimit>,
+) -> Result<Json<UserResponse>> {
+    let values = [
+        rate_limit.rate_limit_rps,
+        rate_limit.rate_limit_burst,
+        rate_limit.max_batch_size,
+    ];
+    if values.iter().flatten().any(|value| *value <= 0) {
+        return Err(Error::BadRequest(
+            "`rate_limit_rps`, `rate_limit_burst` and `max_batch_size` must be positive"
+                .to_string(),
+        ));
+    }
+    set_rate_limit(&app_state, &ctx, &user_id, rate_limit).await
+}
+
+/// Back to the rate limit tier of the role
+async fn delete_user_rate_limit(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(user_id): Path<String>,
+) -> Result<Json<UserResponse>> {
+    set_rate_limit(&app_state, &ctx, &user_id, UserRateLimit::default()).await
+}
+
+async fn set_rate_limit(
+    app_state: &AppState,
+    ctx: &Ctx,
+    user_id: &str,
+    rate_limit: UserRateLimit,
+) -> Result<Json<UserResponse>> {
+    let before = find_user(app_state, ctx, user_id).await?.rate_limit;
+    let user = UserBmc::set_rate_limit(&app_state.mm, &ctx.tenant_id(), user_id, rate_limit)
+        .await
+        .map_err(|_| Error::NotFound(format!("User {user_id}")))?;
+    // The limiter reads the limits of the user from the cache
+    app_state.cache_user.invalidate(user_id).await;
+    tracing::info!("Rate limit of {user_id} set to {rate_limit:?}");
+    let entry = AuditEntryForCreate::new(AuditKind::User, "user.rate_limit", user_id)
+        .before(&before)
+        .after(&rate_limit);
+    audit(app_state, ctx, entry).await;
+    Ok(Json(UserResponse::from(user)))
+}
+
+async fn get_rate_limits(Extension(app_state): Extension<AppState>) -> Json<RateLimitsResponse> {
+    Json(RateLimitsResponse {
+        default: app_state.rate_limits.default_tier(),
+        roles: app_state.rate_limits.role_tiers(),
+    })
+}
+
+async fn set_rate_limit_tier(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(role): Path<Role>,
+    Json(tier): Json<RateTier>,
+) -> Result<Json<RateLimitsResponse>> {
+    require_root(&ctx)?;
+    let before = app_state.rate_limits.role_tiers().remove(&role.to_string());
+    let roles = app_state
+        .rate_limits
+        .set_role_tier(&app_state.mm, &role, Some(tier))
+        .await?;
+    tracing::info!("Rate limit tier of {role} set to {tier:?}");
+    let entry = AuditEntryForCreate::new(AuditKind::Setting, "rate_limit_tier", role.to_string())
+        .before(&before)
+        .after(&tier);
+    audit(&app_state, &ctx, entry).await;
+    Ok(Json(RateLimitsResponse {
+        default: app_state.rate_limits.default_tier(),
+        roles,
+    }))
+}
+
+/// Back to `--rate-limit-per-second` and `--rate-limit-burst` for the role
+async fn delete_rate_limit_tier(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(role): Path<Role>,
+) -> Result<StatusCode> {
+    require_root(&ctx)?;
+    let before = app_state.rate_limits.role_tiers().remove(&role.to_string());
+    app_state
+        .rate_limits
+        .set_role_tier(&app_state.mm, &role, None)
+        .await?;
+    let entry = AuditEntryForCreate::new(AuditKind::Setting, "rate_limit_tier", role.to_string())
+        .before(&before);
+    audit(&app_state, &ctx, entry).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_limits(Extension(app_state): Extension<AppState>) -> Json<LimitsResponse> {
+    let limits = app_state.infer().limits().clone();
+    Json(LimitsResponse {
+        current: limits.snapshot(),
+        ceilings: limits.ceilings(),
+    })
+}
+
+/// Entries waiting in the inference queue, to see what is stuck during an incident
+async fn get_queue(Extension(app_state): Extension<AppState>) -> Json<QueueResponse> {
+    let entries = app_state.infer().pending().await;
+    let mut kinds: BTreeMap<&'static str, QueueKindSummary> = BTreeMap::new();
+    for entry in &entries {
+        let summary = kinds.entry(entry.kind).or_default();
+        summary.pending += 1;
+        summary.oldest_age_ms = summary.oldest_age_ms.max(entry.age_ms);
+    }
+    Json(QueueResponse {
+        pending: entries.len(),
+        kinds,
+        entries,
+    })
+}
+
+/// Reject every pending entry of the inference queue, their clients get `503` with `flushed`.
+/// The queue is shared by every tenant, only the root key flushes it.
+async fn flush_queue(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+) -> Result<Json<QueueFlushResponse>> {
+    require_root(&ctx)?;
+    let flushed = app_state.infer().flush_queue().await;
+    tracing::warn!("Inference queue flushed by an operator, {flushed} requests rejected");
+    let response = QueueFlushResponse { flushed };
+    let entry =
+        AuditEntryForCreate::new(AuditKind::Setting, "queue.flush", "queue").after(&response);
+    audit(&app_state, &ctx, entry).await;
+    Ok(Json(response))
+}
+
+/// Change the queue and batch limits of the live queue, persisted so they survive a restart
+async fn update_limits(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Json(update): Json<LimitsUpdate>,
+) -> Result<Json<LimitsResponse>> {
+    require_root(&ctx)?;
+    let limits = app_state.infer().limits().clone();
+    let before = limits.snapshot();
+    let current = limits.apply(&update)?;
+    SettingMac::set_value(&app_state.mm, LIMITS_SETTING, &current).await?;
+    tracing::info!("Batch limits updated: {current:?}");
+    let entry = AuditEntryForCreate::new(AuditKind::Setting, "limits", LIMITS_SETTING)
+        .before(&before)
+        .after(&current);
+    audit(&app_state, &ctx, entry).await;
+
+    Ok(Json(LimitsResponse {
+        current,
+        ceilings: limits.ceilings(),
+    }))
+}
+
+async fn get_sampling(Extension(app_state): Extension<AppState>) -> Json<SamplingResponse> {
+    let Some(sampler) = app_state.sampler.as_ref() else {
+        return Json(SamplingResponse {
+            enabled: false,
+            rate: 0.0,
+            fields: Vec::new(),
+            opted_out: Vec::new(),
+        });
+    };
+    let config = sampler.config();
+    Json(SamplingResponse {
+        enabled: true,
+        rate: config.rate,
+        fields: config.fields.iter().copied().collect(),
+        opted_out: sampler.opted_out(),
+    })
+}
+
+/// Never sample the requests of this tenant
+async fn opt_out_sampling(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(user_id): Path<String>,
+) -> Result<StatusCode> {
+    set_sampling_opt_out(&app_state, &ctx, &user_id, true).await
+}
+
+async fn opt_in_sampling(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(user_id): Path<String>,
+) -> Result<StatusCode> {
+    set_sampling_opt_out(&app_state, &ctx, &user_id, false).await
+}
+
+async fn set_sampling_opt_out(
+    app_state: &AppState,
+    ctx: &Ctx,
+    user_id: &str,
+    opt_out: bool,
+) -> Result<StatusCode> {
+    let sampler = app_state
+        .sampler
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("Request sampling is disabled".to_string()))?;
+    let before = sampler.opted_out().iter().any(|tenant| tenant == user_id);
+    sampler.set_opt_out(&app_state.mm, user_id, opt_out).await?;
+    tracing::info!("Sampling opt-out of {user_id} set to {opt_out}");
+    let entry = AuditEntryForCreate::new(AuditKind::Setting, "sampling.opt_out", user_id)
+        .before(&before)
+        .after(&opt_out);
+    audit(app_state, ctx, entry).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Disabled paths and their reason
+async fn get_disabled_routes(
+    Extension(app_state): Extension<AppState>,
+) -> Json<BTreeMap<String, String>> {
+    Json(app_state.route_toggles.disabled())
+}
+
+/// The route and every route below it answer `503` with the reason until enabled again
+async fn disable_route(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(path): Path<String>,
+    Json(req): Json<DisableRouteRequest>,
+) -> Result<Json<BTreeMap<String, String>>> {
+    require_root(&ctx)?;
+    let before = app_state.route_toggles.disabled();
+    let disabled = app_state
+        .route_toggles
+        .set(&app_state.mm, &path, Some(req.reason.clone()))
+        .await?;
+    tracing::warn!("Route /{path} disabled: {}", req.reason);
+    let entry = AuditEntryForCreate::new(AuditKind::Setting, "route.disable", &path)
+        .before(&before)
+        .after(&disabled);
+    audit(&app_state, &ctx, entry).await;
+    Ok(Json(disabled))
+}
+
+async fn enable_route(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(path): Path<String>,
+) -> Result<StatusCode> {
+    require_root(&ctx)?;
+    let before = app_state.route_toggles.disabled();
+    let disabled = app_state
+        .route_toggles
+        .set(&app_state.mm, &path, None)
+        .await?;
+    tracing::info!("Route /{path} enabled");
+    let entry = AuditEntryForCreate::new(AuditKind::Setting, "route.enable", &path)
+        .before(&before)
+        .after(&disabled);
+    audit(&app_state, &ctx, entry).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_replication(
+    Extension(app_state): Extension<AppState>,
+) -> Result<Json<ReplicationStatus>> {
+    let cursor = ReplicationMac::current_cursor(&app_state.mm).await?;
+    let applied = SettingMac::get_value(&app_state.mm, REPLICATION_CURSOR_SETTING).await?;
+    Ok(Json(ReplicationStatus { cursor, applied }))
+}
+
+/// Frame of the chunk changes after `cursor` across every tenant, pulled by the replicas with
+/// the root key
+async fn get_replication_changes(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Query(query): Query<ReplicationQuery>,
+) -> Result<Response> {
+    require_root(&ctx)?;
+    let limit = query
+        .limit
+        .unwrap_or(REPLICATION_DEFAULT_LIMIT)
+        .clamp(1, REPLICATION_MAX_LIMIT);
+    let batch = ReplicationMac::read_batch(&app_state.mm, query.cursor, limit).await?;
+    let frame = batch.encode_frame()?;
+    Ok(([(header::CONTENT_TYPE, FRAME_CONTENT_TYPE)], frame).into_response())
+}
+
+/// Oversize policies of the collections (file applicants) with their own policy
+async fn get_oversize_policies(
+    Extension(app_state): Extension<AppState>,
+) -> Result<Json<HashMap<String, OversizePolicy>>> {
+    let policies = SettingMac::get_value(&app_state.mm, OVERSIZE_POLICY_SETTING).await?;
+    Ok(Json(policies.unwrap_or_default()))
+}
+
+/// Applies to the files of the collection ingested from now on
+async fn set_oversize_policy(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(collection): Path<String>,
+    Json(update): Json<OversizePolicyUpdate>,
+) -> Result<Json<HashMap<String, OversizePolicy>>> {
+    require_root(&ctx)?;
+    let mut policies: HashMap<String, OversizePolicy> =
+        SettingMac::get_value(&app_state.mm, OVERSIZE_POLICY_SETTING)
+            .await?
+            .unwrap_or_default();
+    let before = policies.insert(collection.clone(), update.policy);
+    SettingMac::set_value(&app_state.mm, OVERSIZE_POLICY_SETTING, &policies).await?;
+    tracing::info!("Oversize policy of {collection} set to {:?}", update.policy);
+    let entry = AuditEntryForCreate::new(AuditKind::Setting, "oversize_policy", &collection)
+        .before(&before)
+        .after(&update.policy);
+    audit(&app_state, &ctx, entry).await;
+    Ok(Json(policies))
+}
+
+/// Fall back to `CHUNK_OVERSIZE` for the collection
+async fn delete_oversize_policy(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(collection): Path<String>,
+) -> Result<StatusCode> {
+    require_root(&ctx)?;
+    let mut policies: HashMap<String, OversizePolicy> =
+        SettingMac::get_value(&app_state.mm, OVERSIZE_POLICY_SETTING)
+            .await?
+            .unwrap_or_default();
+    let Some(before) = policies.remove(&collection) else {
+        return Err(Error::NotFound(format!("Oversize policy of {collection}")));
+    };
+    SettingMac::set_value(&app_state.mm, OVERSIZE_POLICY_SETTING, &policies).await?;
+    let entry = AuditEntryForCreate::new(AuditKind::Setting, "oversize_policy", &collection)
+        .before(&before);
+    audit(&app_state, &ctx, entry).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Dimensions enforced on the embeddings stored for each collection
+async fn get_embedding_dimensions(
+    Extension(app_state): Extension<AppState>,
+) -> Result<Json<EmbeddingDimensionsResponse>> {
+    let expected = EmbeddingDimsMac::expected(&app_state.mm).await?;
+    Ok(Json(EmbeddingDimensionsResponse {
+        column: expected.column,
+        collections: expected.by_collection,
+    }))
+}
+
+/// Chunks of the collection are rejected from now on unless their embedding has `dimension`
+/// components, stored chunks are not checked
+async fn set_embedding_dimension(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(collection): Path<String>,
+    Json(update): Json<EmbeddingDimensionUpdate>,
+) -> Result<Json<HashMap<String, i32>>> {
+    require_root(&ctx)?;
+    if update.dimension <= 0 {
+        return Err(Error::BadRequest(
+            "`dimension` must be positive".to_string(),
+        ));
+    }
+    let mut dimensions: HashMap<String, i32> =
+        SettingMac::get_value(&app_state.mm, EMBEDDING_DIMENSION_SETTING)
+            .await?
+            .unwrap_or_default();
+    let before = dimensions.insert(collection.clone(), update.dimension);
+    SettingMac::set_value(&app_state.mm, EMBEDDING_DIMENSION_SETTING, &dimensions).await?;
+    tracing::info!(
+        "Embedding dimension of {collection} set to {}",
+        update.dimension
+    );
+    let entry = AuditEntryForCreate::new(AuditKind::Setting, "embedding_dimension", &collection)
+        .before(&before)
+        .after(&update.dimension);
+    audit(&app_state, &ctx, entry).await;
+    Ok(Json(dimensions))
+}
+
+/// Fall back to the dimension of the `embedding` column for the collection
+async fn delete_embedding_dimension(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(collection): Path<String>,
+) -> Result<StatusCode> {
+    require_root(&ctx)?;
+    let mut dimensions: HashMap<String, i32> =
+        SettingMac::get_value(&app_state.mm, EMBEDDING_DIMENSION_SETTING)
+            .await?
+            .unwrap_or_default();
+    let Some(before) = dimensions.remove(&collection) else {
+        return Err(Error::NotFound(format!(
+            "Embedding dimension of {collection}"
+        )));
+    };
+    SettingMac::set_value(&app_state.mm, EMBEDDING_DIMENSION_SETTING, &dimensions).await?;
+    let entry = AuditEntryForCreate::new(AuditKind::Setting, "embedding_dimension", &collection)
+        .before(&before);
+    audit(&app_state, &ctx, entry).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Text search configurations of the collections with their own, the others use
+/// `DEFAULT_TEXT_SEARCH_CONFIG`
+async fn get_text_search_configs(
+    Extension(app_state): Extension<AppState>,
+) -> Result<Json<HashMap<String, String>>> {
+    Ok(Json(TextSearchMac::configs(&app_state.mm).await?))
+}
+
+/// Applies to the chunks of the collection, which are reindexed before the response
+async fn set_text_search_config(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(collection): Path<String>,
+    Json(update): Json<TextSearchConfigUpdate>,
+) -> Result<Json<TextSearchReindexResponse>> {
+    require_root(&ctx)?;
+    if TextSearchMac::find_config(&app_state.mm, &update.config)
+        .await?
+        .is_none()
+    {
+        return Err(Error::NotFound(format!(
+            "Text search configuration {}",
+            update.config
+        )));
+    }
+    let before = TextSearchMac::configs(&app_state.mm)
+        .await?
+        .remove(&collection);
+    let reindexed_chunks =
+        TextSearchMac::set_config(&app_state.mm, &collection, Some(&update.config)).await?;
+    tracing::info!(
+        "Text search configuration of {collection} set to {}, {reindexed_chunks} chunks reindexed",
+        update.config
+    );
+    let entry = AuditEntryForCreate::new(AuditKind::Setting, "text_search_config", &collection)
+        .before(&before)
+        .after(&update.config);
+    audit(&app_state, &ctx, entry).await;
+    Ok(Json(TextSearchReindexResponse {
+        collection,
+        config: update.config,
+        reindexed_chunks,
+    }))
+}
+
+/// Fall back to `DEFAULT_TEXT_SEARCH_CONFIG` for the collection
+async fn delete_text_search_config(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(collection): Path<String>,
+) -> Result<Json<TextSearchReindexResponse>> {
+    require_root(&ctx)?;
+    let Some(before) = TextSearchMac::configs(&app_state.mm)
+        .await?
+        .remove(&collection)
+    else {
+        return Err(Error::NotFound(format!(
+            "Text search configuration of {collection}"
+        )));
+    };
+    let reindexed_chunks = TextSearchMac::set_config(&app_state.mm, &collection, None).await?;
+    let entry = AuditEntryForCreate::new(AuditKind::Setting, "text_search_config", &collection)
+        .before(&before);
+    audit(&app_state, &ctx, entry).await;
+    Ok(Json(TextSearchReindexResponse {
+        collection,
+        config: DEFAULT_TEXT_SEARCH_CONFIG.to_string(),
+        reindexed_chunks,
+    }))
+}
+
+/// Re-apply the limits persisted by `PATCH /limits`, limits above the ceilings of the current
+/// deployment are ignored
+pub async fn restore_limits(app_state: &AppState) {
+    let persisted = SettingMac::get_value::<LimitsSnapshot>(&app_state.mm, LIMITS_SETTING).await;
+    match persisted {
+        Ok(Some(snapshot)) => match app_state.infer().limits().apply(&snapshot.into()) {
+            Ok(current) => tracing::info!("Restored batch limits: {current:?}"),
+            Err(err) => tracing::warn!("Ignoring persisted batch limits: {err}"),
+        },
+        Ok(None) => {}
+        Err(err) => tracing::warn!("Could not load persisted batch limits: {err}"),
+    }
+}
+
+/// First-run bootstrap: with an empty users table, create the `admin` user of the default tenant
+/// and mint its key, written to `secrets_file` or printed once. Does nothing once a user exists.
+pub async fn bootstrap_admin(
+    app_state: &AppState,
+    email: &str,
+    secrets_file: Option<&Path>,
+) -> Result<()> {
+    let admin = UserForCreate {
+        user_id: BOOTSTRAP_ADMIN_ID.to_string(),
+        first_name: "Admin".to_string(),
+        last_name: String::new(),
+        email: email.to_string(),
+    };
+    let Some(admin) = UserBmc::create_first_admin(&app_state.mm, DEFAULT_TENANT, admin).await?
+    else {
+        tracing::info!("Users exist, skipping the admin bootstrap");
+        return Ok(());
+    };
+    let user = UserBmc::get_user_for_auth(&app_state.mm, &admin.user_id).await?;
+    let api_key = mint_api_key(app_state, &user).await?;
+
+    match secrets_file {
+        Some(path) => {
+            write_secret(path, &api_key)?;
+            tracing::info!("Bootstrap admin key written to {}", path.display());
+        }
+        None => println!("Bootstrap admin API key, shown only once: {api_key}"),
+    }
+    Ok(())
+}
+
+/// Written to a new file readable by the owner only, an existing file is never overwritten
+fn write_secret(path: &Path, secret: &str) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{secret}"))
+        .map_err(|err| {
+            Error::Custom(format!(
+                "Failed to write the bootstrap admin key to {}, rotate it with the root key: {err}",
+                path.display()
+            ))
+        })
+}
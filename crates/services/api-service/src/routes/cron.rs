@@ -1,90 +1,204 @@
+//! Cron jobs, scoped by tenant and owner: the root key sees and manages every job, `Role::Admin`
+//! the jobs of its tenant, other users only the jobs they scheduled. Only the root key schedules
+//! the job types acting on the whole node, the others are limited to the tenant job types
+//! (`lib_cron::TENANT_JOB_TYPES`). Added and removed jobs are recorded in the audit log, the runs
+//! of a job in its run history.
+
+use crate::cache::AppState;
 use crate::error::{Error, Result};
+use crate::middleware::mw_auth::Ctm;
+use crate::middleware::mw_history::History;
+use crate::routes::admin::{audit, require_root};
 use axum::{
     Router,
-    extract::Extension,
-    response::{IntoResponse, Json, Response},
-    routing::post,
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::Json,
+    routing::get,
 };
-use lib_cron::ChronJobs;
-use serde_json::json;
+use lib_core::ctx::Ctx;
+use lib_core::model::audit::{AuditEntryForCreate, AuditKind};
+use lib_core::model::cron_jobs::{CronJobMac, JobRun, JobRunStatus};
+use lib_core::model::user::Role;
+use lib_cron::{ConcurrencyPolicy, DEFAULT_TIMEZONE, JobRecord, TENANT_JOB_TYPES};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 pub fn serve_cron() -> Router {
     Router::new()
-        .route("/add", post(add_chron_job))
-        .route("/delete", post(delete_chron_job))
-        .route("/restart", post(restart_chron_jobs).get(get_informaiton))
-}
-
-async fn add_chron_job(
-    Extension(cron_jobs): Extension<ChronJobs>,
-    Json(payload): Json<serde_json::Value>,
-) -> Result<Response> {
-    let res;
-    if let Some(data) = payload.get("data") {
-        let cron = data
-            .get("cron")
-            .and_then(|c| c.as_str())
-            .ok_or(Error::Custom("Missing data".to_string()))?;
-        let description = data
-            .get("description")
-            .and_then(|c| c.as_str())
-            .ok_or(Error::Custom("Missing data".to_string()))?;
-        let _ = cron_jobs
-            .add_job(description.to_string(), cron.to_string())
-            .await;
-        res = json!({
-            "status": 200,
-            "data": "ok",
-        });
-    } else {
-        res = json!({
-            "status": 401,
-            "error": "Missing require data",
-        });
-    }
-    Ok(Json(res).into_response())
-}
-
-async fn delete_chron_job(
-    Extension(cron_jobs): Extension<ChronJobs>,
-    Json(payload): Json<serde_json::Value>,
-) -> Result<Response> {
-    let res;
-    if let Some(data) = payload.get("data") {
-        let id = data
-            .get("id")
-            .and_then(|c| c.as_str())
-            .ok_or(Error::Custom("Missing data".to_string()))?;
-        let uuid =
-            uuid::Uuid::parse_str(id).map_err(|_| Error::Custom("Invalid UUID".to_string()))?;
-        let _ = cron_jobs.remove_job(uuid).await;
-        res = json!({
-            "status": 200,
-            "data": "ok",
-        });
-    } else {
-        res = json!({
-            "status": 401,
-            "error": "Missing require data",
-        });
-    }
-    Ok(Json(res).into_response())
+        .route("/jobs", get(list_jobs).post(add_job))
+        .route("/jobs/{job_id}", get(get_job).delete(delete_job))
+        .route("/jobs/{job_id}/runs", get(list_runs))
+        .route("/job-types", get(list_job_types))
+}
+
+#[derive(Deserialize)]
+struct AddJobRequest {
+    /// One of `GET /job-types`
+    job_type: String,
+    /// Six fields, seconds first: `0 */10 * * * *`
+    cron: String,
+    /// IANA timezone `cron` is evaluated in, `UTC` by default
+    #[serde(default)]
+    timezone: Option<String>,
+    /// `skip` (default), `queue` or `allow` a run while another run of the job type is going
+    #[serde(default)]
+    concurrency: ConcurrencyPolicy,
+}
+
+#[derive(Serialize)]
+struct JobsResponse {
+    data: Vec<JobRecord>,
+}
+
+#[derive(Serialize)]
+struct JobRunsResponse {
+    data: Vec<JobRun>,
+    next_cursor: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct JobTypesResponse {
+    /// Job types the caller can schedule on this node
+    data: Vec<String>,
+}
+
+fn is_admin(ctx: &Ctx) -> bool {
+    ctx.role() == Some(Role::Admin)
+}
+
+/// `None` for the root key, which is not restricted to a tenant
+fn tenant_filter(ctx: &Ctx) -> Option<String> {
+    (!ctx.is_root()).then(|| ctx.tenant_id())
 }
 
-async fn restart_chron_jobs(Extension(_cron_jobs): Extension<ChronJobs>) -> Result<Response> {
-    let res = json!({
-        "status": 200,
-        "message": "ok"
-    });
-    Ok(Json(res).into_response())
+/// `None` for admins, who are not restricted to their own jobs
+fn owner_filter(ctx: &Ctx) -> Option<String> {
+    (!is_admin(ctx)).then(|| ctx.user_id())
 }
 
-async fn get_informaiton(Extension(cron_jobs): Extension<ChronJobs>) -> Result<Response> {
-    let jobs = cron_jobs.cache.get_jobs().await;
+fn parse_job_id(job_id: &str) -> Result<Uuid> {
+    Uuid::parse_str(job_id).map_err(|_| Error::BadRequest(format!("Invalid job id `{job_id}`")))
+}
+
+/// Job of the caller, jobs of other owners are reported as missing so their ids are not disclosed
+async fn find_job(app_state: &AppState, ctx: &Ctx, job_id: Uuid) -> Result<JobRecord> {
+    app_state
+        .cron_jobs
+        .cache
+        .get_job(job_id)
+        .await
+        .filter(|job| job.belongs_to(tenant_filter(ctx).as_deref(), owner_filter(ctx).as_deref()))
+        .ok_or_else(|| Error::NotFound(format!("Job {job_id}")))
+}
+
+async fn list_jobs(Extension(app_state): Extension<AppState>, Ctm(ctx): Ctm) -> Json<JobsResponse> {
+    let tenant_id = tenant_filter(&ctx);
+    let owner = owner_filter(&ctx);
+    let mut data = app_state
+        .cron_jobs
+        .cache
+        .get_jobs_for(tenant_id.as_deref(), owner.as_deref())
+        .await;
+    data.sort_by(|a, b| a.job_type.cmp(&b.job_type).then_with(|| a.id.cmp(&b.id)));
+    Json(JobsResponse { data })
+}
+
+async fn get_job(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(job_id): Path<String>,
+) -> Result<Json<JobRecord>> {
+    let job_id = parse_job_id(&job_id)?;
+    Ok(Json(find_job(&app_state, &ctx, job_id).await?))
+}
+
+/// Latest runs of the job first, skipped runs included. Paged and filtered with the history
+/// parameters, `status` is `succeeded`, `failed` or `skipped`.
+async fn list_runs(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(job_id): Path<String>,
+    History(filter): History<JobRunStatus>,
+) -> Result<Json<JobRunsResponse>> {
+    let job_id = parse_job_id(&job_id)?;
+    find_job(&app_state, &ctx, job_id).await?;
+    let data = CronJobMac::find_runs(&app_state.mm, job_id, &filter).await?;
+    let next_cursor = filter.next_cursor(&data, |run| run.run_id);
+    Ok(Json(JobRunsResponse { data, next_cursor }))
+}
+
+async fn list_job_types(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+) -> Json<JobTypesResponse> {
+    let data = app_state
+        .cron_jobs
+        .job_types()
+        .into_iter()
+        .filter(|job_type| ctx.is_root() || TENANT_JOB_TYPES.contains(job_type))
+        .map(str::to_string)
+        .collect();
+    Json(JobTypesResponse { data })
+}
+
+/// Schedule a job owned by the caller, answers `201 Created` with the job
+async fn add_job(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Json(req): Json<AddJobRequest>,
+) -> Result<(StatusCode, Json<JobRecord>)> {
+    let job_types = app_state.cron_jobs.job_types();
+    if !job_types.contains(&req.job_type.as_str()) {
+        return Err(Error::BadRequest(format!(
+            "Unknown job type `{}`, expected one of: {}",
+            req.job_type,
+            job_types.join(", ")
+        )));
+    }
+    if !TENANT_JOB_TYPES.contains(&req.job_type.as_str()) {
+        require_root(&ctx).map_err(|_| {
+            Error::Forbidden(format!("Job type `{}` requires the root key", req.job_type))
+        })?;
+    }
+    let timezone = req.timezone.unwrap_or_else(|| DEFAULT_TIMEZONE.to_string());
+    let job = app_state
+        .cron_jobs
+        .add_job(
+            req.job_type,
+            req.cron,
+            timezone,
+            req.concurrency,
+            ctx.user_id(),
+            ctx.tenant_id(),
+        )
+        .await?;
+    tracing::info!(
+        "Cron job {} ({}) added by {}",
+        job.id,
+        job.job_type,
+        job.owner
+    );
+    let entry = AuditEntryForCreate::new(AuditKind::Cron, "cron.add", &job.id).after(&job);
+    audit(&app_state, &ctx, entry).await;
+    Ok((StatusCode::CREATED, Json(job)))
+}
 
-    let res = json!({
-        "status": 200,
-        "data": jobs
-    });
-    Ok(Json(res).into_response())
+async fn delete_job(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(job_id): Path<String>,
+) -> Result<StatusCode> {
+    let job_id = parse_job_id(&job_id)?;
+    let job = find_job(&app_state, &ctx, job_id).await?;
+    app_state.cron_jobs.remove_job(job_id).await?;
+    tracing::info!(
+        "Cron job {} of {} removed by {}",
+        job.id,
+        job.owner,
+        ctx.user_id()
+    );
+    let entry = AuditEntryForCreate::new(AuditKind::Cron, "cron.delete", &job.id).before(&job);
+    audit(&app_state, &ctx, entry).await;
+    Ok(StatusCode::NO_CONTENT)
 }
@@ -1,36 +1,48 @@
 use crate::ai::ResponseMetadata;
 use crate::ai::infer::{
-    AllEmbeddingsInferResponse, Infer, InferMetadata, PooledEmbeddingsInferResponse,
+    AllEmbeddingsInferResponse, EmbeddingStats, Infer, InferMetadata, PooledEmbeddingsInferResponse,
 };
+use crate::ai::long_input::{self, LongInputPooling};
+use crate::ai::output_dtype::{Int8Scale, OutputDtype};
+use crate::ai::splade::SpladeQueryEncoder;
 use crate::ai::tokenization::{SimpleToken as CoreSimpleToken, into_tokens};
 use crate::cache::AppState;
 use crate::error::{Error, Result};
-use crate::types::ErrorType;
+use crate::log::sampling::SampleRecord;
+use crate::middleware::mw_auth::Ctm;
+use crate::routes::stream::json_array_response;
 use crate::types::{
-    DecodeRequest, DecodeResponse, EmbedAllRequest, EmbedAllResponse, EmbedRequest, EmbedResponse,
-    EmbedSparseRequest, EmbedSparseResponse, Embedding, EncodingFormat, ErrorResponse, Input,
+    CountTokensRequest, CountTokensResponse, DecodeRequest, DecodeResponse, EmbedAllRequest,
+    EmbedAllResponse, EmbedInputType, EmbedOutput, EmbedRequest, EmbedResponse, EmbedSparseRequest,
+    EmbedSparseResponse, Embedding, EmbeddingMatrix, EncodingFormat, ErrorResponse, Input,
     InputIds, InputType, OpenAICompatEmbedding, OpenAICompatErrorResponse, OpenAICompatRequest,
     OpenAICompatResponse, OpenAICompatUsage, PredictInput, PredictRequest, PredictResponse,
     Prediction, Rank, RerankRequest, RerankResponse, Sequence, SimilarityInput,
-    SimilarityParameters, SimilarityRequest, SimilarityResponse, SimpleToken, SparseValue,
-    TokenizeInput, TokenizeRequest, TokenizeResponse, TruncationDirection, VertexPrediction,
-    VertexRequest, VertexResponse,
+    SimilarityParameters, SimilarityRequest, SimilarityResponse, SimpleToken, SparseInputType,
+    SparseValue, TokenCount, TokenizeInput, TokenizeRequest, TokenizeResponse, TruncationDirection,
+    VertexPrediction, VertexRequest, VertexResponse,
 };
 use axum::{
     Router,
     extract::{DefaultBodyLimit, Extension},
-    http::{HeaderMap, Method, StatusCode},
+    http::{HeaderMap, Method},
     response::{IntoResponse, Json, Response},
     routing::post,
 };
 use futures::future::join_all;
 use lib_embedding::error::Error as TextEmbeddingsError;
 use serde_json::json;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::OwnedSemaphorePermit;
 
 pub fn serve_embed() -> Router {
-    Router::new().route("/embed", post(run_embed))
+    Router::new()
+        .route("/embed", post(run_embed))
+        .route("/embed_sparse", post(run_embed_sparse))
+        .route("/embed_all", post(run_embed_all))
+        .route("/embeddings", post(run_openai_embed))
+        .route("/count_tokens", post(run_count_tokens))
 }
 use tracing::instrument;
 
@@ -40,17 +52,19 @@ tag = "Text Embeddings Inference",
 path = "/embed",
 request_body = EmbedRequest,
 responses(
-(status = 200, description = "Embeddings", body = EmbedResponse),
+(status = 200, description = "Embeddings", body = EmbedOutput),
 (status = 424, description = "Embedding Error", body = ErrorResponse,
 example = json ! ({"error": "Inference failed", "error_type": "backend"})),
-(status = 429, description = "Model is overloaded", body = ErrorResponse,
-example = json ! ({"error": "Model is overloaded", "error_type": "overloaded"})),
+(status = 429, description = "Queue is full", body = ErrorResponse,
+example = json ! ({"error": "Queue is full. Please retry.", "error_type": "queue_full"})),
+(status = 503, description = "Backend is unhealthy", body = ErrorResponse,
+example = json ! ({"error": "Backend is unhealthy", "error_type": "unhealthy"})),
 (status = 422, description = "Tokenization error", body = ErrorResponse,
 example = json ! ({"error": "Tokenization error", "error_type": "tokenizer"})),
 (status = 400, description = "Batch is empty", body = ErrorResponse,
 example = json ! ({"error": "Batch is empty", "error_type": "empty"})),
 (status = 413, description = "Batch size error", body = ErrorResponse,
-example = json ! ({"error": "Batch size error", "error_type": "validation"})),
+example = json ! ({"error": "Batch size error", "error_type": "batch_too_large"})),
 )
 )]
 #[instrument(
@@ -59,148 +73,851 @@ example = json ! ({"error": "Batch size error", "error_type": "validation"})),
 )]
 async fn run_embed(
     Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
     Json(req): Json<EmbedRequest>,
 ) -> Result<Response> {
-    let infer = app_state.infer.clone();
-    let info = app_state.info.clone();
     let span = tracing::Span::current();
+    let tenant = ctx.user_id();
+    // Inputs are hashed before they are consumed, only for the sampled requests
+    let sample = app_state
+        .sampler
+        .as_ref()
+        .filter(|sampler| sampler.should_sample(&tenant))
+        .map(|sampler| (sampler, sampler.hash_inputs(&req.inputs)));
+    let result = embed_output(&app_state, req).await;
+
+    match result {
+        Ok((response, metadata)) => {
+            metadata.record_span(&span);
+            metadata.record_metrics();
+            if let Some((sampler, input_hashes)) = sample {
+                let mut record =
+                    SampleRecord::new("/embed", 200, input_hashes.len()).with_metadata(&metadata);
+                record.input_hashes = Some(input_hashes);
+                sampler.record(&tenant, record);
+            }
+            let headers = HeaderMap::from(metadata);
+            tracing::info!("Success");
+            Ok(json_array_response(
+                headers,
+                response,
+                app_state.stream_threshold,
+            ))
+        }
+
+        Err(err) => {
+            let response = err.into_response();
+            if let Some((sampler, input_hashes)) = sample {
+                let status = response.status().as_u16();
+                let mut record = SampleRecord::new("/embed", status, input_hashes.len());
+                record.input_hashes = Some(input_hashes);
+                sampler.record(&tenant, record);
+            }
+            Ok(response)
+        }
+    }
+}
+
+#[utoipa::path(
+post,
+tag = "Text Embeddings Inference",
+path = "/embed_sparse",
+request_body = EmbedSparseRequest,
+responses(
+(status = 200, description = "Sparse embeddings", body = EmbedSparseResponse),
+(status = 424, description = "Embedding Error", body = ErrorResponse,
+example = json ! ({"error": "Inference failed", "error_type": "backend"})),
+(status = 429, description = "Queue is full", body = ErrorResponse,
+example = json ! ({"error": "Queue is full. Please retry.", "error_type": "queue_full"})),
+(status = 503, description = "Backend is unhealthy", body = ErrorResponse,
+example = json ! ({"error": "Backend is unhealthy", "error_type": "unhealthy"})),
+(status = 422, description = "Tokenization error", body = ErrorResponse,
+example = json ! ({"error": "Tokenization error", "error_type": "tokenizer"})),
+(status = 400, description = "Batch is empty", body = ErrorResponse,
+example = json ! ({"error": "Batch is empty", "error_type": "empty"})),
+(status = 413, description = "Batch size error", body = ErrorResponse,
+example = json ! ({"error": "Batch size error", "error_type": "batch_too_large"})),
+)
+)]
+#[instrument(
+    skip_all,
+    fields(total_time, tokenization_time, queue_time, inference_time,)
+)]
+async fn run_embed_sparse(
+    Extension(app_state): Extension<AppState>,
+    Json(req): Json<EmbedSparseRequest>,
+) -> Result<Response> {
+    let span = tracing::Span::current();
+    match embed_sparse(&app_state, req).await {
+        Ok((response, metadata)) => {
+            metadata.record_span(&span);
+            metadata.record_metrics();
+            let headers = HeaderMap::from(metadata);
+            tracing::info!("Success");
+            Ok(json_array_response(
+                headers,
+                response,
+                app_state.stream_threshold,
+            ))
+        }
+        Err(err) => Ok(err.into_response()),
+    }
+}
+
+#[utoipa::path(
+post,
+tag = "Text Embeddings Inference",
+path = "/embed_all",
+request_body = EmbedAllRequest,
+responses(
+(status = 200, description = "Token embeddings", body = EmbedAllResponse),
+(status = 424, description = "Embedding Error", body = ErrorResponse,
+example = json ! ({"error": "Inference failed", "error_type": "backend"})),
+(status = 429, description = "Queue is full", body = ErrorResponse,
+example = json ! ({"error": "Queue is full. Please retry.", "error_type": "queue_full"})),
+(status = 503, description = "Backend is unhealthy", body = ErrorResponse,
+example = json ! ({"error": "Backend is unhealthy", "error_type": "unhealthy"})),
+(status = 422, description = "Tokenization error", body = ErrorResponse,
+example = json ! ({"error": "Tokenization error", "error_type": "tokenizer"})),
+(status = 400, description = "Batch is empty", body = ErrorResponse,
+example = json ! ({"error": "Batch is empty", "error_type": "empty"})),
+(status = 413, description = "Batch size error", body = ErrorResponse,
+example = json ! ({"error": "Batch size error", "error_type": "batch_too_large"})),
+)
+)]
+#[instrument(
+    skip_all,
+    fields(total_time, tokenization_time, queue_time, inference_time,)
+)]
+async fn run_embed_all(
+    Extension(app_state): Extension<AppState>,
+    Json(req): Json<EmbedAllRequest>,
+) -> Result<Response> {
+    let span = tracing::Span::current();
+    match embed_all(&app_state, req).await {
+        Ok((response, metadata)) => {
+            metadata.record_span(&span);
+            metadata.record_metrics();
+            let headers = HeaderMap::from(metadata);
+            tracing::info!("Success");
+            Ok(json_array_response(
+                headers,
+                response,
+                app_state.stream_threshold,
+            ))
+        }
+        Err(err) => Ok(err.into_response()),
+    }
+}
+
+#[utoipa::path(
+post,
+tag = "Text Embeddings Inference",
+path = "/embeddings",
+request_body = OpenAICompatRequest,
+responses(
+(status = 200, description = "Embeddings", body = OpenAICompatResponse),
+(status = 400, description = "Batch size error", body = OpenAICompatErrorResponse,
+example = json ! ({"error": {"message": "Batch size error", "type": "invalid_request_error", "code": "batch_too_large"}})),
+(status = 429, description = "Queue is full", body = OpenAICompatErrorResponse,
+example = json ! ({"error": {"message": "Queue is full. Please retry.", "type": "rate_limit_error", "code": "queue_full"}})),
+(status = 500, description = "Embedding Error", body = OpenAICompatErrorResponse,
+example = json ! ({"error": {"message": "Inference failed", "type": "server_error", "code": "backend"}})),
+)
+)]
+#[instrument(
+    skip_all,
+    fields(total_time, tokenization_time, queue_time, inference_time,)
+)]
+async fn run_openai_embed(
+    Extension(app_state): Extension<AppState>,
+    Json(req): Json<OpenAICompatRequest>,
+) -> Result<Response> {
+    let span = tracing::Span::current();
+    let encoding_format = req.encoding_format;
+    let output_dtype = req.output_dtype;
+    output_dtype.check_format(encoding_format)?;
+    let request = EmbedRequest {
+        inputs: req.input,
+        truncate: None,
+        truncation_direction: TruncationDirection::default(),
+        prompt_name: None,
+        instruction: None,
+        input_type: None,
+        normalize: true,
+        dimensions: req.dimensions,
+        stats: false,
+        output_dtype: OutputDtype::Float32,
+        long_input: None,
+        window_overlap: None,
+    };
+    // Errors are reshaped for the OpenAI SDKs by `mw_openai_errors`
+    let (EmbedResponse(embeddings), metadata) = embed(&app_state, request).await?;
+    let int8_scale = int8_scale(output_dtype, true, &embeddings);
+    let metadata = metadata.with_int8_scale(int8_scale);
+    metadata.record_span(&span);
+    metadata.record_metrics();
+    // Every row is encoded from the response buffer, base64 skips the float formatting
+    let data = embeddings
+        .rows()
+        .enumerate()
+        .map(|(index, row)| OpenAICompatEmbedding {
+            object: "embedding",
+            embedding: output_dtype.encode(row, encoding_format, int8_scale),
+            index,
+        })
+        .collect();
+    let prompt_tokens = metadata.compute_tokens();
+    let response = OpenAICompatResponse {
+        object: "list",
+        data,
+        model: app_state.info().model_id.clone(),
+        usage: OpenAICompatUsage {
+            prompt_tokens,
+            total_tokens: prompt_tokens,
+        },
+    };
+    Ok((HeaderMap::from(metadata), Json(response)).into_response())
+}
+
+#[utoipa::path(
+post,
+tag = "Text Embeddings Inference",
+path = "/count_tokens",
+request_body = CountTokensRequest,
+responses(
+(status = 200, description = "Token counts", body = CountTokensResponse),
+(status = 422, description = "Tokenization error", body = ErrorResponse,
+example = json ! ({"error": "Tokenization error", "error_type": "tokenizer"})),
+(status = 400, description = "Batch is empty", body = ErrorResponse,
+example = json ! ({"error": "Batch is empty", "error_type": "empty"})),
+(status = 413, description = "Batch size error", body = ErrorResponse,
+example = json ! ({"error": "Batch size error", "error_type": "batch_too_large"})),
+)
+)]
+#[instrument(skip_all)]
+async fn run_count_tokens(
+    Extension(app_state): Extension<AppState>,
+    Json(req): Json<CountTokensRequest>,
+) -> Result<Json<CountTokensResponse>> {
+    Ok(Json(count_tokens(&app_state, req).await?))
+}
+
+/// Token counts of the inputs without running the model, for clients planning their chunk sizes
+pub(crate) async fn count_tokens(
+    app_state: &AppState,
+    req: CountTokensRequest,
+) -> Result<CountTokensResponse> {
+    let served = app_state.models.served();
+    let (infer, info) = (served.infer.clone(), served.info.clone());
+    let inputs = match req.inputs {
+        TokenizeInput::Single(input) => vec![input],
+        TokenizeInput::Batch(inputs) => inputs,
+    };
+    if inputs.is_empty() {
+        return Err(Error::EmptyInput("`inputs` cannot be empty".to_string()));
+    }
+    let batch_size = inputs.len();
+    let max_client_batch_size = app_state.max_client_batch_size(infer.limits());
+    if batch_size > max_client_batch_size {
+        return Err(Error::BatchTooLarge(format!(
+            "batch size {batch_size} > maximum allowed batch size {max_client_batch_size}"
+        )));
+    }
+
+    let futures = inputs.into_iter().map(|input| {
+        let infer = infer.clone();
+        let prompt_name = req.prompt_name.clone();
+        async move {
+            let (_, encoding) = infer.tokenize(input, true, prompt_name).await?;
+            Ok(encoding.len())
+        }
+    });
+    let tokens = join_all(futures)
+        .await
+        .into_iter()
+        .collect::<Result<Vec<usize>>>()?;
+    metrics::counter!("te_request_count", "method" => "count_tokens").increment(1);
+
+    let max_input_length = info.max_input_length;
+    Ok(CountTokensResponse {
+        total_tokens: tokens.iter().sum(),
+        counts: tokens
+            .into_iter()
+            .map(|tokens| TokenCount {
+                tokens,
+                truncated: tokens > max_input_length,
+            })
+            .collect(),
+        max_input_length,
+    })
+}
+
+/// `embed` in the `output_dtype` of the request, shared by `/embed` and the hosting protocol routes
+pub(crate) async fn embed_output(
+    app_state: &AppState,
+    req: EmbedRequest,
+) -> Result<(EmbedOutput, ResponseMetadata)> {
+    let output_dtype = req.output_dtype;
+    let normalized = req.normalize;
+    let (EmbedResponse(embeddings), metadata) = embed(app_state, req).await?;
+    if output_dtype == OutputDtype::Float32 {
+        return Ok((EmbedOutput::Float32(EmbedResponse(embeddings)), metadata));
+    }
+    let int8_scale = int8_scale(output_dtype, normalized, &embeddings);
+    let rows = embeddings
+        .rows()
+        .map(|row| output_dtype.encode(row, EncodingFormat::Float, int8_scale))
+        .collect();
+    Ok((
+        EmbedOutput::Quantized(rows),
+        metadata.with_int8_scale(int8_scale),
+    ))
+}
+
+/// Scale of an `int8` output: the fixed range of normalized embeddings, the range of the response
+/// otherwise
+fn int8_scale(
+    output_dtype: OutputDtype,
+    normalized: bool,
+    embeddings: &EmbeddingMatrix,
+) -> Option<Int8Scale> {
+    match output_dtype {
+        OutputDtype::Int8 if normalized => Some(Int8Scale::NORMALIZED),
+        OutputDtype::Int8 => Some(Int8Scale::fit(embeddings)),
+        _ => None,
+    }
+}
+
+/// Embed the inputs of an `EmbedRequest` as float32
+pub(crate) async fn embed(
+    app_state: &AppState,
+    req: EmbedRequest,
+) -> Result<(EmbedResponse, ResponseMetadata)> {
+    // Infer and info of the same model, even when it is switched during the request
+    let served = app_state.models.served();
+    let (infer, info) = (served.infer.clone(), served.info.clone());
 
     let start_time = Instant::now();
     let truncate = req.truncate.unwrap_or(info.auto_truncate);
+    let with_stats = req.stats;
+    let inputs = match req.instruction.as_deref() {
+        Some(instruction) => {
+            if req.prompt_name.is_some() {
+                return Err(Error::BadRequest(
+                    "`instruction` cannot be combined with `prompt_name`".to_string(),
+                ));
+            }
+            let format = info.instruction_format.ok_or_else(|| {
+                Error::BadRequest(format!(
+                    "`instruction` is not supported by model `{}`",
+                    info.model_id
+                ))
+            })?;
+            if req.input_type == Some(EmbedInputType::Passage) {
+                return Err(Error::BadRequest(
+                    "`instruction` only applies to `query` inputs".to_string(),
+                ));
+            }
+            format.apply_input(instruction, req.inputs)?
+        }
+        None => req.inputs,
+    };
+    // The instruction template replaces the query prompt
+    let prompt_name = match req.input_type {
+        Some(_) if req.prompt_name.is_some() => {
+            return Err(Error::BadRequest(
+                "`input_type` cannot be combined with `prompt_name`".to_string(),
+            ));
+        }
+        Some(_) if req.instruction.is_some() => None,
+        Some(input_type) => Some(info.input_type_prompts.prompt_name(input_type).to_string()),
+        None => req.prompt_name,
+    };
 
-    // Wrap everything in a Result so we can match errors
-    let result: Result<(EmbedResponse, ResponseMetadata)> = (|| async {
-        match req.inputs {
-            Input::Single(input) => {
-                metrics::counter!("te_request_count", "method" => "single").increment(1);
-                let compute_chars = input.count_chars();
-
-                let permit = infer
-                    .try_acquire_permit()
-                    .map_err(|err| Error::Custom(err.to_string()))?;
-                let response = infer
-                    .embed_pooled(
-                        input,
-                        truncate,
-                        req.truncation_direction.into(),
-                        req.prompt_name,
+    match inputs {
+        Input::Single(input) => {
+            metrics::counter!("te_request_count", "method" => "single").increment(1);
+            let compute_chars = input.count_chars();
+
+            let response = match (input, req.long_input) {
+                (InputType::String(text), Some(pooling)) if !truncate => {
+                    embed_long_input(
+                        app_state,
+                        &infer,
+                        info.max_input_length,
+                        text,
+                        pooling,
+                        req.window_overlap,
+                        prompt_name,
                         req.normalize,
                         req.dimensions,
-                        permit,
                     )
-                    .await?;
-
-                metrics::counter!("te_request_success", "method" => "single").increment(1);
-
-                Ok((
-                    EmbedResponse(vec![response.results]),
-                    ResponseMetadata::new(
-                        compute_chars,
-                        response.metadata.prompt_tokens,
-                        start_time,
-                        response.metadata.tokenization,
-                        response.metadata.queue,
-                        response.metadata.inference,
-                    ),
-                ))
+                    .await?
+                }
+                (input, _) => {
+                    let permit = infer.try_acquire_permit()?;
+                    infer
+                        .embed_pooled(
+                            input,
+                            truncate,
+                            req.truncation_direction.into(),
+                            prompt_name,
+                            req.normalize,
+                            req.dimensions,
+                            permit,
+                        )
+                        .await?
+                }
+            };
+
+            metrics::counter!("te_request_success", "method" => "single").increment(1);
+
+            let stats = response.stats.filter(|_| with_stats);
+            let mut embeddings = EmbeddingMatrix::with_capacity(1, response.results.len());
+            embeddings.push_row(&response.results)?;
+            Ok((
+                EmbedResponse(embeddings),
+                ResponseMetadata::new(
+                    compute_chars,
+                    response.metadata.prompt_tokens,
+                    start_time,
+                    response.metadata.tokenization,
+                    response.metadata.queue,
+                    response.metadata.inference,
+                )
+                .with_embedding_stats(stats),
+            ))
+        }
+        Input::Batch(inputs) => {
+            metrics::counter!("te_request_count", "method" => "batch").increment(1);
+
+            if inputs.is_empty() {
+                return Err(Error::EmptyInput("`inputs` cannot be empty".to_string()));
+            }
+            if req.long_input.is_some() {
+                return Err(Error::BadRequest(
+                    "`long_input` only applies to a single input".to_string(),
+                ));
             }
-            Input::Batch(inputs) => {
-                metrics::counter!("te_request_count", "method" => "batch").increment(1);
 
-                if inputs.is_empty() {
-                    return Err(Error::Custom("`inputs` cannot be empty".to_string()));
-                }
+            let batch_size = inputs.len();
+            let max_client_batch_size = app_state.max_client_batch_size(infer.limits());
+            if batch_size > max_client_batch_size {
+                return Err(Error::BatchTooLarge(format!(
+                    "batch size {batch_size} > maximum allowed batch size {max_client_batch_size}"
+                )));
+            }
 
-                let batch_size = inputs.len();
-                if batch_size > info.max_client_batch_size {
-                    return Err(Error::Custom(format!(
-                        "batch size {batch_size} > maximum allowed batch size {}",
-                        info.max_client_batch_size
-                    )));
-                }
+            let compute_chars = inputs.iter().map(|input| input.count_chars()).sum();
+            let results = embed_batch(
+                &infer,
+                inputs,
+                truncate,
+                req.truncation_direction,
+                prompt_name,
+                req.normalize,
+                req.dimensions,
+            )
+            .await?;
+
+            let stats = with_stats
+                .then(|| EmbeddingStats::aggregate(results.iter().filter_map(|r| r.stats)))
+                .flatten();
+            let dim = results.first().map_or(0, |r| r.results.len());
+            let mut embeddings = EmbeddingMatrix::with_capacity(batch_size, dim);
+            let mut total_tokenization_time = 0;
+            let mut total_queue_time = 0;
+            let mut total_inference_time = 0;
+            let mut total_compute_tokens = 0;
+
+            for r in results {
+                total_tokenization_time += r.metadata.tokenization.as_nanos() as u64;
+                total_queue_time += r.metadata.queue.as_nanos() as u64;
+                total_inference_time += r.metadata.inference.as_nanos() as u64;
+                total_compute_tokens += r.metadata.prompt_tokens;
+                embeddings.push_row(&r.results)?;
+            }
+
+            let batch_size = batch_size as u64;
+            metrics::counter!("te_request_success", "method" => "batch").increment(1);
+
+            Ok((
+                EmbedResponse(embeddings),
+                ResponseMetadata::new(
+                    compute_chars,
+                    total_compute_tokens,
+                    start_time,
+                    Duration::from_nanos(total_tokenization_time / batch_size),
+                    Duration::from_nanos(total_queue_time / batch_size),
+                    Duration::from_nanos(total_inference_time / batch_size),
+                )
+                .with_embedding_stats(stats),
+            ))
+        }
+    }
+}
+
+/// Sparse embeddings of an `EmbedSparseRequest`, queries go to the SPLADE query encoder when the
+/// model has one
+pub(crate) async fn embed_sparse(
+    app_state: &AppState,
+    req: EmbedSparseRequest,
+) -> Result<(EmbedSparseResponse, ResponseMetadata)> {
+    let start_time = Instant::now();
+    let truncate = req.truncate.unwrap_or(app_state.info().auto_truncate);
+    let inputs = match req.inputs {
+        Input::Single(input) => vec![input],
+        Input::Batch(inputs) => inputs,
+    };
+    if inputs.is_empty() {
+        return Err(Error::EmptyInput("`inputs` cannot be empty".to_string()));
+    }
+    let max_client_batch_size = app_state.max_client_batch_size(app_state.infer().limits());
+    if inputs.len() > max_client_batch_size {
+        return Err(Error::BatchTooLarge(format!(
+            "batch size {} > maximum allowed batch size {max_client_batch_size}",
+            inputs.len()
+        )));
+    }
+    let batch_size = inputs.len();
+    let compute_chars = inputs.iter().map(|input| input.count_chars()).sum();
 
-                let mut futures = Vec::with_capacity(batch_size);
-                let mut compute_chars = 0;
-
-                for input in inputs {
-                    compute_chars += input.count_chars();
-                    let local_infer = infer.clone();
-                    let prompt_name = req.prompt_name.clone();
-                    futures.push(async move {
-                        let permit = local_infer.acquire_permit().await;
-                        local_infer
-                            .embed_pooled(
-                                input,
-                                truncate,
-                                req.truncation_direction.into(),
-                                prompt_name,
-                                req.normalize,
-                                req.dimensions,
-                                permit,
-                            )
-                            .await
+    let served_infer = app_state.infer();
+    let query_encoder = match req.input_type {
+        SparseInputType::Query => app_state.splade_query.as_deref(),
+        SparseInputType::Document => None,
+    };
+    let results: Vec<(Vec<SparseValue>, InferMetadata)> = match query_encoder {
+        Some(SpladeQueryEncoder::Idf(idf)) => {
+            let futures = inputs
+                .into_iter()
+                .map(|input| idf.encode(&served_infer, input, req.prompt_name.clone()));
+            join_all(futures)
+                .await
+                .into_iter()
+                .map(|encoding| {
+                    encoding.map(|encoding| {
+                        let values = encoding
+                            .values
+                            .into_iter()
+                            .map(|(index, value)| SparseValue {
+                                index: index as usize,
+                                value,
+                            })
+                            .collect();
+                        let metadata = InferMetadata {
+                            prompt_tokens: encoding.prompt_tokens,
+                            tokenization: encoding.tokenization,
+                            queue: Duration::ZERO,
+                            inference: Duration::ZERO,
+                        };
+                        (values, metadata)
                     })
-                }
+                })
+                .collect::<Result<_>>()?
+        }
+        query_encoder => {
+            let infer = match query_encoder {
+                Some(SpladeQueryEncoder::Model { infer, .. }) => infer,
+                _ => &served_infer,
+            };
+            embed_sparse_batch(
+                infer,
+                inputs,
+                truncate,
+                req.truncation_direction,
+                req.prompt_name,
+            )
+            .await?
+            .into_iter()
+            .map(|response| (sparsify(response.results), response.metadata))
+            .collect()
+        }
+    };
 
-                let results = futures::future::join_all(futures)
-                    .await
-                    .into_iter()
-                    .collect::<Result<Vec<PooledEmbeddingsInferResponse>>>()?;
-
-                let mut embeddings = Vec::with_capacity(batch_size);
-                let mut total_tokenization_time = 0;
-                let mut total_queue_time = 0;
-                let mut total_inference_time = 0;
-                let mut total_compute_tokens = 0;
-
-                for r in results {
-                    total_tokenization_time += r.metadata.tokenization.as_nanos() as u64;
-                    total_queue_time += r.metadata.queue.as_nanos() as u64;
-                    total_inference_time += r.metadata.inference.as_nanos() as u64;
-                    total_compute_tokens += r.metadata.prompt_tokens;
-                    embeddings.push(r.results);
-                }
+    let mut embeddings = Vec::with_capacity(batch_size);
+    let mut total_tokenization_time = 0;
+    let mut total_queue_time = 0;
+    let mut total_inference_time = 0;
+    let mut total_compute_tokens = 0;
+    for (values, metadata) in results {
+        total_tokenization_time += metadata.tokenization.as_nanos() as u64;
+        total_queue_time += metadata.queue.as_nanos() as u64;
+        total_inference_time += metadata.inference.as_nanos() as u64;
+        total_compute_tokens += metadata.prompt_tokens;
+        embeddings.push(values);
+    }
 
-                let batch_size = batch_size as u64;
-                metrics::counter!("te_request_success", "method" => "batch").increment(1);
-
-                Ok((
-                    EmbedResponse(embeddings),
-                    ResponseMetadata::new(
-                        compute_chars,
-                        total_compute_tokens,
-                        start_time,
-                        Duration::from_nanos(total_tokenization_time / batch_size),
-                        Duration::from_nanos(total_queue_time / batch_size),
-                        Duration::from_nanos(total_inference_time / batch_size),
-                    ),
-                ))
-            }
+    let batch_size = batch_size as u64;
+    Ok((
+        EmbedSparseResponse(embeddings),
+        ResponseMetadata::new(
+            compute_chars,
+            total_compute_tokens,
+            start_time,
+            Duration::from_nanos(total_tokenization_time / batch_size),
+            Duration::from_nanos(total_queue_time / batch_size),
+            Duration::from_nanos(total_inference_time / batch_size),
+        ),
+    ))
+}
+
+/// Hidden states of every token of an `EmbedAllRequest`, for late-interaction retrieval. They stay
+/// in memory until the response is written, inputs x tokens x hidden size x 4 bytes: 64 MiB for 32
+/// inputs of 512 tokens of a 1024 dimensional model, and about four times more once serialized as
+/// JSON floats, hence the `max_client_batch_size` check and the streaming of large responses.
+pub(crate) async fn embed_all(
+    app_state: &AppState,
+    req: EmbedAllRequest,
+) -> Result<(EmbedAllResponse, ResponseMetadata)> {
+    let start_time = Instant::now();
+    let served = app_state.models.served();
+    let truncate = req.truncate.unwrap_or(served.info.auto_truncate);
+    let inputs = match req.inputs {
+        Input::Single(input) => vec![input],
+        Input::Batch(inputs) => inputs,
+    };
+    if inputs.is_empty() {
+        return Err(Error::EmptyInput("`inputs` cannot be empty".to_string()));
+    }
+    let max_client_batch_size = app_state.max_client_batch_size(served.infer.limits());
+    if inputs.len() > max_client_batch_size {
+        return Err(Error::BatchTooLarge(format!(
+            "batch size {} > maximum allowed batch size {max_client_batch_size}",
+            inputs.len()
+        )));
+    }
+    let batch_size = inputs.len();
+    let compute_chars = inputs.iter().map(|input| input.count_chars()).sum();
+
+    let results = embed_all_batch(
+        &served.infer,
+        inputs,
+        truncate,
+        req.truncation_direction,
+        req.prompt_name,
+    )
+    .await?;
+
+    let encode = |row| req.encoding_format.encode_owned(row);
+    let mut embeddings = Vec::with_capacity(batch_size);
+    let mut total_tokenization_time = 0;
+    let mut total_queue_time = 0;
+    let mut total_inference_time = 0;
+    let mut total_compute_tokens = 0;
+    for r in results {
+        total_tokenization_time += r.metadata.tokenization.as_nanos() as u64;
+        total_queue_time += r.metadata.queue.as_nanos() as u64;
+        total_inference_time += r.metadata.inference.as_nanos() as u64;
+        total_compute_tokens += r.metadata.prompt_tokens;
+        embeddings.push(r.results.into_iter().map(&encode).collect());
+    }
+
+    let batch_size = batch_size as u64;
+    Ok((
+        EmbedAllResponse(embeddings),
+        ResponseMetadata::new(
+            compute_chars,
+            total_compute_tokens,
+            start_time,
+            Duration::from_nanos(total_tokenization_time / batch_size),
+            Duration::from_nanos(total_queue_time / batch_size),
+            Duration::from_nanos(total_inference_time / batch_size),
+        ),
+    ))
+}
+
+/// Non-zero activations of the SPLADE output, indexed by token id
+fn sparsify(values: Vec<f32>) -> Vec<SparseValue> {
+    values
+        .into_iter()
+        .enumerate()
+        .filter(|(_, value)| *value > 0.0)
+        .map(|(index, value)| SparseValue { index, value })
+        .collect()
+}
+
+async fn embed_sparse_batch(
+    infer: &Arc<Infer>,
+    inputs: Vec<InputType>,
+    truncate: bool,
+    truncation_direction: TruncationDirection,
+    prompt_name: Option<String>,
+) -> Result<Vec<PooledEmbeddingsInferResponse>> {
+    let futures = inputs.into_iter().map(|input| {
+        let local_infer = infer.clone();
+        let prompt_name = prompt_name.clone();
+        async move {
+            let permit = local_infer.acquire_permit().await;
+            local_infer
+                .embed_sparse(
+                    input,
+                    truncate,
+                    truncation_direction.into(),
+                    prompt_name,
+                    permit,
+                )
+                .await
         }
-    })()
-    .await;
+    });
+    join_all(futures).await.into_iter().collect()
+}
 
-    match result {
-        Ok((response, metadata)) => {
-            metadata.record_span(&span);
-            metadata.record_metrics();
-            let headers = HeaderMap::from(metadata);
-            tracing::info!("Success");
-            Ok((headers, Json(response)).into_response())
+/// Token embeddings of every input, concurrently like `embed_batch`
+pub(crate) async fn embed_all_batch(
+    infer: &Arc<Infer>,
+    inputs: Vec<InputType>,
+    truncate: bool,
+    truncation_direction: TruncationDirection,
+    prompt_name: Option<String>,
+) -> Result<Vec<AllEmbeddingsInferResponse>> {
+    let futures = inputs.into_iter().map(|input| {
+        let local_infer = infer.clone();
+        let prompt_name = prompt_name.clone();
+        async move {
+            let permit = local_infer.acquire_permit().await;
+            local_infer
+                .embed_all(
+                    input,
+                    truncate,
+                    truncation_direction.into(),
+                    prompt_name,
+                    permit,
+                )
+                .await
         }
+    });
+    join_all(futures).await.into_iter().collect()
+}
 
-        Err(Error::Custom(msg)) if msg.contains("Queue is full") => {
-            tracing::warn!("Queue full: returning 429");
-            Ok((StatusCode::TOO_MANY_REQUESTS, Json(json!({ "error": msg }))).into_response())
+/// Embed every input concurrently, each waiting for a permit, the results keep the input order
+pub(crate) async fn embed_batch(
+    infer: &Arc<Infer>,
+    inputs: Vec<InputType>,
+    truncate: bool,
+    truncation_direction: TruncationDirection,
+    prompt_name: Option<String>,
+    normalize: bool,
+    dimensions: Option<usize>,
+) -> Result<Vec<PooledEmbeddingsInferResponse>> {
+    let futures = inputs.into_iter().map(|input| {
+        let local_infer = infer.clone();
+        let prompt_name = prompt_name.clone();
+        async move {
+            let permit = local_infer.acquire_permit().await;
+            local_infer
+                .embed_pooled(
+                    input,
+                    truncate,
+                    truncation_direction.into(),
+                    prompt_name,
+                    normalize,
+                    dimensions,
+                    permit,
+                )
+                .await
         }
+    });
+    join_all(futures).await.into_iter().collect()
+}
 
-        Err(err) => {
-            tracing::error!("Handler error: {err}");
-            Ok((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({ "error": err.to_string() })),
+/// Embed a text longer than `max_input_length` as the pooled embeddings of its overlapping windows,
+/// the text is embedded as a whole when it fits
+#[allow(clippy::too_many_arguments)]
+async fn embed_long_input(
+    app_state: &AppState,
+    infer: &Arc<Infer>,
+    max_input_length: usize,
+    text: String,
+    pooling: LongInputPooling,
+    overlap: Option<usize>,
+    prompt_name: Option<String>,
+    normalize: bool,
+    dimensions: Option<usize>,
+) -> Result<PooledEmbeddingsInferResponse> {
+    let (prompted, encoding) = infer
+        .tokenize(text.clone(), true, prompt_name.clone())
+        .await?;
+    if encoding.len() <= max_input_length {
+        let permit = infer.try_acquire_permit()?;
+        return infer
+            .embed_pooled(
+                InputType::String(text),
+                false,
+                TruncationDirection::default().into(),
+                prompt_name,
+                normalize,
+                dimensions,
+                permit,
             )
-                .into_response())
-        }
+            .await;
+    }
+
+    // The prompt is prepended to the text, its tokens are added again to every window
+    let prompt_len = prompted.map_or(0, |prompted| prompted.len() - text.len());
+    let offsets: Vec<_> = encoding
+        .get_offsets()
+        .iter()
+        .zip(encoding.get_special_tokens_mask())
+        .filter(|((start, _), special)| **special == 0 && *start >= prompt_len)
+        .map(|((start, stop), _)| (start - prompt_len, stop - prompt_len))
+        .collect();
+    let size = long_input::window_size(max_input_length, encoding.len() - offsets.len());
+    let overlap = overlap.unwrap_or_else(|| long_input::default_overlap(size));
+    if size == 0 || overlap >= size {
+        return Err(Error::BadRequest(format!(
+            "`window_overlap` must be smaller than the window of {size} tokens"
+        )));
+    }
+    let windows = long_input::windows(&offsets, size, overlap);
+    let max_client_batch_size = app_state.max_client_batch_size(infer.limits());
+    if windows.len() > max_client_batch_size {
+        return Err(Error::InputTooLong(format!(
+            "`inputs` is split into {} windows > maximum allowed batch size {max_client_batch_size}",
+            windows.len()
+        )));
+    }
+
+    let inputs = windows
+        .iter()
+        .map(|(start, stop, _)| {
+            InputType::String(long_input::window_text(&text, *start, *stop).to_string())
+        })
+        .collect();
+    let results = embed_batch(
+        infer,
+        inputs,
+        false,
+        TruncationDirection::default(),
+        prompt_name,
+        normalize,
+        dimensions,
+    )
+    .await?;
+
+    let mut metadata = InferMetadata {
+        prompt_tokens: 0,
+        tokenization: Duration::ZERO,
+        queue: Duration::ZERO,
+        inference: Duration::ZERO,
+    };
+    let mut embeddings = Vec::with_capacity(results.len());
+    for (r, (_, _, tokens)) in results.into_iter().zip(&windows) {
+        metadata.prompt_tokens += r.metadata.prompt_tokens;
+        metadata.tokenization += r.metadata.tokenization;
+        metadata.queue += r.metadata.queue;
+        metadata.inference += r.metadata.inference;
+        embeddings.push((r.results, *tokens));
+    }
+    let mut results = long_input::pool(pooling, &embeddings);
+    if normalize {
+        long_input::normalize(&mut results);
     }
+    metrics::counter!("te_request_long_input_windows").increment(windows.len() as u64);
+    Ok(PooledEmbeddingsInferResponse {
+        stats: Some(EmbeddingStats::of(&results)),
+        results,
+        metadata,
+    })
 }
@@ -0,0 +1,98 @@
+//! Golden query set of the tenant and the metric history of its evaluations. The set is managed by
+//! admins and evaluated by the `evaluate_golden_set` cron job.
+
+use crate::cache::AppState;
+use crate::error::{Error, Result};
+use crate::middleware::mw_auth::Ctm;
+use crate::middleware::mw_history::History;
+use axum::{
+    Router,
+    extract::{Extension, Path},
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get},
+};
+use lib_core::ctx::Ctx;
+use lib_core::model::evaluation::{EvaluationMac, GoldenQueryForCreate, RunStatus};
+use lib_core::model::user::Role;
+use serde_json::json;
+
+pub fn serve_evaluation() -> Router {
+    Router::new()
+        .route("/golden", get(list_golden).post(create_golden))
+        .route("/golden/{query_id}", delete(delete_golden))
+        .route("/runs", get(list_runs))
+}
+
+fn require_admin(ctx: &Ctx) -> Result<()> {
+    match ctx.role() {
+        Some(Role::Admin) => Ok(()),
+        _ => Err(Error::Forbidden(
+            "The golden set is managed by admins".to_string(),
+        )),
+    }
+}
+
+async fn list_golden(Extension(app_state): Extension<AppState>, Ctm(ctx): Ctm) -> Result<Response> {
+    let queries = EvaluationMac::list_queries(&app_state.mm, &ctx.tenant_id()).await?;
+    Ok(Json(json!({
+        "status": 200,
+        "data": queries,
+    }))
+    .into_response())
+}
+
+async fn create_golden(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Json(query): Json<GoldenQueryForCreate>,
+) -> Result<Response> {
+    require_admin(&ctx)?;
+    if query.query.trim().is_empty() {
+        return Err(Error::EmptyInput("`query` cannot be empty".to_string()));
+    }
+    if query.relevant_files.is_empty() {
+        return Err(Error::BadRequest(
+            "`relevant_files` needs at least one file id".to_string(),
+        ));
+    }
+    let query = EvaluationMac::create_query(&app_state.mm, &ctx.tenant_id(), query).await?;
+    Ok(Json(json!({
+        "status": 200,
+        "data": query,
+    }))
+    .into_response())
+}
+
+async fn delete_golden(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(query_id): Path<i64>,
+) -> Result<Response> {
+    require_admin(&ctx)?;
+    let deleted = EvaluationMac::delete_query(&app_state.mm, &ctx.tenant_id(), query_id).await?;
+    if deleted == 0 {
+        return Err(Error::NotFound(format!("Golden query {query_id}")));
+    }
+    Ok(Json(json!({
+        "status": 200,
+        "data": { "deleted": query_id },
+    }))
+    .into_response())
+}
+
+/// Latest evaluations first, `regression` marks the runs which raised an alert. Paged and
+/// filtered with the history parameters, `status` is `ok` or `regression`.
+async fn list_runs(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    History(filter): History<RunStatus>,
+) -> Result<Response> {
+    let runs = EvaluationMac::find_runs(&app_state.mm, &ctx.tenant_id(), &filter).await?;
+    let next_cursor = filter.next_cursor(&runs, |run| run.run_id);
+    Ok(Json(json!({
+        "status": 200,
+        "data": runs,
+        "next_cursor": next_cursor,
+    }))
+    .into_response())
+}
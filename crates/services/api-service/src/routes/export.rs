@@ -0,0 +1,121 @@
+//! Bulk export of the chunks of the caller's tenant for offline analytics or to build an index in
+//! another system, instead of paging through `GET /chunks`.
+//!
+//! `GET /export/chunks?file_id=<id>&format=parquet|arrow` streams the chunk ids, text and
+//! embeddings as a Parquet file (default) or an Arrow IPC stream. The chunks are read
+//! `EXPORT_PAGE_SIZE` at a time in `chunk_id` order; a failure past the first page ends the
+//! download early, which the client sees as a truncated file.
+
+use crate::cache::AppState;
+use crate::chunk_export::{ChunkEncoder, ExportFormat};
+use crate::error::{Error, Result};
+use crate::middleware::mw_auth::Ctm;
+use axum::{
+    Router,
+    body::{Body, Bytes},
+    extract::{Extension, Query},
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use futures::StreamExt;
+use lib_core::database::ModelManager;
+use lib_core::model::file_chunks::{FileChunk, FileChunkMac};
+use lib_core::model::pagination::{ListOptions, MAX_PAGE_LIMIT, SortOrder};
+use serde::Deserialize;
+use std::sync::Arc;
+
+pub fn serve_export() -> Router {
+    Router::new().route("/export/chunks", get(export_chunks))
+}
+
+/// Chunks read and encoded at once, a record batch or a Parquet row group each
+const EXPORT_PAGE_SIZE: i64 = MAX_PAGE_LIMIT;
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    /// Only export the chunks of this file
+    #[serde(default)]
+    file_id: Option<i64>,
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+struct ExportState {
+    mm: Arc<ModelManager>,
+    tenant_id: String,
+    file_id: Option<i64>,
+    after_id: Option<i64>,
+    /// `None` once the last page is written
+    encoder: Option<ChunkEncoder>,
+}
+
+impl ExportState {
+    async fn page(&self) -> Result<Vec<FileChunk>> {
+        let options = ListOptions {
+            limit: Some(EXPORT_PAGE_SIZE),
+            after_id: self.after_id,
+            sort: SortOrder::Asc,
+        };
+        Ok(FileChunkMac::list_chunks(&self.mm, &self.tenant_id, self.file_id, &options).await?)
+    }
+
+    /// Encoded `chunks`, followed by the end of the export after the last page
+    fn encode(&mut self, chunks: &[FileChunk]) -> Result<Bytes> {
+        let Some(encoder) = self.encoder.as_mut() else {
+            return Ok(Bytes::new());
+        };
+        let mut bytes = encoder.encode(chunks)?;
+        self.after_id = chunks.last().map(|chunk| chunk.chunk_id).or(self.after_id);
+        if (chunks.len() as i64) < EXPORT_PAGE_SIZE {
+            if let Some(encoder) = self.encoder.take() {
+                bytes.extend(encoder.finish()?);
+            }
+        }
+        Ok(Bytes::from(bytes))
+    }
+}
+
+async fn export_chunks(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response> {
+    let mut state = ExportState {
+        mm: app_state.mm.clone(),
+        tenant_id: ctx.tenant_id(),
+        file_id: query.file_id,
+        after_id: None,
+        encoder: Some(ChunkEncoder::new(query.format)?),
+    };
+    // The first page is read before answering, so that a failing query is a proper error
+    let first = state.page().await?;
+    let first = state.encode(&first)?;
+
+    let rest = futures::stream::try_unfold(state, |mut state| async move {
+        if state.encoder.is_none() {
+            return Ok::<_, Error>(None);
+        }
+        let chunks = state.page().await.inspect_err(|e| {
+            tracing::error!("Chunk export failed: {e:?}");
+        })?;
+        let bytes = state.encode(&chunks)?;
+        Ok(Some((bytes, state)))
+    });
+    let body = futures::stream::once(async { Ok(first) }).chain(rest);
+    let disposition = format!(
+        "attachment; filename=\"chunks.{}\"",
+        query.format.extension()
+    );
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                query.format.content_type().to_string(),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
@@ -0,0 +1,101 @@
+//! Paginated listings of the files and chunks of the caller's tenant.
+//!
+//! Both use keyset pagination: `?limit=100&after_id=<next_after_id of the previous page>`, with
+//! `sort=asc|desc` on the id. Every page carries the `total` of the listing.
+//!
+//! `GET /chunks/dedup` reports the texts stored in more than one chunk of the tenant.
+
+use crate::cache::AppState;
+use crate::error::Result;
+use crate::middleware::mw_auth::Ctm;
+use axum::{
+    Router,
+    extract::{Extension, Query},
+    response::Json,
+    routing::get,
+};
+use lib_core::model::file_chunks::{ChunkMetadata, DedupReport, FileChunk, FileChunkMac};
+use lib_core::model::files::{File, FileMac};
+use lib_core::model::pagination::{ListOptions, Page};
+use serde::{Deserialize, Serialize};
+
+pub fn serve_files() -> Router {
+    Router::new()
+        .route("/files", get(list_files))
+        .route("/chunks", get(list_chunks))
+        .route("/chunks/dedup", get(dedup_report))
+}
+
+/// Most repeated texts listed by the dedup report
+const DEDUP_REPORT_LIMIT: i64 = 100;
+
+#[derive(Deserialize)]
+struct ChunkFilter {
+    /// Only list the chunks of this file
+    #[serde(default)]
+    file_id: Option<i64>,
+}
+
+/// Chunk without its embedding, listings stay small
+#[derive(Serialize)]
+struct ChunkSummary {
+    chunk_id: i64,
+    file_id: i64,
+    chunk_index: i32,
+    content_md: Option<String>,
+    token_count: Option<i32>,
+    oversize: Option<String>,
+    metadata: ChunkMetadata,
+    content_hash: Option<String>,
+}
+
+impl From<FileChunk> for ChunkSummary {
+    fn from(chunk: FileChunk) -> Self {
+        Self {
+            chunk_id: chunk.chunk_id,
+            file_id: chunk.file_id,
+            chunk_index: chunk.chunk_index,
+            content_md: chunk.content_md,
+            token_count: chunk.token_count,
+            oversize: chunk.oversize,
+            metadata: chunk.metadata,
+            content_hash: chunk.content_hash,
+        }
+    }
+}
+
+async fn list_files(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Query(options): Query<ListOptions<i64>>,
+) -> Result<Json<Page<File, i64>>> {
+    let tenant_id = ctx.tenant_id();
+    let files = FileMac::list_files(&app_state.mm, &tenant_id, &options).await?;
+    let total = FileMac::count_files(&app_state.mm, &tenant_id).await?;
+    Ok(Json(Page::new(files, total, options.limit(), |file| {
+        file.file_id
+    })))
+}
+
+async fn list_chunks(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Query(options): Query<ListOptions<i64>>,
+    Query(filter): Query<ChunkFilter>,
+) -> Result<Json<Page<ChunkSummary, i64>>> {
+    let tenant_id = ctx.tenant_id();
+    let chunks =
+        FileChunkMac::list_chunks(&app_state.mm, &tenant_id, filter.file_id, &options).await?;
+    let total = FileChunkMac::count_chunks(&app_state.mm, &tenant_id, filter.file_id).await?;
+    let page = Page::new(chunks, total, options.limit(), |chunk| chunk.chunk_id);
+    Ok(Json(page.map(ChunkSummary::from)))
+}
+
+async fn dedup_report(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+) -> Result<Json<DedupReport>> {
+    let report =
+        FileChunkMac::dedup_report(&app_state.mm, &ctx.tenant_id(), DEDUP_REPORT_LIMIT).await?;
+    Ok(Json(report))
+}
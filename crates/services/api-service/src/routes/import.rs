@@ -0,0 +1,152 @@
+//! Bulk load of chunks with precomputed embeddings, e.g. when migrating from another vector
+//! store, without embedding them again.
+//!
+//! `POST /import/chunks` takes JSONL (one `{"file_id" | "filename", "text", "embedding",
+//! "metadata"}` object per line) or, with `Content-Type: application/vnd.apache.arrow.stream`,
+//! an Arrow IPC stream with the same columns. The files must exist, `filename` names a live file
+//! of the tenant. Every embedding must have the dimension of the served model and of the
+//! collection of its file; the chunks are then copied to the database in one transaction, the
+//! ones repeating a text of their file are skipped.
+
+use crate::cache::AppState;
+use crate::chunk_import::{ImportRecord, parse_arrow, parse_jsonl};
+use crate::error::{Error, Result};
+use crate::middleware::mw_auth::Ctm;
+use crate::routes::embed::embed_batch;
+use crate::types::{InputType, TruncationDirection};
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{DefaultBodyLimit, Extension},
+    http::{HeaderMap, header::CONTENT_TYPE},
+    response::{IntoResponse, Json, Response},
+    routing::post,
+};
+use lib_core::model::file_chunks::{ChunkForImport, FileChunkMac};
+use lib_core::model::files::FileMac;
+use pgvector::Vector;
+use serde_json::json;
+use std::collections::HashSet;
+
+/// Largest import body, about 100k chunks of 768 dimensions as JSONL
+pub const MAX_IMPORT_BYTES: usize = 512 << 20;
+
+const ARROW_STREAM: &str = "application/vnd.apache.arrow.stream";
+
+/// Path of the import below `/api/v1`
+pub const IMPORT_PATH: &str = "/import/chunks";
+
+pub fn serve_import() -> Router {
+    Router::new()
+        .route(IMPORT_PATH, post(import_chunks))
+        .layer(DefaultBodyLimit::max(MAX_IMPORT_BYTES))
+}
+
+async fn import_chunks(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    let arrow = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(ARROW_STREAM));
+    let records = match arrow {
+        true => parse_arrow(&body)?,
+        false => parse_jsonl(&body)?,
+    };
+    if records.is_empty() {
+        return Err(Error::EmptyInput("No chunk to import".to_string()));
+    }
+
+    let dimension = served_dimension(&app_state).await?;
+    let model_id = app_state.info().model_id.clone();
+    for (index, record) in records.iter().enumerate() {
+        validate(index + 1, record, dimension, &model_id)?;
+    }
+
+    let tenant_id = ctx.tenant_id();
+    let filenames: Vec<String> = records
+        .iter()
+        .filter(|record| record.file_id.is_none())
+        .filter_map(|record| record.filename.clone())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let file_ids = FileMac::get_file_ids_by_filename(&app_state.mm, &tenant_id, &filenames).await?;
+    let mut checked = HashSet::new();
+    let mut chunks = Vec::with_capacity(records.len());
+    for record in records {
+        let file_id = match record.file_id {
+            Some(file_id) => file_id,
+            None => {
+                let filename = record.filename.as_deref().unwrap_or_default();
+                *file_ids
+                    .get(filename)
+                    .ok_or_else(|| Error::NotFound(format!("File {filename}")))?
+            }
+        };
+        if checked.insert(file_id) {
+            FileMac::get_file_by_id(&app_state.mm, &tenant_id, &file_id)
+                .await
+                .map_err(|_| Error::NotFound(format!("File {file_id}")))?;
+        }
+        chunks.push(ChunkForImport {
+            file_id,
+            embedding_normalized: record.is_normalized(),
+            content_md: record.text,
+            embedding: Vector::from(record.embedding),
+            embedding_model: Some(record.embedding_model.unwrap_or_else(|| model_id.clone())),
+            metadata: record.metadata,
+        });
+    }
+
+    let summary = FileChunkMac::import_chunks(&app_state.mm, &tenant_id, chunks).await?;
+    Ok(Json(json!({
+        "status": 200,
+        "data": summary,
+    }))
+    .into_response())
+}
+
+/// Dimension of the embeddings of the served model, from the embedding of a probe text
+async fn served_dimension(app_state: &AppState) -> Result<usize> {
+    let probe = vec![InputType::String("dimension probe".to_string())];
+    let results = embed_batch(
+        &app_state.infer(),
+        probe,
+        true,
+        TruncationDirection::default(),
+        None,
+        false,
+        None,
+    )
+    .await?;
+    Ok(results.first().map_or(0, |result| result.results.len()))
+}
+
+fn validate(record: usize, chunk: &ImportRecord, dimension: usize, model_id: &str) -> Result<()> {
+    if chunk.file_id.is_none() && chunk.filename.is_none() {
+        return Err(Error::BadRequest(format!(
+            "Record {record}: `file_id` or `filename` is required"
+        )));
+    }
+    if chunk.text.trim().is_empty() {
+        return Err(Error::EmptyInput(format!(
+            "Record {record}: `text` is empty"
+        )));
+    }
+    if chunk.embedding.len() != dimension {
+        return Err(Error::DimensionMismatch(format!(
+            "Record {record}: embedding of dimension {}, the served model `{model_id}` produces {dimension}",
+            chunk.embedding.len()
+        )));
+    }
+    if chunk.embedding.iter().any(|value| !value.is_finite()) {
+        return Err(Error::BadRequest(format!(
+            "Record {record}: `embedding` has a value that is not finite"
+        )));
+    }
+    Ok(())
+}
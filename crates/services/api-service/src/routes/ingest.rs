@@ -0,0 +1,206 @@
+//! Embed-and-store of chunks produced outside of the ingestion cron, e.g. by bulk loaders.
+//!
+//! `?return=minimal` only returns the chunk ids and token counts, producers loading millions of
+//! chunks do not need the vectors back. `full` (default) also returns the embeddings.
+//!
+//! The language of a chunk without one in its metadata is detected, and the `LANGUAGE_PROMPTS`
+//! prompt of the language is prepended to its text before it is embedded. The stored text has no
+//! prompt.
+//!
+//! A file keeps a single chunk per text (`content_hash`), a batch repeating a text is rejected with
+//! `409 Conflict` before any inference.
+//!
+//! `"normalize": false` stores the raw vectors, the search then ranks the chunks of the model by
+//! cosine whatever `VECTOR_DISTANCE`.
+
+use crate::cache::AppState;
+use crate::error::{Error, Result};
+use crate::middleware::mw_auth::Ctm;
+use crate::routes::embed::embed_batch;
+use crate::types::{InputType, TruncationDirection};
+use axum::{
+    Router,
+    extract::{Extension, Path, Query},
+    response::{IntoResponse, Json, Response},
+    routing::post,
+};
+use lib_core::model::file_chunks::{ChunkMetadata, FileChunkForCreate, FileChunkMac, content_hash};
+use lib_core::model::files::FileMac;
+use lib_core::vector_index::embeddings_normalized;
+use lib_cron::config::auth_config;
+use lib_cron::language::detect_language;
+use pgvector::Vector;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+
+pub fn serve_ingest() -> Router {
+    Router::new().route("/files/{file_id}/chunks", post(ingest_chunks))
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ReturnMode {
+    /// Chunk ids and token counts
+    Minimal,
+    /// Also the embeddings and metadata
+    #[default]
+    Full,
+}
+
+#[derive(Deserialize)]
+struct IngestParams {
+    #[serde(default, rename = "return")]
+    return_mode: ReturnMode,
+}
+
+#[derive(Deserialize)]
+struct IngestRequest {
+    chunks: Vec<ChunkInput>,
+    #[serde(default)]
+    truncate: Option<bool>,
+    /// Store unit vectors, `EMBEDDINGS_NORMALIZED` by default
+    #[serde(default)]
+    normalize: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ChunkInput {
+    text: String,
+    #[serde(default)]
+    metadata: ChunkMetadata,
+}
+
+#[derive(Serialize)]
+struct StoredChunk {
+    chunk_id: i64,
+    chunk_index: i32,
+    token_count: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    embedding: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<ChunkMetadata>,
+}
+
+/// Chunks are appended after the existing chunks of the file, which must belong to the tenant
+async fn ingest_chunks(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(file_id): Path<i64>,
+    Query(params): Query<IngestParams>,
+    Json(mut req): Json<IngestRequest>,
+) -> Result<Response> {
+    if req.chunks.is_empty() {
+        return Err(Error::EmptyInput("`chunks` cannot be empty".to_string()));
+    }
+    let max_client_batch_size = app_state.max_client_batch_size(app_state.infer().limits());
+    if req.chunks.len() > max_client_batch_size {
+        return Err(Error::BatchTooLarge(format!(
+            "batch size {} > maximum allowed batch size {max_client_batch_size}",
+            req.chunks.len()
+        )));
+    }
+    let tenant_id = ctx.tenant_id();
+    FileMac::get_file_by_id(&app_state.mm, &tenant_id, &file_id)
+        .await
+        .map_err(|_| Error::NotFound(format!("File {file_id}")))?;
+
+    let hashes: Vec<String> = req
+        .chunks
+        .iter()
+        .map(|chunk| content_hash(&chunk.text))
+        .collect();
+    let mut distinct = HashSet::new();
+    if let Some(index) = hashes.iter().position(|hash| !distinct.insert(hash)) {
+        return Err(Error::Conflict(format!(
+            "Chunk {index} repeats the text of a previous chunk of the batch"
+        )));
+    }
+    let existing =
+        FileChunkMac::existing_hashes(&app_state.mm, &tenant_id, file_id, &hashes).await?;
+    if let Some(index) = hashes.iter().position(|hash| existing.contains(hash)) {
+        return Err(Error::Conflict(format!(
+            "Chunk {index} repeats the text of a chunk of file {file_id}"
+        )));
+    }
+
+    // The language given by the producer wins over the detected one
+    for chunk in req.chunks.iter_mut() {
+        if chunk.metadata.language.is_none() {
+            chunk.metadata.language = detect_language(&chunk.text);
+        }
+    }
+    let prompts = &auth_config().language_prompts;
+    let inputs = req
+        .chunks
+        .iter()
+        .map(|chunk| {
+            let text = prompts.apply(chunk.metadata.language.as_deref(), chunk.text.clone());
+            InputType::String(text)
+        })
+        .collect();
+    let truncate = req.truncate.unwrap_or(app_state.info().auto_truncate);
+    let normalize = req.normalize.unwrap_or_else(embeddings_normalized);
+    let results = match embed_batch(
+        &app_state.infer(),
+        inputs,
+        truncate,
+        TruncationDirection::default(),
+        None,
+        normalize,
+        None,
+    )
+    .await
+    {
+        Ok(results) => results,
+        Err(err) => return Ok(err.into_response()),
+    };
+
+    let mut prompt_tokens = 0;
+    let mut embeddings = Vec::with_capacity(results.len());
+    let chunks = req
+        .chunks
+        .into_iter()
+        .zip(results)
+        .enumerate()
+        .map(|(offset, (chunk, result))| {
+            prompt_tokens += result.metadata.prompt_tokens;
+            let create = FileChunkForCreate {
+                file_id,
+                // Offset from the last chunk, numbered under the lock of the file
+                chunk_index: offset as i32,
+                content_md: Some(chunk.text),
+                embedding: Some(Vector::from(result.results.clone())),
+                embedding_model: Some(app_state.info().model_id.clone()),
+                embedding_normalized: normalize,
+                token_count: Some(result.metadata.prompt_tokens as i32),
+                oversize: None,
+                metadata: chunk.metadata,
+            };
+            embeddings.push(result.results);
+            create
+        })
+        .collect();
+    let created =
+        FileChunkMac::create_file_chunks(&app_state.mm, &tenant_id, file_id, chunks).await?;
+
+    let full = params.return_mode == ReturnMode::Full;
+    let data: Vec<StoredChunk> = created
+        .into_iter()
+        .zip(embeddings)
+        .map(|(chunk, embedding)| StoredChunk {
+            chunk_id: chunk.chunk_id,
+            chunk_index: chunk.chunk_index,
+            token_count: chunk.token_count,
+            embedding: full.then_some(embedding),
+            metadata: full.then_some(chunk.metadata),
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "status": 200,
+        "data": data,
+        "usage": { "prompt_tokens": prompt_tokens },
+    }))
+    .into_response())
+}
@@ -1,2 +1,16 @@
+pub mod admin;
 pub mod cron;
 pub mod embed;
+pub mod evaluation;
+pub mod export;
+pub mod files;
+pub mod import;
+pub mod ingest;
+pub mod models;
+pub mod sagemaker;
+pub mod score;
+pub mod search;
+pub mod stream;
+pub mod version;
+pub mod vertex;
+pub mod ws;
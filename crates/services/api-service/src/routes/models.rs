@@ -0,0 +1,68 @@
+//! Catalog of the served model and the warm standby models (`--standby-model-id`), and the
+//! switchover to a downloaded standby model, recorded in the audit log.
+
+use crate::cache::AppState;
+use crate::error::{Error, Result};
+use crate::middleware::mw_auth::Ctm;
+use crate::routes::admin::{audit, restore_limits};
+use axum::{
+    Router,
+    extract::{Extension, Path},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+};
+use lib_core::ctx::Ctx;
+use lib_core::model::audit::{AuditEntryForCreate, AuditKind};
+use lib_core::model::user::Role;
+use serde_json::json;
+
+pub fn serve_models() -> Router {
+    Router::new()
+        .route("/models", get(list_models))
+        .route("/models/{model_id}/load", post(load_model))
+}
+
+fn require_admin(ctx: &Ctx) -> Result<()> {
+    match ctx.role() {
+        Some(Role::Admin) => Ok(()),
+        _ => Err(Error::Forbidden(
+            "Models are switched by admins".to_string(),
+        )),
+    }
+}
+
+async fn list_models(Extension(app_state): Extension<AppState>) -> Result<Response> {
+    Ok(Json(json!({
+        "status": 200,
+        "data": app_state.models.list(),
+    }))
+    .into_response())
+}
+
+/// Load a downloaded standby model and serve it instead of the current one. The model id is
+/// percent-encoded in the path, e.g. `BAAI%2Fbge-small-en-v1.5`.
+async fn load_model(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(model_id): Path<String>,
+) -> Result<Response> {
+    require_admin(&ctx)?;
+    if app_state.splade_query.is_some() {
+        return Err(Error::Conflict(
+            "The served model has a SPLADE query encoder, it cannot be switched".to_string(),
+        ));
+    }
+    let before = app_state.info();
+    let info = app_state.models.switch_to(&model_id).await?;
+    // The queue of the new model starts with the limits of the command line
+    restore_limits(&app_state).await;
+    let entry = AuditEntryForCreate::new(AuditKind::Model, "model.load", &model_id)
+        .before(before.as_ref())
+        .after(info.as_ref());
+    audit(&app_state, &ctx, entry).await;
+    Ok(Json(json!({
+        "status": 200,
+        "data": info.as_ref(),
+    }))
+    .into_response())
+}
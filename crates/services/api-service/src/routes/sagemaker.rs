@@ -0,0 +1,180 @@
+//! AWS SageMaker bring-your-own-container hosting contract.
+//!
+//! SageMaker probes `GET /ping` and sends inference requests to `POST /invocations`. The payload
+//! is dispatched on its `Content-Type`: `application/json` is an `EmbedRequest`, `text/plain` is a
+//! single input embedded with the default parameters.
+
+use crate::cache::AppState;
+use crate::error::Result;
+use crate::routes::embed::embed_output;
+use crate::routes::stream::json_array_response;
+use crate::types::{EmbedOutput, EmbedRequest, ErrorResponse, ErrorType};
+use axum::{
+    Router,
+    body::Bytes,
+    extract::Extension,
+    http::{HeaderMap, StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+};
+use serde_json::json;
+use tracing::instrument;
+
+pub fn serve_sagemaker() -> Router {
+    Router::new().route("/invocations", post(run_invocations))
+}
+
+/// The ping probe is mounted outside the authentication layers, SageMaker sends no credentials
+pub fn serve_sagemaker_ping() -> Router {
+    Router::new().route("/ping", get(ping))
+}
+
+/// SageMaker marks the container healthy once this returns 200
+async fn ping(Extension(app_state): Extension<AppState>) -> StatusCode {
+    if app_state.infer().health().await {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+#[utoipa::path(
+post,
+tag = "Text Embeddings Inference",
+path = "/invocations",
+request_body = EmbedRequest,
+responses(
+(status = 200, description = "Embeddings", body = EmbedOutput),
+(status = 400, description = "Invalid payload", body = ErrorResponse,
+example = json ! ({"error": "Invalid payload", "error_type": "validation"})),
+(status = 415, description = "Unsupported content type", body = ErrorResponse,
+example = json ! ({"error": "Unsupported content type", "error_type": "validation"})),
+(status = 429, description = "Queue is full", body = ErrorResponse,
+example = json ! ({"error": "Queue is full. Please retry.", "error_type": "queue_full"})),
+)
+)]
+#[instrument(skip_all, fields(content_type))]
+async fn run_invocations(
+    Extension(app_state): Extension<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/json");
+    tracing::Span::current().record("content_type", content_type);
+
+    let req = match parse_invocation(content_type, &body) {
+        Ok(req) => req,
+        Err((status, error)) => {
+            let error = ErrorResponse {
+                error,
+                error_type: ErrorType::Validation,
+            };
+            return Ok((status, Json(error)).into_response());
+        }
+    };
+
+    match embed_output(&app_state, req).await {
+        Ok((response, metadata)) => {
+            metadata.record_metrics();
+            let headers = HeaderMap::from(metadata);
+            Ok(json_array_response(
+                headers,
+                response,
+                app_state.stream_threshold,
+            ))
+        }
+        Err(err) => Ok(err.into_response()),
+    }
+}
+
+/// Build the `EmbedRequest` of an invocation from its content type, parameters such as
+/// `; charset=utf-8` are ignored
+fn parse_invocation(
+    content_type: &str,
+    body: &[u8],
+) -> core::result::Result<EmbedRequest, (StatusCode, String)> {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    match mime.as_str() {
+        "application/json" => serde_json::from_slice(body).map_err(|err| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("invalid json payload: {err}"),
+            )
+        }),
+        "text/plain" => {
+            let text = std::str::from_utf8(body)
+                .map_err(|_| (StatusCode::BAD_REQUEST, "payload is not utf-8".to_string()))?;
+            serde_json::from_value(json!({ "inputs": text }))
+                .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))
+        }
+        other => Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("unsupported content type `{other}`, use application/json or text/plain"),
+        )),
+    }
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Input, InputType};
+
+    fn single_input(req: EmbedRequest) -> String {
+        match req.inputs {
+            Input::Single(InputType::String(text)) => text,
+            _ => panic!("expected a single text input"),
+        }
+    }
+
+    #[test]
+    fn test_parse_invocation_json() {
+        let req = parse_invocation(
+            "application/json",
+            br#"{"inputs": "hello", "truncate": true}"#,
+        )
+        .unwrap();
+        assert_eq!(req.truncate, Some(true));
+        assert_eq!(single_input(req), "hello");
+
+        let req = parse_invocation("application/json", br#"{"inputs": ["a", "b"]}"#).unwrap();
+        assert!(matches!(req.inputs, Input::Batch(ref inputs) if inputs.len() == 2));
+
+        let (status, _) = parse_invocation("application/json", b"not json").unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_parse_invocation_text() {
+        let req = parse_invocation("text/plain", "héllo {\"inputs\"}".as_bytes()).unwrap();
+        assert_eq!(req.truncate, None);
+        assert_eq!(single_input(req), "héllo {\"inputs\"}");
+
+        let (status, _) = parse_invocation("text/plain", &[0xff, 0xfe]).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_parse_invocation_content_type() {
+        // Parameters and case are ignored
+        let req = parse_invocation("Text/Plain; charset=utf-8", b"hello").unwrap();
+        assert_eq!(single_input(req), "hello");
+        let req =
+            parse_invocation(" application/json ;charset=UTF-8", br#"{"inputs": "hi"}"#).unwrap();
+        assert_eq!(single_input(req), "hi");
+
+        let (status, error) = parse_invocation("application/x-npy", b"").unwrap_err();
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(error.contains("application/x-npy"));
+    }
+}
+// endregion: Unit Test
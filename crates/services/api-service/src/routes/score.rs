@@ -0,0 +1,136 @@
+//! Scoring of candidate documents against a query on the server, so that clients do not have to
+//! download the token matrices of `/embed_all` to rank them.
+
+use crate::ai::ResponseMetadata;
+use crate::ai::late_interaction::{normalize, rank};
+use crate::cache::AppState;
+use crate::error::{Error, Result};
+use crate::routes::embed::embed_all_batch;
+use crate::types::{ErrorResponse, InputType, LateInteractionRequest, Rank, RerankResponse};
+use axum::{
+    Router,
+    extract::Extension,
+    http::HeaderMap,
+    response::{IntoResponse, Json, Response},
+    routing::post,
+};
+use std::time::{Duration, Instant};
+use tracing::instrument;
+
+pub fn serve_score() -> Router {
+    Router::new().route("/score/late-interaction", post(run_late_interaction))
+}
+
+#[utoipa::path(
+post,
+tag = "Text Embeddings Inference",
+path = "/score/late-interaction",
+request_body = LateInteractionRequest,
+responses(
+(status = 200, description = "Ranked documents", body = RerankResponse),
+(status = 424, description = "Embedding Error", body = ErrorResponse,
+example = json ! ({"error": "Inference failed", "error_type": "backend"})),
+(status = 429, description = "Queue is full", body = ErrorResponse,
+example = json ! ({"error": "Queue is full. Please retry.", "error_type": "queue_full"})),
+(status = 413, description = "Batch size error", body = ErrorResponse,
+example = json ! ({"error": "Batch size error", "error_type": "batch_too_large"})),
+)
+)]
+#[instrument(
+    skip_all,
+    fields(total_time, tokenization_time, queue_time, inference_time,)
+)]
+async fn run_late_interaction(
+    Extension(app_state): Extension<AppState>,
+    Json(req): Json<LateInteractionRequest>,
+) -> Result<Response> {
+    let span = tracing::Span::current();
+    match late_interaction(&app_state, req).await {
+        Ok((response, metadata)) => {
+            metadata.record_span(&span);
+            metadata.record_metrics();
+            tracing::info!("Success");
+            Ok((HeaderMap::from(metadata), Json(response)).into_response())
+        }
+        Err(err) => Ok(err.into_response()),
+    }
+}
+
+/// Token embeddings of the query and the documents in one batch, then MaxSim of every document
+/// on a blocking thread: it costs query tokens x document tokens x hidden size per document
+async fn late_interaction(
+    app_state: &AppState,
+    req: LateInteractionRequest,
+) -> Result<(RerankResponse, ResponseMetadata)> {
+    let start_time = Instant::now();
+    if req.texts.is_empty() {
+        return Err(Error::EmptyInput("`texts` cannot be empty".to_string()));
+    }
+    let served = app_state.models.served();
+    // The query is embedded in the same batch as the documents
+    let max_client_batch_size = app_state.max_client_batch_size(served.infer.limits());
+    if req.texts.len() + 1 > max_client_batch_size {
+        return Err(Error::BatchTooLarge(format!(
+            "{} texts and the query > maximum allowed batch size {max_client_batch_size}",
+            req.texts.len()
+        )));
+    }
+    let truncate = req.truncate.unwrap_or(served.info.auto_truncate);
+    let inputs: Vec<InputType> = std::iter::once(&req.query)
+        .chain(&req.texts)
+        .map(|text| InputType::String(text.clone()))
+        .collect();
+    let compute_chars = inputs.iter().map(|input| input.count_chars()).sum();
+
+    let results = embed_all_batch(
+        &served.infer,
+        inputs,
+        truncate,
+        req.truncation_direction,
+        None,
+    )
+    .await?;
+
+    let batch_size = results.len() as u64;
+    let mut matrices = Vec::with_capacity(results.len());
+    let mut total_tokenization_time = 0;
+    let mut total_queue_time = 0;
+    let mut total_inference_time = 0;
+    let mut total_compute_tokens = 0;
+    for r in results {
+        total_tokenization_time += r.metadata.tokenization.as_nanos() as u64;
+        total_queue_time += r.metadata.queue.as_nanos() as u64;
+        total_inference_time += r.metadata.inference.as_nanos() as u64;
+        total_compute_tokens += r.metadata.prompt_tokens;
+        matrices.push(r.results);
+    }
+
+    let top_n = req.top_n;
+    let ranked = tokio::task::spawn_blocking(move || {
+        matrices.iter_mut().for_each(|tokens| normalize(tokens));
+        let (query, documents) = matrices.split_first().expect("the query is embedded");
+        rank(query, documents, top_n)
+    })
+    .await
+    .map_err(|err| Error::Custom(format!("Late-interaction scoring failed: {err}")))?;
+
+    let ranks = ranked
+        .into_iter()
+        .map(|(index, score)| Rank {
+            index,
+            text: req.return_text.then(|| req.texts[index].clone()),
+            score,
+        })
+        .collect();
+    Ok((
+        RerankResponse(ranks),
+        ResponseMetadata::new(
+            compute_chars,
+            total_compute_tokens,
+            start_time,
+            Duration::from_nanos(total_tokenization_time / batch_size),
+            Duration::from_nanos(total_queue_time / batch_size),
+            Duration::from_nanos(total_inference_time / batch_size),
+        ),
+    ))
+}
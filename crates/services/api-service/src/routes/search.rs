@@ -0,0 +1,461 @@
+//! Semantic search over the ingested file chunks. The query is embedded with the loaded model and
+//! matched against the stored chunk embeddings of the caller's tenant, optionally diversified
+//! with MMR, boosted by recency and filtered on the chunk metadata. The options of a search can be
+//! saved by name as a search template, managed by admins and run by every user of the tenant.
+
+use crate::cache::AppState;
+use crate::error::{Error, Result};
+use crate::log::sampling::SampleRecord;
+use crate::middleware::mw_auth::Ctm;
+use crate::routes::embed::embed;
+use crate::types::{EmbedInputType, EmbedRequest};
+use axum::{
+    Router,
+    extract::{Extension, Path},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+};
+use chrono::Utc;
+use lib_core::ctx::Ctx;
+use lib_core::model::file_chunks::{ChunkMetadata, FileChunkMac};
+use lib_core::model::files::FileMac;
+use lib_core::model::search_templates::{SearchTemplateForSave, SearchTemplateMac};
+use lib_core::model::user::Role;
+use lib_core::vector_index::{MmrParams, RecencyParams, cosine_similarity};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 100;
+/// Candidates re-ranked per result when `fetch_k` is not given
+const DEFAULT_FETCH_FACTOR: i64 = 4;
+
+/// Longest name of a search template
+const MAX_TEMPLATE_NAME: usize = 64;
+
+pub fn serve_search() -> Router {
+    Router::new()
+        .route("/search", post(search))
+        .route("/search/templates", get(list_templates).post(save_template))
+        .route(
+            "/search/templates/{name}",
+            get(get_template).delete(delete_template),
+        )
+        .route("/search/templates/{name}/run", post(run_template))
+}
+
+#[derive(Deserialize)]
+struct SearchRequest {
+    query: String,
+    #[serde(flatten)]
+    options: SearchOptions,
+}
+
+/// Options of a search, the `params` of a search template
+#[derive(Serialize, Deserialize, Clone)]
+struct SearchOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    limit: Option<i64>,
+    /// Instruction of instruct-style models, see `EmbedRequest::instruction`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    instruction: Option<String>,
+    /// `query` applies the query prompt of asymmetric models, see `EmbedRequest::input_type`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    input_type: Option<EmbedInputType>,
+    /// Only return chunks whose metadata contains this object, e.g. `{"language": "eng"}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    filter: Option<serde_json::Value>,
+    /// Only return chunks linking to this URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    link: Option<String>,
+    /// Only return chunks of the emails sent by this address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sender: Option<String>,
+    /// Re-rank the nearest chunks with Maximal Marginal Relevance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mmr: Option<MmrOptions>,
+    /// Blend the similarity with the recency of the file of each chunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    recency: Option<RecencyOptions>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct MmrOptions {
+    #[serde(default = "default_lambda")]
+    lambda: f32,
+    #[serde(default)]
+    fetch_k: Option<i64>,
+}
+
+fn default_lambda() -> f32 {
+    0.5
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct RecencyOptions {
+    #[serde(default = "default_half_life_days")]
+    half_life_days: f64,
+    #[serde(default = "default_recency_weight")]
+    weight: f32,
+}
+
+fn default_half_life_days() -> f64 {
+    30.0
+}
+
+fn default_recency_weight() -> f32 {
+    0.3
+}
+
+#[derive(Serialize)]
+struct SearchHit {
+    chunk_id: i64,
+    file_id: i64,
+    chunk_index: i32,
+    content_md: Option<String>,
+    /// Page, heading path, source and language used to cite the chunk
+    metadata: ChunkMetadata,
+    /// Cosine similarity to the query
+    score: f32,
+    /// Similarity blended with the recency of the file, the order of the hits with `recency`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ranking_score: Option<f32>,
+}
+
+/// Validated `SearchOptions`
+struct SearchPlan {
+    limit: i64,
+    /// Chunks fetched before the recency re-ranking, `limit` without it
+    candidates: i64,
+    instruction: Option<String>,
+    input_type: Option<EmbedInputType>,
+    filter: Option<serde_json::Value>,
+    mmr: Option<MmrParams>,
+    recency: Option<RecencyParams>,
+}
+
+impl SearchOptions {
+    fn plan(self) -> Result<SearchPlan> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(Error::BadRequest(format!(
+                "`limit` must be between 1 and {MAX_LIMIT}"
+            )));
+        }
+        let filter = metadata_filter(self.filter, self.link, self.sender)?;
+        let recency = match self.recency {
+            Some(recency) if recency.half_life_days <= 0.0 => {
+                return Err(Error::BadRequest(
+                    "`recency.half_life_days` must be positive".to_string(),
+                ));
+            }
+            Some(recency) if !(0.0..=1.0).contains(&recency.weight) => {
+                return Err(Error::BadRequest(
+                    "`recency.weight` must be between 0 and 1".to_string(),
+                ));
+            }
+            Some(recency) => Some(RecencyParams {
+                half_life_days: recency.half_life_days,
+                weight: recency.weight,
+            }),
+            None => None,
+        };
+        // Recency re-ranks more candidates than it returns, a fresh chunk can come from further
+        // away
+        let candidates = match recency {
+            Some(_) => limit * DEFAULT_FETCH_FACTOR,
+            None => limit,
+        };
+        let mmr = match self.mmr {
+            Some(mmr) if !(0.0..=1.0).contains(&mmr.lambda) => {
+                return Err(Error::BadRequest(
+                    "`mmr.lambda` must be between 0 and 1".to_string(),
+                ));
+            }
+            Some(mmr) => Some(MmrParams {
+                lambda: mmr.lambda,
+                fetch_k: mmr
+                    .fetch_k
+                    .unwrap_or(candidates * DEFAULT_FETCH_FACTOR)
+                    .clamp(candidates, MAX_LIMIT * DEFAULT_FETCH_FACTOR),
+            }),
+            None => None,
+        };
+        Ok(SearchPlan {
+            limit,
+            candidates,
+            instruction: self.instruction,
+            input_type: self.input_type,
+            filter,
+            mmr,
+            recency,
+        })
+    }
+}
+
+async fn search(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Json(req): Json<SearchRequest>,
+) -> Result<Response> {
+    let plan = req.options.plan()?;
+    run_search(&app_state, &ctx, "/search", req.query, plan).await
+}
+
+async fn run_search(
+    app_state: &AppState,
+    ctx: &Ctx,
+    route: &'static str,
+    text: String,
+    plan: SearchPlan,
+) -> Result<Response> {
+    let tenant_id = ctx.tenant_id();
+    let user_id = ctx.user_id();
+    let SearchPlan {
+        limit,
+        candidates,
+        instruction,
+        input_type,
+        filter,
+        mmr,
+        recency,
+    } = plan;
+
+    // The query is hashed before it is consumed, only for the sampled requests
+    let sample = app_state
+        .sampler
+        .as_ref()
+        .filter(|sampler| sampler.should_sample(&user_id))
+        .map(|sampler| (sampler, sampler.hash(text.as_bytes())));
+    let embed_req: EmbedRequest = serde_json::from_value(json!({
+        "inputs": text,
+        "instruction": instruction,
+        "input_type": input_type,
+    }))
+    .map_err(|e| Error::Custom(e.to_string()))?;
+    let (query, metadata) = match embed(app_state, embed_req).await {
+        Ok((mut response, metadata)) => {
+            metadata.record_metrics();
+            (response.0.remove(0), metadata)
+        }
+        Err(err) => {
+            let response = err.into_response();
+            if let Some((sampler, input_hash)) = sample {
+                let mut record = SampleRecord::new(route, response.status().as_u16(), 1);
+                record.input_hashes = Some(vec![input_hash]);
+                sampler.record(&user_id, record);
+            }
+            return Ok(response);
+        }
+    };
+
+    let filter = filter.as_ref();
+    // The query vector is only compared with the chunks embedded by the served model
+    let info = app_state.info();
+    let model = &info.model_id;
+    let chunks = match mmr {
+        Some(mmr) => {
+            FileChunkMac::search_chunks_mmr(
+                &app_state.mm,
+                &tenant_id,
+                query.clone(),
+                model,
+                candidates,
+                filter,
+                mmr,
+            )
+            .await?
+        }
+        None => {
+            FileChunkMac::search_chunks_by_embedding(
+                &app_state.mm,
+                &tenant_id,
+                query.clone(),
+                model,
+                candidates,
+                filter,
+            )
+            .await?
+        }
+    };
+    let mut hits: Vec<SearchHit> = chunks
+        .into_iter()
+        .map(|chunk| SearchHit {
+            score: chunk
+                .embedding
+                .as_ref()
+                .map(|embedding| cosine_similarity(&query, embedding.as_slice()))
+                .unwrap_or_default(),
+            ranking_score: None,
+            chunk_id: chunk.chunk_id,
+            file_id: chunk.file_id,
+            chunk_index: chunk.chunk_index,
+            content_md: chunk.content_md,
+            metadata: chunk.metadata,
+        })
+        .collect();
+    if let Some(recency) = recency {
+        let mut file_ids: Vec<i64> = hits.iter().map(|hit| hit.file_id).collect();
+        file_ids.sort_unstable();
+        file_ids.dedup();
+        let updated_at = FileMac::get_updated_at(&app_state.mm, &tenant_id, &file_ids).await?;
+        let now = Utc::now().naive_utc();
+        for hit in &mut hits {
+            let age_days = updated_at
+                .get(&hit.file_id)
+                .map(|updated_at| (now - *updated_at).num_seconds() as f64 / 86_400.0)
+                .unwrap_or_default();
+            hit.ranking_score = Some(recency.score(hit.score, age_days));
+        }
+        hits.sort_by(|a, b| {
+            let score = |hit: &SearchHit| hit.ranking_score.unwrap_or_default();
+            score(b).total_cmp(&score(a))
+        });
+        hits.truncate(limit as usize);
+    }
+
+    if let Some((sampler, input_hash)) = sample {
+        let mut record = SampleRecord::new(route, 200, 1).with_metadata(&metadata);
+        record.input_hashes = Some(vec![input_hash]);
+        record.result_ids = Some(hits.iter().map(|hit| hit.chunk_id.to_string()).collect());
+        sampler.record(&user_id, record);
+    }
+
+    Ok(Json(json!({
+        "status": 200,
+        "model": model,
+        "data": hits,
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+struct TemplateRunRequest {
+    query: String,
+}
+
+fn require_admin(ctx: &Ctx) -> Result<()> {
+    match ctx.role() {
+        Some(Role::Admin) => Ok(()),
+        _ => Err(Error::Forbidden(
+            "Search templates are managed by admins".to_string(),
+        )),
+    }
+}
+
+async fn list_templates(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+) -> Result<Response> {
+    let templates = SearchTemplateMac::list_templates(&app_state.mm, &ctx.tenant_id()).await?;
+    Ok(Json(json!({
+        "status": 200,
+        "data": templates,
+    }))
+    .into_response())
+}
+
+/// Create or replace a template, its `params` are validated like the options of `/search`
+async fn save_template(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Json(mut template): Json<SearchTemplateForSave>,
+) -> Result<Response> {
+    require_admin(&ctx)?;
+    let valid_name = template
+        .name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if template.name.is_empty() || template.name.len() > MAX_TEMPLATE_NAME || !valid_name {
+        return Err(Error::BadRequest(format!(
+            "`name` must be 1 to {MAX_TEMPLATE_NAME} letters, digits, `-` or `_`"
+        )));
+    }
+    let options: SearchOptions = serde_json::from_value(template.params)
+        .map_err(|e| Error::BadRequest(format!("Invalid `params`: {e}")))?;
+    options.clone().plan()?;
+    template.params = serde_json::to_value(options)?;
+
+    let template =
+        SearchTemplateMac::save_template(&app_state.mm, &ctx.tenant_id(), template).await?;
+    Ok(Json(json!({
+        "status": 200,
+        "data": template,
+    }))
+    .into_response())
+}
+
+async fn get_template(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(name): Path<String>,
+) -> Result<Response> {
+    let template = SearchTemplateMac::get_template(&app_state.mm, &ctx.tenant_id(), &name)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("Search template {name}")))?;
+    Ok(Json(json!({
+        "status": 200,
+        "data": template,
+    }))
+    .into_response())
+}
+
+async fn delete_template(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(name): Path<String>,
+) -> Result<Response> {
+    require_admin(&ctx)?;
+    let deleted =
+        SearchTemplateMac::delete_template(&app_state.mm, &ctx.tenant_id(), &name).await?;
+    if deleted == 0 {
+        return Err(Error::NotFound(format!("Search template {name}")));
+    }
+    Ok(Json(json!({
+        "status": 200,
+        "data": { "deleted": name },
+    }))
+    .into_response())
+}
+
+/// Search with the options of the template, the request only carries the query
+async fn run_template(
+    Extension(app_state): Extension<AppState>,
+    Ctm(ctx): Ctm,
+    Path(name): Path<String>,
+    Json(req): Json<TemplateRunRequest>,
+) -> Result<Response> {
+    let tenant_id = ctx.tenant_id();
+    let template = SearchTemplateMac::get_template(&app_state.mm, &tenant_id, &name)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("Search template {name}")))?;
+    let options: SearchOptions = serde_json::from_value(template.params)?;
+    run_search(
+        &app_state,
+        &ctx,
+        "/search/templates/{name}/run",
+        req.query,
+        options.plan()?,
+    )
+    .await
+}
+
+/// `filter` with the `link` and `sender` shortcuts added, matched by JSONB containment
+fn metadata_filter(
+    filter: Option<serde_json::Value>,
+    link: Option<String>,
+    sender: Option<String>,
+) -> Result<Option<serde_json::Value>> {
+    let mut filter = match filter {
+        Some(serde_json::Value::Object(filter)) => filter,
+        Some(_) => return Err(Error::BadRequest("`filter` must be an object".to_string())),
+        None => serde_json::Map::new(),
+    };
+    if let Some(link) = link {
+        filter.insert("links".to_string(), json!([link]));
+    }
+    if let Some(sender) = sender {
+        filter.insert("sender".to_string(), json!(sender.trim().to_lowercase()));
+    }
+    Ok((!filter.is_empty()).then_some(serde_json::Value::Object(filter)))
+}
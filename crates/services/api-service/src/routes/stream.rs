@@ -0,0 +1,164 @@
+//! Chunked transfer of large JSON array responses.
+//!
+//! A response grows with the batch size times the embedding dimension, and once serialized a
+//! float takes several times its 4 bytes. Above `--stream-response-threshold` the array is written
+//! one element at a time as a chunked body, so the full JSON text is never held in memory.
+
+use crate::types::{
+    EmbedAllResponse, EmbedOutput, EmbedResponse, EmbedSparseResponse, Embedding, SparseValue,
+};
+use axum::{
+    body::{Body, Bytes},
+    http::{HeaderMap, HeaderValue, header::CONTENT_TYPE},
+    response::{IntoResponse, Json, Response},
+};
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+
+/// Upper bound of a serialized `f32` with its separator, e.g. `-0.000012345678,`
+const FLOAT_BYTES: usize = 16;
+/// Upper bound of a serialized `i8` or `u8` with its separator, e.g. `-128,`
+const INT_BYTES: usize = 5;
+/// Upper bound of a serialized `SparseValue`, e.g. `{"index":250000,"value":-0.000012345678},`
+const SPARSE_VALUE_BYTES: usize = 48;
+
+/// Response serialized as a JSON array, whose size can be bounded before it is serialized
+pub(crate) trait JsonArray: Serialize {
+    type Item: Serialize + Send + 'static;
+
+    /// Upper bound of the serialized size in bytes
+    fn estimated_size(&self) -> usize;
+
+    /// Elements of the array in order, serialized one per chunk
+    fn into_items(self) -> impl Iterator<Item = Self::Item> + Send + 'static;
+}
+
+impl JsonArray for EmbedResponse {
+    type Item = Vec<f32>;
+
+    fn estimated_size(&self) -> usize {
+        self.0.len() * (2 + self.0.dim() * FLOAT_BYTES)
+    }
+
+    fn into_items(self) -> impl Iterator<Item = Self::Item> + Send + 'static {
+        self.0.into_rows()
+    }
+}
+
+impl JsonArray for EmbedOutput {
+    type Item = Embedding;
+
+    fn estimated_size(&self) -> usize {
+        match self {
+            EmbedOutput::Float32(response) => response.estimated_size(),
+            EmbedOutput::Quantized(rows) => rows.iter().map(embedding_size).sum(),
+        }
+    }
+
+    fn into_items(self) -> impl Iterator<Item = Self::Item> + Send + 'static {
+        let items: Box<dyn Iterator<Item = Embedding> + Send> = match self {
+            EmbedOutput::Float32(response) => Box::new(response.into_items().map(Embedding::Float)),
+            EmbedOutput::Quantized(rows) => Box::new(rows.into_iter()),
+        };
+        items
+    }
+}
+
+impl JsonArray for EmbedAllResponse {
+    type Item = Vec<Embedding>;
+
+    fn estimated_size(&self) -> usize {
+        self.0.iter().flatten().map(embedding_size).sum()
+    }
+
+    fn into_items(self) -> impl Iterator<Item = Self::Item> + Send + 'static {
+        self.0.into_iter()
+    }
+}
+
+impl JsonArray for EmbedSparseResponse {
+    type Item = Vec<SparseValue>;
+
+    fn estimated_size(&self) -> usize {
+        self.0
+            .iter()
+            .map(|e| 2 + e.len() * SPARSE_VALUE_BYTES)
+            .sum()
+    }
+
+    fn into_items(self) -> impl Iterator<Item = Self::Item> + Send + 'static {
+        self.0.into_iter()
+    }
+}
+
+/// Upper bound of a serialized `Embedding` with its separator
+fn embedding_size(embedding: &Embedding) -> usize {
+    match embedding {
+        Embedding::Float(values) => 2 + values.len() * FLOAT_BYTES,
+        Embedding::Base64(text) => 3 + text.len(),
+        Embedding::Int8(values) => 2 + values.len() * INT_BYTES,
+        Embedding::Binary(bytes) => 2 + bytes.len() * INT_BYTES,
+    }
+}
+
+/// `Json(value)` below `threshold` bytes, a chunked body serializing one element per chunk above
+pub(crate) fn json_array_response<T: JsonArray>(
+    headers: HeaderMap,
+    value: T,
+    threshold: usize,
+) -> Response {
+    if value.estimated_size() <= threshold {
+        return (headers, Json(value)).into_response();
+    }
+
+    let items = stream::iter(value.into_items().enumerate()).map(|(i, item)| {
+        let mut chunk = Vec::new();
+        if i > 0 {
+            chunk.push(b',');
+        }
+        serde_json::to_writer(&mut chunk, &item)?;
+        Ok::<_, serde_json::Error>(Bytes::from(chunk))
+    });
+    let body = stream::once(async { Ok(Bytes::from_static(b"[")) })
+        .chain(items)
+        .chain(stream::once(async { Ok(Bytes::from_static(b"]")) }));
+
+    let mut response = (headers, Body::from_stream(body)).into_response();
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::EmbeddingMatrix;
+
+    async fn body_of(response: Response) -> Vec<u8> {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_matches_json() {
+        let embeddings = vec![vec![0.5, -1.25, 3.0], vec![0.0; 3], vec![1e-7, 2.0, 0.0]];
+        let expected = serde_json::to_vec(&embeddings).unwrap();
+        let matrix = EmbeddingMatrix::try_from(embeddings).unwrap();
+
+        let streamed = json_array_response(HeaderMap::new(), EmbedResponse(matrix.clone()), 0);
+        assert!(streamed.headers().get("content-length").is_none());
+        assert_eq!(body_of(streamed).await, expected);
+
+        let buffered = json_array_response(HeaderMap::new(), EmbedResponse(matrix), usize::MAX);
+        assert_eq!(body_of(buffered).await, expected);
+
+        let empty = EmbedResponse(EmbeddingMatrix::default());
+        let empty = json_array_response(HeaderMap::new(), empty, 0);
+        assert_eq!(body_of(empty).await, b"[]");
+    }
+}
+// endregion: Unit Test
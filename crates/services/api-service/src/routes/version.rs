@@ -0,0 +1,55 @@
+use crate::ai::Info;
+use crate::cache::AppState;
+use crate::types::VersionResponse;
+use axum::{Router, extract::Extension, response::Json, routing::get};
+use chrono::DateTime;
+
+pub fn serve_version() -> Router {
+    Router::new()
+        .route("/version", get(get_version))
+        .route("/info", get(get_info))
+}
+
+/// Model, router parameters and loaded Dense adapters (with their weights digest)
+#[utoipa::path(
+get,
+tag = "Text Embeddings Inference",
+path = "/info",
+responses(
+(status = 200, description = "Served model info"),
+)
+)]
+async fn get_info(Extension(app_state): Extension<AppState>) -> Json<Info> {
+    Json(app_state.info().as_ref().clone())
+}
+
+#[utoipa::path(
+get,
+tag = "Text Embeddings Inference",
+path = "/version",
+responses(
+(status = 200, description = "Build information", body = VersionResponse),
+)
+)]
+async fn get_version(Extension(app_state): Extension<AppState>) -> Json<VersionResponse> {
+    Json(version_response(&app_state.info()))
+}
+
+pub(crate) fn version_response(info: &Info) -> VersionResponse {
+    // `BUILD_TIMESTAMP` is a unix timestamp set by the build script
+    let build_timestamp = info
+        .build_timestamp
+        .and_then(|ts| ts.parse::<i64>().ok())
+        .and_then(|ts| DateTime::from_timestamp(ts, 0))
+        .map(|ts| ts.to_rfc3339());
+
+    VersionResponse {
+        version: info.version,
+        git_sha: info.sha,
+        build_timestamp,
+        features: info.features.clone(),
+        backend: info.backend_kind.to_string(),
+        docker_label: info.docker_label,
+        model_id: info.model_id.clone(),
+    }
+}
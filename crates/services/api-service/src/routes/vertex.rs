@@ -0,0 +1,96 @@
+//! Google Vertex AI custom container prediction contract.
+//!
+//! Vertex sends `{"instances": [...]}` to `AIP_PREDICT_ROUTE` and expects `{"predictions": [...]}`
+//! back, one prediction per instance. Every instance is an `EmbedRequest`. The health probe is
+//! served on `AIP_HEALTH_ROUTE`.
+
+use crate::cache::AppState;
+use crate::error::{Error, Result};
+use crate::routes::embed::embed_output;
+use crate::types::{EmbedRequest, ErrorResponse, VertexPrediction, VertexRequest, VertexResponse};
+use axum::{
+    Router,
+    extract::Extension,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+};
+use serde_json::json;
+use tracing::instrument;
+
+const DEFAULT_PREDICT_ROUTE: &str = "/vertex";
+const DEFAULT_HEALTH_ROUTE: &str = "/vertex/health";
+
+pub fn serve_vertex() -> Router {
+    let mut router = Router::new().route(DEFAULT_PREDICT_ROUTE, post(run_vertex));
+
+    // Vertex picks its own predict route, keep `/vertex` reachable as well
+    if let Ok(predict_route) = std::env::var("AIP_PREDICT_ROUTE") {
+        if predict_route != DEFAULT_PREDICT_ROUTE {
+            router = router.route(&predict_route, post(run_vertex));
+        }
+    }
+    router
+}
+
+/// The health probe is mounted outside the authentication layers, Vertex sends no credentials
+pub fn serve_vertex_health() -> Router {
+    let health_route =
+        std::env::var("AIP_HEALTH_ROUTE").unwrap_or_else(|_| DEFAULT_HEALTH_ROUTE.to_string());
+    Router::new().route(&health_route, get(vertex_health))
+}
+
+#[utoipa::path(
+post,
+tag = "Text Embeddings Inference",
+path = "/vertex",
+request_body = VertexRequest,
+responses(
+(status = 200, description = "Results"),
+(status = 400, description = "Invalid instance", body = ErrorResponse,
+example = json ! ({"error": "Invalid instance", "error_type": "validation"})),
+(status = 429, description = "Queue is full", body = ErrorResponse,
+example = json ! ({"error": "Queue is full. Please retry.", "error_type": "queue_full"})),
+)
+)]
+#[instrument(skip_all, fields(instances))]
+async fn run_vertex(
+    Extension(app_state): Extension<AppState>,
+    Json(req): Json<VertexRequest>,
+) -> Result<Response> {
+    tracing::Span::current().record("instances", req.instances.len());
+
+    let mut requests = Vec::with_capacity(req.instances.len());
+    for (index, instance) in req.instances.into_iter().enumerate() {
+        match serde_json::from_value::<EmbedRequest>(instance) {
+            Ok(request) => requests.push(request),
+            Err(err) => {
+                return Err(Error::BadRequest(format!(
+                    "invalid instance {index}: {err}"
+                )));
+            }
+        }
+    }
+
+    let mut predictions = Vec::with_capacity(requests.len());
+    for request in requests {
+        match embed_output(&app_state, request).await {
+            Ok((response, metadata)) => {
+                metadata.record_metrics();
+                predictions.push(VertexPrediction::Embed(response));
+            }
+            Err(err) => return Ok(err.into_response()),
+        }
+    }
+
+    Ok(Json(VertexResponse { predictions }).into_response())
+}
+
+/// Vertex only routes traffic to the container once this returns 200
+async fn vertex_health(Extension(app_state): Extension<AppState>) -> StatusCode {
+    if app_state.infer().health().await {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
@@ -0,0 +1,159 @@
+//! Streaming embeddings over a WebSocket for interactive clients, e.g. a search box embedding its
+//! query at every keystroke: one text per message, replies sent as soon as each embedding is done.
+//!
+//! Every message takes a permit of the inference queue before the next one is read, so a full
+//! queue stops reading the socket and the client is slowed down by TCP flow control instead of
+//! receiving `queue_full` errors. A connection has at most `max_client_batch_size` texts in flight.
+
+use crate::ai::catalog::ServedModel;
+use crate::cache::AppState;
+use crate::error::{Error, Result};
+use crate::types::{WsEmbedReply, WsEmbedRequest};
+use axum::{
+    Router,
+    extract::{
+        Extension,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::Response,
+    routing::get,
+};
+use futures::stream::SplitSink;
+use futures::{SinkExt, StreamExt};
+use std::sync::Arc;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::task::JoinSet;
+
+pub fn serve_ws() -> Router {
+    Router::new().route("/ws/embed", get(ws_embed))
+}
+
+async fn ws_embed(Extension(app_state): Extension<AppState>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| embed_socket(app_state, socket))
+}
+
+async fn embed_socket(app_state: AppState, socket: WebSocket) {
+    metrics::gauge!("te_websocket_connections").increment(1.0);
+    let (mut sender, mut receiver) = socket.split();
+    let mut in_flight: JoinSet<WsEmbedReply> = JoinSet::new();
+
+    loop {
+        let max_in_flight = app_state.max_client_batch_size(app_state.infer().limits());
+        tokio::select! {
+            Some(done) = in_flight.join_next() => {
+                let reply = done.unwrap_or_else(|err| {
+                    let err = Error::Custom(format!("Embedding task failed: {err}"));
+                    WsEmbedReply::error(None, err)
+                });
+                if send_reply(&mut sender, &reply).await.is_err() {
+                    break;
+                }
+            }
+            message = receiver.next(), if in_flight.len() < max_in_flight => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Binary(_))) => {
+                        let err = Error::BadRequest("Messages must be JSON text".to_string());
+                        let reply = WsEmbedReply::error(None, err);
+                        if send_reply(&mut sender, &reply).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    // Pings are answered by axum
+                    Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                };
+                let req = match serde_json::from_str::<WsEmbedRequest>(text.as_str()) {
+                    Ok(req) => req,
+                    Err(err) => {
+                        let err = Error::BadRequest(format!("Invalid message: {err}"));
+                        let reply = WsEmbedReply::error(None, err);
+                        if send_reply(&mut sender, &reply).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                // Infer and info of the same model, even when it is switched in the meantime
+                let served = app_state.models.served();
+                let permit = served.infer.acquire_permit().await;
+                in_flight.spawn(embed_message(served, req, permit));
+            }
+            else => break,
+        }
+    }
+
+    // Dropping the set aborts the embeddings nobody will receive
+    drop(in_flight);
+    metrics::gauge!("te_websocket_connections").decrement(1.0);
+}
+
+async fn send_reply(
+    sender: &mut SplitSink<WebSocket, Message>,
+    reply: &WsEmbedReply,
+) -> Result<()> {
+    let text = serde_json::to_string(reply)
+        .map_err(|err| Error::Custom(format!("Could not serialize the reply: {err}")))?;
+    sender
+        .send(Message::Text(text.into()))
+        .await
+        .map_err(|err| Error::Custom(format!("Could not send the reply: {err}")))
+}
+
+async fn embed_message(
+    served: Arc<ServedModel>,
+    req: WsEmbedRequest,
+    permit: OwnedSemaphorePermit,
+) -> WsEmbedReply {
+    metrics::counter!("te_request_count", "method" => "websocket").increment(1);
+    let id = req.id.clone();
+    match embed_input(&served, req, permit).await {
+        Ok((embedding, prompt_tokens)) => {
+            metrics::counter!("te_request_success", "method" => "websocket").increment(1);
+            WsEmbedReply {
+                id,
+                embedding: Some(embedding),
+                prompt_tokens: Some(prompt_tokens),
+                error: None,
+            }
+        }
+        Err(err) => WsEmbedReply::error(id, err),
+    }
+}
+
+/// Embedding of the input with its number of tokens
+async fn embed_input(
+    served: &ServedModel,
+    req: WsEmbedRequest,
+    permit: OwnedSemaphorePermit,
+) -> Result<(Vec<f32>, usize)> {
+    let prompt_name = match req.input_type {
+        Some(_) if req.prompt_name.is_some() => {
+            return Err(Error::BadRequest(
+                "`input_type` cannot be combined with `prompt_name`".to_string(),
+            ));
+        }
+        Some(input_type) => Some(
+            served
+                .info
+                .input_type_prompts
+                .prompt_name(input_type)
+                .to_string(),
+        ),
+        None => req.prompt_name,
+    };
+    let response = served
+        .infer
+        .embed_pooled(
+            req.input,
+            req.truncate.unwrap_or(served.info.auto_truncate),
+            req.truncation_direction.into(),
+            prompt_name,
+            req.normalize,
+            req.dimensions,
+            permit,
+        )
+        .await?;
+    Ok((response.results, response.metadata.prompt_tokens))
+}
@@ -0,0 +1,62 @@
+//! Env variables referencing AWS Secrets Manager or SSM (`secretsmanager://...`, `ssm://...`) are
+//! replaced by their secret at startup, before any crate reads its settings. The `DATABASE_URL`
+//! secret is fetched again periodically to follow password rotations.
+
+use crate::error::{Error, Result};
+use lib_core::database::ModelManager;
+use lib_storage::secrets::{SecretRef, SecretResolver, env_references};
+use tokio::time::{Duration, interval};
+use tracing::{error, info};
+
+/// Resolve the secret references of the env, returns them by env variable.
+///
+/// # Safety
+/// No other thread may read or write the env meanwhile, call it before spawning any task.
+pub async unsafe fn resolve_env() -> Result<Vec<(String, SecretRef)>> {
+    let references = env_references();
+    if references.is_empty() {
+        return Ok(references);
+    }
+    let resolver = SecretResolver::new().await;
+    for (env, reference) in &references {
+        let secret = resolver
+            .resolve(reference)
+            .await
+            .map_err(|e| Error::Custom(format!("{env}: {e}")))?;
+        // SAFETY: single threaded access to the env, see the caller contract
+        unsafe { std::env::set_var(env, secret) };
+    }
+    Ok(references)
+}
+
+/// Fetch the `DATABASE_URL` secret every `period` and point the pool of `mm` to it when it
+/// changed. The other secrets are only read at startup, the crates load their settings once.
+pub fn spawn_db_url_refresh(mm: ModelManager, reference: SecretRef, period: Duration) {
+    tokio::spawn(async move {
+        let resolver = SecretResolver::new().await;
+        let mut current = std::env::var("DATABASE_URL").ok();
+        let mut ticks = interval(period);
+        // The first tick completes immediately, the secret was just fetched
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            let db_url = match resolver.resolve(&reference).await {
+                Ok(db_url) => db_url,
+                Err(e) => {
+                    error!("Failed to refresh the DATABASE_URL secret: {e:?}");
+                    continue;
+                }
+            };
+            if current.as_deref() == Some(db_url.as_str()) {
+                continue;
+            }
+            match mm.set_db_url(&db_url) {
+                Ok(()) => {
+                    info!("DATABASE_URL secret rotated, new connections use it");
+                    current = Some(db_url);
+                }
+                Err(e) => error!("Rotated DATABASE_URL secret is invalid: {e:?}"),
+            }
+        }
+    });
+}
@@ -1,5 +1,8 @@
+use crate::ai::long_input::LongInputPooling;
+use crate::ai::output_dtype::OutputDtype;
 use crate::ai::tokenization::EncodingInput;
 use crate::error::Error;
+use lib_utils::base64::b64_encode;
 use serde::de::{SeqAccess, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, de};
 use serde_json::json;
@@ -275,6 +278,74 @@ pub(crate) struct Rank {
 #[derive(Serialize, ToSchema)]
 pub(crate) struct RerankResponse(pub Vec<Rank>);
 
+/// Candidate documents ranked by their late-interaction (MaxSim) score with the query, computed
+/// from the token embeddings of both on the server
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct LateInteractionRequest {
+    #[schema(example = "What is Deep Learning?")]
+    pub query: String,
+    #[schema(example = json!(["Deep Learning is ..."]))]
+    pub texts: Vec<String>,
+    #[serde(default)]
+    #[schema(default = "false", example = "false", nullable = true)]
+    pub truncate: Option<bool>,
+    #[serde(default)]
+    #[schema(default = "right", example = "right")]
+    pub truncation_direction: TruncationDirection,
+    /// Number of best documents returned, all of them when unset
+    #[schema(default = "null", example = "10", nullable = true)]
+    pub top_n: Option<usize>,
+    #[serde(default)]
+    #[schema(default = "false", example = "false")]
+    pub return_text: bool,
+}
+
+/// Text frame sent on `/ws/embed`, one input per message
+#[derive(Deserialize)]
+pub(crate) struct WsEmbedRequest {
+    /// Echoed in the reply, replies come in the order the embeddings complete
+    #[serde(default)]
+    pub id: Option<serde_json::Value>,
+    pub input: InputType,
+    #[serde(default)]
+    pub truncate: Option<bool>,
+    #[serde(default)]
+    pub truncation_direction: TruncationDirection,
+    #[serde(default)]
+    pub prompt_name: Option<String>,
+    /// `query` or `passage`, same as the `input_type` of `/embed`
+    #[serde(default)]
+    pub input_type: Option<EmbedInputType>,
+    #[serde(default = "default_normalize")]
+    pub normalize: bool,
+    #[serde(default)]
+    pub dimensions: Option<usize>,
+}
+
+/// Reply to a `WsEmbedRequest`: the embedding, or the `error` and `error_type` of the embed routes
+#[derive(Serialize)]
+pub(crate) struct WsEmbedReply {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<usize>,
+    #[serde(flatten)]
+    pub error: Option<ErrorResponse>,
+}
+
+impl WsEmbedReply {
+    pub(crate) fn error(id: Option<serde_json::Value>, err: Error) -> Self {
+        Self {
+            id,
+            embedding: None,
+            prompt_tokens: None,
+            error: Some(ErrorResponse::from(err)),
+        }
+    }
+}
+
 #[derive(Deserialize, ToSchema, Debug)]
 #[serde(untagged)]
 pub(crate) enum InputType {
@@ -307,12 +378,43 @@ pub(crate) enum Input {
     Batch(Vec<InputType>),
 }
 
-#[derive(Deserialize, ToSchema, Default)]
+/// `base64` is the little-endian `f32` bytes of the embedding, `base64_f16` the `f16` ones
+/// (not part of the OpenAI API) for half the payload
+#[derive(Deserialize, ToSchema, Default, Debug, Clone, Copy, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub(crate) enum EncodingFormat {
     #[default]
     Float,
     Base64,
+    Base64F16,
+}
+
+impl EncodingFormat {
+    /// `embedding` of one row of the response, the base64 text is written from the row directly
+    pub fn encode(self, row: &[f32]) -> Embedding {
+        match self {
+            EncodingFormat::Float => Embedding::Float(row.to_vec()),
+            EncodingFormat::Base64 => {
+                let bytes: Vec<u8> = row.iter().flat_map(|v| v.to_le_bytes()).collect();
+                Embedding::Base64(b64_encode(bytes))
+            }
+            EncodingFormat::Base64F16 => {
+                let bytes: Vec<u8> = row
+                    .iter()
+                    .flat_map(|v| half::f16::from_f32(*v).to_le_bytes())
+                    .collect();
+                Embedding::Base64(b64_encode(bytes))
+            }
+        }
+    }
+
+    /// `encode` without copying the row of the `float` format
+    pub fn encode_owned(self, row: Vec<f32>) -> Embedding {
+        match self {
+            EncodingFormat::Float => Embedding::Float(row),
+            format => format.encode(&row),
+        }
+    }
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -329,6 +431,10 @@ pub(crate) struct OpenAICompatRequest {
     pub encoding_format: EncodingFormat,
     #[schema(default = "null", example = "null", nullable = true)]
     pub dimensions: Option<usize>,
+    /// Quantization of the embeddings (not part of the OpenAI API), see `EmbedRequest`
+    #[serde(default)]
+    #[schema(default = "float32", example = "int8")]
+    pub output_dtype: OutputDtype,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -336,6 +442,9 @@ pub(crate) struct OpenAICompatRequest {
 pub(crate) enum Embedding {
     Float(Vec<f32>),
     Base64(String),
+    Int8(Vec<i8>),
+    /// Sign bits packed 8 per byte
+    Binary(Vec<u8>),
 }
 
 #[derive(Serialize, ToSchema)]
@@ -433,6 +542,24 @@ pub(crate) struct EmbedRequest {
     #[schema(default = "null", example = "null", nullable = true)]
     pub prompt_name: Option<String>,
 
+    /// Task instruction of instruct-style models such as Qwen3-Embedding. Queries are wrapped in
+    /// the template of the model family, e.g. `Instruct: {instruction}\nQuery:{input}`, documents
+    /// should be sent without it. Cannot be combined with `prompt_name`.
+    #[serde(default)]
+    #[schema(
+        default = "null",
+        example = "Given a web search query, retrieve relevant passages that answer the query",
+        nullable = true
+    )]
+    pub instruction: Option<String>,
+
+    /// `query` or `passage`, applies the prompt of the model for this side of the retrieval: the
+    /// `query`/`passage` (or `document`) prompts of the Sentence Transformers configuration, or the
+    /// `query: `/`passage: ` prefixes of the E5 models. Cannot be combined with `prompt_name`.
+    #[serde(default)]
+    #[schema(default = "null", example = "query", nullable = true)]
+    pub input_type: Option<EmbedInputType>,
+
     #[serde(default = "default_normalize")]
     #[schema(default = "true", example = "true")]
     pub normalize: bool,
@@ -441,6 +568,30 @@ pub(crate) struct EmbedRequest {
     /// shape of the representation will be returned instead.
     #[schema(default = "null", example = "null", nullable = true)]
     pub dimensions: Option<usize>,
+
+    /// Return the mean norm, min/max component and dimension of the embeddings as the
+    /// `x-embedding-*` headers, to catch a double normalization or a dimension mismatch early.
+    #[serde(default)]
+    #[schema(default = "false", example = "false")]
+    pub stats: bool,
+
+    /// Quantize the embeddings on the server: `float16`, `int8` with the `x-embedding-scale` and
+    /// `x-embedding-offset` headers to restore them, or `binary` sign bits packed 8 per byte.
+    #[serde(default)]
+    #[schema(default = "float32", example = "float32")]
+    pub output_dtype: OutputDtype,
+
+    /// Embed a single input longer than `max_input_length` instead of failing when it is not
+    /// truncated: the text is split into overlapping windows and their embeddings are pooled,
+    /// `mean` or `weighted` by their number of tokens.
+    #[serde(default)]
+    #[schema(default = "null", example = "mean", nullable = true)]
+    pub long_input: Option<LongInputPooling>,
+
+    /// Tokens shared by consecutive windows of `long_input`, an eighth of the window by default
+    #[serde(default)]
+    #[schema(default = "null", example = "64", nullable = true)]
+    pub window_overlap: Option<usize>,
 }
 
 fn default_normalize() -> bool {
@@ -449,7 +600,88 @@ fn default_normalize() -> bool {
 
 #[derive(Serialize, ToSchema)]
 #[schema(example = json!([[0.0, 1.0, 2.0]]))]
-pub(crate) struct EmbedResponse(pub Vec<Vec<f32>>);
+pub(crate) struct EmbedResponse(#[schema(value_type = Vec<Vec<f32>>)] pub EmbeddingMatrix);
+
+/// Embeddings of an `EmbedRequest` in its `output_dtype`
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub(crate) enum EmbedOutput {
+    Float32(EmbedResponse),
+    #[schema(example = json!([[-128, 0, 127]]))]
+    Quantized(Vec<Embedding>),
+}
+
+/// Pooled embeddings of a request in one row-major buffer. The rows are appended as the results
+/// come in and serialized straight from the buffer, without a `Vec` per embedding.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct EmbeddingMatrix {
+    rows: usize,
+    dim: usize,
+    values: Vec<f32>,
+}
+
+impl EmbeddingMatrix {
+    pub fn with_capacity(rows: usize, dim: usize) -> Self {
+        Self {
+            rows: 0,
+            dim,
+            values: Vec::with_capacity(rows * dim),
+        }
+    }
+
+    /// The first row sets the dimension, every row of a request has the same one
+    pub fn push_row(&mut self, row: &[f32]) -> Result<(), Error> {
+        if self.rows == 0 {
+            self.dim = row.len();
+        } else if row.len() != self.dim {
+            return Err(Error::Custom(format!(
+                "embedding of dimension {} in a response of dimension {}",
+                row.len(),
+                self.dim
+            )));
+        }
+        self.values.extend_from_slice(row);
+        self.rows += 1;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn rows(&self) -> impl ExactSizeIterator<Item = &[f32]> {
+        (0..self.rows).map(|i| &self.values[i * self.dim..(i + 1) * self.dim])
+    }
+
+    /// Owned rows, copied one at a time while they are consumed
+    pub fn into_rows(self) -> impl Iterator<Item = Vec<f32>> + Send + 'static {
+        let Self { rows, dim, values } = self;
+        (0..rows).map(move |i| values[i * dim..(i + 1) * dim].to_vec())
+    }
+}
+
+impl Serialize for EmbeddingMatrix {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.rows())
+    }
+}
+
+impl TryFrom<Vec<Vec<f32>>> for EmbeddingMatrix {
+    type Error = Error;
+
+    fn try_from(embeddings: Vec<Vec<f32>>) -> Result<Self, Error> {
+        let dim = embeddings.first().map_or(0, Vec::len);
+        let mut matrix = Self::with_capacity(embeddings.len(), dim);
+        for row in &embeddings {
+            matrix.push_row(row)?;
+        }
+        Ok(matrix)
+    }
+}
 
 #[derive(Deserialize, ToSchema)]
 pub(crate) struct EmbedSparseRequest {
@@ -471,6 +703,28 @@ pub(crate) struct EmbedSparseRequest {
     /// any text to encode.
     #[schema(default = "null", example = "null", nullable = true)]
     pub prompt_name: Option<String>,
+    /// Encoder of the inputs. `query` uses the query encoder of the SPLADE model when it has one
+    /// (see `splade_query_encoder` on `/info`), the served model otherwise.
+    #[serde(default)]
+    #[schema(default = "document", example = "query")]
+    pub input_type: SparseInputType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ToSchema, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SparseInputType {
+    Query,
+    #[default]
+    Document,
+}
+
+/// Side of an asymmetric retrieval model the inputs are embedded for, see `InputTypePrompts`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EmbedInputType {
+    Query,
+    #[serde(alias = "document")]
+    Passage,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -502,18 +756,35 @@ pub(crate) struct EmbedAllRequest {
     /// any text to encode.
     #[schema(default = "null", example = "null", nullable = true)]
     pub prompt_name: Option<String>,
+    /// `base64` or `base64_f16` encode every token vector like `/embeddings`, `base64_f16` halves
+    /// the payload of the hidden states
+    #[schema(default = "float", example = "float")]
+    #[serde(default)]
+    pub encoding_format: EncodingFormat,
 }
 
+/// Hidden state of every token of every input, special tokens and prompt included
 #[derive(Serialize, ToSchema)]
 #[schema(example = json!([[[0.0, 1.0, 2.0]]]))]
-pub(crate) struct EmbedAllResponse(pub Vec<Vec<Vec<f32>>>);
+pub(crate) struct EmbedAllResponse(pub Vec<Vec<Embedding>>);
 
+/// Error body of `/embeddings` in the shape of the OpenAI API, see `mw_openai_errors`
 #[derive(Serialize, ToSchema)]
 pub(crate) struct OpenAICompatErrorResponse {
+    pub error: OpenAICompatError,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct OpenAICompatError {
     pub message: String,
-    pub code: u16,
+    /// Category of the OpenAI API, e.g. `invalid_request_error` or `rate_limit_error`
     #[serde(rename(serialize = "type"))]
-    pub error_type: Error,
+    #[schema(value_type = String, example = "rate_limit_error")]
+    pub error_type: &'static str,
+    /// `error_type` of the other routes, `null` when the request was rejected before reaching the
+    /// route, e.g. for a malformed body
+    #[schema(value_type = Option<String>, example = "queue_full")]
+    pub code: Option<ErrorType>,
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -564,6 +835,35 @@ pub(crate) struct SimpleToken {
 #[schema(example = json!([[{"id": 0, "text": "test", "special": false, "start": 0, "stop": 2}]]))]
 pub(crate) struct TokenizeResponse(pub Vec<Vec<SimpleToken>>);
 
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct CountTokensRequest {
+    pub inputs: TokenizeInput,
+    /// The name of the prompt applied when embedding, its tokens count against `max_input_length`
+    #[serde(default)]
+    #[schema(default = "null", example = "null", nullable = true)]
+    pub prompt_name: Option<String>,
+}
+
+/// Tokens of an input once embedded, with the special tokens and the prompt
+#[derive(Serialize, ToSchema)]
+pub(crate) struct TokenCount {
+    #[schema(example = 12)]
+    pub tokens: usize,
+    /// The input exceeds `max_input_length`, it is truncated, or rejected with `truncate: false`
+    #[schema(example = false)]
+    pub truncated: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct CountTokensResponse {
+    /// Token counts in the order of the inputs
+    pub counts: Vec<TokenCount>,
+    #[schema(example = 12)]
+    pub total_tokens: usize,
+    #[schema(example = 512)]
+    pub max_input_length: usize,
+}
+
 #[derive(Deserialize, ToSchema)]
 #[serde(untagged)]
 pub(crate) enum InputIds {
@@ -595,7 +895,7 @@ pub(crate) struct VertexRequest {
 #[derive(Serialize, ToSchema)]
 #[serde(untagged)]
 pub(crate) enum VertexPrediction {
-    Embed(EmbedResponse),
+    Embed(EmbedOutput),
     EmbedSparse(EmbedSparseResponse),
     Predict(PredictResponse),
     Rerank(RerankResponse),
@@ -606,15 +906,94 @@ pub(crate) struct VertexResponse {
     pub predictions: Vec<VertexPrediction>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
+pub(crate) struct VersionResponse {
+    #[schema(example = "0.1.0")]
+    pub version: &'static str,
+    #[schema(nullable = true, example = "fca14538aa9956a46526bd1d0d11d69e19b5a101")]
+    pub git_sha: Option<&'static str>,
+    #[schema(nullable = true, example = "2026-10-17T08:00:00+00:00")]
+    pub build_timestamp: Option<String>,
+    #[schema(example = json!(["candle", "cuda"]))]
+    pub features: Vec<&'static str>,
+    #[schema(example = "candle")]
+    pub backend: String,
+    #[schema(nullable = true, example = "null")]
+    pub docker_label: Option<&'static str>,
+    #[schema(example = "Qwen/Qwen3-Embedding-0.6B")]
+    pub model_id: String,
+}
+
+/// Stable `error_type` values, clients branch their retry logic on them
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "http", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
 pub enum ErrorType {
+    /// `503`, the backend is down
     Unhealthy,
+    /// `424`, the model failed to run the batch of the request
     Backend,
+    /// `429`, every permit of the inference queue is taken
     Overloaded,
+    /// `400` for malformed parameters, `413` for inputs over the model limits, `422` for
+    /// embeddings of the wrong dimension
     Validation,
+    /// `422`, the input could not be tokenized
     Tokenizer,
+    /// `400`, no input was sent
     Empty,
+    /// `429`, the inference queue is full
+    QueueFull,
+    /// `429`, the caller exceeded the requests per second of its rate limit tier, see `Retry-After`
+    RateLimited,
+    /// `413`, more inputs than `max_client_batch_size`
+    BatchTooLarge,
+    /// `503`, the route was turned off by an operator
+    Disabled,
+    /// `503`, the request was rejected by a queue flush
+    Flushed,
+    /// `504` when the response was not ready by the deadline of the route, `408` when the client
+    /// had not sent the request body yet
+    Timeout,
+    /// `401`, missing or invalid credentials
+    Unauthorized,
+    /// `403`
+    Forbidden,
+    /// `404`
+    NotFound,
+    /// `409`
+    Conflict,
+    /// `500`, a failure unrelated to the request
+    Internal,
+}
+
+impl From<&Error> for ErrorType {
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::QueueFull => ErrorType::QueueFull,
+            Error::Overloaded => ErrorType::Overloaded,
+            Error::RateLimited(_) => ErrorType::RateLimited,
+            Error::BadRequest(_) | Error::InputTooLong(_) | Error::DimensionMismatch(_) => {
+                ErrorType::Validation
+            }
+            Error::EmptyInput(_) => ErrorType::Empty,
+            Error::Tokenizer(_) => ErrorType::Tokenizer,
+            Error::BatchTooLarge(_) => ErrorType::BatchTooLarge,
+            Error::Backend(_) => ErrorType::Backend,
+            Error::BackendUnhealthy(_) => ErrorType::Unhealthy,
+            Error::RouteDisabled(_) => ErrorType::Disabled,
+            Error::QueueFlushed => ErrorType::Flushed,
+            Error::Timeout(_) | Error::RequestTimeout(_) => ErrorType::Timeout,
+            Error::UnableToExtractKey
+            | Error::InvalidTokenFromCtx
+            | Error::FailToB64uDecode
+            | Error::AuthenticationFails(_) => ErrorType::Unauthorized,
+            Error::Forbidden(_) => ErrorType::Forbidden,
+            Error::NotFound(_) => ErrorType::NotFound,
+            Error::Conflict(_) => ErrorType::Conflict,
+            _ => ErrorType::Internal,
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -624,6 +1003,39 @@ pub struct ErrorResponse {
     pub error_type: ErrorType,
 }
 
+impl From<Error> for ErrorResponse {
+    fn from(err: Error) -> Self {
+        let error_type = ErrorType::from(&err);
+        let error = match err {
+            Error::QueueFull => "Queue is full. Please retry.".to_string(),
+            Error::Overloaded => "Model is overloaded. Please retry.".to_string(),
+            Error::QueueFlushed => "Queue was flushed by an operator. Please retry.".to_string(),
+            Error::UnableToExtractKey | Error::InvalidTokenFromCtx | Error::FailToB64uDecode => {
+                "Missing or invalid API key".to_string()
+            }
+            Error::BadRequest(msg)
+            | Error::EmptyInput(msg)
+            | Error::InputTooLong(msg)
+            | Error::Tokenizer(msg)
+            | Error::Backend(msg)
+            | Error::RateLimited(msg)
+            | Error::BatchTooLarge(msg)
+            | Error::BackendUnhealthy(msg)
+            | Error::RouteDisabled(msg)
+            | Error::Timeout(msg)
+            | Error::RequestTimeout(msg)
+            | Error::AuthenticationFails(msg)
+            | Error::Forbidden(msg)
+            | Error::NotFound(msg)
+            | Error::Conflict(msg)
+            | Error::DimensionMismatch(msg)
+            | Error::Custom(msg) => msg,
+            err => err.to_string(),
+        };
+        ErrorResponse { error, error_type }
+    }
+}
+
 impl PartialSchema for ErrorResponse {
     fn schema() -> RefOr<Schema> {
         utoipa::openapi::ObjectBuilder::new()
@@ -655,3 +1067,103 @@ impl ToSchema for ErrorResponse {
         // No need to push anything to schemas here unless you want to register custom schemas
     }
 }
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib_utils::base64::b64u_decode;
+
+    #[test]
+    fn test_embedding_matrix() {
+        let mut matrix = EmbeddingMatrix::with_capacity(2, 2);
+        matrix.push_row(&[1.0, -2.0]).unwrap();
+        matrix.push_row(&[0.5, 0.0]).unwrap();
+        assert!(matrix.push_row(&[1.0]).is_err());
+        assert_eq!(matrix.len(), 2);
+        assert_eq!(
+            serde_json::to_string(&matrix).unwrap(),
+            "[[1.0,-2.0],[0.5,0.0]]"
+        );
+        assert_eq!(
+            matrix.into_rows().collect::<Vec<_>>(),
+            vec![vec![1.0, -2.0], vec![0.5, 0.0]]
+        );
+    }
+
+    #[test]
+    fn test_encoding_format() {
+        let Embedding::Base64(encoded) = EncodingFormat::Base64.encode(&[1.0, -2.0]) else {
+            panic!("expected base64");
+        };
+        // Both alphabets agree on this payload, the standard one only adds padding
+        let bytes = b64u_decode(encoded.trim_end_matches('=')).unwrap();
+        assert_eq!(
+            bytes,
+            [1.0f32.to_le_bytes(), (-2.0f32).to_le_bytes()].concat()
+        );
+
+        let Embedding::Base64(encoded) = EncodingFormat::Base64F16.encode(&[1.0]) else {
+            panic!("expected base64");
+        };
+        assert_eq!(encoded, "ADw=");
+        assert!(matches!(
+            EncodingFormat::Float.encode_owned(vec![1.0]),
+            Embedding::Float(row) if row == [1.0]
+        ));
+    }
+
+    #[test]
+    fn test_error_response() {
+        use axum::http::StatusCode;
+
+        let cases = [
+            (
+                Error::EmptyInput("`inputs` cannot be empty".to_string()),
+                StatusCode::BAD_REQUEST,
+                ErrorType::Empty,
+            ),
+            (
+                Error::InputTooLong("too long".to_string()),
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ErrorType::Validation,
+            ),
+            (
+                Error::Tokenizer("bad input".to_string()),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ErrorType::Tokenizer,
+            ),
+            (
+                Error::Overloaded,
+                StatusCode::TOO_MANY_REQUESTS,
+                ErrorType::Overloaded,
+            ),
+            (
+                Error::Backend("CUDA out of memory".to_string()),
+                StatusCode::FAILED_DEPENDENCY,
+                ErrorType::Backend,
+            ),
+            (
+                Error::UnableToExtractKey,
+                StatusCode::UNAUTHORIZED,
+                ErrorType::Unauthorized,
+            ),
+            (
+                Error::Custom("database down".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorType::Internal,
+            ),
+        ];
+        for (err, status, error_type) in cases {
+            assert_eq!(err.status(), status);
+            assert_eq!(ErrorType::from(&err), error_type);
+        }
+        let response = ErrorResponse::from(Error::NotFound("File 42".to_string()));
+        assert_eq!(response.error, "File 42");
+        assert_eq!(
+            serde_json::to_value(response.error_type).unwrap(),
+            json!("not_found")
+        );
+    }
+}
+// endregion: Unit Test
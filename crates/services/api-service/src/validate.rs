@@ -0,0 +1,138 @@
+//! Startup validation of the settings of every crate. The crates load their settings lazily and
+//! some fall back to defaults when one is missing, which surfaces later as confusing AWS or
+//! database failures. Every problem is collected and reported at once before serving.
+
+use crate::config_file::missing_keys;
+use crate::error::{Error, Result};
+use lib_core::database::new_db_pool;
+use lib_storage::config::{Encryption, StorageBackend};
+use lib_storage::store::create_object_store;
+use lib_utils::envs::get_env;
+use std::future::Future;
+use tokio::time::{Duration, timeout};
+use tracing::error;
+
+/// Longest wait for the database, a bucket or a parser to answer
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Check the required settings and their format, then unless `skip_connectivity` that the
+/// database, the buckets and the parser endpoints answer
+pub async fn validate_config(skip_connectivity: bool) -> Result<()> {
+    let mut problems: Vec<String> = missing_keys(|env| std::env::var(env).ok())
+        .into_iter()
+        .map(|key| format!("{key} is not set"))
+        .collect();
+
+    // Values the crates fail to parse, the missing ones are reported above
+    let cron_config = match lib_cron::config::AuthConfig::load_from_env() {
+        Ok(config) => Some(config),
+        Err(lib_utils::error::Error::MissingEnv(_)) => None,
+        Err(e) => {
+            problems.push(describe(e));
+            None
+        }
+    };
+    if let Err(e) = lib_storage::config::Config::load_from_env() {
+        problems.push(describe(e));
+    }
+    if let Err(e) = Encryption::load_from_env() {
+        problems.push(describe(e));
+    }
+    if let Err(e @ lib_utils::error::Error::WrongFormat(_)) =
+        get_env::<StorageBackend>("STORAGE_BACKEND")
+    {
+        problems.push(describe(e));
+    }
+
+    if !skip_connectivity {
+        let (database, storage, parsers) = tokio::join!(check_database(), check_buckets(), async {
+            match &cron_config {
+                Some(config) => lib_cron::parser::check_endpoints(config, CHECK_TIMEOUT).await,
+                None => Vec::new(),
+            }
+        });
+        problems.extend(database.into_iter().chain(storage).chain(parsers));
+    }
+    report(problems)
+}
+
+/// Connect to `DATABASE_URL` and the replica of `DATABASE_READ_URL` when they are set
+async fn check_database() -> Vec<String> {
+    let mut errors = Vec::new();
+    for env in ["DATABASE_URL", "DATABASE_READ_URL"] {
+        let Ok(url) = std::env::var(env) else {
+            continue;
+        };
+        match with_timeout(new_db_pool(&url, 1)).await {
+            Ok(Ok(pool)) => pool.close().await,
+            Ok(Err(e)) => errors.push(format!("{env}: cannot connect to the database: {e}")),
+            Err(e) => errors.push(format!("{env}: {e}")),
+        }
+    }
+    errors
+}
+
+/// Reach `UPLOAD_BUCKET` and `CHUNK_CONTENT_BUCKET` with the configured credentials
+async fn check_buckets() -> Vec<String> {
+    let mut errors = Vec::new();
+    for env in ["UPLOAD_BUCKET", "CHUNK_CONTENT_BUCKET"] {
+        let Ok(bucket) = std::env::var(env) else {
+            continue;
+        };
+        let checked =
+            with_timeout(async { create_object_store(&bucket).await?.check().await }).await;
+        match checked {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => errors.push(format!("{env}: cannot access bucket {bucket}: {e}")),
+            Err(e) => errors.push(format!("{env}: bucket {bucket}: {e}")),
+        }
+    }
+    errors
+}
+
+async fn with_timeout<T>(future: impl Future<Output = T>) -> std::result::Result<T, String> {
+    timeout(CHECK_TIMEOUT, future)
+        .await
+        .map_err(|_| format!("no answer within {}s", CHECK_TIMEOUT.as_secs()))
+}
+
+fn describe(err: lib_utils::error::Error) -> String {
+    match err {
+        lib_utils::error::Error::MissingEnv(env) => format!("{env} is not set"),
+        lib_utils::error::Error::WrongFormat(env) => format!("{env} has an invalid value"),
+        err => err.to_string(),
+    }
+}
+
+/// Every problem logged on its own line, then one error listing them
+fn report(problems: Vec<String>) -> Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    for problem in &problems {
+        error!("Invalid configuration: {problem}");
+    }
+    Err(Error::Custom(format!(
+        "Invalid configuration, {} problem(s): {}",
+        problems.len(),
+        problems.join("; ")
+    )))
+}
+
+// region: Unit Test
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        assert!(report(Vec::new()).is_ok());
+        let problems = vec![
+            describe(lib_utils::error::Error::MissingEnv("DATABASE_URL")),
+            describe(lib_utils::error::Error::WrongFormat("S3_SSE")),
+        ];
+        let err = report(problems).unwrap_err().to_string();
+        assert!(err.contains("2 problem(s): DATABASE_URL is not set; S3_SSE has an invalid value"));
+    }
+}
+// endregion: Unit Test
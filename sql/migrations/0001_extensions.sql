@@ -0,0 +1,8 @@
+-- pgvector for the chunk embeddings, gen_random_uuid() is built in since PostgreSQL 13
+CREATE EXTENSION IF NOT EXISTS vector;
+
+DO $$ BEGIN
+    CREATE TYPE Role AS ENUM ('admin', 'viewer', 'inactive');
+EXCEPTION
+    WHEN duplicate_object THEN NULL;
+END $$;
@@ -0,0 +1,31 @@
+CREATE SEQUENCE IF NOT EXISTS files_file_id_seq START 1000;
+
+CREATE TABLE IF NOT EXISTS Files (
+    "file_id" BIGINT PRIMARY KEY DEFAULT nextval('files_file_id_seq'),
+    -- First segment of the S3 key, every query is scoped by tenant
+    "tenant_id" TEXT NOT NULL DEFAULT 'default',
+    "filename" TEXT NOT NULL,
+    "applicant" TEXT NOT NULL,
+    "file_type" TEXT NOT NULL,
+    "created_at" TIMESTAMP DEFAULT now(),
+    "processed" BOOLEAN DEFAULT FALSE,
+    "warning" TEXT,
+    -- Soft delete, the row and its chunks are purged once the retention window has passed
+    "deleted_at" TIMESTAMP
+);
+
+-- Databases created from the original 01-schema.sql have the table without these columns
+ALTER TABLE Files ADD COLUMN IF NOT EXISTS "tenant_id" TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE Files ADD COLUMN IF NOT EXISTS "warning" TEXT;
+ALTER TABLE Files ADD COLUMN IF NOT EXISTS "deleted_at" TIMESTAMP;
+
+-- Their files belong to the first segment of their S3 key, as the sync assigns them
+UPDATE Files SET "tenant_id" = split_part("filename", '/', 1)
+WHERE "tenant_id" = 'default' AND split_part("filename", '/', 1) <> ''
+    AND position('/' IN "filename") > 0;
+
+-- Was on ("applicant") alone in the original schema
+DROP INDEX IF EXISTS idx_file_applicant;
+CREATE INDEX IF NOT EXISTS idx_file_applicant ON Files ("tenant_id", "applicant");
+CREATE INDEX IF NOT EXISTS idx_file_filename ON Files ("filename");
+CREATE INDEX IF NOT EXISTS idx_file_deleted ON Files ("deleted_at") WHERE "deleted_at" IS NOT NULL;
@@ -0,0 +1,46 @@
+CREATE TABLE IF NOT EXISTS File_Chunks (
+    "chunk_id" BIGSERIAL PRIMARY KEY,
+    "file_id" BIGINT NOT NULL REFERENCES Files(file_id) ON DELETE CASCADE,
+    -- Tenant of the file, denormalized so searches do not join Files
+    "tenant_id" TEXT NOT NULL DEFAULT 'default',
+    "chunk_index" INT,
+    "content_md" TEXT,
+    "content_zstd" BYTEA,
+    "content_encoding" TEXT NOT NULL DEFAULT 'plain',
+    "content_offset" BIGINT,
+    "content_length" INT,
+    "embedding" vector(768),
+    "token_count" INT,
+    -- Set when the chunk exceeded the model limits: 'split', 'truncated' or 'skipped'
+    "oversize" TEXT,
+    -- Citation metadata: page, heading_path, source_url, language
+    "metadata" JSONB NOT NULL DEFAULT '{}',
+    -- Deletion time of the file, denormalized so searches do not join Files
+    "deleted_at" TIMESTAMP
+);
+
+-- Databases created from the original 01-schema.sql have the table without these columns
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "tenant_id" TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "content_zstd" BYTEA;
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "content_encoding" TEXT NOT NULL DEFAULT 'plain';
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "content_offset" BIGINT;
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "content_length" INT;
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "oversize" TEXT;
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "metadata" JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "deleted_at" TIMESTAMP;
+
+-- Their chunks take the tenant and deletion time of their file
+UPDATE File_Chunks SET "tenant_id" = Files."tenant_id", "deleted_at" = Files."deleted_at"
+FROM Files
+WHERE Files."file_id" = File_Chunks."file_id"
+    AND (File_Chunks."tenant_id" <> Files."tenant_id"
+        OR File_Chunks."deleted_at" IS DISTINCT FROM Files."deleted_at");
+
+CREATE INDEX IF NOT EXISTS idx_chunk_content_md_gin
+    ON File_Chunks USING gin (to_tsvector('english', "content_md"));
+CREATE INDEX IF NOT EXISTS idx_chunk_metadata
+    ON File_Chunks USING gin ("metadata" jsonb_path_ops);
+-- The embedding index is created at startup from the VECTOR_INDEX_* settings
+CREATE INDEX IF NOT EXISTS idx_chunk_file_order
+    ON File_Chunks ("file_id", "chunk_index");
+CREATE INDEX IF NOT EXISTS idx_chunk_tenant ON File_Chunks ("tenant_id");
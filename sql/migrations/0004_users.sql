@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS Users (
+    "user_id" VARCHAR PRIMARY KEY,
+    "tenant_id" TEXT NOT NULL DEFAULT 'default',
+    "first_name" VARCHAR NOT NULL,
+    "last_name" VARCHAR NOT NULL,
+    "email" VARCHAR NOT NULL UNIQUE,
+    "role" Role DEFAULT 'viewer',
+    "api_key" TEXT UNIQUE,
+    "salt" UUID DEFAULT gen_random_uuid(),
+    "created_at" TIMESTAMP DEFAULT now()
+);
+
+-- Databases created from the original 01-schema.sql have the table without the tenant, their
+-- users stay in the default tenant
+ALTER TABLE Users ADD COLUMN IF NOT EXISTS "tenant_id" TEXT NOT NULL DEFAULT 'default';
+
+CREATE INDEX IF NOT EXISTS idx_user_api_key ON Users ("api_key");
+CREATE INDEX IF NOT EXISTS idx_user_email ON Users ("email");
+CREATE INDEX IF NOT EXISTS idx_user_tenant ON Users ("tenant_id");
@@ -0,0 +1,6 @@
+-- Runtime settings changed through the admin API, `value` is the JSON of the setting
+CREATE TABLE IF NOT EXISTS Settings (
+    "key" TEXT PRIMARY KEY,
+    "value" JSONB NOT NULL,
+    "updated_at" TIMESTAMP DEFAULT now()
+);
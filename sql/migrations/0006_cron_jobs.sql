@@ -0,0 +1,11 @@
+-- Scheduled jobs, restored into the scheduler on startup
+CREATE TABLE IF NOT EXISTS Cron_Jobs (
+    "job_id" UUID PRIMARY KEY,
+    "job_type" TEXT NOT NULL,
+    "cron" TEXT NOT NULL,
+    -- User id of the tenant that scheduled the job
+    "owner" TEXT NOT NULL DEFAULT 'root',
+    "created_at" TIMESTAMP DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_cron_job_owner ON Cron_Jobs ("owner");
@@ -0,0 +1,9 @@
+-- Model that computed the embedding of the chunk, chunks of another model are re-embedded by the
+-- `reembed_chunks` job. Chunks embedded before this migration have no model and are re-embedded.
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "embedding_model" TEXT;
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "embedding_dim" INT;
+
+UPDATE File_Chunks SET "embedding_dim" = vector_dims("embedding") WHERE "embedding" IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_chunk_embedding_model
+    ON File_Chunks ("embedding_model") WHERE "embedding" IS NOT NULL;
@@ -0,0 +1,11 @@
+-- SHA-256 of the whitespace normalized text of the chunk, a file keeps a single chunk per text.
+-- Chunks stored before this migration get their hash when their file is processed again.
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "content_hash" TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_chunk_file_content_hash
+    ON File_Chunks ("file_id", "content_hash") WHERE "content_hash" IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_chunk_tenant_content_hash
+    ON File_Chunks ("tenant_id", "content_hash") WHERE "content_hash" IS NOT NULL;
+
+-- ETag of the S3 object when it was last synced, a new ETag means the object was overwritten
+ALTER TABLE Files ADD COLUMN IF NOT EXISTS "etag" TEXT;
@@ -0,0 +1,4 @@
+-- LastModified of the S3 object when it was last synced
+ALTER TABLE Files ADD COLUMN IF NOT EXISTS "last_modified" TIMESTAMP;
+-- Version of the object the chunks were parsed from, NULL for buckets without versioning
+ALTER TABLE Files ADD COLUMN IF NOT EXISTS "version_id" TEXT;
@@ -0,0 +1,11 @@
+-- Named search settings of a tenant, executed with only the query text. `params` is the JSON of
+-- the `/search` options (limit, filter, mmr, recency, instruction).
+CREATE TABLE IF NOT EXISTS Search_Templates (
+    "tenant_id" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    "description" TEXT,
+    "params" JSONB NOT NULL DEFAULT '{}',
+    "created_at" TIMESTAMP DEFAULT now(),
+    "updated_at" TIMESTAMP DEFAULT now(),
+    PRIMARY KEY ("tenant_id", "name")
+);
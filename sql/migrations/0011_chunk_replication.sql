@@ -0,0 +1,40 @@
+-- Replication cursor: every insert, update and delete of a chunk takes the next value of the
+-- sequence, replicas pull the changes after the last value they applied
+CREATE SEQUENCE IF NOT EXISTS file_chunks_replication_seq;
+
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "replication_seq" BIGINT;
+UPDATE File_Chunks SET "replication_seq" = nextval('file_chunks_replication_seq')
+    WHERE "replication_seq" IS NULL;
+ALTER TABLE File_Chunks
+    ALTER COLUMN "replication_seq" SET DEFAULT nextval('file_chunks_replication_seq');
+
+CREATE INDEX IF NOT EXISTS idx_chunk_replication_seq ON File_Chunks ("replication_seq");
+
+CREATE OR REPLACE FUNCTION bump_chunk_replication_seq() RETURNS trigger AS $$
+BEGIN
+    NEW."replication_seq" := nextval('file_chunks_replication_seq');
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trg_chunk_replication_seq ON File_Chunks;
+CREATE TRIGGER trg_chunk_replication_seq BEFORE UPDATE ON File_Chunks
+    FOR EACH ROW EXECUTE FUNCTION bump_chunk_replication_seq();
+
+-- Hard deleted chunks, replayed by the replicas
+CREATE TABLE IF NOT EXISTS Chunk_Tombstones (
+    "replication_seq" BIGINT PRIMARY KEY DEFAULT nextval('file_chunks_replication_seq'),
+    "chunk_id" BIGINT NOT NULL,
+    "deleted_at" TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE OR REPLACE FUNCTION record_chunk_tombstone() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO Chunk_Tombstones ("chunk_id") VALUES (OLD."chunk_id");
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trg_chunk_tombstone ON File_Chunks;
+CREATE TRIGGER trg_chunk_tombstone AFTER DELETE ON File_Chunks
+    FOR EACH ROW EXECUTE FUNCTION record_chunk_tombstone();
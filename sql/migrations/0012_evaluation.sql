@@ -0,0 +1,28 @@
+-- Golden query set of a tenant: each query with the files a good search returns for it. Files
+-- rather than chunks so the set survives re-chunking.
+CREATE TABLE IF NOT EXISTS Golden_Queries (
+    "query_id" BIGSERIAL PRIMARY KEY,
+    "tenant_id" TEXT NOT NULL,
+    "query" TEXT NOT NULL,
+    "instruction" TEXT,
+    "relevant_files" BIGINT[] NOT NULL,
+    "created_at" TIMESTAMP DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_golden_query_tenant ON Golden_Queries ("tenant_id");
+
+-- Metric history of the evaluations of the golden set, one row per tenant and run
+CREATE TABLE IF NOT EXISTS Evaluation_Runs (
+    "run_id" BIGSERIAL PRIMARY KEY,
+    "tenant_id" TEXT NOT NULL,
+    "model" TEXT NOT NULL,
+    "k" INT NOT NULL,
+    "queries" INT NOT NULL,
+    "recall" DOUBLE PRECISION NOT NULL,
+    "mrr" DOUBLE PRECISION NOT NULL,
+    -- Set when recall dropped beyond the threshold since the previous run
+    "regression" BOOLEAN NOT NULL DEFAULT FALSE,
+    "created_at" TIMESTAMP DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_evaluation_run_tenant ON Evaluation_Runs ("tenant_id", "run_id");
@@ -0,0 +1,38 @@
+-- Text search configuration of the keyword search per collection (file applicant), from the
+-- `text_search_configs` setting, 'english' for the collections without one
+CREATE OR REPLACE FUNCTION chunk_search_config(chunk_file_id BIGINT) RETURNS regconfig AS $$
+    SELECT COALESCE(
+        (SELECT s."value" ->> f."applicant" FROM Files f, Settings s
+            WHERE f."file_id" = chunk_file_id AND s."key" = 'text_search_configs'),
+        'english'
+    )::regconfig;
+$$ LANGUAGE sql STABLE;
+
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "search_config" regconfig;
+-- Lexemes of the text, computed from the inserted text when the content is zstd or S3 encoded
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "search_tsv" tsvector;
+
+-- Compressed and S3 backed chunks get their lexemes when they are reindexed through the admin API
+UPDATE File_Chunks SET
+    "search_config" = chunk_search_config("file_id"),
+    "search_tsv" = to_tsvector(chunk_search_config("file_id"), "content_md");
+
+CREATE OR REPLACE FUNCTION fill_chunk_search_tsv() RETURNS trigger AS $$
+BEGIN
+    IF NEW."search_config" IS NULL THEN
+        NEW."search_config" := chunk_search_config(NEW."file_id");
+    END IF;
+    IF NEW."search_tsv" IS NULL AND NEW."content_md" IS NOT NULL THEN
+        NEW."search_tsv" := to_tsvector(NEW."search_config", NEW."content_md");
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trg_chunk_search_tsv ON File_Chunks;
+CREATE TRIGGER trg_chunk_search_tsv BEFORE INSERT ON File_Chunks
+    FOR EACH ROW EXECUTE FUNCTION fill_chunk_search_tsv();
+
+-- Replaced by the index of the lexemes, the english stemming of every chunk
+DROP INDEX IF EXISTS idx_chunk_content_md_gin;
+CREATE INDEX IF NOT EXISTS idx_chunk_search_tsv ON File_Chunks USING gin ("search_tsv");
@@ -0,0 +1,56 @@
+-- Column type of the chunk embeddings, switched at startup to the `EMBEDDING_STORAGE` setting:
+-- `vector` (float32), `halfvec` (float16, half the size) or `bit` (sign of every component, 32
+-- times smaller). The vector indexes are dropped first since their operator class depends on the
+-- type, the vector index migration then builds the configured one. `bit` only keeps the signs and
+-- cannot be converted back, the chunks have to be embedded again. Returns whether the column
+-- was converted.
+CREATE OR REPLACE FUNCTION set_chunk_embedding_storage(storage TEXT) RETURNS BOOLEAN AS $$
+DECLARE
+    current_type TEXT;
+    dims INT;
+    target TEXT;
+    vector_index RECORD;
+BEGIN
+    IF storage NOT IN ('vector', 'halfvec', 'bit') THEN
+        RAISE EXCEPTION 'Unknown embedding storage %', storage;
+    END IF;
+
+    SELECT t.typname, NULLIF(a.atttypmod, -1) INTO current_type, dims
+    FROM pg_attribute a JOIN pg_type t ON t.oid = a.atttypid
+    WHERE a.attrelid = 'file_chunks'::regclass AND a.attname = 'embedding';
+
+    IF current_type = storage OR (current_type = 'varbit' AND storage = 'bit') THEN
+        RETURN FALSE;
+    END IF;
+    IF current_type IN ('bit', 'varbit') THEN
+        RAISE EXCEPTION 'Embeddings stored as bit cannot be converted to %', storage;
+    END IF;
+
+    -- A bit column without a length would be bit(1)
+    target := CASE
+        WHEN dims IS NOT NULL THEN format('%s(%s)', storage, dims)
+        WHEN storage = 'bit' THEN 'varbit'
+        ELSE storage
+    END;
+
+    FOR vector_index IN
+        SELECT indexname FROM pg_indexes
+        WHERE tablename = 'file_chunks' AND indexdef ~ 'USING (hnsw|ivfflat)'
+    LOOP
+        EXECUTE format('DROP INDEX IF EXISTS %I', vector_index.indexname);
+    END LOOP;
+
+    IF storage = 'bit' THEN
+        EXECUTE format(
+            'ALTER TABLE file_chunks ALTER COLUMN "embedding" TYPE %s USING binary_quantize("embedding")::%s',
+            target, target
+        );
+    ELSE
+        EXECUTE format(
+            'ALTER TABLE file_chunks ALTER COLUMN "embedding" TYPE %s USING "embedding"::%s',
+            target, target
+        );
+    END IF;
+    RETURN TRUE;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,5 @@
+-- Rate limit of a user, overriding the tier of its role field by field: requests per second,
+-- burst and maximum inputs per request. NULL keeps the value of the role tier.
+ALTER TABLE Users ADD COLUMN IF NOT EXISTS "rate_limit_rps" INT;
+ALTER TABLE Users ADD COLUMN IF NOT EXISTS "rate_limit_burst" INT;
+ALTER TABLE Users ADD COLUMN IF NOT EXISTS "max_batch_size" INT;
@@ -0,0 +1,16 @@
+-- Mutations of the admin API and of the cron schedule, one row per change with the state of its
+-- target before and after it. Written by the server only, never updated.
+CREATE TABLE IF NOT EXISTS Audit_Log (
+    "audit_id" BIGSERIAL PRIMARY KEY,
+    "tenant_id" TEXT NOT NULL,
+    -- User id of the caller, from the request context
+    "actor" TEXT NOT NULL,
+    "kind" TEXT NOT NULL,
+    "action" TEXT NOT NULL,
+    "target" TEXT NOT NULL,
+    "before" JSONB,
+    "after" JSONB,
+    "created_at" TIMESTAMP DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_tenant ON Audit_Log ("tenant_id", "audit_id");
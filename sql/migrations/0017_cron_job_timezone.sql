@@ -0,0 +1,2 @@
+-- IANA timezone the cron expression of a job is evaluated in, jobs scheduled before run in UTC
+ALTER TABLE Cron_Jobs ADD COLUMN IF NOT EXISTS "timezone" TEXT NOT NULL DEFAULT 'UTC';
@@ -0,0 +1,18 @@
+-- What a run does while another run of the same job type is still going: `skip`, `queue` or
+-- `allow`
+ALTER TABLE Cron_Jobs ADD COLUMN IF NOT EXISTS "concurrency" TEXT NOT NULL DEFAULT 'skip';
+
+-- History of the runs of the scheduled jobs, including the runs skipped because of an overlap
+CREATE TABLE IF NOT EXISTS Job_Runs (
+    "run_id" BIGSERIAL PRIMARY KEY,
+    "job_id" UUID NOT NULL REFERENCES Cron_Jobs ("job_id") ON DELETE CASCADE,
+    "job_type" TEXT NOT NULL,
+    -- succeeded, failed or skipped
+    "status" TEXT NOT NULL,
+    "error" TEXT,
+    "started_at" TIMESTAMP NOT NULL DEFAULT now(),
+    -- Unset for skipped runs
+    "duration_ms" BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_run_job ON Job_Runs ("job_id", "run_id");
@@ -0,0 +1,8 @@
+-- Whether the embedding of the chunk has a unit L2 norm. The inner product and L2 distances only
+-- rank like cosine on normalized vectors, the search falls back to cosine when the chunks of a
+-- model are not all normalized. Chunks embedded before this migration were always normalized.
+ALTER TABLE File_Chunks ADD COLUMN IF NOT EXISTS "embedding_normalized" BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE INDEX IF NOT EXISTS idx_chunk_embedding_unnormalized
+    ON File_Chunks ("tenant_id", "embedding_model")
+    WHERE "embedding" IS NOT NULL AND NOT "embedding_normalized";
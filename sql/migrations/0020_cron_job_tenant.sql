@@ -0,0 +1,9 @@
+-- Tenant of the user that scheduled a job, jobs are only listed and managed within their tenant
+ALTER TABLE Cron_Jobs ADD COLUMN IF NOT EXISTS "tenant_id" TEXT NOT NULL DEFAULT 'default';
+
+-- Jobs scheduled before take the tenant of their owner, the root key's jobs stay in 'default'
+UPDATE Cron_Jobs SET "tenant_id" = Users."tenant_id"
+FROM Users
+WHERE Users."user_id" = Cron_Jobs."owner" AND Cron_Jobs."tenant_id" <> Users."tenant_id";
+
+CREATE INDEX IF NOT EXISTS idx_cron_job_tenant ON Cron_Jobs ("tenant_id");